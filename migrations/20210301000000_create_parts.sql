@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS parts (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS edges (
+    parent UUID NOT NULL REFERENCES parts (id) ON DELETE CASCADE,
+    child UUID NOT NULL REFERENCES parts (id) ON DELETE CASCADE,
+    PRIMARY KEY (parent, child)
+);
+
+CREATE INDEX IF NOT EXISTS edges_child_idx ON edges (child);
@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN created BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+CREATE UNIQUE INDEX IF NOT EXISTS parts_name_idx ON parts (name);
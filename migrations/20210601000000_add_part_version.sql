@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
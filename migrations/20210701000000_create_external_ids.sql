@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS external_ids (
+    part UUID NOT NULL REFERENCES parts (id) ON DELETE CASCADE,
+    system TEXT NOT NULL,
+    external_id TEXT NOT NULL,
+    PRIMARY KEY (system, external_id)
+);
+
+CREATE INDEX IF NOT EXISTS external_ids_part_idx ON external_ids (part);
@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN on_hand BIGINT NOT NULL DEFAULT 0;
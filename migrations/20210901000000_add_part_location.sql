@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN location TEXT NOT NULL DEFAULT '';
@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN phantom BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE parts ADD COLUMN options TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE parts ADD COLUMN child_options TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,20 @@
+ALTER TABLE edges ADD COLUMN id UUID;
+ALTER TABLE edges ADD COLUMN quantity BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE edges ADD COLUMN notes TEXT NOT NULL DEFAULT '';
+
+-- version 3 UUID naming the child in the namespace of the parent
+UPDATE edges SET id = encode(
+    set_byte(
+        set_byte(h, 6, (get_byte(h, 6) & 15) | 48),
+        8, (get_byte(h, 8) & 63) | 128
+    ),
+    'hex'
+)::uuid
+FROM (
+    SELECT parent AS p, child AS c, decode(md5(uuid_send(parent) || uuid_send(child)), 'hex') AS h
+    FROM edges
+) hashes
+WHERE parent = hashes.p AND child = hashes.c;
+
+ALTER TABLE edges ALTER COLUMN id SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS edges_id_idx ON edges (id);
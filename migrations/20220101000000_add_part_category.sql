@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN category UUID;
@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN attributes TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN lead_time_days BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN routing TEXT NOT NULL DEFAULT '[]';
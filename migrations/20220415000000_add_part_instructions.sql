@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN instructions TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,2 @@
+ALTER TABLE parts ADD COLUMN unit_cost DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE parts ADD COLUMN cost_history TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS part_suppliers (
+    part UUID NOT NULL REFERENCES parts (id) ON DELETE CASCADE,
+    preference INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    unit_cost DOUBLE PRECISION NOT NULL DEFAULT 0,
+    lead_time_days BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (part, name)
+);
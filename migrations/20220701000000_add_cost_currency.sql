@@ -0,0 +1,2 @@
+ALTER TABLE parts ADD COLUMN currency TEXT;
+ALTER TABLE part_suppliers ADD COLUMN currency TEXT;
@@ -0,0 +1 @@
+ALTER TABLE part_suppliers ADD COLUMN price_breaks TEXT NOT NULL DEFAULT '[]';
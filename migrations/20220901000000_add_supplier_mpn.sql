@@ -0,0 +1 @@
+ALTER TABLE part_suppliers ADD COLUMN mpn TEXT;
@@ -0,0 +1 @@
+ALTER TABLE parts ADD COLUMN lifecycle TEXT NOT NULL DEFAULT 'in_work';
@@ -0,0 +1,176 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::parts_list::{Part, PartsListError};
+use crate::store::PartsStore;
+
+/// Components a part is built from and the quantity of each used by one of
+/// the part, ordered by component id
+type Structure = Vec<(Uuid, u64)>;
+
+/// Subassembly belonging to a group of duplicates
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DuplicateMember {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// Subassemblies built from the same components in the same quantities,
+/// which are candidates for being merged into one
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DuplicateGroup {
+    /// Hash of the structure shared by the subassemblies
+    pub signature: String,
+    /// Number of distinct components used by each subassembly
+    pub components: usize,
+    /// Total quantity of components used by each subassembly
+    pub quantity: u64,
+    /// Subassemblies of the group, ordered by name
+    pub parts: Vec<DuplicateMember>,
+}
+
+/// Find subassemblies which are structurally identical, being built from the
+/// same components in the same quantities once flattened
+///
+/// Intermediate levels are ignored, so a subassembly grouping its components
+/// differently from another still duplicates it. Groups with the most
+/// subassemblies come first.
+#[instrument(level = "debug", skip(parts))]
+pub fn duplicates(parts: &dyn PartsStore) -> Result<Vec<DuplicateGroup>, PartsListError> {
+    let all = parts.parts()?;
+    let by_id: HashMap<Uuid, &Part> = all.iter().map(|part| (part.id, part)).collect();
+
+    let mut structures: HashMap<Uuid, BTreeMap<Uuid, u64>> = HashMap::new();
+    let mut groups: HashMap<Structure, Vec<&Part>> = HashMap::new();
+    for part in all.iter().filter(|part| !part.children.is_empty()) {
+        let structure = flatten(&by_id, &part.id, &mut structures);
+        let structure: Structure = structure.into_iter().collect();
+        groups.entry(structure).or_default().push(part);
+    }
+
+    let mut duplicates: Vec<DuplicateGroup> = groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(structure, mut members)| {
+            members.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+            DuplicateGroup {
+                signature: signature(&structure),
+                components: structure.len(),
+                quantity: structure
+                    .iter()
+                    .fold(0u64, |total, (_, quantity)| total.saturating_add(*quantity)),
+                parts: members
+                    .into_iter()
+                    .map(|part| DuplicateMember {
+                        id: part.id,
+                        name: part.name.clone(),
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| {
+        b.parts
+            .len()
+            .cmp(&a.parts.len())
+            .then_with(|| a.parts[0].name.cmp(&b.parts[0].name))
+            .then_with(|| a.parts[0].id.cmp(&b.parts[0].id))
+    });
+    Ok(duplicates)
+}
+
+/// Work out the components part `id` is built from, recording the structure
+/// of it and each subassembly below it in `structures`
+pub(crate) fn flatten(
+    by_id: &HashMap<Uuid, &Part>,
+    id: &Uuid,
+    structures: &mut HashMap<Uuid, BTreeMap<Uuid, u64>>,
+) -> BTreeMap<Uuid, u64> {
+    if let Some(structure) = structures.get(id) {
+        return structure.clone();
+    }
+    let mut structure = BTreeMap::new();
+    match by_id.get(id) {
+        Some(part) if !part.children.is_empty() => {
+            for child in &part.children {
+                let per_part = part.quantity_of(child);
+                for (component, quantity) in flatten(by_id, child, structures) {
+                    let total = structure.entry(component).or_insert(0u64);
+                    *total = total.saturating_add(quantity.saturating_mul(per_part));
+                }
+            }
+        }
+        _ => {
+            structure.insert(*id, 1);
+        }
+    }
+    structures.insert(*id, structure.clone());
+    structure
+}
+
+/// Hash of a structure, as 16 hexadecimal digits
+fn signature(structure: &[(Uuid, u64)]) -> String {
+    let mut hasher = DefaultHasher::new();
+    structure.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{PartsList, PartsListUpdate, Relationship, RelationshipEdit};
+
+    #[test]
+    fn duplicate_subassemblies() {
+        let mut parts = PartsList::new();
+        let resistor = parts.add(Part::new("resistor")).unwrap().id;
+        let capacitor = parts.add(Part::new("capacitor")).unwrap().id;
+        let filter = parts.add(Part::new("filter")).unwrap().id;
+        let filter_copy = parts.add(Part::new("filter copy")).unwrap().id;
+        let nested = parts.add(Part::new("nested filter")).unwrap().id;
+        let inner = parts.add(Part::new("inner")).unwrap().id;
+        let other = parts.add(Part::new("other")).unwrap().id;
+
+        for id in &[filter, filter_copy, other] {
+            parts
+                .update(id, &[&resistor, &capacitor], PartsListUpdate::Add)
+                .unwrap();
+        }
+        let double = RelationshipEdit {
+            quantity: Some(2),
+            notes: None,
+        };
+        for id in &[filter, filter_copy] {
+            let relationship = Relationship::id_for(id, &resistor);
+            parts.edit_relationship(&relationship, &double).unwrap();
+        }
+        // the same components grouped through an intermediate subassembly
+        parts
+            .update(&inner, &[&resistor, &capacitor], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&nested, &[&inner], PartsListUpdate::Add)
+            .unwrap();
+        let relationship = Relationship::id_for(&inner, &resistor);
+        parts.edit_relationship(&relationship, &double).unwrap();
+
+        let groups = duplicates(&parts).unwrap();
+        assert_eq!(groups.len(), 1);
+        let names: Vec<&str> = groups[0]
+            .parts
+            .iter()
+            .map(|part| part.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["filter", "filter copy", "inner", "nested filter"]
+        );
+        assert_eq!(groups[0].components, 2);
+        assert_eq!(groups[0].quantity, 3);
+        assert!(!groups[0].parts.iter().any(|part| part.id == other));
+    }
+}
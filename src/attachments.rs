@@ -0,0 +1,381 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use multipart::server::Multipart;
+use rocket::Config;
+use uuid::Uuid;
+
+use crate::parts_list::PartsListError;
+
+/// Directory holding attachments when not configured
+pub const DEFAULT_ATTACHMENTS_DIR: &str = "attachments";
+
+/// Largest file accepted as an attachment when not configured
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Extension of the file holding the metadata of an attachment
+const METADATA_EXTENSION: &str = "json";
+
+/// File attached to a part, such as a datasheet or drawing
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub part: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Upload time in milliseconds since the UNIX epoch
+    pub created: u64,
+}
+
+impl Attachment {
+    /// Describe a new attachment, keeping only the final component of the
+    /// client supplied file name
+    pub fn new(part: Uuid, filename: &str, content_type: &str, size: u64) -> Attachment {
+        let filename: String = Path::new(filename)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("attachment")
+            .chars()
+            .filter(|c| !c.is_control() && *c != '"')
+            .collect();
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Attachment {
+            id: Uuid::new_v4(),
+            part,
+            filename,
+            content_type: content_type.into(),
+            size,
+            created,
+        }
+    }
+}
+
+/// Storage backend for the files attached to parts
+///
+/// Attachments are stored separately from the parts list, so any attachment
+/// store can be combined with any parts store.
+pub trait AttachmentStore: Send + Sync {
+    /// Store the contents of a new attachment, read from `contents`
+    fn save(&self, attachment: &Attachment, contents: &mut dyn Read) -> Result<(), PartsListError>;
+
+    /// Retrieve the attachments of a part, oldest first
+    fn list(&self, part: &Uuid) -> Result<Vec<Attachment>, PartsListError>;
+
+    /// Open an attachment of a part for reading
+    fn open(
+        &self,
+        part: &Uuid,
+        id: &Uuid,
+    ) -> Result<(Attachment, Box<dyn Read + Send>), PartsListError>;
+
+    /// Remove an attachment of a part, returning it
+    fn remove(&self, part: &Uuid, id: &Uuid) -> Result<Attachment, PartsListError>;
+
+    /// Remove every attachment of a part which was deleted
+    fn remove_part(&self, part: &Uuid) -> Result<(), PartsListError>;
+}
+
+/// Attachment store shared by the routes, along with the size of the largest
+/// upload it accepts
+pub struct SharedAttachments {
+    pub store: Box<dyn AttachmentStore>,
+    pub max_size: u64,
+}
+
+impl SharedAttachments {
+    pub fn new<S: AttachmentStore + 'static>(store: S, max_size: u64) -> SharedAttachments {
+        SharedAttachments {
+            store: Box::new(store),
+            max_size,
+        }
+    }
+
+    /// Store attachments on disk in the directory given by the
+    /// `attachments_dir` setting of the Rocket configuration, accepting
+    /// uploads of up to `max_attachment_size` bytes
+    pub fn from_config(config: &Config) -> SharedAttachments {
+        let dir = config
+            .get_str("attachments_dir")
+            .unwrap_or(DEFAULT_ATTACHMENTS_DIR);
+        let max_size = config
+            .get_int("max_attachment_size")
+            .map_or(DEFAULT_MAX_ATTACHMENT_SIZE, |size| size.max(0) as u64);
+        SharedAttachments::new(DiskAttachments::new(dir), max_size)
+    }
+
+    /// Remove the attachments of parts deleted for good, only logging any
+    /// failure as the parts themselves are already gone
+    pub fn remove_parts(&self, parts: &[Uuid]) {
+        for part in parts {
+            if let Err(e) = self.store.remove_part(part) {
+                tracing::warn!("Failed to remove attachments of {}: {}", part, e);
+            }
+        }
+    }
+}
+
+/// File sent in a `multipart/form-data` upload, spooled to a temporary file
+/// which is removed once the upload is dropped
+#[derive(Debug)]
+pub struct Upload {
+    pub filename: String,
+    pub content_type: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    path: PathBuf,
+}
+
+impl Upload {
+    /// Read the files of a `multipart/form-data` body of at most `max_size`
+    /// bytes, ignoring form fields which aren't files
+    ///
+    /// Each file is streamed to a temporary file as it is read, so uploads
+    /// aren't held in memory.
+    pub fn read_all<R: Read>(
+        body: R,
+        boundary: &str,
+        max_size: u64,
+    ) -> Result<Vec<Upload>, PartsListError> {
+        let mut body = Limited {
+            inner: body,
+            remaining: max_size,
+            exceeded: false,
+        };
+        let uploads = Upload::read_files(&mut body, boundary);
+        if body.exceeded {
+            return Err(PartsListError::UploadTooLarge { limit: max_size });
+        }
+        uploads
+    }
+
+    fn read_files<R: Read>(body: R, boundary: &str) -> Result<Vec<Upload>, PartsListError> {
+        let mut multipart = Multipart::with_body(body, boundary);
+        let mut uploads = Vec::new();
+        while let Some(mut field) = multipart.read_entry()? {
+            let filename = match field.headers.filename.take() {
+                Some(filename) => filename,
+                None => continue,
+            };
+            let content_type = field.headers.content_type.as_ref().map_or_else(
+                || "application/octet-stream".into(),
+                |mime| mime.to_string(),
+            );
+            let mut upload = Upload {
+                filename,
+                content_type,
+                size: 0,
+                path: std::env::temp_dir().join(format!("bom-upload-{}", Uuid::new_v4())),
+            };
+            let mut file = File::create(&upload.path)?;
+            upload.size = io::copy(&mut field.data, &mut file)?;
+            uploads.push(upload);
+        }
+        Ok(uploads)
+    }
+
+    /// Open the uploaded file for reading
+    pub fn open(&self) -> Result<File, PartsListError> {
+        Ok(File::open(&self.path)?)
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reader failing once more than `remaining` bytes would be read from it
+struct Limited<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // read one byte past the limit to tell whether the body goes on
+        let len = self.remaining.saturating_add(1).min(buf.len() as u64) as usize;
+        let read = self.inner.read(&mut buf[..len])?;
+        if read as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "upload is larger than the limit",
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Attachments stored on disk, in a directory for each part holding the
+/// contents of each attachment alongside its metadata
+pub struct DiskAttachments {
+    dir: PathBuf,
+}
+
+impl DiskAttachments {
+    pub fn new<P: AsRef<Path>>(dir: P) -> DiskAttachments {
+        DiskAttachments {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn contents_path(&self, part: &Uuid, id: &Uuid) -> PathBuf {
+        self.dir.join(part.to_string()).join(id.to_string())
+    }
+
+    fn metadata(&self, part: &Uuid, id: &Uuid) -> Result<Attachment, PartsListError> {
+        let path = self
+            .contents_path(part, id)
+            .with_extension(METADATA_EXTENSION);
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(file)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(PartsListError::AttachmentDoesNotExist { id: *id })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl AttachmentStore for DiskAttachments {
+    fn save(&self, attachment: &Attachment, contents: &mut dyn Read) -> Result<(), PartsListError> {
+        let path = self.contents_path(&attachment.part, &attachment.id);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        io::copy(contents, &mut File::create(&path)?)?;
+        // the metadata is written last, so attachments aren't listed until
+        // their contents are complete
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            serde_json::to_writer(&mut file, attachment)?;
+            file.flush()?;
+        }
+        fs::rename(&tmp_path, path.with_extension(METADATA_EXTENSION))?;
+        Ok(())
+    }
+
+    fn list(&self, part: &Uuid) -> Result<Vec<Attachment>, PartsListError> {
+        let entries = match fs::read_dir(self.dir.join(part.to_string())) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut attachments = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(METADATA_EXTENSION) {
+                continue;
+            }
+            attachments.push(serde_json::from_reader(File::open(path)?)?);
+        }
+        attachments.sort_by_key(|attachment: &Attachment| (attachment.created, attachment.id));
+        Ok(attachments)
+    }
+
+    fn open(
+        &self,
+        part: &Uuid,
+        id: &Uuid,
+    ) -> Result<(Attachment, Box<dyn Read + Send>), PartsListError> {
+        let attachment = self.metadata(part, id)?;
+        let file = File::open(self.contents_path(part, id))?;
+        Ok((attachment, Box::new(file)))
+    }
+
+    fn remove(&self, part: &Uuid, id: &Uuid) -> Result<Attachment, PartsListError> {
+        let attachment = self.metadata(part, id)?;
+        let path = self.contents_path(part, id);
+        fs::remove_file(path.with_extension(METADATA_EXTENSION))?;
+        fs::remove_file(path)?;
+        Ok(attachment)
+    }
+
+    fn remove_part(&self, part: &Uuid) -> Result<(), PartsListError> {
+        match fs::remove_dir_all(self.dir.join(part.to_string())) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_attachments() {
+        let dir = std::env::temp_dir().join(format!("bom-attachments-{}", Uuid::new_v4()));
+        let store = DiskAttachments::new(&dir);
+        let part = Uuid::new_v4();
+        assert!(store.list(&part).unwrap().is_empty());
+
+        let attachment = Attachment::new(part, "../docs/\"sheet\".pdf", "application/pdf", 5);
+        assert_eq!(attachment.filename, "sheet.pdf");
+        store.save(&attachment, &mut &b"%PDF-"[..]).unwrap();
+        assert_eq!(store.list(&part).unwrap(), vec![attachment.clone()]);
+
+        let (found, mut reader) = store.open(&part, &attachment.id).unwrap();
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).unwrap();
+        assert_eq!(found, attachment);
+        assert_eq!(contents, b"%PDF-");
+
+        assert_eq!(store.remove(&part, &attachment.id).unwrap(), attachment);
+        assert!(store.list(&part).unwrap().is_empty());
+        assert_matches!(
+            store.open(&part, &attachment.id).err(),
+            Some(PartsListError::AttachmentDoesNotExist { .. })
+        );
+
+        store.save(&attachment, &mut &b"%PDF-"[..]).unwrap();
+        store.remove_part(&part).unwrap();
+        assert!(!dir.join(part.to_string()).exists());
+        store.remove_part(&part).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multipart_uploads() {
+        let body = "--XYZ\r\n\
+            Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+            not a file\r\n\
+            --XYZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"drawing.svg\"\r\n\
+            Content-Type: image/svg+xml\r\n\r\n\
+            <svg/>\r\n\
+            --XYZ--\r\n";
+        let uploads = Upload::read_all(body.as_bytes(), "XYZ", 1024).unwrap();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].filename, "drawing.svg");
+        assert_eq!(uploads[0].content_type, "image/svg+xml");
+        assert_eq!(uploads[0].size, 6);
+        let mut contents = Vec::new();
+        uploads[0]
+            .open()
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"<svg/>");
+
+        // the spooled file goes along with the upload
+        let path = uploads[0].path.clone();
+        drop(uploads);
+        assert!(!path.exists());
+
+        assert_matches!(
+            Upload::read_all(body.as_bytes(), "XYZ", 16),
+            Err(PartsListError::UploadTooLarge { limit: 16 })
+        );
+    }
+}
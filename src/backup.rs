@@ -0,0 +1,256 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use flate2::read::{GzDecoder, GzEncoder};
+use flate2::Compression;
+
+use crate::category::Category;
+use crate::eco::Eco;
+use crate::parts_list::{Part, PartsList, PartsListError};
+use crate::release::Release;
+use crate::store::PartsStore;
+
+/// Version of the archive format written by `Backup`
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Prefix of the files written by automatic snapshots
+const SNAPSHOT_PREFIX: &str = "bom-";
+
+/// Extension of the files written by automatic snapshots
+const SNAPSHOT_EXTENSION: &str = ".json";
+
+/// Full copy of the parts held by a server, exchanged as gzip compressed json
+/// by the backup and restore admin endpoints
+///
+/// The server keeps no audit log or other history, so the parts, the
+/// categories they are assigned to, the ECOs changing them and the releases of
+/// assemblies are the entire dataset.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Backup {
+    pub version: u32,
+    /// Milliseconds since the unix epoch when the backup was taken
+    pub created: u64,
+    pub parts: Vec<Part>,
+    /// Categories the parts are assigned to, missing from archives of servers
+    /// without any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<Category>,
+    /// ECOs proposed to change the parts, missing from archives of servers
+    /// without any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ecos: Vec<Eco>,
+    /// Assemblies submitted for release, missing from archives of servers
+    /// without any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub releases: Vec<Release>,
+}
+
+impl Backup {
+    pub fn new(mut parts: Vec<Part>) -> Backup {
+        parts.sort_by_key(|part| part.id);
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Backup {
+            version: BACKUP_VERSION,
+            created,
+            parts,
+            categories: Vec::new(),
+            ecos: Vec::new(),
+            releases: Vec::new(),
+        }
+    }
+
+    pub fn with_categories(mut self, mut categories: Vec<Category>) -> Backup {
+        categories.sort_by_key(|category| category.id);
+        self.categories = categories;
+        self
+    }
+
+    pub fn with_ecos(mut self, mut ecos: Vec<Eco>) -> Backup {
+        ecos.sort_by_key(|eco| eco.id);
+        self.ecos = ecos;
+        self
+    }
+
+    pub fn with_releases(mut self, mut releases: Vec<Release>) -> Backup {
+        releases.sort_by_key(|release| release.id);
+        self.releases = releases;
+        self
+    }
+
+    /// Reader producing the compressed archive, which is compressed as it is
+    /// read so it can be streamed
+    pub fn compressed(&self) -> Result<GzEncoder<Cursor<Vec<u8>>>, PartsListError> {
+        let json = serde_json::to_vec(self)?;
+        Ok(GzEncoder::new(Cursor::new(json), Compression::default()))
+    }
+
+    /// Read a compressed archive, rejecting those written in another format
+    pub fn decompress<R: Read>(reader: R) -> Result<Backup, PartsListError> {
+        let backup: Backup = serde_json::from_reader(GzDecoder::new(reader))?;
+        if backup.version != BACKUP_VERSION {
+            return Err(PartsListError::UnsupportedBackupVersion {
+                version: backup.version,
+            });
+        }
+        Ok(backup)
+    }
+}
+
+/// Periodic backup of the parts list to timestamped files in a directory,
+/// keeping only the most recent ones
+#[derive(Clone, Debug)]
+pub struct AutoSnapshot {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    pub keep: usize,
+}
+
+impl AutoSnapshot {
+    pub fn new<P: AsRef<Path>>(dir: P, interval: Duration, keep: usize) -> AutoSnapshot {
+        AutoSnapshot {
+            dir: dir.as_ref().to_path_buf(),
+            interval,
+            keep,
+        }
+    }
+
+    /// Write `parts` to a new snapshot named after the current time, returning
+    /// its path, then remove all but the most recent `keep` snapshots
+    pub fn write(&self, parts: &PartsList) -> Result<PathBuf, PartsListError> {
+        fs::create_dir_all(&self.dir)?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis());
+        let path = self.dir.join(format!(
+            "{}{}{}",
+            SNAPSHOT_PREFIX, millis, SNAPSHOT_EXTENSION
+        ));
+        // written aside and renamed so a snapshot is never seen half written
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, parts)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Snapshots in the directory, oldest first
+    pub fn snapshots(&self) -> Result<Vec<PathBuf>, PartsListError> {
+        let mut snapshots: Vec<(u128, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let millis = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(SNAPSHOT_PREFIX))
+                .and_then(|name| name.strip_suffix(SNAPSHOT_EXTENSION))
+                .and_then(|millis| millis.parse::<u128>().ok());
+            if let Some(millis) = millis {
+                snapshots.push((millis, path));
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn prune(&self) -> Result<(), PartsListError> {
+        let snapshots = self.snapshots()?;
+        let excess = snapshots.len().saturating_sub(self.keep);
+        for path in &snapshots[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Write a snapshot of `store` every interval from a background thread
+    ///
+    /// The store is only read locked while it is copied, so requests are
+    /// held up no longer than by any other read.
+    pub fn spawn(self, store: Arc<RwLock<Box<dyn PartsStore>>>) -> thread::JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(self.interval);
+            let parts = match store.read() {
+                Ok(store) => store.snapshot(),
+                Err(_) => {
+                    tracing::error!("Stopping automatic snapshots: parts list lock poisoned");
+                    return;
+                }
+            };
+            if let Err(e) = parts.and_then(|parts| self.write(&parts)) {
+                tracing::warn!("Failed to write automatic snapshot: {}", e);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::PartsListUpdate;
+
+    #[test]
+    fn backup_round_trip() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        parts
+            .update(&assy, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+        let backup =
+            Backup::new(PartsStore::parts(&parts).unwrap()).with_categories(vec![Category {
+                id: uuid::Uuid::new_v4(),
+                name: String::from("Fasteners"),
+                parent: None,
+                attributes: Vec::new(),
+            }]);
+
+        let mut archive = Vec::new();
+        backup
+            .compressed()
+            .unwrap()
+            .read_to_end(&mut archive)
+            .unwrap();
+        let restored = Backup::decompress(archive.as_slice()).unwrap();
+        assert_eq!(restored, backup);
+
+        // links are restored regardless of the order of the parts
+        let mut store = PartsList::new();
+        store.add(Part::new("stale")).unwrap();
+        let mut reversed = restored.parts;
+        reversed.reverse();
+        store.replace_all(reversed).unwrap();
+        assert_eq!(PartsStore::parts(&store).unwrap().len(), 2);
+        assert!(store.get(&screw).unwrap().parents.contains(&assy));
+        assert!(PartsStore::validate(&store).unwrap().is_valid());
+
+        assert!(Backup::decompress(&b"not an archive"[..]).is_err());
+    }
+
+    #[test]
+    fn keeps_latest_snapshots() {
+        let dir = std::env::temp_dir().join(format!("bom-snapshots-{}", uuid::Uuid::new_v4()));
+        let auto = AutoSnapshot::new(&dir, Duration::from_secs(60), 2);
+        let mut parts = PartsList::new();
+        let mut written = Vec::new();
+        for name in &["a", "b", "c"] {
+            parts.add(Part::new(name)).unwrap();
+            written.push(auto.write(&parts).unwrap());
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        assert_eq!(auto.snapshots().unwrap(), written[1..].to_vec());
+        let latest: PartsList = serde_json::from_reader(File::open(&written[2]).unwrap()).unwrap();
+        assert_eq!(PartsStore::parts(&latest).unwrap().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
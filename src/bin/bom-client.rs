@@ -1,15 +1,32 @@
 extern crate anyhow;
 extern crate clap;
+extern crate colored;
+extern crate csv;
 extern crate reqwest;
 extern crate url;
 
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
 use clap::Clap;
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_derive::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 use url::Url;
 use uuid::Uuid;
 
 use bom_server::client;
-use bom_server::parts_list::{PartsListFilter, PartsListUpdate};
+use bom_server::client::offline::{self, OfflineQueue, QueuedChange, ReplayReport};
+use bom_server::errors::PartsErrorCode;
+use bom_server::kicad;
+use bom_server::parts_list::{
+    Part, PartsListFilter, PartsListPredicate, PartsListQuery, PartsListSort, PartsListSortKey,
+    PartsListUpdate, Relationship, RelationshipEdit, SortOrder, Violation,
+};
+use bom_server::response::Response;
 
 #[derive(Clap, PartialEq, Debug)]
 #[clap(rename_all = "screaming_snake")]
@@ -36,6 +53,42 @@ impl From<FilterOption> for PartsListFilter {
     }
 }
 
+#[derive(Clap, PartialEq, Debug)]
+#[clap(rename_all = "screaming_snake")]
+pub enum SortOption {
+    Name,
+    Created,
+    ChildrenCount,
+}
+
+/// Convert from structopt cli sort repr to internal sort key type
+impl From<SortOption> for PartsListSortKey {
+    fn from(sort: SortOption) -> PartsListSortKey {
+        match sort {
+            SortOption::Name => PartsListSortKey::Name,
+            SortOption::Created => PartsListSortKey::Created,
+            SortOption::ChildrenCount => PartsListSortKey::ChildrenCount,
+        }
+    }
+}
+
+#[derive(Clap, PartialEq, Debug)]
+#[clap(rename_all = "screaming_snake")]
+pub enum OrderOption {
+    Asc,
+    Desc,
+}
+
+/// Convert from structopt cli order repr to internal sort order type
+impl From<OrderOption> for SortOrder {
+    fn from(order: OrderOption) -> SortOrder {
+        match order {
+            OrderOption::Asc => SortOrder::Asc,
+            OrderOption::Desc => SortOrder::Desc,
+        }
+    }
+}
+
 #[derive(Clap, PartialEq, Debug)]
 #[clap(rename_all = "screaming_snake")]
 pub enum ActionOption {
@@ -55,6 +108,92 @@ impl From<ActionOption> for PartsListUpdate {
     }
 }
 
+#[derive(Clap, PartialEq, Debug)]
+#[clap(rename_all = "screaming_snake")]
+pub enum ImportFormat {
+    ParentChild,
+    Indented,
+}
+
+#[derive(Clap, Deserialize, PartialEq, Debug)]
+#[clap(rename_all = "screaming_snake")]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Dot,
+}
+
+/// Settings for one of the servers the client talks to, named in the
+/// configuration file
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub host: Option<String>,
+    /// Sent as a bearer token, for servers behind an authenticating proxy
+    pub token: Option<String>,
+    /// Output format used by export when none is given
+    pub format: Option<ExportFormat>,
+    /// Queue changes while the server can't be reached, as with `--offline`
+    pub offline: Option<bool>,
+}
+
+/// Configuration file of the client, e.g.
+///
+/// ```toml
+/// default_profile = "dev"
+///
+/// [profiles.dev]
+/// host = "http://localhost:8000"
+///
+/// [profiles.prod]
+/// host = "https://bom.example.com/api"
+/// token = "..."
+/// format = "csv"
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    /// Profile used when `--profile` isn't given
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+/// Location of the configuration file, following the XDG base directories
+fn config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("bom-client").join("config.toml"))
+}
+
+/// Read the profile named by `--profile`, or else the default profile of the
+/// configuration file, which may be missing unless given with `--config`
+fn load_profile(opts: &Opts) -> anyhow::Result<Profile> {
+    let path = match opts.config.clone().or_else(config_path) {
+        Some(path) => path,
+        None => return Ok(Profile::default()),
+    };
+    let mut config: ClientConfig = match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Invalid configuration in {}: {}", path.display(), e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && opts.config.is_none() => {
+            ClientConfig::default()
+        }
+        Err(e) => return Err(anyhow::anyhow!("Couldn't read {}: {}", path.display(), e)),
+    };
+    let name = match opts.profile.clone().or(config.default_profile) {
+        Some(name) => name,
+        None => return Ok(Profile::default()),
+    };
+    config
+        .profiles
+        .remove(&name)
+        .ok_or_else(|| anyhow::anyhow!("No profile named {:?} in {}", name, path.display()))
+}
+
 /// A simple client to test BOM-Server
 #[derive(Clap)]
 #[clap(version = "0.1.0")]
@@ -62,8 +201,26 @@ pub struct Opts {
     /// A level of verbosity, can be used multiple times
     #[clap(short, long, default_value = "warn")]
     pub log_level: String,
-    #[clap(short, long, default_value = "http://localhost:8000")]
-    pub host: String,
+    /// Url of the server, including the base path it is mounted under if any,
+    /// overriding the host of the profile [default: http://localhost:8000]
+    #[clap(short, long)]
+    pub host: Option<String>,
+    /// Profile of the configuration file to take the host, token and output
+    /// format from, rather than its default profile
+    #[clap(short, long)]
+    pub profile: Option<String>,
+    /// Configuration file holding the profiles, read from
+    /// ~/.config/bom-client/config.toml when omitted
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+    /// Exchange MessagePack rather than JSON with the server, which is smaller
+    /// and quicker to parse for large part lists
+    #[clap(long)]
+    pub msgpack: bool,
+    /// Queue parts created, updated or deleted while the server can't be
+    /// reached, making the queued changes before any later command once it can
+    #[clap(long)]
+    pub offline: bool,
     #[clap(subcommand)]
     pub subcmd: SubCommand,
 }
@@ -73,11 +230,29 @@ pub enum SubCommand {
     GetIndex(GetIndex),
     ListParts(ListParts),
     CreatePart(CreatePart),
+    UpsertPart(UpsertPart),
     GetPart(GetPart),
     DeletePart(DeletePart),
+    BulkDelete(BulkDelete),
+    RestorePart(RestorePart),
     GetChildren(GetChildren),
     UpdatePart(UpdatePart),
+    MovePart(MovePart),
+    GetParents(GetParents),
     GetContained(GetContained),
+    Import(Import),
+    ImportKicad(ImportKicad),
+    Export(Export),
+    Diff(Diff),
+    Watch(Watch),
+    DeleteTree(DeleteTree),
+    Sync(Sync),
+    Backup(Backup),
+    Restore(Restore),
+    Stats(Stats),
+    Validate(Validate),
+    Search(Search),
+    Replay(Replay),
 }
 
 /// Return text from BOM Server root
@@ -90,6 +265,24 @@ pub struct ListParts {
     /// Filter for listing parts matching the variants listed
     #[clap(short, long, default_value = "ALL", arg_enum, case_insensitive(true))]
     pub filter: FilterOption,
+
+    /// Order the parts by the key listed
+    #[clap(short, long, arg_enum, case_insensitive(true))]
+    pub sort: Option<SortOption>,
+
+    /// Direction of the ordering when sorting
+    #[clap(short, long, default_value = "ASC", arg_enum, case_insensitive(true))]
+    pub order: OrderOption,
+
+    /// Predicates parts must also satisfy, as KEY=VALUE (e.g. name_contains=pen, min_children=2,
+    /// has_parent=<id>)
+    #[clap(
+        short = 'w',
+        long = "where",
+        multiple_occurrences(true),
+        number_of_values(1)
+    )]
+    pub predicates: Vec<String>,
 }
 
 /// Creates a part in the BOM Server
@@ -98,14 +291,39 @@ pub struct CreatePart {
     /// The name of the new part, used for uniqueness
     #[clap(short, long)]
     pub name: String,
+
+    /// Id for the new part, randomly generated when not given
+    #[clap(short, long)]
+    pub id: Option<Uuid>,
+}
+
+/// Creates a part on the BOM Server unless it already exists
+#[derive(Clap)]
+pub struct UpsertPart {
+    /// The name of the part
+    #[clap(short, long)]
+    pub name: String,
+
+    /// External system the part is identified by, matching the existing part
+    /// by its id there rather than by name
+    #[clap(short, long, requires = "external-id")]
+    pub system: Option<String>,
+
+    /// Id of the part in the external system
+    #[clap(short, long, requires = "system")]
+    pub external_id: Option<String>,
 }
 
 /// Retrieves a part from the BOM Server
 #[derive(Clap)]
 pub struct GetPart {
     /// Part id to retrieve, assigned during creation
+    #[clap(short, long, required_unless_present = "name", conflicts_with = "name")]
+    pub id: Option<Uuid>,
+
+    /// Name of the part to retrieve instead of its id
     #[clap(short, long)]
-    pub id: Uuid,
+    pub name: Option<String>,
 }
 
 /// Deletes a part from the BOM Server
@@ -114,6 +332,34 @@ pub struct DeletePart {
     /// Part id to delete, assigned during creation
     #[clap(short, long)]
     pub id: Uuid,
+
+    /// Only delete the part if it is still at this version
+    #[clap(short, long)]
+    pub expected_version: Option<u64>,
+
+    /// Delete the part even if it is still used by assemblies
+    #[clap(short, long)]
+    pub force: bool,
+}
+
+/// Deletes several parts from the BOM Server in a single change
+#[derive(Clap)]
+pub struct BulkDelete {
+    /// The ids of the parts to delete
+    #[clap(short, long)]
+    pub ids: Vec<Uuid>,
+
+    /// Also delete the subtree of each part
+    #[clap(short, long)]
+    pub recursive: bool,
+}
+
+/// Restores a deleted part on the BOM Server
+#[derive(Clap)]
+pub struct RestorePart {
+    /// Part id to restore, assigned during creation
+    #[clap(short, long)]
+    pub id: Uuid,
 }
 
 /// Retrieves a part from the BOM Server
@@ -126,6 +372,14 @@ pub struct GetChildren {
     /// Filter for listing children matching the variants listed
     #[clap(short, long, default_value = "ALL", arg_enum, case_insensitive(true))]
     pub filter: FilterOption,
+
+    /// Order the parts by the key listed
+    #[clap(short, long, arg_enum, case_insensitive(true))]
+    pub sort: Option<SortOption>,
+
+    /// Direction of the ordering when sorting
+    #[clap(short, long, default_value = "ASC", arg_enum, case_insensitive(true))]
+    pub order: OrderOption,
 }
 
 /// Updates children of part in the BOM Server
@@ -142,6 +396,34 @@ pub struct UpdatePart {
     /// Action for updating the children of a part
     #[clap(short, long, default_value = "ADD", arg_enum, case_insensitive(true))]
     pub action: ActionOption,
+
+    /// Only update the part if it is still at this version
+    #[clap(short, long)]
+    pub expected_version: Option<u64>,
+}
+
+/// Moves a part from one parent to another in a single change
+#[derive(Clap)]
+pub struct MovePart {
+    /// Id of part to move
+    #[clap(short, long)]
+    pub id: Uuid,
+
+    /// Id of the parent to move the part from
+    #[clap(short, long)]
+    pub from: Uuid,
+
+    /// Id of the parent to move the part to
+    #[clap(short, long)]
+    pub to: Uuid,
+}
+
+/// Finds the assemblies a part is a direct child of
+#[derive(Clap)]
+pub struct GetParents {
+    /// Part id to retrieve parents of
+    #[clap(short, long)]
+    pub id: Uuid,
 }
 
 /// Finds all assemblies which contain a part
@@ -150,13 +432,1472 @@ pub struct GetContained {
     /// Part id to retrieve contained assemblies from
     #[clap(short, long)]
     pub id: Uuid,
+
+    /// Only find assemblies up to this many levels above the part
+    #[clap(short, long)]
+    pub depth: Option<usize>,
+}
+
+/// Imports parts and their relationships from a CSV file
+///
+/// In the `parent_child` format the file must have a header row with the
+/// columns `parent`, `child` and optionally `quantity`. Parts are referenced by
+/// name, any part not already present on the server is created, and each row
+/// links the child to the parent. Rows with an empty `child` only create the
+/// parent part.
+///
+/// In the `indented` format the file is a classical indented BOM with the
+/// columns `Level`, `Part Number`, `Description` and `Qty`, where each part is a
+/// child of the closest row above it with a level one lower. Rows at the level
+/// of the first row are top level parts. Descriptions are not stored by the
+/// server and are ignored. Rows whose level doesn't fit the tree are reported
+/// and skipped along with the rows below them.
+#[derive(Clap)]
+pub struct Import {
+    /// Path to the CSV file to import
+    #[clap(short, long)]
+    pub file: PathBuf,
+
+    /// Layout of the CSV file
+    #[clap(
+        short = 'F',
+        long,
+        default_value = "PARENT_CHILD",
+        arg_enum,
+        case_insensitive(true)
+    )]
+    pub format: ImportFormat,
+}
+
+/// Single parent/child relationship read from an import file
+#[derive(Deserialize)]
+struct ImportRow {
+    parent: String,
+    child: String,
+    quantity: Option<u32>,
+}
+
+/// Single row of an indented BOM, where the level of the part places it in the
+/// tree
+#[derive(Deserialize)]
+struct IndentedRow {
+    #[serde(rename = "Level", alias = "level")]
+    level: String,
+    #[serde(rename = "Part Number", alias = "part_number")]
+    part_number: String,
+    #[serde(rename = "Qty", alias = "qty", default)]
+    qty: Option<String>,
+}
+
+/// Convert an indented BOM into parent/child rows, returning them along with
+/// the structural errors found, each naming its line of the file
+fn read_indented(file: &Path) -> anyhow::Result<(Vec<ImportRow>, Vec<String>)> {
+    let mut rows = Vec::new();
+    let mut failures = Vec::new();
+    // parts above the current row, with their levels
+    let mut stack: Vec<(u32, String)> = Vec::new();
+    let mut top_level = None;
+    // level of a skipped row, whose descendants are skipped too
+    let mut skipped: Option<u32> = None;
+    for (index, record) in csv::Reader::from_path(file)?
+        .deserialize::<IndentedRow>()
+        .enumerate()
+    {
+        // line 1 is the header row
+        let line = index + 2;
+        let row = match record {
+            Ok(row) => row,
+            Err(e) => {
+                failures.push(format!("Line {}: unreadable row: {}", line, e));
+                continue;
+            }
+        };
+        let level = match row.level.trim().trim_start_matches('.').parse::<u32>() {
+            Ok(level) => level,
+            Err(_) => {
+                failures.push(format!("Line {}: invalid level {:?}", line, row.level));
+                continue;
+            }
+        };
+        if skipped.is_some_and(|skipped| level > skipped) {
+            failures.push(format!(
+                "Line {}: skipped {:?}, its parent was skipped",
+                line, row.part_number
+            ));
+            continue;
+        }
+        skipped = None;
+        let top_level = *top_level.get_or_insert(level);
+        let error = if row.part_number.trim().is_empty() {
+            Some("missing part number".to_string())
+        } else if level < top_level {
+            Some(format!(
+                "level {} is above the top level {}",
+                level, top_level
+            ))
+        } else {
+            None
+        };
+        let quantity = match row.qty.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(qty) => qty.parse::<u32>().map(Some).map_err(|_| qty),
+        };
+        let error = error.or_else(|| {
+            quantity
+                .err()
+                .map(|qty| format!("invalid quantity {:?}", qty))
+        });
+        while stack.last().is_some_and(|(above, _)| *above >= level) {
+            stack.pop();
+        }
+        let error = error.or_else(|| match stack.last() {
+            Some((above, _)) if level != above + 1 => {
+                Some(format!("level {} doesn't follow level {}", level, above))
+            }
+            None if level != top_level => Some(format!(
+                "level {} has no parent at level {}",
+                level,
+                level.saturating_sub(1)
+            )),
+            _ => None,
+        });
+        if let Some(error) = error {
+            failures.push(format!("Line {}: {}", line, error));
+            skipped = Some(level);
+            continue;
+        }
+        let name = row.part_number.trim().to_string();
+        rows.push(match stack.last() {
+            Some((_, parent)) => ImportRow {
+                parent: parent.clone(),
+                child: name.clone(),
+                quantity: quantity.unwrap_or_default(),
+            },
+            None => ImportRow {
+                parent: name.clone(),
+                child: String::new(),
+                quantity: None,
+            },
+        });
+        stack.push((level, name));
+    }
+    Ok((rows, failures))
+}
+
+/// Running tally of import results, printed once the import completes
+#[derive(Default)]
+struct ImportSummary {
+    created: usize,
+    existing: usize,
+    linked: usize,
+    skipped: Vec<String>,
+    failures: Vec<String>,
+}
+
+/// Progress bar over the steps of a bulk operation, drawn on stderr while it
+/// is a terminal
+///
+/// Lines printed while it is shown must go through `ProgressBar::suspend` so
+/// they aren't overwritten.
+fn progress_bar(len: usize, message: &'static str) -> ProgressBar {
+    let bar = ProgressBar::new(len as u64);
+    if let Ok(style) = ProgressStyle::with_template("{msg:16} [{bar:40}] {pos}/{len} ({elapsed})") {
+        bar.set_style(style.progress_chars("=> "));
+    }
+    bar.set_message(message);
+    bar
+}
+
+/// Print the lines describing skipped steps and failures below a summary,
+/// failing the command when any step failed
+fn print_details(skipped: &[String], failures: &[String]) -> anyhow::Result<()> {
+    for line in skipped.iter().chain(failures) {
+        println!("  {}", line);
+    }
+    match failures.len() {
+        0 => Ok(()),
+        failed => Err(CliError::Partial { failed }.into()),
+    }
+}
+
+async fn import(context: &client::ClientContext, subopts: &Import) -> anyhow::Result<()> {
+    let mut summary = ImportSummary::default();
+    let rows = match subopts.format {
+        ImportFormat::ParentChild => csv::Reader::from_path(&subopts.file)?
+            .deserialize()
+            .collect::<Result<Vec<ImportRow>, _>>()?,
+        ImportFormat::Indented => {
+            let (rows, failures) = read_indented(&subopts.file)?;
+            summary.failures = failures;
+            rows
+        }
+    };
+    import_rows(context, &rows, summary).await
+}
+
+/// Create the parts named by import rows and link them, reporting the results
+/// along with any failures already in the summary
+async fn import_rows(
+    context: &client::ClientContext,
+    rows: &[ImportRow],
+    mut summary: ImportSummary,
+) -> anyhow::Result<()> {
+    // Resolve names of parts already on the server
+    let response = client::list_parts(context, PartsListFilter::All, None).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to list the parts already on the server"));
+    }
+    let mut ids: HashMap<String, Uuid> = response
+        .data
+        .unwrap_or_default()
+        .into_iter()
+        .map(|part| (part.name, part.id))
+        .collect();
+
+    // Create any parts that are missing, in order of first appearance
+    let mut seen = HashSet::new();
+    let names: Vec<&String> = rows
+        .iter()
+        .flat_map(|row| vec![&row.parent, &row.child])
+        .filter(|name| !name.is_empty() && seen.insert(*name))
+        .collect();
+    let bar = progress_bar(names.len(), "Creating parts");
+    for name in names {
+        bar.inc(1);
+        if ids.contains_key(name) {
+            summary.existing += 1;
+            continue;
+        }
+        let response = client::create_part(context, name, None).await?;
+        match (response.error_message(), response.data) {
+            (None, Some(data)) if !data.is_empty() => {
+                bar.suspend(|| println!("Created part {:?} ({})", name, data[0].id));
+                ids.insert(name.clone(), data[0].id);
+                summary.created += 1;
+            }
+            (Some(e), _) => summary
+                .failures
+                .push(format!("Failed to create part {:?}: {}", name, e)),
+            _ => summary
+                .failures
+                .push(format!("Failed to create part {:?}: empty response", name)),
+        }
+    }
+    bar.finish_and_clear();
+
+    // Link each child to its parent
+    let bar = progress_bar(rows.len(), "Linking parts");
+    for (line, row) in rows.iter().enumerate() {
+        bar.inc(1);
+        // Rows without a child only declare a standalone part
+        if row.child.is_empty() {
+            continue;
+        }
+        let (parent, child) = match (ids.get(&row.parent), ids.get(&row.child)) {
+            (Some(parent), Some(child)) => (parent, child),
+            _ => {
+                summary.skipped.push(format!(
+                    "Row {}: skipped link {:?} -> {:?}, part missing",
+                    line + 1,
+                    row.parent,
+                    row.child
+                ));
+                continue;
+            }
+        };
+        let response =
+            client::update_part(context, parent, &[*child], PartsListUpdate::Add, None).await?;
+        if let Some(e) = response.error_message() {
+            summary.failures.push(format!(
+                "Row {}: failed to link {:?} -> {:?}: {}",
+                line + 1,
+                row.parent,
+                row.child,
+                e
+            ));
+        } else {
+            bar.suspend(|| println!("Linked {:?} -> {:?}", row.parent, row.child));
+            summary.linked += 1;
+        }
+        if let Some(quantity) = row.quantity.filter(|quantity| *quantity != 1) {
+            let edit = RelationshipEdit {
+                quantity: Some(quantity.into()),
+                notes: None,
+            };
+            let id = Relationship::id_for(parent, child);
+            let response = client::edit_relationship(context, &id, &edit).await?;
+            if let Some(e) = response.error_message() {
+                summary.failures.push(format!(
+                    "Row {}: failed to set quantity of {:?} -> {:?}: {}",
+                    line + 1,
+                    row.parent,
+                    row.child,
+                    e
+                ));
+            }
+        }
+    }
+
+    bar.finish_and_clear();
+
+    println!(
+        "Import complete: {} parts created, {} already present, {} links added, {} skipped, {} failures",
+        summary.created,
+        summary.existing,
+        summary.linked,
+        summary.skipped.len(),
+        summary.failures.len()
+    );
+    print_details(&summary.skipped, &summary.failures)
+}
+
+/// Imports the components of a KiCad schematic BOM under an assembly
+///
+/// Both grouped CSV BOMs and the XML netlist exported for BOM plugins are read,
+/// depending on the extension of the file. Components sharing a value and
+/// footprint become a single part named by both, such as `10k R_0603`, which
+/// is created if not already present and linked to the assembly. Components
+/// marked as do not populate are left out. The quantity of each part is set on
+/// its link to the assembly, while reference designators are printed for each
+/// part.
+#[derive(Clap)]
+pub struct ImportKicad {
+    /// Path to the CSV or XML file exported by KiCad
+    #[clap(short, long)]
+    pub file: PathBuf,
+
+    /// Name of the assembly to import the components under, created if missing
+    #[clap(short, long)]
+    pub assembly: String,
+}
+
+async fn import_kicad(
+    context: &client::ClientContext,
+    subopts: &ImportKicad,
+) -> anyhow::Result<()> {
+    let xml = subopts
+        .file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xml"));
+    let groups = if xml {
+        kicad::read_xml(&std::fs::read_to_string(&subopts.file)?)?
+    } else {
+        kicad::read_csv(File::open(&subopts.file)?)?
+    };
+    for group in &groups {
+        println!(
+            "{:?}: {} ({})",
+            group.name,
+            group.references.join(", "),
+            group.quantity
+        );
+    }
+    let mut rows = vec![ImportRow {
+        parent: subopts.assembly.clone(),
+        child: String::new(),
+        quantity: None,
+    }];
+    rows.extend(groups.into_iter().map(|group| ImportRow {
+        parent: subopts.assembly.clone(),
+        child: group.name,
+        quantity: Some(group.quantity),
+    }));
+    import_rows(context, &rows, ImportSummary::default()).await
+}
+
+/// Exports an assembly, or every part on the server, to a local file
+///
+/// The `csv` format uses the same `parent,child,quantity` layout accepted by
+/// `import`, followed by the external ids of the parent and child, `json`
+/// writes the full part records and `dot` writes a Graphviz digraph of the
+/// relationships.
+#[derive(Clap)]
+pub struct Export {
+    /// Id of the assembly to export, all parts are exported if omitted
+    #[clap(short, long)]
+    pub id: Option<Uuid>,
+
+    /// Output format for the exported parts, JSON unless set by the profile
+    #[clap(short, long, arg_enum, case_insensitive(true))]
+    pub format: Option<ExportFormat>,
+
+    /// File to write the export to, printed to stdout if omitted
+    #[clap(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+/// Single row written by a CSV export, matching the layout read by import
+/// with the external ids of both parts added as `system=id` pairs separated
+/// by semicolons
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    parent: &'a str,
+    child: &'a str,
+    quantity: Option<u64>,
+    parent_external_ids: String,
+    child_external_ids: String,
+}
+
+fn format_external_ids(part: &Part) -> String {
+    part.external_ids
+        .iter()
+        .map(|(system, id)| format!("{}={}", system, id))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/// Fetch the parts making up an export, either a single assembly with all of
+/// its descendants or the entire parts list
+async fn fetch_export_parts(
+    context: &client::ClientContext,
+    id: Option<&Uuid>,
+) -> anyhow::Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let responses = match id {
+        Some(id) => vec![
+            client::get_part(context, id).await?,
+            client::get_children(context, id, PartsListFilter::All, None).await?,
+        ],
+        None => vec![client::list_parts(context, PartsListFilter::All, None).await?],
+    };
+    for response in responses {
+        if let Some(e) = server_error(&response) {
+            return Err(anyhow::Error::new(e).context("Failed to fetch parts"));
+        }
+        parts.extend(response.data.unwrap_or_default());
+    }
+    parts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(parts)
+}
+
+fn write_export(parts: &[Part], format: ExportFormat, out: &mut dyn Write) -> anyhow::Result<()> {
+    let names: HashMap<&Uuid, &str> = parts.iter().map(|p| (&p.id, p.name.as_str())).collect();
+    match format {
+        ExportFormat::Json => {
+            writeln!(out, "{}", to_string_pretty(parts)?)?;
+        }
+        ExportFormat::Csv => {
+            let by_id: HashMap<&Uuid, &Part> = parts.iter().map(|p| (&p.id, p)).collect();
+            let mut writer = csv::Writer::from_writer(out);
+            for part in parts {
+                // Only relationships within the exported set are written
+                let mut children: Vec<&Part> = part
+                    .children
+                    .iter()
+                    .filter_map(|id| by_id.get(id).copied())
+                    .collect();
+                children.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+                for child in &children {
+                    writer.serialize(ExportRow {
+                        parent: &part.name,
+                        child: &child.name,
+                        quantity: Some(part.quantity_of(&child.id)),
+                        parent_external_ids: format_external_ids(part),
+                        child_external_ids: format_external_ids(child),
+                    })?;
+                }
+                let has_parent = part.parents.iter().any(|id| names.contains_key(id));
+                if children.is_empty() && !has_parent {
+                    writer.serialize(ExportRow {
+                        parent: &part.name,
+                        child: "",
+                        quantity: None,
+                        parent_external_ids: format_external_ids(part),
+                        child_external_ids: String::new(),
+                    })?;
+                }
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Dot => {
+            writeln!(out, "digraph bom {{")?;
+            for part in parts {
+                writeln!(out, "    \"{}\" [label={:?}];", part.id, part.name)?;
+            }
+            for part in parts {
+                let mut children: Vec<&Uuid> = part
+                    .children
+                    .iter()
+                    .filter(|id| names.contains_key(id))
+                    .collect();
+                children.sort_unstable();
+                for child in children {
+                    writeln!(out, "    \"{}\" -> \"{}\";", part.id, child)?;
+                }
+            }
+            writeln!(out, "}}")?;
+        }
+    }
+    Ok(())
+}
+
+async fn export(context: &client::ClientContext, subopts: Export) -> anyhow::Result<()> {
+    let spinner = ProgressBar::new_spinner().with_message("Fetching parts");
+    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    let parts = fetch_export_parts(context, subopts.id.as_ref()).await;
+    spinner.finish_and_clear();
+    let parts = parts?;
+    let format = subopts.format.unwrap_or(ExportFormat::Json);
+    match &subopts.out {
+        Some(path) => {
+            let mut file = File::create(path)?;
+            write_export(&parts, format, &mut file)?;
+            println!("Exported {} parts to {}", parts.len(), path.display());
+        }
+        None => write_export(&parts, format, &mut io::stdout())?,
+    }
+    Ok(())
+}
+
+/// Compares the exploded BOMs of two assemblies
+///
+/// Components only found under `b` are reported as added, those only found
+/// under `a` as removed, and components in both whose children differ as changed.
+#[derive(Clap)]
+pub struct Diff {
+    /// Id of the assembly to compare from
+    #[clap(long)]
+    pub a: Uuid,
+
+    /// Id of the assembly to compare against
+    #[clap(long)]
+    pub b: Uuid,
+}
+
+/// Fetch an assembly along with every part beneath it
+async fn fetch_assembly(
+    context: &client::ClientContext,
+    id: &Uuid,
+) -> anyhow::Result<(Part, HashMap<Uuid, Part>)> {
+    let response = client::get_part(context, id).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context(format!("Failed to fetch part {}", id)));
+    }
+    let root = response
+        .data
+        .and_then(|mut data| data.pop())
+        .ok_or_else(|| anyhow::anyhow!("Failed to fetch part {}: empty response", id))?;
+    let response = client::get_children(context, id, PartsListFilter::All, None).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context(format!("Failed to fetch children of {}", id)));
+    }
+    let children = response
+        .data
+        .unwrap_or_default()
+        .into_iter()
+        .map(|part| (part.id, part))
+        .collect();
+    Ok((root, children))
+}
+
+async fn diff(context: &client::ClientContext, subopts: &Diff) -> anyhow::Result<()> {
+    let (a, a_parts) = fetch_assembly(context, &subopts.a).await?;
+    let (b, b_parts) = fetch_assembly(context, &subopts.b).await?;
+    let child_names = |part: &Part, parts: &HashMap<Uuid, Part>| {
+        let mut names: Vec<String> = part
+            .children
+            .iter()
+            .map(|id| {
+                parts
+                    .get(id)
+                    .map_or_else(|| id.to_string(), |p| p.name.clone())
+            })
+            .collect();
+        names.sort_unstable();
+        names
+    };
+
+    println!("{}", format!("--- {} ({})", a.name, a.id).bold());
+    println!("{}", format!("+++ {} ({})", b.name, b.id).bold());
+
+    let mut lines: Vec<(&str, String)> = Vec::new();
+    for (id, part) in &a_parts {
+        match b_parts.get(id) {
+            None => lines.push(("-", part.name.clone())),
+            Some(other) if part.children != other.children => {
+                let before = child_names(part, &a_parts);
+                let after = child_names(other, &b_parts);
+                lines.push((
+                    "~",
+                    format!(
+                        "{}: [{}] -> [{}]",
+                        part.name,
+                        before.join(", "),
+                        after.join(", ")
+                    ),
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+    for (id, part) in &b_parts {
+        if !a_parts.contains_key(id) {
+            lines.push(("+", part.name.clone()));
+        }
+    }
+    lines.sort_by(|x, y| x.1.cmp(&y.1));
+
+    for (marker, text) in &lines {
+        let line = format!("{} {}", marker, text);
+        match *marker {
+            "+" => println!("{}", line.green()),
+            "-" => println!("{}", line.red()),
+            _ => println!("{}", line.yellow()),
+        }
+    }
+    let count = |m: &str| lines.iter().filter(|(marker, _)| *marker == m).count();
+    println!(
+        "{} added, {} removed, {} changed",
+        count("+"),
+        count("-"),
+        count("~")
+    );
+    Ok(())
+}
+
+/// Watches a part and its subtree, printing a line whenever either changes
+///
+/// Polls which can't reach the server are retried, waiting twice as long
+/// after each failure up to five minutes, while any other error ends the
+/// watch.
+#[derive(Clap)]
+pub struct Watch {
+    /// Id of the part to watch
+    #[clap(short, long)]
+    pub id: Uuid,
+
+    /// Seconds to wait between polls of the server
+    #[clap(short = 'n', long, default_value = "2", validator = positive_seconds)]
+    pub interval: u64,
+}
+
+/// Longest wait between polls of `watch` retrying while the server can't be
+/// reached
+const MAX_WATCH_BACKOFF: std::time::Duration = std::time::Duration::from_secs(300);
+
+fn positive_seconds(value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err(String::from("must be at least 1 second")),
+        Ok(seconds) => Ok(seconds),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Print the differences between two snapshots of a watched subtree
+fn report_changes(previous: &HashMap<Uuid, Part>, current: &HashMap<Uuid, Part>) {
+    for (id, part) in current {
+        match previous.get(id) {
+            None => println!("{} ({}) added to subtree", part.name, id),
+            Some(old) if old.children != part.children || old.parents != part.parents => {
+                println!(
+                    "{} ({}) relationships changed: {} children, {} parents",
+                    part.name,
+                    id,
+                    part.children.len(),
+                    part.parents.len()
+                )
+            }
+            Some(_) => {}
+        }
+    }
+    for (id, part) in previous {
+        if !current.contains_key(id) {
+            println!("{} ({}) removed from subtree", part.name, id);
+        }
+    }
+}
+
+async fn watch(context: &client::ClientContext, subopts: &Watch) -> anyhow::Result<()> {
+    let interval = std::time::Duration::from_secs(subopts.interval);
+    let (root, children) = fetch_assembly(context, &subopts.id).await?;
+    println!(
+        "Watching {} ({}) with {} parts in subtree",
+        root.name,
+        root.id,
+        children.len()
+    );
+    let mut previous = children;
+    previous.insert(root.id, root);
+    let mut wait = interval;
+    loop {
+        tokio::time::sleep(wait).await;
+        let current = match fetch_assembly(context, &subopts.id).await {
+            Ok((root, mut children)) => {
+                children.insert(root.id, root);
+                children
+            }
+            Err(e) if offline::is_unreachable(&e) => {
+                wait = (wait * 2).min(MAX_WATCH_BACKOFF.max(interval));
+                eprintln!("{}, retrying in {}s", describe(&e), wait.as_secs());
+                continue;
+            }
+            Err(e) => return Err(e.context(format!("Stopped watching {}", subopts.id))),
+        };
+        wait = interval;
+        report_changes(&previous, &current);
+        previous = current;
+    }
+}
+
+/// Deletes a part along with every descendant not used elsewhere
+///
+/// The parts that would be removed are listed first and confirmation is
+/// requested before anything is deleted, unless `--yes` is given.
+#[derive(Clap)]
+pub struct DeleteTree {
+    /// Id of the part at the top of the subtree to delete
+    #[clap(short, long)]
+    pub id: Uuid,
+
+    /// Skip the confirmation prompt
+    #[clap(short, long)]
+    pub yes: bool,
+}
+
+async fn delete_tree(context: &client::ClientContext, subopts: &DeleteTree) -> anyhow::Result<()> {
+    let response = client::delete_tree(context, &subopts.id, true).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to preview delete"));
+    }
+    let mut preview = response.data.unwrap_or_default();
+    preview.sort_by(|a, b| a.name.cmp(&b.name));
+    println!("The following {} parts will be deleted:", preview.len());
+    for part in &preview {
+        println!("  {} ({})", part.name, part.id);
+    }
+
+    if !subopts.yes {
+        print!("Continue? [y/N] ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted, nothing was deleted");
+            return Ok(());
+        }
+    }
+
+    let response = client::delete_tree(context, &subopts.id, false).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to delete"));
+    }
+    println!(
+        "Deleted {} parts",
+        response.data.map_or(0, |data| data.len())
+    );
+    Ok(())
+}
+
+/// Reproduces every part and relationship of one BOM Server on another, such
+/// as when promoting a staging environment to production
+///
+/// Parts are matched by name, and those missing from the destination are
+/// created with the id they have on the source. Children missing from a part
+/// on the destination are linked, and the quantity and notes of each link are
+/// copied. With `--prune`, links and parts on the destination which aren't on
+/// the source are removed.
+#[derive(Clap)]
+pub struct Sync {
+    /// URL of the server to copy the parts from
+    #[clap(long)]
+    pub from: Url,
+
+    /// URL of the server to copy the parts to
+    #[clap(long)]
+    pub to: Url,
+
+    /// Remove links and parts not found on the source from the destination
+    #[clap(long)]
+    pub prune: bool,
+}
+
+/// Running tally of sync results, printed once the sync completes
+#[derive(Default)]
+struct SyncSummary {
+    created: usize,
+    existing: usize,
+    linked: usize,
+    updated: usize,
+    unlinked: usize,
+    deleted: usize,
+    skipped: Vec<String>,
+    failures: Vec<String>,
+}
+
+/// Fetch every part on a server, keyed by name
+async fn fetch_by_name(
+    context: &client::ClientContext,
+    server: &Url,
+) -> anyhow::Result<HashMap<String, Part>> {
+    let response = client::list_parts(context, PartsListFilter::All, None).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context(format!("Failed to list parts on {}", server)));
+    }
+    let mut parts = HashMap::new();
+    for part in response.data.unwrap_or_default() {
+        if parts.contains_key(&part.name) {
+            return Err(anyhow::anyhow!(
+                "Several parts on {} are named {:?}, so they can't be matched by name",
+                server,
+                part.name
+            ));
+        }
+        parts.insert(part.name.clone(), part);
+    }
+    Ok(parts)
+}
+
+/// Copy the parts of one server to another, authenticating with the `token`
+/// of the profile on both
+async fn sync(
+    subopts: &Sync,
+    format: client::WireFormat,
+    token: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut source = client::ClientContext::new(subopts.from.clone()).with_format(format);
+    let mut target = client::ClientContext::new(subopts.to.clone()).with_format(format);
+    if let Some(token) = token {
+        source = source.with_token(token.into());
+        target = target.with_token(token.into());
+    }
+    let from = fetch_by_name(&source, &subopts.from).await?;
+    let mut to = fetch_by_name(&target, &subopts.to).await?;
+    let mut summary = SyncSummary::default();
+
+    // Create any parts that are missing, in order of name
+    let mut names: Vec<&String> = from.keys().collect();
+    names.sort_unstable();
+    let bar = progress_bar(names.len(), "Creating parts");
+    for name in &names {
+        bar.inc(1);
+        if to.contains_key(*name) {
+            summary.existing += 1;
+            continue;
+        }
+        let response = client::create_part(&target, name, Some(from[*name].id)).await?;
+        match (response.error_message(), response.data) {
+            (None, Some(mut data)) if !data.is_empty() => {
+                let part = data.remove(0);
+                bar.suspend(|| println!("Created part {:?} ({})", name, part.id));
+                to.insert((*name).clone(), part);
+                summary.created += 1;
+            }
+            (Some(e), _) => summary
+                .failures
+                .push(format!("Failed to create part {:?}: {}", name, e)),
+            _ => summary
+                .failures
+                .push(format!("Failed to create part {:?}: empty response", name)),
+        }
+    }
+    bar.finish_and_clear();
+    let source_names: HashMap<&Uuid, &str> =
+        from.values().map(|p| (&p.id, p.name.as_str())).collect();
+    let target_names: HashMap<&Uuid, &str> =
+        to.values().map(|p| (&p.id, p.name.as_str())).collect();
+
+    // Link the children of each part, copying the metadata of each link
+    let bar = progress_bar(names.len(), "Linking parts");
+    for name in &names {
+        bar.inc(1);
+        let (part, parent) = match (from.get(*name), to.get(*name)) {
+            (Some(part), Some(parent)) => (part, parent),
+            _ => continue,
+        };
+        let mut missing = Vec::new();
+        let mut links = Vec::new();
+        for child in &part.children {
+            let child_name = source_names[child];
+            let target_child = match to.get(child_name) {
+                Some(target_child) => target_child.id,
+                None => {
+                    summary.skipped.push(format!(
+                        "Skipped link {:?} -> {:?}, part missing",
+                        name, child_name
+                    ));
+                    continue;
+                }
+            };
+            if !parent.children.contains(&target_child) {
+                missing.push(target_child);
+            }
+            links.push((child, target_child, child_name));
+        }
+        if !missing.is_empty() {
+            let response =
+                client::update_part(&target, &parent.id, &missing, PartsListUpdate::Add, None)
+                    .await?;
+            if let Some(e) = response.error_message() {
+                summary
+                    .failures
+                    .push(format!("Failed to link children of {:?}: {}", name, e));
+                continue;
+            }
+            bar.suspend(|| println!("Linked {} children to {:?}", missing.len(), name));
+            summary.linked += missing.len();
+        }
+        for (child, target_child, child_name) in links {
+            let wanted = part.relationships.get(child);
+            let current = parent.relationships.get(&target_child);
+            let quantity = wanted.map_or(1, |r| r.quantity);
+            let notes = wanted.map_or("", |r| r.notes.as_str());
+            if current.map_or(1, |r| r.quantity) == quantity
+                && current.map_or("", |r| r.notes.as_str()) == notes
+            {
+                continue;
+            }
+            let edit = RelationshipEdit {
+                quantity: Some(quantity),
+                notes: Some(notes.to_string()),
+            };
+            let id = Relationship::id_for(&parent.id, &target_child);
+            let response = client::edit_relationship(&target, &id, &edit).await?;
+            if let Some(e) = response.error_message() {
+                summary.failures.push(format!(
+                    "Failed to update link {:?} -> {:?}: {}",
+                    name, child_name, e
+                ));
+            } else {
+                summary.updated += 1;
+            }
+        }
+    }
+
+    bar.finish_and_clear();
+
+    if subopts.prune {
+        // Unlink children the source part doesn't have, then delete the parts
+        // the source doesn't have at all
+        let mut target_parts: Vec<&Part> = to.values().collect();
+        target_parts.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let bar = progress_bar(target_parts.len() * 2, "Pruning parts");
+        for parent in &target_parts {
+            bar.inc(1);
+            let wanted: HashSet<&str> = from.get(&parent.name).map_or_else(HashSet::new, |part| {
+                part.children.iter().map(|id| source_names[id]).collect()
+            });
+            let extra: Vec<Uuid> = parent
+                .children
+                .iter()
+                .filter(|id| {
+                    target_names
+                        .get(id)
+                        .is_none_or(|child| !wanted.contains(child))
+                })
+                .copied()
+                .collect();
+            if extra.is_empty() {
+                continue;
+            }
+            let response =
+                client::update_part(&target, &parent.id, &extra, PartsListUpdate::Remove, None)
+                    .await?;
+            if let Some(e) = response.error_message() {
+                summary.failures.push(format!(
+                    "Failed to unlink children of {:?}: {}",
+                    parent.name, e
+                ));
+            } else {
+                bar.suspend(|| {
+                    println!("Unlinked {} children from {:?}", extra.len(), parent.name)
+                });
+                summary.unlinked += extra.len();
+            }
+        }
+        for part in target_parts {
+            bar.inc(1);
+            if from.contains_key(&part.name) {
+                continue;
+            }
+            let response = client::delete_part(&target, &part.id, None, true).await?;
+            if let Some(e) = response.error_message() {
+                summary
+                    .failures
+                    .push(format!("Failed to delete part {:?}: {}", part.name, e));
+            } else {
+                bar.suspend(|| println!("Deleted part {:?} ({})", part.name, part.id));
+                summary.deleted += 1;
+            }
+        }
+        bar.finish_and_clear();
+    }
+
+    println!(
+        "Sync complete: {} parts created, {} already present, {} links added, {} links updated, {} links removed, {} parts deleted, {} skipped, {} failures",
+        summary.created,
+        summary.existing,
+        summary.linked,
+        summary.updated,
+        summary.unlinked,
+        summary.deleted,
+        summary.skipped.len(),
+        summary.failures.len()
+    );
+    print_details(&summary.skipped, &summary.failures)
+}
+
+/// Downloads a compressed archive of every part on the BOM Server
+#[derive(Clap)]
+pub struct Backup {
+    /// File to write the archive to
+    #[clap(short, long)]
+    pub out: PathBuf,
+}
+
+/// Replaces every part on the BOM Server with those in a backup archive
+#[derive(Clap)]
+pub struct Restore {
+    /// Archive previously written by the backup subcommand
+    #[clap(short, long)]
+    pub file: PathBuf,
+
+    /// Only check the archive, leaving the parts on the server unchanged
+    #[clap(short, long)]
+    pub dry_run: bool,
+}
+
+/// Print statistics of the parts on the BOM Server: the number of parts of
+/// each kind and in each category, the depth of the deepest assembly and the
+/// components used by the most assemblies
+#[derive(Clap)]
+pub struct Stats {
+    /// Number of most used components to list
+    #[clap(short, long, default_value = "10")]
+    pub top: usize,
+}
+
+async fn stats(context: &client::ClientContext, subopts: &Stats) -> anyhow::Result<()> {
+    let response = client::get_stats(context).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to fetch statistics"));
+    }
+    let stats = response
+        .stats
+        .ok_or_else(|| anyhow::anyhow!("Failed to fetch statistics: empty response"))?;
+    println!("Parts: {}", stats.total);
+    println!("  top level: {}", stats.top_level);
+    println!("  subassemblies: {}", stats.subassembly);
+    println!("  components: {}", stats.component);
+    println!("  orphans: {}", stats.orphan);
+    println!("Max depth: {}", stats.max_depth);
+    println!("Average fan-out: {:.2}", stats.average_fan_out);
+
+    let response = client::list_parts(context, PartsListFilter::All, None).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to fetch parts"));
+    }
+    let parts = response.data.unwrap_or_default();
+    let response = client::list_categories(context).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to fetch categories"));
+    }
+    let names: HashMap<Uuid, String> = response
+        .categories
+        .unwrap_or_default()
+        .into_iter()
+        .map(|category| (category.id, category.name))
+        .collect();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for part in &parts {
+        let category = match part.category {
+            Some(id) => names.get(&id).cloned().unwrap_or_else(|| id.to_string()),
+            None => "(uncategorized)".to_string(),
+        };
+        *counts.entry(category).or_default() += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    println!();
+    println!("Parts by category:");
+    for (category, count) in &counts {
+        println!("  {}: {}", category, count);
+    }
+
+    let response = client::top_used(context, subopts.top).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to rank components"));
+    }
+    let ranked = response.data.unwrap_or_default();
+    let used_by = response.used_by.unwrap_or_default();
+    println!();
+    println!("Most used components:");
+    for (part, used_by) in ranked.iter().zip(used_by) {
+        println!("  {:>4}  {} ({})", used_by, part.name, part.id);
+    }
+    Ok(())
+}
+
+/// Check the parts on the BOM Server for broken relationships, cycles and
+/// duplicate names, failing when any are found
+#[derive(Clap)]
+pub struct Validate {}
+
+/// Name and id of a part, or just the id of a part which doesn't exist
+fn describe_part(names: &HashMap<Uuid, String>, id: &Uuid) -> String {
+    match names.get(id) {
+        Some(name) => format!("{:?} ({})", name, id),
+        None => format!("missing part {}", id),
+    }
+}
+
+async fn validate(context: &client::ClientContext) -> anyhow::Result<()> {
+    let response = client::validate(context).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to validate parts"));
+    }
+    let report = response
+        .validation
+        .ok_or_else(|| anyhow::anyhow!("Failed to validate parts: empty response"))?;
+    let response = client::list_parts(context, PartsListFilter::All, None).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to fetch parts"));
+    }
+    let names: HashMap<Uuid, String> = response
+        .data
+        .unwrap_or_default()
+        .into_iter()
+        .map(|part| (part.id, part.name))
+        .collect();
+    println!(
+        "Checked {} parts, found {} violations",
+        report.checked,
+        report.violations.len()
+    );
+    for violation in &report.violations {
+        let line = match violation {
+            Violation::MissingParentBacklink { parent, child } => format!(
+                "missing backlink: {} lists {} as a child, which doesn't list it as a parent",
+                describe_part(&names, parent),
+                describe_part(&names, child)
+            ),
+            Violation::MissingChildBacklink { parent, child } => format!(
+                "missing backlink: {} lists {} as a parent, which doesn't list it as a child",
+                describe_part(&names, child),
+                describe_part(&names, parent)
+            ),
+            Violation::DanglingReference { id, relative } => format!(
+                "dangling reference: {} refers to missing part {}",
+                describe_part(&names, id),
+                relative
+            ),
+            Violation::Cycle { path } => {
+                let path: Vec<String> = path.iter().map(|id| describe_part(&names, id)).collect();
+                format!("cycle: {}", path.join(" -> "))
+            }
+            Violation::DuplicateName { name, ids } => {
+                let ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+                format!("duplicate name: {:?} is used by {}", name, ids.join(", "))
+            }
+        };
+        println!("  {}", line);
+    }
+    match report.violations.len() {
+        0 => Ok(()),
+        violations => Err(CliError::Invalid { violations }.into()),
+    }
+}
+
+/// Search parts by name, printing the matches with their ids
+#[derive(Clap)]
+pub struct Search {
+    /// Text to look for in the names of parts
+    pub query: String,
+
+    /// Only list matches of the variants listed
+    #[clap(short, long, default_value = "ALL", arg_enum, case_insensitive(true))]
+    pub filter: FilterOption,
+
+    /// Attributes the matches must have, as NAME=VALUE (e.g. package=0603)
+    #[clap(short, long, multiple_occurrences(true), number_of_values(1))]
+    pub tag: Vec<String>,
+
+    /// Also match names with typos, ranked by similarity
+    #[clap(long)]
+    pub fuzzy: bool,
+}
+
+async fn search(context: &client::ClientContext, subopts: Search) -> anyhow::Result<()> {
+    let mut tags = Vec::new();
+    for tag in &subopts.tag {
+        let (name, value) = tag
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Tag must be given as NAME=VALUE: {}", tag))?;
+        tags.push(PartsListPredicate::Attribute(name.into(), value.into()));
+    }
+    let filter = PartsListFilter::from(subopts.filter);
+    let response = client::search(context, &subopts.query, subopts.fuzzy).await?;
+    if let Some(e) = server_error(&response) {
+        return Err(anyhow::Error::new(e).context("Failed to search parts"));
+    }
+    let scores = response.scores.unwrap_or_default();
+    let matches: Vec<(Part, f64)> = response
+        .data
+        .unwrap_or_default()
+        .into_iter()
+        .zip(scores)
+        .filter(|(part, _)| filter.matches(part) && tags.iter().all(|tag| tag.matches(part)))
+        .collect();
+    if matches.is_empty() {
+        println!("No parts match {:?}", subopts.query);
+        return Ok(());
+    }
+    let width = matches
+        .iter()
+        .map(|(part, _)| part.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("NAME".len());
+    println!("{:<36}  {:<width$}  SCORE", "ID", "NAME", width = width);
+    for (part, score) in &matches {
+        println!(
+            "{:<36}  {:<width$}  {:.2}",
+            part.id,
+            part.name,
+            score,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// Makes the changes queued while the server couldn't be reached in offline
+/// mode, reporting those the server refuses
+#[derive(Clap)]
+pub struct Replay {
+    /// List the queued changes without making them
+    #[clap(short, long)]
+    pub list: bool,
+}
+
+/// Queue of the changes made offline for `host`, kept under the XDG data
+/// directory with a file for each server
+fn offline_queue(host: &str) -> anyhow::Result<OfflineQueue> {
+    let dir = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share"))
+        })
+        .ok_or_else(|| anyhow::anyhow!("No home directory to keep the offline queue in"))?;
+    let name: String = host
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    Ok(OfflineQueue::new(
+        dir.join("bom-client")
+            .join("queue")
+            .join(format!("{}.jsonl", name)),
+    ))
+}
+
+/// Make the changes of the queue on the server, reporting on stderr so the
+/// output of the command running after them is left as it is
+async fn replay_queue(
+    context: &client::ClientContext,
+    queue: &OfflineQueue,
+) -> anyhow::Result<ReplayReport> {
+    let report = queue.replay(context).await?;
+    if report.applied > 0 || !report.conflicts.is_empty() {
+        eprintln!(
+            "Replayed queued changes: {} made, {} conflicts, {} still queued",
+            report.applied,
+            report.conflicts.len(),
+            report.pending
+        );
+    }
+    for conflict in &report.conflicts {
+        eprintln!(
+            "  {} {}: {}",
+            "conflict:".yellow().bold(),
+            serde_json::to_string(&conflict.change)?,
+            conflict.error
+        );
+    }
+    if let Some(error) = &report.stopped {
+        eprintln!(
+            "Stopped replaying queued changes, {} still queued: {}",
+            report.pending, error
+        );
+    }
+    Ok(report)
+}
+
+async fn replay(
+    context: &client::ClientContext,
+    queue: &OfflineQueue,
+    subopts: &Replay,
+) -> anyhow::Result<()> {
+    if subopts.list {
+        let changes = queue.pending()?;
+        println!(
+            "{} queued changes in {}",
+            changes.len(),
+            queue.path().display()
+        );
+        for change in &changes {
+            println!("  {}", serde_json::to_string(change)?);
+        }
+        return Ok(());
+    }
+    let report = replay_queue(context, queue).await?;
+    if report.pending > 0 {
+        return Err(CliError::Queued {
+            pending: report.pending,
+        }
+        .into());
+    }
+    match report.conflicts.len() {
+        0 => Ok(()),
+        failed => Err(CliError::Partial { failed }.into()),
+    }
+}
+
+/// Make a change on the server, queueing it instead when offline mode is
+/// enabled and the server can't be reached
+async fn send_change(
+    context: &client::ClientContext,
+    queue: Option<&OfflineQueue>,
+    change: QueuedChange,
+) -> anyhow::Result<()> {
+    match (change.send(context).await, queue) {
+        (Ok(response), _) => print_response(&response),
+        (Err(e), Some(queue)) if offline::is_unreachable(&e) => {
+            queue.push(&change)?;
+            eprintln!(
+                "Server unreachable, queued change until it can be made: {}",
+                serde_json::to_string(&change)?
+            );
+            Ok(())
+        }
+        (Err(e), _) => Err(e),
+    }
+}
+
+/// Exit status of a command failing without the server reporting an error,
+/// e.g. when a file can't be read (invalid usage exits with 2)
+const EXIT_FAILURE: i32 = 1;
+/// Exit status when the server can't be reached
+const EXIT_UNREACHABLE: i32 = 3;
+/// Exit status when only some of the steps of a command failed
+const EXIT_PARTIAL: i32 = 4;
+/// Exit status when validation found problems with the parts
+const EXIT_INVALID: i32 = 5;
+/// Offset added to the code of an error reported by the server, so each
+/// error code exits with its own status
+const EXIT_SERVER_ERROR: i32 = 10;
+
+/// Failures of a command which end the client with a particular exit status
+#[derive(Debug, thiserror::Error)]
+enum CliError {
+    #[error("{message}")]
+    Server {
+        code: PartsErrorCode,
+        message: String,
+    },
+    #[error("{failed} of the steps failed")]
+    Partial { failed: usize },
+    #[error("Parts list has {violations} violations")]
+    Invalid { violations: usize },
+    #[error("Server unreachable, {pending} changes still queued")]
+    Queued { pending: usize },
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Server { code, .. } => EXIT_SERVER_ERROR + *code as i32,
+            CliError::Partial { .. } => EXIT_PARTIAL,
+            CliError::Invalid { .. } => EXIT_INVALID,
+            CliError::Queued { .. } => EXIT_UNREACHABLE,
+        }
+    }
+}
+
+/// The error reported by the server in a response, if any
+fn server_error(response: &Response) -> Option<CliError> {
+    Some(CliError::Server {
+        code: *response.error.as_ref()?.code(),
+        message: response.error_message()?,
+    })
+}
+
+/// Print a response of the server, failing with the error it reports if any
+fn print_response(response: &Response) -> anyhow::Result<()> {
+    if let Some(e) = server_error(response) {
+        return Err(e.into());
+    }
+    println!("{}", to_string_pretty(response)?);
+    let failed = response
+        .deletions
+        .iter()
+        .flatten()
+        .filter(|deletion| deletion.error.is_some())
+        .count();
+    match failed {
+        0 => Ok(()),
+        failed => Err(CliError::Partial { failed }.into()),
+    }
+}
+
+/// Describe why a command failed in a single line, rather than the whole
+/// chain of errors of the http client when the server can't be reached
+fn describe(error: &anyhow::Error) -> String {
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) if e.is_connect() || e.is_timeout() => match e.url() {
+            Some(url) => format!("Couldn't reach {}: {}", url, error.root_cause()),
+            None => format!("Couldn't reach the server: {}", error.root_cause()),
+        },
+        _ => format!("{:#}", error),
+    }
+}
+
+/// Exit status telling scripts why a command failed
+fn exit_code(error: &anyhow::Error) -> i32 {
+    if let Some(e) = error.downcast_ref::<CliError>() {
+        return e.exit_code();
+    }
+    if offline::is_unreachable(error) {
+        EXIT_UNREACHABLE
+    } else {
+        EXIT_FAILURE
+    }
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let opts: Opts = Opts::parse();
-    let base_url = Url::parse(&opts.host)?;
-    let context = client::ClientContext::new(base_url);
+async fn main() {
+    if let Err(e) = run(Opts::parse()).await {
+        eprintln!("{} {}", "error:".red().bold(), describe(&e));
+        std::process::exit(exit_code(&e));
+    }
+}
+
+async fn run(opts: Opts) -> anyhow::Result<()> {
+    let profile = load_profile(&opts)?;
+    let host = opts
+        .host
+        .as_deref()
+        .or(profile.host.as_deref())
+        .unwrap_or("http://localhost:8000");
+    let base_url = Url::parse(host)?;
+    let format = if opts.msgpack {
+        client::WireFormat::MessagePack
+    } else {
+        client::WireFormat::Json
+    };
+    let mut context = client::ClientContext::new(base_url).with_format(format);
+    if let Some(token) = &profile.token {
+        context = context.with_token(token.clone());
+    }
+    let queue = if opts.offline || profile.offline.unwrap_or(false) {
+        Some(offline_queue(host)?)
+    } else {
+        None
+    };
+    if let Some(queue) = &queue {
+        if !matches!(opts.subcmd, SubCommand::Replay(_)) {
+            replay_queue(&context, queue).await?;
+        }
+    }
     match opts.subcmd {
         SubCommand::GetIndex(_) => {
             let response = client::get_index(&context).await?;
@@ -164,47 +1905,139 @@ async fn main() -> anyhow::Result<()> {
             Ok(())
         }
         SubCommand::ListParts(subopts) => {
-            let response = client::list_parts(&context, subopts.filter.into()).await?;
-            println!("{}", to_string_pretty(&response)?);
-            Ok(())
+            let order = subopts.order;
+            let sort = subopts
+                .sort
+                .map(|key| PartsListSort::new(key.into(), order.into()));
+            let mut query = PartsListQuery::new(subopts.filter.into());
+            for predicate in &subopts.predicates {
+                let (key, value) = predicate.split_once('=').ok_or_else(|| {
+                    anyhow::anyhow!("Predicate must be given as KEY=VALUE: {}", predicate)
+                })?;
+                query = query.and(PartsListPredicate::parse(key, value)?);
+            }
+            let response = client::query_parts(&context, &query, sort).await?;
+            print_response(&response)
         }
-        SubCommand::CreatePart(subopts) => {
-            let response = client::create_part(&context, &subopts.name).await?;
-            println!("{}", to_string_pretty(&response)?);
-            Ok(())
+        SubCommand::CreatePart(subopts) => match &queue {
+            // the id is chosen here so queued changes can refer to the part
+            Some(_) => {
+                let change = QueuedChange::CreatePart {
+                    name: subopts.name,
+                    id: subopts.id.unwrap_or_else(Uuid::new_v4),
+                };
+                send_change(&context, queue.as_ref(), change).await
+            }
+            None => {
+                let response = client::create_part(&context, &subopts.name, subopts.id).await?;
+                print_response(&response)
+            }
+        },
+        SubCommand::UpsertPart(subopts) => {
+            let external_ids = subopts
+                .system
+                .clone()
+                .zip(subopts.external_id)
+                .into_iter()
+                .collect();
+            let response = client::upsert_part(
+                &context,
+                &subopts.name,
+                external_ids,
+                subopts.system.as_deref(),
+            )
+            .await?;
+            print_response(&response)
         }
         SubCommand::GetPart(subopts) => {
-            let response = client::get_part(&context, &subopts.id).await?;
-            println!("{}", to_string_pretty(&response)?);
-            Ok(())
+            let response = match (subopts.id, subopts.name) {
+                (Some(id), _) => client::get_part(&context, &id).await?,
+                (None, Some(name)) => client::get_part_by_name(&context, &name).await?,
+                (None, None) => unreachable!("clap requires an id or name"),
+            };
+            print_response(&response)
         }
         SubCommand::DeletePart(subopts) => {
-            let response = client::delete_part(&context, &subopts.id).await?;
-            println!("{}", to_string_pretty(&response)?);
-            Ok(())
+            let change = QueuedChange::DeletePart {
+                id: subopts.id,
+                expected_version: subopts.expected_version,
+                force: subopts.force,
+            };
+            send_change(&context, queue.as_ref(), change).await
+        }
+        SubCommand::BulkDelete(subopts) => {
+            let response = client::bulk_delete(&context, &subopts.ids, subopts.recursive).await?;
+            print_response(&response)
+        }
+        SubCommand::RestorePart(subopts) => {
+            let response = client::restore_part(&context, &subopts.id).await?;
+            print_response(&response)
         }
         SubCommand::GetChildren(subopts) => {
+            let order = subopts.order;
+            let sort = subopts
+                .sort
+                .map(|key| PartsListSort::new(key.into(), order.into()));
             let response =
-                client::get_children(&context, &subopts.id, subopts.filter.into()).await?;
-            println!("{}", to_string_pretty(&response)?);
-            Ok(())
+                client::get_children(&context, &subopts.id, subopts.filter.into(), sort).await?;
+            print_response(&response)
         }
         SubCommand::UpdatePart(subopts) => {
-            let response = client::update_part(
-                &context,
-                &subopts.id,
-                &subopts.children,
+            let change = QueuedChange::update_part(
+                subopts.id,
+                subopts.children,
                 subopts.action.into(),
-            )
-            .await?;
-            println!("{}", to_string_pretty(&response)?);
-            Ok(())
+                subopts.expected_version,
+            );
+            send_change(&context, queue.as_ref(), change).await
         }
-        SubCommand::GetContained(subopts) => {
+        SubCommand::MovePart(subopts) => {
             let response =
-                client::get_children(&context, &subopts.id, PartsListFilter::Assembly).await?;
-            println!("{}", to_string_pretty(&response)?);
+                client::move_part(&context, &subopts.id, &subopts.from, &subopts.to).await?;
+            print_response(&response)
+        }
+        SubCommand::GetParents(subopts) => {
+            let response = client::get_parents(&context, &subopts.id).await?;
+            print_response(&response)
+        }
+        SubCommand::GetContained(subopts) => {
+            let response = client::get_ancestors(&context, &subopts.id, subopts.depth).await?;
+            print_response(&response)
+        }
+        SubCommand::Import(subopts) => import(&context, &subopts).await,
+        SubCommand::ImportKicad(subopts) => import_kicad(&context, &subopts).await,
+        SubCommand::Export(mut subopts) => {
+            subopts.format = subopts.format.or(profile.format);
+            export(&context, subopts).await
+        }
+        SubCommand::Diff(subopts) => diff(&context, &subopts).await,
+        SubCommand::Watch(subopts) => watch(&context, &subopts).await,
+        SubCommand::DeleteTree(subopts) => delete_tree(&context, &subopts).await,
+        SubCommand::Sync(subopts) => sync(&subopts, format, profile.token.as_deref()).await,
+        SubCommand::Backup(subopts) => {
+            let archive = client::backup(&context).await?;
+            std::fs::write(&subopts.out, &archive)?;
+            println!(
+                "Wrote {} byte backup to {}",
+                archive.len(),
+                subopts.out.display()
+            );
             Ok(())
         }
+        SubCommand::Restore(subopts) => {
+            let archive = std::fs::read(&subopts.file)?;
+            let response = client::restore(&context, archive, subopts.dry_run).await?;
+            print_response(&response)
+        }
+        SubCommand::Stats(subopts) => stats(&context, &subopts).await,
+        SubCommand::Validate(_) => validate(&context).await,
+        SubCommand::Search(subopts) => search(&context, subopts).await,
+        SubCommand::Replay(subopts) => {
+            let queue = match queue {
+                Some(queue) => queue,
+                None => offline_queue(host)?,
+            };
+            replay(&context, &queue, &subopts).await
+        }
     }
 }
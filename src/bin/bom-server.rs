@@ -1,8 +1,44 @@
-use ::bom_server::{make_rocket, SharedPartsList};
+extern crate clap;
+
+use std::path::PathBuf;
+
+use clap::Clap;
+
+use ::bom_server::{make_configured_rocket, SharedPartsList};
+
+/// Serve a parts list configured through the Rocket configuration
+#[derive(Clap)]
+#[clap(version = "0.1.0")]
+pub struct Opts {
+    /// JSON or CSV file of parts loaded before serving when the parts list is
+    /// empty
+    #[clap(long)]
+    pub seed: Option<PathBuf>,
+}
 
 /// Use bom-server library to create a parts list and manage
 /// with rocket based server reactor
 fn main() {
-    let parts_list = SharedPartsList::new();
-    make_rocket(parts_list).launch();
+    let opts = Opts::parse();
+    let rocket = match make_configured_rocket() {
+        Ok(rocket) => rocket,
+        Err(e) => {
+            eprintln!("Failed to configure bom-server: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let (Some(seed), Some(parts)) = (&opts.seed, rocket.state::<SharedPartsList>()) {
+        match parts.seed(seed) {
+            Ok(Some(count)) => println!("Seeded {} parts from {}", count, seed.display()),
+            Ok(None) => println!(
+                "Parts list not empty, skipped seeding from {}",
+                seed.display()
+            ),
+            Err(e) => {
+                eprintln!("Failed to seed bom-server: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    rocket.launch();
 }
@@ -0,0 +1,618 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::parts_list::{
+    nullable, PartsListError, PartsListFilter, PartsListPredicate, PartsListQuery,
+};
+use crate::sidecar;
+use crate::store::PartsStore;
+
+/// Category parts can be assigned to, which may itself be a subcategory of
+/// another, such as `M3` within `Screws` within `Fasteners`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Category {
+    pub id: Uuid,
+    pub name: String,
+    /// Category this is a subcategory of, `None` for a top level category
+    pub parent: Option<Uuid>,
+    /// Attributes expected of the parts in the category and its subcategories
+    #[serde(default)]
+    pub attributes: Vec<AttributeSchema>,
+}
+
+/// Type of the values of an attribute
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributeType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+}
+
+impl AttributeType {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            AttributeType::String => value.is_string(),
+            AttributeType::Number => value.is_number(),
+            AttributeType::Integer => value.is_i64() || value.is_u64(),
+            AttributeType::Boolean => value.is_boolean(),
+        }
+    }
+}
+
+impl From<AttributeType> for &str {
+    fn from(t: AttributeType) -> &'static str {
+        match t {
+            AttributeType::String => "string",
+            AttributeType::Number => "number",
+            AttributeType::Integer => "integer",
+            AttributeType::Boolean => "boolean",
+        }
+    }
+}
+
+/// Attribute the parts in a category are expected to have, such as the
+/// `thread` of a screw
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AttributeSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: AttributeType,
+    /// Whether every part in the category must have the attribute
+    #[serde(default)]
+    pub required: bool,
+    /// Values the attribute is limited to, any value of its type when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<Value>,
+}
+
+impl AttributeSchema {
+    /// Describe why a part's value for the attribute doesn't match, if it
+    /// doesn't
+    fn check(&self, value: Option<&Value>) -> Option<String> {
+        match value {
+            None if self.required => Some("is required".into()),
+            None => None,
+            Some(value) if !self.kind.matches(value) => {
+                Some(format!("must be of type {}", Into::<&str>::into(self.kind)))
+            }
+            Some(value) if !self.allowed.is_empty() && !self.allowed.contains(value) => {
+                let allowed: Vec<String> = self.allowed.iter().map(Value::to_string).collect();
+                Some(format!("must be one of {}", allowed.join(", ")))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// Attribute of a part which doesn't match a schema of its category
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AttributeError {
+    pub attribute: String,
+    pub description: String,
+}
+
+/// Changes to a category, leaving those not given as they are
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CategoryEdit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Category to move this below, or `null` to make it a top level category
+    #[serde(
+        default,
+        deserialize_with = "nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub parent: Option<Option<Uuid>>,
+    /// Attribute schemas replacing all of the category's schemas
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<Vec<AttributeSchema>>,
+}
+
+/// Categories created on the server, held in memory and saved to a sidecar
+/// file after every change when the parts are stored outside of memory
+///
+/// Clones share the categories, so changes made on the writer thread can
+/// check parts against them.
+#[derive(Clone)]
+pub struct CategoryRegistry {
+    categories: Arc<Mutex<HashMap<Uuid, Category>>>,
+    path: Option<PathBuf>,
+}
+
+/// Locked access to the categories of a registry, along with the file they
+/// are saved to if any
+pub struct Categories<'a>(MutexGuard<'a, HashMap<Uuid, Category>>, Option<&'a Path>);
+
+impl Default for CategoryRegistry {
+    fn default() -> Self {
+        CategoryRegistry::new()
+    }
+}
+
+impl CategoryRegistry {
+    pub fn new() -> CategoryRegistry {
+        CategoryRegistry {
+            categories: Arc::new(Mutex::new(HashMap::new())),
+            path: None,
+        }
+    }
+
+    /// Registry saving its categories to the file at `path`, starting with
+    /// those saved there before
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CategoryRegistry, PartsListError> {
+        let path = path.as_ref();
+        let categories: Vec<Category> = sidecar::load(path)?;
+        check_tree(&categories)?;
+        Ok(CategoryRegistry {
+            categories: Arc::new(Mutex::new(
+                categories
+                    .into_iter()
+                    .map(|category| (category.id, category))
+                    .collect(),
+            )),
+            path: Some(path.into()),
+        })
+    }
+
+    /// Lock the categories, failing only if a previous holder of the lock
+    /// panicked
+    pub fn lock(&self) -> Option<Categories<'_>> {
+        let path = self.path.as_deref();
+        self.categories
+            .lock()
+            .ok()
+            .map(|categories| Categories(categories, path))
+    }
+}
+
+impl<'a> Categories<'a> {
+    /// Create a category, below `parent` when given, whose name must not be
+    /// used by another category with the same parent
+    pub fn create(
+        &mut self,
+        name: &str,
+        parent: Option<Uuid>,
+        attributes: Vec<AttributeSchema>,
+    ) -> Result<Category, PartsListError> {
+        if let Some(parent) = &parent {
+            self.get(parent)?;
+        }
+        let category = Category {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            parent,
+            attributes,
+        };
+        self.check_name(&category)?;
+        check_schemas(&category.attributes)?;
+        self.save(|categories| {
+            categories.insert(category.id, category.clone());
+        })?;
+        Ok(category)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<Category, PartsListError> {
+        self.0
+            .get(id)
+            .cloned()
+            .ok_or(PartsListError::CategoryDoesNotExist { id: *id })
+    }
+
+    /// List every category, ordered by name
+    pub fn list(&self) -> Vec<Category> {
+        let mut categories: Vec<Category> = self.0.values().cloned().collect();
+        categories.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        categories
+    }
+
+    /// Ids of a category and all of the subcategories below it
+    pub fn subtree(&self, id: &Uuid) -> Result<Vec<Uuid>, PartsListError> {
+        self.get(id)?;
+        let mut subtree = vec![*id];
+        let mut i = 0;
+        while i < subtree.len() {
+            let parent = subtree[i];
+            subtree.extend(
+                self.0
+                    .values()
+                    .filter(|category| category.parent == Some(parent))
+                    .map(|category| category.id),
+            );
+            i += 1;
+        }
+        Ok(subtree)
+    }
+
+    /// Check each of the categories exist, adding the subcategories below them
+    /// when `include_subcategories`
+    pub fn expand(
+        &self,
+        ids: &[Uuid],
+        include_subcategories: bool,
+    ) -> Result<Vec<Uuid>, PartsListError> {
+        let mut expanded = Vec::new();
+        for id in ids {
+            if include_subcategories {
+                expanded.extend(self.subtree(id)?);
+            } else {
+                expanded.push(self.get(id)?.id);
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Rename a category or move it below another, which can't be the category
+    /// itself or one of its subcategories
+    pub fn edit(&mut self, id: &Uuid, edit: &CategoryEdit) -> Result<Category, PartsListError> {
+        let mut category = self.get(id)?;
+        if let Some(name) = &edit.name {
+            category.name = name.clone();
+        }
+        if let Some(parent) = edit.parent {
+            if let Some(parent) = &parent {
+                self.get(parent)?;
+                if self.subtree(id)?.contains(parent) {
+                    return Err(PartsListError::CategoryCycle {
+                        id: *id,
+                        parent: *parent,
+                    });
+                }
+            }
+            category.parent = parent;
+        }
+        if let Some(attributes) = &edit.attributes {
+            check_schemas(attributes)?;
+            category.attributes = attributes.clone();
+        }
+        self.check_name(&category)?;
+        self.save(|categories| {
+            categories.insert(*id, category.clone());
+        })?;
+        Ok(category)
+    }
+
+    /// Delete a category, which must have no subcategories and no parts
+    /// assigned to it
+    pub fn delete(
+        &mut self,
+        id: &Uuid,
+        parts: &dyn PartsStore,
+    ) -> Result<Category, PartsListError> {
+        let category = self.get(id)?;
+        let query = PartsListQuery::new(PartsListFilter::All)
+            .and(PartsListPredicate::InCategory(vec![*id]));
+        if self.subtree(id)?.len() > 1 || !parts.query(&query)?.is_empty() {
+            return Err(PartsListError::CategoryInUse { id: *id });
+        }
+        self.save(|categories| {
+            categories.remove(id);
+        })?;
+        Ok(category)
+    }
+
+    /// Check the attributes of a part in `category` against the schemas of the
+    /// category and of the categories above it, returning every mismatch
+    ///
+    /// Attributes without a schema are left unchecked, as are the attributes of
+    /// parts without a category.
+    pub fn check_attributes(
+        &self,
+        category: Option<Uuid>,
+        attributes: &BTreeMap<String, Value>,
+    ) -> Result<Vec<AttributeError>, PartsListError> {
+        let mut errors = Vec::new();
+        let mut next = category;
+        while let Some(id) = next {
+            let category = self.get(&id)?;
+            for schema in &category.attributes {
+                if let Some(description) = schema.check(attributes.get(&schema.name)) {
+                    errors.push(AttributeError {
+                        attribute: schema.name.clone(),
+                        description,
+                    });
+                }
+            }
+            next = category.parent;
+        }
+        Ok(errors)
+    }
+
+    /// Replace every category with those of a backup, which must form a tree
+    pub fn replace_all(&mut self, categories: Vec<Category>) -> Result<(), PartsListError> {
+        check_tree(&categories)?;
+        self.save(|replaced| {
+            *replaced = categories
+                .into_iter()
+                .map(|category| (category.id, category))
+                .collect();
+        })
+    }
+
+    /// Make a change to a copy of the categories and save it before it
+    /// replaces them, so a change which can't be saved isn't made
+    fn save<F>(&mut self, change: F) -> Result<(), PartsListError>
+    where
+        F: FnOnce(&mut HashMap<Uuid, Category>),
+    {
+        let mut categories = self.0.clone();
+        change(&mut categories);
+        if let Some(path) = self.1 {
+            let mut saved: Vec<&Category> = categories.values().collect();
+            saved.sort_by_key(|category| category.id);
+            sidecar::save(path, &saved)?;
+        }
+        *self.0 = categories;
+        Ok(())
+    }
+
+    /// Check no other category with the same parent has the name of `category`
+    fn check_name(&self, category: &Category) -> Result<(), PartsListError> {
+        match self.0.values().find(|other| {
+            other.id != category.id
+                && other.parent == category.parent
+                && other.name == category.name
+        }) {
+            Some(other) => Err(PartsListError::CategoryNameExists {
+                name: other.name.clone(),
+                id: other.id,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Check that the parent of each category is one of the categories, and that
+/// no category is below itself
+pub fn check_tree(categories: &[Category]) -> Result<(), PartsListError> {
+    let parents: HashMap<Uuid, Option<Uuid>> = categories
+        .iter()
+        .map(|category| (category.id, category.parent))
+        .collect();
+    for category in categories {
+        let mut next = category.parent;
+        // a path longer than the number of categories must repeat one
+        for _ in 0..categories.len() {
+            match next {
+                Some(parent) if parent == category.id => {
+                    return Err(PartsListError::CategoryCycle {
+                        id: category.id,
+                        parent: category.parent.unwrap_or(parent),
+                    })
+                }
+                Some(parent) => match parents.get(&parent) {
+                    Some(above) => next = *above,
+                    None => return Err(PartsListError::CategoryDoesNotExist { id: parent }),
+                },
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check the allowed values of each schema are of its type
+fn check_schemas(schemas: &[AttributeSchema]) -> Result<(), PartsListError> {
+    match schemas.iter().find(|schema| {
+        !schema
+            .allowed
+            .iter()
+            .all(|value| schema.kind.matches(value))
+    }) {
+        Some(schema) => Err(PartsListError::InvalidAttributeSchema {
+            name: schema.name.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::parts_list::{Part, PartEdit, PartsList};
+
+    #[test]
+    fn category_hierarchy() {
+        let registry = CategoryRegistry::new();
+        let mut categories = registry.lock().unwrap();
+        let fasteners = categories.create("Fasteners", None, Vec::new()).unwrap();
+        let screws = categories
+            .create("Screws", Some(fasteners.id), Vec::new())
+            .unwrap();
+        let m3 = categories
+            .create("M3", Some(screws.id), Vec::new())
+            .unwrap();
+        let nuts = categories
+            .create("Nuts", Some(fasteners.id), Vec::new())
+            .unwrap();
+
+        // names only need to be unique among siblings
+        categories.create("M3", Some(nuts.id), Vec::new()).unwrap();
+        assert_matches!(
+            categories.create("Screws", Some(fasteners.id), Vec::new()),
+            Err(PartsListError::CategoryNameExists { .. })
+        );
+
+        let mut subtree = categories.subtree(&screws.id).unwrap();
+        subtree.sort();
+        let mut expected = vec![screws.id, m3.id];
+        expected.sort();
+        assert_eq!(subtree, expected);
+        assert_eq!(categories.subtree(&fasteners.id).unwrap().len(), 5);
+
+        let move_below = |parent| CategoryEdit {
+            parent: Some(parent),
+            ..CategoryEdit::default()
+        };
+        assert_matches!(
+            categories.edit(&fasteners.id, &move_below(Some(m3.id))),
+            Err(PartsListError::CategoryCycle { .. })
+        );
+        let moved = categories.edit(&m3.id, &move_below(None)).unwrap();
+        assert_eq!(moved.parent, None);
+
+        let mut parts = PartsList::new();
+        let screw = parts.insert(Part::new("M3x8 screw")).unwrap();
+        let assign = PartEdit {
+            category: Some(Some(m3.id)),
+            ..PartEdit::default()
+        };
+        PartsStore::edit(&mut parts, &screw.id, &assign).unwrap();
+        assert_matches!(
+            categories.delete(&m3.id, &parts),
+            Err(PartsListError::CategoryInUse { .. })
+        );
+        assert_matches!(
+            categories.delete(&fasteners.id, &parts),
+            Err(PartsListError::CategoryInUse { .. })
+        );
+        categories.delete(&screws.id, &parts).unwrap();
+        assert_matches!(
+            categories.get(&screws.id),
+            Err(PartsListError::CategoryDoesNotExist { .. })
+        );
+    }
+
+    #[test]
+    fn restores_tree() {
+        let registry = CategoryRegistry::new();
+        let mut categories = registry.lock().unwrap();
+        let fasteners = categories.create("Fasteners", None, Vec::new()).unwrap();
+        let screws = categories
+            .create("Screws", Some(fasteners.id), Vec::new())
+            .unwrap();
+        let backup = categories.list();
+
+        categories.create("Nuts", None, Vec::new()).unwrap();
+        categories.replace_all(backup.clone()).unwrap();
+        assert_eq!(categories.list(), backup);
+
+        // archives whose categories don't form a tree are refused whole
+        let mut looped = backup.clone();
+        looped[0].parent = Some(screws.id);
+        assert_matches!(
+            categories.replace_all(looped),
+            Err(PartsListError::CategoryCycle { .. })
+        );
+        let orphaned = vec![backup[1].clone()];
+        assert_matches!(
+            categories.replace_all(orphaned),
+            Err(PartsListError::CategoryDoesNotExist { id }) if id == fasteners.id
+        );
+        assert_eq!(categories.list(), backup);
+    }
+
+    #[test]
+    fn saved_to_file() {
+        let dir = std::env::temp_dir().join(format!("bom-categories-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bom.categories.json");
+        let registry = CategoryRegistry::open(&path).unwrap();
+        let mut categories = registry.lock().unwrap();
+        let fasteners = categories.create("Fasteners", None, Vec::new()).unwrap();
+        let screws = categories
+            .create("Screws", Some(fasteners.id), Vec::new())
+            .unwrap();
+        let rename = CategoryEdit {
+            name: Some("Bolts".into()),
+            ..CategoryEdit::default()
+        };
+        categories.edit(&screws.id, &rename).unwrap();
+        let nuts = categories.create("Nuts", None, Vec::new()).unwrap();
+        categories.delete(&nuts.id, &PartsList::new()).unwrap();
+        let saved = categories.list();
+        drop(categories);
+
+        let reopened = CategoryRegistry::open(&path).unwrap();
+        assert_eq!(reopened.lock().unwrap().list(), saved);
+
+        // a change which can't be saved isn't made
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut categories = reopened.lock().unwrap();
+        assert_matches!(
+            categories.create("Washers", None, Vec::new()),
+            Err(PartsListError::Storage { .. })
+        );
+        assert_eq!(categories.list(), saved);
+    }
+
+    #[test]
+    fn attribute_schemas() {
+        let registry = CategoryRegistry::new();
+        let mut categories = registry.lock().unwrap();
+        let schema = |name: &str, kind, required, allowed: Vec<Value>| AttributeSchema {
+            name: name.into(),
+            kind,
+            required,
+            allowed,
+        };
+        assert_matches!(
+            categories.create(
+                "Resistors",
+                None,
+                vec![schema(
+                    "package",
+                    AttributeType::String,
+                    false,
+                    vec![json!(603)]
+                )],
+            ),
+            Err(PartsListError::InvalidAttributeSchema { .. })
+        );
+        let passives = categories
+            .create(
+                "Passives",
+                None,
+                vec![schema(
+                    "package",
+                    AttributeType::String,
+                    true,
+                    vec![json!("0402"), json!("0603")],
+                )],
+            )
+            .unwrap();
+        let resistors = categories
+            .create(
+                "Resistors",
+                Some(passives.id),
+                vec![schema("ohms", AttributeType::Number, true, Vec::new())],
+            )
+            .unwrap();
+
+        let mut attributes = BTreeMap::new();
+        attributes.insert("package".to_string(), json!("0603"));
+        attributes.insert("ohms".to_string(), json!(10000));
+        attributes.insert("tolerance".to_string(), json!("1%"));
+        assert!(categories
+            .check_attributes(Some(resistors.id), &attributes)
+            .unwrap()
+            .is_empty());
+
+        // the schemas of parent categories also apply
+        attributes.insert("package".to_string(), json!("1206"));
+        attributes.insert("ohms".to_string(), json!("10k"));
+        let errors = categories
+            .check_attributes(Some(resistors.id), &attributes)
+            .unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].attribute, "ohms");
+        assert_eq!(errors[0].description, "must be of type number");
+        assert_eq!(errors[1].description, "must be one of \"0402\", \"0603\"");
+
+        let errors = categories
+            .check_attributes(Some(passives.id), &BTreeMap::new())
+            .unwrap();
+        assert_eq!(errors[0].description, "is required");
+        assert!(categories
+            .check_attributes(None, &BTreeMap::new())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,90 +1,392 @@
-use reqwest::Client;
+use std::collections::BTreeMap;
+
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, Method, RequestBuilder};
+use serde::Serialize;
 use url::Url;
 use uuid::Uuid;
 
-use crate::parts_list::{PartsListFilter, PartsListUpdate};
+use crate::category::{AttributeSchema, CategoryEdit};
+use crate::eco::{EcoChange, EcoStatus};
+use crate::label::{ImageFormat, LabelFormat};
+use crate::negotiate::{from_msgpack, to_msgpack};
+use crate::parts_list::{
+    PartEdit, PartsListFilter, PartsListQuery, PartsListSort, PartsListUpdate, RelationshipEdit,
+};
 use crate::query;
+use crate::quota::QuotaLimits;
+use crate::release::ReleaseStatus;
 use crate::response::Response;
+use crate::routing::RoutingStep;
+use crate::supplier::SupplierPolicy;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod graph_cache;
+pub mod offline;
+
+pub use graph_cache::{BomDiff, GraphCache, GraphChanges};
+
+/// Encoding of the bodies exchanged with the server
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WireFormat {
+    Json,
+    /// MessagePack, being smaller and quicker to parse for large part lists
+    MessagePack,
+}
 
 pub struct ClientContext {
     pub client: Client,
     pub base_url: Url,
+    pub format: WireFormat,
+    /// Bearer token sent with every request, for servers behind a proxy
+    /// requiring authentication
+    pub token: Option<String>,
 }
 
 impl ClientContext {
-    pub fn new(base_url: Url) -> ClientContext {
+    pub fn new(mut base_url: Url) -> ClientContext {
+        // kept as a directory so the paths of the API are joined below it
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
         ClientContext {
             client: Client::new(),
             base_url,
+            format: WireFormat::Json,
+            token: None,
+        }
+    }
+
+    /// Url of an API path, below any base path the server is mounted under
+    pub fn url(&self, path: &str) -> Result<Url, url::ParseError> {
+        self.base_url.join(path.trim_start_matches('/'))
+    }
+
+    /// Exchange bodies with the server in `format` rather than JSON
+    pub fn with_format(mut self, format: WireFormat) -> ClientContext {
+        self.format = format;
+        self
+    }
+
+    /// Authenticate every request with `token` as a bearer token
+    pub fn with_token(mut self, token: String) -> ClientContext {
+        self.token = Some(token);
+        self
+    }
+
+    /// Start a request carrying the token of the client if any
+    fn authorized(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
         }
     }
+
+    /// Start a request, asking for the reply in the wire format
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.authorized(method, url);
+        match self.format {
+            WireFormat::Json => request,
+            WireFormat::MessagePack => request.header(ACCEPT, "application/msgpack"),
+        }
+    }
+
+    fn get(&self, url: Url) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    fn post(&self, url: Url) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    fn put(&self, url: Url) -> RequestBuilder {
+        self.request(Method::PUT, url)
+    }
+
+    fn patch(&self, url: Url) -> RequestBuilder {
+        self.request(Method::PATCH, url)
+    }
+
+    fn delete(&self, url: Url) -> RequestBuilder {
+        self.request(Method::DELETE, url)
+    }
+}
+
+/// Body of a request encoded in the wire format of the client
+trait EncodedBody: Sized {
+    fn encoded<T: Serialize>(self, format: WireFormat, body: &T) -> anyhow::Result<Self>;
+}
+
+impl EncodedBody for RequestBuilder {
+    fn encoded<T: Serialize>(self, format: WireFormat, body: &T) -> anyhow::Result<Self> {
+        Ok(match format {
+            WireFormat::Json => self.json(body),
+            WireFormat::MessagePack => self
+                .header(CONTENT_TYPE, "application/msgpack")
+                .body(to_msgpack(body)?),
+        })
+    }
+}
+
+/// Send a request and read the reply of the server, in whichever format it
+/// was sent
+async fn send(request: RequestBuilder) -> anyhow::Result<Response> {
+    let reply = request.send().await?;
+    let msgpack = reply
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/msgpack"));
+    if msgpack {
+        Ok(from_msgpack(&reply.bytes().await?)?)
+    } else {
+        Ok(reply.json::<Response>().await?)
+    }
 }
 
 pub async fn get_index(context: &ClientContext) -> anyhow::Result<String> {
-    Ok(reqwest::get(context.base_url.join("/")?)
+    Ok(context
+        .authorized(Method::GET, context.url("/")?)
+        .send()
         .await?
         .text()
         .await?)
 }
 
+/// Query parameters requesting listed parts in a particular order
+fn sort_query(sort: Option<PartsListSort>) -> String {
+    match sort {
+        Some(sort) => format!(
+            "&sort={}&order={}",
+            Into::<&str>::into(sort.key),
+            Into::<&str>::into(sort.order)
+        ),
+        None => String::new(),
+    }
+}
+
 pub async fn list_parts(
     context: &ClientContext,
     filter: PartsListFilter,
+    sort: Option<PartsListSort>,
 ) -> anyhow::Result<Response> {
-    let request_uri: String = format!("/v1/parts?filter={}", Into::<&str>::into(filter));
-    Ok(reqwest::get(context.base_url.join(&request_uri)?)
-        .await?
-        .json::<Response>()
-        .await?)
+    query_parts(context, &PartsListQuery::new(filter), sort).await
+}
+
+pub async fn query_parts(
+    context: &ClientContext,
+    query: &PartsListQuery,
+    sort: Option<PartsListSort>,
+) -> anyhow::Result<Response> {
+    let request_uri: String = format!(
+        "/v1/parts?filter={}{}",
+        Into::<&str>::into(query.filter),
+        sort_query(sort)
+    );
+    let mut request_url = context.url(&request_uri)?;
+    for predicate in &query.predicates {
+        request_url
+            .query_pairs_mut()
+            .append_pair(&predicate.key(), &predicate.value());
+    }
+    send(context.get(request_url)).await
 }
 
-pub async fn create_part(context: &ClientContext, name: &str) -> anyhow::Result<Response> {
+/// List only the id and version of every part, to tell which parts changed
+/// since they were last fetched
+pub async fn list_versions(context: &ClientContext) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/parts?filter=all&fields=id,version")?;
+    send(context.get(request_url)).await
+}
+
+/// Fetch a page of the parts matching `query`, starting after `cursor`
+pub async fn query_parts_page(
+    context: &ClientContext,
+    query: &PartsListQuery,
+    limit: usize,
+    cursor: Option<&str>,
+) -> anyhow::Result<Response> {
+    let request_uri: String = format!(
+        "/v1/parts?filter={}&limit={}",
+        Into::<&str>::into(query.filter),
+        limit
+    );
+    let mut request_url = context.url(&request_uri)?;
+    if let Some(cursor) = cursor {
+        request_url.query_pairs_mut().append_pair("cursor", cursor);
+    }
+    for predicate in &query.predicates {
+        request_url
+            .query_pairs_mut()
+            .append_pair(&predicate.key(), &predicate.value());
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn create_part(
+    context: &ClientContext,
+    name: &str,
+    id: Option<Uuid>,
+) -> anyhow::Result<Response> {
     let uri_path = "/v1/parts";
-    let request_url = context.base_url.join(&uri_path)?;
-    Ok(context
-        .client
-        .post(request_url)
-        .json(&query::NewPart { name: name.into() })
-        .send()
-        .await?
-        .json::<Response>()
-        .await?)
+    let request_url = context.url(uri_path)?;
+    send(context.post(request_url).encoded(
+        context.format,
+        &query::NewPart {
+            name: name.into(),
+            id,
+            external_ids: BTreeMap::new(),
+            category: None,
+            attributes: BTreeMap::new(),
+        },
+    )?)
+    .await
+}
+
+/// Create a part, or add the external ids to the part which already exists
+/// with its external id in `key` when given, or otherwise with its name
+pub async fn upsert_part(
+    context: &ClientContext,
+    name: &str,
+    external_ids: BTreeMap<String, String>,
+    key: Option<&str>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/parts")?;
+    if let Some(key) = key {
+        request_url.query_pairs_mut().append_pair("key", key);
+    }
+    send(context.put(request_url).encoded(
+        context.format,
+        &query::NewPart {
+            name: name.into(),
+            id: None,
+            external_ids,
+            category: None,
+            attributes: BTreeMap::new(),
+        },
+    )?)
+    .await
 }
 
 pub async fn get_part(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
     let uri_path: String = format!("/v1/parts/{}", id);
-    Ok(reqwest::get(context.base_url.join(&uri_path)?)
-        .await?
-        .json::<Response>()
-        .await?)
+    send(context.get(context.url(&uri_path)?)).await
 }
 
-pub async fn delete_part(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
-    let uri_path: String = format!("/v1/parts/{}", id);
-    let request_url = context.base_url.join(&uri_path)?;
-    Ok(context
-        .client
-        .delete(request_url)
-        .send()
-        .await?
-        .json::<Response>()
-        .await?)
+pub async fn get_part_by_name(context: &ClientContext, name: &str) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/parts/by_name/")?;
+    request_url
+        .path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid base url: {}", context.base_url))?
+        .pop_if_empty()
+        .push(name);
+    send(context.get(request_url)).await
+}
+
+pub async fn get_part_by_external(
+    context: &ClientContext,
+    system: &str,
+    external_id: &str,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/parts/by_external/")?;
+    request_url
+        .path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid base url: {}", context.base_url))?
+        .pop_if_empty()
+        .push(system)
+        .push(external_id);
+    send(context.get(request_url)).await
+}
+
+pub async fn batch_get(context: &ClientContext, ids: &[Uuid]) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/parts/batch_get")?;
+    send(
+        context
+            .post(request_url)
+            .encoded(context.format, &query::BatchGet { ids: ids.to_vec() })?,
+    )
+    .await
+}
+
+/// Query string requiring a part to still be at the version a change is based on
+fn version_query(expected_version: Option<u64>) -> String {
+    match expected_version {
+        Some(version) => format!("expected_version={}", version),
+        None => String::new(),
+    }
+}
+
+pub async fn delete_part(
+    context: &ClientContext,
+    id: &Uuid,
+    expected_version: Option<u64>,
+    force: bool,
+) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}?{}", id, version_query(expected_version));
+    let mut request_url = context.url(&uri_path)?;
+    if force {
+        request_url.query_pairs_mut().append_pair("force", "true");
+    }
+    send(context.delete(request_url)).await
+}
+
+pub async fn bulk_delete(
+    context: &ClientContext,
+    ids: &[Uuid],
+    recursive: bool,
+) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/parts/bulk_delete")?;
+    send(context.post(request_url).encoded(
+        context.format,
+        &query::BulkDelete {
+            ids: ids.to_vec(),
+            recursive,
+        },
+    )?)
+    .await
+}
+
+pub async fn edit_part(
+    context: &ClientContext,
+    id: &Uuid,
+    edit: &PartEdit,
+    expected_version: Option<u64>,
+) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}?{}", id, version_query(expected_version));
+    let request_url = context.url(&uri_path)?;
+    send(context.patch(request_url).encoded(context.format, edit)?).await
+}
+
+pub async fn delete_orphans(context: &ClientContext) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/parts?filter=orphan")?;
+    send(context.delete(request_url)).await
+}
+
+pub async fn delete_tree(
+    context: &ClientContext,
+    id: &Uuid,
+    dry_run: bool,
+) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}?recursive=true&dry_run={}", id, dry_run);
+    let request_url = context.url(&uri_path)?;
+    send(context.delete(request_url)).await
 }
 
 pub async fn get_children(
     context: &ClientContext,
     id: &Uuid,
     filter: PartsListFilter,
+    sort: Option<PartsListSort>,
 ) -> anyhow::Result<Response> {
     let uri_path: String = format!(
-        "/v1/parts/{}/children?filter={}",
+        "/v1/parts/{}/children?filter={}{}",
         id,
-        Into::<&str>::into(filter)
+        Into::<&str>::into(filter),
+        sort_query(sort)
     );
-    Ok(reqwest::get(context.base_url.join(&uri_path)?)
-        .await?
-        .json::<Response>()
-        .await?)
+    send(context.get(context.url(&uri_path)?)).await
 }
 
 pub async fn update_part(
@@ -92,29 +394,625 @@ pub async fn update_part(
     id: &Uuid,
     children: &[Uuid],
     action: PartsListUpdate,
+    expected_version: Option<u64>,
 ) -> anyhow::Result<Response> {
     let uri_path = format!(
-        "/v1/parts/{}/children?action={}",
+        "/v1/parts/{}/children?action={}&{}",
         id,
-        Into::<&str>::into(action)
+        Into::<&str>::into(action),
+        version_query(expected_version)
     );
-    let request_url = context.base_url.join(&uri_path)?;
-    Ok(context
-        .client
-        .post(request_url)
-        .json(&query::UpdateChildren {
-            children: children.iter().copied().collect(),
-        })
-        .send()
-        .await?
-        .json::<Response>()
-        .await?)
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url).encoded(
+        context.format,
+        &query::UpdateChildren {
+            children: children.to_vec(),
+        },
+    )?)
+    .await
+}
+
+pub async fn restore_part(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}/restore", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url)).await
+}
+
+pub async fn move_part(
+    context: &ClientContext,
+    id: &Uuid,
+    from: &Uuid,
+    to: &Uuid,
+) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}/move?from={}&to={}", id, from, to);
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url)).await
+}
+
+pub async fn get_relationship(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/relationships/{}", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.get(request_url)).await
+}
+
+pub async fn edit_relationship(
+    context: &ClientContext,
+    id: &Uuid,
+    edit: &RelationshipEdit,
+) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/relationships/{}", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.patch(request_url).encoded(context.format, edit)?).await
+}
+
+pub async fn get_parents(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}/parents", id);
+    send(context.get(context.url(&uri_path)?)).await
 }
 
 pub async fn get_contained(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
     let uri_path: String = format!("/v1/parts/{}/contained", id);
-    Ok(reqwest::get(context.base_url.join(&uri_path)?)
-        .await?
-        .json::<Response>()
-        .await?)
+    send(context.get(context.url(&uri_path)?)).await
+}
+
+pub async fn get_ancestors(
+    context: &ClientContext,
+    id: &Uuid,
+    depth: Option<usize>,
+) -> anyhow::Result<Response> {
+    let mut uri_path: String = format!("/v1/parts/{}/ancestors", id);
+    if let Some(depth) = depth {
+        uri_path.push_str(&format!("?depth={}", depth));
+    }
+    send(context.get(context.url(&uri_path)?)).await
+}
+
+pub async fn get_summary(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}/summary", id);
+    send(context.get(context.url(&uri_path)?)).await
+}
+
+pub async fn get_shortage(
+    context: &ClientContext,
+    id: &Uuid,
+    build_qty: u64,
+    config: Option<&str>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url(&format!("/v1/parts/{}/shortage", id))?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("build_qty", &build_qty.to_string());
+    if let Some(config) = config {
+        request_url.query_pairs_mut().append_pair("config", config);
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn get_lead_time(
+    context: &ClientContext,
+    id: &Uuid,
+    config: Option<&str>,
+    policy: SupplierPolicy,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url(&format!("/v1/parts/{}/lead_time", id))?;
+    if let Some(config) = config {
+        request_url.query_pairs_mut().append_pair("config", config);
+    }
+    request_url
+        .query_pairs_mut()
+        .append_pair("policy", policy.into());
+    send(context.get(request_url)).await
+}
+
+pub async fn get_cost(
+    context: &ClientContext,
+    id: &Uuid,
+    qty: u64,
+    policy: SupplierPolicy,
+    currency: Option<&str>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url(&format!("/v1/parts/{}/cost", id))?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("qty", &qty.to_string())
+        .append_pair("policy", policy.into());
+    if let Some(currency) = currency {
+        request_url
+            .query_pairs_mut()
+            .append_pair("currency", currency);
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn get_cost_history(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let request_url = context.url(&format!("/v1/parts/{}/cost_history", id))?;
+    send(context.get(request_url)).await
+}
+
+pub async fn get_cost_trend(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let request_url = context.url(&format!("/v1/parts/{}/cost_trend", id))?;
+    send(context.get(request_url)).await
+}
+
+pub async fn get_rfq(
+    context: &ClientContext,
+    id: &Uuid,
+    qty: u64,
+    policy: SupplierPolicy,
+    currency: Option<&str>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url(&format!("/v1/parts/{}/rfq", id))?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("qty", &qty.to_string())
+        .append_pair("policy", policy.into());
+    if let Some(currency) = currency {
+        request_url
+            .query_pairs_mut()
+            .append_pair("currency", currency);
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn get_picklist(
+    context: &ClientContext,
+    id: &Uuid,
+    qty: u64,
+    config: Option<&str>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url(&format!("/v1/parts/{}/picklist", id))?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("qty", &qty.to_string());
+    if let Some(config) = config {
+        request_url.query_pairs_mut().append_pair("config", config);
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn search(context: &ClientContext, query: &str, fuzzy: bool) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/search")?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("q", query)
+        .append_pair("fuzzy", &fuzzy.to_string());
+    send(context.get(request_url)).await
+}
+
+pub async fn top_used(context: &ClientContext, limit: usize) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/parts/top_used")?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("limit", &limit.to_string());
+    send(context.get(request_url)).await
+}
+
+pub async fn common_parts(context: &ClientContext, ids: &[Uuid]) -> anyhow::Result<Response> {
+    let ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+    let mut request_url = context.url("/v1/parts/common")?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("ids", &ids.join(","));
+    send(context.get(request_url)).await
+}
+
+pub async fn get_stats(context: &ClientContext) -> anyhow::Result<Response> {
+    send(context.get(context.url("/v1/stats")?)).await
+}
+
+pub async fn get_changes(
+    context: &ClientContext,
+    since: u64,
+    limit: Option<usize>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/changes")?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("since", &since.to_string());
+    if let Some(limit) = limit {
+        request_url
+            .query_pairs_mut()
+            .append_pair("limit", &limit.to_string());
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn get_change_snapshot(context: &ClientContext) -> anyhow::Result<Response> {
+    send(context.get(context.url("/v1/changes/snapshot")?)).await
+}
+
+pub async fn get_replication(context: &ClientContext) -> anyhow::Result<Response> {
+    send(context.get(context.url("/v1/replication")?)).await
+}
+
+pub async fn get_perf(context: &ClientContext) -> anyhow::Result<Response> {
+    send(context.get(context.url("/v1/admin/perf")?)).await
+}
+
+pub async fn validate(context: &ClientContext) -> anyhow::Result<Response> {
+    send(context.get(context.url("/v1/admin/validate")?)).await
+}
+
+/// Find groups of subassemblies built from the same components in the same quantities
+pub async fn duplicates(context: &ClientContext) -> anyhow::Result<Response> {
+    send(context.get(context.url("/v1/admin/duplicates")?)).await
+}
+
+/// Attach a file to a part, uploaded as `multipart/form-data`
+pub async fn upload_attachment(
+    context: &ClientContext,
+    id: &Uuid,
+    filename: &str,
+    content_type: &str,
+    contents: Vec<u8>,
+) -> anyhow::Result<Response> {
+    let uri_path = format!("/v1/parts/{}/attachments", id);
+    let request_url = context.url(&uri_path)?;
+    let file = reqwest::multipart::Part::bytes(contents)
+        .file_name(filename.to_string())
+        .mime_str(content_type)?;
+    send(
+        context
+            .post(request_url)
+            .multipart(reqwest::multipart::Form::new().part("file", file)),
+    )
+    .await
+}
+
+pub async fn list_attachments(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}/attachments", id);
+    send(context.get(context.url(&uri_path)?)).await
+}
+
+/// Download the contents of a file attached to a part
+pub async fn download_attachment(
+    context: &ClientContext,
+    id: &Uuid,
+    attachment: &Uuid,
+) -> anyhow::Result<Vec<u8>> {
+    let uri_path: String = format!("/v1/parts/{}/attachments/{}", id, attachment);
+    let response = context
+        .authorized(Method::GET, context.url(&uri_path)?)
+        .send()
+        .await?;
+    let is_json = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    // failures are reported in a regular response instead of the file, which
+    // could itself be json, so only a response with an error is a failure
+    let bytes = response.bytes().await?;
+    if is_json {
+        if let Ok(Response { error: Some(e), .. }) = serde_json::from_slice::<Response>(&bytes) {
+            return Err(anyhow::anyhow!("Failed to download attachment: {}", e));
+        }
+    }
+    Ok(bytes.to_vec())
+}
+
+pub async fn delete_attachment(
+    context: &ClientContext,
+    id: &Uuid,
+    attachment: &Uuid,
+) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/parts/{}/attachments/{}", id, attachment);
+    let request_url = context.url(&uri_path)?;
+    send(context.delete(request_url)).await
+}
+
+/// Download a compressed archive of every part on the server
+/// Download the label of a part, being an image unless `format` is ZPL
+pub async fn get_label(
+    context: &ClientContext,
+    id: &Uuid,
+    format: LabelFormat,
+    image: ImageFormat,
+) -> anyhow::Result<Vec<u8>> {
+    let mut request_url = context.url(&format!("/v1/parts/{}/label", id))?;
+    request_url
+        .query_pairs_mut()
+        .append_pair("format", format.into())
+        .append_pair("image", image.into());
+    let response = context.authorized(Method::GET, request_url).send().await?;
+    let is_json = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    // failures are reported in a regular response instead of the label
+    if is_json {
+        let response = response.json::<Response>().await?;
+        return Err(match response.error {
+            Some(e) => anyhow::anyhow!("Failed to get label: {}", e),
+            None => anyhow::anyhow!("Failed to get label: no label returned"),
+        });
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+pub async fn get_routing(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let request_url = context.url(&format!("/v1/parts/{}/routing", id))?;
+    send(context.get(request_url)).await
+}
+
+/// Replace every step of the routing of an assembly, clearing it when `steps`
+/// is empty
+pub async fn set_routing(
+    context: &ClientContext,
+    id: &Uuid,
+    steps: &[RoutingStep],
+) -> anyhow::Result<Response> {
+    let request_url = context.url(&format!("/v1/parts/{}/routing", id))?;
+    send(context.put(request_url).encoded(context.format, &steps)?).await
+}
+
+/// Add a step to the end of the routing of an assembly
+pub async fn add_routing_step(
+    context: &ClientContext,
+    id: &Uuid,
+    step: &RoutingStep,
+) -> anyhow::Result<Response> {
+    let request_url = context.url(&format!("/v1/parts/{}/routing", id))?;
+    send(context.post(request_url).encoded(context.format, step)?).await
+}
+
+pub async fn get_instructions(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let request_url = context.url(&format!("/v1/parts/{}/instructions", id))?;
+    send(context.get(request_url)).await
+}
+
+/// Replace the markdown work instructions of an assembly, only when it is
+/// still at `expected_version` when given
+pub async fn set_instructions(
+    context: &ClientContext,
+    id: &Uuid,
+    markdown: &str,
+    expected_version: Option<u64>,
+) -> anyhow::Result<Response> {
+    let uri_path = format!(
+        "/v1/parts/{}/instructions?{}",
+        id,
+        version_query(expected_version)
+    );
+    let request_url = context.url(&uri_path)?;
+    send(context.put(request_url).encoded(
+        context.format,
+        &query::NewInstructions {
+            markdown: markdown.into(),
+        },
+    )?)
+    .await
+}
+
+pub async fn backup(context: &ClientContext) -> anyhow::Result<Vec<u8>> {
+    let response = context
+        .authorized(Method::GET, context.url("/v1/admin/backup")?)
+        .send()
+        .await?;
+    let is_json = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    // failures are reported in a regular response instead of an archive
+    if is_json {
+        let response = response.json::<Response>().await?;
+        return Err(match response.error {
+            Some(e) => anyhow::anyhow!("Failed to back up parts: {}", e),
+            None => anyhow::anyhow!("Failed to back up parts: no archive returned"),
+        });
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Replace every part on the server with those in a backup archive, or only
+/// check the archive when `dry_run` is set
+pub async fn restore(
+    context: &ClientContext,
+    archive: Vec<u8>,
+    dry_run: bool,
+) -> anyhow::Result<Response> {
+    let uri_path = format!("/v1/admin/restore?dry_run={}", dry_run);
+    let request_url = context.url(&uri_path)?;
+    send(
+        context
+            .post(request_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/gzip")
+            .body(archive),
+    )
+    .await
+}
+
+pub async fn list_quotas(context: &ClientContext) -> anyhow::Result<Response> {
+    send(context.get(context.url("/v1/admin/quotas")?)).await
+}
+
+/// Url of the quota of an API key, which is escaped as it may hold any
+/// character
+fn quota_url(context: &ClientContext, key: &str) -> anyhow::Result<Url> {
+    let mut request_url = context.url("/v1/admin/quotas/")?;
+    request_url
+        .path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid base url: {}", context.base_url))?
+        .pop_if_empty()
+        .push(key);
+    Ok(request_url)
+}
+
+pub async fn get_quota(context: &ClientContext, key: &str) -> anyhow::Result<Response> {
+    send(context.get(quota_url(context, key)?)).await
+}
+
+/// Limit what an API key may do each day, keeping what it has already used
+/// today
+pub async fn set_quota(
+    context: &ClientContext,
+    key: &str,
+    limits: &QuotaLimits,
+) -> anyhow::Result<Response> {
+    let request_url = quota_url(context, key)?;
+    send(context.put(request_url).encoded(context.format, limits)?).await
+}
+
+/// Remove the quota of an API key, leaving it unlimited
+pub async fn delete_quota(context: &ClientContext, key: &str) -> anyhow::Result<Response> {
+    send(context.delete(quota_url(context, key)?)).await
+}
+
+/// Propose an engineering change order staging `changes` to the parts of
+/// `assembly`
+pub async fn create_category(
+    context: &ClientContext,
+    name: &str,
+    parent: Option<Uuid>,
+    attributes: Vec<AttributeSchema>,
+) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/categories")?;
+    send(context.post(request_url).encoded(
+        context.format,
+        &query::NewCategory {
+            name: name.into(),
+            parent,
+            attributes,
+        },
+    )?)
+    .await
+}
+
+pub async fn list_categories(context: &ClientContext) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/categories")?;
+    send(context.get(request_url)).await
+}
+
+pub async fn get_category(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/categories/{}", id);
+    send(context.get(context.url(&uri_path)?)).await
+}
+
+pub async fn edit_category(
+    context: &ClientContext,
+    id: &Uuid,
+    edit: &CategoryEdit,
+) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/categories/{}", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.patch(request_url).encoded(context.format, edit)?).await
+}
+
+pub async fn delete_category(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/categories/{}", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.delete(request_url)).await
+}
+
+pub async fn create_eco(
+    context: &ClientContext,
+    assembly: &Uuid,
+    description: &str,
+    changes: Vec<EcoChange>,
+) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/ecos")?;
+    send(context.post(request_url).encoded(
+        context.format,
+        &query::NewEco {
+            assembly: *assembly,
+            description: description.into(),
+            changes,
+        },
+    )?)
+    .await
+}
+
+pub async fn list_ecos(
+    context: &ClientContext,
+    assembly: Option<&Uuid>,
+    status: Option<EcoStatus>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/ecos")?;
+    if let Some(assembly) = assembly {
+        request_url
+            .query_pairs_mut()
+            .append_pair("assembly", &assembly.to_string());
+    }
+    if let Some(status) = status {
+        request_url
+            .query_pairs_mut()
+            .append_pair("status", status.into());
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn get_eco(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/ecos/{}", id);
+    send(context.get(context.url(&uri_path)?)).await
+}
+
+pub async fn approve_eco(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/ecos/{}/approve", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url)).await
+}
+
+pub async fn reject_eco(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/ecos/{}/reject", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url)).await
+}
+
+pub async fn apply_eco(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/ecos/{}/apply", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url)).await
+}
+
+pub async fn submit_release(
+    context: &ClientContext,
+    assembly: &Uuid,
+    description: &str,
+) -> anyhow::Result<Response> {
+    let request_url = context.url("/v1/releases")?;
+    send(context.post(request_url).encoded(
+        context.format,
+        &query::NewRelease {
+            assembly: *assembly,
+            description: description.into(),
+        },
+    )?)
+    .await
+}
+
+pub async fn list_releases(
+    context: &ClientContext,
+    assembly: Option<&Uuid>,
+    status: Option<ReleaseStatus>,
+) -> anyhow::Result<Response> {
+    let mut request_url = context.url("/v1/releases")?;
+    if let Some(assembly) = assembly {
+        request_url
+            .query_pairs_mut()
+            .append_pair("assembly", &assembly.to_string());
+    }
+    if let Some(status) = status {
+        request_url
+            .query_pairs_mut()
+            .append_pair("status", status.into());
+    }
+    send(context.get(request_url)).await
+}
+
+pub async fn get_release(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/releases/{}", id);
+    send(context.get(context.url(&uri_path)?)).await
+}
+
+/// Approve a release on behalf of the role whose token the client was given
+pub async fn approve_release(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/releases/{}/approve", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url)).await
+}
+
+/// Reject a release on behalf of the role whose token the client was given
+pub async fn reject_release(context: &ClientContext, id: &Uuid) -> anyhow::Result<Response> {
+    let uri_path: String = format!("/v1/releases/{}/reject", id);
+    let request_url = context.url(&uri_path)?;
+    send(context.post(request_url)).await
 }
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+
+use tokio::runtime::{Builder, Runtime};
+use url::Url;
+use uuid::Uuid;
+
+use crate::category::{AttributeSchema, CategoryEdit};
+use crate::eco::{EcoChange, EcoStatus};
+use crate::label::{ImageFormat, LabelFormat};
+use crate::parts_list::{
+    PartEdit, PartsListFilter, PartsListQuery, PartsListSort, PartsListUpdate, RelationshipEdit,
+};
+use crate::quota::QuotaLimits;
+use crate::release::ReleaseStatus;
+use crate::response::Response;
+use crate::routing::RoutingStep;
+use crate::supplier::SupplierPolicy;
+
+use super::{ClientContext, GraphCache, GraphChanges, WireFormat};
+
+/// Client waiting for each reply of the server before returning, for scripts
+/// and tools which don't run an async runtime of their own
+///
+/// Requests are driven by a runtime owned by the client, so it must not be
+/// used from within another async runtime.
+pub struct Client {
+    context: ClientContext,
+    runtime: Runtime,
+}
+
+impl Client {
+    pub fn new(base_url: Url) -> anyhow::Result<Client> {
+        Ok(Client {
+            context: ClientContext::new(base_url),
+            runtime: Builder::new_current_thread().enable_all().build()?,
+        })
+    }
+
+    /// Exchange bodies with the server in `format` rather than JSON
+    pub fn with_format(mut self, format: WireFormat) -> Client {
+        self.context = self.context.with_format(format);
+        self
+    }
+
+    /// Authenticate every request with `token` as a bearer token
+    pub fn with_token(mut self, token: String) -> Client {
+        self.context = self.context.with_token(token);
+        self
+    }
+
+    pub fn context(&self) -> &ClientContext {
+        &self.context
+    }
+
+    /// Blocking form of [`GraphCache::fetch`]
+    pub fn fetch_graph(&self) -> anyhow::Result<GraphCache> {
+        self.runtime.block_on(GraphCache::fetch(&self.context))
+    }
+
+    /// Blocking form of [`GraphCache::refresh`]
+    pub fn refresh_graph(&self, cache: &mut GraphCache) -> anyhow::Result<GraphChanges> {
+        self.runtime.block_on(cache.refresh(&self.context))
+    }
+}
+
+/// Define a method of `Client` for each async function of the client, taking
+/// the same arguments apart from the context
+macro_rules! blocking {
+    ($(fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        impl Client {
+            $(
+                #[doc = concat!("Blocking form of [`super::", stringify!($name), "`]")]
+                pub fn $name(&self, $($arg: $ty),*) -> anyhow::Result<$ret> {
+                    self.runtime.block_on(super::$name(&self.context, $($arg),*))
+                }
+            )*
+        }
+    };
+}
+
+blocking! {
+    fn get_index() -> String;
+    fn list_parts(filter: PartsListFilter, sort: Option<PartsListSort>) -> Response;
+    fn query_parts(query: &PartsListQuery, sort: Option<PartsListSort>) -> Response;
+    fn list_versions() -> Response;
+    fn query_parts_page(
+        query: &PartsListQuery,
+        limit: usize,
+        cursor: Option<&str>
+    ) -> Response;
+    fn create_part(name: &str, id: Option<Uuid>) -> Response;
+    fn upsert_part(
+        name: &str,
+        external_ids: BTreeMap<String, String>,
+        key: Option<&str>
+    ) -> Response;
+    fn get_part(id: &Uuid) -> Response;
+    fn get_part_by_name(name: &str) -> Response;
+    fn get_part_by_external(system: &str, external_id: &str) -> Response;
+    fn batch_get(ids: &[Uuid]) -> Response;
+    fn delete_part(id: &Uuid, expected_version: Option<u64>, force: bool) -> Response;
+    fn bulk_delete(ids: &[Uuid], recursive: bool) -> Response;
+    fn edit_part(id: &Uuid, edit: &PartEdit, expected_version: Option<u64>) -> Response;
+    fn delete_orphans() -> Response;
+    fn delete_tree(id: &Uuid, dry_run: bool) -> Response;
+    fn get_children(
+        id: &Uuid,
+        filter: PartsListFilter,
+        sort: Option<PartsListSort>
+    ) -> Response;
+    fn update_part(
+        id: &Uuid,
+        children: &[Uuid],
+        action: PartsListUpdate,
+        expected_version: Option<u64>
+    ) -> Response;
+    fn restore_part(id: &Uuid) -> Response;
+    fn move_part(id: &Uuid, from: &Uuid, to: &Uuid) -> Response;
+    fn get_relationship(id: &Uuid) -> Response;
+    fn edit_relationship(id: &Uuid, edit: &RelationshipEdit) -> Response;
+    fn get_parents(id: &Uuid) -> Response;
+    fn get_contained(id: &Uuid) -> Response;
+    fn get_ancestors(id: &Uuid, depth: Option<usize>) -> Response;
+    fn get_summary(id: &Uuid) -> Response;
+    fn get_shortage(id: &Uuid, build_qty: u64, config: Option<&str>) -> Response;
+    fn get_lead_time(id: &Uuid, config: Option<&str>, policy: SupplierPolicy) -> Response;
+    fn get_cost(id: &Uuid, qty: u64, policy: SupplierPolicy, currency: Option<&str>) -> Response;
+    fn get_cost_history(id: &Uuid) -> Response;
+    fn get_cost_trend(id: &Uuid) -> Response;
+    fn get_rfq(id: &Uuid, qty: u64, policy: SupplierPolicy, currency: Option<&str>) -> Response;
+    fn get_picklist(id: &Uuid, qty: u64, config: Option<&str>) -> Response;
+    fn get_label(id: &Uuid, format: LabelFormat, image: ImageFormat) -> Vec<u8>;
+    fn get_routing(id: &Uuid) -> Response;
+    fn set_routing(id: &Uuid, steps: &[RoutingStep]) -> Response;
+    fn add_routing_step(id: &Uuid, step: &RoutingStep) -> Response;
+    fn get_instructions(id: &Uuid) -> Response;
+    fn set_instructions(id: &Uuid, markdown: &str, expected_version: Option<u64>) -> Response;
+    fn search(query: &str, fuzzy: bool) -> Response;
+    fn top_used(limit: usize) -> Response;
+    fn common_parts(ids: &[Uuid]) -> Response;
+    fn get_stats() -> Response;
+    fn get_changes(since: u64, limit: Option<usize>) -> Response;
+    fn get_change_snapshot() -> Response;
+    fn get_replication() -> Response;
+    fn get_perf() -> Response;
+    fn validate() -> Response;
+    fn duplicates() -> Response;
+    fn upload_attachment(
+        id: &Uuid,
+        filename: &str,
+        content_type: &str,
+        contents: Vec<u8>
+    ) -> Response;
+    fn list_attachments(id: &Uuid) -> Response;
+    fn download_attachment(id: &Uuid, attachment: &Uuid) -> Vec<u8>;
+    fn delete_attachment(id: &Uuid, attachment: &Uuid) -> Response;
+    fn backup() -> Vec<u8>;
+    fn restore(archive: Vec<u8>, dry_run: bool) -> Response;
+    fn list_quotas() -> Response;
+    fn get_quota(key: &str) -> Response;
+    fn set_quota(key: &str, limits: &QuotaLimits) -> Response;
+    fn delete_quota(key: &str) -> Response;
+    fn create_category(
+        name: &str,
+        parent: Option<Uuid>,
+        attributes: Vec<AttributeSchema>
+    ) -> Response;
+    fn list_categories() -> Response;
+    fn get_category(id: &Uuid) -> Response;
+    fn edit_category(id: &Uuid, edit: &CategoryEdit) -> Response;
+    fn delete_category(id: &Uuid) -> Response;
+    fn create_eco(assembly: &Uuid, description: &str, changes: Vec<EcoChange>) -> Response;
+    fn list_ecos(assembly: Option<&Uuid>, status: Option<EcoStatus>) -> Response;
+    fn get_eco(id: &Uuid) -> Response;
+    fn approve_eco(id: &Uuid) -> Response;
+    fn reject_eco(id: &Uuid) -> Response;
+    fn apply_eco(id: &Uuid) -> Response;
+    fn submit_release(assembly: &Uuid, description: &str) -> Response;
+    fn list_releases(assembly: Option<&Uuid>, status: Option<ReleaseStatus>) -> Response;
+    fn get_release(id: &Uuid) -> Response;
+    fn approve_release(id: &Uuid) -> Response;
+    fn reject_release(id: &Uuid) -> Response;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::client::offline::is_unreachable;
+    use crate::errors::PartsErrorCode;
+
+    /// Url of a server answering a single request with `status` and `body`
+    fn replying(status: &'static str, body: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 4096]);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        });
+        url.parse().unwrap()
+    }
+
+    #[test]
+    fn reports_failures() {
+        // nothing listens on the port once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = Client::new(format!("http://127.0.0.1:{}", port).parse().unwrap()).unwrap();
+        let e = client.get_stats().unwrap_err();
+        assert!(is_unreachable(&e));
+
+        // a reply which isn't a response of the server is an error, but not
+        // one of reaching it
+        let client = Client::new(replying("502 Bad Gateway", "<html>proxy down</html>")).unwrap();
+        let e = client.get_stats().unwrap_err();
+        assert!(!is_unreachable(&e));
+
+        // while errors reported by the server are part of its response
+        let client = Client::new(replying(
+            "200 OK",
+            r#"{"error":{"code":2,"description":"Part doesn't exist"}}"#,
+        ))
+        .unwrap();
+        let response = client.get_part(&Uuid::new_v4()).unwrap();
+        assert_eq!(
+            response.error.map(|e| *e.code()),
+            Some(PartsErrorCode::MissingPartError)
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::analysis;
+use crate::parts_list::{Part, PartsList, PartsListError, PartsListFilter};
+
+use super::ClientContext;
+
+/// Number of changed parts fetched by each request of a refresh, below the
+/// default limit on the parts listed per request
+const REFRESH_BATCH: usize = 500;
+
+/// Parts added, changed and removed on the server since a cache was last
+/// brought up to date
+#[derive(Debug, Default, PartialEq)]
+pub struct GraphChanges {
+    pub added: Vec<Uuid>,
+    pub changed: Vec<Uuid>,
+    pub removed: Vec<Uuid>,
+}
+
+impl GraphChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Difference between the components two assemblies are built from, with the
+/// quantity of each used by one of the assembly
+#[derive(Debug, Default, PartialEq)]
+pub struct BomDiff {
+    /// Components only used by the second assembly
+    pub added: Vec<(Uuid, u64)>,
+    /// Components only used by the first assembly
+    pub removed: Vec<(Uuid, u64)>,
+    /// Components used by both in different quantities, as used by the first
+    /// and then the second
+    pub changed: Vec<(Uuid, u64, u64)>,
+}
+
+/// Copy of every part of a server, answering traversals of the graph locally
+/// rather than with a request each
+///
+/// `refresh` brings the copy up to date by comparing the version of each part
+/// on the server with the copy, only fetching the parts which changed.
+pub struct GraphCache {
+    parts: PartsList,
+}
+
+impl GraphCache {
+    /// Cache of parts already at hand, such as those of a backup
+    pub fn new<I: IntoIterator<Item = Part>>(parts: I) -> GraphCache {
+        GraphCache {
+            parts: parts.into_iter().collect(),
+        }
+    }
+
+    /// Download every part of the server
+    pub async fn fetch(context: &ClientContext) -> anyhow::Result<GraphCache> {
+        let response = super::list_parts(context, PartsListFilter::All, None).await?;
+        if let Some(e) = response.error_message() {
+            return Err(anyhow::anyhow!("Failed to fetch parts: {}", e));
+        }
+        Ok(GraphCache::new(response.data.unwrap_or_default()))
+    }
+
+    /// Bring the copy up to date with the server, fetching only the parts
+    /// whose version changed since they were cached
+    pub async fn refresh(&mut self, context: &ClientContext) -> anyhow::Result<GraphChanges> {
+        let response = super::list_versions(context).await?;
+        if let Some(e) = response.error_message() {
+            return Err(anyhow::anyhow!("Failed to list part versions: {}", e));
+        }
+        let mut changes = GraphChanges::default();
+        let mut current = HashSet::new();
+        for part in response.data.unwrap_or_default() {
+            current.insert(part.id);
+            match self.parts.get(&part.id) {
+                Ok(cached) if cached.version == part.version => {}
+                Ok(_) => changes.changed.push(part.id),
+                Err(_) => changes.added.push(part.id),
+            }
+        }
+        changes.removed = self
+            .parts
+            .iter()
+            .map(|part| part.id)
+            .filter(|id| !current.contains(id))
+            .collect();
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+
+        let mut parts: HashMap<Uuid, Part> = self
+            .parts
+            .iter()
+            .filter(|part| current.contains(&part.id))
+            .map(|part| (part.id, part.clone()))
+            .collect();
+        let stale: Vec<Uuid> = changes
+            .added
+            .iter()
+            .chain(&changes.changed)
+            .copied()
+            .collect();
+        for ids in stale.chunks(REFRESH_BATCH) {
+            let response = super::batch_get(context, ids).await?;
+            if let Some(e) = response.error_message() {
+                return Err(anyhow::anyhow!("Failed to fetch changed parts: {}", e));
+            }
+            // parts deleted since they were listed are dropped with the rest
+            for id in response.missing.unwrap_or_default() {
+                parts.remove(&id);
+            }
+            for part in response.data.unwrap_or_default() {
+                parts.insert(part.id, part);
+            }
+        }
+        changes.added.sort();
+        changes.changed.sort();
+        changes.removed.sort();
+        self.parts = parts.into_values().collect();
+        Ok(changes)
+    }
+
+    /// Every part held by the cache
+    pub fn parts(&self) -> &PartsList {
+        &self.parts
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&Part> {
+        self.parts.get(id).ok()
+    }
+
+    /// Direct children of a part
+    pub fn children(&self, id: &Uuid) -> Result<Vec<&Part>, PartsListError> {
+        self.parts.get_children(id, PartsListFilter::TopLevel)
+    }
+
+    /// Every part below a part, at any level
+    pub fn descendants(&self, id: &Uuid) -> Result<Vec<&Part>, PartsListError> {
+        self.parts.get_children(id, PartsListFilter::All)
+    }
+
+    /// Every assembly containing a part, at any level
+    pub fn ancestors(&self, id: &Uuid) -> Result<Vec<&Part>, PartsListError> {
+        self.parts.get_ancestors(id, None)
+    }
+
+    /// Components a part is built from and the quantity of each used by one of
+    /// the part, a part without children being its own only component
+    pub fn explode(&self, id: &Uuid) -> Result<BTreeMap<Uuid, u64>, PartsListError> {
+        self.parts.get(id)?;
+        let by_id: HashMap<Uuid, &Part> = self.parts.iter().map(|part| (part.id, part)).collect();
+        Ok(analysis::flatten(&by_id, id, &mut HashMap::new()))
+    }
+
+    /// Compare the components assembly `a` is built from with those of `b`
+    pub fn diff(&self, a: &Uuid, b: &Uuid) -> Result<BomDiff, PartsListError> {
+        let before = self.explode(a)?;
+        let after = self.explode(b)?;
+        let mut diff = BomDiff::default();
+        for (id, quantity) in &before {
+            match after.get(id) {
+                None => diff.removed.push((*id, *quantity)),
+                Some(other) if other != quantity => diff.changed.push((*id, *quantity, *other)),
+                Some(_) => {}
+            }
+        }
+        for (id, quantity) in &after {
+            if !before.contains_key(id) {
+                diff.added.push((*id, *quantity));
+            }
+        }
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{PartsListUpdate, Relationship, RelationshipEdit};
+
+    #[test]
+    fn local_traversals() {
+        let mut parts = PartsList::new();
+        let bike = parts.add(Part::new("bike")).unwrap().id;
+        let trike = parts.add(Part::new("trike")).unwrap().id;
+        let wheel = parts.add(Part::new("wheel")).unwrap().id;
+        let spoke = parts.add(Part::new("spoke")).unwrap().id;
+        let bell = parts.add(Part::new("bell")).unwrap().id;
+        parts
+            .update(&bike, &[&wheel, &bell], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&trike, &[&wheel], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&wheel, &[&spoke], PartsListUpdate::Add)
+            .unwrap();
+        let quantity = |n: u64| RelationshipEdit {
+            quantity: Some(n),
+            notes: None,
+        };
+        parts
+            .edit_relationship(&Relationship::id_for(&bike, &wheel), &quantity(2))
+            .unwrap();
+        parts
+            .edit_relationship(&Relationship::id_for(&trike, &wheel), &quantity(3))
+            .unwrap();
+        parts
+            .edit_relationship(&Relationship::id_for(&wheel, &spoke), &quantity(32))
+            .unwrap();
+
+        let cache = GraphCache::new(parts.iter().cloned());
+        assert_eq!(cache.children(&bike).unwrap().len(), 2);
+        assert_eq!(cache.descendants(&bike).unwrap().len(), 3);
+        assert_eq!(cache.ancestors(&spoke).unwrap().len(), 3);
+        let exploded = cache.explode(&bike).unwrap();
+        assert_eq!(exploded.get(&spoke), Some(&64));
+        assert_eq!(exploded.get(&bell), Some(&1));
+        assert_eq!(
+            cache.diff(&bike, &trike).unwrap(),
+            BomDiff {
+                added: vec![],
+                removed: vec![(bell, 1)],
+                changed: vec![(spoke, 64, 96)],
+            }
+        );
+        assert!(cache.explode(&Uuid::new_v4()).is_err());
+    }
+}
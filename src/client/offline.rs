@@ -0,0 +1,292 @@
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::errors::PartsErrorCode;
+use crate::parts_list::PartsListUpdate;
+use crate::response::Response;
+
+use super::ClientContext;
+
+/// Change to the parts of a server recorded while it couldn't be reached
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum QueuedChange {
+    /// Part created with an id chosen by the client, so later changes can
+    /// refer to it before it reaches the server
+    CreatePart { name: String, id: Uuid },
+    UpdatePart {
+        id: Uuid,
+        children: Vec<Uuid>,
+        action: String,
+        expected_version: Option<u64>,
+    },
+    DeletePart {
+        id: Uuid,
+        expected_version: Option<u64>,
+        force: bool,
+    },
+}
+
+impl QueuedChange {
+    pub fn update_part(
+        id: Uuid,
+        children: Vec<Uuid>,
+        action: PartsListUpdate,
+        expected_version: Option<u64>,
+    ) -> QueuedChange {
+        QueuedChange::UpdatePart {
+            id,
+            children,
+            action: Into::<&str>::into(action).to_string(),
+            expected_version,
+        }
+    }
+
+    /// Make the change on the server
+    pub async fn send(&self, context: &ClientContext) -> anyhow::Result<Response> {
+        match self {
+            QueuedChange::CreatePart { name, id } => {
+                super::create_part(context, name, Some(*id)).await
+            }
+            QueuedChange::UpdatePart {
+                id,
+                children,
+                action,
+                expected_version,
+            } => {
+                let action = PartsListUpdate::try_from(action.as_str())?;
+                super::update_part(context, id, children, action, *expected_version).await
+            }
+            QueuedChange::DeletePart {
+                id,
+                expected_version,
+                force,
+            } => super::delete_part(context, id, *expected_version, *force).await,
+        }
+    }
+}
+
+/// Queued change the server refused when it was replayed
+#[derive(Debug)]
+pub struct Conflict {
+    pub change: QueuedChange,
+    pub error: String,
+}
+
+/// Outcome of replaying the changes of a queue
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Number of changes made on the server
+    pub applied: usize,
+    /// Changes refused by the server, which are dropped from the queue
+    pub conflicts: Vec<Conflict>,
+    /// Changes left in the queue as the server became unreachable again, or
+    /// answered with an error which isn't a conflict
+    pub pending: usize,
+    /// Error of the reply which stopped the replay, such as the server being
+    /// busy, whose change is kept in the queue to be made later
+    pub stopped: Option<String>,
+}
+
+/// Whether an error replying to a queued change means it can't be made as
+/// recorded, as the parts it refers to were changed, deleted or are still in
+/// use, rather than the server being unable to make it for now
+fn is_conflict(code: &PartsErrorCode) -> bool {
+    matches!(
+        code,
+        PartsErrorCode::VersionConflict
+            | PartsErrorCode::MissingPartError
+            | PartsErrorCode::PartInUse
+    )
+}
+
+/// Whether a request failed for lack of a connection to the server, rather
+/// than being answered with an error
+pub fn is_unreachable(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.is_connect() || e.is_timeout(),
+        None => false,
+    }
+}
+
+/// Changes waiting for the server to be reachable again, kept in a file of
+/// one JSON change per line so they survive the client exiting
+pub struct OfflineQueue {
+    path: PathBuf,
+}
+
+impl OfflineQueue {
+    pub fn new<P: AsRef<Path>>(path: P) -> OfflineQueue {
+        OfflineQueue {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record a change at the end of the queue
+    pub fn push(&self, change: &QueuedChange) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(change)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Changes in the queue, in the order they were recorded
+    pub fn pending(&self) -> anyhow::Result<Vec<QueuedChange>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut changes = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                changes.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Make the queued changes on the server in order, stopping as soon as it
+    /// can't be reached and keeping the changes not made yet
+    ///
+    /// Changes the server refuses, such as an update of a part deleted or
+    /// changed by someone else in the meantime, are reported as conflicts and
+    /// dropped. Any other error, such as the server being busy, also stops the
+    /// replay, keeping the change it answered and those after it.
+    pub async fn replay(&self, context: &ClientContext) -> anyhow::Result<ReplayReport> {
+        let changes = self.pending()?;
+        let mut report = ReplayReport::default();
+        let mut sent = 0;
+        for change in &changes {
+            let response = match change.send(context).await {
+                Ok(response) => response,
+                Err(e) if is_unreachable(&e) => break,
+                Err(e) => return Err(e),
+            };
+            match (response.error.as_ref(), response.error_message()) {
+                (Some(e), Some(error)) if is_conflict(e.code()) => {
+                    report.conflicts.push(Conflict {
+                        change: change.clone(),
+                        error,
+                    })
+                }
+                (_, Some(error)) => {
+                    report.stopped = Some(error);
+                    break;
+                }
+                _ => report.applied += 1,
+            }
+            sent += 1;
+        }
+        report.pending = changes.len() - sent;
+        self.rewrite(&changes[sent..])?;
+        Ok(report)
+    }
+
+    /// Replace the queue with `changes`, removing the file once it is empty
+    fn rewrite(&self, changes: &[QueuedChange]) -> anyhow::Result<()> {
+        if changes.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        let mut replacement = self.path.clone().into_os_string();
+        replacement.push(".tmp");
+        let mut contents = String::new();
+        for change in changes {
+            contents.push_str(&serde_json::to_string(change)?);
+            contents.push('\n');
+        }
+        fs::write(&replacement, contents)?;
+        fs::rename(&replacement, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_until_unreachable() {
+        let path = std::env::temp_dir().join(format!("bom-queue-{}.jsonl", Uuid::new_v4()));
+        let queue = OfflineQueue::new(&path);
+        assert!(queue.pending().unwrap().is_empty());
+        let id = Uuid::new_v4();
+        let changes = vec![
+            QueuedChange::CreatePart {
+                name: String::from("bracket"),
+                id,
+            },
+            QueuedChange::update_part(id, vec![Uuid::new_v4()], PartsListUpdate::Add, Some(1)),
+        ];
+        for change in &changes {
+            queue.push(change).unwrap();
+        }
+        assert_eq!(queue.pending().unwrap(), changes);
+
+        // nothing listens on the port, so every change stays queued
+        let context = ClientContext::new("http://127.0.0.1:9".parse().unwrap());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let report = runtime.block_on(queue.replay(&context)).unwrap();
+        assert_eq!((report.applied, report.pending), (0, 2));
+        assert!(report.conflicts.is_empty());
+        assert_eq!(queue.pending().unwrap(), changes);
+
+        // a busy server leaves the change it refused and those after it queued
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let context = ClientContext::new(
+            format!("http://{}", listener.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+        );
+        let busy = serde_json::to_string(
+            &Response::new().error(PartsErrorCode::Busy, "Too many writes are waiting"),
+        )
+        .unwrap();
+        let server = std::thread::spawn(move || {
+            use std::io::Read;
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                busy.len(),
+                busy
+            )
+            .unwrap();
+        });
+        let report = runtime.block_on(queue.replay(&context)).unwrap();
+        server.join().unwrap();
+        assert_eq!((report.applied, report.pending), (0, 2));
+        assert!(report.conflicts.is_empty());
+        assert_matches!(report.stopped, Some(e) if e.contains("Too many writes"));
+        assert_eq!(queue.pending().unwrap(), changes);
+
+        queue.rewrite(&changes[1..]).unwrap();
+        assert_eq!(queue.pending().unwrap(), changes[1..]);
+        queue.rewrite(&[]).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use crate::analysis;
+use crate::currency::{self, ExchangeRates};
+use crate::parts_list::{Part, PartsListError, PartsListFilter};
+use crate::store::PartsStore;
+use crate::supplier::{self, Quote, SupplierPolicy};
+
+/// Unit cost a part was given at a point in time, which it kept until the
+/// next record
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CostRecord {
+    /// Milliseconds since the UNIX epoch
+    pub time: u64,
+    pub unit_cost: f64,
+}
+
+/// Unit cost of a part along with every cost it had before, oldest first
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CostHistory {
+    pub id: Uuid,
+    pub unit_cost: f64,
+    pub history: Vec<CostRecord>,
+}
+
+impl CostHistory {
+    pub fn of(part: &Part) -> CostHistory {
+        CostHistory {
+            id: part.id,
+            unit_cost: part.unit_cost,
+            history: part.cost_history.clone(),
+        }
+    }
+}
+
+/// Cost of the components of an assembly at a point in time, from when one of
+/// their costs changed, in the base currency
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CostPoint {
+    /// Milliseconds since the UNIX epoch
+    pub time: u64,
+    pub cost: f64,
+}
+
+/// Cost of a component of an assembly, bought from the supplier chosen by the
+/// policy of the rollup when it has any
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CostLine {
+    pub id: Uuid,
+    pub name: String,
+    /// Quantity of the component used to build the quantity of the assembly
+    pub quantity: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supplier: Option<String>,
+    /// Least quantity of the price break of the supplier the component is
+    /// bought at, when buying the quantity reaches one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_break: Option<u64>,
+    pub unit_cost: f64,
+    /// Cost of the quantity of the component
+    pub cost: f64,
+}
+
+/// Cost of building a quantity of an assembly from the current costs of its
+/// components
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CostRollup {
+    pub id: Uuid,
+    /// Quantity of the assembly built
+    pub quantity: u64,
+    /// Currency every cost of the rollup is given in
+    pub currency: String,
+    pub cost: f64,
+    /// Components of the assembly, ordered by name
+    pub lines: Vec<CostLine>,
+}
+
+/// Component of a request for quote, asking suppliers to price the quantity
+/// of it needed for a number of builds
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RfqLine {
+    pub id: Uuid,
+    pub name: String,
+    /// Quantity of the component needed for the builds
+    pub quantity: u64,
+    /// Manufacturer part number the chosen supplier sells the component under
+    pub mpn: Option<String>,
+    pub supplier: Option<String>,
+    /// Price aimed for each of the component, being what it costs today
+    pub target_price: f64,
+    pub currency: String,
+}
+
+/// Milliseconds since the UNIX epoch, as recorded in cost histories
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Give a part a new unit cost at `time`, recording it in the history of the
+/// part when it differs from the current one
+pub fn record(part: &mut Part, unit_cost: f64, time: u64) {
+    if unit_cost == part.unit_cost && !part.cost_history.is_empty() {
+        return;
+    }
+    part.unit_cost = unit_cost;
+    part.cost_history.push(CostRecord { time, unit_cost });
+}
+
+/// Unit cost a part had at `time`, which is 0 before its cost was first set
+pub fn cost_at(part: &Part, time: u64) -> f64 {
+    part.cost_history
+        .iter()
+        .rev()
+        .find(|record| record.time <= time)
+        .map_or(0.0, |record| record.unit_cost)
+}
+
+/// Check that the unit cost of a part is finite and isn't negative, and is
+/// given in a currency named by its code
+pub fn check(part: &Part) -> Result<(), PartsListError> {
+    if !part.unit_cost.is_finite() || part.unit_cost < 0.0 {
+        return Err(PartsListError::InvalidUnitCost {
+            id: part.id,
+            cost: part.unit_cost,
+        });
+    }
+    part.currency
+        .as_deref()
+        .map_or(Ok(()), currency::check_code)
+}
+
+/// Work out the cost of building `quantity` of an assembly from the
+/// components it is flattened into, as for `cost_trend`
+///
+/// Components with suppliers cost what the supplier chosen by `policy`
+/// charges for the quantity of them needed, at the price break it reaches,
+/// and others their own unit cost. Every cost is converted through
+/// `rates` into `currency`, failing when a component is costed in a currency
+/// without a rate.
+pub fn rollup(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    policy: SupplierPolicy,
+    quantity: u64,
+    rates: &ExchangeRates,
+    currency: &str,
+) -> Result<CostRollup, PartsListError> {
+    let mut lines = price_components(
+        parts,
+        id,
+        policy,
+        quantity,
+        rates,
+        currency,
+        |part, needed, quote| CostLine {
+            id: part.id,
+            name: part.name.clone(),
+            quantity: needed,
+            supplier: quote.supplier.map(|supplier| supplier.name.clone()),
+            price_break: quote.price_break,
+            unit_cost: quote.unit_cost,
+            cost: quote.unit_cost * needed as f64,
+        },
+    )?;
+    lines.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    Ok(CostRollup {
+        id: *id,
+        quantity,
+        currency: currency.into(),
+        cost: lines.iter().fold(0.0, |total, line| total + line.cost),
+        lines,
+    })
+}
+
+/// List the components to buy for `quantity` builds of an assembly as a
+/// request for quote, ordered by name
+///
+/// Each component is priced as for `rollup`, and its target price is what the
+/// supplier chosen by `policy` charges for the quantity today, or its own unit
+/// cost when it has no suppliers.
+pub fn rfq(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    policy: SupplierPolicy,
+    quantity: u64,
+    rates: &ExchangeRates,
+    currency: &str,
+) -> Result<Vec<RfqLine>, PartsListError> {
+    let mut lines = price_components(
+        parts,
+        id,
+        policy,
+        quantity,
+        rates,
+        currency,
+        |part, needed, quote| RfqLine {
+            id: part.id,
+            name: part.name.clone(),
+            quantity: needed,
+            mpn: quote.supplier.and_then(|supplier| supplier.mpn.clone()),
+            supplier: quote.supplier.map(|supplier| supplier.name.clone()),
+            target_price: quote.unit_cost,
+            currency: currency.into(),
+        },
+    )?;
+    lines.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    Ok(lines)
+}
+
+/// Write a request for quote as CSV, with a header row naming the columns
+pub fn rfq_csv(lines: &[RfqLine]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for line in lines {
+        writer.serialize(line)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Price the quantity of each component needed for `quantity` of an
+/// assembly, as flattened for `cost_trend`, making a line of each with `line`
+fn price_components<T>(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    policy: SupplierPolicy,
+    quantity: u64,
+    rates: &ExchangeRates,
+    currency: &str,
+    line: impl Fn(&Part, u64, Quote) -> T,
+) -> Result<Vec<T>, PartsListError> {
+    let assembly = parts.get(id)?;
+    let descendants = parts.get_children(id, PartsListFilter::All)?;
+    let mut by_id: HashMap<Uuid, &Part> = descendants.iter().map(|part| (part.id, part)).collect();
+    by_id.insert(assembly.id, &assembly);
+
+    let mut lines = Vec::new();
+    for (component, per_assembly) in analysis::flatten(&by_id, id, &mut HashMap::new()) {
+        let part = match by_id.get(&component) {
+            Some(part) => part,
+            None => continue,
+        };
+        let needed = per_assembly.saturating_mul(quantity);
+        let quote = supplier::quote(part, policy, needed, rates, currency)?;
+        lines.push(line(part, needed, quote));
+    }
+    Ok(lines)
+}
+
+/// Work out the cost of the components needed to build one of an assembly
+/// each time the cost of one of them changed, oldest first
+///
+/// Each component counts for its quantity in the assembly as flattened, with
+/// every subassembly blown through and stock ignored, so the trend follows the
+/// costs of purchased parts rather than those given to subassemblies. Costs in
+/// other currencies are converted into the base currency at today's rates.
+pub fn cost_trend(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    rates: &ExchangeRates,
+) -> Result<Vec<CostPoint>, PartsListError> {
+    let assembly = parts.get(id)?;
+    let descendants = parts.get_children(id, PartsListFilter::All)?;
+    let mut by_id: HashMap<Uuid, &Part> = descendants.iter().map(|part| (part.id, part)).collect();
+    by_id.insert(assembly.id, &assembly);
+
+    let components = analysis::flatten(&by_id, id, &mut HashMap::new())
+        .into_iter()
+        .filter_map(|(component, quantity)| by_id.get(&component).map(|part| (*part, quantity)))
+        .map(|(part, quantity)| {
+            let rate = rates.convert(1.0, part.currency.as_deref(), rates.base())?;
+            Ok((part, quantity as f64 * rate))
+        })
+        .collect::<Result<Vec<(&Part, f64)>, PartsListError>>()?;
+    let times: BTreeSet<u64> = components
+        .iter()
+        .flat_map(|(part, _)| part.cost_history.iter().map(|record| record.time))
+        .collect();
+    Ok(times
+        .into_iter()
+        .map(|time| CostPoint {
+            time,
+            cost: components
+                .iter()
+                .map(|(part, quantity)| cost_at(part, time) * quantity)
+                .sum(),
+        })
+        .collect())
+}
+
+/// Write a cost trend as CSV, with a header row naming the columns
+pub fn cost_trend_csv(points: &[CostPoint]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for point in points {
+        writer.serialize(point)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{PartEdit, PartsList, PartsListUpdate, Relationship, RelationshipEdit};
+    use crate::supplier::{PriceBreak, Supplier};
+
+    #[test]
+    fn assembly_cost_over_time() {
+        let mut parts = PartsList::new();
+        let mut screw = Part::new("screw");
+        record(&mut screw, 0.1, 100);
+        record(&mut screw, 0.1, 150);
+        record(&mut screw, 0.2, 300);
+        let mut bracket = Part::new("bracket");
+        record(&mut bracket, 2.0, 200);
+        assert_eq!(screw.cost_history.len(), 2);
+        assert_eq!((cost_at(&screw, 99), cost_at(&screw, 299)), (0.0, 0.1));
+
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(screw).unwrap().id;
+        let bracket = parts.add(bracket).unwrap().id;
+        parts
+            .update(&assy, &[&screw, &bracket], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .edit_relationship(
+                &Relationship::id_for(&assy, &screw),
+                &RelationshipEdit {
+                    quantity: Some(4),
+                    notes: None,
+                },
+            )
+            .unwrap();
+
+        let rates = ExchangeRates::default();
+        let trend = cost_trend(&parts, &assy, &rates).unwrap();
+        let points: Vec<(u64, String)> = trend
+            .iter()
+            .map(|point| (point.time, format!("{:.2}", point.cost)))
+            .collect();
+        assert_eq!(
+            points,
+            vec![
+                (100, String::from("0.40")),
+                (200, String::from("2.40")),
+                (300, String::from("2.80")),
+            ]
+        );
+        assert!(cost_trend_csv(&trend)
+            .unwrap()
+            .starts_with("time,cost\n100,"));
+
+        let bought = PartEdit {
+            suppliers: Some(vec![Supplier {
+                name: String::from("Acme"),
+                unit_cost: 1.5,
+                price_breaks: Vec::new(),
+                lead_time_days: 5,
+                currency: None,
+                mpn: None,
+            }]),
+            ..PartEdit::default()
+        };
+        parts.edit(&bracket, &bought).unwrap();
+        let rolled = rollup(&parts, &assy, SupplierPolicy::Preferred, 1, &rates, "USD").unwrap();
+        assert_eq!(format!("{:.2}", rolled.cost), "2.30");
+        assert_eq!(rolled.lines[0].supplier.as_deref(), Some("Acme"));
+        assert_eq!(
+            (rolled.lines[1].quantity, rolled.lines[1].supplier.as_ref()),
+            (4, None)
+        );
+
+        // building more reaches the price break of the supplier
+        let price_breaks = PartEdit {
+            suppliers: Some(vec![Supplier {
+                name: String::from("Acme"),
+                unit_cost: 1.5,
+                price_breaks: vec![PriceBreak {
+                    min_quantity: 50,
+                    unit_cost: 1.0,
+                }],
+                lead_time_days: 5,
+                currency: None,
+                mpn: Some(String::from("BRK-10")),
+            }]),
+            ..PartEdit::default()
+        };
+        parts.edit(&bracket, &price_breaks).unwrap();
+        let rolled = rollup(&parts, &assy, SupplierPolicy::Preferred, 50, &rates, "USD").unwrap();
+        assert_eq!(rolled.quantity, 50);
+        assert_eq!(
+            (rolled.lines[0].quantity, rolled.lines[0].price_break),
+            (50, Some(50))
+        );
+        assert_eq!(
+            (rolled.lines[1].quantity, format!("{:.2}", rolled.cost)),
+            (200, String::from("90.00"))
+        );
+
+        // the request for quote lists the same lines with their part numbers
+        let lines = rfq(&parts, &assy, SupplierPolicy::Preferred, 50, &rates, "USD").unwrap();
+        assert_eq!(
+            (lines[0].mpn.as_deref(), lines[0].target_price),
+            (Some("BRK-10"), 1.0)
+        );
+        assert_eq!((lines[1].quantity, lines[1].mpn.as_ref()), (200, None));
+        let csv = rfq_csv(&lines).unwrap();
+        assert!(csv.starts_with("id,name,quantity,mpn,supplier,target_price,currency\n"));
+        assert!(csv.contains(",bracket,50,BRK-10,Acme,1.0,USD\n"));
+        assert!(csv.contains(",screw,200,,,0.2,USD\n"));
+
+        let mut broken = Part::new("broken");
+        broken.unit_cost = -1.0;
+        assert!(check(&broken).is_err());
+    }
+
+    #[test]
+    fn mixed_currency_rollup() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let mut bracket = Part::new("bracket");
+        record(&mut bracket, 2.0, 100);
+        bracket.currency = Some(String::from("EUR"));
+        let bracket = parts.add(bracket).unwrap().id;
+        let mut screw = Part::new("screw");
+        record(&mut screw, 0.5, 200);
+        let screw = parts.add(screw).unwrap().id;
+        parts
+            .update(&assy, &[&bracket, &screw], PartsListUpdate::Add)
+            .unwrap();
+
+        let mut rates = ExchangeRates::default();
+        assert_matches!(
+            rollup(&parts, &assy, SupplierPolicy::Preferred, 1, &rates, "USD"),
+            Err(PartsListError::UnknownCurrency { ref currency }) if currency == "EUR"
+        );
+        rates.with_rate("EUR", 1.25).unwrap();
+        let usd = rollup(&parts, &assy, SupplierPolicy::Preferred, 1, &rates, "USD").unwrap();
+        assert_eq!((usd.currency.as_str(), usd.cost), ("USD", 3.0));
+        let eur = rollup(&parts, &assy, SupplierPolicy::Preferred, 1, &rates, "EUR").unwrap();
+        assert_eq!((eur.lines[0].unit_cost, eur.cost), (2.0, 2.4));
+
+        // the trend is given in the base currency
+        let trend = cost_trend(&parts, &assy, &rates).unwrap();
+        assert_eq!(trend[1].cost, 3.0);
+
+        let mut broken = Part::new("broken");
+        broken.currency = Some(String::from("euro"));
+        assert_matches!(check(&broken), Err(PartsListError::InvalidCurrency { .. }));
+    }
+}
@@ -0,0 +1,322 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::{self, DefaultHasher};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::slice;
+use std::sync::Arc;
+use std::vec;
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Number of shards the entries of a `CowMap` are split between
+const SHARDS: usize = 64;
+
+/// Hash map split into shards shared between its clones
+///
+/// Cloning the map only clones a reference to each shard, and changing an
+/// entry copies the one shard holding it if another clone still shares it.
+/// Snapshots of a large map can then be taken after every change while each
+/// change only copies a small part of the map.
+pub struct CowMap<K, V> {
+    shards: Vec<Arc<HashMap<K, V>>>,
+    len: usize,
+}
+
+impl<K, V> CowMap<K, V> {
+    pub fn new() -> CowMap<K, V> {
+        CowMap {
+            shards: (0..SHARDS).map(|_| Arc::new(HashMap::new())).collect(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the entries in arbitrary order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            shards: self.shards.iter(),
+            entries: None,
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.iter())
+    }
+
+    /// Keys of the shards not shared with `other`, covering every entry either
+    /// map changed since one was cloned from the other
+    ///
+    /// Keys held by both maps may be listed twice.
+    pub fn keys_not_shared<'a>(&'a self, other: &'a CowMap<K, V>) -> impl Iterator<Item = &'a K> {
+        self.shards
+            .iter()
+            .zip(&other.shards)
+            .filter(|(shard, other)| !Arc::ptr_eq(shard, other))
+            .flat_map(|(shard, other)| shard.keys().chain(other.keys()))
+    }
+}
+
+impl<K: Hash + Eq, V> CowMap<K, V> {
+    fn shard<Q>(key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % SHARDS as u64) as usize
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shards[CowMap::<K, V>::shard(key)].get(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> CowMap<K, V> {
+    /// Shard holding `key`, copied first if it's shared with another clone
+    fn shard_mut<Q>(&mut self, key: &Q) -> &mut HashMap<K, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        Arc::make_mut(&mut self.shards[CowMap::<K, V>::shard(key)])
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // a missing key leaves the shard shared
+        if !self.contains_key(key) {
+            return None;
+        }
+        self.shard_mut(key).get_mut(key)
+    }
+
+    /// Value of `key`, inserting the default value first when it's missing
+    pub fn get_or_default(&mut self, key: K) -> &mut V
+    where
+        V: Default,
+    {
+        if !self.contains_key(&key) {
+            self.len += 1;
+        }
+        self.shard_mut(&key).entry(key).or_default()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let replaced = self.shard_mut(&key).insert(key, value);
+        if replaced.is_none() {
+            self.len += 1;
+        }
+        replaced
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.contains_key(key) {
+            return None;
+        }
+        self.len -= 1;
+        self.shard_mut(key).remove(key)
+    }
+}
+
+impl<K, V> Clone for CowMap<K, V> {
+    fn clone(&self) -> Self {
+        CowMap {
+            shards: self.shards.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K, V> Default for CowMap<K, V> {
+    fn default() -> Self {
+        CowMap::new()
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> PartialEq for CowMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for CowMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for CowMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = CowMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl<K: Serialize, V: Serialize> Serialize for CowMap<K, V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.len))?;
+        for (key, value) in self.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Iterator over the entries of a `CowMap`
+pub struct Iter<'a, K, V> {
+    shards: slice::Iter<'a, Arc<HashMap<K, V>>>,
+    entries: Option<hash_map::Iter<'a, K, V>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.as_mut().and_then(|entries| entries.next()) {
+                return Some(entry);
+            }
+            self.entries = Some(self.shards.next()?.iter());
+        }
+    }
+}
+
+/// Iterator over the values of a `CowMap`
+pub struct Values<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, value)| value)
+    }
+}
+
+/// Iterator taking the entries of a `CowMap`, copying those of shards still
+/// shared with another clone
+pub struct IntoIter<K, V> {
+    shards: vec::IntoIter<Arc<HashMap<K, V>>>,
+    entries: Option<hash_map::IntoIter<K, V>>,
+    len: usize,
+}
+
+impl<K: Clone, V: Clone> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.as_mut().and_then(|entries| entries.next()) {
+                self.len -= 1;
+                return Some(entry);
+            }
+            let shard = self.shards.next()?;
+            let shard = Arc::try_unwrap(shard).unwrap_or_else(|shared| (*shared).clone());
+            self.entries = Some(shard.into_iter());
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K: Clone, V: Clone> IntoIterator for CowMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter {
+            shards: self.shards.into_iter(),
+            entries: None,
+            len: self.len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_copy_changed_shards() {
+        let mut map: CowMap<u32, String> = (0..1000).map(|n| (n, n.to_string())).collect();
+        assert_eq!(map.len(), 1000);
+        let snapshot = map.clone();
+
+        map.get_mut(&7).unwrap().push('!');
+        map.remove(&8);
+        map.insert(1000, String::from("1000"));
+        *map.get_or_default(1001) += "new";
+        assert!(map.get_mut(&5000).is_none());
+        assert!(map.remove(&5000).is_none());
+
+        // the snapshot is unchanged, and still shares the untouched shards
+        assert_eq!(snapshot.get(&7).map(String::as_str), Some("7"));
+        assert!(snapshot.contains_key(&8));
+        assert_eq!((snapshot.len(), map.len()), (1000, 1001));
+        let shared = map
+            .shards
+            .iter()
+            .zip(&snapshot.shards)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count();
+        assert!(shared >= SHARDS - 4);
+        let mut changed: Vec<u32> = map.keys_not_shared(&snapshot).copied().collect();
+        changed.sort_unstable();
+        changed.dedup();
+        for key in [7, 8, 1000, 1001].iter() {
+            assert!(changed.contains(key));
+        }
+        assert!(changed.len() < 1000);
+
+        assert_eq!(map.get(&7).map(String::as_str), Some("7!"));
+        assert_eq!(map.iter().count(), 1001);
+        let mut keys: Vec<u32> = map.into_iter().map(|(key, _)| key).collect();
+        keys.sort_unstable();
+        assert_eq!(keys.len(), 1001);
+        assert_eq!(keys[8], 9);
+        assert_eq!(snapshot.values().count(), 1000);
+    }
+}
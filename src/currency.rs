@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use rocket::config::Value;
+use rocket::Config;
+
+use crate::parts_list::PartsListError;
+
+/// Currency costs are given in when the `currency` setting isn't configured
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// Exchange rates converting costs given in other currencies into the base
+/// currency of the server
+///
+/// Each rate is the value of one unit of a currency in the base currency, so a
+/// rate of `1.08` for `EUR` converts 10 EUR into 10.80 of the base currency.
+/// Costs without a currency are in the base currency.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExchangeRates {
+    base: String,
+    rates: BTreeMap<String, f64>,
+}
+
+impl Default for ExchangeRates {
+    fn default() -> Self {
+        ExchangeRates::new(DEFAULT_CURRENCY)
+    }
+}
+
+impl ExchangeRates {
+    /// Rates with only the base currency known
+    pub fn new(base: &str) -> ExchangeRates {
+        ExchangeRates {
+            base: base.into(),
+            rates: BTreeMap::new(),
+        }
+    }
+
+    /// Read the base currency from the `currency` setting of the Rocket
+    /// configuration, `USD` by default, and the rates of other currencies from
+    /// the `exchange_rates` table, such as `exchange_rates = { EUR = 1.08 }`
+    pub fn from_config(config: &Config) -> Result<ExchangeRates, PartsListError> {
+        let base = config.get_str("currency").unwrap_or(DEFAULT_CURRENCY);
+        check_code(base).map_err(|e| PartsListError::Config {
+            s: format!("currency: {}", e),
+        })?;
+        let mut rates = ExchangeRates::new(base);
+        if let Ok(table) = config.get_table("exchange_rates") {
+            for (currency, rate) in table {
+                let rate = match rate {
+                    Value::Float(rate) => *rate,
+                    Value::Integer(rate) => *rate as f64,
+                    _ => f64::NAN,
+                };
+                rates
+                    .with_rate(currency, rate)
+                    .map_err(|e| PartsListError::Config {
+                        s: format!("exchange_rates: {}", e),
+                    })?;
+            }
+        }
+        Ok(rates)
+    }
+
+    /// Add the value of one unit of `currency` in the base currency
+    pub fn with_rate(&mut self, currency: &str, rate: f64) -> Result<(), PartsListError> {
+        check_code(currency)?;
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(PartsListError::InvalidExchangeRate {
+                currency: currency.into(),
+            });
+        }
+        self.rates.insert(currency.into(), rate);
+        Ok(())
+    }
+
+    /// Currency costs without a currency are given in
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Value of one unit of `currency` in the base currency
+    fn rate(&self, currency: &str) -> Result<f64, PartsListError> {
+        if currency == self.base {
+            return Ok(1.0);
+        }
+        self.rates
+            .get(currency)
+            .copied()
+            .ok_or_else(|| PartsListError::UnknownCurrency {
+                currency: currency.into(),
+            })
+    }
+
+    /// Convert `amount` from `from`, the base currency when not given, into
+    /// `to`, failing when either has no rate
+    pub fn convert(
+        &self,
+        amount: f64,
+        from: Option<&str>,
+        to: &str,
+    ) -> Result<f64, PartsListError> {
+        let from = from.unwrap_or(&self.base);
+        if from == to {
+            return Ok(amount);
+        }
+        Ok(amount * self.rate(from)? / self.rate(to)?)
+    }
+}
+
+/// Check that a currency is given as a three letter ISO 4217 code, such as
+/// `USD`
+pub fn check_code(currency: &str) -> Result<(), PartsListError> {
+    if currency.len() == 3 && currency.bytes().all(|b| b.is_ascii_uppercase()) {
+        Ok(())
+    } else {
+        Err(PartsListError::InvalidCurrency {
+            currency: currency.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_through_base() {
+        let config = Config::build(rocket::config::Environment::Development)
+            .extra("currency", "EUR")
+            .extra(
+                "exchange_rates",
+                vec![("USD", 0.9), ("GBP", 1.2)]
+                    .into_iter()
+                    .map(|(currency, rate)| (currency.to_string(), Value::Float(rate)))
+                    .collect::<BTreeMap<String, Value>>(),
+            )
+            .unwrap();
+        let rates = ExchangeRates::from_config(&config).unwrap();
+        assert_eq!(rates.base(), "EUR");
+        assert_eq!(rates.convert(10.0, None, "EUR").unwrap(), 10.0);
+        assert_eq!(rates.convert(10.0, Some("USD"), "EUR").unwrap(), 9.0);
+        assert_eq!(
+            format!("{:.2}", rates.convert(9.0, Some("USD"), "GBP").unwrap()),
+            "6.75"
+        );
+        assert_matches!(
+            rates.convert(1.0, Some("JPY"), "EUR"),
+            Err(PartsListError::UnknownCurrency { ref currency }) if currency == "JPY"
+        );
+        assert_matches!(
+            rates.convert(1.0, None, "JPY"),
+            Err(PartsListError::UnknownCurrency { .. })
+        );
+
+        assert!(check_code("usd").is_err());
+        let config = Config::build(rocket::config::Environment::Development)
+            .extra(
+                "exchange_rates",
+                vec![("GBP".to_string(), Value::Integer(0))]
+                    .into_iter()
+                    .collect::<BTreeMap<String, Value>>(),
+            )
+            .unwrap();
+        assert_matches!(
+            ExchangeRates::from_config(&config),
+            Err(PartsListError::Config { .. })
+        );
+    }
+}
@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use crate::parts_list::{Part, PartsList, PartsListError, PartsListFilter, PartsListUpdate};
+use crate::sidecar;
+use crate::store::PartsStore;
+
+/// Stage of review an engineering change order is in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EcoStatus {
+    Proposed,
+    Approved,
+    Rejected,
+    Applied,
+}
+
+impl TryFrom<&str> for EcoStatus {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "proposed" => Ok(EcoStatus::Proposed),
+            "approved" => Ok(EcoStatus::Approved),
+            "rejected" => Ok(EcoStatus::Rejected),
+            "applied" => Ok(EcoStatus::Applied),
+            _ => Err(PartsListError::InvalidEcoStatusString { s: s.into() }),
+        }
+    }
+}
+
+impl From<EcoStatus> for &str {
+    fn from(s: EcoStatus) -> &'static str {
+        match s {
+            EcoStatus::Proposed => "proposed",
+            EcoStatus::Approved => "approved",
+            EcoStatus::Rejected => "rejected",
+            EcoStatus::Applied => "applied",
+        }
+    }
+}
+
+/// Update to the children of one part staged by an engineering change order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EcoChange {
+    pub part: Uuid,
+    /// Either `add`, `remove` or `replace`, as when updating children
+    #[serde(default = "default_action")]
+    pub action: String,
+    pub children: Vec<Uuid>,
+    /// Version of the part when the change was proposed, set by the server
+    #[serde(default)]
+    pub base_version: u64,
+}
+
+fn default_action() -> String {
+    Into::<&str>::into(PartsListUpdate::Add).into()
+}
+
+/// Engineering change order, a set of changes to the parts of an assembly
+/// which are reviewed together and then applied at once
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Eco {
+    pub id: Uuid,
+    /// Assembly whose parts are changed, which must include every changed part
+    pub assembly: Uuid,
+    pub description: String,
+    pub changes: Vec<EcoChange>,
+    pub status: EcoStatus,
+    /// Times in milliseconds since the UNIX epoch at which the ECO was
+    /// proposed, approved, rejected and applied
+    pub created: u64,
+    pub approved: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<u64>,
+    pub applied: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Engineering change orders proposed to the server, held in memory and, like
+/// categories, saved to a sidecar file after every change when the parts are
+/// stored outside of memory
+///
+/// Clones share the ECOs, so they can be applied on the writer thread.
+#[derive(Clone)]
+pub struct EcoRegistry {
+    ecos: Arc<Mutex<HashMap<Uuid, Eco>>>,
+    path: Option<PathBuf>,
+}
+
+/// Locked access to the ECOs of a registry, along with the file they are
+/// saved to if any
+pub struct Ecos<'a>(MutexGuard<'a, HashMap<Uuid, Eco>>, Option<&'a Path>);
+
+impl Default for EcoRegistry {
+    fn default() -> Self {
+        EcoRegistry::new()
+    }
+}
+
+impl EcoRegistry {
+    pub fn new() -> EcoRegistry {
+        EcoRegistry {
+            ecos: Arc::new(Mutex::new(HashMap::new())),
+            path: None,
+        }
+    }
+
+    /// Registry saving its ECOs to the file at `path`, starting with those
+    /// saved there before
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<EcoRegistry, PartsListError> {
+        let path = path.as_ref();
+        let ecos: Vec<Eco> = sidecar::load(path)?;
+        Ok(EcoRegistry {
+            ecos: Arc::new(Mutex::new(
+                ecos.into_iter().map(|eco| (eco.id, eco)).collect(),
+            )),
+            path: Some(path.into()),
+        })
+    }
+
+    /// Lock the ECOs, failing only if a previous holder of the lock panicked
+    pub fn lock(&self) -> Option<Ecos<'_>> {
+        let path = self.path.as_deref();
+        self.ecos.lock().ok().map(|ecos| Ecos(ecos, path))
+    }
+}
+
+impl<'a> Ecos<'a> {
+    /// Propose changes to `assembly`, recording the version of each changed
+    /// part so changes made to them before the ECO is applied are detected
+    pub fn create(
+        &mut self,
+        parts: &dyn PartsStore,
+        assembly: Uuid,
+        description: &str,
+        mut changes: Vec<EcoChange>,
+    ) -> Result<Eco, PartsListError> {
+        parts.get(&assembly)?;
+        for change in &mut changes {
+            PartsListUpdate::try_from(change.action.as_str())?;
+            change.base_version = parts.get(&change.part)?.version;
+        }
+        let eco = Eco {
+            id: Uuid::new_v4(),
+            assembly,
+            description: description.into(),
+            changes,
+            status: EcoStatus::Proposed,
+            created: now(),
+            approved: None,
+            rejected: None,
+            applied: None,
+        };
+        self.save(|ecos| {
+            ecos.insert(eco.id, eco.clone());
+        })?;
+        Ok(eco)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<Eco, PartsListError> {
+        self.0
+            .get(id)
+            .cloned()
+            .ok_or(PartsListError::EcoDoesNotExist { id: *id })
+    }
+
+    /// List ECOs, optionally only those against an assembly or in a status,
+    /// oldest first
+    pub fn list(&self, assembly: Option<Uuid>, status: Option<EcoStatus>) -> Vec<Eco> {
+        let mut ecos: Vec<Eco> = self
+            .0
+            .values()
+            .filter(|eco| assembly.is_none_or(|assembly| eco.assembly == assembly))
+            .filter(|eco| status.is_none_or(|status| eco.status == status))
+            .cloned()
+            .collect();
+        ecos.sort_by_key(|eco| (eco.created, eco.id));
+        ecos
+    }
+
+    /// Replace every ECO with those of a backup
+    pub fn replace_all(&mut self, ecos: Vec<Eco>) -> Result<(), PartsListError> {
+        self.save(|replaced| {
+            *replaced = ecos.into_iter().map(|eco| (eco.id, eco)).collect();
+        })
+    }
+
+    /// Get an ECO, failing unless it is in `status`
+    fn get_in(&self, id: &Uuid, status: EcoStatus, action: &str) -> Result<Eco, PartsListError> {
+        let eco = self.get(id)?;
+        if eco.status != status {
+            return Err(PartsListError::EcoStatusMismatch {
+                id: *id,
+                status: Into::<&str>::into(eco.status).into(),
+                action: action.into(),
+            });
+        }
+        Ok(eco)
+    }
+
+    pub fn approve(&mut self, id: &Uuid) -> Result<Eco, PartsListError> {
+        let mut eco = self.get_in(id, EcoStatus::Proposed, "approved")?;
+        eco.status = EcoStatus::Approved;
+        eco.approved = Some(now());
+        self.save(|ecos| {
+            ecos.insert(eco.id, eco.clone());
+        })?;
+        Ok(eco)
+    }
+
+    /// Turn down a proposed ECO, which can then no longer be approved or
+    /// applied
+    pub fn reject(&mut self, id: &Uuid) -> Result<Eco, PartsListError> {
+        let mut eco = self.get_in(id, EcoStatus::Proposed, "rejected")?;
+        eco.status = EcoStatus::Rejected;
+        eco.rejected = Some(now());
+        self.save(|ecos| {
+            ecos.insert(eco.id, eco.clone());
+        })?;
+        Ok(eco)
+    }
+
+    /// Apply the changes of an approved ECO to `parts`, returning the ECO and
+    /// the changed parts
+    ///
+    /// Every change is first tried against a copy of the parts, so nothing is
+    /// modified unless all of them succeed. Changed parts must still be at the
+    /// version they were at when the ECO was proposed, and belong to the
+    /// assembly once the changes before them are made.
+    pub fn apply(
+        &mut self,
+        id: &Uuid,
+        parts: &mut dyn PartsStore,
+    ) -> Result<(Eco, Vec<Part>), PartsListError> {
+        let mut eco = self.get_in(id, EcoStatus::Approved, "applied")?;
+        for change in &eco.changes {
+            parts.expect_version(&change.part, change.base_version)?;
+        }
+        let mut staged = parts.snapshot()?;
+        for change in &eco.changes {
+            stage(&mut staged, eco.assembly, change)?;
+        }
+
+        let mut changed: Vec<Uuid> = Vec::new();
+        for change in &eco.changes {
+            let action = PartsListUpdate::try_from(change.action.as_str())?;
+            let children: Vec<&Uuid> = change.children.iter().collect();
+            parts.update(&change.part, &children, action)?;
+            changed.push(change.part);
+            changed.extend(&change.children);
+        }
+        changed.sort();
+        changed.dedup();
+        let (changed, _) = parts.get_many(&changed)?;
+
+        // the parts are already changed, so an ECO which can't be saved as
+        // applied fails to apply again on the versions of its parts
+        eco.status = EcoStatus::Applied;
+        eco.applied = Some(now());
+        self.save(|ecos| {
+            ecos.insert(eco.id, eco.clone());
+        })?;
+        Ok((eco, changed))
+    }
+
+    /// Make a change to a copy of the ECOs and save it before it replaces them,
+    /// so a change which can't be saved isn't made
+    fn save<F>(&mut self, change: F) -> Result<(), PartsListError>
+    where
+        F: FnOnce(&mut HashMap<Uuid, Eco>),
+    {
+        let mut ecos = self.0.clone();
+        change(&mut ecos);
+        if let Some(path) = self.1 {
+            let mut saved: Vec<&Eco> = ecos.values().collect();
+            saved.sort_by_key(|eco| (eco.created, eco.id));
+            sidecar::save(path, &saved)?;
+        }
+        *self.0 = ecos;
+        Ok(())
+    }
+}
+
+/// Make a change to the staged copy of the parts, checking that it stays
+/// within the assembly
+fn stage(staged: &mut PartsList, assembly: Uuid, change: &EcoChange) -> Result<(), PartsListError> {
+    if change.part != assembly
+        && !staged
+            .get_children(&assembly, PartsListFilter::All)?
+            .iter()
+            .any(|part| part.id == change.part)
+    {
+        return Err(PartsListError::PartOutsideAssembly {
+            id: change.part,
+            assembly,
+        });
+    }
+    let action = PartsListUpdate::try_from(change.action.as_str())?;
+    if let PartsListUpdate::Remove = action {
+        let part = staged.get(&change.part)?;
+        if let Some(child) = change
+            .children
+            .iter()
+            .find(|child| !part.children.contains(child))
+        {
+            return Err(PartsListError::NotAChild {
+                parent: change.part,
+                child: *child,
+            });
+        }
+    }
+    let children: Vec<&Uuid> = change.children.iter().collect();
+    staged.update(&change.part, &children, action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(part: Uuid, action: PartsListUpdate, children: &[Uuid]) -> EcoChange {
+        EcoChange {
+            part,
+            action: Into::<&str>::into(action).into(),
+            children: children.to_vec(),
+            base_version: 0,
+        }
+    }
+
+    #[test]
+    fn eco_lifecycle() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        let bolt = parts.add(Part::new("bolt")).unwrap().id;
+        parts
+            .update(&assy, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+
+        let registry = EcoRegistry::new();
+        let mut ecos = registry.lock().unwrap();
+        let eco = ecos
+            .create(
+                &parts,
+                assy,
+                "swap screw for bolt",
+                vec![change(assy, PartsListUpdate::Replace, &[bolt])],
+            )
+            .unwrap();
+        assert_eq!(eco.changes[0].base_version, 2);
+        assert_matches!(
+            ecos.apply(&eco.id, &mut parts),
+            Err(PartsListError::EcoStatusMismatch { .. })
+        );
+        ecos.approve(&eco.id).unwrap();
+        let (applied, changed) = ecos.apply(&eco.id, &mut parts).unwrap();
+        assert_eq!(applied.status, EcoStatus::Applied);
+        assert_eq!(changed.len(), 2);
+        assert_eq!(
+            parts.get(&assy).unwrap().children,
+            [bolt].iter().copied().collect()
+        );
+        assert_eq!(
+            ecos.list(Some(assy), Some(EcoStatus::Applied)),
+            vec![applied]
+        );
+
+        // a failing change leaves every part untouched
+        let nut = parts.add(Part::new("nut")).unwrap().id;
+        let eco = ecos
+            .create(
+                &parts,
+                assy,
+                "add nut, drop screw",
+                vec![
+                    change(assy, PartsListUpdate::Add, &[nut]),
+                    change(assy, PartsListUpdate::Remove, &[screw]),
+                ],
+            )
+            .unwrap();
+        ecos.approve(&eco.id).unwrap();
+        assert_matches!(
+            ecos.apply(&eco.id, &mut parts),
+            Err(PartsListError::NotAChild { .. })
+        );
+        assert!(parts.get(&nut).unwrap().parents.is_empty());
+
+        // parts changed since the ECO was proposed are rejected
+        let eco = ecos
+            .create(
+                &parts,
+                assy,
+                "add nut",
+                vec![change(assy, PartsListUpdate::Add, &[nut])],
+            )
+            .unwrap();
+        ecos.approve(&eco.id).unwrap();
+        parts
+            .update(&assy, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+        assert_matches!(
+            ecos.apply(&eco.id, &mut parts),
+            Err(PartsListError::VersionConflict { .. })
+        );
+
+        // as are parts outside the assembly
+        let eco = ecos
+            .create(
+                &parts,
+                assy,
+                "add to nut",
+                vec![change(nut, PartsListUpdate::Add, &[bolt])],
+            )
+            .unwrap();
+        ecos.approve(&eco.id).unwrap();
+        assert_matches!(
+            ecos.apply(&eco.id, &mut parts),
+            Err(PartsListError::PartOutsideAssembly { .. })
+        );
+
+        // restoring a backup replaces every ECO
+        let backup = ecos.list(None, None);
+        ecos.replace_all(backup[..1].to_vec()).unwrap();
+        assert_eq!(ecos.list(None, None), backup[..1].to_vec());
+        ecos.replace_all(backup.clone()).unwrap();
+        assert_eq!(ecos.list(None, None), backup);
+    }
+
+    #[test]
+    fn status_transitions() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        let registry = EcoRegistry::new();
+        let mut ecos = registry.lock().unwrap();
+        let propose = |ecos: &mut Ecos, parts: &PartsList| {
+            ecos.create(
+                parts,
+                assy,
+                "add screw",
+                vec![change(assy, PartsListUpdate::Add, &[screw])],
+            )
+            .unwrap()
+            .id
+        };
+        let refused = |result: Result<Eco, PartsListError>, from: &str, to: &str| match result {
+            Err(PartsListError::EcoStatusMismatch { status, action, .. }) => {
+                assert_eq!((status.as_str(), action.as_str()), (from, to))
+            }
+            other => panic!("{} ECO was {}: {:?}", from, to, other),
+        };
+
+        // proposed ECOs can be approved or rejected, but not applied
+        let proposed = propose(&mut ecos, &parts);
+        refused(
+            ecos.apply(&proposed, &mut parts).map(|(eco, _)| eco),
+            "proposed",
+            "applied",
+        );
+
+        // rejected ECOs can't move on
+        let rejected = propose(&mut ecos, &parts);
+        let eco = ecos.reject(&rejected).unwrap();
+        assert_eq!(eco.status, EcoStatus::Rejected);
+        assert!(eco.rejected.is_some() && eco.approved.is_none());
+        refused(ecos.approve(&rejected), "rejected", "approved");
+        refused(ecos.reject(&rejected), "rejected", "rejected");
+        refused(
+            ecos.apply(&rejected, &mut parts).map(|(eco, _)| eco),
+            "rejected",
+            "applied",
+        );
+
+        // approved ECOs can only be applied
+        let eco = ecos.approve(&proposed).unwrap();
+        assert_eq!(eco.status, EcoStatus::Approved);
+        assert!(eco.approved.is_some() && eco.rejected.is_none());
+        refused(ecos.approve(&proposed), "approved", "approved");
+        refused(ecos.reject(&proposed), "approved", "rejected");
+
+        // and applied ECOs are done with
+        let (eco, _) = ecos.apply(&proposed, &mut parts).unwrap();
+        assert_eq!(eco.status, EcoStatus::Applied);
+        assert!(eco.applied.is_some());
+        refused(ecos.approve(&proposed), "applied", "approved");
+        refused(ecos.reject(&proposed), "applied", "rejected");
+        refused(
+            ecos.apply(&proposed, &mut parts).map(|(eco, _)| eco),
+            "applied",
+            "applied",
+        );
+
+        let missing = Uuid::new_v4();
+        assert_matches!(
+            ecos.reject(&missing),
+            Err(PartsListError::EcoDoesNotExist { id }) if id == missing
+        );
+        assert_eq!(ecos.list(None, Some(EcoStatus::Rejected))[0].id, rejected);
+    }
+
+    #[test]
+    fn saved_to_file() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        let dir = std::env::temp_dir().join(format!("bom-ecos-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bom.ecos.json");
+
+        let registry = EcoRegistry::open(&path).unwrap();
+        let mut ecos = registry.lock().unwrap();
+        let changes = vec![change(assy, PartsListUpdate::Add, &[screw])];
+        let applied = ecos
+            .create(&parts, assy, "add screw", changes.clone())
+            .unwrap();
+        ecos.approve(&applied.id).unwrap();
+        ecos.apply(&applied.id, &mut parts).unwrap();
+        let rejected = ecos.create(&parts, assy, "add screw", changes).unwrap();
+        ecos.reject(&rejected.id).unwrap();
+        let saved = ecos.list(None, None);
+        drop(ecos);
+
+        let reopened = EcoRegistry::open(&path).unwrap();
+        assert_eq!(reopened.lock().unwrap().list(None, None), saved);
+
+        // a change which can't be saved isn't made
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut ecos = reopened.lock().unwrap();
+        assert_matches!(
+            ecos.create(&parts, assy, "again", Vec::new()),
+            Err(PartsListError::Storage { .. })
+        );
+        assert_eq!(ecos.list(None, None), saved);
+    }
+}
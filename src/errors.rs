@@ -1,22 +1,62 @@
+use std::fmt;
+
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use uuid::Uuid;
 
-#[derive(Serialize_repr, Deserialize_repr, PartialEq, Debug)]
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Debug)]
 #[repr(u32)]
 pub enum PartsErrorCode {
     LockError = 1,
     MissingPartError = 2,
     CreatePartError = 3,
     RequestError = 4,
+    StorageError = 5,
+    VersionConflict = 6,
+    PartInUse = 7,
+    InternalError = 8,
+    Busy = 9,
+    QuotaExceeded = 10,
+    ChangesExpired = 11,
+    Forbidden = 12,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PartsError {
     code: PartsErrorCode,
     description: String,
+    /// Parts which a refused change would have linked into a cycle, each a
+    /// parent of the next
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cycle: Option<Vec<Uuid>>,
 }
 
 impl PartsError {
     pub fn new(code: PartsErrorCode, description: String) -> PartsError {
-        PartsError { code, description }
+        PartsError {
+            code,
+            description,
+            cycle: None,
+        }
+    }
+
+    pub fn with_cycle(mut self, cycle: Vec<Uuid>) -> PartsError {
+        self.cycle = Some(cycle);
+        self
+    }
+
+    pub fn code(&self) -> &PartsErrorCode {
+        &self.code
+    }
+
+    pub fn cycle(&self) -> Option<&[Uuid]> {
+        self.cycle.as_deref()
     }
 }
+
+impl fmt::Display for PartsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code: {:?})", self.description, self.code)
+    }
+}
+
+impl std::error::Error for PartsError {}
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::parts_list::{Part, PartsListError, PartsListUpdate};
+use crate::store::PartsStore;
+
+/// Kinds of assemblies generated, each named after one of these
+const ASSEMBLIES: &[&str] = &[
+    "Frame",
+    "Gearbox",
+    "Housing",
+    "Controller board",
+    "Sensor module",
+    "Drive unit",
+    "Wiring harness",
+    "Front panel",
+];
+
+/// Kinds of components generated, each named after one of these
+const COMPONENTS: &[&str] = &[
+    "Bracket",
+    "Resistor 10k",
+    "Capacitor 100n",
+    "Screw M3x8",
+    "Nut M3",
+    "Washer M3",
+    "Spring",
+    "Bearing 608",
+    "Gasket",
+    "Connector 4-pin",
+    "LED red",
+    "Shaft",
+];
+
+/// Chance of a child of an assembly being a subassembly, when it isn't at the
+/// deepest level
+const SUBASSEMBLY_CHANCE: f64 = 0.5;
+
+/// Chance of a component being reused wherever components are needed, like
+/// common fasteners, and of a component needed reusing one of those
+const SHARED_CHANCE: f64 = 0.25;
+
+/// Shape of a generated BOM
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BomShape {
+    /// Number of parts generated
+    pub parts: usize,
+    /// Most levels below the top of each assembly
+    pub depth: usize,
+    /// Most children of each assembly
+    pub fanout: usize,
+}
+
+/// Generate random assemblies of the given shape, with named parts holding
+/// stock in bin locations and common components shared between assemblies
+///
+/// Top level assemblies are added until there are `shape.parts` parts, each
+/// filled breadth first with between one and `shape.fanout` children per
+/// assembly. Parts are named after their kind, a tag shared by the parts of
+/// the batch and their number within it, so batches don't clash.
+pub fn generate<R: Rng>(shape: &BomShape, rng: &mut R) -> Vec<Part> {
+    let batch = format!("{:04X}", rng.gen::<u16>());
+    let mut parts: Vec<Part> = Vec::with_capacity(shape.parts);
+    let mut shared: Vec<usize> = Vec::new();
+
+    let new_part = |parts: &mut Vec<Part>, rng: &mut R, assembly: bool| -> usize {
+        let kinds = if assembly { ASSEMBLIES } else { COMPONENTS };
+        let kind = kinds.choose(rng).copied().unwrap_or("Part");
+        let mut part = Part::new(&format!("{} {}-{:04}", kind, batch, parts.len() + 1));
+        if assembly {
+            part.on_hand = rng.gen_range(0..5);
+        } else {
+            part.on_hand = rng.gen_range(0..500);
+            part.location = format!(
+                "Bin {}-{:02}",
+                (b'A' + rng.gen_range(0..8)) as char,
+                rng.gen_range(1..=40)
+            );
+        }
+        parts.push(part);
+        parts.len() - 1
+    };
+
+    while parts.len() < shape.parts {
+        let root = new_part(&mut parts, rng, true);
+        let mut unfilled = VecDeque::from(vec![(root, 0)]);
+        while let Some((parent, level)) = unfilled.pop_front() {
+            if level >= shape.depth {
+                continue;
+            }
+            for _ in 0..rng.gen_range(1..=shape.fanout.max(1)) {
+                let reuse = shared
+                    .choose(rng)
+                    .copied()
+                    .filter(|_| rng.gen_bool(SHARED_CHANCE))
+                    .filter(|child| !parts[parent].children.contains(&parts[*child].id));
+                let child = match reuse {
+                    Some(child) => child,
+                    None if parts.len() < shape.parts => {
+                        let assembly = level + 1 < shape.depth && rng.gen_bool(SUBASSEMBLY_CHANCE);
+                        let child = new_part(&mut parts, rng, assembly);
+                        if assembly {
+                            unfilled.push_back((child, level + 1));
+                        } else if rng.gen_bool(SHARED_CHANCE) {
+                            shared.push(child);
+                        }
+                        child
+                    }
+                    None => break,
+                };
+                let (parent_id, child_id) = (parts[parent].id, parts[child].id);
+                parts[parent].children.insert(child_id);
+                parts[child].parents.insert(parent_id);
+            }
+        }
+    }
+    parts
+}
+
+/// Add generated parts to a store alongside the parts it already holds,
+/// returning the ids of the top level assemblies
+///
+/// Parts are added without relationships before being linked to their
+/// children, as when restoring a backup.
+pub fn add_to(store: &mut dyn PartsStore, parts: Vec<Part>) -> Result<Vec<Uuid>, PartsListError> {
+    let mut roots = Vec::new();
+    let mut links = Vec::new();
+    for mut part in parts {
+        if part.parents.is_empty() {
+            roots.push(part.id);
+        }
+        let children: Vec<Uuid> = part.children.drain().collect();
+        part.parents.clear();
+        if !children.is_empty() {
+            links.push((part.id, children));
+        }
+        store.insert(part)?;
+    }
+    for (id, children) in links {
+        let children: Vec<&Uuid> = children.iter().collect();
+        store.update(&id, &children, PartsListUpdate::Add)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{PartsList, ValidationReport};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn generated_shape() {
+        let shape = BomShape {
+            parts: 200,
+            depth: 3,
+            fanout: 4,
+        };
+        let parts = generate(&shape, &mut StdRng::seed_from_u64(7));
+        assert_eq!(parts.len(), 200);
+        assert!(ValidationReport::check(&parts).is_valid());
+        assert!(parts.iter().all(|part| part.children.len() <= 4));
+        let names: Vec<&str> = parts.iter().map(|part| part.name.as_str()).collect();
+        let again = generate(&shape, &mut StdRng::seed_from_u64(7));
+        assert_eq!(
+            names,
+            again
+                .iter()
+                .map(|part| part.name.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        let mut store = PartsList::new();
+        let roots = add_to(&mut store, parts).unwrap();
+        assert!(!roots.is_empty());
+        let stats = store.stats();
+        assert_eq!(stats.total, 200);
+        assert!(stats.max_depth <= 3);
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::Mutex;
+
+use rocket::request::{self, FromRequest, Request};
+
+use crate::errors::PartsErrorCode;
+use crate::response::Response;
+
+/// Number of idempotency keys remembered when not configured
+pub const DEFAULT_IDEMPOTENCY_KEYS: usize = 10_000;
+
+/// Key sent by a client in the `Idempotency-Key` header so a retried request is
+/// only applied once, along with the method and uri it was sent to
+pub struct IdempotencyKey {
+    key: Option<String>,
+    request: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IdempotencyKey {
+    type Error = Infallible;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(IdempotencyKey {
+            key: request
+                .headers()
+                .get_one("Idempotency-Key")
+                .map(String::from),
+            request: format!("{} {}", request.method(), request.uri()),
+        })
+    }
+}
+
+/// Reply sent for a key, along with the request it answered
+struct Reply {
+    request: String,
+    response: String,
+}
+
+/// Replies to the most recent requests sent with an idempotency key, so a
+/// retried request gets the original reply instead of being applied again
+///
+/// Once full, the oldest keys are forgotten first. The lock is only held to
+/// look up and remember replies, not while requests are handled.
+pub struct IdempotencyCache(Mutex<Replies>);
+
+struct Replies {
+    capacity: usize,
+    replies: HashMap<String, Reply>,
+    order: VecDeque<String>,
+    /// Request being handled for each key which hasn't been replied to yet
+    in_flight: HashMap<String, String>,
+}
+
+/// Marks a key as being handled until dropped, even if handling panics
+struct InFlight<'a> {
+    cache: &'a IdempotencyCache,
+    id: String,
+}
+
+impl<'a> Drop for InFlight<'a> {
+    fn drop(&mut self) {
+        if let Ok(mut replies) = self.cache.0.lock() {
+            replies.in_flight.remove(&self.id);
+        }
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(capacity: usize) -> IdempotencyCache {
+        IdempotencyCache(Mutex::new(Replies {
+            capacity,
+            replies: HashMap::new(),
+            order: VecDeque::new(),
+            in_flight: HashMap::new(),
+        }))
+    }
+
+    /// Reply to a request, either with the reply already sent for its key or
+    /// with the response made by `handle`, which is remembered for the key
+    ///
+    /// `body` identifies the content of the request, so a key reused for a
+    /// different request is rejected, and one still being handled is refused
+    /// as busy. Responses to requests failing to take the parts list lock,
+    /// refused while too many writes are waiting or by the quota of their API
+    /// key, or failing unexpectedly aren't remembered, so they can be retried.
+    pub fn reply<F: FnOnce() -> Response>(
+        &self,
+        key: &IdempotencyKey,
+        body: &str,
+        handle: F,
+    ) -> Response {
+        let id = match &key.key {
+            Some(id) => id,
+            None => return handle(),
+        };
+        let mut replies = match self.0.lock() {
+            Ok(replies) => replies,
+            Err(_) => {
+                return Response::new()
+                    .error(PartsErrorCode::LockError, "Couldn't lock idempotency keys!")
+            }
+        };
+        let request = format!("{} {}", key.request, body);
+        if let Some(reply) = replies.replies.get(id) {
+            if reply.request != request {
+                return Response::new().error(
+                    PartsErrorCode::RequestError,
+                    &format!("Idempotency key {:?} was used for a different request", id),
+                );
+            }
+            if let Ok(response) = serde_json::from_str(&reply.response) {
+                return response;
+            }
+        }
+        match replies.in_flight.get(id) {
+            Some(handled) if *handled != request => {
+                return Response::new().error(
+                    PartsErrorCode::RequestError,
+                    &format!("Idempotency key {:?} was used for a different request", id),
+                )
+            }
+            Some(_) => {
+                return Response::new().error(
+                    PartsErrorCode::Busy,
+                    &format!(
+                        "Request with idempotency key {:?} is still being handled",
+                        id
+                    ),
+                )
+            }
+            None => {}
+        }
+        replies.in_flight.insert(id.clone(), request.clone());
+        let _in_flight = InFlight {
+            cache: self,
+            id: id.clone(),
+        };
+        drop(replies);
+
+        let response = handle();
+        let retryable = matches!(
+            response.error.as_ref().map(|e| e.code()),
+            Some(PartsErrorCode::LockError)
+                | Some(PartsErrorCode::QuotaExceeded)
+                | Some(PartsErrorCode::Busy)
+                | Some(PartsErrorCode::InternalError)
+        );
+        if let (false, Ok(serialized)) = (retryable, serde_json::to_string(&response)) {
+            // the reply is remembered before the key stops being in flight
+            if let Ok(mut replies) = self.0.lock() {
+                replies.insert(
+                    id.clone(),
+                    Reply {
+                        request,
+                        response: serialized,
+                    },
+                );
+            }
+        }
+        response
+    }
+}
+
+impl Replies {
+    fn insert(&mut self, id: String, reply: Reply) {
+        if self.replies.insert(id.clone(), reply).is_none() {
+            self.order.push_back(id);
+        }
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDEMPOTENCY_KEYS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str) -> IdempotencyKey {
+        IdempotencyKey {
+            key: Some(id.into()),
+            request: "POST /v1/parts".into(),
+        }
+    }
+
+    #[test]
+    fn replays_replies() {
+        let cache = IdempotencyCache::new(2);
+        let mut calls = 0;
+        let mut send = |id: &str, body: &str| {
+            cache.reply(&key(id), body, || {
+                calls += 1;
+                Response::new().result(201, &format!("call {}", calls))
+            })
+        };
+
+        assert_eq!(send("a", "{}").result.unwrap().description, "call 1");
+        assert_eq!(send("a", "{}").result.unwrap().description, "call 1");
+        assert_matches!(
+            send("a", "{\"name\":\"other\"}").error.map(|e| e.to_string()),
+            Some(e) if e.contains("different request")
+        );
+
+        // the oldest key is forgotten once the cache is full
+        send("b", "{}");
+        send("c", "{}");
+        assert_eq!(send("a", "{}").result.unwrap().description, "call 4");
+
+        let unkeyed = IdempotencyKey {
+            key: None,
+            request: "POST /v1/parts".into(),
+        };
+        let response = cache.reply(&unkeyed, "{}", Response::new);
+        assert!(response.result.is_none());
+    }
+
+    #[test]
+    fn retries_busy_and_failed_requests() {
+        let cache = IdempotencyCache::new(2);
+        for code in &[PartsErrorCode::Busy, PartsErrorCode::InternalError] {
+            let failed = cache.reply(&key("a"), "{}", || Response::new().error(*code, "failed"));
+            assert!(failed.error.is_some());
+        }
+        let retried = cache.reply(&key("a"), "{}", || Response::new().result(201, "created"));
+        assert_eq!(retried.result.unwrap().description, "created");
+    }
+
+    #[test]
+    fn refuses_keys_still_in_flight() {
+        let cache = IdempotencyCache::new(2);
+        let code = |response: &Response| response.error.as_ref().map(|e| *e.code());
+        let response = cache.reply(&key("a"), "{}", || {
+            // the lock isn't held while handling, so the key can be looked up
+            let retry = cache.reply(&key("a"), "{}", || unreachable!());
+            assert_eq!(code(&retry), Some(PartsErrorCode::Busy));
+            let other = cache.reply(&key("a"), "{\"name\":\"other\"}", || unreachable!());
+            assert_eq!(code(&other), Some(PartsErrorCode::RequestError));
+            Response::new().result(201, "created")
+        });
+        assert_eq!(response.result.unwrap().description, "created");
+        let replayed = cache.reply(&key("a"), "{}", || unreachable!());
+        assert_eq!(replayed.result.unwrap().description, "created");
+    }
+}
@@ -0,0 +1,96 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use uuid::Uuid;
+
+use crate::parts_list::Part;
+
+/// URL schemes links and images of work instructions may use, anything else
+/// such as `javascript:` being dropped from reports
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// Markdown work instructions for building an assembly, along with the version
+/// of the part they were fetched or changed at
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Instructions {
+    pub id: Uuid,
+    pub version: u64,
+    pub markdown: String,
+}
+
+impl Instructions {
+    pub fn of(part: &Part) -> Instructions {
+        Instructions {
+            id: part.id,
+            version: part.version,
+            markdown: part.instructions.clone(),
+        }
+    }
+}
+
+/// Render markdown as HTML to embed in a page, with tables, strikethrough and
+/// task lists
+///
+/// Raw HTML is escaped and links to anything but web pages or email addresses
+/// are dropped, so instructions can't run scripts in the page they are shown
+/// in.
+pub fn to_html(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(s) => Event::Text(s),
+        Event::Start(tag) => Event::Start(safe_tag(tag)),
+        Event::End(tag) => Event::End(safe_tag(tag)),
+        event => event,
+    });
+    let mut rendered = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut rendered, events);
+    rendered
+}
+
+fn safe_tag(tag: Tag) -> Tag {
+    match tag {
+        Tag::Link(kind, url, title) if !is_safe_url(&url) => {
+            Tag::Link(kind, CowStr::Borrowed(""), title)
+        }
+        Tag::Image(kind, url, title) if !is_safe_url(&url) => {
+            Tag::Image(kind, CowStr::Borrowed(""), title)
+        }
+        tag => tag,
+    }
+}
+
+/// Whether a URL is relative or uses one of the safe schemes
+fn is_safe_url(url: &str) -> bool {
+    match url.find(&[':', '/', '?', '#'][..]) {
+        Some(end) if url[end..].starts_with(':') => SAFE_SCHEMES
+            .iter()
+            .any(|scheme| url[..end].eq_ignore_ascii_case(scheme)),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_safe_html() {
+        let html = to_html(
+            "# Torque\n\n1. Fit the **bracket**\n2. Torque to 4 Nm\n\n\
+             | Screw | Nm |\n|---|---|\n| M4 | 4 |\n\n\
+             <script>alert(1)</script>\n\n\
+             [datasheet](https://example.com/m4.pdf) [bad](javascript:alert(1)) [local](#torque)",
+        );
+        assert!(html.contains("<h1>Torque</h1>"));
+        assert!(html.contains("<li>Fit the <strong>bracket</strong></li>"));
+        assert!(html.contains("<td>M4</td>"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains(r#"<a href="https://example.com/m4.pdf">"#));
+        assert!(html.contains(r##"<a href="#torque">"##));
+        assert!(!html.contains("javascript"));
+
+        let mut part = Part::new("assy");
+        part.instructions = String::from("Fit the bracket");
+        assert_eq!(Instructions::of(&part).version, part.version);
+    }
+}
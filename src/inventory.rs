@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::currency::ExchangeRates;
+use crate::parts_list::{Part, PartsListError, PartsListFilter};
+use crate::store::PartsStore;
+use crate::supplier::{self, SupplierPolicy};
+use crate::variants::{self, Configuration};
+
+/// Component which is short of the quantity needed to build an assembly
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Shortage {
+    pub id: Uuid,
+    pub name: String,
+    /// Quantity needed for the build
+    pub required: u64,
+    pub on_hand: u64,
+    /// Quantity missing from stock, being `required` less `on_hand`
+    pub short: u64,
+}
+
+/// Line of a kitting list, giving the quantity of a component to pull from
+/// stock and where to find it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PickItem {
+    pub id: Uuid,
+    pub name: String,
+    pub quantity: u64,
+    pub location: String,
+}
+
+/// Part on the critical procurement path of an assembly
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LeadTimeStep {
+    pub id: Uuid,
+    pub name: String,
+    /// Days the part adds to the path
+    pub lead_time_days: u64,
+    /// Supplier the part is bought from, when it has any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supplier: Option<String>,
+}
+
+/// Longest chain of lead times through an assembly, which bounds how soon it
+/// could be built
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LeadTime {
+    /// Days until the assembly could be built, being the total of the lead
+    /// times along the path
+    pub days: u64,
+    /// Parts on the path, from the assembly down to the part ordered first
+    pub path: Vec<LeadTimeStep>,
+}
+
+/// Explode an assembly into the components needed to build `quantity` of it,
+/// ordered by name
+///
+/// Every link between a part and its child counts for the quantity of the
+/// child on their relationship, so a component used in several places of the
+/// assembly is needed for each of them. A part without children is its own only component.
+///
+/// Subassemblies with stock are pulled from stock rather than built, so only
+/// the quantity their `on_hand` doesn't cover is exploded into their children.
+/// Phantom subassemblies are never stocked, so they are always blown through
+/// and their children are needed as if they were children of their parents.
+///
+/// When a `config` is given, only the children each part includes for it are
+/// exploded.
+#[instrument(level = "debug", skip(parts))]
+pub fn explode(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    quantity: u64,
+    config: Option<&Configuration>,
+) -> Result<Vec<(Part, u64)>, PartsListError> {
+    let assembly = parts.get(id)?;
+    let descendants = parts.get_children(id, PartsListFilter::All)?;
+    let mut by_id: HashMap<Uuid, &Part> = descendants.iter().map(|part| (part.id, part)).collect();
+    by_id.insert(assembly.id, &assembly);
+
+    // children of each part reached in the configured assembly
+    let mut links: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    let mut unvisited = vec![*id];
+    while let Some(next) = unvisited.pop() {
+        if links.contains_key(&next) {
+            continue;
+        }
+        let children = match by_id.get(&next) {
+            Some(part) => variants::configured_children(part, config)?,
+            None => Vec::new(),
+        };
+        unvisited.extend(&children);
+        links.insert(next, children);
+    }
+
+    // a part is only exploded once the quantity needed by each of its parents
+    // within the assembly is known
+    let mut waiting: HashMap<Uuid, usize> = HashMap::new();
+    for child in links.values().flatten() {
+        *waiting.entry(*child).or_default() += 1;
+    }
+    let mut required: HashMap<Uuid, u64> = HashMap::new();
+    required.insert(*id, quantity);
+    let mut ready = vec![*id];
+    let mut exploded = Vec::new();
+    while let Some(next) = ready.pop() {
+        let part = match by_id.get(&next) {
+            Some(part) => *part,
+            None => continue,
+        };
+        let needed = required.get(&next).copied().unwrap_or(0);
+        let children = links.get(&next).map(Vec::as_slice).unwrap_or_default();
+        if part.children.is_empty() {
+            exploded.push((part.clone(), needed));
+            continue;
+        }
+        // the assembly itself is what's being built, so its stock isn't used
+        let pulled = if next == *id || part.phantom {
+            0
+        } else {
+            needed.min(part.on_hand)
+        };
+        exploded.push((part.clone(), pulled));
+        for child in children {
+            let total = required.entry(*child).or_default();
+            let per_part = part.quantity_of(child);
+            *total = total.saturating_add((needed - pulled).saturating_mul(per_part));
+            if let Some(count) = waiting.get_mut(child) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    ready.push(*child);
+                }
+            }
+        }
+    }
+    exploded.retain(|(_, quantity)| *quantity > 0);
+    exploded.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    Ok(exploded)
+}
+
+/// Find the critical procurement path of an assembly
+///
+/// A part is available its own lead time after the last of its children is,
+/// so the path follows the child which takes longest at each level, picking
+/// the first by name when several take as long. Phantom subassemblies are
+/// never built, so their own lead time is ignored. Stock isn't taken into
+/// account, giving the time to build the assembly when nothing is on hand.
+///
+/// When a `config` is given, only the children each part includes for it are
+/// followed. Parts with suppliers take the lead time of the one chosen by
+/// `policy`, comparing costs through `rates`.
+#[instrument(level = "debug", skip(parts, rates))]
+pub fn lead_time(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    config: Option<&Configuration>,
+    policy: SupplierPolicy,
+    rates: &ExchangeRates,
+) -> Result<LeadTime, PartsListError> {
+    let assembly = parts.get(id)?;
+    let descendants = parts.get_children(id, PartsListFilter::All)?;
+    let mut by_id: HashMap<Uuid, &Part> = descendants.iter().map(|part| (part.id, part)).collect();
+    by_id.insert(assembly.id, &assembly);
+
+    // days until each part is available, and the child on its critical path
+    let mut chains: HashMap<Uuid, (u64, Option<Uuid>)> = HashMap::new();
+    critical_chain(&by_id, id, config, policy, rates, &mut chains)?;
+
+    let mut path = Vec::new();
+    let mut next = Some(*id);
+    while let Some(step) = next.and_then(|id| by_id.get(&id)) {
+        path.push(LeadTimeStep {
+            id: step.id,
+            name: step.name.clone(),
+            lead_time_days: own_lead_time(step, policy, rates)?,
+            supplier: supplier::choose(step, policy, 1, rates)?
+                .filter(|_| !step.phantom)
+                .map(|supplier| supplier.name.clone()),
+        });
+        next = chains.get(&step.id).and_then(|(_, child)| *child);
+    }
+    Ok(LeadTime {
+        days: chains.get(id).map_or(0, |(days, _)| *days),
+        path,
+    })
+}
+
+fn own_lead_time(
+    part: &Part,
+    policy: SupplierPolicy,
+    rates: &ExchangeRates,
+) -> Result<u64, PartsListError> {
+    if part.phantom {
+        Ok(0)
+    } else {
+        supplier::lead_time_days(part, policy, rates)
+    }
+}
+
+/// Work out the days until part `id` is available, recording the critical
+/// chain of it and each part below it in `chains`
+fn critical_chain(
+    by_id: &HashMap<Uuid, &Part>,
+    id: &Uuid,
+    config: Option<&Configuration>,
+    policy: SupplierPolicy,
+    rates: &ExchangeRates,
+    chains: &mut HashMap<Uuid, (u64, Option<Uuid>)>,
+) -> Result<u64, PartsListError> {
+    if let Some((days, _)) = chains.get(id) {
+        return Ok(*days);
+    }
+    let part = match by_id.get(id) {
+        Some(part) => *part,
+        None => return Ok(0),
+    };
+    let mut longest: Option<(u64, &Part)> = None;
+    for child in variants::configured_children(part, config)? {
+        let days = critical_chain(by_id, &child, config, policy, rates, chains)?;
+        let child = match by_id.get(&child) {
+            Some(child) => *child,
+            None => continue,
+        };
+        let longer = longest.is_none_or(|(most, current)| {
+            days > most || (days == most && (&child.name, child.id) < (&current.name, current.id))
+        });
+        if longer {
+            longest = Some((days, child));
+        }
+    }
+    let days =
+        own_lead_time(part, policy, rates)?.saturating_add(longest.map_or(0, |(days, _)| days));
+    chains.insert(*id, (days, longest.map(|(_, child)| child.id)));
+    Ok(days)
+}
+
+/// Find the components with too little stock to build `build_qty` of an
+/// assembly, ordered by name
+pub fn shortages(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    build_qty: u64,
+    config: Option<&Configuration>,
+) -> Result<Vec<Shortage>, PartsListError> {
+    Ok(explode(parts, id, build_qty, config)?
+        .into_iter()
+        .filter(|(part, required)| part.on_hand < *required)
+        .map(|(part, required)| Shortage {
+            id: part.id,
+            short: required - part.on_hand,
+            on_hand: part.on_hand,
+            required,
+            name: part.name,
+        })
+        .collect())
+}
+
+/// List the components to pull from stock to build `quantity` of an assembly,
+/// ordered by location and then name so they can be picked in one pass
+pub fn picklist(
+    parts: &dyn PartsStore,
+    id: &Uuid,
+    quantity: u64,
+    config: Option<&Configuration>,
+) -> Result<Vec<PickItem>, PartsListError> {
+    let mut items: Vec<PickItem> = explode(parts, id, quantity, config)?
+        .into_iter()
+        .map(|(part, quantity)| PickItem {
+            id: part.id,
+            name: part.name,
+            quantity,
+            location: part.location,
+        })
+        .collect();
+    items.sort_by(|a, b| {
+        a.location
+            .cmp(&b.location)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    Ok(items)
+}
+
+/// Write a kitting list as CSV, with a header row naming the columns
+pub fn picklist_csv(items: &[PickItem]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for item in items {
+        writer.serialize(item)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{PartEdit, PartsList, PartsListUpdate, Relationship, RelationshipEdit};
+    use crate::supplier::Supplier;
+
+    #[test]
+    fn shortages_of_build() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let bracket = parts.add(Part::new("bracket")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        let label = parts.add(Part::new("label")).unwrap().id;
+        parts
+            .update(&bracket, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&assy, &[&bracket, &screw, &label], PartsListUpdate::Add)
+            .unwrap();
+        let stock = |on_hand| PartEdit {
+            on_hand: Some(on_hand),
+            ..PartEdit::default()
+        };
+        parts.edit(&screw, &stock(5)).unwrap();
+        parts.edit(&label, &stock(3)).unwrap();
+
+        // the screw is used directly and through the bracket
+        let exploded: Vec<(Uuid, u64)> = explode(&parts, &assy, 3, None)
+            .unwrap()
+            .into_iter()
+            .map(|(part, quantity)| (part.id, quantity))
+            .collect();
+        assert_eq!(exploded, vec![(label, 3), (screw, 6)]);
+
+        let short = shortages(&parts, &assy, 3, None).unwrap();
+        assert_eq!(short.len(), 1);
+        assert_eq!(short[0].id, screw);
+        assert_eq!((short[0].required, short[0].short), (6, 1));
+        assert!(shortages(&parts, &assy, 2, None).unwrap().is_empty());
+
+        // the bracket takes four screws
+        let four = RelationshipEdit {
+            quantity: Some(4),
+            notes: None,
+        };
+        parts
+            .edit_relationship(&Relationship::id_for(&bracket, &screw), &four)
+            .unwrap();
+        let short = shortages(&parts, &assy, 2, None).unwrap();
+        assert_eq!((short[0].required, short[0].short), (10, 5));
+    }
+
+    #[test]
+    fn picklist_by_location() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        let nut = parts.add(Part::new("nut")).unwrap().id;
+        parts
+            .update(&assy, &[&screw, &nut], PartsListUpdate::Add)
+            .unwrap();
+        let shelve = |location: &str| PartEdit {
+            location: Some(location.into()),
+            ..PartEdit::default()
+        };
+        parts.edit(&screw, &shelve("A1")).unwrap();
+        parts.edit(&nut, &shelve("B2")).unwrap();
+
+        let items = picklist(&parts, &assy, 4, None).unwrap();
+        let names: Vec<&str> = items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, vec!["screw", "nut"]);
+        assert_eq!(items[0].quantity, 4);
+
+        let csv = picklist_csv(&items).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("id,name,quantity,location"));
+        assert_eq!(lines.next(), Some(format!("{},screw,4,A1", screw).as_str()));
+    }
+
+    #[test]
+    fn phantoms_and_stocked_subassemblies() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let harness = parts.add(Part::new("harness")).unwrap().id;
+        let kit = parts.add(Part::new("kit")).unwrap().id;
+        let wire = parts.add(Part::new("wire")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        parts
+            .update(&harness, &[&wire], PartsListUpdate::Add)
+            .unwrap();
+        parts.update(&kit, &[&screw], PartsListUpdate::Add).unwrap();
+        parts
+            .update(&assy, &[&harness, &kit], PartsListUpdate::Add)
+            .unwrap();
+        let exploded = |parts: &PartsList| -> Vec<(Uuid, u64)> {
+            explode(parts, &assy, 3, None)
+                .unwrap()
+                .into_iter()
+                .map(|(part, quantity)| (part.id, quantity))
+                .collect()
+        };
+        assert_eq!(exploded(&parts), vec![(screw, 3), (wire, 3)]);
+
+        // stocked subassemblies are pulled, phantoms are blown through
+        let edit = |on_hand, phantom| PartEdit {
+            on_hand: Some(on_hand),
+            phantom: Some(phantom),
+            ..PartEdit::default()
+        };
+        parts.edit(&harness, &edit(2, false)).unwrap();
+        parts.edit(&kit, &edit(2, true)).unwrap();
+        assert_eq!(exploded(&parts), vec![(harness, 2), (screw, 3), (wire, 1)]);
+
+        // structure is unchanged
+        assert_eq!(parts.get(&assy).unwrap().children.len(), 2);
+    }
+
+    #[test]
+    fn lead_time_critical_path() {
+        let rates = ExchangeRates::default();
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let board = parts.add(Part::new("board")).unwrap().id;
+        let chip = parts.add(Part::new("chip")).unwrap().id;
+        let case = parts.add(Part::new("case")).unwrap().id;
+        parts
+            .update(&board, &[&chip], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&assy, &[&board, &case], PartsListUpdate::Add)
+            .unwrap();
+        let days = |lead_time_days| PartEdit {
+            lead_time_days: Some(lead_time_days),
+            ..PartEdit::default()
+        };
+        parts.edit(&assy, &days(2)).unwrap();
+        parts.edit(&board, &days(5)).unwrap();
+        parts.edit(&chip, &days(30)).unwrap();
+        parts.edit(&case, &days(20)).unwrap();
+
+        let critical = lead_time(&parts, &assy, None, SupplierPolicy::default(), &rates).unwrap();
+        assert_eq!(critical.days, 37);
+        let path: Vec<(Uuid, u64)> = critical
+            .path
+            .iter()
+            .map(|step| (step.id, step.lead_time_days))
+            .collect();
+        assert_eq!(path, vec![(assy, 2), (board, 5), (chip, 30)]);
+
+        // a phantom board takes no time of its own, leaving the case as long
+        let phantom = PartEdit {
+            phantom: Some(true),
+            ..PartEdit::default()
+        };
+        parts.edit(&board, &phantom).unwrap();
+        parts.edit(&case, &days(30)).unwrap();
+        let critical = lead_time(&parts, &assy, None, SupplierPolicy::default(), &rates).unwrap();
+        assert_eq!(critical.days, 32);
+        assert_eq!(critical.path[1].id, board);
+        assert_eq!(critical.path[1].lead_time_days, 0);
+
+        // the chip takes the lead time of the supplier chosen by the policy
+        let supplier = |name: &str, unit_cost, lead_time_days| Supplier {
+            name: name.into(),
+            unit_cost,
+            price_breaks: Vec::new(),
+            lead_time_days,
+            currency: None,
+            mpn: None,
+        };
+        let suppliers = PartEdit {
+            suppliers: Some(vec![
+                supplier("Distributor", 1.0, 40),
+                supplier("Factory", 0.5, 60),
+            ]),
+            ..PartEdit::default()
+        };
+        parts.edit(&chip, &suppliers).unwrap();
+        let critical = lead_time(&parts, &assy, None, SupplierPolicy::Preferred, &rates).unwrap();
+        assert_eq!(critical.days, 42);
+        assert_eq!(critical.path[2].supplier.as_deref(), Some("Distributor"));
+        let critical = lead_time(&parts, &assy, None, SupplierPolicy::Cheapest, &rates).unwrap();
+        assert_eq!(critical.days, 62);
+        assert_eq!(critical.path[2].supplier.as_deref(), Some("Factory"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use anyhow::{anyhow, Context};
+
+/// Components of a KiCad BOM sharing a value and footprint, which become a
+/// single part
+#[derive(Clone, Debug, PartialEq)]
+pub struct KicadGroup {
+    /// Name of the part, being the value followed by the footprint when the
+    /// component has one
+    pub name: String,
+    /// Reference designators of the components, such as `R1`
+    pub references: Vec<String>,
+    pub quantity: u32,
+}
+
+/// Name a part by its value and the footprint name, leaving out the library
+/// the footprint comes from
+fn part_name(value: &str, footprint: &str) -> String {
+    let footprint = footprint.rsplit(':').next().unwrap_or_default().trim();
+    if footprint.is_empty() {
+        value.trim().into()
+    } else {
+        format!("{} {}", value.trim(), footprint)
+    }
+}
+
+/// Group components by the name of their part, keeping the groups in order of
+/// name and the reference designators of each in order of appearance
+fn group(components: Vec<(String, String, u32)>) -> Vec<KicadGroup> {
+    let mut groups: BTreeMap<String, KicadGroup> = BTreeMap::new();
+    for (name, references, quantity) in components {
+        let group = groups.entry(name.clone()).or_insert_with(|| KicadGroup {
+            name,
+            references: Vec::new(),
+            quantity: 0,
+        });
+        group.references.extend(
+            references
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|reference| !reference.is_empty())
+                .map(String::from),
+        );
+        group.quantity += quantity;
+    }
+    groups.into_values().collect()
+}
+
+/// Find the column of a CSV header with any of the given names, ignoring case
+fn column(headers: &csv::StringRecord, names: &[&str]) -> Option<usize> {
+    headers.iter().position(|header| {
+        names
+            .iter()
+            .any(|name| header.trim().eq_ignore_ascii_case(name))
+    })
+}
+
+/// Read a grouped CSV BOM exported by KiCad, with columns for the references,
+/// value, footprint and quantity of each group of components
+///
+/// Rows marked as do not populate are left out. The quantity of a row without
+/// one is its number of references.
+pub fn read_csv<R: Read>(reader: R) -> anyhow::Result<Vec<KicadGroup>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let references = column(&headers, &["Refs", "Reference", "References", "Ref"])
+        .ok_or_else(|| anyhow!("No reference column in KiCad BOM"))?;
+    let value =
+        column(&headers, &["Value"]).ok_or_else(|| anyhow!("No value column in KiCad BOM"))?;
+    let footprint = column(&headers, &["Footprint"]);
+    let quantity = column(&headers, &["Qty", "Quantity", "Quantity Per PCB"]);
+    let dnp = column(&headers, &["DNP"]);
+
+    let mut components = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record?;
+        let field = |column: Option<usize>| column.and_then(|i| record.get(i)).unwrap_or("");
+        if !field(dnp).trim().is_empty() || field(Some(value)).trim().is_empty() {
+            continue;
+        }
+        let refs = field(Some(references));
+        let count = match field(quantity).trim() {
+            "" => refs
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|reference| !reference.is_empty())
+                .count() as u32,
+            qty => qty
+                .parse()
+                .with_context(|| format!("Invalid quantity {:?} on line {}", qty, index + 2))?,
+        };
+        components.push((
+            part_name(field(Some(value)), field(footprint)),
+            refs.into(),
+            count,
+        ));
+    }
+    Ok(group(components))
+}
+
+/// Read the components of an XML netlist exported by KiCad for BOM plugins,
+/// grouping them by value and footprint
+///
+/// Components marked as do not populate are left out.
+pub fn read_xml(text: &str) -> anyhow::Result<Vec<KicadGroup>> {
+    let document = roxmltree::Document::parse(text)?;
+    let child_text = |node: roxmltree::Node, name: &str| -> String {
+        node.children()
+            .find(|child| child.has_tag_name(name))
+            .and_then(|child| child.text())
+            .unwrap_or("")
+            .into()
+    };
+    let components = document
+        .descendants()
+        .filter(|node| node.has_tag_name("comp"))
+        .filter(|comp| {
+            !comp.children().any(|child| {
+                child.has_tag_name("property") && child.attribute("name") == Some("dnp")
+            })
+        })
+        .filter_map(|comp| {
+            let reference = comp.attribute("ref")?;
+            let value = child_text(comp, "value");
+            if value.trim().is_empty() {
+                return None;
+            }
+            Some((
+                part_name(&value, &child_text(comp, "footprint")),
+                reference.into(),
+                1,
+            ))
+        })
+        .collect();
+    Ok(group(components))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kicad_boms() {
+        let csv = "\"Refs\",\"Value\",\"Footprint\",\"Qty\",\"DNP\"\n\
+            \"R1,R2\",\"10k\",\"Resistor_SMD:R_0603\",\"2\",\"\"\n\
+            \"C1\",\"100n\",\"Capacitor_SMD:C_0402\",\"1\",\"\"\n\
+            \"R3\",\"10k\",\"Resistor_SMD:R_0603\",\"\",\"\"\n\
+            \"J1\",\"Header\",\"\",\"1\",\"DNP\"\n";
+        let groups = read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                KicadGroup {
+                    name: "100n C_0402".into(),
+                    references: vec!["C1".into()],
+                    quantity: 1,
+                },
+                KicadGroup {
+                    name: "10k R_0603".into(),
+                    references: vec!["R1".into(), "R2".into(), "R3".into()],
+                    quantity: 3,
+                },
+            ]
+        );
+
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <export version="E">
+              <components>
+                <comp ref="R1"><value>10k</value><footprint>Resistor_SMD:R_0603</footprint></comp>
+                <comp ref="R2"><value>10k</value><footprint>Resistor_SMD:R_0603</footprint></comp>
+                <comp ref="C1"><value>100n</value><footprint>Capacitor_SMD:C_0402</footprint></comp>
+                <comp ref="J1"><value>Header</value><property name="dnp"/></comp>
+              </components>
+            </export>"#;
+        let groups = read_xml(xml).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1].references, vec!["R1", "R2"]);
+        assert_eq!(groups[1].quantity, 2);
+
+        assert!(read_csv("Part,Qty\nR1,1\n".as_bytes()).is_err());
+    }
+}
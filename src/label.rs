@@ -0,0 +1,340 @@
+use std::convert::TryFrom;
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use qrcode::{Color, QrCode};
+
+use crate::parts_list::{Part, PartsListError};
+
+/// Light modules left around QR codes, as required by the standard
+const QR_QUIET_ZONE: usize = 4;
+
+/// Light modules left either side of Code 128 barcodes, as required by the
+/// standard
+const CODE128_QUIET_ZONE: usize = 10;
+
+/// Height of the bars of Code 128 barcodes in modules, tall enough for
+/// handheld scanners at the size of PNG images
+const CODE128_HEIGHT: usize = 40;
+
+/// Pixels per module of PNG images
+const PNG_SCALE: usize = 4;
+
+/// Value of the start symbol selecting code set B, which covers printable
+/// ASCII
+const CODE128_START_B: usize = 104;
+
+/// Value of the stop symbol, which also ends in the final bar
+const CODE128_STOP: usize = 106;
+
+/// Widths of the alternating bars and spaces of each Code 128 symbol, by value
+const CODE128_PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+
+/// Kind of label rendered for a part
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LabelFormat {
+    /// QR code of the id of the part
+    Qr,
+    /// Code 128 barcode of the id of the part
+    Code128,
+    /// ZPL program printing the name of the part above a barcode of its id
+    Zpl,
+}
+
+impl TryFrom<&str> for LabelFormat {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "qr" => Ok(LabelFormat::Qr),
+            "code128" => Ok(LabelFormat::Code128),
+            "zpl" => Ok(LabelFormat::Zpl),
+            _ => Err(PartsListError::InvalidLabelString { s: s.into() }),
+        }
+    }
+}
+
+impl From<LabelFormat> for &str {
+    fn from(f: LabelFormat) -> &'static str {
+        match f {
+            LabelFormat::Qr => "qr",
+            LabelFormat::Code128 => "code128",
+            LabelFormat::Zpl => "zpl",
+        }
+    }
+}
+
+/// Image format barcodes are rendered in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+impl TryFrom<&str> for ImageFormat {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "svg" => Ok(ImageFormat::Svg),
+            "png" => Ok(ImageFormat::Png),
+            _ => Err(PartsListError::InvalidLabelString { s: s.into() }),
+        }
+    }
+}
+
+impl From<ImageFormat> for &str {
+    fn from(f: ImageFormat) -> &'static str {
+        match f {
+            ImageFormat::Svg => "svg",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+/// Barcode as a grid of dark and light modules, surrounded by a quiet zone of
+/// light modules once rendered
+#[derive(Clone, Debug, PartialEq)]
+pub struct Barcode {
+    width: usize,
+    height: usize,
+    quiet_zone: usize,
+    modules: Vec<bool>,
+}
+
+impl Barcode {
+    /// QR code of `data`
+    pub fn qr(data: &str) -> Result<Barcode, PartsListError> {
+        let code = QrCode::new(data).map_err(|e| PartsListError::LabelEncoding {
+            s: format!("{}", e),
+        })?;
+        Ok(Barcode {
+            width: code.width(),
+            height: code.width(),
+            quiet_zone: QR_QUIET_ZONE,
+            modules: code
+                .to_colors()
+                .into_iter()
+                .map(|color| color == Color::Dark)
+                .collect(),
+        })
+    }
+
+    /// Code 128 barcode of `data` using code set B, so limited to printable
+    /// ASCII
+    pub fn code128(data: &str) -> Result<Barcode, PartsListError> {
+        let mut values = vec![CODE128_START_B];
+        for c in data.chars() {
+            match c {
+                ' '..='~' => values.push(c as usize - ' ' as usize),
+                _ => {
+                    return Err(PartsListError::LabelEncoding {
+                        s: format!("{:?} can't be encoded in a Code 128 barcode", c),
+                    })
+                }
+            }
+        }
+        let checksum = values
+            .iter()
+            .enumerate()
+            .map(|(position, value)| position.max(1) * value)
+            .sum::<usize>()
+            % 103;
+        values.push(checksum);
+        values.push(CODE128_STOP);
+
+        let mut row = Vec::new();
+        for value in values {
+            for (i, width) in CODE128_PATTERNS[value].bytes().enumerate() {
+                let bar = i % 2 == 0;
+                row.resize(row.len() + (width - b'0') as usize, bar);
+            }
+        }
+        Ok(Barcode {
+            width: row.len(),
+            height: CODE128_HEIGHT,
+            quiet_zone: CODE128_QUIET_ZONE,
+            modules: row.repeat(CODE128_HEIGHT),
+        })
+    }
+
+    /// Width and height in modules, including the quiet zone
+    pub fn size(&self) -> (usize, usize) {
+        (
+            self.width + 2 * self.quiet_zone,
+            self.height + 2 * self.quiet_zone,
+        )
+    }
+
+    /// Whether the module at `x` and `y` of the rendered barcode is dark
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        let (x, y) = match (
+            x.checked_sub(self.quiet_zone),
+            y.checked_sub(self.quiet_zone),
+        ) {
+            (Some(x), Some(y)) if x < self.width && y < self.height => (x, y),
+            _ => return false,
+        };
+        self.modules[y * self.width + x]
+    }
+
+    /// SVG image drawing each module as a unit square, which scales to any size
+    pub fn to_svg(&self) -> String {
+        let (width, height) = self.size();
+        let mut path = String::new();
+        for y in 0..height {
+            let mut x = 0;
+            while x < width {
+                if !self.is_dark(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < width && self.is_dark(x, y) {
+                    x += 1;
+                }
+                path.push_str(&format!("M{},{}h{}v1h-{}z", start, y, x - start, x - start));
+            }
+        }
+        format!(
+            concat!(
+                r#"<?xml version="1.0" standalone="yes"?>"#,
+                r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" "#,
+                r#"width="{w}" height="{h}" viewBox="0 0 {vw} {vh}" shape-rendering="crispEdges">"#,
+                r##"<rect width="{vw}" height="{vh}" fill="#fff"/>"##,
+                r##"<path fill="#000" d="{path}"/></svg>"##,
+            ),
+            w = width * PNG_SCALE,
+            h = height * PNG_SCALE,
+            vw = width,
+            vh = height,
+            path = path,
+        )
+    }
+
+    /// Greyscale PNG image drawing each module as a square of pixels
+    pub fn to_png(&self) -> Result<Vec<u8>, PartsListError> {
+        let (width, height) = self.size();
+        let (pixels_wide, pixels_high) = (width * PNG_SCALE, height * PNG_SCALE);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut row = Vec::with_capacity(pixels_wide + 1);
+        for y in 0..pixels_high {
+            row.clear();
+            // rows are stored without filtering
+            row.push(0);
+            row.extend((0..pixels_wide).map(|x| {
+                if self.is_dark(x / PNG_SCALE, y / PNG_SCALE) {
+                    0
+                } else {
+                    255
+                }
+            }));
+            encoder.write_all(&row).map_err(storage_error)?;
+        }
+        let data = encoder.finish().map_err(storage_error)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(pixels_wide as u32).to_be_bytes());
+        header.extend_from_slice(&(pixels_high as u32).to_be_bytes());
+        // 8 bits per pixel of greyscale, with the only compression, filtering
+        // and interlacing methods
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &data);
+        png_chunk(&mut png, b"IEND", &[]);
+        Ok(png)
+    }
+}
+
+/// Append a chunk of `kind` holding `data` to a PNG image
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+fn storage_error(e: std::io::Error) -> PartsListError {
+    PartsListError::Storage {
+        s: format!("Couldn't render label: {}", e),
+    }
+}
+
+/// ZPL program printing the name of a part above a Code 128 barcode of its
+/// id, with the id written below
+pub fn zpl(part: &Part) -> String {
+    format!(
+        "^XA\n^CI28\n^FO40,30^A0N,40,40^FH^FD{}^FS\n^FO40,90^BY2^BCN,100,Y,N,N^FD{}^FS\n^XZ\n",
+        zpl_field(&part.name),
+        part.id
+    )
+}
+
+/// Escape the characters of a field which ZPL would take as commands, as
+/// hexadecimal following the `_` set by `^FH`
+fn zpl_field(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '^' | '~' | '_' => escaped.push_str(&format!("_{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_labels() {
+        // each symbol is 11 modules wide apart from the stop symbol
+        for pattern in &CODE128_PATTERNS[..CODE128_STOP] {
+            assert_eq!(
+                pattern.bytes().map(|w| (w - b'0') as usize).sum::<usize>(),
+                11
+            );
+        }
+        let part = Part::new("Bolt ^M6_x~20");
+        let id = part.id.to_string();
+        let barcode = Barcode::code128(&id).unwrap();
+        // start, data, checksum and stop symbols
+        assert_eq!(barcode.width, 11 * (id.len() + 2) + 13);
+        assert!(!barcode.is_dark(CODE128_QUIET_ZONE - 1, CODE128_QUIET_ZONE));
+        assert!(barcode.is_dark(CODE128_QUIET_ZONE, CODE128_QUIET_ZONE));
+        assert!(Barcode::code128("µ").is_err());
+
+        let qr = Barcode::qr(&id).unwrap();
+        assert_eq!(qr.size(), (qr.width + 8, qr.width + 8));
+        assert!(qr.to_svg().contains(r#"viewBox="0 0"#));
+        let png = qr.to_png().unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+
+        let label = zpl(&part);
+        assert!(label.contains("^FDBolt _5EM6_5Fx_7E20^FS"));
+        assert!(label.contains(&format!("^FD{}^FS", id)));
+        assert_eq!(LabelFormat::try_from("zpl").unwrap(), LabelFormat::Zpl);
+        assert!(ImageFormat::try_from("gif").is_err());
+    }
+}
@@ -9,29 +9,361 @@ extern crate rocket;
 extern crate rocket_contrib;
 #[macro_use]
 extern crate serde_derive;
+extern crate flate2;
 extern crate thiserror;
 extern crate uuid;
 
+pub mod analysis;
+pub mod attachments;
+pub mod backup;
+pub mod category;
 pub mod client;
+pub mod cost;
+pub mod cow_map;
+pub mod currency;
+pub mod eco;
 pub mod errors;
+pub mod generator;
+pub mod idempotency;
+pub mod instructions;
+pub mod inventory;
+pub mod kicad;
+pub mod label;
+pub mod negotiate;
 pub mod parts_list;
+pub mod perf;
 pub mod query;
+pub mod quota;
+pub mod release;
+pub mod replication;
+pub mod report;
+pub mod request_id;
 pub mod response;
 pub mod routes;
+pub mod routing;
+pub mod search;
+pub mod seed;
+pub mod sidecar;
+pub mod store;
+pub mod supplier;
+pub mod telemetry;
+#[cfg(unix)]
+pub mod unix_socket;
+pub mod variants;
+pub mod writer;
 
-use std::sync::RwLock;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
-use crate::parts_list::PartsList;
+use arc_swap::ArcSwapOption;
+use rocket::config::{ConfigError, RocketConfig};
+use rocket::Config;
 
-/// Use Reader Writer Lock to control access to a parts list
-pub struct SharedPartsList(RwLock<PartsList>);
+use std::convert::TryFrom;
+
+use crate::attachments::SharedAttachments;
+use crate::backup::AutoSnapshot;
+use crate::category::CategoryRegistry;
+use crate::currency::ExchangeRates;
+use crate::eco::EcoRegistry;
+use crate::idempotency::{IdempotencyCache, DEFAULT_IDEMPOTENCY_KEYS};
+use crate::negotiate::MessagePackReplies;
+use crate::parts_list::{DuplicateNamePolicy, Part, PartsList, PartsListError};
+use crate::perf::LockMode;
+use crate::quota::{AdminToken, QuotaRegistry};
+use crate::release::ReleaseRegistry;
+use crate::replication::{Follower, Replication};
+use crate::request_id::RequestIds;
+use crate::routes::{MaxBatchSize, StrictDelete};
+use crate::store::cache::CachedStore;
+use crate::store::soft_delete::SoftDeleteStore;
+use crate::store::wal::{ChangeJournal, WalStore};
+use crate::store::PartsStore;
+use crate::telemetry::RequestSpans;
+use crate::writer::{WriteQueue, WriteRejected};
+
+/// Log written by the `wal` storage backend when not configured
+const DEFAULT_WAL_PATH: &str = "bom.wal";
+
+/// Number of log entries after which the `wal` storage backend writes a new
+/// snapshot when not configured
+const DEFAULT_WAL_COMPACT_AFTER: i64 = 1000;
+
+/// Seconds a soft deleted part can be restored for when not configured
+const DEFAULT_SOFT_DELETE_RETENTION: i64 = 24 * 60 * 60;
+
+/// Largest number of parts a single request may list when not configured
+const DEFAULT_MAX_BATCH_SIZE: i64 = 1000;
+
+/// Seconds between automatic snapshots when not configured
+const DEFAULT_SNAPSHOT_INTERVAL: i64 = 60 * 60;
+
+/// Number of automatic snapshots kept when not configured
+const DEFAULT_SNAPSHOT_KEEP: i64 = 24;
+
+/// Number of writes which may wait for the writer thread when the parts list
+/// isn't created from a configuration
+const DEFAULT_WRITE_QUEUE: usize = 4;
+
+/// Seconds between polls of the leader by a follower when not configured
+const DEFAULT_REPLICATE_INTERVAL: i64 = 5;
+
+/// Parts store shared between requests and any background tasks
+///
+/// Writes are sent as commands over a bounded queue to a writer thread, the
+/// only thread changing the store, which makes them one at a time under the
+/// write lock. Callers only wait for the reply to their change, which is
+/// refused straight away once too many are waiting. Reads take the read lock
+/// unless served from a snapshot.
+///
+/// Stores held in process can also keep an immutable snapshot of their parts,
+/// which writers replace after each change. Readers then share the latest
+/// snapshot instead of taking the lock, so reads never wait on writes.
+///
+/// Stores journaling their changes, such as the `wal` backend, also share
+/// their journal for the change feed.
+///
+/// Clones share the store, its snapshot and the write queue, so background
+/// tasks change the parts through the writer thread the same way requests do.
+#[derive(Clone)]
+pub struct SharedPartsList {
+    store: Arc<RwLock<Box<dyn PartsStore>>>,
+    snapshot: Option<Arc<ArcSwapOption<PartsList>>>,
+    writes: WriteQueue,
+    changes: Option<ChangeJournal>,
+}
+
+/// Read access to the parts, either through the latest snapshot or the lock
+pub enum PartsReader<'a> {
+    Snapshot(Arc<PartsList>),
+    Locked(RwLockReadGuard<'a, Box<dyn PartsStore>>),
+}
+
+impl<'a> Deref for PartsReader<'a> {
+    type Target = dyn PartsStore;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            PartsReader::Snapshot(parts) => &**parts,
+            PartsReader::Locked(store) => &***store,
+        }
+    }
+}
+
+/// Write access to the store given to changes on the writer thread, ending
+/// the write and replacing the snapshot once any change is made
+pub struct PartsWriter<'a> {
+    store: RwLockWriteGuard<'a, Box<dyn PartsStore>>,
+    snapshot: Option<&'a ArcSwapOption<PartsList>>,
+    changed: bool,
+}
+
+impl<'a> Deref for PartsWriter<'a> {
+    type Target = dyn PartsStore;
+
+    fn deref(&self) -> &Self::Target {
+        &**self.store
+    }
+}
+
+impl<'a> DerefMut for PartsWriter<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.changed = true;
+        &mut **self.store
+    }
+}
+
+impl<'a> Drop for PartsWriter<'a> {
+    fn drop(&mut self) {
+        self.store.end_write();
+        if let (Some(snapshot), true) = (self.snapshot, self.changed) {
+            // readers fall back to the lock rather than see stale parts
+            snapshot.store(self.store.snapshot().ok().map(Arc::new));
+        }
+    }
+}
 
 impl SharedPartsList {
+    /// Create an empty shared parts list held in memory
     pub fn new() -> SharedPartsList {
-        SharedPartsList(RwLock::new(PartsList::new()))
+        SharedPartsList::from_store(Box::new(PartsList::new()), true)
+    }
+
+    /// Share an existing parts store, allowing alternative storage backends
+    pub fn with_store<S: PartsStore + 'static>(store: S) -> SharedPartsList {
+        SharedPartsList::from_store(Box::new(store), false)
+    }
+
+    /// Share a parts store, keeping snapshots for readers when `snapshots` is
+    /// set, which is only correct when nothing else modifies the store
+    fn from_store(store: Box<dyn PartsStore>, snapshots: bool) -> SharedPartsList {
+        let snapshot = if snapshots {
+            Some(Arc::new(ArcSwapOption::new(
+                store.snapshot().ok().map(Arc::new),
+            )))
+        } else {
+            None
+        };
+        let store = Arc::new(RwLock::new(store));
+        SharedPartsList {
+            writes: WriteQueue::new(DEFAULT_WRITE_QUEUE, store.clone(), snapshot.clone()),
+            store,
+            snapshot,
+            changes: None,
+        }
+    }
+
+    /// Let at most `capacity` writes wait for the writer thread, refusing
+    /// further writes
+    pub fn with_write_queue(mut self, capacity: usize) -> SharedPartsList {
+        self.writes = WriteQueue::new(capacity, self.store.clone(), self.snapshot.clone());
+        self
+    }
+
+    /// Read the parts without blocking, failing only when there is no
+    /// snapshot and the lock is held by a writer
+    pub fn try_read(&self) -> Option<PartsReader<'_>> {
+        let _span = tracing::debug_span!("lock", mode = "read").entered();
+        let started = Instant::now();
+        let reader = match self.snapshot.as_ref().and_then(|s| s.load_full()) {
+            Some(parts) => Some(PartsReader::Snapshot(parts)),
+            None => self.store.try_read().ok().map(PartsReader::Locked),
+        };
+        perf::record_lock(LockMode::Read, started.elapsed(), reader.is_some());
+        reader
+    }
+
+    /// Have the writer thread make `change` once the writes queued before are
+    /// done, returning what it returns once readers can see the change, and
+    /// failing straight away when the queue is full
+    pub fn change<R, F>(&self, change: F) -> Result<R, WriteRejected>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut PartsWriter<'_>) -> R + Send + 'static,
+    {
+        let _span = tracing::debug_span!("lock", mode = "write").entered();
+        self.writes.change(change)
+    }
+
+    /// Journal of the changes made to the store, when it keeps one
+    pub fn changes(&self) -> Result<&ChangeJournal, PartsListError> {
+        self.changes
+            .as_ref()
+            .ok_or(PartsListError::ChangesUnavailable)
+    }
+
+    /// Every part along with the number of the latest change they include, so
+    /// a mirror copying them can carry on from the change feed
+    ///
+    /// Changes are journaled while the store is write locked, so the parts
+    /// are read under the lock rather than from the snapshot, which is only
+    /// replaced once a change is done.
+    pub fn change_snapshot(&self) -> Result<(u64, Vec<Part>), PartsListError> {
+        let journal = self.changes()?;
+        let store = self.store.read().map_err(|_| PartsListError::Storage {
+            s: String::from("Couldn't read lock parts list!"),
+        })?;
+        Ok((journal.cursor()?, store.parts()?))
+    }
+
+    /// Load the parts of a seed file when the store holds no parts, returning
+    /// the number loaded, or nothing when it already held parts
+    pub fn seed(&self, path: &Path) -> Result<Option<usize>, PartsListError> {
+        let path = path.to_path_buf();
+        self.change(move |store| seed::load(&mut **store, &path))
+            .map_err(|e| PartsListError::Storage { s: e.to_string() })?
+    }
+
+    /// Create the parts store selected by the `storage` setting of the Rocket
+    /// configuration, either `memory` (the default), `wal`, which logs changes
+    /// to the file given by the `wal_path` setting and writes a snapshot every
+    /// `wal_compact_after` changes, or `postgres` or `redis`, which connect to
+    /// the server given by the `database_url` setting
+    ///
+    /// New parts given the name of an existing part are handled according to the
+    /// `duplicate_names` setting, either `reject` (the default), `allow` or
+    /// `suffix`. Deleted parts can be restored for `soft_delete_retention`
+    /// seconds when the `soft_delete` setting is enabled, and subtree queries
+    /// are cached when the `rollup_cache` setting is enabled.
+    ///
+    /// Readers are served from snapshots with the `memory` and `wal` backends,
+    /// which are only modified through this server, unless subtree queries are
+    /// cached, which only readers taking the lock can use. At most
+    /// `write_queue` writes wait for the writer thread, by default a quarter
+    /// of the `workers` serving requests and at least one, as each holds a
+    /// worker until its change is made and reads must still find a free one.
+    pub fn from_config(config: &Config) -> Result<SharedPartsList, PartsListError> {
+        let policy =
+            DuplicateNamePolicy::try_from(config.get_str("duplicate_names").unwrap_or("reject"))?;
+        let storage = config.get_str("storage").unwrap_or("memory");
+        let mut changes = None;
+        let store: Box<dyn PartsStore> = match storage {
+            "memory" => {
+                let mut parts = PartsList::new();
+                parts.set_name_policy(policy);
+                Box::new(parts)
+            }
+            "wal" => {
+                let path = config.get_str("wal_path").unwrap_or(DEFAULT_WAL_PATH);
+                let compact_after = config
+                    .get_int("wal_compact_after")
+                    .unwrap_or(DEFAULT_WAL_COMPACT_AFTER);
+                let store = WalStore::open(path, policy, compact_after.max(1) as usize)?;
+                changes = Some(store.journal());
+                Box::new(store)
+            }
+            #[cfg(feature = "postgres")]
+            "postgres" => Box::new(store::postgres::PgStore::connect(
+                database_url(config)?,
+                policy,
+            )?),
+            #[cfg(feature = "redis")]
+            "redis" => Box::new(store::redis::RedisStore::connect(
+                database_url(config)?,
+                policy,
+            )?),
+            other => {
+                return Err(PartsListError::Storage {
+                    s: format!("Unsupported storage backend {:?}", other),
+                })
+            }
+        };
+        let store: Box<dyn PartsStore> = if config.get_bool("soft_delete").unwrap_or(false) {
+            let retention = config
+                .get_int("soft_delete_retention")
+                .unwrap_or(DEFAULT_SOFT_DELETE_RETENTION);
+            let retention = Duration::from_secs(retention.max(0) as u64);
+            Box::new(SoftDeleteStore::new(store, retention))
+        } else {
+            store
+        };
+        let cached = config.get_bool("rollup_cache").unwrap_or(false);
+        let store: Box<dyn PartsStore> = if cached {
+            Box::new(CachedStore::new(store))
+        } else {
+            store
+        };
+        // readers of snapshots would skip the cache
+        let snapshots = matches!(storage, "memory" | "wal") && !cached;
+        let write_queue = config
+            .get_int("write_queue")
+            .unwrap_or(i64::from(config.workers) / 4);
+        let mut shared = SharedPartsList::from_store(store, snapshots)
+            .with_write_queue(write_queue.max(1) as usize);
+        shared.changes = changes;
+        Ok(shared)
     }
 }
 
+#[cfg(any(feature = "postgres", feature = "redis"))]
+fn database_url(config: &Config) -> Result<&str, PartsListError> {
+    config
+        .get_str("database_url")
+        .map_err(|e| PartsListError::Storage { s: e.to_string() })
+}
+
 impl Default for SharedPartsList {
     fn default() -> Self {
         Self::new()
@@ -39,21 +371,531 @@ impl Default for SharedPartsList {
 }
 
 /// Create reactor for bom-server taking ownership of a parts list instance
-/// and mount all API paths from the routes module
+/// and mount all API paths from the routes module, under the `base_path`
+/// setting of the Rocket configuration when set
+///
+/// # Panics
+///
+/// Panics where `try_make_rocket` fails, as `rocket::ignite` does for invalid
+/// Rocket settings.
 pub fn make_rocket(parts_list: SharedPartsList) -> rocket::Rocket {
-    rocket::ignite()
+    try_make_rocket(parts_list).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Create reactor for bom-server as `make_rocket` does, failing when the
+/// `currency`, `exchange_rates`, `anonymous_quota`, `release_roles` or
+/// `release_tokens` settings are invalid or the saved categories, ECOs,
+/// releases or quotas can't be read
+pub fn try_make_rocket(parts_list: SharedPartsList) -> Result<rocket::Rocket, PartsListError> {
+    let rocket = rocket::ignite();
+    let rates = ExchangeRates::from_config(rocket.config())?;
+    let quotas = quotas_config(rocket.config())?;
+    let categories = categories_config(rocket.config())?;
+    let ecos = ecos_config(rocket.config())?;
+    let releases = releases_config(rocket.config())?;
+    Ok(mount_routes(
+        rocket,
+        parts_list,
+        rates,
+        quotas,
+        categories,
+        ecos,
+        releases,
+        Replication::default(),
+    ))
+}
+
+/// File next to the log given by the `wal_path` setting keeping data stored
+/// alongside the parts, or `None` when the `storage` backend holds the parts in
+/// memory, so that data is held in memory too
+fn sidecar_path(config: &Config, name: &str) -> Option<PathBuf> {
+    match config.get_str("storage").unwrap_or("memory") {
+        "memory" => None,
+        _ => {
+            let wal_path = config.get_str("wal_path").unwrap_or(DEFAULT_WAL_PATH);
+            Some(sidecar::path(Path::new(wal_path), name))
+        }
+    }
+}
+
+/// File next to the `wal_path` log keeping data every server must agree on, as
+/// `sidecar_path` gives, refusing the `postgres` and `redis` backends, which
+/// several servers may share while each keeps its own file, unless the
+/// `single_instance` setting promises only one server uses them
+fn shared_sidecar_path(config: &Config, name: &str) -> Result<Option<PathBuf>, PartsListError> {
+    let storage = config.get_str("storage").unwrap_or("memory");
+    let single_instance = config.get_bool("single_instance").unwrap_or(false);
+    if matches!(storage, "postgres" | "redis") && !single_instance {
+        return Err(PartsListError::Config {
+            s: format!(
+                "{} are saved next to wal_path rather than in {}, so single_instance must be set \
+                 to confirm no other server shares the {} storage",
+                name, storage, storage
+            ),
+        });
+    }
+    Ok(sidecar_path(config, name))
+}
+
+/// Categories of the server, saved next to the `wal_path` log unless the parts
+/// are held in memory
+pub fn categories_config(config: &Config) -> Result<CategoryRegistry, PartsListError> {
+    match shared_sidecar_path(config, "categories")? {
+        Some(path) => CategoryRegistry::open(path),
+        None => Ok(CategoryRegistry::new()),
+    }
+}
+
+/// ECOs of the server, saved next to the `wal_path` log unless the parts are
+/// held in memory
+pub fn ecos_config(config: &Config) -> Result<EcoRegistry, PartsListError> {
+    match shared_sidecar_path(config, "ecos")? {
+        Some(path) => EcoRegistry::open(path),
+        None => Ok(EcoRegistry::new()),
+    }
+}
+
+/// Releases of the server, each approved by the roles of the `release_roles`
+/// setting sending their token from `release_tokens`, and saved next to the
+/// `wal_path` log unless the parts are held in memory
+pub fn releases_config(config: &Config) -> Result<ReleaseRegistry, PartsListError> {
+    let roles = release::roles_config(config)?;
+    let tokens = release::tokens_config(config, &roles)?;
+    let releases = match sidecar_path(config, "releases") {
+        Some(path) => ReleaseRegistry::open(path, roles)?,
+        None => ReleaseRegistry::new(roles),
+    };
+    Ok(releases.with_tokens(tokens))
+}
+
+/// Quotas of the server, limiting requests sent without an API key of their
+/// own by the `anonymous_quota` setting, with the limits of each key saved next
+/// to the `wal_path` log unless the parts are held in memory
+pub fn quotas_config(config: &Config) -> Result<QuotaRegistry, PartsListError> {
+    let anonymous = quota::anonymous_config(config)?;
+    match sidecar_path(config, "quotas") {
+        Some(path) => QuotaRegistry::open(path, anonymous),
+        None => Ok(QuotaRegistry::new(anonymous)),
+    }
+}
+
+/// Automatic snapshots selected by the `snapshot_dir` setting of the Rocket
+/// configuration, written every `snapshot_interval` seconds and keeping the
+/// latest `snapshot_keep`
+pub fn auto_snapshot_config(config: &Config) -> Option<AutoSnapshot> {
+    let dir = config.get_str("snapshot_dir").ok()?;
+    let interval = config
+        .get_int("snapshot_interval")
+        .unwrap_or(DEFAULT_SNAPSHOT_INTERVAL);
+    let keep = config
+        .get_int("snapshot_keep")
+        .unwrap_or(DEFAULT_SNAPSHOT_KEEP);
+    Some(AutoSnapshot::new(
+        dir,
+        Duration::from_secs(interval.max(1) as u64),
+        keep.max(1) as usize,
+    ))
+}
+
+/// Follower selected by the `replicate_from` setting of the Rocket
+/// configuration, the url of the leader to copy, polling it every
+/// `replicate_interval` seconds and authenticating with `replicate_token`
+/// when set
+pub fn replication_config(config: &Config) -> Result<Option<Follower>, PartsListError> {
+    let leader = match config.get_str("replicate_from") {
+        Ok(leader) => leader,
+        Err(_) => return Ok(None),
+    };
+    let leader = leader.parse().map_err(|e| PartsListError::Config {
+        s: format!("replicate_from: {}", e),
+    })?;
+    let interval = config
+        .get_int("replicate_interval")
+        .unwrap_or(DEFAULT_REPLICATE_INTERVAL);
+    let token = config.get_str("replicate_token").ok().map(String::from);
+    Ok(Some(Follower::new(
+        leader,
+        token,
+        Duration::from_secs(interval.max(1) as u64),
+    )))
+}
+
+/// Rocket configuration of the active environment, read from `Rocket.toml` and
+/// the environment like `rocket::ignite` does
+fn active_config() -> Result<Config, PartsListError> {
+    let config = match RocketConfig::read() {
+        Err(ConfigError::NotFound) | Err(ConfigError::IoError) => RocketConfig::active_default(),
+        config => config,
+    };
+    config
+        .map(|config| config.active().clone())
+        .map_err(|e| PartsListError::Config { s: e.to_string() })
+}
+
+/// Serve HTTPS using the PEM certificate chain and private key files given by
+/// the `tls_certs` and `tls_key` settings of the Rocket configuration,
+/// returning whether they were set
+pub fn tls_config(config: &mut Config) -> Result<bool, PartsListError> {
+    let (certs, key) = match (config.get_str("tls_certs"), config.get_str("tls_key")) {
+        (Ok(certs), Ok(key)) => (certs.to_string(), key.to_string()),
+        (Err(_), Err(_)) => return Ok(false),
+        _ => {
+            return Err(PartsListError::Config {
+                s: "tls_certs and tls_key must be set together".into(),
+            })
+        }
+    };
+    #[cfg(feature = "tls")]
+    {
+        config
+            .set_tls(&certs, &key)
+            .map_err(|e| PartsListError::Config {
+                s: format!("Invalid TLS certificate or key: {}", e),
+            })?;
+        Ok(true)
+    }
+    #[cfg(not(feature = "tls"))]
+    {
+        let _ = (certs, key);
+        Err(PartsListError::Config {
+            s: "tls_certs and tls_key require bom-server to be built with the tls feature".into(),
+        })
+    }
+}
+
+/// Create reactor for bom-server using the parts store selected in the Rocket
+/// configuration and mount all API paths from the routes module, starting
+/// automatic snapshots, tracing and the Unix socket listener and serving HTTPS
+/// when configured
+pub fn make_configured_rocket() -> Result<rocket::Rocket, PartsListError> {
+    let mut config = active_config()?;
+    tls_config(&mut config)?;
+    let mut rocket = rocket::custom(config);
+    if let Some(telemetry) = telemetry::init(rocket.config())? {
+        rocket = rocket.manage(telemetry);
+    }
+    let parts_list = SharedPartsList::from_config(rocket.config())?;
+    let rates = ExchangeRates::from_config(rocket.config())?;
+    let quotas = quotas_config(rocket.config())?;
+    let categories = categories_config(rocket.config())?;
+    let ecos = ecos_config(rocket.config())?;
+    let releases = releases_config(rocket.config())?;
+    if let Some(auto_snapshot) = auto_snapshot_config(rocket.config()) {
+        auto_snapshot.spawn(parts_list.store.clone());
+    }
+    #[cfg(unix)]
+    if let Some(relay) = unix_socket::UnixSocketRelay::from_config(rocket.config())? {
+        relay.spawn()?;
+    }
+    let replication = match replication_config(rocket.config())? {
+        Some(follower) => follower.spawn(parts_list.clone()).map_err(|e| match e {
+            PartsListError::ChangesUnavailable => PartsListError::Config {
+                s: String::from("replicate_from requires the wal storage backend"),
+            },
+            e => e,
+        })?,
+        None => Replication::default(),
+    };
+    Ok(mount_routes(
+        rocket,
+        parts_list,
+        rates,
+        quotas,
+        categories,
+        ecos,
+        releases,
+        replication,
+    ))
+}
+
+/// Path all API paths are mounted under, given by the `base_path` setting of
+/// the Rocket configuration, such as `/bom`, or `/` when not configured
+pub fn base_path(config: &Config) -> String {
+    let path = config.get_str("base_path").unwrap_or("/").trim();
+    match path.trim_matches('/') {
+        "" => "/".into(),
+        path => format!("/{}", path),
+    }
+}
+
+/// Mount all API paths under the configured base path, remembering replies to
+/// the number of requests given by the `idempotency_keys` setting so they can
+/// be retried safely, storing attachments as configured, refusing to delete
+/// parts still in use when the `strict_delete` setting is enabled, refusing
+/// requests listing more parts than the `max_batch_size` setting,
+/// converting costs between currencies with `rates`, limiting changes by
+/// `quotas` managed with the `admin_token` setting, keeping `categories`,
+/// `ecos` and `releases` and reporting the status of any `replication` from a
+/// leader
+#[allow(clippy::too_many_arguments)]
+fn mount_routes(
+    rocket: rocket::Rocket,
+    parts_list: SharedPartsList,
+    rates: ExchangeRates,
+    quotas: QuotaRegistry,
+    categories: CategoryRegistry,
+    ecos: EcoRegistry,
+    releases: ReleaseRegistry,
+    replication: Replication,
+) -> rocket::Rocket {
+    let idempotency_keys = rocket
+        .config()
+        .get_int("idempotency_keys")
+        .map_or(DEFAULT_IDEMPOTENCY_KEYS, |keys| keys.max(0) as usize);
+    let attachments = SharedAttachments::from_config(rocket.config());
+    let strict_delete = rocket.config().get_bool("strict_delete").unwrap_or(false);
+    let max_batch_size = rocket
+        .config()
+        .get_int("max_batch_size")
+        .unwrap_or(DEFAULT_MAX_BATCH_SIZE);
+    let admin_token = rocket
+        .config()
+        .get_str("admin_token")
+        .ok()
+        .map(String::from);
+    let base_path = base_path(rocket.config());
+    rocket
         .mount(
-            "/",
+            &base_path,
             routes![
                 routes::index,
+                routes::ui,
                 routes::list_parts,
                 routes::create_part,
+                routes::upsert_part,
+                routes::batch_get,
+                routes::top_used,
+                routes::common_parts,
                 routes::get_part,
+                routes::get_part_by_name,
+                routes::get_part_by_external,
+                routes::edit_part,
+                routes::delete_parts,
+                routes::bulk_delete,
                 routes::delete_part,
                 routes::get_children,
                 routes::update_children,
+                routes::restore_part,
+                routes::move_part,
+                routes::get_relationship,
+                routes::edit_relationship,
+                routes::upload_attachments,
+                routes::list_attachments,
+                routes::download_attachment,
+                routes::delete_attachment,
+                routes::get_parents,
+                routes::get_summary,
                 routes::get_contained,
+                routes::get_ancestors,
+                routes::get_shortage,
+                routes::get_lead_time,
+                routes::get_picklist,
+                routes::get_report,
+                routes::get_report_formatted,
+                routes::get_label,
+                routes::get_routing,
+                routes::set_routing,
+                routes::add_routing_step,
+                routes::delete_routing,
+                routes::get_instructions,
+                routes::set_instructions,
+                routes::get_cost,
+                routes::get_cost_history,
+                routes::get_cost_trend,
+                routes::get_rfq,
+                routes::search,
+                routes::get_stats,
+                routes::get_changes,
+                routes::get_change_snapshot,
+                routes::get_replication,
+                routes::get_perf,
+                routes::validate,
+                routes::duplicates,
+                routes::backup,
+                routes::restore,
+                routes::generate,
+                routes::list_quotas,
+                routes::get_quota,
+                routes::set_quota,
+                routes::delete_quota,
+                routes::create_eco,
+                routes::list_ecos,
+                routes::get_eco,
+                routes::approve_eco,
+                routes::reject_eco,
+                routes::apply_eco,
+                routes::submit_release,
+                routes::list_releases,
+                routes::get_release,
+                routes::approve_release,
+                routes::reject_release,
+                routes::create_category,
+                routes::list_categories,
+                routes::get_category,
+                routes::edit_category,
+                routes::delete_category,
+                routes::v2::list_parts,
+                routes::v2::create_part,
+                routes::v2::get_part,
+                routes::v2::edit_part,
+                routes::v2::delete_part,
+                routes::v2::get_children,
+                routes::v2::update_children,
+                routes::v2::get_summary,
+                routes::v2::get_stats,
             ],
         )
+        .register(catchers![
+            routes::bad_request,
+            routes::forbidden,
+            routes::not_found,
+            routes::unprocessable_entity,
+            routes::payload_too_large,
+            routes::too_many_requests,
+            routes::internal_error,
+        ])
         .manage(parts_list)
+        .manage(IdempotencyCache::new(idempotency_keys))
+        .manage(ecos)
+        .manage(releases)
+        .manage(categories)
+        .manage(quotas)
+        .manage(AdminToken(admin_token))
+        .manage(attachments)
+        .manage(StrictDelete(strict_delete))
+        .manage(MaxBatchSize(max_batch_size.max(1) as usize))
+        .manage(rates)
+        .manage(replication)
+        .attach(RequestIds)
+        .attach(RequestSpans)
+        .attach(MessagePackReplies)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+    use crate::parts_list::{Part, PartsListFilter, PartsListUpdate};
+
+    /// Start a change inserting a part, which only finishes once the returned
+    /// sender is dropped
+    fn start_insert(shared: &SharedPartsList) -> (mpsc::Sender<()>, thread::JoinHandle<Part>) {
+        let (inserted, started) = mpsc::channel();
+        let (finish, finishing) = mpsc::channel::<()>();
+        let writer = shared.clone();
+        let change = thread::spawn(move || {
+            writer
+                .change(move |store| {
+                    let part = store.insert(Part::new("my part")).unwrap();
+                    inserted.send(()).unwrap();
+                    let _ = finishing.recv();
+                    part
+                })
+                .unwrap()
+        });
+        started.recv().unwrap();
+        (finish, change)
+    }
+
+    #[test]
+    fn reads_while_writing() {
+        let shared = SharedPartsList::new();
+        let (finish, change) = start_insert(&shared);
+
+        // readers keep seeing the last snapshot until the change is done
+        assert!(shared.try_read().unwrap().parts().unwrap().is_empty());
+        drop(finish);
+        let part = change.join().unwrap();
+        assert_eq!(shared.try_read().unwrap().get(&part.id).unwrap(), part);
+
+        // without snapshots readers wait for the lock
+        let shared = SharedPartsList::with_store(PartsList::new());
+        let (finish, change) = start_insert(&shared);
+        assert!(shared.try_read().is_none());
+        drop(finish);
+        change.join().unwrap();
+        assert!(shared.try_read().is_some());
+    }
+
+    #[test]
+    fn cached_reads_take_the_lock() {
+        let config = Config::build(rocket::config::Environment::Development)
+            .extra("rollup_cache", true)
+            .unwrap();
+        let shared = SharedPartsList::from_config(&config).unwrap();
+        let (assy, screw) = shared
+            .change(|store| {
+                let assy = store.insert(Part::new("assy")).unwrap();
+                let screw = store.insert(Part::new("screw")).unwrap();
+                (assy, screw)
+            })
+            .unwrap();
+        let explode = || {
+            let reader = shared.try_read().unwrap();
+            assert!(matches!(reader, PartsReader::Locked(_)));
+            reader.get_children(&assy.id, PartsListFilter::All).unwrap()
+        };
+        assert!(explode().is_empty());
+        assert!(explode().is_empty());
+
+        // the cached explosion is invalidated by the write
+        let (assy_id, screw_id) = (assy.id, screw.id);
+        shared
+            .change(move |store| store.update(&assy_id, &[&screw_id], PartsListUpdate::Add))
+            .unwrap()
+            .unwrap();
+        assert_eq!(explode().len(), 1);
+    }
+
+    #[test]
+    fn configured_base_path() {
+        let base_path = |path: &str| {
+            let config = Config::build(rocket::config::Environment::Development)
+                .extra("base_path", path)
+                .unwrap();
+            super::base_path(&config)
+        };
+        assert_eq!(super::base_path(&Config::development()), "/");
+        assert_eq!(base_path("/bom"), "/bom");
+        assert_eq!(base_path("bom/api/"), "/bom/api");
+        assert_eq!(base_path("/"), "/");
+    }
+
+    #[test]
+    fn tls_settings() {
+        let mut config = Config::development();
+        assert!(!tls_config(&mut config).unwrap());
+
+        config.set_extras(
+            vec![("tls_certs".to_string(), "certs.pem".into())]
+                .into_iter()
+                .collect(),
+        );
+        assert_matches!(tls_config(&mut config), Err(PartsListError::Config { .. }));
+    }
+
+    #[test]
+    fn shared_storage_sidecars() {
+        let wal_path =
+            std::env::temp_dir().join(format!("bom-shared-{}.wal", uuid::Uuid::new_v4()));
+        let config = |single_instance: bool| {
+            Config::build(rocket::config::Environment::Development)
+                .extra("storage", "postgres")
+                .extra("wal_path", wal_path.to_str().unwrap())
+                .extra("single_instance", single_instance)
+                .unwrap()
+        };
+        assert_matches!(
+            categories_config(&config(false)),
+            Err(PartsListError::Config { .. })
+        );
+        assert!(categories_config(&config(true)).is_ok());
+        assert_matches!(
+            ecos_config(&config(false)),
+            Err(PartsListError::Config { .. })
+        );
+        assert!(ecos_config(&config(true)).is_ok());
+    }
 }
@@ -0,0 +1,341 @@
+use std::convert::Infallible;
+use std::io::{self, Cursor, Read};
+use std::ops::Deref;
+
+use rocket::data::{self, FromDataSimple};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Accept, ContentType, MediaType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::Data;
+use rocket_contrib::json::Json;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::response::Response;
+
+/// Format of the parts listed in reply to a request, as negotiated through
+/// its `Accept` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataFormat {
+    /// The usual JSON response
+    Json,
+    /// Only the listed parts, one per row
+    Csv,
+    /// The usual response, encoded as MessagePack
+    MessagePack,
+}
+
+impl DataFormat {
+    /// Format for a media type, if it is one of those supported
+    fn of(top: &str, sub: &str) -> Option<DataFormat> {
+        match (top, sub) {
+            ("*", "*") | ("application", "*") | ("application", "json") => Some(DataFormat::Json),
+            ("text", "*") | ("text", "csv") => Some(DataFormat::Csv),
+            ("application", "msgpack") | ("application", "x-msgpack") => {
+                Some(DataFormat::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    /// Pick the supported format given the most weight by an `Accept` header,
+    /// taking the first listed among those with the same weight, and JSON when
+    /// there is no header or none of the formats it lists are supported
+    pub fn negotiate(accept: Option<&Accept>) -> DataFormat {
+        let mut best: Option<(f32, DataFormat)> = None;
+        for media_type in accept.into_iter().flat_map(|accept| accept.iter()) {
+            let weight = media_type.weight_or(1.0);
+            let top = media_type.top().as_str().to_ascii_lowercase();
+            let sub = media_type.sub().as_str().to_ascii_lowercase();
+            let format = match DataFormat::of(&top, &sub) {
+                Some(format) if weight > 0.0 => format,
+                _ => continue,
+            };
+            if best.is_none_or(|(most, _)| weight > most) {
+                best = Some((weight, format));
+            }
+        }
+        best.map_or(DataFormat::Json, |(_, format)| format)
+    }
+}
+
+/// Largest request body read when no `msgpack` or `json` limit is configured
+const DEFAULT_PAYLOAD_LIMIT: u64 = 1024 * 1024;
+
+/// Media type of MessagePack requests and replies
+pub fn msgpack() -> MediaType {
+    MediaType::new("application", "msgpack")
+}
+
+/// Encode a value as MessagePack, with structs as maps and ids as strings as
+/// they are in JSON
+pub fn to_msgpack<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    let mut encoded = Vec::new();
+    value.serialize(
+        &mut rmp_serde::Serializer::new(&mut encoded)
+            .with_struct_map()
+            .with_human_readable(),
+    )?;
+    Ok(encoded)
+}
+
+/// Decode a value encoded by `to_msgpack`
+pub fn from_msgpack<T: DeserializeOwned>(encoded: &[u8]) -> Result<T, rmp_serde::decode::Error> {
+    T::deserialize(&mut rmp_serde::Deserializer::new(encoded).with_human_readable())
+}
+
+/// Whether a media type is one of those used for MessagePack
+pub fn is_msgpack(media_type: &MediaType) -> bool {
+    media_type.top() == "application"
+        && (media_type.sub() == "msgpack" || media_type.sub() == "x-msgpack")
+}
+
+/// Format negotiated for the reply to a request
+pub struct Accepted(pub DataFormat);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Accepted {
+    type Error = Infallible;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(Accepted(DataFormat::negotiate(request.accept())))
+    }
+}
+
+/// Reply holding a response, of which only the parts in `data` are sent as
+/// rows when CSV was negotiated
+///
+/// Responses reporting an error are always sent whole, as are those for which
+/// MessagePack was negotiated, being encoded by `MessagePackReplies`. The
+/// cursor of the next page of a CSV listing is sent in the `X-Next-Cursor`
+/// header instead.
+pub struct Negotiated(pub DataFormat, pub Response);
+
+impl<'r> Responder<'r> for Negotiated {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let Negotiated(format, response) = self;
+        if format != DataFormat::Csv || response.error.is_some() || response.data.is_none() {
+            return Json(response).respond_to(request);
+        }
+        // serialized first so sparse fieldsets and inline scores are kept
+        let envelope = serde_json::to_value(&response).map_err(|_| Status::InternalServerError)?;
+        let data = envelope.get("data").cloned().unwrap_or(Value::Null);
+        let body = parts_csv(&data).map_err(|_| Status::InternalServerError)?;
+        let mut reply = rocket::Response::build();
+        reply.header(ContentType::CSV).sized_body(Cursor::new(body));
+        if let Some(cursor) = envelope.get("next_cursor").and_then(Value::as_str) {
+            reply.raw_header("X-Next-Cursor", cursor.to_string());
+        }
+        reply.ok()
+    }
+}
+
+/// Fairing encoding JSON replies as MessagePack for requests which prefer it
+/// by their `Accept` header
+///
+/// It must be attached after any fairing changing the JSON replies, such as
+/// `RequestIds`.
+pub struct MessagePackReplies;
+
+impl Fairing for MessagePackReplies {
+    fn info(&self) -> Info {
+        Info {
+            name: "MessagePack replies",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut rocket::Response) {
+        if DataFormat::negotiate(request.accept()) != DataFormat::MessagePack
+            || response.content_type() != Some(ContentType::JSON)
+        {
+            return;
+        }
+        if let Some(body) = response.body_string() {
+            match serde_json::from_str::<Value>(&body).map(|value| to_msgpack(&value)) {
+                Ok(Ok(encoded)) => {
+                    response.set_header(ContentType(msgpack()));
+                    response.set_sized_body(Cursor::new(encoded));
+                }
+                _ => response.set_sized_body(Cursor::new(body)),
+            }
+        }
+    }
+}
+
+/// Failure to read the body of a request
+#[derive(Error, Debug)]
+pub enum PayloadError {
+    #[error("Couldn't read request body: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Couldn't parse JSON body: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Couldn't parse MessagePack body: {0}")]
+    MessagePack(#[from] rmp_serde::decode::Error),
+
+    #[error("Request body is larger than the limit of {limit} bytes")]
+    TooLarge { limit: u64 },
+}
+
+/// Largest body read for a request, given by the `msgpack` or else `json`
+/// limit of the server
+pub fn payload_limit(request: &Request) -> u64 {
+    let limits = request.limits();
+    limits
+        .get("msgpack")
+        .or_else(|| limits.get("json"))
+        .unwrap_or(DEFAULT_PAYLOAD_LIMIT)
+}
+
+/// Body of a request, sent either as JSON or, with a `Content-Type` of
+/// `application/msgpack`, as MessagePack
+///
+/// The body is limited to the `msgpack` or else `json` limit of the server,
+/// and larger bodies are refused with `413 Payload Too Large`.
+#[derive(Debug)]
+pub struct Payload<T>(pub T);
+
+impl<T> Payload<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Payload<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromDataSimple for Payload<T> {
+    type Error = PayloadError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        let limit = payload_limit(request);
+        let mut body = Vec::new();
+        // one byte past the limit tells a body at the limit from a larger one
+        if let Err(e) = data
+            .open()
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut body)
+        {
+            return data::Outcome::Failure((Status::BadRequest, e.into()));
+        }
+        if body.len() as u64 > limit {
+            return data::Outcome::Failure((
+                Status::PayloadTooLarge,
+                PayloadError::TooLarge { limit },
+            ));
+        }
+        let parsed = match request.content_type() {
+            Some(content_type) if is_msgpack(content_type.media_type()) => {
+                from_msgpack(&body).map_err(PayloadError::from)
+            }
+            _ => serde_json::from_slice(&body).map_err(PayloadError::from),
+        };
+        match parsed {
+            Ok(value) => data::Outcome::Success(Payload(value)),
+            // as for JSON bodies, only bodies which are malformed are bad requests
+            Err(PayloadError::Json(e)) if !e.is_data() => {
+                data::Outcome::Failure((Status::BadRequest, e.into()))
+            }
+            Err(e) => data::Outcome::Failure((Status::UnprocessableEntity, e)),
+        }
+    }
+}
+
+/// Write serialized parts as CSV, with a column for each of their fields
+///
+/// Lists of ids are joined by `;`, and other nested fields such as
+/// relationships are written as JSON.
+pub fn parts_csv(data: &Value) -> Result<Vec<u8>, csv::Error> {
+    let rows = data.as_array().map_or(&[][..], |rows| rows.as_slice());
+    let mut columns: Vec<&str> = Vec::new();
+    for row in rows.iter().filter_map(Value::as_object) {
+        for column in row.keys() {
+            if !columns.contains(&column.as_str()) {
+                columns.push(column);
+            }
+        }
+    }
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&columns)?;
+    for row in rows {
+        writer.write_record(columns.iter().map(|column| cell(row.get(column))))?;
+    }
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) => s.clone(),
+                item => item.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(";"),
+        Some(value) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::Part;
+
+    #[test]
+    fn negotiated_formats() {
+        let negotiate = |header: &str| DataFormat::negotiate(Some(&header.parse().unwrap()));
+        assert_eq!(DataFormat::negotiate(None), DataFormat::Json);
+        assert_eq!(negotiate("text/csv"), DataFormat::Csv);
+        assert_eq!(negotiate("application/x-msgpack"), DataFormat::MessagePack);
+        assert_eq!(
+            negotiate("text/csv;q=0.5, application/msgpack"),
+            DataFormat::MessagePack
+        );
+        assert_eq!(
+            negotiate("text/html, text/csv;q=0.9, */*;q=0.8"),
+            DataFormat::Csv
+        );
+        assert_eq!(negotiate("image/png"), DataFormat::Json);
+        assert_eq!(negotiate("text/csv;q=0"), DataFormat::Json);
+
+        let mut part = Part::new("bracket, steel");
+        part.children.insert(uuid::Uuid::new_v4());
+        let data = serde_json::to_value(vec![&part]).unwrap();
+        let csv = String::from_utf8(parts_csv(&data).unwrap()).unwrap();
+        let mut lines = csv.lines();
+        let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(&header[..2], &["id", "name"]);
+        let row = lines.next().unwrap();
+        assert!(row.starts_with(&format!("{},\"bracket, steel\"", part.id)));
+        assert!(row.contains(&part.children.iter().next().unwrap().to_string()));
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn msgpack_round_trip() {
+        let mut part = Part::new("bracket");
+        part.children.insert(uuid::Uuid::new_v4());
+        let response = Response::new()
+            .result(200, "Fetched all parts successfully")
+            .data(vec![part.clone()]);
+        let decoded: Response = from_msgpack(&to_msgpack(&response).unwrap()).unwrap();
+        assert_eq!(decoded.data, Some(vec![part.clone()]));
+
+        // replies are encoded from their JSON form, keeping ids as strings
+        let value = serde_json::to_value(&response).unwrap();
+        let decoded: Response = from_msgpack(&to_msgpack(&value).unwrap()).unwrap();
+        assert_eq!(decoded.data, Some(vec![part]));
+    }
+}
@@ -1,27 +1,371 @@
+use std::borrow::Borrow;
 use std::cmp::Ordering;
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::{From, TryFrom};
+use std::io::{Read, Write};
+use std::iter::FromIterator;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
+use tracing::{field, instrument, Span};
 use uuid::Uuid;
 
+use crate::cost::{self, CostRecord};
+use crate::cow_map::{self, CowMap, Values};
+use crate::perf;
+use crate::release::Lifecycle;
+use crate::routing::{self, RoutingStep};
+use crate::search::SearchIndex;
+use crate::supplier::{self, Supplier};
+use crate::variants::{self, OptionGroups};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Part {
     pub id: Uuid,
+    // fields other than the id may be left out of responses by a sparse fieldset
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub parents: HashSet<Uuid>,
+    #[serde(default)]
     pub children: HashSet<Uuid>,
+    /// Creation time in milliseconds since the UNIX epoch
+    #[serde(default)]
+    pub created: u64,
+    /// Incremented each time the part's relationships change, so clients can
+    /// detect edits made since they last read it
+    #[serde(default)]
+    pub version: u64,
+    /// Identifiers of the part in other systems, such as an ERP material
+    /// number, keyed by the name of the system
+    #[serde(default)]
+    pub external_ids: BTreeMap<String, String>,
+    /// Quantity of the part in stock
+    #[serde(default)]
+    pub on_hand: u64,
+    /// Where the part is stored, such as a shelf or bin, empty when unknown
+    #[serde(default)]
+    pub location: String,
+    /// Whether the part is a phantom subassembly, which groups parts in the
+    /// structure of an assembly but is never built or stocked by itself
+    #[serde(default)]
+    pub phantom: bool,
+    /// Option groups the part can be configured with, such as `color`
+    #[serde(default)]
+    pub options: OptionGroups,
+    /// Option values each restricted child is only included for, keyed by
+    /// the id of the child
+    #[serde(default)]
+    pub child_options: BTreeMap<Uuid, OptionGroups>,
+    /// Relationship to each child, keyed by the id of the child
+    #[serde(default)]
+    pub relationships: BTreeMap<Uuid, Relationship>,
+    /// Category the part is assigned to, if any
+    #[serde(default)]
+    pub category: Option<Uuid>,
+    /// Custom attributes of the part, such as `voltage` or `package`, checked
+    /// against the schemas of its category
+    #[serde(default)]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    /// Days it takes to procure the part, or to build it once its children
+    /// are available
+    #[serde(default)]
+    pub lead_time_days: u64,
+    /// Operations done in order to build the part, empty unless it is an
+    /// assembly built in house
+    #[serde(default)]
+    pub routing: Vec<RoutingStep>,
+    /// Markdown work instructions for building the part, changed along with
+    /// its version like the rest of the part
+    #[serde(default)]
+    pub instructions: String,
+    /// Cost to buy or build one of the part
+    #[serde(default)]
+    pub unit_cost: f64,
+    /// Unit costs the part was given, oldest first
+    #[serde(default)]
+    pub cost_history: Vec<CostRecord>,
+    /// Sources the part can be bought from, in order of preference
+    #[serde(default)]
+    pub suppliers: Vec<Supplier>,
+    /// Currency the unit cost is given in, the base currency of the server
+    /// when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Stage of its life the part is in, only moved to `released` by approving
+    /// a release of it
+    #[serde(default)]
+    pub lifecycle: Lifecycle,
 }
 
 impl Part {
     pub fn new(name: &str) -> Part {
+        Part::with_id(Uuid::new_v4(), name)
+    }
+
+    /// Create a part with an id chosen by the caller rather than a random one,
+    /// such as one mirrored from another system
+    pub fn with_id(id: Uuid, name: &str) -> Part {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
         Part {
-            id: Uuid::new_v3(&Uuid::NAMESPACE_URL, name.as_bytes()),
+            id,
             name: String::from(name),
             parents: HashSet::new(),
             children: HashSet::new(),
+            created,
+            version: 1,
+            external_ids: BTreeMap::new(),
+            on_hand: 0,
+            location: String::new(),
+            phantom: false,
+            options: OptionGroups::new(),
+            child_options: BTreeMap::new(),
+            relationships: BTreeMap::new(),
+            category: None,
+            attributes: BTreeMap::new(),
+            lead_time_days: 0,
+            routing: Vec::new(),
+            instructions: String::new(),
+            unit_cost: 0.0,
+            cost_history: Vec::new(),
+            suppliers: Vec::new(),
+            currency: None,
+            lifecycle: Lifecycle::default(),
+        }
+    }
+
+    /// Quantity of a child used by the part, which is 1 unless set on their
+    /// relationship
+    pub fn quantity_of(&self, child: &Uuid) -> u64 {
+        self.relationships
+            .get(child)
+            .map_or(1, |relationship| relationship.quantity)
+    }
+}
+
+/// Link between a parent and one of its children, carrying how many of the
+/// child the parent uses
+///
+/// The id of a relationship is derived from the ids of its parent and child,
+/// so a link keeps its id wherever the parts are stored or restored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Relationship {
+    pub id: Uuid,
+    pub parent: Uuid,
+    pub child: Uuid,
+    /// Number of the child used by the parent
+    #[serde(default = "Relationship::default_quantity")]
+    pub quantity: u64,
+    /// Free text about the use of the child, such as reference designators
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl Relationship {
+    pub fn new(parent: &Uuid, child: &Uuid) -> Relationship {
+        Relationship {
+            id: Relationship::id_for(parent, child),
+            parent: *parent,
+            child: *child,
+            quantity: Relationship::default_quantity(),
+            notes: String::new(),
+        }
+    }
+
+    /// Id of the relationship between `parent` and `child`, being a version 3
+    /// UUID naming the child in the namespace of the parent
+    pub fn id_for(parent: &Uuid, child: &Uuid) -> Uuid {
+        Uuid::new_v3(parent, child.as_bytes())
+    }
+
+    fn default_quantity() -> u64 {
+        1
+    }
+}
+
+/// Changes to the metadata of a relationship, leaving those not given as they
+/// are
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RelationshipEdit {
+    /// Number of the child used by the parent, at least 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+impl RelationshipEdit {
+    /// Edit restoring the metadata of `relationship` onto a new link
+    pub fn copy_of(relationship: &Relationship) -> RelationshipEdit {
+        RelationshipEdit {
+            quantity: Some(relationship.quantity),
+            notes: Some(relationship.notes.clone()),
+        }
+    }
+
+    /// Whether the edit leaves a new relationship as it is
+    pub fn is_default(&self) -> bool {
+        self.quantity.unwrap_or(1) == 1 && self.notes.as_deref().unwrap_or("").is_empty()
+    }
+
+    /// Make the changes to `relationship`, rejecting a quantity of 0
+    pub fn apply(&self, relationship: &mut Relationship) -> Result<(), PartsListError> {
+        if self.quantity == Some(0) {
+            return Err(PartsListError::InvalidQuantity {
+                id: relationship.id,
+            });
+        }
+        if let Some(quantity) = self.quantity {
+            relationship.quantity = quantity;
+        }
+        if let Some(notes) = &self.notes {
+            relationship.notes = notes.clone();
+        }
+        Ok(())
+    }
+}
+
+/// Changes to the attributes of a part, leaving those not given as they are
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PartEdit {
+    /// New name for the part, checked against the names of the other parts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Identifiers replacing all of the part's external ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_ids: Option<BTreeMap<String, String>>,
+    /// Quantity of the part in stock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_hand: Option<u64>,
+    /// Where the part is stored, such as a shelf or bin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Whether the part is a phantom subassembly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phantom: Option<bool>,
+    /// Option groups replacing all of the part's option groups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<OptionGroups>,
+    /// Restrictions replacing all of the options the part's children are
+    /// included for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child_options: Option<BTreeMap<Uuid, OptionGroups>>,
+    /// Category to assign the part to, or `null` to unassign it
+    #[serde(
+        default,
+        deserialize_with = "nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub category: Option<Option<Uuid>>,
+    /// Custom attributes replacing all of the part's attributes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<BTreeMap<String, serde_json::Value>>,
+    /// Days it takes to procure or build the part
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lead_time_days: Option<u64>,
+    /// Operations replacing all of the steps of the part's routing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<Vec<RoutingStep>>,
+    /// Markdown work instructions replacing those of the part
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    /// Cost to buy or build one of the part, recorded in its cost history when
+    /// it changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_cost: Option<f64>,
+    /// Suppliers replacing those of the part, in order of preference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suppliers: Option<Vec<Supplier>>,
+    /// Currency the unit cost is given in, or `null` for the base currency
+    #[serde(
+        default,
+        deserialize_with = "nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub currency: Option<Option<String>>,
+    /// Time the unit cost was given, in milliseconds since the UNIX epoch,
+    /// set by `stamped` so the edit records the same time whenever applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_time: Option<u64>,
+    /// Lifecycle state replacing that of the part, only set by the release
+    /// workflow as clients can't edit it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifecycle: Option<Lifecycle>,
+}
+
+/// Deserialize a field which can be left out, set to `null` or given a value
+/// into `None`, `Some(None)` or `Some(Some(value))`
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl PartEdit {
+    /// Stamp the edit with the time it was received, replacing any time it
+    /// was sent with
+    pub fn stamped(mut self) -> PartEdit {
+        self.cost_time = self.unit_cost.map(|_| cost::now());
+        self
+    }
+
+    /// Make the changes to `part`, bumping its version
+    pub fn apply(&self, part: &mut Part) {
+        if let Some(name) = &self.name {
+            part.name = name.clone();
+        }
+        if let Some(external_ids) = &self.external_ids {
+            part.external_ids = external_ids.clone();
+        }
+        if let Some(on_hand) = self.on_hand {
+            part.on_hand = on_hand;
+        }
+        if let Some(location) = &self.location {
+            part.location = location.clone();
+        }
+        if let Some(phantom) = self.phantom {
+            part.phantom = phantom;
+        }
+        if let Some(options) = &self.options {
+            part.options = options.clone();
+        }
+        if let Some(child_options) = &self.child_options {
+            part.child_options = child_options.clone();
+        }
+        if let Some(category) = self.category {
+            part.category = category;
+        }
+        if let Some(attributes) = &self.attributes {
+            part.attributes = attributes.clone();
         }
+        if let Some(lead_time_days) = self.lead_time_days {
+            part.lead_time_days = lead_time_days;
+        }
+        if let Some(routing) = &self.routing {
+            part.routing = routing.clone();
+        }
+        if let Some(instructions) = &self.instructions {
+            part.instructions = instructions.clone();
+        }
+        if let Some(unit_cost) = self.unit_cost {
+            let time = self.cost_time.unwrap_or_else(cost::now);
+            cost::record(part, unit_cost, time);
+        }
+        if let Some(suppliers) = &self.suppliers {
+            part.suppliers = suppliers.clone();
+        }
+        if let Some(currency) = &self.currency {
+            part.currency = currency.clone();
+        }
+        if let Some(lifecycle) = self.lifecycle {
+            part.lifecycle = lifecycle;
+        }
+        part.version += 1;
     }
 }
 
@@ -32,6 +376,25 @@ impl Clone for Part {
             name: self.name.clone(),
             parents: self.parents.clone(),
             children: self.children.clone(),
+            created: self.created,
+            version: self.version,
+            external_ids: self.external_ids.clone(),
+            on_hand: self.on_hand,
+            location: self.location.clone(),
+            phantom: self.phantom,
+            options: self.options.clone(),
+            child_options: self.child_options.clone(),
+            relationships: self.relationships.clone(),
+            category: self.category,
+            attributes: self.attributes.clone(),
+            lead_time_days: self.lead_time_days,
+            routing: self.routing.clone(),
+            instructions: self.instructions.clone(),
+            unit_cost: self.unit_cost,
+            cost_history: self.cost_history.clone(),
+            suppliers: self.suppliers.clone(),
+            currency: self.currency.clone(),
+            lifecycle: self.lifecycle,
         }
     }
 }
@@ -56,6 +419,107 @@ impl PartialEq for Part {
 
 impl Eq for Part {}
 
+/// Field of a part which can be selected for sparse responses
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartField {
+    Id,
+    Name,
+    Parents,
+    Children,
+    Created,
+    Version,
+    ExternalIds,
+    OnHand,
+    Location,
+    Phantom,
+    Options,
+    ChildOptions,
+    Relationships,
+    Category,
+    Attributes,
+    LeadTimeDays,
+    Routing,
+    Instructions,
+    UnitCost,
+    CostHistory,
+    Suppliers,
+    Currency,
+    Lifecycle,
+}
+
+impl PartField {
+    /// Parse a comma separated list of field names
+    pub fn parse_list(s: &str) -> Result<Vec<PartField>, PartsListError> {
+        s.split(',')
+            .map(|field| PartField::try_from(field.trim()))
+            .collect()
+    }
+}
+
+impl TryFrom<&str> for PartField {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "id" => Ok(PartField::Id),
+            "name" => Ok(PartField::Name),
+            "parents" => Ok(PartField::Parents),
+            "children" => Ok(PartField::Children),
+            "created" => Ok(PartField::Created),
+            "version" => Ok(PartField::Version),
+            "external_ids" => Ok(PartField::ExternalIds),
+            "on_hand" => Ok(PartField::OnHand),
+            "location" => Ok(PartField::Location),
+            "phantom" => Ok(PartField::Phantom),
+            "options" => Ok(PartField::Options),
+            "child_options" => Ok(PartField::ChildOptions),
+            "relationships" => Ok(PartField::Relationships),
+            "category" => Ok(PartField::Category),
+            "attributes" => Ok(PartField::Attributes),
+            "lead_time_days" => Ok(PartField::LeadTimeDays),
+            "routing" => Ok(PartField::Routing),
+            "instructions" => Ok(PartField::Instructions),
+            "unit_cost" => Ok(PartField::UnitCost),
+            "cost_history" => Ok(PartField::CostHistory),
+            "suppliers" => Ok(PartField::Suppliers),
+            "currency" => Ok(PartField::Currency),
+            "lifecycle" => Ok(PartField::Lifecycle),
+            _ => Err(PartsListError::InvalidFieldString { s: s.into() }),
+        }
+    }
+}
+
+impl From<PartField> for &str {
+    fn from(f: PartField) -> &'static str {
+        match f {
+            PartField::Id => "id",
+            PartField::Name => "name",
+            PartField::Parents => "parents",
+            PartField::Children => "children",
+            PartField::Created => "created",
+            PartField::Version => "version",
+            PartField::ExternalIds => "external_ids",
+            PartField::OnHand => "on_hand",
+            PartField::Location => "location",
+            PartField::Phantom => "phantom",
+            PartField::Options => "options",
+            PartField::ChildOptions => "child_options",
+            PartField::Relationships => "relationships",
+            PartField::Category => "category",
+            PartField::Attributes => "attributes",
+            PartField::LeadTimeDays => "lead_time_days",
+            PartField::Routing => "routing",
+            PartField::Instructions => "instructions",
+            PartField::UnitCost => "unit_cost",
+            PartField::CostHistory => "cost_history",
+            PartField::Suppliers => "suppliers",
+            PartField::Currency => "currency",
+            PartField::Lifecycle => "lifecycle",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum PartsListFilter {
     All,
     TopLevel,
@@ -65,6 +529,19 @@ pub enum PartsListFilter {
     Orphan,
 }
 
+impl PartsListFilter {
+    pub fn matches(&self, part: &Part) -> bool {
+        match self {
+            PartsListFilter::All => true,
+            PartsListFilter::TopLevel => part.parents.is_empty(),
+            PartsListFilter::Assembly => !part.children.is_empty(),
+            PartsListFilter::Component => !part.parents.is_empty() && part.children.is_empty(),
+            PartsListFilter::Subassembly => !part.parents.is_empty() && !part.children.is_empty(),
+            PartsListFilter::Orphan => part.parents.is_empty() && part.children.is_empty(),
+        }
+    }
+}
+
 impl TryFrom<&str> for PartsListFilter {
     type Error = PartsListError;
 
@@ -101,13 +578,51 @@ pub enum PartsListError {
     #[error("Part does not exist (id: {id:?})")]
     PartDoesNotExist { id: Uuid },
 
+    /// Error occuring when attempting to retrieve a part by a name no part has
+    #[error("No part named {name:?}")]
+    PartNameDoesNotExist { name: String },
+
     /// Error occuring when attempting to add a part that already exists
-    #[error("Hash collision, part already exists (name: {name:?}, id: {id:?})")]
+    #[error("Part already exists (name: {name:?}, id: {id:?})")]
     PartExists { name: String, id: Uuid },
 
-    /// Error occuring when attempting to add a child who is already a parent of the part
-    #[error("Cycle detected, part has child in its parental line (parent: {parent:?}, child: {child:?})")]
-    AddChildCyclicalRelative { parent: Uuid, child: Uuid },
+    /// Error occuring when attempting to add a part with the name of another part
+    #[error("Part name already in use (name: {name:?}, id: {id:?})")]
+    PartNameExists { name: String, id: Uuid },
+
+    /// Error occuring when attempting to retrieve a part by an external id no part has
+    #[error("No part with external id {external_id:?} in {system:?}")]
+    ExternalIdDoesNotExist { system: String, external_id: String },
+
+    /// Error occuring when matching a part by a name several parts have
+    #[error("Several parts are named {name:?}")]
+    AmbiguousName { name: String },
+
+    /// Error occuring when matching a part by an external id it wasn't given
+    #[error("Part has no external id in {system:?}")]
+    MissingExternalId { system: String },
+
+    /// Error occuring when a part matched by its external id has another name
+    #[error("Part matched by external id is named differently (id: {id:?}, name: {name:?})")]
+    MatchedPartNameDiffers { id: Uuid, name: String },
+
+    /// Error occuring when giving a part an external id which identifies another part
+    #[error("External id {external_id:?} in {system:?} already in use (id: {id:?})")]
+    ExternalIdInUse {
+        system: String,
+        external_id: String,
+        id: Uuid,
+    },
+
+    /// Error occuring when attempting to add a child who is already a parent of the part,
+    /// along with the path of parts from the child down to the parent, each a
+    /// parent of the next, which the new link would close into a cycle
+    #[error("Cycle detected, part has child in its parental line (parent: {parent:?}, child: {child:?}, path: {path:?})")]
+    AddChildCyclicalRelative {
+        parent: Uuid,
+        child: Uuid,
+        path: Vec<Uuid>,
+    },
 
     /// Failure to parse string into valid PartsListFilter
     #[error("Invalid string: {s:?}, unable to convert into PartsListFilter")]
@@ -121,11 +636,641 @@ pub enum PartsListError {
     #[error("Invalid filter operation {s:?} for {s:?}, unable to execute")]
     InvalidFilterChoice { s: String, f: String },
 
+    /// Failure to parse string into valid PartsListSortKey or SortOrder
+    #[error("Invalid string: {s:?}, unable to convert into sort option")]
+    InvalidSortString { s: String },
+
+    /// Failure to parse key and value into valid PartsListPredicate
+    #[error("Invalid predicate: {s:?}, unable to convert into PartsListPredicate")]
+    InvalidPredicateString { s: String },
+
+    /// Failure to parse string into valid PartsListCursor
+    #[error("Invalid cursor: {s:?}, unable to convert into PartsListCursor")]
+    InvalidCursorString { s: String },
+
+    /// Failure to parse string into valid DuplicateNamePolicy
+    #[error("Invalid string: {s:?}, unable to convert into DuplicateNamePolicy")]
+    InvalidNamePolicyString { s: String },
+
+    /// Failure to parse string into valid PartField
+    #[error("Invalid string: {s:?}, unable to convert into PartField")]
+    InvalidFieldString { s: String },
+
+    /// Failure to parse string into valid EcoStatus
+    #[error("Invalid string: {s:?}, unable to convert into EcoStatus")]
+    InvalidEcoStatusString { s: String },
+
+    /// Failure to parse string into valid ReleaseStatus
+    #[error("Invalid string: {s:?}, unable to convert into ReleaseStatus")]
+    InvalidReleaseStatusString { s: String },
+
+    /// Failure to parse string into valid Lifecycle
+    #[error("Invalid string: {s:?}, unable to convert into Lifecycle")]
+    InvalidLifecycleString { s: String },
+
+    /// Failure to parse string into valid SupplierPolicy
+    #[error("Invalid string: {s:?}, unable to convert into SupplierPolicy")]
+    InvalidSupplierPolicyString { s: String },
+
+    /// Error occuring when a supplier of a part can't be used
+    #[error("Invalid supplier {name:?} of part (id: {id:?}): {reason}")]
+    InvalidSupplier {
+        id: Uuid,
+        name: String,
+        reason: String,
+    },
+
+    /// Error occuring when a part is given a cost which can't be used
+    #[error("Invalid unit cost {cost} of part (id: {id:?}), costs must be positive or zero")]
+    InvalidUnitCost { id: Uuid, cost: f64 },
+
+    /// Error occuring when a cost is given in a currency which isn't an ISO
+    /// 4217 code
+    #[error("Invalid currency {currency:?}, currencies must be three letter codes like \"USD\"")]
+    InvalidCurrency { currency: String },
+
+    /// Error occuring when converting a cost from or into a currency without an
+    /// exchange rate
+    #[error("No exchange rate is configured for currency {currency:?}")]
+    UnknownCurrency { currency: String },
+
+    /// Error occuring when an exchange rate isn't a positive number
+    #[error("Invalid exchange rate of currency {currency:?}, rates must be positive")]
+    InvalidExchangeRate { currency: String },
+
+    /// Error occuring when a step of the routing of a part can't be used
+    #[error("Invalid routing step {position} of part (id: {id:?}): {reason}")]
+    InvalidRoutingStep {
+        id: Uuid,
+        position: usize,
+        reason: String,
+    },
+
+    /// Failure to parse string into valid LabelFormat or ImageFormat
+    #[error("Invalid string: {s:?}, unable to convert into label format")]
+    InvalidLabelString { s: String },
+
+    /// Error occuring when the contents of a label can't be encoded as a barcode
+    #[error("Couldn't encode label: {s}")]
+    LabelEncoding { s: String },
+
+    /// Error occuring when a part is stored under an id other than its own
+    #[error("Part stored under mismatched id (key: {key:?}, id: {id:?})")]
+    MismatchedId { key: Uuid, id: Uuid },
+
+    /// Error occuring when a part references a parent or child that does not exist
+    #[error("Part references missing relative (id: {id:?}, relative: {relative:?})")]
+    MissingRelative { id: Uuid, relative: Uuid },
+
+    /// Error occuring when loaded parts contain themselves, along with the path
+    /// of parts making the cycle, each a parent of the next
+    #[error("Parts contain themselves (path: {path:?})")]
+    PartCycle { path: Vec<Uuid> },
+
+    /// Error occuring when a part was changed since the client last read it
+    #[error("Part has changed (id: {id:?}, expected version: {expected}, version: {actual})")]
+    VersionConflict {
+        id: Uuid,
+        expected: u64,
+        actual: u64,
+    },
+
+    /// Error occuring when removing a child from a part which doesn't contain it
+    #[error("Part is not a child of the parent (parent: {parent:?}, child: {child:?})")]
+    NotAChild { parent: Uuid, child: Uuid },
+
+    /// Failure to parse string into valid Configuration
+    #[error("Invalid config: {s:?}, expected comma separated group:value choices")]
+    InvalidConfigString { s: String },
+
+    /// Error occuring when referring to an option group a part doesn't offer
+    #[error("Part has no option group {group:?} (id: {id:?})")]
+    UnknownOptionGroup { id: Uuid, group: String },
+
+    /// Error occuring when referring to a value an option group doesn't offer
+    #[error("Option group {group:?} has no value {value:?} (id: {id:?})")]
+    UnknownOptionValue {
+        id: Uuid,
+        group: String,
+        value: String,
+    },
+
+    /// Error occuring when resolving a configured assembly without choosing a
+    /// value for one of its option groups
+    #[error("Option group {group:?} is not configured (id: {id:?})")]
+    OptionNotConfigured { id: Uuid, group: String },
+
+    /// Error occuring when attempting to retrieve non-existant engineering change order
+    #[error("ECO does not exist (id: {id:?})")]
+    EcoDoesNotExist { id: Uuid },
+
+    /// Error occuring when an engineering change order isn't at the stage of review
+    /// an operation requires
+    #[error("ECO is {status} and can't be {action} (id: {id:?})")]
+    EcoStatusMismatch {
+        id: Uuid,
+        status: String,
+        action: String,
+    },
+
+    /// Error occuring when an engineering change order changes a part outside of its
+    /// assembly
+    #[error("Part is not in the assembly changed by the ECO (id: {id:?}, assembly: {assembly:?})")]
+    PartOutsideAssembly { id: Uuid, assembly: Uuid },
+
+    /// Error occuring when attempting to retrieve non-existant release
+    #[error("Release does not exist (id: {id:?})")]
+    ReleaseDoesNotExist { id: Uuid },
+
+    /// Error occuring when approving or rejecting a release which has already been
+    /// released or rejected
+    #[error("Release is {status} and can't be {action} (id: {id:?})")]
+    ReleaseStatusMismatch {
+        id: Uuid,
+        status: String,
+        action: String,
+    },
+
+    /// Error occuring when submitting an assembly for release while an earlier
+    /// release of it is still in review
+    #[error("Assembly is already in review for release (id: {id:?}, assembly: {assembly:?})")]
+    ReleaseInReview { id: Uuid, assembly: Uuid },
+
+    /// Error occuring when approving or rejecting a release on behalf of a role
+    /// it doesn't require
+    #[error("Role {role:?} doesn't approve the release (id: {id:?})")]
+    ReleaseRoleNotRequired { id: Uuid, role: String },
+
+    /// Error occuring when a role approves a release it has already approved
+    #[error("Role {role:?} has already approved the release (id: {id:?})")]
+    ReleaseRoleApproved { id: Uuid, role: String },
+
+    /// Error occuring when a release is approved or rejected without the token of
+    /// one of the release roles
+    #[error("Releases are only decided with the token of a release role as bearer token")]
+    NotReleaseApprover,
+
+    /// Error occuring when an edit sent by a client sets the lifecycle state of
+    /// a part, which only the release workflow moves
+    #[error("The lifecycle of a part is only changed by approving a release of it (id: {id:?})")]
+    LifecycleNotEditable { id: Uuid },
+
+    /// Error occuring when attempting to retrieve non-existant category
+    #[error("Category does not exist (id: {id:?})")]
+    CategoryDoesNotExist { id: Uuid },
+
+    /// Error occuring when giving a category the name of another category with the
+    /// same parent
+    #[error("Category name already in use (name: {name:?}, id: {id:?})")]
+    CategoryNameExists { name: String, id: Uuid },
+
+    /// Error occuring when moving a category below itself or one of its subcategories
+    #[error("Category can't be its own ancestor (id: {id:?}, parent: {parent:?})")]
+    CategoryCycle { id: Uuid, parent: Uuid },
+
+    /// Error occuring when an attribute schema allows values which aren't of its type
+    #[error("Attribute schema allows values of the wrong type (name: {name:?})")]
+    InvalidAttributeSchema { name: String },
+
+    /// Error occuring when deleting a part still used by assemblies in strict mode
+    #[error("Part is still used by {} assemblies (id: {id:?})", parents.len())]
+    PartInUse { id: Uuid, parents: Vec<Uuid> },
+
+    /// Error occuring when deleting a category which still has subcategories or parts
+    #[error("Category still has subcategories or parts (id: {id:?})")]
+    CategoryInUse { id: Uuid },
+
+    /// Error occuring when attempting to retrieve non-existant attachment
+    #[error("Attachment does not exist (id: {id:?})")]
+    AttachmentDoesNotExist { id: Uuid },
+
+    /// Error occuring when an upload is larger than the server accepts
+    #[error("Upload is larger than the limit of {limit} bytes")]
+    UploadTooLarge { limit: u64 },
+
+    /// Error occuring when an API key, or requests without one, have used up one of
+    /// the limits of their quota
+    #[error("Daily quota of {limit} {what} is used up until midnight UTC")]
+    QuotaExceeded { limit: u64, what: String },
+
+    /// Error occuring when an admin route is requested without the admin token of the server
+    #[error("Admin routes require the admin_token of the server as the bearer token")]
+    NotAdmin,
+
+    /// Error occuring when attempting to retrieve the quota of an API key without one
+    #[error("No quota is set for the API key")]
+    QuotaDoesNotExist,
+
+    /// Error occuring when reading the change feed of a server whose storage doesn't
+    /// journal its changes
+    #[error("The change feed requires the wal storage backend")]
+    ChangesUnavailable,
+
+    /// Error occuring when reading the change feed from a cursor later than the
+    /// latest change
+    #[error("Cursor is past the latest change (cursor: {cursor})")]
+    InvalidCursor { cursor: u64 },
+
+    /// Error occuring when asking for the replication status of a server which
+    /// doesn't follow a leader
+    #[error("The server isn't replicating from a leader")]
+    NotReplicating,
+
+    /// Error occuring when a follower was written to other than by replication, or
+    /// a change of its leader doesn't apply to its parts
+    #[error("Follower no longer matches its leader at change {cursor}: {s}")]
+    ReplicationConflict { cursor: u64, s: String },
+
+    /// Error occuring when reading the change feed from a cursor older than the
+    /// changes still journaled
+    #[error("Changes after {cursor} are no longer journaled, copy the parts afresh (horizon: {horizon})")]
+    ChangesExpired { cursor: u64, horizon: u64 },
+
+    /// Failure to serialize or deserialize a parts list
+    #[error("Parts list serialization failed: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    /// Error occuring when loading a backup written in an unknown format
+    #[error("Unsupported backup version {version}")]
+    UnsupportedBackupVersion { version: u32 },
+
+    /// Error occuring when restoring a part without a tombstone, either because it
+    /// was never deleted or its retention window has passed
+    #[error("Part is not deleted or can no longer be restored (id: {id:?})")]
+    NotDeleted { id: Uuid },
+
+    /// Error occuring when a seed file can't be read or holds inconsistent parts
+    #[error("Invalid seed file {path}: {s}")]
+    InvalidSeed { path: String, s: String },
+
+    /// Error occuring when attempting to retrieve a relationship between parts
+    /// which aren't linked
+    #[error("Relationship does not exist (id: {id:?})")]
+    RelationshipDoesNotExist { id: Uuid },
+
+    /// Error occuring when a relationship is given a quantity of 0
+    #[error("Relationship quantity must be at least 1 (id: {id:?})")]
+    InvalidQuantity { id: Uuid },
+
+    /// Error occuring when an entry of a write-ahead log fails when replayed,
+    /// which would leave the parts different from when it was written
+    #[error("Write-ahead log entry {seq} can't be replayed: {s}")]
+    WalReplay { seq: u64, s: String },
+
+    /// Failure reported by the storage backend holding the parts list
+    #[error("Storage backend error: {s}")]
+    Storage { s: String },
+
+    /// Error occuring when the server is configured with settings it can't
+    /// start with, such as a missing TLS key
+    #[error("Invalid configuration: {s}")]
+    Config { s: String },
+
     /// Unknown error related to parts list
     #[error("unknown parts list error")]
     Unknown,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartsListSortKey {
+    Name,
+    Created,
+    ChildrenCount,
+}
+
+impl TryFrom<&str> for PartsListSortKey {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "name" => Ok(PartsListSortKey::Name),
+            "created" => Ok(PartsListSortKey::Created),
+            "children_count" => Ok(PartsListSortKey::ChildrenCount),
+            _ => Err(PartsListError::InvalidSortString { s: s.into() }),
+        }
+    }
+}
+
+impl From<PartsListSortKey> for &str {
+    fn from(k: PartsListSortKey) -> &'static str {
+        match k {
+            PartsListSortKey::Name => "name",
+            PartsListSortKey::Created => "created",
+            PartsListSortKey::ChildrenCount => "children_count",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl TryFrom<&str> for SortOrder {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "" => Ok(SortOrder::Asc),
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(PartsListError::InvalidSortString { s: s.into() }),
+        }
+    }
+}
+
+impl From<SortOrder> for &str {
+    fn from(o: SortOrder) -> &'static str {
+        match o {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+/// Ordering applied to listed parts, with ties broken by part id so the
+/// order is stable between requests
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PartsListSort {
+    pub key: PartsListSortKey,
+    pub order: SortOrder,
+}
+
+impl PartsListSort {
+    pub fn new(key: PartsListSortKey, order: SortOrder) -> PartsListSort {
+        PartsListSort { key, order }
+    }
+
+    pub fn sort<T: Borrow<Part>>(&self, parts: &mut [T]) {
+        parts.sort_by(|a, b| {
+            let (a, b) = (a.borrow(), b.borrow());
+            let ordering = match self.key {
+                PartsListSortKey::Name => a.name.cmp(&b.name),
+                PartsListSortKey::Created => a.created.cmp(&b.created),
+                PartsListSortKey::ChildrenCount => a.children.len().cmp(&b.children.len()),
+            }
+            .then_with(|| a.id.cmp(&b.id));
+            match self.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+    }
+}
+
+/// Condition on a single part which listed parts must satisfy
+#[derive(Clone, Debug, PartialEq)]
+pub enum PartsListPredicate {
+    NameContains(String),
+    MinChildren(usize),
+    MaxChildren(usize),
+    HasParent(Uuid),
+    HasChild(Uuid),
+    /// Part is assigned to one of the categories
+    InCategory(Vec<Uuid>),
+    /// Part has the attribute with the value, compared as text
+    Attribute(String, String),
+}
+
+/// Text an attribute value is compared as by predicates, being a string
+/// without quotes or the JSON of any other value
+pub fn attribute_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+impl PartsListPredicate {
+    /// Parse a predicate from its query parameter key and value
+    pub fn parse(key: &str, value: &str) -> Result<PartsListPredicate, PartsListError> {
+        let invalid = || PartsListError::InvalidPredicateString {
+            s: format!("{}={}", key, value),
+        };
+        match key {
+            "name_contains" => Ok(PartsListPredicate::NameContains(value.into())),
+            "min_children" => value
+                .parse()
+                .map(PartsListPredicate::MinChildren)
+                .map_err(|_| invalid()),
+            "max_children" => value
+                .parse()
+                .map(PartsListPredicate::MaxChildren)
+                .map_err(|_| invalid()),
+            "has_parent" => value
+                .parse()
+                .map(PartsListPredicate::HasParent)
+                .map_err(|_| invalid()),
+            "has_child" => value
+                .parse()
+                .map(PartsListPredicate::HasChild)
+                .map_err(|_| invalid()),
+            "category" => value
+                .split(',')
+                .map(|id| id.trim().parse())
+                .collect::<Result<_, _>>()
+                .map(PartsListPredicate::InCategory)
+                .map_err(|_| invalid()),
+            _ => match key.strip_prefix("attr.") {
+                Some(name) if !name.is_empty() => {
+                    Ok(PartsListPredicate::Attribute(name.into(), value.into()))
+                }
+                _ => Err(invalid()),
+            },
+        }
+    }
+
+    /// Query parameter key of the predicate
+    pub fn key(&self) -> String {
+        match self {
+            PartsListPredicate::NameContains(_) => "name_contains".into(),
+            PartsListPredicate::MinChildren(_) => "min_children".into(),
+            PartsListPredicate::MaxChildren(_) => "max_children".into(),
+            PartsListPredicate::HasParent(_) => "has_parent".into(),
+            PartsListPredicate::HasChild(_) => "has_child".into(),
+            PartsListPredicate::InCategory(_) => "category".into(),
+            PartsListPredicate::Attribute(name, _) => format!("attr.{}", name),
+        }
+    }
+
+    /// Query parameter value of the predicate
+    pub fn value(&self) -> String {
+        match self {
+            PartsListPredicate::NameContains(s) => s.clone(),
+            PartsListPredicate::MinChildren(n) | PartsListPredicate::MaxChildren(n) => {
+                n.to_string()
+            }
+            PartsListPredicate::HasParent(id) | PartsListPredicate::HasChild(id) => id.to_string(),
+            PartsListPredicate::InCategory(ids) => ids
+                .iter()
+                .map(Uuid::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            PartsListPredicate::Attribute(_, value) => value.clone(),
+        }
+    }
+
+    pub fn matches(&self, part: &Part) -> bool {
+        match self {
+            PartsListPredicate::NameContains(s) => part.name.contains(s.as_str()),
+            PartsListPredicate::MinChildren(n) => part.children.len() >= *n,
+            PartsListPredicate::MaxChildren(n) => part.children.len() <= *n,
+            PartsListPredicate::HasParent(id) => part.parents.contains(id),
+            PartsListPredicate::HasChild(id) => part.children.contains(id),
+            PartsListPredicate::InCategory(ids) => part
+                .category
+                .is_some_and(|category| ids.contains(&category)),
+            PartsListPredicate::Attribute(name, value) => part
+                .attributes
+                .get(name)
+                .is_some_and(|attribute| attribute_text(attribute) == *value),
+        }
+    }
+}
+
+/// Filter combined with any number of predicates, all of which a listed part
+/// must satisfy
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartsListQuery {
+    pub filter: PartsListFilter,
+    pub predicates: Vec<PartsListPredicate>,
+}
+
+impl PartsListQuery {
+    pub fn new(filter: PartsListFilter) -> PartsListQuery {
+        PartsListQuery {
+            filter,
+            predicates: Vec::new(),
+        }
+    }
+
+    /// Add a predicate which parts must also satisfy
+    pub fn and(mut self, predicate: PartsListPredicate) -> PartsListQuery {
+        self.predicates.push(predicate);
+        self
+    }
+
+    /// Check the predicates against a part, the filter is applied by the list
+    pub fn matches(&self, part: &Part) -> bool {
+        self.predicates
+            .iter()
+            .all(|predicate| predicate.matches(part))
+    }
+}
+
+/// Opaque position in a listing of parts ordered by id, marking the last part
+/// already returned
+///
+/// Since the position is a part id rather than an offset, iterating with a
+/// cursor neither skips nor repeats parts when others are added or removed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PartsListCursor(Uuid);
+
+impl PartsListCursor {
+    /// Order `parts` by id and return at most `limit` of those following the
+    /// cursor, along with the cursor for the next page when more parts remain
+    pub fn paginate<T: Borrow<Part>>(
+        cursor: Option<PartsListCursor>,
+        limit: usize,
+        mut parts: Vec<T>,
+    ) -> (Vec<T>, Option<PartsListCursor>) {
+        if let Some(PartsListCursor(after)) = cursor {
+            parts.retain(|part| part.borrow().id > after);
+        }
+        parts.sort_by_key(|part| part.borrow().id);
+        let next = if parts.len() > limit {
+            parts.truncate(limit);
+            parts.last().map(|part| PartsListCursor(part.borrow().id))
+        } else {
+            None
+        };
+        (parts, next)
+    }
+}
+
+impl TryFrom<&str> for PartsListCursor {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Uuid::parse_str(s)
+            .map(PartsListCursor)
+            .map_err(|_| PartsListError::InvalidCursorString { s: s.into() })
+    }
+}
+
+impl From<PartsListCursor> for String {
+    fn from(c: PartsListCursor) -> String {
+        c.0.to_simple().to_string()
+    }
+}
+
+/// Handling of a new part given the name of an existing part
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DuplicateNamePolicy {
+    /// Fail with `PartNameExists`
+    #[default]
+    Reject,
+    /// Add the part, so several parts share the name
+    Allow,
+    /// Add the part with the first free name suffixed by a number, like "bolt (2)"
+    Suffix,
+}
+
+impl DuplicateNamePolicy {
+    /// Choose the name for a new part, where `taken` gives the id of an existing
+    /// part using a name
+    pub fn resolve<F>(&self, name: &str, mut taken: F) -> Result<String, PartsListError>
+    where
+        F: FnMut(&str) -> Result<Option<Uuid>, PartsListError>,
+    {
+        let existing = match taken(name)? {
+            Some(existing) => existing,
+            None => return Ok(name.into()),
+        };
+        match self {
+            DuplicateNamePolicy::Reject => Err(PartsListError::PartNameExists {
+                name: name.into(),
+                id: existing,
+            }),
+            DuplicateNamePolicy::Allow => Ok(name.into()),
+            DuplicateNamePolicy::Suffix => {
+                for n in 2.. {
+                    let suffixed = format!("{} ({})", name, n);
+                    if taken(&suffixed)?.is_none() {
+                        return Ok(suffixed);
+                    }
+                }
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl TryFrom<&str> for DuplicateNamePolicy {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "reject" => Ok(DuplicateNamePolicy::Reject),
+            "allow" => Ok(DuplicateNamePolicy::Allow),
+            "suffix" => Ok(DuplicateNamePolicy::Suffix),
+            _ => Err(PartsListError::InvalidNamePolicyString { s: s.into() }),
+        }
+    }
+}
+
+impl From<DuplicateNamePolicy> for &str {
+    fn from(p: DuplicateNamePolicy) -> &'static str {
+        match p {
+            DuplicateNamePolicy::Reject => "reject",
+            DuplicateNamePolicy::Allow => "allow",
+            DuplicateNamePolicy::Suffix => "suffix",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub enum PartsListUpdate {
     Add,
     Remove,
@@ -156,134 +1301,956 @@ impl From<PartsListUpdate> for &str {
     }
 }
 
-#[derive(Serialize)]
-pub struct PartsList(HashMap<Uuid, Part>);
+/// Summary statistics describing the shape of a parts list
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PartsListStats {
+    pub total: usize,
+    pub top_level: usize,
+    pub component: usize,
+    pub subassembly: usize,
+    pub orphan: usize,
+    /// Number of levels below the top of the deepest assembly
+    pub max_depth: usize,
+    /// Average number of direct children of the parts that have children
+    pub average_fan_out: f64,
+}
 
-impl PartsList {
-    pub fn new() -> PartsList {
-        PartsList(HashMap::new())
-    }
+/// Size and depth of the subtree below a part
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PartSummary {
+    pub id: Uuid,
+    /// Number of direct children
+    pub children: usize,
+    /// Number of distinct parts below the part at any level
+    pub descendants: usize,
+    /// Number of levels of children below the part
+    pub depth: usize,
+}
 
-    pub fn get(&self, id: &Uuid) -> Result<&Part, PartsListError> {
-        if let Some(part) = self.0.get(id) {
-            Ok(part)
-        } else {
-            Err(PartsListError::PartDoesNotExist { id: *id })
+/// Broken invariant found when validating the relationships between parts
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Violation {
+    /// `parent` lists `child` as a child, but `child` doesn't list `parent`
+    MissingParentBacklink { parent: Uuid, child: Uuid },
+    /// `child` lists `parent` as a parent, but `parent` doesn't list `child`
+    MissingChildBacklink { parent: Uuid, child: Uuid },
+    /// `id` refers to a relative which doesn't exist
+    DanglingReference { id: Uuid, relative: Uuid },
+    /// Parts which contain themselves, listed in parent to child order
+    Cycle { path: Vec<Uuid> },
+    /// Parts sharing a name, which should be unique
+    DuplicateName { name: String, ids: Vec<Uuid> },
+}
+
+/// Result of validating a set of parts, listing every violation found
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub checked: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    /// Check the relationships between `parts` for missing backlinks,
+    /// references to parts which aren't present and cycles
+    pub fn check<'a, I: IntoIterator<Item = &'a Part>>(parts: I) -> ValidationReport {
+        let parts: HashMap<Uuid, &Part> = parts.into_iter().map(|part| (part.id, part)).collect();
+        let mut ids: Vec<&Uuid> = parts.keys().collect();
+        ids.sort();
+        let mut report = ValidationReport {
+            checked: parts.len(),
+            violations: Vec::new(),
+        };
+        for id in &ids {
+            let part = parts[*id];
+            let mut children: Vec<&Uuid> = part.children.iter().collect();
+            children.sort();
+            for child in children {
+                match parts.get(child) {
+                    Some(child) if !child.parents.contains(id) => {
+                        report.violations.push(Violation::MissingParentBacklink {
+                            parent: part.id,
+                            child: child.id,
+                        })
+                    }
+                    Some(_) => (),
+                    None => report.violations.push(Violation::DanglingReference {
+                        id: part.id,
+                        relative: *child,
+                    }),
+                }
+            }
+            let mut parents: Vec<&Uuid> = part.parents.iter().collect();
+            parents.sort();
+            for parent in parents {
+                match parts.get(parent) {
+                    Some(parent) if !parent.children.contains(id) => {
+                        report.violations.push(Violation::MissingChildBacklink {
+                            parent: parent.id,
+                            child: part.id,
+                        })
+                    }
+                    Some(_) => (),
+                    None => report.violations.push(Violation::DanglingReference {
+                        id: part.id,
+                        relative: *parent,
+                    }),
+                }
+            }
         }
+        let mut finished = HashSet::new();
+        for id in ids {
+            let mut path = Vec::new();
+            ValidationReport::find_cycles(&parts, id, &mut path, &mut finished, &mut report);
+        }
+        report
     }
 
-    pub fn get_mut(&mut self, id: &Uuid) -> Result<&mut Part, PartsListError> {
-        if let Some(part) = self.0.get_mut(id) {
-            Ok(part)
-        } else {
-            Err(PartsListError::PartDoesNotExist { id: *id })
+    /// Also report the parts sharing a name, for parts whose names should be
+    /// unique
+    pub fn check_names<'a, I: IntoIterator<Item = &'a Part>>(
+        mut self,
+        parts: I,
+    ) -> ValidationReport {
+        let mut names: BTreeMap<&str, Vec<Uuid>> = BTreeMap::new();
+        for part in parts {
+            names.entry(&part.name).or_default().push(part.id);
+        }
+        for (name, mut ids) in names {
+            if ids.len() > 1 {
+                ids.sort();
+                self.violations.push(Violation::DuplicateName {
+                    name: name.to_string(),
+                    ids,
+                });
+            }
         }
+        self
     }
 
-    pub fn add(&mut self, new_part: Part) -> Result<&Part, PartsListError> {
-        let id = new_part.id;
-        // Check for part id collision based on name
-        if let Entry::Vacant(entry) = self.0.entry(id) {
-            entry.insert(new_part);
-            if let Some(part) = self.0.get(&id) {
-                Ok(part)
-            } else {
-                Err(PartsListError::Unknown)
+    /// Depth first search along children, reporting a cycle whenever a part
+    /// already on the current path is reached again
+    fn find_cycles(
+        parts: &HashMap<Uuid, &Part>,
+        id: &Uuid,
+        path: &mut Vec<Uuid>,
+        finished: &mut HashSet<Uuid>,
+        report: &mut ValidationReport,
+    ) {
+        if finished.contains(id) {
+            return;
+        }
+        if let Some(start) = path.iter().position(|x| x == id) {
+            report.violations.push(Violation::Cycle {
+                path: path[start..].to_vec(),
+            });
+            return;
+        }
+        if let Some(part) = parts.get(id) {
+            path.push(*id);
+            let mut children: Vec<&Uuid> = part.children.iter().collect();
+            children.sort();
+            for child in children {
+                ValidationReport::find_cycles(parts, child, path, finished, report);
             }
-        } else {
-            Err(PartsListError::PartExists {
-                name: new_part.name,
-                id: new_part.id,
-            })
+            path.pop();
         }
+        finished.insert(*id);
     }
 
-    pub fn delete(&mut self, id: &Uuid) -> Result<(), PartsListError> {
-        // Make sure part exists
-        if let Some(part) = self.0.remove(id) {
-            // Remove part from all parents and children
-            for parent in part.parents {
-                self.get_mut(&parent).unwrap().children.remove(id);
-            }
-            for child in part.children {
-                self.get_mut(&child).unwrap().parents.remove(id);
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Transitive closure of the parent/child relationships, kept up to date as
+/// links change so ancestry checks are lookups instead of graph walks
+#[derive(Debug, Default, Clone)]
+struct Closure {
+    ancestors: CowMap<Uuid, HashSet<Uuid>>,
+    descendants: CowMap<Uuid, HashSet<Uuid>>,
+}
+
+impl Closure {
+    fn build(parts: &CowMap<Uuid, Part>) -> Closure {
+        let mut closure = Closure::default();
+        closure.recompute(parts, parts.keys().copied(), parts.keys().copied());
+        closure
+    }
+
+    /// Collect every part reachable from `id` by repeatedly following `next`
+    fn walk(
+        parts: &CowMap<Uuid, Part>,
+        id: &Uuid,
+        next: fn(&Part) -> &HashSet<Uuid>,
+    ) -> HashSet<Uuid> {
+        let mut found = HashSet::new();
+        let mut stack = vec![*id];
+        while let Some(id) = stack.pop() {
+            if let Some(part) = parts.get(&id) {
+                for relative in next(part) {
+                    if found.insert(*relative) {
+                        stack.push(*relative);
+                    }
+                }
             }
-            // Finally remove actual part
-            Ok(())
-        } else {
-            Err(PartsListError::PartDoesNotExist { id: *id })
         }
+        found
     }
 
-    fn recurse_parts_list<'a, T, V>(
-        &'a self,
-        id: &Uuid,
-        accumulate: &mut HashMap<&'a Uuid, &'a Part>,
-        next_set: &T,
-        test: &V,
-    ) -> Result<(), PartsListError>
+    /// Recompute the descendants of the parts in `above` and the ancestors of
+    /// the parts in `below` from the relationships in `parts`
+    fn recompute<A, B>(&mut self, parts: &CowMap<Uuid, Part>, above: A, below: B)
     where
-        T: Fn(&Part) -> Vec<&Uuid>,
-        V: Fn(&Part) -> bool,
+        A: IntoIterator<Item = Uuid>,
+        B: IntoIterator<Item = Uuid>,
     {
-        let part = self.get(id)?;
-        for child in next_set(part) {
-            let child = self.get(child)?;
-            if test(child) {
-                accumulate.insert(&child.id, child);
-            }
-            self.recurse_parts_list(&child.id, accumulate, next_set, test)?;
+        for id in above {
+            let descendants = Closure::walk(parts, &id, |part| &part.children);
+            self.descendants.insert(id, descendants);
+        }
+        for id in below {
+            let ancestors = Closure::walk(parts, &id, |part| &part.parents);
+            self.ancestors.insert(id, ancestors);
         }
-        Ok(())
     }
 
-    fn recurse_match(
-        &self,
-        next_set: fn(&Part) -> Vec<&Uuid>,
-        parts: Vec<&Uuid>,
-        candidate: &Uuid,
-    ) -> Result<bool, PartsListError> {
-        for part in parts {
-            // found a match
-            if part == candidate {
-                return Ok(true);
-            }
-            let part = self.get(part)?;
-            // recurse for further matches
-            if self.recurse_match(next_set, next_set(&part), candidate)? {
-                return Ok(true);
-            }
+    fn ancestors(&self, id: &Uuid) -> impl Iterator<Item = &Uuid> {
+        self.ancestors.get(id).into_iter().flatten()
+    }
+
+    fn descendants(&self, id: &Uuid) -> impl Iterator<Item = &Uuid> {
+        self.descendants.get(id).into_iter().flatten()
+    }
+
+    /// Parts whose closures change when a link between `parent` and `child` is
+    /// added or removed, being the parent with its ancestors and the child with
+    /// its descendants
+    fn affected(&self, parent: &Uuid, child: &Uuid) -> (HashSet<Uuid>, HashSet<Uuid>) {
+        let mut above: HashSet<Uuid> = self.ancestors(parent).copied().collect();
+        above.insert(*parent);
+        let mut below: HashSet<Uuid> = self.descendants(child).copied().collect();
+        below.insert(*child);
+        (above, below)
+    }
+
+    fn is_ancestor(&self, part: &Uuid, candidate: &Uuid) -> bool {
+        match self.ancestors.get(part) {
+            Some(ancestors) => ancestors.contains(candidate),
+            None => false,
         }
-        // no matches found after exhausting the list
-        Ok(false)
     }
 
-    fn get_part_children(part: &Part) -> Vec<&Uuid> {
-        part.children.iter().collect()
+    fn link(&mut self, parent: &Uuid, child: &Uuid) {
+        let (above, below) = self.affected(parent, child);
+        for id in &above {
+            self.descendants.get_or_default(*id).extend(&below);
+        }
+        for id in &below {
+            self.ancestors.get_or_default(*id).extend(&above);
+        }
     }
 
-    fn get_part_parents(part: &Part) -> Vec<&Uuid> {
-        part.parents.iter().collect()
+    /// Update the closure after the link between `parent` and `child` was
+    /// removed from `parts`
+    fn unlink(&mut self, parts: &CowMap<Uuid, Part>, parent: &Uuid, child: &Uuid) {
+        let (above, below) = self.affected(parent, child);
+        self.recompute(parts, above, below);
     }
 
-    fn is_ancestor(&self, part: &Uuid, candidate: &Uuid) -> Result<bool, PartsListError> {
-        self.recurse_match(
-            PartsList::get_part_parents,
-            PartsList::get_part_parents(self.get(part)?),
-            candidate,
-        )
+    /// Update the closure after the part `id` was removed from `parts`
+    fn remove(&mut self, parts: &CowMap<Uuid, Part>, id: &Uuid) {
+        let above = self.ancestors.remove(id).unwrap_or_default();
+        let below = self.descendants.remove(id).unwrap_or_default();
+        self.recompute(parts, above, below);
     }
+}
 
-    pub fn get_children(
+/// Parts keyed by id, along with the closure of their relationships and the
+/// indexes finding parts by name, external id, relationship and attribute
+///
+/// Every map is copied on write, so a clone is cheap and only copies what
+/// either list changes afterwards.
+#[derive(Debug, Clone)]
+pub struct PartsList {
+    parts: CowMap<Uuid, Part>,
+    closure: Closure,
+    /// Ids of the parts with each name
+    name_index: CowMap<String, HashSet<Uuid>>,
+    name_policy: DuplicateNamePolicy,
+    /// Id of the part known by each external system and id
+    external_index: CowMap<(String, String), Uuid>,
+    /// Parent and child of each relationship by its id
+    relationships: CowMap<Uuid, (Uuid, Uuid)>,
+    /// Ids of the parts with each attribute name and value
+    attribute_index: CowMap<(String, String), HashSet<Uuid>>,
+    /// Terms of the names and attribute values of the parts
+    search_index: SearchIndex,
+}
+
+impl PartsList {
+    pub fn new() -> PartsList {
+        PartsList {
+            parts: CowMap::new(),
+            closure: Closure::default(),
+            name_index: CowMap::new(),
+            name_policy: DuplicateNamePolicy::default(),
+            external_index: CowMap::new(),
+            relationships: CowMap::new(),
+            attribute_index: CowMap::new(),
+            search_index: SearchIndex::new(),
+        }
+    }
+
+    fn with_parts(mut parts: HashMap<Uuid, Part>) -> PartsList {
+        let mut names: CowMap<String, HashSet<Uuid>> = CowMap::new();
+        let mut external_ids = CowMap::new();
+        let mut relationships = CowMap::new();
+        let mut attributes: CowMap<(String, String), HashSet<Uuid>> = CowMap::new();
+        for part in parts.values_mut() {
+            names.get_or_default(part.name.clone()).insert(part.id);
+            for (name, value) in &part.attributes {
+                attributes
+                    .get_or_default((name.clone(), attribute_text(value)))
+                    .insert(part.id);
+            }
+            for (system, external_id) in &part.external_ids {
+                external_ids.insert((system.clone(), external_id.clone()), part.id);
+            }
+            // parts stored before relationships were kept link with defaults
+            let (id, children) = (part.id, &part.children);
+            part.relationships
+                .retain(|child, _| children.contains(child));
+            for child in children {
+                let relationship = part
+                    .relationships
+                    .entry(*child)
+                    .or_insert_with(|| Relationship::new(&id, child));
+                relationships.insert(relationship.id, (id, *child));
+            }
+        }
+        let parts: CowMap<Uuid, Part> = parts.into_iter().collect();
+        let closure = Closure::build(&parts);
+        let search_index = parts.values().collect();
+        PartsList {
+            parts,
+            closure,
+            name_index: names,
+            name_policy: DuplicateNamePolicy::default(),
+            external_index: external_ids,
+            relationships,
+            attribute_index: attributes,
+            search_index,
+        }
+    }
+
+    /// Set how parts added with the name of an existing part are handled
+    pub fn set_name_policy(&mut self, policy: DuplicateNamePolicy) {
+        self.name_policy = policy;
+    }
+
+    /// How parts added with the name of an existing part are handled
+    pub fn name_policy(&self) -> DuplicateNamePolicy {
+        self.name_policy
+    }
+
+    /// Add a part to the indexes by name, external id, relationship id,
+    /// attribute and search term
+    fn index(&mut self, part: &Part) {
+        self.search_index.insert(part);
+        self.name_index
+            .get_or_default(part.name.clone())
+            .insert(part.id);
+        for (system, external_id) in &part.external_ids {
+            self.external_index
+                .insert((system.clone(), external_id.clone()), part.id);
+        }
+        for relationship in part.relationships.values() {
+            self.relationships
+                .insert(relationship.id, (relationship.parent, relationship.child));
+        }
+        for (name, value) in &part.attributes {
+            self.attribute_index
+                .get_or_default((name.clone(), attribute_text(value)))
+                .insert(part.id);
+        }
+    }
+
+    /// Remove a part from the indexes by name, external id, relationship id,
+    /// attribute and search term
+    fn unindex(&mut self, part: &Part) {
+        self.search_index.remove(part);
+        if let Some(ids) = self.name_index.get_mut(&part.name) {
+            ids.remove(&part.id);
+            if ids.is_empty() {
+                self.name_index.remove(&part.name);
+            }
+        }
+        for (system, external_id) in &part.external_ids {
+            self.external_index
+                .remove(&(system.clone(), external_id.clone()));
+        }
+        for relationship in part.relationships.values() {
+            self.relationships.remove(&relationship.id);
+        }
+        for (name, value) in &part.attributes {
+            let key = (name.clone(), attribute_text(value));
+            if let Some(ids) = self.attribute_index.get_mut(&key) {
+                ids.remove(&part.id);
+                if ids.is_empty() {
+                    self.attribute_index.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Fail if any of `external_ids` already identifies a part other than `id`
+    fn check_external_ids(
+        &self,
+        id: &Uuid,
+        external_ids: &BTreeMap<String, String>,
+    ) -> Result<(), PartsListError> {
+        for (system, external_id) in external_ids {
+            match self
+                .external_index
+                .get(&(system.clone(), external_id.clone()))
+            {
+                Some(existing) if existing != id => {
+                    return Err(PartsListError::ExternalIdInUse {
+                        system: system.clone(),
+                        external_id: external_id.clone(),
+                        id: *existing,
+                    })
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Build a parts list from previously stored parts, checking that every referenced
+    /// relative exists and no part contains itself, and restoring any parent/child
+    /// links missing their counterpart
+    fn from_parts(mut parts: HashMap<Uuid, Part>) -> Result<PartsList, PartsListError> {
+        for (key, part) in &parts {
+            if *key != part.id {
+                return Err(PartsListError::MismatchedId {
+                    key: *key,
+                    id: part.id,
+                });
+            }
+            for relative in part.parents.iter().chain(part.children.iter()) {
+                if !parts.contains_key(relative) {
+                    return Err(PartsListError::MissingRelative {
+                        id: part.id,
+                        relative: *relative,
+                    });
+                }
+            }
+        }
+        PartsList::restore_links(&mut parts);
+        if let Some(path) = PartsList::find_cycle(&parts) {
+            return Err(PartsListError::PartCycle { path });
+        }
+        Ok(PartsList::with_parts(parts))
+    }
+
+    /// Find a part containing itself, giving the path from it down to the part
+    /// listing it as a child, each a parent of the next
+    ///
+    /// The search keeps its own stack rather than recursing, so deep
+    /// assemblies can't overflow the stack.
+    fn find_cycle(parts: &HashMap<Uuid, Part>) -> Option<Vec<Uuid>> {
+        let children = |id: &Uuid| -> Vec<Uuid> {
+            parts
+                .get(id)
+                .map_or_else(Vec::new, |part| part.children.iter().copied().collect())
+        };
+        let mut finished = HashSet::new();
+        for start in parts.keys() {
+            if finished.contains(start) {
+                continue;
+            }
+            let mut path = vec![(*start, children(start))];
+            let mut on_path: HashSet<Uuid> = vec![*start].into_iter().collect();
+            while let Some((id, unvisited)) = path.last_mut() {
+                let id = *id;
+                match unvisited.pop() {
+                    Some(child) if on_path.contains(&child) => {
+                        let begin = path.iter().position(|(id, _)| *id == child)?;
+                        return Some(path[begin..].iter().map(|(id, _)| *id).collect());
+                    }
+                    Some(child) if !finished.contains(&child) => {
+                        on_path.insert(child);
+                        path.push((child, children(&child)));
+                    }
+                    Some(_) => (),
+                    None => {
+                        on_path.remove(&id);
+                        finished.insert(id);
+                        path.pop();
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Make every parent/child link symmetric, so a child listed by a parent also
+    /// lists that parent and vice versa
+    fn restore_links(parts: &mut HashMap<Uuid, Part>) {
+        let mut links = Vec::new();
+        for part in parts.values() {
+            links.extend(part.children.iter().map(|child| (part.id, *child)));
+            links.extend(part.parents.iter().map(|parent| (*parent, part.id)));
+        }
+        for (parent, child) in links {
+            if let Some(part) = parts.get_mut(&parent) {
+                part.children.insert(child);
+            }
+            if let Some(part) = parts.get_mut(&child) {
+                part.parents.insert(parent);
+            }
+        }
+    }
+
+    /// Iterate over all parts in the list in arbitrary order
+    pub fn iter(&self) -> Values<'_, Uuid, Part> {
+        self.parts.values()
+    }
+
+    /// Number of parts in the list
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// Parts changed in this list since it was cloned from `before`, along
+    /// with the ids of the parts removed since
+    ///
+    /// Only the entries the change copied are compared, so finding the
+    /// changes costs no more than making them.
+    pub fn changes_since(&self, before: &PartsList) -> (Vec<&Part>, Vec<Uuid>) {
+        let mut seen = HashSet::new();
+        let mut changed = Vec::new();
+        let mut removed = Vec::new();
+        for id in self.parts.keys_not_shared(&before.parts) {
+            if !seen.insert(*id) {
+                continue;
+            }
+            match (before.parts.get(id), self.parts.get(id)) {
+                // every change to a part bumps its version
+                (Some(old), Some(part)) if old.version == part.version => (),
+                (_, Some(part)) => changed.push(part),
+                (Some(_), None) => removed.push(*id),
+                (None, None) => (),
+            }
+        }
+        (changed, removed)
+    }
+
+    /// Take parts changed elsewhere as they are, such as by another server
+    /// sharing the same store, and drop the parts removed there
+    ///
+    /// Both sides of every link changed must be among the `changed` parts, as
+    /// they are when taken from the same change.
+    pub fn load_changes(&mut self, changed: Vec<Part>, removed: &[Uuid]) {
+        // the closures of the relatives of the parts before and after the
+        // change are recomputed
+        let mut above: HashSet<Uuid> = HashSet::new();
+        let mut below: HashSet<Uuid> = HashSet::new();
+        for id in changed.iter().map(|part| &part.id).chain(removed) {
+            above.insert(*id);
+            above.extend(self.closure.ancestors(id));
+            below.insert(*id);
+            below.extend(self.closure.descendants(id));
+        }
+        for id in removed {
+            if let Some(old) = self.parts.remove(id) {
+                self.unindex(&old);
+            }
+            self.closure.ancestors.remove(id);
+            self.closure.descendants.remove(id);
+            above.remove(id);
+            below.remove(id);
+        }
+        let ids: Vec<Uuid> = changed.iter().map(|part| part.id).collect();
+        for part in changed {
+            if let Some(old) = self.parts.remove(&part.id) {
+                self.unindex(&old);
+            }
+            self.index(&part);
+            self.parts.insert(part.id, part);
+        }
+        for id in &ids {
+            above.extend(Closure::walk(&self.parts, id, |part| &part.parents));
+            below.extend(Closure::walk(&self.parts, id, |part| &part.children));
+        }
+        self.closure.recompute(&self.parts, above, below);
+    }
+
+    /// Load a parts list from JSON previously written by `to_writer`
+    pub fn from_reader<R: Read>(reader: R) -> Result<PartsList, PartsListError> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Save the parts list as JSON which can be loaded again with `from_reader`
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<(), PartsListError> {
+        Ok(serde_json::to_writer(writer, self)?)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<&Part, PartsListError> {
+        if let Some(part) = self.parts.get(id) {
+            Ok(part)
+        } else {
+            Err(PartsListError::PartDoesNotExist { id: *id })
+        }
+    }
+
+    /// Look up the parts with a name, of which there is at most one unless
+    /// duplicate names are allowed
+    pub fn get_by_name(&self, name: &str) -> Vec<&Part> {
+        let mut parts: Vec<&Part> = self
+            .name_index
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.parts.get(id))
+            .collect();
+        parts.sort_by_key(|part| part.id);
+        parts
+    }
+
+    /// Look up the part known by `external_id` in an external `system`
+    pub fn get_by_external(
+        &self,
+        system: &str,
+        external_id: &str,
+    ) -> Result<&Part, PartsListError> {
+        self.external_index
+            .get(&(system.into(), external_id.into()))
+            .and_then(|id| self.parts.get(id))
+            .ok_or_else(|| PartsListError::ExternalIdDoesNotExist {
+                system: system.into(),
+                external_id: external_id.into(),
+            })
+    }
+
+    fn get_mut(&mut self, id: &Uuid) -> Result<&mut Part, PartsListError> {
+        if let Some(part) = self.parts.get_mut(id) {
+            Ok(part)
+        } else {
+            Err(PartsListError::PartDoesNotExist { id: *id })
+        }
+    }
+
+    /// Add a part, handling a name already in use according to the duplicate
+    /// name policy
+    pub fn add(&mut self, mut new_part: Part) -> Result<&Part, PartsListError> {
+        let id = new_part.id;
+        if self.parts.contains_key(&id) {
+            return Err(PartsListError::PartExists {
+                name: new_part.name,
+                id,
+            });
+        }
+        self.check_external_ids(&id, &new_part.external_ids)?;
+        let names = &self.name_index;
+        new_part.name = self.name_policy.resolve(&new_part.name, |name| {
+            Ok(names.get(name).and_then(|ids| ids.iter().next()).copied())
+        })?;
+        let children = &new_part.children;
+        new_part
+            .relationships
+            .retain(|child, relationship| children.contains(child) && relationship.parent == id);
+        self.index(&new_part);
+        self.closure.ancestors.insert(id, HashSet::new());
+        self.closure.descendants.insert(id, HashSet::new());
+        self.parts.insert(id, new_part);
+        self.get(&id)
+    }
+
+    /// Change the attributes of a part, returning the edited part
+    pub fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<&Part, PartsListError> {
+        let old = self.get(id)?.clone();
+        if let Some(external_ids) = &edit.external_ids {
+            self.check_external_ids(id, external_ids)?;
+        }
+        let mut part = old.clone();
+        edit.apply(&mut part);
+        if part.name != old.name {
+            let names = &self.name_index;
+            part.name = self.name_policy.resolve(&part.name, |name| {
+                Ok(names
+                    .get(name)
+                    .and_then(|ids| ids.iter().find(|other| *other != id))
+                    .copied())
+            })?;
+        }
+        variants::check(&part)?;
+        routing::check(&part)?;
+        cost::check(&part)?;
+        supplier::check(&part)?;
+        self.unindex(&old);
+        self.index(&part);
+        let stored = self.get_mut(id)?;
+        *stored = part;
+        Ok(stored)
+    }
+
+    /// Look up the relationship between a parent and child by its id
+    pub fn get_relationship(&self, id: &Uuid) -> Result<&Relationship, PartsListError> {
+        self.relationships
+            .get(id)
+            .and_then(|(parent, child)| self.parts.get(parent)?.relationships.get(child))
+            .ok_or(PartsListError::RelationshipDoesNotExist { id: *id })
+    }
+
+    /// Change the metadata of a relationship, returning the edited relationship
+    ///
+    /// The relationship belongs to its parent, so the parent's version is
+    /// bumped.
+    pub fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<&Relationship, PartsListError> {
+        let (parent, child) = match self.relationships.get(id) {
+            Some(link) => *link,
+            None => return Err(PartsListError::RelationshipDoesNotExist { id: *id }),
+        };
+        let mut relationship = self.get_relationship(id)?.clone();
+        edit.apply(&mut relationship)?;
+        let parent = self.get_mut(&parent)?;
+        parent.version += 1;
+        Ok(parent
+            .relationships
+            .entry(child)
+            .and_modify(|stored| *stored = relationship.clone())
+            .or_insert(relationship))
+    }
+
+    pub fn delete(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+        // Make sure part exists
+        if let Some(part) = self.parts.remove(id) {
+            self.unindex(&part);
+            // Remove part from all parents and children
+            for parent in part.parents {
+                let parent = self.get_mut(&parent).unwrap();
+                parent.children.remove(id);
+                let relationship = parent.relationships.remove(id);
+                parent.version += 1;
+                if let Some(relationship) = relationship {
+                    self.relationships.remove(&relationship.id);
+                }
+            }
+            for child in part.children {
+                let child = self.get_mut(&child).unwrap();
+                child.parents.remove(id);
+                child.version += 1;
+            }
+            self.closure.remove(&self.parts, id);
+            // Finally remove actual part
+            Ok(())
+        } else {
+            Err(PartsListError::PartDoesNotExist { id: *id })
+        }
+    }
+
+    /// Find the parts removed by a cascading delete of `id`, which includes the part
+    /// itself and any descendants that are not used outside of the deleted subtree
+    #[instrument(level = "debug", skip(self))]
+    pub fn delete_tree_candidates(&self, id: &Uuid) -> Result<Vec<&Part>, PartsListError> {
+        let mut removed: HashSet<&Uuid> = HashSet::new();
+        removed.insert(&self.get(id)?.id);
+        let mut descendants = self.get_children(id, PartsListFilter::All)?;
+        // Keep claiming descendants whose parents are all being removed until none remain
+        loop {
+            let (claimed, remaining): (Vec<&Part>, Vec<&Part>) = descendants
+                .into_iter()
+                .partition(|part| part.parents.iter().all(|parent| removed.contains(parent)));
+            if claimed.is_empty() {
+                break;
+            }
+            removed.extend(claimed.iter().map(|part| &part.id));
+            descendants = remaining;
+        }
+        removed.into_iter().map(|id| self.get(id)).collect()
+    }
+
+    /// Delete a part along with every descendant not used outside of its subtree,
+    /// returning the removed parts
+    pub fn delete_tree(&mut self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        let removed: Vec<Part> = self
+            .delete_tree_candidates(id)?
+            .into_iter()
+            .cloned()
+            .collect();
+        for part in &removed {
+            self.delete(&part.id)?;
+        }
+        Ok(removed)
+    }
+
+    /// Delete every part with neither parents nor children, returning the
+    /// removed parts
+    pub fn delete_orphans(&mut self) -> Vec<Part> {
+        let orphans: Vec<Uuid> = self
+            .list(PartsListFilter::Orphan)
+            .iter()
+            .map(|part| part.id)
+            .collect();
+        orphans
+            .iter()
+            .filter_map(|id| {
+                let part = self.parts.remove(id)?;
+                self.closure.remove(&self.parts, id);
+                self.unindex(&part);
+                Some(part)
+            })
+            .collect()
+    }
+
+    /// Check that every relationship has a matching backlink, refers to parts in
+    /// the list and that no part contains itself, and that no two parts share a
+    /// name unless duplicate names are allowed
+    pub fn validate(&self) -> ValidationReport {
+        let report = ValidationReport::check(self.iter());
+        match self.name_policy {
+            DuplicateNamePolicy::Allow => report,
+            _ => report.check_names(self.iter()),
+        }
+    }
+
+    /// Number of levels of children below a part, memoized in `depths`
+    fn depth(&self, id: &Uuid, depths: &mut HashMap<Uuid, usize>) -> usize {
+        if let Some(depth) = depths.get(id) {
+            return *depth;
+        }
+        let depth = match self.parts.get(id) {
+            Some(part) => part
+                .children
+                .iter()
+                .map(|child| self.depth(child, depths) + 1)
+                .max()
+                .unwrap_or(0),
+            None => 0,
+        };
+        depths.insert(*id, depth);
+        depth
+    }
+
+    /// Aggregate part counts per filter category along with the depth and
+    /// fan-out of the assemblies in the list
+    pub fn stats(&self) -> PartsListStats {
+        let mut depths = HashMap::new();
+        let max_depth = self
+            .list(PartsListFilter::TopLevel)
+            .iter()
+            .map(|part| self.depth(&part.id, &mut depths))
+            .max()
+            .unwrap_or(0);
+        let assemblies = self.list(PartsListFilter::Assembly);
+        let links: usize = assemblies.iter().map(|part| part.children.len()).sum();
+        let average_fan_out = if assemblies.is_empty() {
+            0.0
+        } else {
+            links as f64 / assemblies.len() as f64
+        };
+        PartsListStats {
+            total: self.len(),
+            top_level: self.list(PartsListFilter::TopLevel).len(),
+            component: self.list(PartsListFilter::Component).len(),
+            subassembly: self.list(PartsListFilter::Subassembly).len(),
+            orphan: self.list(PartsListFilter::Orphan).len(),
+            max_depth,
+            average_fan_out,
+        }
+    }
+
+    /// Rank the components by the number of distinct assemblies containing
+    /// them directly or indirectly, most used first and then by name, keeping
+    /// the first `limit`
+    pub fn top_used(&self, limit: usize) -> Vec<(&Part, usize)> {
+        let mut ranked: Vec<(&Part, usize)> = self
+            .list(PartsListFilter::Component)
+            .into_iter()
+            .map(|part| (part, self.closure.ancestors(&part.id).count()))
+            .collect();
+        ranked.sort_by(|(a, a_used), (b, b_used)| {
+            b_used
+                .cmp(a_used)
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        ranked.truncate(limit);
+        ranked
+    }
+
+    fn is_ancestor(&self, part: &Uuid, candidate: &Uuid) -> Result<bool, PartsListError> {
+        self.get(part)?;
+        Ok(self.closure.is_ancestor(part, candidate))
+    }
+
+    /// Find the shortest path of parts from `child` down to `parent`, each a
+    /// parent of the next, which linking `child` below `parent` would close
+    /// into a cycle, being empty when there is none
+    pub fn cycle_path(&self, parent: &Uuid, child: &Uuid) -> Vec<Uuid> {
+        let mut previous: HashMap<Uuid, Uuid> = HashMap::new();
+        let mut level = vec![*child];
+        while !level.is_empty() && !level.contains(parent) {
+            let mut next = Vec::new();
+            for id in &level {
+                let part = match self.get(id) {
+                    Ok(part) => part,
+                    Err(_) => continue,
+                };
+                for below in &part.children {
+                    if below != child && !previous.contains_key(below) {
+                        previous.insert(*below, *id);
+                        next.push(*below);
+                    }
+                }
+            }
+            level = next;
+        }
+        if !level.contains(parent) {
+            return Vec::new();
+        }
+        let mut path = vec![*parent];
+        let mut current = *parent;
+        while let Some(above) = previous.get(&current) {
+            path.push(*above);
+            current = *above;
+        }
+        path.reverse();
+        path
+    }
+
+    fn get_parts<'a, I>(&'a self, ids: I) -> Result<Vec<&'a Part>, PartsListError>
+    where
+        I: Iterator<Item = &'a Uuid>,
+    {
+        ids.map(|id| self.get(id)).collect()
+    }
+
+    /// Find the descendants of a part matching `filter`
+    ///
+    /// The `Assembly` filter finds the ancestors of the part rather than its
+    /// descendants, and is only kept for compatibility with `get_ancestors`.
+    #[instrument(level = "debug", skip(self), fields(nodes = field::Empty))]
+    pub fn get_children(
         &self,
         id: &Uuid,
         filter: PartsListFilter,
     ) -> Result<Vec<&Part>, PartsListError> {
-        match filter {
+        let children = match filter {
             PartsListFilter::All => {
-                let mut acc = HashMap::new();
-                self.recurse_parts_list(id, &mut acc, &PartsList::get_part_children, &|_| true)?;
-                Ok(acc.values().copied().collect())
+                self.get(id)?;
+                self.get_parts(self.closure.descendants(id))
             }
             PartsListFilter::TopLevel => {
                 let children = self
@@ -295,62 +2262,141 @@ impl PartsList {
                 Ok(children)
             }
             PartsListFilter::Component => {
-                let mut acc = HashMap::new();
-                let test = |x: &Part| !x.parents.is_empty() && x.children.is_empty();
-                self.recurse_parts_list(id, &mut acc, &PartsList::get_part_children, &test)?;
-                Ok(acc.values().copied().collect())
+                self.get(id)?;
+                let mut descendants = self.get_parts(self.closure.descendants(id))?;
+                descendants.retain(|x| !x.parents.is_empty() && x.children.is_empty());
+                Ok(descendants)
+            }
+            PartsListFilter::Subassembly => {
+                self.get(id)?;
+                let mut descendants = self.get_parts(self.closure.descendants(id))?;
+                descendants.retain(|x| !x.parents.is_empty() && !x.children.is_empty());
+                Ok(descendants)
             }
             PartsListFilter::Assembly => {
-                let mut acc = HashMap::new();
-                self.recurse_parts_list(id, &mut acc, &PartsList::get_part_parents, &|_| true)?;
-                Ok(acc.values().copied().collect())
+                self.get(id)?;
+                self.get_parts(self.closure.ancestors(id))
             }
             _ => Err(PartsListError::InvalidFilterChoice {
                 s: "get_children".into(),
                 f: String::from(Into::<&str>::into(filter)),
             }),
-        }
+        }?;
+        Span::current().record("nodes", &children.len());
+        perf::record_traversal(children.len());
+        Ok(children)
     }
 
-    fn add_children(&mut self, parent: &Uuid, children: &[&Uuid]) -> Result<(), PartsListError> {
-        // add each child one at a time
+    /// Find the assemblies containing a part, up to `depth` levels above it or
+    /// all of them when no depth is given
+    #[instrument(level = "debug", skip(self), fields(nodes = field::Empty))]
+    pub fn get_ancestors(
+        &self,
+        id: &Uuid,
+        depth: Option<usize>,
+    ) -> Result<Vec<&Part>, PartsListError> {
+        let part = self.get(id)?;
+        let ancestors = match depth {
+            None => self.get_parts(self.closure.ancestors(id))?,
+            Some(depth) => {
+                let mut found: HashSet<Uuid> = HashSet::new();
+                let mut level: Vec<Uuid> = part.parents.iter().copied().collect();
+                for _ in 0..depth {
+                    level.retain(|parent| found.insert(*parent));
+                    level = self
+                        .get_parts(level.iter())?
+                        .iter()
+                        .flat_map(|parent| parent.parents.iter().copied())
+                        .collect();
+                }
+                found
+                    .iter()
+                    .map(|id| self.get(id))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+        Span::current().record("nodes", &ancestors.len());
+        perf::record_traversal(ancestors.len());
+        Ok(ancestors)
+    }
+
+    /// Check that each child exists and can be added to `parent` without
+    /// making a part its own ancestor
+    ///
+    /// Adding children never changes the ancestors of the parent, so checking
+    /// them all before any is added is the same as checking each in turn.
+    fn check_children_added(
+        &self,
+        parent: &Uuid,
+        children: &[&Uuid],
+    ) -> Result<(), PartsListError> {
+        self.get(parent)?;
         for child in children {
-            // can't add itself as a child
-            if parent == *child {
+            self.get(child)?;
+            // can't add itself or a part in its parental line as a child
+            if parent == *child || self.is_ancestor(parent, child)? {
                 return Err(PartsListError::AddChildCyclicalRelative {
                     parent: *parent,
                     child: **child,
+                    path: self.cycle_path(parent, child),
                 });
             }
-            // check child does not have parent in tree
-            if self.is_ancestor(parent, child)? {
-                return Err(PartsListError::AddChildCyclicalRelative {
-                    parent: *parent,
-                    child: **child,
-                });
-            } else {
-                // actually add child and update parents
-                {
-                    let parent_ref = self.get_mut(parent)?;
-                    parent_ref.children.insert(**child);
-                }
-                let child_ref = self.get_mut(child)?;
-                child_ref.parents.insert(*parent);
+        }
+        Ok(())
+    }
+
+    fn add_children(&mut self, parent: &Uuid, children: &[&Uuid]) -> Result<(), PartsListError> {
+        // every child is checked first, so a refused update changes nothing
+        self.check_children_added(parent, children)?;
+        for child in children {
+            let parent_ref = self.get_mut(parent)?;
+            if !parent_ref.children.insert(**child) {
+                continue;
             }
+            let relationship = Relationship::new(parent, child);
+            let id = relationship.id;
+            parent_ref.relationships.insert(**child, relationship);
+            parent_ref.version += 1;
+            self.relationships.insert(id, (*parent, **child));
+            let child_ref = self.get_mut(child)?;
+            child_ref.parents.insert(*parent);
+            child_ref.version += 1;
+            self.closure.link(parent, child);
         }
         Ok(())
     }
 
     fn remove_children(&mut self, parent: &Uuid, children: &[&Uuid]) -> Result<(), PartsListError> {
-        // add each child one at a time
+        // every child is checked first, so a refused update changes nothing
+        let parent_ref = self.get(parent)?;
+        let mut removed: Vec<Uuid> = Vec::with_capacity(children.len());
         for child in children {
+            if removed.contains(child) {
+                continue;
+            }
+            if !parent_ref.children.contains(*child) || !self.get(child)?.parents.contains(parent) {
+                return Err(PartsListError::NotAChild {
+                    parent: *parent,
+                    child: **child,
+                });
+            }
+            removed.push(**child);
+        }
+        for child in &removed {
             let child_ref = self.get_mut(child)?;
-            assert!(child_ref.parents.remove(parent));
+            child_ref.parents.remove(parent);
+            child_ref.version += 1;
             // remove child from parent and update child to remove parent
             {
                 let parent_ref = self.get_mut(parent)?;
-                assert!(parent_ref.children.remove(child));
+                parent_ref.children.remove(child);
+                let relationship = parent_ref.relationships.remove(child);
+                parent_ref.version += 1;
+                if let Some(relationship) = relationship {
+                    self.relationships.remove(&relationship.id);
+                }
             }
+            self.closure.unlink(&self.parts, parent, child);
         }
         Ok(())
     }
@@ -362,73 +2408,425 @@ impl PartsList {
         op: PartsListUpdate,
     ) -> Result<(), PartsListError> {
         match op {
-            PartsListUpdate::Add => self.add_children(&id, children),
-            PartsListUpdate::Remove => self.remove_children(&id, children),
+            PartsListUpdate::Add => self.add_children(id, children),
+            PartsListUpdate::Remove => self.remove_children(id, children),
             PartsListUpdate::Replace => {
-                let part = self.get(&id)?.clone();
-                let old_children = &PartsList::get_part_children(&part);
-                self.remove_children(&id, old_children)?;
-                self.add_children(&id, children)
+                // only the dropped children are unlinked, so the children kept
+                // keep their relationships
+                let dropped: Vec<Uuid> = self
+                    .get(id)?
+                    .children
+                    .iter()
+                    .filter(|child| !children.contains(child))
+                    .copied()
+                    .collect();
+                self.check_children_added(id, children)?;
+                self.remove_children(id, &dropped.iter().collect::<Vec<_>>())?;
+                self.add_children(id, children)
             }
         }
     }
 
-    pub fn list(&self, filter: PartsListFilter) -> Vec<&Part> {
-        match filter {
-            PartsListFilter::All => self.0.values().collect(),
-            PartsListFilter::TopLevel => self.0.values().filter(|x| x.parents.is_empty()).collect(),
-            PartsListFilter::Assembly => {
-                self.0.values().filter(|x| !x.children.is_empty()).collect()
+    /// List parts matching `filter` in the order given by `sort`
+    pub fn list_sorted(&self, filter: PartsListFilter, sort: PartsListSort) -> Vec<&Part> {
+        let mut list = self.list(filter);
+        sort.sort(&mut list);
+        list
+    }
+
+    /// Find the parts whose names or attribute values match the terms of
+    /// `query` along with their scores, most relevant first, tolerating misspelt
+    /// terms when `fuzzy`
+    #[instrument(level = "debug", skip(self))]
+    pub fn search(&self, query: &str, fuzzy: bool) -> Vec<(&Part, f64)> {
+        self.search_index
+            .search(query, fuzzy)
+            .iter()
+            .filter_map(|hit| Some((self.parts.get(&hit.id)?, hit.score)))
+            .collect()
+    }
+
+    /// List parts matching the filter and every predicate of `query`
+    #[instrument(level = "debug", skip(self))]
+    pub fn query(&self, query: &PartsListQuery) -> Vec<&Part> {
+        // attribute predicates are answered by the attribute index, so only the
+        // parts having every listed attribute value are checked
+        let mut candidates: Option<HashSet<Uuid>> = None;
+        for predicate in &query.predicates {
+            if let PartsListPredicate::Attribute(name, value) = predicate {
+                let ids = self.attribute_index.get(&(name.clone(), value.clone()));
+                let ids = ids.into_iter().flatten().copied();
+                candidates = Some(match candidates {
+                    Some(candidates) => ids.filter(|id| candidates.contains(id)).collect(),
+                    None => ids.collect(),
+                });
             }
-            PartsListFilter::Component => self
-                .0
-                .values()
-                .filter(|x| !x.parents.is_empty() && x.children.is_empty())
-                .collect(),
-            PartsListFilter::Subassembly => self
-                .0
-                .values()
-                .filter(|x| !x.parents.is_empty() && !x.children.is_empty())
-                .collect(),
-            PartsListFilter::Orphan => self
-                .0
-                .values()
-                .filter(|x| x.parents.is_empty() && x.children.is_empty())
+        }
+        let mut list = match candidates {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| self.parts.get(id))
+                .filter(|part| query.filter.matches(part))
                 .collect(),
+            None => self.list(query.filter),
+        };
+        list.retain(|part| query.matches(part));
+        list
+    }
+
+    pub fn list(&self, filter: PartsListFilter) -> Vec<&Part> {
+        self.parts
+            .values()
+            .filter(|part| filter.matches(part))
+            .collect()
+    }
+}
+
+/// Owning iterator over the parts of a `PartsList`
+pub struct IntoIter(cow_map::IntoIter<Uuid, Part>);
+
+impl Iterator for IntoIter {
+    type Item = Part;
+
+    fn next(&mut self) -> Option<Part> {
+        self.0.next().map(|(_, part)| part)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl IntoIterator for PartsList {
+    type Item = Part;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter(self.parts.into_iter())
+    }
+}
+
+impl<'a> IntoIterator for &'a PartsList {
+    type Item = &'a Part;
+    type IntoIter = Values<'a, Uuid, Part>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Collect parts into a list, dropping references to parts that were not collected
+/// and restoring any parent/child links missing their counterpart
+impl FromIterator<Part> for PartsList {
+    fn from_iter<I: IntoIterator<Item = Part>>(iter: I) -> Self {
+        let mut parts: HashMap<Uuid, Part> = iter.into_iter().map(|part| (part.id, part)).collect();
+        let ids: HashSet<Uuid> = parts.keys().copied().collect();
+        for part in parts.values_mut() {
+            part.parents.retain(|id| ids.contains(id));
+            part.children.retain(|id| ids.contains(id));
         }
+        PartsList::restore_links(&mut parts);
+        PartsList::with_parts(parts)
+    }
+}
+
+impl<'de> Deserialize<'de> for PartsList {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let parts = HashMap::<Uuid, Part>::deserialize(deserializer)?;
+        PartsList::from_parts(parts).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for PartsList {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.parts.serialize(serializer)
+    }
+}
+
+impl Default for PartsList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::PartsStore;
+
+    fn list_contains_part<'a, 'b, T: Iterator<Item = &'a &'a Part>>(
+        list_iter: &mut T,
+        part: &'b Part,
+    ) -> bool {
+        list_iter.find(|x| x.id == part.id).is_none() == false
+    }
+
+    fn list_compare(list: &[&Part], other: &[&Part]) -> () {
+        let mut list: Vec<&Part> = list.iter().copied().collect();
+        let mut other: Vec<&Part> = other.iter().copied().collect();
+        assert_eq!(list.sort(), other.sort());
+    }
+
+    /// Assert that two lists hold the same parts, in any order
+    fn assert_same_parts(list: &[&Part], other: &[&Part]) {
+        let mut list = list.to_vec();
+        let mut other = other.to_vec();
+        list.sort();
+        other.sort();
+        assert_eq!(list, other);
+    }
+
+    #[test]
+    fn create_part() {
+        let name = "my part";
+        let part = Part::new(name);
+        assert_eq!(name, part.name);
+        assert!(part.parents.is_empty());
+        assert!(part.children.is_empty());
+    }
+
+    #[test]
+    fn add_part_with_id() {
+        let mut parts = PartsList::new();
+        let id = Uuid::parse_str("3f1c8e3e-9a43-4f55-b0a8-52a1c1fb7b2e").unwrap();
+        let part = parts.add(Part::with_id(id, "my part")).unwrap().clone();
+        assert_eq!(part.id, id);
+        assert_ne!(part.id, Part::new("my part").id);
+        assert_matches!(
+            parts.add(Part::with_id(id, "other part")),
+            Err(PartsListError::PartExists { .. })
+        );
+    }
+
+    #[test]
+    fn part_versions() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        let nut = parts.add(Part::new("nut")).unwrap().id;
+        assert_eq!(parts.get(&assy).unwrap().version, 1);
+
+        parts
+            .update(&assy, &[&screw, &nut], PartsListUpdate::Add)
+            .unwrap();
+        assert_eq!(parts.get(&assy).unwrap().version, 3);
+        assert_eq!(parts.get(&screw).unwrap().version, 2);
+        // adding an existing child changes nothing
+        parts
+            .update(&assy, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+        assert_eq!(parts.get(&assy).unwrap().version, 3);
+
+        parts.delete(&nut).unwrap();
+        assert_eq!(parts.get(&assy).unwrap().version, 4);
+        assert!(PartsStore::check_version(&parts, &assy, 4).is_ok());
+        assert_matches!(
+            PartsStore::check_version(&parts, &assy, 3),
+            Err(PartsListError::VersionConflict {
+                expected: 3,
+                actual: 4,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn add_duplicate_name() {
+        let mut parts = PartsList::new();
+        let part = parts.add(Part::new("my part")).unwrap().clone();
+        assert_eq!(parts.get_by_name("my part"), vec![&part]);
+        assert_matches!(
+            parts.add(Part::new("my part")),
+            Err(PartsListError::PartNameExists { id, .. }) if id == part.id
+        );
+
+        // name is released once the part is deleted
+        parts.delete(&part.id).unwrap();
+        assert!(parts.get_by_name("my part").is_empty());
+        let other = parts.add(Part::new("my part")).unwrap().clone();
+        assert_ne!(other.id, part.id);
+        assert_eq!(parts.get_by_name("my part"), vec![&other]);
+    }
+
+    #[test]
+    fn external_ids() {
+        let mut parts = PartsList::new();
+        let mut bolt = Part::new("bolt");
+        bolt.external_ids.insert("sap".into(), "100-200".into());
+        let bolt = parts.add(bolt).unwrap().clone();
+        assert_eq!(parts.get_by_external("sap", "100-200").unwrap(), &bolt);
+
+        let mut nut = Part::new("nut");
+        nut.external_ids.insert("sap".into(), "100-200".into());
+        assert_matches!(
+            parts.add(nut),
+            Err(PartsListError::ExternalIdInUse { id, .. }) if id == bolt.id
+        );
+
+        // edits replace every external id and bump the version
+        let mut external_ids = BTreeMap::new();
+        external_ids.insert("arena".into(), "PRT-7".into());
+        let edit = PartEdit {
+            external_ids: Some(external_ids),
+            ..PartEdit::default()
+        };
+        let edited = parts.edit(&bolt.id, &edit).unwrap().clone();
+        assert_eq!(edited.version, bolt.version + 1);
+        assert_matches!(
+            parts.get_by_external("sap", "100-200"),
+            Err(PartsListError::ExternalIdDoesNotExist { .. })
+        );
+        assert_eq!(parts.get_by_external("arena", "PRT-7").unwrap().id, bolt.id);
+
+        // external ids survive saving and are released once the part is deleted
+        let mut buffer = Vec::new();
+        parts.to_writer(&mut buffer).unwrap();
+        let mut loaded = PartsList::from_reader(buffer.as_slice()).unwrap();
+        assert_eq!(
+            loaded.get_by_external("arena", "PRT-7").unwrap().id,
+            bolt.id
+        );
+        loaded.delete(&bolt.id).unwrap();
+        assert!(loaded.get_by_external("arena", "PRT-7").is_err());
     }
-}
 
-impl Default for PartsList {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn duplicate_name_policies() {
+        let mut parts = PartsList::new();
+        parts.set_name_policy(DuplicateNamePolicy::Allow);
+        parts.add(Part::new("bolt")).unwrap();
+        parts.add(Part::new("bolt")).unwrap();
+        assert_eq!(parts.get_by_name("bolt").len(), 2);
+
+        parts.set_name_policy(DuplicateNamePolicy::Suffix);
+        assert_eq!(parts.add(Part::new("bolt")).unwrap().name, "bolt (2)");
+        assert_eq!(parts.add(Part::new("bolt")).unwrap().name, "bolt (3)");
+        assert_eq!(parts.add(Part::new("nut")).unwrap().name, "nut");
+        assert_eq!(parts.get_by_name("bolt (2)").len(), 1);
+
+        assert_matches!(
+            DuplicateNamePolicy::try_from("sometimes"),
+            Err(PartsListError::InvalidNamePolicyString { .. })
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn rename_part() {
+        let mut parts = PartsList::new();
+        let bolt = parts.add(Part::new("bolt")).unwrap().clone();
+        let nut = parts.add(Part::new("nut")).unwrap().clone();
+        let rename = |name: &str| PartEdit {
+            name: Some(name.into()),
+            ..PartEdit::default()
+        };
+
+        // the name index follows the new name
+        let renamed = parts.edit(&bolt.id, &rename("hex bolt")).unwrap().clone();
+        assert_eq!(renamed.name, "hex bolt");
+        assert_eq!(renamed.version, bolt.version + 1);
+        assert!(parts.get_by_name("bolt").is_empty());
+        assert_eq!(parts.get_by_name("hex bolt")[0].id, bolt.id);
+
+        // keeping its own name is not a clash
+        assert!(parts.edit(&bolt.id, &rename("hex bolt")).is_ok());
+        assert_matches!(
+            parts.edit(&bolt.id, &rename("nut")),
+            Err(PartsListError::PartNameExists { id, .. }) if id == nut.id
+        );
+        assert_eq!(parts.get(&bolt.id).unwrap().name, "hex bolt");
 
-    fn list_contains_part<'a, 'b, T: Iterator<Item = &'a &'a Part>>(
-        list_iter: &mut T,
-        part: &'b Part,
-    ) -> bool {
-        list_iter.find(|x| x.id == part.id).is_none() == false
+        parts.set_name_policy(DuplicateNamePolicy::Suffix);
+        assert_eq!(
+            parts.edit(&bolt.id, &rename("nut")).unwrap().name,
+            "nut (2)"
+        );
     }
 
-    fn list_compare(list: &[&Part], other: &[&Part]) -> () {
-        let mut list: Vec<&Part> = list.iter().copied().collect();
-        let mut other: Vec<&Part> = other.iter().copied().collect();
-        assert_eq!(list.sort(), other.sort());
+    #[test]
+    fn search_follows_changes() {
+        let mut parts = PartsList::new();
+        let bolt = parts.add(Part::new("bolt")).unwrap().clone();
+        let mut nut = Part::new("nut");
+        nut.attributes.insert("finish".into(), json!("Zinc plated"));
+        let nut = parts.add(nut).unwrap().clone();
+        let ids = |parts: &PartsList, query| -> Vec<Uuid> {
+            parts
+                .search(query, false)
+                .iter()
+                .map(|(part, _)| part.id)
+                .collect()
+        };
+        assert_eq!(ids(&parts, "zinc"), vec![nut.id]);
+
+        let mut attributes = BTreeMap::new();
+        attributes.insert("finish".to_string(), json!("zinc"));
+        let edit = PartEdit {
+            name: Some("hex bolt".into()),
+            attributes: Some(attributes),
+            ..PartEdit::default()
+        };
+        parts.edit(&bolt.id, &edit).unwrap();
+        assert!(ids(&parts, "bolt").contains(&bolt.id));
+        assert_eq!(ids(&parts, "zinc").len(), 2);
+
+        parts.delete(&nut.id).unwrap();
+        assert_eq!(ids(&parts, "zinc plated"), vec![bolt.id]);
+        assert!(ids(&parts, "nut").is_empty());
     }
 
     #[test]
-    fn create_part() {
-        let name = "my part";
-        let part = Part::new(name);
-        assert_eq!(name, part.name);
-        assert!(part.parents.is_empty());
-        assert!(part.children.is_empty());
+    fn loads_changes_made_elsewhere() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let board = parts.add(Part::new("board")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        parts
+            .update(&assy, &[&board, &screw], PartsListUpdate::Add)
+            .unwrap();
+        let mut elsewhere = parts.clone();
+
+        // another copy changes the parts, and only tells which it changed
+        let before = parts.clone();
+        let resistor = parts.add(Part::new("resistor")).unwrap().id;
+        parts
+            .update(&board, &[&resistor], PartsListUpdate::Add)
+            .unwrap();
+        parts.delete(&screw).unwrap();
+        let (changed, removed) = parts.changes_since(&before);
+        let mut changed: Vec<Part> = changed.into_iter().cloned().collect();
+        changed.sort();
+        assert_eq!(changed.len(), 3);
+        assert_eq!(removed, vec![screw]);
+
+        elsewhere.load_changes(changed, &removed);
+        let ids = |parts: Vec<&Part>| -> Vec<Uuid> {
+            let mut ids: Vec<Uuid> = parts.iter().map(|part| part.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(
+            ids(elsewhere.get_ancestors(&resistor, None).unwrap()),
+            ids(parts.get_ancestors(&resistor, None).unwrap())
+        );
+        assert_eq!(
+            ids(elsewhere.get_children(&assy, PartsListFilter::All).unwrap()),
+            ids(parts.get_children(&assy, PartsListFilter::All).unwrap())
+        );
+        assert!(elsewhere.get(&screw).is_err());
+        assert!(elsewhere.get_by_name("screw").is_empty());
+        assert_eq!(elsewhere.get_by_name("resistor")[0].id, resistor);
+        assert_matches!(
+            elsewhere.update(&resistor, &[&assy], PartsListUpdate::Add),
+            Err(PartsListError::AddChildCyclicalRelative { .. })
+        );
     }
 
     #[test]
@@ -562,10 +2960,21 @@ mod tests {
         let list = parts.get_children(&part1.id, PartsListFilter::All).unwrap();
         list_compare(&list, &vec![&part3, &part4]);
 
+        let list = parts
+            .get_children(&part1.id, PartsListFilter::Subassembly)
+            .unwrap();
+        assert_same_parts(&list, &vec![&part3]);
+
         let list = parts
             .get_children(&part4.id, PartsListFilter::Assembly)
             .unwrap();
         list_compare(&list, &vec![&part1, &part2, &part3]);
+
+        let list = parts.get_ancestors(&part4.id, None).unwrap();
+        assert_same_parts(&list, &vec![&part1, &part2, &part3]);
+        let list = parts.get_ancestors(&part4.id, Some(1)).unwrap();
+        assert_same_parts(&list, &vec![&part3]);
+        assert!(parts.get_ancestors(&part4.id, Some(0)).unwrap().is_empty());
     }
 
     #[test]
@@ -601,6 +3010,537 @@ mod tests {
         list_compare(&list, &vec![&part2, &part3, &part4]);
     }
 
+    #[test]
+    fn closure_tracks_relationship_changes() {
+        let mut parts = PartsList::new();
+        let ids: Vec<Uuid> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|name| parts.add(Part::new(name)).unwrap().id)
+            .collect();
+        let check = |parts: &PartsList| {
+            let rebuilt = Closure::build(&parts.parts);
+            assert_eq!(parts.closure.ancestors, rebuilt.ancestors);
+            assert_eq!(parts.closure.descendants, rebuilt.descendants);
+        };
+        // diamond a -> (b, c) -> d -> e
+        parts
+            .update(&ids[0], &[&ids[1], &ids[2]], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&ids[1], &[&ids[3]], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&ids[2], &[&ids[3]], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&ids[3], &[&ids[4]], PartsListUpdate::Add)
+            .unwrap();
+        check(&parts);
+        assert!(parts.is_ancestor(&ids[4], &ids[0]).unwrap());
+
+        // e is still below a through c after unlinking b from d
+        parts
+            .update(&ids[1], &[&ids[3]], PartsListUpdate::Remove)
+            .unwrap();
+        check(&parts);
+        assert!(parts.is_ancestor(&ids[4], &ids[0]).unwrap());
+        assert!(!parts.is_ancestor(&ids[4], &ids[1]).unwrap());
+
+        parts.delete(&ids[2]).unwrap();
+        check(&parts);
+        assert!(!parts.is_ancestor(&ids[4], &ids[0]).unwrap());
+        assert_matches!(
+            parts.update(&ids[4], &[&ids[3]], PartsListUpdate::Add),
+            Err(PartsListError::AddChildCyclicalRelative { .. })
+        );
+    }
+
+    #[test]
+    fn cycle_error_carries_path() {
+        let mut parts = PartsList::new();
+        let ids: Vec<Uuid> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| parts.add(Part::new(name)).unwrap().id)
+            .collect();
+        // a -> b -> c, with a shortcut a -> c and d off to the side
+        parts
+            .update(&ids[0], &[&ids[1], &ids[2]], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&ids[1], &[&ids[2]], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&ids[2], &[&ids[3]], PartsListUpdate::Add)
+            .unwrap();
+
+        assert_matches!(
+            parts.update(&ids[3], &[&ids[0]], PartsListUpdate::Add),
+            Err(PartsListError::AddChildCyclicalRelative { path, .. }) => {
+                assert_eq!(path, vec![ids[0], ids[2], ids[3]]);
+            }
+        );
+        assert_matches!(
+            parts.update(&ids[1], &[&ids[1]], PartsListUpdate::Add),
+            Err(PartsListError::AddChildCyclicalRelative { path, .. }) => {
+                assert_eq!(path, vec![ids[1]]);
+            }
+        );
+        assert!(parts.cycle_path(&ids[0], &ids[3]).is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut parts = PartsList::new();
+        assert_eq!(parts.stats().max_depth, 0);
+        assert_eq!(parts.stats().average_fan_out, 0.0);
+
+        let part1 = parts.add(Part::new("my part")).unwrap().id;
+        let part2 = parts.add(Part::new("subassy")).unwrap().id;
+        let part3 = parts.add(Part::new("deep component")).unwrap().id;
+        let part4 = parts.add(Part::new("shallow component")).unwrap().id;
+        parts.add(Part::new("orphan")).unwrap();
+        parts
+            .update(&part1, &[&part2, &part4], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&part2, &[&part3], PartsListUpdate::Add)
+            .unwrap();
+
+        let stats = parts.stats();
+        assert_eq!(
+            stats,
+            PartsListStats {
+                total: 5,
+                top_level: 2,
+                component: 2,
+                subassembly: 1,
+                orphan: 1,
+                max_depth: 2,
+                average_fan_out: 1.5,
+            }
+        );
+    }
+
+    #[test]
+    fn top_used_components() {
+        let mut parts = PartsList::new();
+        let bike = parts.add(Part::new("bike")).unwrap().id;
+        let trike = parts.add(Part::new("trike")).unwrap().id;
+        let wheel = parts.add(Part::new("wheel")).unwrap().id;
+        let spoke = parts.add(Part::new("spoke")).unwrap().id;
+        let bell = parts.add(Part::new("bell")).unwrap().id;
+        parts
+            .update(&wheel, &[&spoke], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&bike, &[&wheel, &bell], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&trike, &[&wheel, &spoke], PartsListUpdate::Add)
+            .unwrap();
+
+        // the spoke is in both vehicles through the wheel, and counted once in
+        // the trike which also uses it directly
+        let ranked: Vec<(Uuid, usize)> = parts
+            .top_used(10)
+            .into_iter()
+            .map(|(part, used_by)| (part.id, used_by))
+            .collect();
+        assert_eq!(ranked, vec![(spoke, 3), (bell, 1)]);
+        assert_eq!(parts.top_used(1).len(), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut parts = PartsList::new();
+        let part1 = parts.add(Part::new("my part")).unwrap().id;
+        let part2 = parts.add(Part::new("subassy")).unwrap().id;
+        parts
+            .update(&part1, &[&part2], PartsListUpdate::Add)
+            .unwrap();
+        assert!(parts.validate().is_valid());
+        assert_eq!(parts.validate().checked, 2);
+
+        // corrupt the list behind the back of the public API
+        let missing = Part::new("missing").id;
+        parts
+            .parts
+            .get_mut(&part1)
+            .unwrap()
+            .children
+            .insert(missing);
+        parts.parts.get_mut(&part2).unwrap().parents.remove(&part1);
+        parts.parts.get_mut(&part2).unwrap().children.insert(part1);
+        parts.parts.get_mut(&part1).unwrap().parents.insert(part2);
+
+        let report = parts.validate();
+        assert!(!report.is_valid());
+        assert!(report.violations.contains(&Violation::DanglingReference {
+            id: part1,
+            relative: missing
+        }));
+        assert!(report
+            .violations
+            .contains(&Violation::MissingParentBacklink {
+                parent: part1,
+                child: part2
+            }));
+        assert!(report.violations.iter().any(|violation| matches!(
+            violation,
+            Violation::Cycle { path } if path.len() == 2
+        )));
+
+        parts.parts.get_mut(&part2).unwrap().name = String::from("my part");
+        let mut ids = vec![part1, part2];
+        ids.sort();
+        let duplicate = Violation::DuplicateName {
+            name: String::from("my part"),
+            ids,
+        };
+        assert!(parts.validate().violations.contains(&duplicate));
+        parts.set_name_policy(DuplicateNamePolicy::Allow);
+        assert!(!parts.validate().violations.contains(&duplicate));
+    }
+
+    #[test]
+    fn test_delete_orphans() {
+        let mut parts = PartsList::new();
+        let part1 = parts.add(Part::new("my part")).unwrap().id;
+        let part2 = parts.add(Part::new("subassy")).unwrap().id;
+        let orphan = parts.add(Part::new("orphan")).unwrap().clone();
+        parts
+            .update(&part1, &[&part2], PartsListUpdate::Add)
+            .unwrap();
+
+        assert_eq!(parts.delete_orphans(), vec![orphan]);
+        assert_eq!(parts.len(), 2);
+        assert!(parts.delete_orphans().is_empty());
+    }
+
+    #[test]
+    fn test_list_sorted() {
+        let mut parts = PartsList::new();
+        let mut assy = Part::new("b assy");
+        assy.created = 3;
+        let mut screw = Part::new("c screw");
+        screw.created = 1;
+        let mut nut = Part::new("a nut");
+        nut.created = 2;
+        let (assy, screw, nut) = (
+            parts.add(assy).unwrap().id,
+            parts.add(screw).unwrap().id,
+            parts.add(nut).unwrap().id,
+        );
+        parts
+            .update(&assy, &[&screw, &nut], PartsListUpdate::Add)
+            .unwrap();
+
+        let ids = |sort| -> Vec<Uuid> {
+            parts
+                .list_sorted(PartsListFilter::All, sort)
+                .iter()
+                .map(|part| part.id)
+                .collect()
+        };
+        assert_eq!(
+            ids(PartsListSort::new(PartsListSortKey::Name, SortOrder::Asc)),
+            vec![nut, assy, screw]
+        );
+        assert_eq!(
+            ids(PartsListSort::new(
+                PartsListSortKey::Created,
+                SortOrder::Desc
+            )),
+            vec![assy, nut, screw]
+        );
+        assert_eq!(
+            ids(PartsListSort::new(
+                PartsListSortKey::ChildrenCount,
+                SortOrder::Desc
+            ))[0],
+            assy
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("pen assy")).unwrap().id;
+        let cap = parts.add(Part::new("pen cap")).unwrap().id;
+        let clip = parts.add(Part::new("clip")).unwrap().id;
+        let ink = parts.add(Part::new("ink")).unwrap().id;
+        parts
+            .update(&assy, &[&cap, &ink], PartsListUpdate::Add)
+            .unwrap();
+        parts.update(&cap, &[&clip], PartsListUpdate::Add).unwrap();
+
+        let ids = |query: PartsListQuery| -> Vec<Uuid> {
+            let mut ids: Vec<Uuid> = parts.query(&query).iter().map(|part| part.id).collect();
+            ids.sort();
+            ids
+        };
+        let name = PartsListPredicate::parse("name_contains", "pen").unwrap();
+        assert_eq!(ids(PartsListQuery::new(PartsListFilter::All)).len(), 4);
+        assert_eq!(
+            ids(PartsListQuery::new(PartsListFilter::Subassembly).and(name.clone())),
+            vec![cap]
+        );
+        assert_eq!(
+            ids(PartsListQuery::new(PartsListFilter::All)
+                .and(name)
+                .and(PartsListPredicate::MinChildren(2))),
+            vec![assy]
+        );
+        let has_parent = PartsListPredicate::parse("has_parent", &assy.to_string()).unwrap();
+        let mut expected = vec![cap, ink];
+        expected.sort();
+        assert_eq!(
+            ids(PartsListQuery::new(PartsListFilter::All).and(has_parent)),
+            expected
+        );
+        assert_eq!(
+            ids(PartsListQuery::new(PartsListFilter::Component)
+                .and(PartsListPredicate::HasParent(cap))
+                .and(PartsListPredicate::MaxChildren(0))),
+            vec![clip]
+        );
+
+        assert_matches!(
+            PartsListPredicate::parse("min_children", "many"),
+            Err(PartsListError::InvalidPredicateString { .. })
+        );
+        assert_matches!(
+            PartsListPredicate::parse("colour", "red"),
+            Err(PartsListError::InvalidPredicateString { .. })
+        );
+    }
+
+    #[test]
+    fn attribute_predicates() {
+        let mut parts = PartsList::new();
+        let mut ids = Vec::new();
+        for (name, voltage, package) in &[
+            ("regulator", json!("5V"), "0603"),
+            ("led", json!("5V"), "0402"),
+            ("fuse", json!(5), "0603"),
+        ] {
+            let mut part = Part::new(name);
+            part.attributes.insert("voltage".into(), voltage.clone());
+            part.attributes.insert("package".into(), json!(package));
+            ids.push(parts.add(part).unwrap().id);
+        }
+        let query = |parts: &PartsList, pairs: &[(&str, &str)]| -> Vec<Uuid> {
+            let mut query = PartsListQuery::new(PartsListFilter::All);
+            for (key, value) in pairs {
+                query = query.and(PartsListPredicate::parse(key, value).unwrap());
+            }
+            let mut found: Vec<Uuid> = parts.query(&query).iter().map(|part| part.id).collect();
+            found.sort();
+            found
+        };
+        assert_eq!(
+            query(&parts, &[("attr.voltage", "5V"), ("attr.package", "0603")]),
+            vec![ids[0]]
+        );
+        // values other than strings are compared as JSON
+        assert_eq!(query(&parts, &[("attr.voltage", "5")]), vec![ids[2]]);
+        assert!(query(&parts, &[("attr.voltage", "12V")]).is_empty());
+
+        // the index follows edits and removals
+        let edit = PartEdit {
+            attributes: Some(BTreeMap::new()),
+            ..PartEdit::default()
+        };
+        parts.edit(&ids[1], &edit).unwrap();
+        assert_eq!(query(&parts, &[("attr.voltage", "5V")]), vec![ids[0]]);
+        parts.remove(&ids[0]).unwrap();
+        assert!(query(&parts, &[("attr.voltage", "5V")]).is_empty());
+        assert_matches!(
+            PartsListPredicate::parse("attr.", "5V"),
+            Err(PartsListError::InvalidPredicateString { .. })
+        );
+    }
+
+    #[test]
+    fn test_paginate() {
+        let mut parts = PartsList::new();
+        for i in 0..5 {
+            parts.add(Part::new(&format!("part {}", i))).unwrap();
+        }
+        let mut ids: Vec<Uuid> = parts.iter().map(|part| part.id).collect();
+        ids.sort();
+
+        let (page, cursor) = PartsListCursor::paginate(None, 2, parts.list(PartsListFilter::All));
+        assert_eq!(
+            page.iter().map(|part| part.id).collect::<Vec<_>>(),
+            ids[..2]
+        );
+        let token: String = cursor.unwrap().into();
+        let cursor = PartsListCursor::try_from(token.as_str()).unwrap();
+
+        // removing an already returned part doesn't shift the following pages
+        parts.delete(&ids[0]).unwrap();
+        let (page, cursor) =
+            PartsListCursor::paginate(Some(cursor), 2, parts.list(PartsListFilter::All));
+        assert_eq!(
+            page.iter().map(|part| part.id).collect::<Vec<_>>(),
+            ids[2..4]
+        );
+        let (page, cursor) = PartsListCursor::paginate(cursor, 2, parts.list(PartsListFilter::All));
+        assert_eq!(
+            page.iter().map(|part| part.id).collect::<Vec<_>>(),
+            ids[4..]
+        );
+        assert_eq!(cursor, None);
+
+        assert_matches!(
+            PartsListCursor::try_from("not a cursor"),
+            Err(PartsListError::InvalidCursorString { .. })
+        );
+    }
+
+    #[test]
+    fn test_delete_tree() {
+        let mut parts = PartsList::new();
+        let part1 = parts.add(Part::new("my part")).unwrap().clone();
+        let part2 = parts.add(Part::new("other part")).unwrap().clone();
+        let part3 = parts.add(Part::new("subassy")).unwrap().clone();
+        let part4 = parts.add(Part::new("deep component")).unwrap().clone();
+        let part5 = parts.add(Part::new("shared component")).unwrap().clone();
+
+        parts
+            .update(&part1.id, &[&part3.id, &part5.id], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&part2.id, &[&part5.id], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&part3.id, &[&part4.id], PartsListUpdate::Add)
+            .unwrap();
+
+        let list = parts.delete_tree_candidates(&part1.id).unwrap();
+        assert_same_parts(&list, &[&part1, &part3, &part4]);
+
+        let removed = parts.delete_tree(&part1.id).unwrap();
+        assert_same_parts(
+            &removed.iter().collect::<Vec<_>>(),
+            &[&part1, &part3, &part4],
+        );
+        let list = parts.list(PartsListFilter::All);
+        assert_same_parts(&list, &[&part2, &part5]);
+        assert!(!parts.get(&part5.id).unwrap().parents.contains(&part1.id));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut parts = PartsList::new();
+        let part1 = parts.add(Part::new("my part")).unwrap().clone();
+        let part2 = parts.add(Part::new("other part")).unwrap().clone();
+        let part3 = parts.add(Part::new("orphan part")).unwrap().clone();
+        parts
+            .update(&part1.id, &[&part2.id], PartsListUpdate::Add)
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        parts.to_writer(&mut buffer).unwrap();
+        let loaded = PartsList::from_reader(buffer.as_slice()).unwrap();
+
+        let list = loaded.list(PartsListFilter::All);
+        assert_same_parts(&list, &[&part1, &part2, &part3]);
+        assert!(loaded.get(&part1.id).unwrap().children.contains(&part2.id));
+        assert!(loaded.get(&part2.id).unwrap().parents.contains(&part1.id));
+    }
+
+    #[test]
+    fn load_restores_missing_backlinks() {
+        let mut part1 = Part::new("my part");
+        let part2 = Part::new("other part");
+        part1.children.insert(part2.id);
+        let stored: HashMap<Uuid, Part> =
+            vec![(part1.id, part1.clone()), (part2.id, part2.clone())]
+                .into_iter()
+                .collect();
+        let buffer = serde_json::to_vec(&stored).unwrap();
+
+        let loaded = PartsList::from_reader(buffer.as_slice()).unwrap();
+        assert!(loaded.get(&part2.id).unwrap().parents.contains(&part1.id));
+    }
+
+    #[test]
+    fn load_with_missing_relative() {
+        let mut part1 = Part::new("my part");
+        let part2 = Part::new("other part");
+        part1.children.insert(part2.id);
+        let stored: HashMap<Uuid, Part> = vec![(part1.id, part1)].into_iter().collect();
+        let buffer = serde_json::to_vec(&stored).unwrap();
+
+        let result = PartsList::from_reader(buffer.as_slice());
+        assert_matches!(result, Err(e) => {
+            assert_matches!(e, PartsListError::Serialization(..));
+        });
+    }
+
+    #[test]
+    fn load_with_cycle() {
+        let mut parts: Vec<Part> = ["frame", "fork", "wheel"]
+            .iter()
+            .map(|name| Part::new(name))
+            .collect();
+        let ids: Vec<Uuid> = parts.iter().map(|part| part.id).collect();
+        for (i, part) in parts.iter_mut().enumerate() {
+            part.children.insert(ids[(i + 1) % 3]);
+        }
+        let stored: HashMap<Uuid, Part> = parts.into_iter().map(|part| (part.id, part)).collect();
+        let buffer = serde_json::to_vec(&stored).unwrap();
+        assert_matches!(
+            PartsList::from_reader(buffer.as_slice()),
+            Err(PartsListError::Serialization(ref e)) if e.to_string().contains("contain themselves")
+        );
+
+        let path = match PartsList::from_parts(stored) {
+            Err(PartsListError::PartCycle { path }) => path,
+            result => panic!("cycle not found: {:?}", result.map(|parts| parts.len())),
+        };
+        assert_eq!(path.len(), 3);
+        let start = ids.iter().position(|id| *id == path[0]).unwrap();
+        let expected: Vec<Uuid> = (0..3).map(|i| ids[(start + i) % 3]).collect();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn iterate_parts_list() {
+        let mut parts = PartsList::new();
+        assert!(parts.is_empty());
+        let part1 = parts.add(Part::new("my part")).unwrap().clone();
+        let part2 = parts.add(Part::new("other part")).unwrap().clone();
+        assert_eq!(parts.len(), 2);
+
+        let list: Vec<&Part> = parts.iter().collect();
+        assert_same_parts(&list, &[&part1, &part2]);
+        let list: Vec<&Part> = (&parts).into_iter().collect();
+        assert_same_parts(&list, &[&part1, &part2]);
+        let mut owned: Vec<Part> = parts.into_iter().collect();
+        owned.sort();
+        let mut expected = vec![part1, part2];
+        expected.sort();
+        assert_eq!(owned, expected);
+    }
+
+    #[test]
+    fn collect_parts_list() {
+        let mut part1 = Part::new("my part");
+        let part2 = Part::new("other part");
+        let part3 = Part::new("uncollected part");
+        part1.children.insert(part2.id);
+        part1.children.insert(part3.id);
+
+        let parts: PartsList = vec![part1.clone(), part2.clone()].into_iter().collect();
+        assert_eq!(parts.len(), 2);
+        assert!(parts.get(&part1.id).unwrap().children.contains(&part2.id));
+        assert!(!parts.get(&part1.id).unwrap().children.contains(&part3.id));
+        assert!(parts.get(&part2.id).unwrap().parents.contains(&part1.id));
+    }
+
     #[test]
     fn test_delete_part() {
         let mut parts = PartsList::new();
@@ -649,4 +3589,93 @@ mod tests {
             false
         );
     }
+
+    #[test]
+    fn relationship_metadata() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let screw = parts.add(Part::new("screw")).unwrap().id;
+        parts
+            .update(&assy, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+        let id = Relationship::id_for(&assy, &screw);
+        assert_eq!(parts.get_relationship(&id).unwrap().quantity, 1);
+
+        let edit = RelationshipEdit {
+            quantity: Some(4),
+            notes: Some("M3 x 8".into()),
+        };
+        let version = parts.get(&assy).unwrap().version;
+        parts.edit_relationship(&id, &edit).unwrap();
+        let part = parts.get(&assy).unwrap();
+        assert_eq!(part.version, version + 1);
+        assert_eq!(part.quantity_of(&screw), 4);
+        assert_matches!(
+            parts.edit_relationship(
+                &id,
+                &RelationshipEdit {
+                    quantity: Some(0),
+                    notes: None
+                }
+            ),
+            Err(PartsListError::InvalidQuantity { .. })
+        );
+
+        // relationships keep their id and metadata through a save and load
+        let loaded: PartsList =
+            serde_json::from_str(&serde_json::to_string(&parts).unwrap()).unwrap();
+        assert_eq!(loaded.get_relationship(&id).unwrap().notes, "M3 x 8");
+
+        parts
+            .update(&assy, &[&screw], PartsListUpdate::Remove)
+            .unwrap();
+        assert_matches!(
+            parts.get_relationship(&id),
+            Err(PartsListError::RelationshipDoesNotExist { .. })
+        );
+    }
+
+    #[test]
+    fn replace_keeps_relationships() {
+        let mut parts = PartsList::new();
+        let board = parts.add(Part::new("board")).unwrap().id;
+        let resistor = parts.add(Part::new("resistor")).unwrap().id;
+        let capacitor = parts.add(Part::new("capacitor")).unwrap().id;
+        let led = parts.add(Part::new("led")).unwrap().id;
+        parts
+            .update(&board, &[&resistor, &capacitor], PartsListUpdate::Add)
+            .unwrap();
+        let id = Relationship::id_for(&board, &resistor);
+        parts
+            .edit_relationship(
+                &id,
+                &RelationshipEdit {
+                    quantity: Some(4),
+                    notes: Some("R1-R4".into()),
+                },
+            )
+            .unwrap();
+
+        parts
+            .update(&board, &[&resistor, &led], PartsListUpdate::Replace)
+            .unwrap();
+        let part = parts.get(&board).unwrap();
+        assert_eq!(part.children, [resistor, led].iter().copied().collect());
+        let relationship = parts.get_relationship(&id).unwrap();
+        assert_eq!(relationship.quantity, 4);
+        assert_eq!(relationship.notes, "R1-R4");
+        assert_eq!(part.quantity_of(&led), 1);
+        assert!(parts.get(&capacitor).unwrap().parents.is_empty());
+        assert_matches!(
+            parts.get_relationship(&Relationship::id_for(&board, &capacitor)),
+            Err(PartsListError::RelationshipDoesNotExist { .. })
+        );
+
+        // a refused replace leaves every child linked
+        assert_matches!(
+            parts.update(&board, &[&board], PartsListUpdate::Replace),
+            Err(PartsListError::AddChildCyclicalRelative { .. })
+        );
+        assert_eq!(parts.get_relationship(&id).unwrap().quantity, 4);
+    }
 }
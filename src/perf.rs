@@ -0,0 +1,226 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Inclusive upper bounds of the buckets of every histogram, with a last
+/// bucket counting the larger values
+pub const BUCKET_BOUNDS: [u64; 7] = [1, 10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// Counts of values falling in each of the buckets of `BUCKET_BOUNDS`, which
+/// can be recorded from any thread
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS.len() + 1],
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Histogram {
+        Histogram {
+            buckets: [
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+                AtomicU64::new(0),
+            ],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        let bucket = BUCKET_BOUNDS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> HistogramSummary {
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum.load(Ordering::Relaxed);
+        HistogramSummary {
+            count,
+            sum,
+            mean: if count > 0 {
+                Some(sum as f64 / count as f64)
+            } else {
+                None
+            },
+            buckets: self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(i, bucket)| HistogramBucket {
+                    le: BUCKET_BOUNDS.get(i).copied(),
+                    count: bucket.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Values recorded in a histogram, along with the number in each bucket
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistogramSummary {
+    pub count: u64,
+    pub sum: u64,
+    pub mean: Option<f64>,
+    pub buckets: Vec<HistogramBucket>,
+}
+
+/// Number of values recorded up to `le`, and above the previous bound, with
+/// no bound for the last bucket
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistogramBucket {
+    pub le: Option<u64>,
+    pub count: u64,
+}
+
+/// Access taken to the shared parts store
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockMode {
+    Read,
+    Write,
+}
+
+/// Counters describing where the server spends its time, kept for the life of
+/// the process
+pub struct PerfCounters {
+    /// Microseconds taken to gain read access to the parts
+    read_lock_wait: Histogram,
+    /// Microseconds taken to gain write access to the parts
+    write_lock_wait: Histogram,
+    /// Reads refused as a writer held the lock
+    read_lock_failures: AtomicU64,
+    /// Writes refused as the lock was held
+    write_lock_failures: AtomicU64,
+    /// Parts returned by each traversal of the relatives of a part
+    traversal_nodes: Histogram,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+/// Counters recorded by every parts store and lock in the process
+pub static COUNTERS: PerfCounters = PerfCounters {
+    read_lock_wait: Histogram::new(),
+    write_lock_wait: Histogram::new(),
+    read_lock_failures: AtomicU64::new(0),
+    write_lock_failures: AtomicU64::new(0),
+    traversal_nodes: Histogram::new(),
+    cache_hits: AtomicU64::new(0),
+    cache_misses: AtomicU64::new(0),
+};
+
+/// Record an attempt to access the parts, which took `waited` and succeeded
+/// when `acquired`
+pub fn record_lock(mode: LockMode, waited: Duration, acquired: bool) {
+    let (wait, failures) = match mode {
+        LockMode::Read => (&COUNTERS.read_lock_wait, &COUNTERS.read_lock_failures),
+        LockMode::Write => (&COUNTERS.write_lock_wait, &COUNTERS.write_lock_failures),
+    };
+    if acquired {
+        wait.record(waited.as_micros() as u64);
+    } else {
+        failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Record a traversal of the relatives of a part which found `nodes` parts
+pub fn record_traversal(nodes: usize) {
+    COUNTERS.traversal_nodes.record(nodes as u64);
+}
+
+/// Record a lookup in the subtree cache
+pub fn record_cache(hit: bool) {
+    let counter = if hit {
+        &COUNTERS.cache_hits
+    } else {
+        &COUNTERS.cache_misses
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Summary of the counters recorded since the server started
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PerfSummary {
+    pub read_lock_wait_us: HistogramSummary,
+    pub write_lock_wait_us: HistogramSummary,
+    pub read_lock_failures: u64,
+    pub write_lock_failures: u64,
+    pub traversal_nodes: HistogramSummary,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Share of subtree cache lookups answered from the cache, if any were made
+    pub cache_hit_rate: Option<f64>,
+}
+
+/// Summarize the counters recorded so far
+pub fn summary() -> PerfSummary {
+    let cache_hits = COUNTERS.cache_hits.load(Ordering::Relaxed);
+    let cache_misses = COUNTERS.cache_misses.load(Ordering::Relaxed);
+    let lookups = cache_hits + cache_misses;
+    PerfSummary {
+        read_lock_wait_us: COUNTERS.read_lock_wait.summary(),
+        write_lock_wait_us: COUNTERS.write_lock_wait.summary(),
+        read_lock_failures: COUNTERS.read_lock_failures.load(Ordering::Relaxed),
+        write_lock_failures: COUNTERS.write_lock_failures.load(Ordering::Relaxed),
+        traversal_nodes: COUNTERS.traversal_nodes.summary(),
+        cache_hits,
+        cache_misses,
+        cache_hit_rate: if lookups > 0 {
+            Some(cache_hits as f64 / lookups as f64)
+        } else {
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let histogram = Histogram::new();
+        assert_eq!(histogram.summary().mean, None);
+        for value in &[0, 1, 5, 10, 11, 5_000_000] {
+            histogram.record(*value);
+        }
+        let recorded = histogram.summary();
+        assert_eq!(recorded.count, 6);
+        assert_eq!(recorded.sum, 5_000_027);
+        let counts: Vec<(Option<u64>, u64)> = recorded
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.le, bucket.count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                (Some(1), 2),
+                (Some(10), 2),
+                (Some(100), 1),
+                (Some(1_000), 0),
+                (Some(10_000), 0),
+                (Some(100_000), 0),
+                (Some(1_000_000), 0),
+                (None, 1),
+            ]
+        );
+
+        record_cache(true);
+        assert!(summary().cache_hit_rate.is_some());
+    }
+}
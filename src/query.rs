@@ -1,12 +1,74 @@
+use std::collections::BTreeMap;
 use std::vec::Vec;
 use uuid::Uuid;
 
+use crate::category::AttributeSchema;
+use crate::eco::EcoChange;
+
 #[derive(Serialize, Deserialize)]
 pub struct NewPart {
     pub name: String,
+    /// Id to create the part with, randomly generated when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    /// Identifiers of the part in other systems, keyed by system
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub external_ids: BTreeMap<String, String>,
+    /// Category to assign the part to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<Uuid>,
+    /// Custom attributes of the part
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct UpdateChildren {
     pub children: Vec<Uuid>,
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct NewInstructions {
+    /// Markdown replacing the work instructions of a part, which are removed
+    /// when it is empty
+    pub markdown: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchGet {
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BulkDelete {
+    pub ids: Vec<Uuid>,
+    /// Also delete the subtree of each listed part
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewCategory {
+    pub name: String,
+    /// Category the new category is a subcategory of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Uuid>,
+    /// Attributes expected of the parts in the category
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<AttributeSchema>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewEco {
+    pub assembly: Uuid,
+    #[serde(default)]
+    pub description: String,
+    pub changes: Vec<EcoChange>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewRelease {
+    pub assembly: Uuid,
+    #[serde(default)]
+    pub description: String,
+}
@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::config::Value;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Config, Outcome, State};
+
+use crate::parts_list::PartsListError;
+use crate::sidecar;
+
+/// Milliseconds in the day over which the usage of a quota is counted
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Most an API key may do each day, counted from midnight UTC, with no limit
+/// on what isn't given
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct QuotaLimits {
+    /// Requests changing anything held by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+    /// Parts created, whether one at a time, by upserts or by generating
+    /// assemblies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_parts_created: Option<u64>,
+}
+
+/// Limits of an API key along with what it has used of them today
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Quota {
+    pub key: String,
+    pub limits: QuotaLimits,
+    pub requests: u64,
+    pub parts_created: u64,
+    /// Time the usage is next reset, at midnight UTC, in milliseconds since
+    /// the UNIX epoch
+    pub resets_at: u64,
+}
+
+impl Quota {
+    /// Count usage afresh once the day it was counted over has passed
+    fn roll_over(&mut self, now: u64) {
+        if now >= self.resets_at {
+            self.requests = 0;
+            self.parts_created = 0;
+            self.resets_at = (now / DAY_MILLIS + 1) * DAY_MILLIS;
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+impl Quota {
+    fn new(key: &str, limits: QuotaLimits) -> Quota {
+        Quota {
+            key: key.into(),
+            limits,
+            requests: 0,
+            parts_created: 0,
+            resets_at: 0,
+        }
+    }
+}
+
+/// Limits of an API key as saved to a sidecar file, without its usage, which
+/// is counted afresh after a restart
+#[derive(Serialize, Deserialize)]
+struct SavedQuota {
+    key: String,
+    limits: QuotaLimits,
+}
+
+/// Quotas of the API keys allowed to change the server, and of requests sent
+/// without a key, held in memory and saved to a sidecar file after every
+/// change to their limits when the parts are stored outside of memory
+///
+/// Requests sent with a key without a quota of its own count against the
+/// quota of requests sent without a key, so inventing a key gains nothing.
+/// That quota is unlimited unless the `anonymous_quota` setting limits it.
+#[derive(Default)]
+pub struct QuotaRegistry {
+    table: Mutex<QuotaTable>,
+    path: Option<PathBuf>,
+}
+
+/// Quotas of the API keys and of requests without a key
+#[derive(Default)]
+struct QuotaTable {
+    keys: HashMap<String, Quota>,
+    /// Quota shared by every request sent without a key of its own
+    anonymous: Option<Quota>,
+}
+
+/// Locked access to the quotas of a registry, along with the file their
+/// limits are saved to if any
+pub struct Quotas<'a>(MutexGuard<'a, QuotaTable>, Option<&'a Path>);
+
+/// Limits of requests sent without a key given by the `anonymous_quota`
+/// setting of the Rocket configuration, such as
+/// `anonymous_quota = { max_requests = 1000, max_parts_created = 100 }`
+pub fn anonymous_config(config: &Config) -> Result<Option<QuotaLimits>, PartsListError> {
+    match config.get_table("anonymous_quota") {
+        Ok(table) => Value::Table(table.clone())
+            .try_into()
+            .map(Some)
+            .map_err(|e| PartsListError::Config {
+                s: format!("anonymous_quota: {}", e),
+            }),
+        Err(_) => Ok(None),
+    }
+}
+
+impl QuotaRegistry {
+    /// Registry limiting requests sent without a key by `anonymous` when given
+    pub fn new(anonymous: Option<QuotaLimits>) -> QuotaRegistry {
+        QuotaRegistry {
+            table: Mutex::new(QuotaTable {
+                keys: HashMap::new(),
+                anonymous: anonymous.map(|limits| Quota::new("", limits)),
+            }),
+            path: None,
+        }
+    }
+
+    /// Registry saving the limits of its API keys to the file at `path`,
+    /// starting with those saved there before
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        anonymous: Option<QuotaLimits>,
+    ) -> Result<QuotaRegistry, PartsListError> {
+        let path = path.as_ref();
+        let saved: Vec<SavedQuota> = sidecar::load(path)?;
+        let mut registry = QuotaRegistry::new(anonymous);
+        if let Ok(table) = registry.table.get_mut() {
+            table.keys = saved
+                .into_iter()
+                .map(|saved| (saved.key.clone(), Quota::new(&saved.key, saved.limits)))
+                .collect();
+        }
+        registry.path = Some(path.into());
+        Ok(registry)
+    }
+
+    /// Lock the quotas, failing only if a previous holder of the lock panicked
+    pub fn lock(&self) -> Option<Quotas<'_>> {
+        let path = self.path.as_deref();
+        self.table.lock().ok().map(|table| Quotas(table, path))
+    }
+}
+
+impl<'a> Quotas<'a> {
+    /// Quota counting the requests sent with `key`, which is that of requests
+    /// without a key when it isn't given or has no quota of its own, with its
+    /// usage counted over the current day
+    fn current(&mut self, key: Option<&str>) -> Option<&mut Quota> {
+        let quota = match key {
+            Some(key) if self.0.keys.contains_key(key) => self.0.keys.get_mut(key)?,
+            _ => self.0.anonymous.as_mut()?,
+        };
+        quota.roll_over(now());
+        Some(quota)
+    }
+
+    /// Save the limits of every API key, with those of `key` replaced by
+    /// `limits`, or removed when not given
+    fn save(&self, key: &str, limits: Option<&QuotaLimits>) -> Result<(), PartsListError> {
+        if let Some(path) = self.1 {
+            let mut saved: Vec<SavedQuota> = self
+                .0
+                .keys
+                .values()
+                .filter(|quota| quota.key != key)
+                .map(|quota| SavedQuota {
+                    key: quota.key.clone(),
+                    limits: quota.limits.clone(),
+                })
+                .chain(limits.map(|limits| SavedQuota {
+                    key: key.into(),
+                    limits: limits.clone(),
+                }))
+                .collect();
+            saved.sort_by(|a, b| a.key.cmp(&b.key));
+            sidecar::save(path, &saved)?;
+        }
+        Ok(())
+    }
+
+    /// Set the limits of an API key, keeping what it has already used today,
+    /// once they are saved
+    pub fn set(&mut self, key: &str, limits: QuotaLimits) -> Result<Quota, PartsListError> {
+        self.save(key, Some(&limits))?;
+        let quota = self
+            .0
+            .keys
+            .entry(key.into())
+            .or_insert_with(|| Quota::new(key, QuotaLimits::default()));
+        quota.roll_over(now());
+        quota.limits = limits;
+        Ok(quota.clone())
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Quota, PartsListError> {
+        let quota = self
+            .0
+            .keys
+            .get_mut(key)
+            .ok_or(PartsListError::QuotaDoesNotExist)?;
+        quota.roll_over(now());
+        Ok(quota.clone())
+    }
+
+    /// Every quota of an API key, ordered by key
+    pub fn list(&mut self) -> Vec<Quota> {
+        let now = now();
+        let mut quotas: Vec<Quota> = self
+            .0
+            .keys
+            .values_mut()
+            .map(|quota| {
+                quota.roll_over(now);
+                quota.clone()
+            })
+            .collect();
+        quotas.sort_by(|a, b| a.key.cmp(&b.key));
+        quotas
+    }
+
+    /// Remove the quota of an API key, leaving it to count against the quota
+    /// of requests without a key
+    pub fn remove(&mut self, key: &str) -> Result<Quota, PartsListError> {
+        if !self.0.keys.contains_key(key) {
+            return Err(PartsListError::QuotaDoesNotExist);
+        }
+        self.save(key, None)?;
+        self.0
+            .keys
+            .remove(key)
+            .ok_or(PartsListError::QuotaDoesNotExist)
+    }
+
+    /// Count a request against the quota of `key`, or of requests without a
+    /// key, refusing it once as many have been sent as may be today
+    pub fn admit(&mut self, key: Option<&str>) -> Result<(), PartsListError> {
+        if let Some(quota) = self.current(key) {
+            match quota.limits.max_requests {
+                Some(limit) if quota.requests >= limit => {
+                    return Err(PartsListError::QuotaExceeded {
+                        limit,
+                        what: String::from("requests"),
+                    })
+                }
+                _ => quota.requests += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Count `count` parts about to be created with `key` against its quota,
+    /// refusing them when that would go over its limit
+    ///
+    /// Parts are counted before they are created, so requests creating parts
+    /// at the same time can't all pass the check. The count is given back by
+    /// `release_parts` when they aren't created after all.
+    pub fn reserve_parts(&mut self, key: Option<&str>, count: u64) -> Result<(), PartsListError> {
+        if let Some(quota) = self.current(key) {
+            match quota.limits.max_parts_created {
+                Some(limit) if quota.parts_created + count > limit => {
+                    return Err(PartsListError::QuotaExceeded {
+                        limit,
+                        what: String::from("parts created"),
+                    })
+                }
+                _ => quota.parts_created += count,
+            }
+        }
+        Ok(())
+    }
+
+    /// Give back parts reserved with `key` which weren't created
+    pub fn release_parts(&mut self, key: Option<&str>, count: u64) {
+        if let Some(quota) = self.current(key) {
+            quota.parts_created = quota.parts_created.saturating_sub(count);
+        }
+    }
+}
+
+/// API key of a request, which is the bearer token of its `Authorization`
+/// header as forwarded by an authenticating proxy
+///
+/// The server doesn't authenticate the key, so it only names the quota the
+/// request counts against rather than who sent it.
+pub(crate) fn api_key(request: &Request) -> Option<String> {
+    let header = request.headers().get_one("Authorization")?;
+    let mut words = header.splitn(2, ' ');
+    match (words.next(), words.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            Some(token.trim().into())
+        }
+        _ => None,
+    }
+}
+
+/// Description of why a request was refused by its quota, kept for the
+/// catcher replying to it
+#[derive(Default)]
+pub struct QuotaRejection(pub Option<String>);
+
+/// Request guard of routes changing the server, counting each request against
+/// the quota of the API key it was sent with
+///
+/// Requests over the quota are refused with `429 Too Many Requests` before
+/// reaching the route. Routes creating parts also reserve them through the
+/// guard before queuing their change.
+pub struct Metered<'r> {
+    key: Option<String>,
+    quotas: State<'r, QuotaRegistry>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Metered<'r> {
+    type Error = PartsListError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let quotas = match request.guard::<State<QuotaRegistry>>() {
+            Outcome::Success(quotas) => quotas,
+            _ => return Outcome::Failure((Status::InternalServerError, PartsListError::Unknown)),
+        };
+        let key = api_key(request);
+        let admitted = match quotas.lock() {
+            Some(mut quotas) => quotas.admit(key.as_deref()),
+            None => return Outcome::Failure((Status::InternalServerError, lock_error())),
+        };
+        match admitted {
+            Ok(()) => Outcome::Success(Metered { key, quotas }),
+            Err(e) => {
+                request.local_cache(|| QuotaRejection(Some(format!("{}", e))));
+                Outcome::Failure((Status::TooManyRequests, e))
+            }
+        }
+    }
+}
+
+impl Metered<'_> {
+    /// Reserve `count` parts about to be created by the request
+    pub fn reserve_parts(&self, count: u64) -> Result<(), PartsListError> {
+        match self.quotas.lock() {
+            Some(mut quotas) => quotas.reserve_parts(self.key.as_deref(), count),
+            None => Err(lock_error()),
+        }
+    }
+
+    /// Give back parts reserved by the request which weren't created
+    pub fn release_parts(&self, count: u64) {
+        if let Some(mut quotas) = self.quotas.lock() {
+            quotas.release_parts(self.key.as_deref(), count);
+        }
+    }
+}
+
+/// Secret an admin sends as the bearer token of requests to the admin
+/// routes, given by the `admin_token` setting
+pub struct AdminToken(pub Option<String>);
+
+/// Request guard of the `/v1/admin` routes, refusing with `403 Forbidden`
+/// requests not sent with the `admin_token` of the server, and every request
+/// while it isn't set
+pub struct Admin;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = PartsListError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let admin = match request.guard::<State<AdminToken>>() {
+            Outcome::Success(admin) => admin,
+            _ => return Outcome::Failure((Status::InternalServerError, PartsListError::Unknown)),
+        };
+        match (&admin.0, api_key(request)) {
+            (Some(token), Some(key)) if same_secret(token, &key) => Outcome::Success(Admin),
+            _ => Outcome::Failure((Status::Forbidden, PartsListError::NotAdmin)),
+        }
+    }
+}
+
+/// Compare secrets in a time depending only on their lengths, so guesses
+/// can't be refined by timing the replies
+pub(crate) fn same_secret(secret: &str, guess: &str) -> bool {
+    secret.len() == guess.len()
+        && secret
+            .bytes()
+            .zip(guess.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn lock_error() -> PartsListError {
+    PartsListError::Storage {
+        s: String::from("Couldn't lock quotas!"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc, Barrier};
+    use std::thread;
+
+    use super::*;
+
+    fn limits(max_requests: Option<u64>, max_parts_created: Option<u64>) -> QuotaLimits {
+        QuotaLimits {
+            max_requests,
+            max_parts_created,
+        }
+    }
+
+    #[test]
+    fn limits_keys_daily() {
+        let registry = QuotaRegistry::default();
+        let mut quotas = registry.lock().unwrap();
+        quotas.set("ci", limits(Some(2), Some(10))).unwrap();
+        quotas.admit(Some("ci")).unwrap();
+        quotas.admit(Some("ci")).unwrap();
+        assert_matches!(
+            quotas.admit(Some("ci")),
+            Err(PartsListError::QuotaExceeded { limit: 2, .. })
+        );
+        // keys without a quota share that of requests without a key, which
+        // is unlimited here
+        quotas.admit(Some("other")).unwrap();
+        quotas.reserve_parts(Some("other"), 1000).unwrap();
+        assert_matches!(quotas.get("other"), Err(PartsListError::QuotaDoesNotExist));
+
+        quotas.reserve_parts(Some("ci"), 8).unwrap();
+        assert!(quotas.reserve_parts(Some("ci"), 3).is_err());
+        // parts which weren't created are given back
+        quotas.reserve_parts(Some("ci"), 2).unwrap();
+        quotas.release_parts(Some("ci"), 2);
+        let quota = quotas.get("ci").unwrap();
+        assert_eq!((quota.requests, quota.parts_created), (2, 8));
+        assert_eq!(quota.resets_at % DAY_MILLIS, 0);
+
+        // raising the limit keeps what was used today
+        quotas.set("ci", limits(Some(3), None)).unwrap();
+        quotas.admit(Some("ci")).unwrap();
+        quotas.reserve_parts(Some("ci"), 1000).unwrap();
+        assert!(quotas.admit(Some("ci")).is_err());
+
+        // usage is counted afresh the next day
+        quotas.0.keys.get_mut("ci").unwrap().resets_at = 0;
+        quotas.admit(Some("ci")).unwrap();
+        assert_eq!(quotas.list()[0].requests, 1);
+
+        quotas.remove("ci").unwrap();
+        assert_matches!(quotas.get("ci"), Err(PartsListError::QuotaDoesNotExist));
+    }
+
+    #[test]
+    fn limits_anonymous_requests() {
+        let config = Config::build(rocket::config::Environment::Development)
+            .extra(
+                "anonymous_quota",
+                vec![
+                    ("max_requests".to_string(), Value::Integer(1)),
+                    ("max_parts_created".to_string(), Value::Integer(5)),
+                ]
+                .into_iter()
+                .collect::<std::collections::BTreeMap<String, Value>>(),
+            )
+            .unwrap();
+        let registry = QuotaRegistry::new(anonymous_config(&config).unwrap());
+        let mut quotas = registry.lock().unwrap();
+        quotas.admit(None).unwrap();
+        assert_matches!(
+            quotas.admit(None),
+            Err(PartsListError::QuotaExceeded { limit: 1, .. })
+        );
+        quotas.reserve_parts(None, 5).unwrap();
+        assert_matches!(
+            quotas.reserve_parts(None, 1),
+            Err(PartsListError::QuotaExceeded { limit: 5, .. })
+        );
+        // requests with a key of no quota count against the anonymous quota,
+        // which isn't listed with those of the keys
+        assert_matches!(
+            quotas.admit(Some("ci")),
+            Err(PartsListError::QuotaExceeded { limit: 1, .. })
+        );
+        assert!(quotas.reserve_parts(Some("made up"), 1).is_err());
+        assert!(quotas.list().is_empty());
+        // while keys with a quota count against their own
+        quotas.set("ci", limits(Some(1), None)).unwrap();
+        quotas.admit(Some("ci")).unwrap();
+
+        // and it is reset at midnight like any other
+        quotas.0.anonymous.as_mut().unwrap().resets_at = 0;
+        quotas.admit(None).unwrap();
+        quotas.reserve_parts(None, 5).unwrap();
+
+        // without the setting, requests without a key are unlimited
+        let registry = QuotaRegistry::new(anonymous_config(&Config::development()).unwrap());
+        let mut quotas = registry.lock().unwrap();
+        for _ in 0..10 {
+            quotas.admit(None).unwrap();
+        }
+
+        let config = Config::build(rocket::config::Environment::Development)
+            .extra(
+                "anonymous_quota",
+                vec![("max_requests".to_string(), Value::Integer(-1))]
+                    .into_iter()
+                    .collect::<std::collections::BTreeMap<String, Value>>(),
+            )
+            .unwrap();
+        assert_matches!(
+            anonymous_config(&config),
+            Err(PartsListError::Config { .. })
+        );
+    }
+
+    #[test]
+    fn concurrent_creates_stay_within_the_limit() {
+        let registry = Arc::new(QuotaRegistry::default());
+        registry
+            .lock()
+            .unwrap()
+            .set("ci", limits(None, Some(5)))
+            .unwrap();
+        // every request reserves its part before any of them makes its change,
+        // as they would with a busy writer thread
+        let barrier = Arc::new(Barrier::new(8));
+        let (created, creates) = mpsc::channel();
+        let requests: Vec<_> = (0..8)
+            .map(|_| {
+                let (registry, barrier, created) =
+                    (registry.clone(), barrier.clone(), created.clone());
+                thread::spawn(move || {
+                    let reserved = registry
+                        .lock()
+                        .unwrap()
+                        .reserve_parts(Some("ci"), 1)
+                        .is_ok();
+                    barrier.wait();
+                    created.send(reserved).unwrap();
+                })
+            })
+            .collect();
+        for request in requests {
+            request.join().unwrap();
+        }
+        drop(created);
+        assert_eq!(creates.iter().filter(|created| *created).count(), 5);
+        assert_eq!(registry.lock().unwrap().get("ci").unwrap().parts_created, 5);
+    }
+
+    #[test]
+    fn saved_to_file() {
+        let dir = std::env::temp_dir().join(format!("bom-quotas-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bom.quotas.json");
+        let anonymous = Some(limits(Some(1), None));
+        let registry = QuotaRegistry::open(&path, anonymous.clone()).unwrap();
+        let mut quotas = registry.lock().unwrap();
+        quotas.set("ci", limits(Some(2), Some(10))).unwrap();
+        quotas.set("nightly", limits(None, Some(100))).unwrap();
+        quotas.set("old", limits(Some(1), None)).unwrap();
+        quotas.remove("old").unwrap();
+        quotas.admit(Some("ci")).unwrap();
+        drop(quotas);
+
+        // limits are kept across restarts, while usage is counted afresh
+        let reopened = QuotaRegistry::open(&path, anonymous).unwrap();
+        let mut quotas = reopened.lock().unwrap();
+        let saved = quotas.list();
+        assert_eq!(
+            saved.iter().map(|q| q.key.as_str()).collect::<Vec<_>>(),
+            vec!["ci", "nightly"]
+        );
+        assert_eq!(saved[0].limits, limits(Some(2), Some(10)));
+        assert_eq!(saved[0].requests, 0);
+
+        // a change which can't be saved isn't made
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_matches!(
+            quotas.set("ci", limits(None, None)),
+            Err(PartsListError::Storage { .. })
+        );
+        assert_matches!(quotas.remove("ci"), Err(PartsListError::Storage { .. }));
+        assert_eq!(quotas.get("ci").unwrap().limits, limits(Some(2), Some(10)));
+    }
+
+    #[test]
+    fn compares_admin_token() {
+        assert!(same_secret("s3cret", "s3cret"));
+        assert!(!same_secret("s3cret", "s3cres"));
+        assert!(!same_secret("s3cret", "s3cret!"));
+        assert!(!same_secret("s3cret", ""));
+    }
+}
@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::config::Value;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Config, Outcome, State};
+use uuid::Uuid;
+
+use crate::parts_list::{PartEdit, PartsListError};
+use crate::quota::{api_key, same_secret};
+use crate::sidecar;
+use crate::store::PartsStore;
+
+/// Roles approving each release when the `release_roles` setting isn't given
+const DEFAULT_RELEASE_ROLES: [&str; 2] = ["engineering", "quality"];
+
+/// Stage of the release workflow an assembly submitted for release is in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseStatus {
+    InReview,
+    Released,
+    Rejected,
+}
+
+impl TryFrom<&str> for ReleaseStatus {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "in_review" => Ok(ReleaseStatus::InReview),
+            "released" => Ok(ReleaseStatus::Released),
+            "rejected" => Ok(ReleaseStatus::Rejected),
+            _ => Err(PartsListError::InvalidReleaseStatusString { s: s.into() }),
+        }
+    }
+}
+
+impl From<ReleaseStatus> for &str {
+    fn from(s: ReleaseStatus) -> &'static str {
+        match s {
+            ReleaseStatus::InReview => "in_review",
+            ReleaseStatus::Released => "released",
+            ReleaseStatus::Rejected => "rejected",
+        }
+    }
+}
+
+/// Stage of its life a part is in, which the release workflow moves to
+/// `Released` once every required role approves a release of it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifecycle {
+    #[default]
+    InWork,
+    Released,
+}
+
+impl TryFrom<&str> for Lifecycle {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "in_work" => Ok(Lifecycle::InWork),
+            "released" => Ok(Lifecycle::Released),
+            _ => Err(PartsListError::InvalidLifecycleString { s: s.into() }),
+        }
+    }
+}
+
+impl From<Lifecycle> for &str {
+    fn from(l: Lifecycle) -> &'static str {
+        match l {
+            Lifecycle::InWork => "in_work",
+            Lifecycle::Released => "released",
+        }
+    }
+}
+
+/// Sign off of a release by one of the roles it requires
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReleaseApproval {
+    pub role: String,
+    /// Time in milliseconds since the UNIX epoch the role approved at
+    pub approved: u64,
+}
+
+/// Request to release an assembly, which is released once every required role
+/// has approved it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Release {
+    pub id: Uuid,
+    pub assembly: Uuid,
+    pub description: String,
+    /// Version of the assembly when it was submitted, set by the server
+    pub base_version: u64,
+    /// Roles which must each approve the release, from the `release_roles`
+    /// setting when it was submitted
+    pub required_roles: Vec<String>,
+    pub approvals: Vec<ReleaseApproval>,
+    pub status: ReleaseStatus,
+    /// Times in milliseconds since the UNIX epoch at which the assembly was
+    /// submitted, released and rejected
+    pub created: u64,
+    pub released: Option<u64>,
+    pub rejected: Option<u64>,
+    /// Role which rejected the release
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_by: Option<String>,
+}
+
+impl Release {
+    /// Fail unless `role` is one of the roles the release requires
+    fn check_role(&self, role: &str) -> Result<(), PartsListError> {
+        if self.required_roles.iter().any(|required| required == role) {
+            Ok(())
+        } else {
+            Err(PartsListError::ReleaseRoleNotRequired {
+                id: self.id,
+                role: role.into(),
+            })
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Roles which must approve each release, given by the `release_roles` setting
+/// of the Rocket configuration, such as `["engineering", "quality"]`
+pub fn roles_config(config: &Config) -> Result<Vec<String>, PartsListError> {
+    let roles = match config.get_slice("release_roles") {
+        Ok(roles) => roles,
+        Err(_) => {
+            return Ok(DEFAULT_RELEASE_ROLES
+                .iter()
+                .map(|&role| role.into())
+                .collect())
+        }
+    };
+    let invalid = || PartsListError::Config {
+        s: String::from("release_roles must be a list of role names"),
+    };
+    if roles.is_empty() {
+        return Err(invalid());
+    }
+    roles
+        .iter()
+        .map(|role| match role {
+            Value::String(role) if !role.is_empty() => Ok(role.clone()),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// Token of each release role, given by the `release_tokens` setting of the
+/// Rocket configuration, such as `{ engineering = "...", quality = "..." }`
+///
+/// Every role named must be one of `roles`, and each must be given its own
+/// token so the token of a request names a single role. Roles without a
+/// token can't approve or reject releases.
+pub fn tokens_config(
+    config: &Config,
+    roles: &[String],
+) -> Result<Vec<(String, String)>, PartsListError> {
+    let table = match config.get_table("release_tokens") {
+        Ok(table) => table,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut tokens: Vec<(String, String)> = Vec::new();
+    for (role, token) in table {
+        let token = match token {
+            Value::String(token) if !token.is_empty() => token,
+            _ => {
+                return Err(PartsListError::Config {
+                    s: format!("release_tokens: the token of {:?} must be a string", role),
+                })
+            }
+        };
+        if !roles.contains(role) {
+            return Err(PartsListError::Config {
+                s: format!("release_tokens: {:?} isn't one of the release_roles", role),
+            });
+        }
+        if tokens.iter().any(|(_, other)| other == token) {
+            return Err(PartsListError::Config {
+                s: String::from("release_tokens: each role must have its own token"),
+            });
+        }
+        tokens.push((role.clone(), token.clone()));
+    }
+    Ok(tokens)
+}
+
+/// Assemblies submitted for release, held in memory and, like ECOs, saved to a
+/// sidecar file after every change when the parts are stored outside of
+/// memory
+///
+/// Clones share the releases, so approvals can release assemblies on the
+/// writer thread.
+#[derive(Clone)]
+pub struct ReleaseRegistry {
+    releases: Arc<Mutex<HashMap<Uuid, Release>>>,
+    roles: Vec<String>,
+    /// Token of each role allowed to approve and reject releases
+    tokens: Vec<(String, String)>,
+    path: Option<PathBuf>,
+}
+
+/// Locked access to the releases of a registry, along with the roles they
+/// require and the file they are saved to if any
+pub struct Releases<'a>(
+    MutexGuard<'a, HashMap<Uuid, Release>>,
+    &'a [String],
+    Option<&'a Path>,
+);
+
+impl Default for ReleaseRegistry {
+    fn default() -> Self {
+        ReleaseRegistry::new(
+            DEFAULT_RELEASE_ROLES
+                .iter()
+                .map(|&role| role.into())
+                .collect(),
+        )
+    }
+}
+
+impl ReleaseRegistry {
+    /// Registry of releases each requiring the approval of every one of
+    /// `roles`
+    pub fn new(roles: Vec<String>) -> ReleaseRegistry {
+        ReleaseRegistry {
+            releases: Arc::new(Mutex::new(HashMap::new())),
+            roles,
+            tokens: Vec::new(),
+            path: None,
+        }
+    }
+
+    /// Registry saving its releases to the file at `path`, starting with those
+    /// saved there before
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        roles: Vec<String>,
+    ) -> Result<ReleaseRegistry, PartsListError> {
+        let path = path.as_ref();
+        let releases: Vec<Release> = sidecar::load(path)?;
+        Ok(ReleaseRegistry {
+            releases: Arc::new(Mutex::new(
+                releases
+                    .into_iter()
+                    .map(|release| (release.id, release))
+                    .collect(),
+            )),
+            roles,
+            tokens: Vec::new(),
+            path: Some(path.into()),
+        })
+    }
+
+    /// Let the roles given in `tokens` approve and reject releases by sending
+    /// their token as the bearer token of the request
+    pub fn with_tokens(mut self, tokens: Vec<(String, String)>) -> ReleaseRegistry {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Role whose token is `token`, if any
+    fn role_of(&self, token: &str) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|(_, secret)| same_secret(secret, token))
+            .map(|(role, _)| role.as_str())
+    }
+
+    /// Lock the releases, failing only if a previous holder of the lock
+    /// panicked
+    pub fn lock(&self) -> Option<Releases<'_>> {
+        let path = self.path.as_deref();
+        self.releases
+            .lock()
+            .ok()
+            .map(|releases| Releases(releases, &self.roles, path))
+    }
+}
+
+impl<'a> Releases<'a> {
+    /// Submit `assembly` for release, recording its version so changes made to
+    /// it while it is reviewed are detected
+    ///
+    /// An assembly can only be in review once at a time.
+    pub fn submit(
+        &mut self,
+        parts: &dyn PartsStore,
+        assembly: Uuid,
+        description: &str,
+    ) -> Result<Release, PartsListError> {
+        let base_version = parts.get(&assembly)?.version;
+        if let Some(pending) = self.0.values().find(|release| {
+            release.assembly == assembly && release.status == ReleaseStatus::InReview
+        }) {
+            return Err(PartsListError::ReleaseInReview {
+                id: pending.id,
+                assembly,
+            });
+        }
+        let release = Release {
+            id: Uuid::new_v4(),
+            assembly,
+            description: description.into(),
+            base_version,
+            required_roles: self.1.to_vec(),
+            approvals: Vec::new(),
+            status: ReleaseStatus::InReview,
+            created: now(),
+            released: None,
+            rejected: None,
+            rejected_by: None,
+        };
+        self.save(|releases| {
+            releases.insert(release.id, release.clone());
+        })?;
+        Ok(release)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<Release, PartsListError> {
+        self.0
+            .get(id)
+            .cloned()
+            .ok_or(PartsListError::ReleaseDoesNotExist { id: *id })
+    }
+
+    /// List releases, optionally only those of an assembly or in a status,
+    /// oldest first
+    pub fn list(&self, assembly: Option<Uuid>, status: Option<ReleaseStatus>) -> Vec<Release> {
+        let mut releases: Vec<Release> = self
+            .0
+            .values()
+            .filter(|release| assembly.is_none_or(|assembly| release.assembly == assembly))
+            .filter(|release| status.is_none_or(|status| release.status == status))
+            .cloned()
+            .collect();
+        releases.sort_by_key(|release| (release.created, release.id));
+        releases
+    }
+
+    /// Replace every release with those of a backup
+    pub fn replace_all(&mut self, releases: Vec<Release>) -> Result<(), PartsListError> {
+        self.save(|replaced| {
+            *replaced = releases
+                .into_iter()
+                .map(|release| (release.id, release))
+                .collect();
+        })
+    }
+
+    /// Get a release in review, failing if it has been released or rejected
+    fn get_in_review(&self, id: &Uuid, action: &str) -> Result<Release, PartsListError> {
+        let release = self.get(id)?;
+        if release.status != ReleaseStatus::InReview {
+            return Err(PartsListError::ReleaseStatusMismatch {
+                id: *id,
+                status: Into::<&str>::into(release.status).into(),
+                action: action.into(),
+            });
+        }
+        Ok(release)
+    }
+
+    /// Record the approval of `role`, moving the assembly to the released
+    /// lifecycle state once every required role has approved it
+    ///
+    /// Approvals are refused once the assembly has changed since it was
+    /// submitted, as they would sign off a different assembly than the one
+    /// reviewed.
+    pub fn approve(
+        &mut self,
+        parts: &mut dyn PartsStore,
+        id: &Uuid,
+        role: &str,
+    ) -> Result<Release, PartsListError> {
+        let mut release = self.get_in_review(id, "approved")?;
+        release.check_role(role)?;
+        if release
+            .approvals
+            .iter()
+            .any(|approval| approval.role == role)
+        {
+            return Err(PartsListError::ReleaseRoleApproved {
+                id: *id,
+                role: role.into(),
+            });
+        }
+        parts.expect_version(&release.assembly, release.base_version)?;
+        release.approvals.push(ReleaseApproval {
+            role: role.into(),
+            approved: now(),
+        });
+        let approved = |role: &String| {
+            release
+                .approvals
+                .iter()
+                .any(|approval| approval.role == *role)
+        };
+        if release.required_roles.iter().all(approved) {
+            // the assembly is already released, so a release which can't be
+            // saved as released fails to be approved again on its version
+            let released = PartEdit {
+                lifecycle: Some(Lifecycle::Released),
+                ..PartEdit::default()
+            };
+            parts.edit(&release.assembly, &released)?;
+            release.status = ReleaseStatus::Released;
+            release.released = Some(now());
+        }
+        self.save(|releases| {
+            releases.insert(release.id, release.clone());
+        })?;
+        Ok(release)
+    }
+
+    /// Turn down a release in review on behalf of one of the roles it requires
+    pub fn reject(&mut self, id: &Uuid, role: &str) -> Result<Release, PartsListError> {
+        let mut release = self.get_in_review(id, "rejected")?;
+        release.check_role(role)?;
+        release.status = ReleaseStatus::Rejected;
+        release.rejected = Some(now());
+        release.rejected_by = Some(role.into());
+        self.save(|releases| {
+            releases.insert(release.id, release.clone());
+        })?;
+        Ok(release)
+    }
+
+    /// Make a change to a copy of the releases and save it before it replaces
+    /// them, so a change which can't be saved isn't made
+    fn save<F>(&mut self, change: F) -> Result<(), PartsListError>
+    where
+        F: FnOnce(&mut HashMap<Uuid, Release>),
+    {
+        let mut releases = self.0.clone();
+        change(&mut releases);
+        if let Some(path) = self.2 {
+            let mut saved: Vec<&Release> = releases.values().collect();
+            saved.sort_by_key(|release| (release.created, release.id));
+            sidecar::save(path, &saved)?;
+        }
+        *self.0 = releases;
+        Ok(())
+    }
+}
+
+/// Description of why a request was refused as not sent by a release role,
+/// kept for the catcher replying to it
+#[derive(Default)]
+pub struct ApproverRejection(pub Option<String>);
+
+/// Request guard of the routes approving and rejecting releases, giving the
+/// role whose token from the `release_tokens` setting the request was sent
+/// with as its bearer token
+///
+/// Requests sent without the token of a role are refused with
+/// `403 Forbidden`, so approvals are only ever recorded for the role which
+/// sent them.
+pub struct ReleaseApprover(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ReleaseApprover {
+    type Error = PartsListError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let releases = match request.guard::<State<ReleaseRegistry>>() {
+            Outcome::Success(releases) => releases,
+            _ => return Outcome::Failure((Status::InternalServerError, PartsListError::Unknown)),
+        };
+        match api_key(request).and_then(|key| releases.role_of(&key).map(String::from)) {
+            Some(role) => Outcome::Success(ReleaseApprover(role)),
+            None => {
+                let e = PartsListError::NotReleaseApprover;
+                request.local_cache(|| ApproverRejection(Some(format!("{}", e))));
+                Outcome::Failure((Status::Forbidden, e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{Part, PartEdit, PartsList};
+
+    fn roles() -> Vec<String> {
+        vec!["engineering".into(), "quality".into()]
+    }
+
+    #[test]
+    fn released_once_every_role_approves() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let registry = ReleaseRegistry::new(roles());
+        let mut releases = registry.lock().unwrap();
+
+        let release = releases.submit(&parts, assy, "first build").unwrap();
+        assert_eq!(release.status, ReleaseStatus::InReview);
+        assert_matches!(
+            releases.submit(&parts, assy, "again"),
+            Err(PartsListError::ReleaseInReview { id, .. }) if id == release.id
+        );
+
+        let approved = releases
+            .approve(&mut parts, &release.id, "quality")
+            .unwrap();
+        assert_eq!(approved.status, ReleaseStatus::InReview);
+        assert_matches!(
+            releases.approve(&mut parts, &release.id, "quality"),
+            Err(PartsListError::ReleaseRoleApproved { .. })
+        );
+        assert_matches!(
+            releases.approve(&mut parts, &release.id, "purchasing"),
+            Err(PartsListError::ReleaseRoleNotRequired { .. })
+        );
+
+        assert_eq!(parts.get(&assy).unwrap().lifecycle, Lifecycle::InWork);
+        let released = releases
+            .approve(&mut parts, &release.id, "engineering")
+            .unwrap();
+        assert_eq!(released.status, ReleaseStatus::Released);
+        assert_eq!(parts.get(&assy).unwrap().lifecycle, Lifecycle::Released);
+        assert!(released.released.is_some());
+        assert_eq!(released.approvals.len(), 2);
+        assert_eq!(
+            releases.list(Some(assy), Some(ReleaseStatus::Released)),
+            vec![released]
+        );
+
+        // a released assembly is done with, and can be submitted again
+        assert_matches!(
+            releases.reject(&release.id, "quality"),
+            Err(PartsListError::ReleaseStatusMismatch { .. })
+        );
+        assert!(releases.submit(&parts, assy, "second build").is_ok());
+    }
+
+    #[test]
+    fn rejected_or_changed_assemblies_arent_released() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let registry = ReleaseRegistry::new(roles());
+        let mut releases = registry.lock().unwrap();
+
+        let release = releases.submit(&parts, assy, "first build").unwrap();
+        assert_matches!(
+            releases.reject(&release.id, "purchasing"),
+            Err(PartsListError::ReleaseRoleNotRequired { .. })
+        );
+        let rejected = releases.reject(&release.id, "quality").unwrap();
+        assert_eq!(rejected.status, ReleaseStatus::Rejected);
+        assert_eq!(rejected.rejected_by.as_deref(), Some("quality"));
+        assert_matches!(
+            releases.approve(&mut parts, &release.id, "engineering"),
+            Err(PartsListError::ReleaseStatusMismatch { .. })
+        );
+
+        // approvals are refused once the assembly changes under review
+        let release = releases.submit(&parts, assy, "second build").unwrap();
+        let edit = PartEdit {
+            phantom: Some(true),
+            ..PartEdit::default()
+        };
+        parts.edit(&assy, &edit).unwrap();
+        assert_matches!(
+            releases.approve(&mut parts, &release.id, "engineering"),
+            Err(PartsListError::VersionConflict { .. })
+        );
+        assert_matches!(
+            releases.approve(&mut parts, &Uuid::new_v4(), "engineering"),
+            Err(PartsListError::ReleaseDoesNotExist { .. })
+        );
+    }
+
+    #[test]
+    fn saved_to_file() {
+        let mut parts = PartsList::new();
+        let assy = parts.add(Part::new("assy")).unwrap().id;
+        let dir = std::env::temp_dir().join(format!("bom-releases-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bom.releases.json");
+
+        let registry = ReleaseRegistry::open(&path, roles()).unwrap();
+        let mut releases = registry.lock().unwrap();
+        let release = releases.submit(&parts, assy, "first build").unwrap();
+        releases
+            .approve(&mut parts, &release.id, "quality")
+            .unwrap();
+        let saved = releases.list(None, None);
+        drop(releases);
+
+        let reopened = ReleaseRegistry::open(&path, roles()).unwrap();
+        assert_eq!(reopened.lock().unwrap().list(None, None), saved);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_roles() {
+        let config = Config::build(rocket::config::Environment::Development)
+            .extra("release_roles", vec!["design", "quality", "purchasing"])
+            .unwrap();
+        assert_eq!(roles_config(&config).unwrap().len(), 3);
+
+        let config = Config::build(rocket::config::Environment::Development)
+            .extra("release_roles", Vec::<String>::new())
+            .unwrap();
+        assert_matches!(roles_config(&config), Err(PartsListError::Config { .. }));
+    }
+
+    #[test]
+    fn reads_tokens() {
+        let tokens = |tokens: Vec<(&str, Value)>| {
+            let config = Config::build(rocket::config::Environment::Development)
+                .extra(
+                    "release_tokens",
+                    tokens
+                        .into_iter()
+                        .map(|(role, token)| (role.to_string(), token))
+                        .collect::<std::collections::BTreeMap<String, Value>>(),
+                )
+                .unwrap();
+            tokens_config(&config, &roles())
+        };
+        let registry = ReleaseRegistry::new(roles()).with_tokens(
+            tokens(vec![
+                ("engineering", "e-secret".into()),
+                ("quality", "q-secret".into()),
+            ])
+            .unwrap(),
+        );
+        assert_eq!(registry.role_of("q-secret"), Some("quality"));
+        assert_eq!(registry.role_of("e-secret"), Some("engineering"));
+        assert_eq!(registry.role_of("quality"), None);
+        assert_eq!(registry.role_of(""), None);
+
+        // roles without a token can't approve at all
+        assert!(tokens_config(&Config::development(), &roles())
+            .unwrap()
+            .is_empty());
+        assert_matches!(
+            tokens(vec![("purchasing", "p-secret".into())]),
+            Err(PartsListError::Config { .. })
+        );
+        assert_matches!(
+            tokens(vec![
+                ("engineering", "shared".into()),
+                ("quality", "shared".into())
+            ]),
+            Err(PartsListError::Config { .. })
+        );
+        assert_matches!(
+            tokens(vec![("quality", Value::Integer(1))]),
+            Err(PartsListError::Config { .. })
+        );
+    }
+}
@@ -0,0 +1,363 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Runtime};
+use url::Url;
+
+use crate::client::{self, ClientContext};
+use crate::cost;
+use crate::errors::PartsErrorCode;
+use crate::parts_list::{Part, PartsListError};
+use crate::response::Response;
+use crate::store::wal::{ChangeFeed, ChangeJournal};
+use crate::store::PartsStore;
+use crate::SharedPartsList;
+
+/// Progress of a follower copying the parts of its leader
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReplicationStatus {
+    /// Url of the leader the parts are copied from
+    pub leader: String,
+    /// Number of the last change of the leader applied, until the parts are
+    /// first copied
+    pub cursor: Option<u64>,
+    /// Time the follower last caught up with the leader, in milliseconds
+    /// since the UNIX epoch
+    pub synced_at: Option<u64>,
+    /// Whether replication stopped as the follower was changed other than by
+    /// replication
+    pub conflict: bool,
+    /// Why the last attempt to catch up failed
+    pub error: Option<String>,
+}
+
+/// Status of replication shared between the follower thread and the routes
+/// reporting it, which is empty when the server doesn't follow a leader
+#[derive(Clone, Default)]
+pub struct Replication(Option<Arc<Mutex<ReplicationStatus>>>);
+
+impl Replication {
+    pub fn status(&self) -> Result<ReplicationStatus, PartsListError> {
+        let status = self.0.as_ref().ok_or(PartsListError::NotReplicating)?;
+        status
+            .lock()
+            .map(|status| status.clone())
+            .map_err(|_| PartsListError::Storage {
+                s: String::from("Couldn't lock replication status!"),
+            })
+    }
+
+    fn update(&self, update: impl FnOnce(&mut ReplicationStatus)) {
+        if let Some(Ok(mut status)) = self.0.as_ref().map(|status| status.lock()) {
+            update(&mut status);
+        }
+    }
+}
+
+/// Where a follower is in the changes of its leader and in its own journal
+#[derive(Clone, Copy, Default)]
+struct Position {
+    /// Number of the last change of the leader applied
+    leader: Option<u64>,
+    /// Number of the last change in the journal of the follower once it last
+    /// caught up, which any other write moves past
+    local: Option<u64>,
+}
+
+impl Position {
+    /// Check that nothing but replication changed the follower since it last
+    /// caught up
+    fn check_local(&self, journal: &ChangeJournal) -> Result<(), PartsListError> {
+        match self.local {
+            Some(local) if journal.cursor()? != local => Err(PartsListError::ReplicationConflict {
+                cursor: self.leader.unwrap_or(0),
+                s: String::from("the follower was written to"),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replace the parts of the follower with those of the leader as of the
+    /// change numbered `cursor`
+    fn copy(
+        &mut self,
+        store: &mut dyn PartsStore,
+        journal: &ChangeJournal,
+        cursor: u64,
+        parts: Vec<Part>,
+    ) -> Result<(), PartsListError> {
+        self.check_local(journal)?;
+        store.replace_all(parts)?;
+        self.leader = Some(cursor);
+        self.local = Some(journal.cursor()?);
+        Ok(())
+    }
+
+    /// Apply the changes of the leader in order, stopping at the first which
+    /// doesn't apply to the parts of the follower
+    fn follow(
+        &mut self,
+        store: &mut dyn PartsStore,
+        journal: &ChangeJournal,
+        feed: ChangeFeed,
+    ) -> Result<(), PartsListError> {
+        self.check_local(journal)?;
+        for change in feed.changes {
+            let seq = change.seq;
+            change
+                .op
+                .apply(store)
+                .map_err(|e| PartsListError::ReplicationConflict {
+                    cursor: seq,
+                    s: format!("{}", e),
+                })?;
+            self.leader = Some(seq);
+            self.local = Some(journal.cursor()?);
+        }
+        self.leader = Some(feed.cursor);
+        Ok(())
+    }
+}
+
+/// Follower keeping a copy of the parts of a leader bom-server by pulling
+/// its change feed every interval
+///
+/// The follower must store its parts with the `wal` backend, whose journal
+/// shows whether anything but replication wrote to it. Replication stops
+/// once it has, or once a change of the leader can't be applied, rather than
+/// let the copy drift from the leader. The parts are copied afresh when the
+/// follower starts and whenever it falls further behind than the leader
+/// journals.
+pub struct Follower {
+    leader: Url,
+    token: Option<String>,
+    interval: Duration,
+}
+
+impl Follower {
+    pub fn new(leader: Url, token: Option<String>, interval: Duration) -> Follower {
+        Follower {
+            leader,
+            token,
+            interval,
+        }
+    }
+
+    /// Copy the leader into `parts` from a background thread, returning the
+    /// status it keeps up to date
+    pub fn spawn(self, parts: SharedPartsList) -> Result<Replication, PartsListError> {
+        let journal = parts.changes()?.clone();
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let status = Arc::new(Mutex::new(ReplicationStatus {
+            leader: self.leader.to_string(),
+            cursor: None,
+            synced_at: None,
+            conflict: false,
+            error: None,
+        }));
+        let replication = Replication(Some(status));
+        let shared = replication.clone();
+        thread::spawn(move || self.run(&runtime, &parts, &journal, &shared));
+        Ok(replication)
+    }
+
+    fn run(
+        &self,
+        runtime: &Runtime,
+        parts: &SharedPartsList,
+        journal: &ChangeJournal,
+        replication: &Replication,
+    ) {
+        let mut context = ClientContext::new(self.leader.clone());
+        if let Some(token) = &self.token {
+            context = context.with_token(token.clone());
+        }
+        let mut position = Position::default();
+        loop {
+            let more = match self.sync(runtime, &context, parts, journal, &mut position) {
+                Ok(more) => {
+                    replication.update(|status| {
+                        status.cursor = position.leader;
+                        status.error = None;
+                        if !more {
+                            status.synced_at = Some(cost::now());
+                        }
+                    });
+                    more
+                }
+                Err(e) => {
+                    let conflict = matches!(
+                        e.downcast_ref::<PartsListError>(),
+                        Some(PartsListError::ReplicationConflict { .. })
+                    );
+                    replication.update(|status| {
+                        status.cursor = position.leader;
+                        status.conflict = conflict;
+                        status.error = Some(format!("{}", e));
+                    });
+                    if conflict {
+                        tracing::error!("Stopping replication: {}", e);
+                        return;
+                    }
+                    tracing::warn!("Failed to replicate from {}: {}", self.leader, e);
+                    false
+                }
+            };
+            if !more {
+                thread::sleep(self.interval);
+            }
+        }
+    }
+
+    /// Catch up with the leader, returning whether it has more changes to
+    /// fetch straight away
+    fn sync(
+        &self,
+        runtime: &Runtime,
+        context: &ClientContext,
+        parts: &SharedPartsList,
+        journal: &ChangeJournal,
+        position: &mut Position,
+    ) -> anyhow::Result<bool> {
+        let since = match position.leader {
+            Some(since) => since,
+            None => {
+                let response =
+                    leader_reply(runtime.block_on(client::get_change_snapshot(context))?)?;
+                let cursor = response.changes.map(|feed| feed.cursor).unwrap_or(0);
+                let copied = response.data.unwrap_or_default();
+                advance(parts, journal, position, move |position, store, journal| {
+                    position.copy(store, journal, cursor, copied)
+                })?;
+                return Ok(false);
+            }
+        };
+        let response = runtime.block_on(client::get_changes(context, since, None))?;
+        if let Some(PartsErrorCode::ChangesExpired) = response.error.as_ref().map(|e| *e.code()) {
+            // copy the parts afresh straight away
+            position.leader = None;
+            return Ok(true);
+        }
+        let feed = match leader_reply(response)?.changes {
+            Some(feed) => feed,
+            None => anyhow::bail!("Leader replied without changes"),
+        };
+        if feed.changes.is_empty() {
+            return Ok(false);
+        }
+        let more = feed.more;
+        advance(parts, journal, position, move |position, store, journal| {
+            position.follow(store, journal, feed)
+        })?;
+        Ok(more)
+    }
+}
+
+/// Have the writer thread change the parts of the follower by a `step` of
+/// replication, keeping how far it got even when it fails part way
+fn advance<F>(
+    parts: &SharedPartsList,
+    journal: &ChangeJournal,
+    position: &mut Position,
+    step: F,
+) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Position, &mut dyn PartsStore, &ChangeJournal) -> Result<(), PartsListError>
+        + Send
+        + 'static,
+{
+    let mut moved = *position;
+    let journal = journal.clone();
+    let (moved, stepped) = parts.change(move |store| {
+        let stepped = step(&mut moved, &mut **store, &journal);
+        (moved, stepped)
+    })?;
+    *position = moved;
+    Ok(stepped?)
+}
+
+/// Reply of the leader, failing when it reported an error
+fn leader_reply(response: Response) -> anyhow::Result<Response> {
+    match response.error {
+        Some(e) => Err(anyhow::anyhow!("Leader replied with an error: {}", e)),
+        None => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{DuplicateNamePolicy, PartsListUpdate};
+    use crate::store::wal::WalStore;
+
+    fn temp_log() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bom-replica-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn cleanup(path: &std::path::Path) {
+        let _ = std::fs::remove_file(path);
+        let mut snapshot = path.to_path_buf().into_os_string();
+        snapshot.push(".snapshot");
+        let _ = std::fs::remove_file(snapshot);
+    }
+
+    #[test]
+    fn follower_detects_local_writes() {
+        let (leader_path, follower_path) = (temp_log(), temp_log());
+        let mut leader = WalStore::open(&leader_path, DuplicateNamePolicy::Reject, 100).unwrap();
+        let mut follower =
+            WalStore::open(&follower_path, DuplicateNamePolicy::Reject, 100).unwrap();
+        let (changes, journal) = (leader.journal(), follower.journal());
+        let mut position = Position::default();
+
+        // the follower starts from a copy of the leader
+        let assy = leader.insert(Part::new("assy")).unwrap();
+        follower.insert(Part::new("stale")).unwrap();
+        position
+            .copy(&mut follower, &journal, 1, leader.parts().unwrap())
+            .unwrap();
+        assert_eq!(follower.parts().unwrap().len(), 1);
+
+        let screw = leader.insert(Part::new("screw")).unwrap();
+        leader
+            .update(&assy.id, &[&screw.id], PartsListUpdate::Add)
+            .unwrap();
+        position
+            .follow(&mut follower, &journal, changes.since(1, 100).unwrap())
+            .unwrap();
+        assert_eq!(position.leader, Some(3));
+        let children = follower
+            .get_children(&assy.id, crate::parts_list::PartsListFilter::All)
+            .unwrap();
+        assert_eq!(children[0].id, screw.id);
+
+        // a change which doesn't apply to the follower stops replication
+        follower.remove(&screw.id).unwrap();
+        let nut = leader.insert(Part::new("nut")).unwrap();
+        assert_matches!(
+            position.follow(&mut follower, &journal, changes.since(3, 100).unwrap()),
+            Err(PartsListError::ReplicationConflict { cursor: 3, .. })
+        );
+        // treat the removal as replicated to reach the change which fails
+        position.local = Some(journal.cursor().unwrap());
+        leader
+            .update(&screw.id, &[&nut.id], PartsListUpdate::Add)
+            .unwrap();
+        assert_matches!(
+            position.follow(&mut follower, &journal, changes.since(3, 100).unwrap()),
+            Err(PartsListError::ReplicationConflict { cursor: 5, .. })
+        );
+        assert_eq!(position.leader, Some(4));
+
+        // as does writing to the follower, even when copying afresh
+        follower.insert(Part::new("local")).unwrap();
+        assert_matches!(
+            position.copy(&mut follower, &journal, 5, leader.parts().unwrap()),
+            Err(PartsListError::ReplicationConflict { .. })
+        );
+        assert!(follower.get_by_name("local").is_ok());
+        cleanup(&leader_path);
+        cleanup(&follower_path);
+    }
+}
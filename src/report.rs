@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use askama::Template;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, Stream};
+use uuid::Uuid;
+
+use crate::instructions;
+use crate::inventory;
+use crate::parts_list::{Part, PartsListError, PartsListFilter};
+use crate::routing::Routing;
+use crate::store::PartsStore;
+
+/// Width and height of the pages of PDF reports, being US letter in points
+const PAGE_SIZE: (f64, f64) = (612.0, 792.0);
+
+/// Space left around the text of each page of PDF reports, in points
+const PAGE_MARGIN: f64 = 54.0;
+
+/// Longest part name printed in PDF reports before it is cut short
+const MAX_PDF_NAME: usize = 48;
+
+/// Line of text in a PDF report, made of a font size and the text starting at
+/// each horizontal offset
+type PdfLine = (f64, Vec<(f64, String)>);
+
+/// Line of an indented BOM, placing a part below its parent
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportRow {
+    /// Depth of the part below the assembly, which is at level 0
+    pub level: usize,
+    pub id: Uuid,
+    pub name: String,
+    /// Quantity of the part used by its parent on this line
+    pub quantity: u64,
+    pub on_hand: u64,
+    pub location: String,
+    pub phantom: bool,
+}
+
+/// Self-contained HTML page describing an assembly, with its indented BOM and
+/// totals rolled up over it
+#[derive(Template)]
+#[template(path = "report.html")]
+pub struct BomReport {
+    pub assembly: Part,
+    pub rows: Vec<ReportRow>,
+    /// Number of distinct parts used by the assembly, not counting itself
+    pub distinct_parts: usize,
+    /// Deepest level of the indented BOM
+    pub depth: usize,
+    /// Total quantity of components pulled from stock to build one
+    pub components: u64,
+    /// Number of components with too little stock to build one
+    pub shortages: usize,
+    /// Operations the assembly is built with, empty when it has no routing
+    pub routing: Routing,
+    /// Work instructions of the assembly rendered as HTML, empty when it has
+    /// none
+    pub instructions: String,
+}
+
+impl BomReport {
+    /// Gather the report for an assembly
+    pub fn new(parts: &dyn PartsStore, id: &Uuid) -> Result<BomReport, PartsListError> {
+        let assembly = parts.get(id)?;
+        let descendants = parts.get_children(id, PartsListFilter::All)?;
+        let rows = indented(&assembly, &descendants);
+        let components = inventory::explode(parts, id, 1, None)?
+            .iter()
+            .map(|(_, quantity)| quantity)
+            .sum();
+        Ok(BomReport {
+            distinct_parts: descendants.len(),
+            depth: rows.iter().map(|row| row.level).max().unwrap_or_default(),
+            components,
+            shortages: inventory::shortages(parts, id, 1, None)?.len(),
+            routing: Routing::of(&assembly),
+            instructions: instructions::to_html(&assembly.instructions),
+            assembly,
+            rows,
+        })
+    }
+
+    /// Render the report as a printable PDF document, with the summary on the
+    /// first page followed by the indented BOM over as many pages as needed
+    pub fn to_pdf(&self) -> Result<Vec<u8>, lopdf::Error> {
+        let mut lines: Vec<PdfLine> = vec![
+            (16.0, vec![(0.0, self.assembly.name.clone())]),
+            (
+                9.0,
+                vec![(
+                    0.0,
+                    format!("{} (version {})", self.assembly.id, self.assembly.version),
+                )],
+            ),
+            (10.0, Vec::new()),
+        ];
+        for (label, value) in &[
+            ("Distinct parts", self.distinct_parts as u64),
+            ("Levels", self.depth as u64),
+            ("Components to build one", self.components),
+            ("Components short of stock", self.shortages as u64),
+        ] {
+            lines.push((
+                10.0,
+                vec![(0.0, label.to_string()), (180.0, value.to_string())],
+            ));
+        }
+        lines.push((10.0, Vec::new()));
+        let columns = [0.0, 40.0, 330.0, 370.0, 420.0];
+        lines.push((
+            10.0,
+            ["Level", "Part", "Qty", "On hand", "Location"]
+                .iter()
+                .zip(&columns)
+                .map(|(header, x)| (*x, header.to_string()))
+                .collect(),
+        ));
+        for row in &self.rows {
+            let mut name: String = row.name.chars().take(MAX_PDF_NAME).collect();
+            if row.phantom {
+                name.push_str(" (phantom)");
+            }
+            lines.push((
+                10.0,
+                vec![
+                    (columns[0], row.level.to_string()),
+                    (columns[1] + 10.0 * row.level.min(12) as f64, name),
+                    (columns[2], row.quantity.to_string()),
+                    (columns[3], row.on_hand.to_string()),
+                    (columns[4], row.location.clone()),
+                ],
+            ));
+        }
+        if !self.routing.steps.is_empty() {
+            lines.push((10.0, Vec::new()));
+            let columns = [0.0, 40.0, 200.0, 330.0, 400.0];
+            lines.push((
+                10.0,
+                ["Step", "Operation", "Work center", "Setup min", "Run min"]
+                    .iter()
+                    .zip(&columns)
+                    .map(|(header, x)| (*x, header.to_string()))
+                    .collect(),
+            ));
+            for (position, step) in self.routing.steps.iter().enumerate() {
+                lines.push((
+                    10.0,
+                    vec![
+                        (columns[0], (position + 1).to_string()),
+                        (columns[1], step.operation.chars().take(28).collect()),
+                        (columns[2], step.work_center.chars().take(24).collect()),
+                        (columns[3], step.setup_minutes.to_string()),
+                        (columns[4], step.run_minutes.to_string()),
+                    ],
+                ));
+            }
+            lines.push((
+                10.0,
+                vec![
+                    (columns[1], String::from("Total")),
+                    (columns[3], self.routing.setup_minutes.to_string()),
+                    (columns[4], self.routing.run_minutes.to_string()),
+                ],
+            ));
+        }
+        write_pdf(&lines)
+    }
+}
+
+/// Write lines of text in Helvetica onto as many pages as they need
+fn write_pdf(lines: &[PdfLine]) -> Result<Vec<u8>, lopdf::Error> {
+    let (width, height) = PAGE_SIZE;
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let resources_id = document.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+
+    let mut pages: Vec<Vec<Operation>> = vec![Vec::new()];
+    let mut y = height - PAGE_MARGIN;
+    for (size, texts) in lines {
+        let spacing = size * 1.4;
+        if y - spacing < PAGE_MARGIN {
+            pages.push(Vec::new());
+            y = height - PAGE_MARGIN;
+        }
+        y -= spacing;
+        let operations = match pages.last_mut() {
+            Some(operations) => operations,
+            None => continue,
+        };
+        for (x, text) in texts {
+            operations.extend(vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), (*size).into()]),
+                Operation::new("Td", vec![(PAGE_MARGIN + x).into(), y.into()]),
+                Operation::new(
+                    "Tj",
+                    vec![Object::string_literal(Document::encode_text(
+                        Some("WinAnsiEncoding"),
+                        text,
+                    ))],
+                ),
+                Operation::new("ET", vec![]),
+            ]);
+        }
+    }
+
+    let mut kids: Vec<Object> = Vec::new();
+    for operations in pages {
+        let content = Content { operations };
+        let content_id = document.add_object(Stream::new(dictionary! {}, content.encode()?));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        });
+        kids.push(page_id.into());
+    }
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        }),
+    );
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Lay out an assembly and its descendants as an indented BOM, listing the
+/// children of each part by name below it
+///
+/// Parts used in several places of the assembly are listed in each of them.
+pub fn indented(assembly: &Part, descendants: &[Part]) -> Vec<ReportRow> {
+    let by_id: HashMap<Uuid, &Part> = descendants.iter().map(|part| (part.id, part)).collect();
+    let mut rows = Vec::new();
+    let mut unvisited = vec![(0, 1, assembly)];
+    while let Some((level, quantity, part)) = unvisited.pop() {
+        rows.push(ReportRow {
+            level,
+            id: part.id,
+            name: part.name.clone(),
+            quantity,
+            on_hand: part.on_hand,
+            location: part.location.clone(),
+            phantom: part.phantom,
+        });
+        let mut children: Vec<&Part> = part
+            .children
+            .iter()
+            .filter_map(|child| by_id.get(child).copied())
+            .collect();
+        // pushed in reverse so they are visited by name
+        children.sort_by(|a, b| b.name.cmp(&a.name).then_with(|| b.id.cmp(&a.id)));
+        unvisited.extend(
+            children
+                .into_iter()
+                .map(|child| (level + 1, part.quantity_of(&child.id), child)),
+        );
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{PartsList, PartsListUpdate};
+    use crate::routing::RoutingStep;
+
+    #[test]
+    fn indented_report() {
+        let mut parts = PartsList::new();
+        let mut assy = Part::new("assy");
+        assy.routing = vec![RoutingStep {
+            operation: String::from("Reflow"),
+            work_center: String::from("Line 1"),
+            setup_minutes: 15.0,
+            run_minutes: 2.5,
+        }];
+        assy.instructions = String::from("Torque the <screw> to **4 Nm**");
+        let assy = parts.add(assy).unwrap().id;
+        let bracket = parts.add(Part::new("bracket")).unwrap().id;
+        let screw = parts.add(Part::new("<screw>")).unwrap().id;
+        parts
+            .update(&bracket, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+        parts
+            .update(&assy, &[&bracket, &screw], PartsListUpdate::Add)
+            .unwrap();
+
+        let report = BomReport::new(&parts, &assy).unwrap();
+        let lines: Vec<(usize, &str)> = report
+            .rows
+            .iter()
+            .map(|row| (row.level, row.name.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(0, "assy"), (1, "<screw>"), (1, "bracket"), (2, "<screw>")]
+        );
+        assert_eq!((report.distinct_parts, report.depth), (2, 2));
+        assert_eq!((report.components, report.shortages), (2, 1));
+
+        let html = report.render().unwrap();
+        assert!(html.contains("&lt;screw&gt;"));
+        assert!(!html.contains("<screw>"));
+        assert!(html.contains("<td>Reflow</td>"));
+        assert!(html.contains("<p>Torque the &lt;screw&gt; to <strong>4 Nm</strong></p>"));
+
+        let pdf = report.to_pdf().unwrap();
+        assert!(pdf.starts_with(b"%PDF-1.5"));
+        let document = Document::load_mem(&pdf).unwrap();
+        assert_eq!(document.get_pages().len(), 1);
+    }
+}
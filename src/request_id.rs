@@ -0,0 +1,76 @@
+use std::io::Cursor;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::{Data, Request};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Longest request id accepted from the `X-Request-Id` header of a request
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id identifying a request in the server logs and in its response
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Use the id passed by the client, such as one assigned by a proxy, or
+    /// else a new random one
+    fn for_request(request: &Request) -> RequestId {
+        let passed = request.headers().get_one("X-Request-Id").filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.chars().all(|c| c.is_ascii_graphic())
+        });
+        RequestId(match passed {
+            Some(id) => id.into(),
+            None => Uuid::new_v4().to_simple().to_string(),
+        })
+    }
+}
+
+/// Fairing giving each request an id, which is traced along with the outcome
+/// of the request, returned in the `X-Request-Id` header and added to JSON
+/// responses along with the time they were sent
+///
+/// Must be attached before the request spans fairing, so the outcome is
+/// traced before the span of the request is left.
+pub struct RequestIds;
+
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request ids",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let id = RequestId::for_request(request);
+        request.local_cache(|| id);
+    }
+
+    fn on_response(&self, request: &Request, response: &mut rocket::Response) {
+        let id = request.local_cache(|| RequestId::for_request(request));
+        response.set_raw_header("X-Request-Id", id.0.clone());
+        if response.content_type() == Some(ContentType::JSON) {
+            if let Some(body) = response.body_string() {
+                let body = match serde_json::from_str(&body) {
+                    Ok(Value::Object(mut envelope)) => {
+                        let timestamp = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+                        envelope.insert("request_id".into(), id.0.clone().into());
+                        envelope.insert("timestamp".into(), timestamp.into());
+                        Value::Object(envelope).to_string()
+                    }
+                    _ => body,
+                };
+                response.set_sized_body(Cursor::new(body));
+            }
+        }
+        // recorded within the span of the request, which carries its id
+        tracing::info!(status = %response.status(), "Responded to request");
+    }
+}
@@ -1,10 +1,26 @@
 use std::vec::Vec;
 
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 use uuid::Uuid;
 
+use crate::analysis::DuplicateGroup;
+use crate::attachments::Attachment;
+use crate::category::{AttributeError, Category};
+use crate::cost::{CostHistory, CostPoint, CostRollup, RfqLine};
+use crate::eco::Eco;
 use crate::errors::{PartsError, PartsErrorCode};
-use crate::parts_list::Part;
+use crate::instructions::Instructions;
+use crate::inventory::{LeadTime, PickItem, Shortage};
+use crate::parts_list::{
+    Part, PartField, PartSummary, PartsListCursor, PartsListStats, Relationship, ValidationReport,
+};
+use crate::perf::PerfSummary;
+use crate::quota::Quota;
+use crate::release::Release;
+use crate::replication::ReplicationStatus;
+use crate::routing::Routing;
+use crate::store::wal::ChangeFeed;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct QueryResult {
@@ -12,11 +28,471 @@ pub struct QueryResult {
     pub description: String,
 }
 
+/// Outcome of deleting one of the parts listed in a bulk delete
 #[derive(Serialize, Deserialize, Debug)]
+pub struct DeleteResult {
+    pub id: Uuid,
+    /// Ids of the removed parts, including the subtree of `id` when the delete
+    /// was recursive
+    #[serde(default)]
+    pub deleted: Vec<Uuid>,
+    pub error: Option<PartsError>,
+}
+
+/// Body of a successful reply from the `/v2` API, whose HTTP status tells the
+/// outcome, holding data of any type rather than always a list of parts
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reply<T> {
+    pub data: T,
+    /// Cursor of the next page of a paginated listing with more parts to fetch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Reply<T> {
+    pub fn new(data: T) -> Reply<T> {
+        Reply {
+            data,
+            next_cursor: None,
+        }
+    }
+
+    pub fn next_cursor(mut self, cursor: Option<PartsListCursor>) -> Reply<T> {
+        self.next_cursor = cursor.map(String::from);
+        self
+    }
+}
+
+/// Body of a failed reply from the `/v2` API, sent with a non-2xx status
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorReply {
+    pub error: PartsError,
+    /// Attributes of a part which don't match the schemas of its category
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribute_errors: Option<Vec<AttributeError>>,
+}
+
+impl ErrorReply {
+    pub fn new(error: PartsError) -> ErrorReply {
+        ErrorReply {
+            error,
+            attribute_errors: None,
+        }
+    }
+
+    /// Error reported by a `/v1` response, if any
+    pub fn from_response(response: Response) -> Option<ErrorReply> {
+        Some(ErrorReply {
+            error: response.error?,
+            attribute_errors: response.attribute_errors,
+        })
+    }
+}
+
+/// Every field of a part, in the order they are serialized
+const ALL_FIELDS: [PartField; 23] = [
+    PartField::Id,
+    PartField::Name,
+    PartField::Parents,
+    PartField::Children,
+    PartField::Created,
+    PartField::Version,
+    PartField::ExternalIds,
+    PartField::OnHand,
+    PartField::Location,
+    PartField::Phantom,
+    PartField::Options,
+    PartField::ChildOptions,
+    PartField::Relationships,
+    PartField::Category,
+    PartField::Attributes,
+    PartField::LeadTimeDays,
+    PartField::Routing,
+    PartField::Instructions,
+    PartField::UnitCost,
+    PartField::CostHistory,
+    PartField::Suppliers,
+    PartField::Currency,
+    PartField::Lifecycle,
+];
+
+/// Serializes only the selected fields of a part, always including its id,
+/// followed by its search score and the number of assemblies using it when it
+/// has them
+struct PartProjection<'a> {
+    part: &'a Part,
+    fields: &'a [PartField],
+    score: Option<f64>,
+    used_by: Option<usize>,
+}
+
+impl<'a> Serialize for PartProjection<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("id", &self.part.id)?;
+        for field in self.fields {
+            match field {
+                PartField::Id => continue,
+                PartField::Name => map.serialize_entry("name", &self.part.name)?,
+                PartField::Parents => map.serialize_entry("parents", &self.part.parents)?,
+                PartField::Children => map.serialize_entry("children", &self.part.children)?,
+                PartField::Created => map.serialize_entry("created", &self.part.created)?,
+                PartField::Version => map.serialize_entry("version", &self.part.version)?,
+                PartField::ExternalIds => {
+                    map.serialize_entry("external_ids", &self.part.external_ids)?
+                }
+                PartField::OnHand => map.serialize_entry("on_hand", &self.part.on_hand)?,
+                PartField::Location => map.serialize_entry("location", &self.part.location)?,
+                PartField::Phantom => map.serialize_entry("phantom", &self.part.phantom)?,
+                PartField::Options => map.serialize_entry("options", &self.part.options)?,
+                PartField::ChildOptions => {
+                    map.serialize_entry("child_options", &self.part.child_options)?
+                }
+                PartField::Relationships => {
+                    map.serialize_entry("relationships", &self.part.relationships)?
+                }
+                PartField::Category => map.serialize_entry("category", &self.part.category)?,
+                PartField::Attributes => {
+                    map.serialize_entry("attributes", &self.part.attributes)?
+                }
+                PartField::LeadTimeDays => {
+                    map.serialize_entry("lead_time_days", &self.part.lead_time_days)?
+                }
+                PartField::Routing => map.serialize_entry("routing", &self.part.routing)?,
+                PartField::Instructions => {
+                    map.serialize_entry("instructions", &self.part.instructions)?
+                }
+                PartField::UnitCost => map.serialize_entry("unit_cost", &self.part.unit_cost)?,
+                PartField::CostHistory => {
+                    map.serialize_entry("cost_history", &self.part.cost_history)?
+                }
+                PartField::Suppliers => map.serialize_entry("suppliers", &self.part.suppliers)?,
+                PartField::Currency => map.serialize_entry("currency", &self.part.currency)?,
+                PartField::Lifecycle => map.serialize_entry("lifecycle", &self.part.lifecycle)?,
+            }
+        }
+        if let Some(score) = self.score {
+            map.serialize_entry("score", &score)?;
+        }
+        if let Some(used_by) = self.used_by {
+            map.serialize_entry("used_by", &used_by)?;
+        }
+        map.end()
+    }
+}
+
+/// Part in the data of a response, which carries a score in search results
+/// and the number of assemblies using it in usage rankings
+#[derive(Deserialize)]
+struct ScoredPart {
+    #[serde(flatten)]
+    part: Part,
+    #[serde(default)]
+    score: Option<f64>,
+    #[serde(default)]
+    used_by: Option<usize>,
+}
+
+/// Response as it is sent over the wire, with scores and usage counts held in
+/// the parts
+#[derive(Deserialize)]
+struct ResponseBody {
+    result: Option<QueryResult>,
+    data: Option<Vec<ScoredPart>>,
+    error: Option<PartsError>,
+    #[serde(default)]
+    stats: Option<PartsListStats>,
+    #[serde(default)]
+    summary: Option<PartSummary>,
+    #[serde(default)]
+    perf: Option<PerfSummary>,
+    #[serde(default)]
+    validation: Option<ValidationReport>,
+    #[serde(default)]
+    duplicates: Option<Vec<DuplicateGroup>>,
+    #[serde(default)]
+    missing: Option<Vec<Uuid>>,
+    #[serde(default)]
+    next_cursor: Option<String>,
+    #[serde(default)]
+    ecos: Option<Vec<Eco>>,
+    #[serde(default)]
+    releases: Option<Vec<Release>>,
+    #[serde(default)]
+    attachments: Option<Vec<Attachment>>,
+    #[serde(default)]
+    shortages: Option<Vec<Shortage>>,
+    #[serde(default)]
+    lead_time: Option<LeadTime>,
+    #[serde(default)]
+    routing: Option<Routing>,
+    #[serde(default)]
+    instructions: Option<Instructions>,
+    #[serde(default)]
+    cost: Option<CostRollup>,
+    #[serde(default)]
+    cost_history: Option<CostHistory>,
+    #[serde(default)]
+    cost_trend: Option<Vec<CostPoint>>,
+    #[serde(default)]
+    picklist: Option<Vec<PickItem>>,
+    #[serde(default)]
+    rfq: Option<Vec<RfqLine>>,
+    #[serde(default)]
+    relationships: Option<Vec<Relationship>>,
+    #[serde(default)]
+    deletions: Option<Vec<DeleteResult>>,
+    #[serde(default)]
+    categories: Option<Vec<Category>>,
+    #[serde(default)]
+    quotas: Option<Vec<Quota>>,
+    #[serde(default)]
+    changes: Option<ChangeFeed>,
+    #[serde(default)]
+    replication: Option<ReplicationStatus>,
+    #[serde(default)]
+    attribute_errors: Option<Vec<AttributeError>>,
+    #[serde(default)]
+    request_id: Option<String>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+
+impl From<ResponseBody> for Response {
+    fn from(body: ResponseBody) -> Response {
+        let (data, scores, used_by) = match body.data {
+            Some(data) => {
+                let scored = data.iter().any(|part| part.score.is_some());
+                let counted = data.iter().any(|part| part.used_by.is_some());
+                let mut parts = Vec::new();
+                let mut scores = Vec::new();
+                let mut used_by = Vec::new();
+                for part in data {
+                    scores.push(part.score.unwrap_or_default());
+                    used_by.push(part.used_by.unwrap_or_default());
+                    parts.push(part.part);
+                }
+                (
+                    Some(parts),
+                    if scored { Some(scores) } else { None },
+                    if counted { Some(used_by) } else { None },
+                )
+            }
+            None => (None, None, None),
+        };
+        Response {
+            result: body.result,
+            data,
+            error: body.error,
+            stats: body.stats,
+            summary: body.summary,
+            perf: body.perf,
+            validation: body.validation,
+            duplicates: body.duplicates,
+            missing: body.missing,
+            next_cursor: body.next_cursor,
+            ecos: body.ecos,
+            releases: body.releases,
+            attachments: body.attachments,
+            shortages: body.shortages,
+            lead_time: body.lead_time,
+            routing: body.routing,
+            instructions: body.instructions,
+            cost: body.cost,
+            cost_history: body.cost_history,
+            cost_trend: body.cost_trend,
+            picklist: body.picklist,
+            rfq: body.rfq,
+            relationships: body.relationships,
+            deletions: body.deletions,
+            categories: body.categories,
+            quotas: body.quotas,
+            changes: body.changes,
+            replication: body.replication,
+            attribute_errors: body.attribute_errors,
+            scores,
+            used_by,
+            request_id: body.request_id,
+            timestamp: body.timestamp,
+            fields: None,
+        }
+    }
+}
+
+/// Envelope for every reply from the server
+///
+/// Serialization is implemented by hand so the parts in `data` can be limited to
+/// a sparse fieldset and carry search scores or usage counts, and it is deserialized through
+/// `ResponseBody`, so any field added here must also be added to both.
+#[derive(Deserialize, Debug)]
+#[serde(from = "ResponseBody")]
 pub struct Response {
     pub result: Option<QueryResult>,
     pub data: Option<Vec<Part>>,
     pub error: Option<PartsError>,
+    pub stats: Option<PartsListStats>,
+    /// Size and depth of the subtree below a part
+    pub summary: Option<PartSummary>,
+    /// Performance counters of the server
+    pub perf: Option<PerfSummary>,
+    pub validation: Option<ValidationReport>,
+    /// Groups of structurally identical subassemblies
+    pub duplicates: Option<Vec<DuplicateGroup>>,
+    pub missing: Option<Vec<Uuid>>,
+    pub next_cursor: Option<String>,
+    pub ecos: Option<Vec<Eco>>,
+    pub releases: Option<Vec<Release>>,
+    pub attachments: Option<Vec<Attachment>>,
+    pub shortages: Option<Vec<Shortage>>,
+    /// Critical procurement path of an assembly
+    pub lead_time: Option<LeadTime>,
+    /// Routing of an assembly with its standard times
+    pub routing: Option<Routing>,
+    /// Work instructions of an assembly
+    pub instructions: Option<Instructions>,
+    /// Cost of an assembly rolled up over its components
+    pub cost: Option<CostRollup>,
+    /// Unit costs a part has had
+    pub cost_history: Option<CostHistory>,
+    /// Cost of the components of an assembly over time
+    pub cost_trend: Option<Vec<CostPoint>>,
+    pub picklist: Option<Vec<PickItem>>,
+    /// Components of a request for quote
+    pub rfq: Option<Vec<RfqLine>>,
+    pub relationships: Option<Vec<Relationship>>,
+    /// Outcome for each part listed in a bulk delete
+    pub deletions: Option<Vec<DeleteResult>>,
+    pub categories: Option<Vec<Category>>,
+    /// Quotas of API keys with their usage today
+    pub quotas: Option<Vec<Quota>>,
+    /// Changes made after the cursor of a change feed request
+    pub changes: Option<ChangeFeed>,
+    /// Progress of a follower copying its leader
+    pub replication: Option<ReplicationStatus>,
+    /// Attributes of a part which don't match the schemas of its category
+    pub attribute_errors: Option<Vec<AttributeError>>,
+    /// Search score of each part in `data`, in the same order
+    pub scores: Option<Vec<f64>>,
+    /// Number of assemblies using each part in `data`, in the same order
+    pub used_by: Option<Vec<usize>>,
+    /// Id of the request answered, as logged by the server
+    pub request_id: Option<String>,
+    /// Time the response was sent in milliseconds since the UNIX epoch
+    pub timestamp: Option<u64>,
+    fields: Option<Vec<PartField>>,
+}
+
+impl Serialize for Response {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let _span = tracing::debug_span!("serialize").entered();
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("result", &self.result)?;
+        match (&self.data, &self.fields, &self.scores, &self.used_by) {
+            (Some(data), fields, scores, used_by)
+                if fields.is_some() || scores.is_some() || used_by.is_some() =>
+            {
+                let fields = fields.as_deref().unwrap_or(&ALL_FIELDS);
+                let projected: Vec<PartProjection> = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, part)| PartProjection {
+                        part,
+                        fields,
+                        score: scores.as_ref().and_then(|scores| scores.get(i).copied()),
+                        used_by: used_by.as_ref().and_then(|used_by| used_by.get(i).copied()),
+                    })
+                    .collect();
+                map.serialize_entry("data", &projected)?
+            }
+            _ => map.serialize_entry("data", &self.data)?,
+        }
+        map.serialize_entry("error", &self.error)?;
+        if let Some(stats) = &self.stats {
+            map.serialize_entry("stats", stats)?;
+        }
+        if let Some(summary) = &self.summary {
+            map.serialize_entry("summary", summary)?;
+        }
+        if let Some(perf) = &self.perf {
+            map.serialize_entry("perf", perf)?;
+        }
+        if let Some(validation) = &self.validation {
+            map.serialize_entry("validation", validation)?;
+        }
+        if let Some(duplicates) = &self.duplicates {
+            map.serialize_entry("duplicates", duplicates)?;
+        }
+        if let Some(missing) = &self.missing {
+            map.serialize_entry("missing", missing)?;
+        }
+        if let Some(next_cursor) = &self.next_cursor {
+            map.serialize_entry("next_cursor", next_cursor)?;
+        }
+        if let Some(ecos) = &self.ecos {
+            map.serialize_entry("ecos", ecos)?;
+        }
+        if let Some(releases) = &self.releases {
+            map.serialize_entry("releases", releases)?;
+        }
+        if let Some(attachments) = &self.attachments {
+            map.serialize_entry("attachments", attachments)?;
+        }
+        if let Some(shortages) = &self.shortages {
+            map.serialize_entry("shortages", shortages)?;
+        }
+        if let Some(lead_time) = &self.lead_time {
+            map.serialize_entry("lead_time", lead_time)?;
+        }
+        if let Some(routing) = &self.routing {
+            map.serialize_entry("routing", routing)?;
+        }
+        if let Some(instructions) = &self.instructions {
+            map.serialize_entry("instructions", instructions)?;
+        }
+        if let Some(cost) = &self.cost {
+            map.serialize_entry("cost", cost)?;
+        }
+        if let Some(cost_history) = &self.cost_history {
+            map.serialize_entry("cost_history", cost_history)?;
+        }
+        if let Some(cost_trend) = &self.cost_trend {
+            map.serialize_entry("cost_trend", cost_trend)?;
+        }
+        if let Some(picklist) = &self.picklist {
+            map.serialize_entry("picklist", picklist)?;
+        }
+        if let Some(rfq) = &self.rfq {
+            map.serialize_entry("rfq", rfq)?;
+        }
+        if let Some(relationships) = &self.relationships {
+            map.serialize_entry("relationships", relationships)?;
+        }
+        if let Some(deletions) = &self.deletions {
+            map.serialize_entry("deletions", deletions)?;
+        }
+        if let Some(categories) = &self.categories {
+            map.serialize_entry("categories", categories)?;
+        }
+        if let Some(quotas) = &self.quotas {
+            map.serialize_entry("quotas", quotas)?;
+        }
+        if let Some(changes) = &self.changes {
+            map.serialize_entry("changes", changes)?;
+        }
+        if let Some(replication) = &self.replication {
+            map.serialize_entry("replication", replication)?;
+        }
+        if let Some(attribute_errors) = &self.attribute_errors {
+            map.serialize_entry("attribute_errors", attribute_errors)?;
+        }
+        if let Some(request_id) = &self.request_id {
+            map.serialize_entry("request_id", request_id)?;
+        }
+        if let Some(timestamp) = &self.timestamp {
+            map.serialize_entry("timestamp", timestamp)?;
+        }
+        map.end()
+    }
 }
 
 impl Response {
@@ -25,6 +501,37 @@ impl Response {
             result: None,
             data: None,
             error: None,
+            stats: None,
+            summary: None,
+            perf: None,
+            validation: None,
+            duplicates: None,
+            missing: None,
+            next_cursor: None,
+            ecos: None,
+            releases: None,
+            attachments: None,
+            shortages: None,
+            lead_time: None,
+            routing: None,
+            instructions: None,
+            cost: None,
+            cost_history: None,
+            cost_trend: None,
+            picklist: None,
+            rfq: None,
+            relationships: None,
+            deletions: None,
+            categories: None,
+            quotas: None,
+            changes: None,
+            replication: None,
+            attribute_errors: None,
+            scores: None,
+            used_by: None,
+            request_id: None,
+            timestamp: None,
+            fields: None,
         }
     }
 
@@ -41,10 +548,182 @@ impl Response {
         self
     }
 
+    /// Limit the parts in `data` to the listed fields when serialized
+    pub fn fields(mut self, fields: Option<Vec<PartField>>) -> Response {
+        self.fields = fields;
+        self
+    }
+
+    /// Attach the search score of each part in `data`, in the same order
+    pub fn scores(mut self, scores: Vec<f64>) -> Response {
+        self.scores = Some(scores);
+        self
+    }
+
+    /// Attach the number of assemblies using each part in `data`, in the same
+    /// order
+    pub fn used_by(mut self, used_by: Vec<usize>) -> Response {
+        self.used_by = Some(used_by);
+        self
+    }
+
+    pub fn stats(mut self, stats: PartsListStats) -> Response {
+        self.stats = Some(stats);
+        self
+    }
+
+    pub fn summary(mut self, summary: PartSummary) -> Response {
+        self.summary = Some(summary);
+        self
+    }
+
+    pub fn perf(mut self, perf: PerfSummary) -> Response {
+        self.perf = Some(perf);
+        self
+    }
+
+    pub fn validation(mut self, report: ValidationReport) -> Response {
+        self.validation = Some(report);
+        self
+    }
+
+    pub fn duplicates(mut self, duplicates: Vec<DuplicateGroup>) -> Response {
+        self.duplicates = Some(duplicates);
+        self
+    }
+
+    pub fn missing(mut self, ids: Vec<Uuid>) -> Response {
+        self.missing = Some(ids);
+        self
+    }
+
+    /// Set the cursor to request the page following the parts in `data`
+    pub fn next_cursor(mut self, cursor: Option<PartsListCursor>) -> Response {
+        self.next_cursor = cursor.map(String::from);
+        self
+    }
+
+    pub fn ecos(mut self, ecos: Vec<Eco>) -> Response {
+        self.ecos = Some(ecos);
+        self
+    }
+
+    pub fn releases(mut self, releases: Vec<Release>) -> Response {
+        self.releases = Some(releases);
+        self
+    }
+
+    pub fn attachments(mut self, attachments: Vec<Attachment>) -> Response {
+        self.attachments = Some(attachments);
+        self
+    }
+
+    pub fn shortages(mut self, shortages: Vec<Shortage>) -> Response {
+        self.shortages = Some(shortages);
+        self
+    }
+
+    pub fn lead_time(mut self, lead_time: LeadTime) -> Response {
+        self.lead_time = Some(lead_time);
+        self
+    }
+
+    pub fn routing(mut self, routing: Routing) -> Response {
+        self.routing = Some(routing);
+        self
+    }
+
+    pub fn instructions(mut self, instructions: Instructions) -> Response {
+        self.instructions = Some(instructions);
+        self
+    }
+
+    pub fn cost(mut self, cost: CostRollup) -> Response {
+        self.cost = Some(cost);
+        self
+    }
+
+    pub fn cost_history(mut self, cost_history: CostHistory) -> Response {
+        self.cost_history = Some(cost_history);
+        self
+    }
+
+    pub fn cost_trend(mut self, cost_trend: Vec<CostPoint>) -> Response {
+        self.cost_trend = Some(cost_trend);
+        self
+    }
+
+    pub fn picklist(mut self, picklist: Vec<PickItem>) -> Response {
+        self.picklist = Some(picklist);
+        self
+    }
+
+    pub fn rfq(mut self, rfq: Vec<RfqLine>) -> Response {
+        self.rfq = Some(rfq);
+        self
+    }
+
+    pub fn relationships(mut self, relationships: Vec<Relationship>) -> Response {
+        self.relationships = Some(relationships);
+        self
+    }
+
+    pub fn deletions(mut self, deletions: Vec<DeleteResult>) -> Response {
+        self.deletions = Some(deletions);
+        self
+    }
+
+    pub fn categories(mut self, categories: Vec<Category>) -> Response {
+        self.categories = Some(categories);
+        self
+    }
+
+    pub fn quotas(mut self, quotas: Vec<Quota>) -> Response {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    pub fn changes(mut self, changes: ChangeFeed) -> Response {
+        self.changes = Some(changes);
+        self
+    }
+
+    pub fn replication(mut self, replication: ReplicationStatus) -> Response {
+        self.replication = Some(replication);
+        self
+    }
+
+    pub fn attribute_errors(mut self, errors: Vec<AttributeError>) -> Response {
+        self.attribute_errors = Some(errors);
+        self
+    }
+
     pub fn error(mut self, code: PartsErrorCode, description: &str) -> Response {
         self.error = Some(PartsError::new(code, description.into()));
         self
     }
+
+    /// Report an error for a change which would have linked the parts of
+    /// `cycle` into a cycle
+    pub fn cycle_error(
+        mut self,
+        code: PartsErrorCode,
+        description: &str,
+        cycle: Vec<Uuid>,
+    ) -> Response {
+        self.error = Some(PartsError::new(code, description.into()).with_cycle(cycle));
+        self
+    }
+
+    /// Describe the error reported by the response, if any, along with the id
+    /// of the request so it can be found in the server logs
+    pub fn error_message(&self) -> Option<String> {
+        let error = self.error.as_ref()?;
+        Some(match &self.request_id {
+            Some(id) => format!("{} [request id: {}]", error, id),
+            None => error.to_string(),
+        })
+    }
 }
 
 impl Default for Response {
@@ -52,3 +731,68 @@ impl Default for Response {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_fieldset() {
+        let part = Part::new("my part");
+        let response = Response::new()
+            .data(vec![part.clone()])
+            .fields(Some(vec![PartField::Name]));
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            value["data"],
+            serde_json::json!([{"id": part.id, "name": "my part"}])
+        );
+
+        // parts left out of the fieldset still deserialize
+        let response: Response = serde_json::from_value(value).unwrap();
+        assert!(response.data.unwrap()[0].children.is_empty());
+    }
+
+    #[test]
+    fn request_id_in_errors() {
+        let value = serde_json::json!({
+            "result": null,
+            "data": null,
+            "error": {"code": 2, "description": "Part does not exist"},
+            "request_id": "abc123",
+            "timestamp": 1620000000000u64,
+        });
+        let response: Response = serde_json::from_value(value).unwrap();
+        assert_eq!(response.timestamp, Some(1620000000000));
+        assert_eq!(
+            response.error_message().unwrap(),
+            "Part does not exist (code: MissingPartError) [request id: abc123]"
+        );
+        assert!(Response::new().error_message().is_none());
+    }
+
+    #[test]
+    fn scores_round_trip() {
+        let parts = vec![Part::new("resistor"), Part::new("resistor network")];
+        let response = Response::new().data(parts.clone()).scores(vec![1.5, 0.5]);
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(value["data"][0]["score"], 1.5);
+        assert_eq!(value["data"][1]["name"], "resistor network");
+
+        let response: Response = serde_json::from_value(value).unwrap();
+        assert_eq!(response.data.unwrap(), parts);
+        assert_eq!(response.scores, Some(vec![1.5, 0.5]));
+
+        let response = Response::new().data(parts.clone()).used_by(vec![3, 1]);
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(value["data"][0]["used_by"], 3);
+        let response: Response = serde_json::from_value(value).unwrap();
+        assert_eq!(response.used_by, Some(vec![3, 1]));
+        assert_eq!(response.scores, None);
+
+        let value = serde_json::to_value(Response::new().data(parts)).unwrap();
+        assert!(value["data"][0].get("score").is_none());
+        let response: Response = serde_json::from_value(value).unwrap();
+        assert_eq!(response.scores, None);
+    }
+}
@@ -1,16 +1,55 @@
-use std::convert::TryInto;
+use std::convert::{Infallible, TryInto};
+use std::io::{Cursor, Read};
 
-use rocket::{http::RawStr, State};
+use askama::Template;
+use flate2::read::GzEncoder;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromQuery, FromRequest, Query, Request};
+use rocket::response::{content::Content, status, Stream};
+use rocket::{http::RawStr, Data, State};
 use rocket_contrib::json::Json;
 use rocket_contrib::uuid::Uuid as RocketUuid;
 use uuid::Uuid;
 
+use crate::analysis;
+use crate::attachments::{Attachment, SharedAttachments, Upload};
+use crate::backup::Backup;
+use crate::category::{self, Category, CategoryEdit, CategoryRegistry};
+use crate::cost::{self, CostHistory};
+use crate::currency::{self, ExchangeRates};
+use crate::eco::{Eco, EcoRegistry};
 use crate::errors::PartsErrorCode;
-use crate::parts_list::{Part, PartsList, PartsListFilter};
-use crate::query::{NewPart, UpdateChildren};
-use crate::response::Response;
+use crate::generator::{self, BomShape};
+use crate::idempotency::{IdempotencyCache, IdempotencyKey};
+use crate::instructions::Instructions;
+use crate::inventory;
+use crate::label::{self, Barcode, ImageFormat, LabelFormat};
+use crate::negotiate::{payload_limit, Accepted, Negotiated, Payload};
+use crate::parts_list::{
+    Part, PartEdit, PartField, PartsList, PartsListCursor, PartsListError, PartsListFilter,
+    PartsListPredicate, PartsListQuery, PartsListSort, RelationshipEdit, ValidationReport,
+};
+use crate::perf;
+use crate::query::{
+    BatchGet, BulkDelete, NewCategory, NewEco, NewInstructions, NewPart, NewRelease, UpdateChildren,
+};
+use crate::quota::{Admin, Metered, QuotaLimits, QuotaRegistry, QuotaRejection};
+use crate::release::{ApproverRejection, Release, ReleaseApprover, ReleaseRegistry};
+use crate::replication::Replication;
+use crate::report::BomReport;
+use crate::response::{DeleteResult, Response};
+use crate::routing::{Routing, RoutingStep};
+use crate::store::wal::ChangeFeed;
+use crate::store::PartsStore;
+use crate::supplier::SupplierPolicy;
+use crate::variants::{self, Configuration};
+use crate::writer::WriteRejected;
 use crate::SharedPartsList;
 
+pub mod v2;
+
 #[get("/")]
 pub fn index() -> &'static str {
     r####"# BOM Server API
@@ -20,14 +59,110 @@ The `bom-server` API exposes a simple REST API to allow for management of BOM pa
 The following APIs can be used to interact with the BOM Server:
 
 ```
-GET     /v1/parts?filter=<all|top_level|assembly|component|subassembly|orphan> -> list all parts
+GET     /v1/parts?filter=<all|top_level|assembly|component|subassembly|orphan>&sort=<name|created|children_count>&order=<asc|desc>&fields=<id,name,...>&limit=<n>&cursor=<cursor>&include_subcategories=<bool>&<predicate>=<value>... -> list all parts
 POST    /v1/parts                                                -> create a new part
+PUT     /v1/parts?key=<system>                                   -> create a part or update the existing part with its name or external id
+POST    /v1/parts/batch_get                                      -> get information for several parts
+GET     /v1/parts/top_used?limit=<n>&fields=<id,name,...>        -> get the components used by the most assemblies
+GET     /v1/parts/common?ids=<id,...>&fields=<id,name,...>       -> get the components used by all of the listed assemblies
 GET     /v1/parts/<id>                                           -> get part <id> information
-DELETE  /v1/parts/<id>                                           -> delete part <id> from server
-GET     /v1/parts/<id>/children?filter=<all|component|top_level> -> get children of part <id>
-POST    /v1/parts/<id>/children?action=<add|remove|replace>      -> update children of part <id>
-GET     /v1/parts/<id>/contained -> get assemblies that include part <id> directly or indirectly
+GET     /v1/parts/by_name/<name>                                 -> get information for the part named <name>
+GET     /v1/parts/by_external/<system>/<external id>             -> get information for the part known by <external id> in <system>
+PATCH   /v1/parts/<id>?expected_version=<n>                      -> edit the attributes of part <id>
+DELETE  /v1/parts?filter=orphan                                  -> delete all orphan parts
+POST    /v1/parts/bulk_delete                                    -> delete several parts
+DELETE  /v1/parts/<id>?recursive=<bool>&dry_run=<bool>&force=<bool>&expected_version=<n> -> delete part <id> from server
+GET     /v1/parts/<id>/children?filter=<all|component|subassembly|top_level>&sort=<name|created|children_count>&order=<asc|desc>&fields=<id,name,...>&limit=<n>&cursor=<cursor> -> get children of part <id>
+POST    /v1/parts/<id>/children?action=<add|remove|replace>&expected_version=<n> -> update children of part <id>
+POST    /v1/parts/<id>/restore                                   -> restore deleted part <id>
+POST    /v1/parts/<id>/move?from=<parent id>&to=<parent id>      -> move part <id> from one parent to another
+GET     /v1/relationships/<id>                                   -> get relationship <id> between a parent and child
+PATCH   /v1/relationships/<id>                                   -> edit the quantity and notes of relationship <id>
+POST    /v1/parts/<id>/attachments                               -> upload files attached to part <id>
+GET     /v1/parts/<id>/attachments                               -> list files attached to part <id>
+GET     /v1/parts/<id>/attachments/<attachment id>               -> download a file attached to part <id>
+DELETE  /v1/parts/<id>/attachments/<attachment id>               -> delete a file attached to part <id>
+GET     /v1/parts/<id>/parents?fields=<id,name,...>          -> get the direct parents of part <id>
+GET     /v1/parts/<id>/summary                                   -> get the number of children and descendants of part <id> and its depth
+GET     /v1/parts/<id>/contained?fields=<id,name,...> -> get assemblies that include part <id> directly or indirectly
+GET     /v1/parts/<id>/ancestors?depth=<n>&fields=<id,name,...> -> get assemblies up to <n> levels above part <id>
+GET     /v1/parts/<id>/shortage?build_qty=<n>&config=<group:value,...> -> get components short of stock to build <n> of part <id>
+GET     /v1/parts/<id>/lead_time?config=<group:value,...>&policy=<preferred|cheapest|shortest_lead_time> -> get the longest chain of lead times through part <id>
+GET     /v1/parts/<id>/cost?qty=<n>&policy=<preferred|cheapest|shortest_lead_time>&currency=<code> -> get the cost of the components to build <n> of part <id>
+GET     /v1/parts/<id>/cost_history                              -> get the unit costs part <id> has had
+GET     /v1/parts/<id>/cost_trend?format=<json|csv>              -> get the cost of the components of part <id> over time
+GET     /v1/parts/<id>/rfq?qty=<n>&policy=<..>&currency=<code>&format=<json|csv> -> get a request for quote for the components to build <n> of part <id>
+GET     /v1/parts/<id>/picklist?qty=<n>&format=<json|csv>&config=<group:value,...> -> get components to pull from stock to build <n> of part <id>
+GET     /v1/parts/<id>/report.html                               -> get an HTML report of the indented BOM of part <id>
+GET     /v1/parts/<id>/report?format=<html|pdf>                  -> get an HTML or PDF report of the indented BOM of part <id>
+GET     /v1/parts/<id>/label?format=<qr|code128|zpl>&image=<svg|png> -> get a barcode or ZPL label identifying part <id>
+GET     /v1/parts/<id>/routing                                   -> get the routing of part <id> with its standard times
+PUT     /v1/parts/<id>/routing                                   -> replace the routing of part <id>
+POST    /v1/parts/<id>/routing                                   -> add a step to the end of the routing of part <id>
+DELETE  /v1/parts/<id>/routing                                   -> remove the routing of part <id>
+GET     /v1/parts/<id>/instructions                              -> get the markdown work instructions of part <id>
+PUT     /v1/parts/<id>/instructions?expected_version=<n>         -> replace the work instructions of part <id>
+GET     /v1/search?q=<text>&fuzzy=<bool>&fields=<id,name,...>    -> search for parts by name or attribute value
+GET     /v1/stats                                                -> get statistics about all parts
+GET     /v1/changes?since=<cursor>&limit=<n>                     -> get the changes made after <cursor>, oldest first
+GET     /v1/changes/snapshot                                     -> get every part with the cursor of the latest change they include
+GET     /v1/replication                                          -> get the progress of copying the parts of the leader
+GET     /v1/admin/perf                                           -> get performance counters of the server
+GET     /v1/admin/validate                                       -> check integrity of all parts
+GET     /v1/admin/duplicates                                     -> find structurally identical subassemblies
+GET     /v1/admin/backup                                         -> download a compressed archive of all parts
+POST    /v1/admin/restore?dry_run=<bool>                         -> replace all parts with those in an archive
+POST    /v1/admin/generate?parts=<n>&depth=<n>&fanout=<n>&seed=<n> -> add random assemblies for demos and load tests
+GET     /v1/admin/quotas                                         -> list the daily quotas of API keys with their usage
+GET     /v1/admin/quotas/<key>                                   -> get the quota of an API key
+PUT     /v1/admin/quotas/<key>                                   -> limit the requests and new parts of an API key each day
+DELETE  /v1/admin/quotas/<key>                                   -> remove the quota of an API key
+POST    /v1/ecos                                                 -> propose an engineering change order
+GET     /v1/ecos?assembly=<id>&status=<proposed|approved|rejected|applied> -> list engineering change orders
+GET     /v1/ecos/<id>                                            -> get engineering change order <id>
+POST    /v1/ecos/<id>/approve                                    -> approve engineering change order <id>
+POST    /v1/ecos/<id>/reject                                     -> reject proposed engineering change order <id>
+POST    /v1/ecos/<id>/apply                                      -> apply the changes of approved order <id>
+POST    /v1/releases                                             -> submit an assembly for release
+GET     /v1/releases?assembly=<id>&status=<in_review|released|rejected> -> list releases
+GET     /v1/releases/<id>                                        -> get release <id>
+POST    /v1/releases/<id>/approve                                -> approve release <id> for a role
+POST    /v1/releases/<id>/reject                                 -> reject release <id> for a role
+POST    /v1/categories                                           -> create a category of parts
+GET     /v1/categories                                           -> list all categories
+GET     /v1/categories/<id>                                      -> get category <id>
+PATCH   /v1/categories/<id>                                      -> rename category <id> or move it below another
+DELETE  /v1/categories/<id>                                      -> delete empty category <id>
+GET     /ui                                                      -> open the web UI for browsing and editing parts
+```
+
+The `/v2` API replies with the HTTP status of the outcome, sending only the error as the body of a failure:
+
+```
+GET     /v2/parts?filter=<all|top_level|assembly|component|subassembly|orphan>&sort=<name|created|children_count>&order=<asc|desc>&limit=<n>&cursor=<cursor> -> list all parts
+POST    /v2/parts                                                -> create a new part
+GET     /v2/parts/<id>                                           -> get part <id>
+PATCH   /v2/parts/<id>?expected_version=<n>                      -> edit the attributes of part <id>
+DELETE  /v2/parts/<id>?force=<bool>&expected_version=<n>         -> delete part <id>, replying with it
+GET     /v2/parts/<id>/children?filter=<all|component|subassembly|top_level>&sort=<name|created|children_count>&order=<asc|desc>&limit=<n>&cursor=<cursor> -> get children of part <id>
+POST    /v2/parts/<id>/children?action=<add|remove|replace>&expected_version=<n> -> update children of part <id>, replying with it
+GET     /v2/parts/<id>/summary                                   -> get the number of children and descendants of part <id> and its depth
+GET     /v2/stats                                                -> get statistics about all parts
+```
+
+Parts listed by `GET /v1/parts` can be narrowed down with any number of predicates, which must all hold:
+
 ```
+name_contains=<text>  -> part name includes the text
+min_children=<count>  -> part has at least this many direct children
+max_children=<count>  -> part has at most this many direct children
+has_parent=<id>       -> part <id> is a direct parent of the part
+has_child=<id>        -> part <id> is a direct child of the part
+category=<id,...>     -> part is assigned to one of the categories
+attr.<name>=<value>   -> part has the attribute with the value, such as attr.package=0603
+```
+
+When `include_subcategories` is `true`, parts assigned to a subcategory of a listed category also match.
 
 ## Responses
 Each query to a valid API on the server returns a response object in JSON format the body of the reply.
@@ -48,17 +183,38 @@ and `data` should be populated as shown below.
             "id": "<UUID String>",
             "name": "<part name>",
             "parents" : [ "<UUID String>", ... ],
-            "children" : [ "<UUID String>", ... ]
+            "children" : [ "<UUID String>", ... ],
+            "created": <milliseconds since UNIX epoch>,
+            "external_ids": { "<system>": "<id in system>", ... },
+            "on_hand": <quantity in stock>,
+            "location": "<where the part is stored>",
+            "phantom": <bool>,
+            "options": { "<group>": [ "<value>", ... ], ... },
+            "child_options": { "<child UUID String>": { "<group>": [ "<value>", ... ], ... }, ... },
+            "relationships": { "<child UUID String>": { <relationship> }, ... },
+            "category": "<UUID String or null>",
+            "attributes": { "<name>": <value>, ... },
+            "lead_time_days": <days to procure or build>,
+            "routing": [ { <routing step> }, ... ],
+            "instructions": "<markdown work instructions>",
+            "unit_cost": <cost to buy or build one>,
+            "cost_history": [ { "time": <milliseconds since UNIX epoch>, "unit_cost": <cost> }, ... ],
+            "suppliers": [ { "name": "<supplier name>", "unit_cost": <cost>, "price_breaks": [ { "min_quantity": <n>, "unit_cost": <cost> }, ... ], "lead_time_days": <days>, "currency": "<code>" }, ... ],
+            "currency": "<currency code of the unit cost>"
         },
         ...
     ]
     "error": {
         "code": <int>,
         "description": "<Error description String>"
-    }
+    },
+    "next_cursor": "<cursor String>"
 }
 ```
 
+The `next_cursor` field is only included in paginated listings with more parts to fetch, and is passed
+as the `cursor` of the next request.
+
 ## Requests
 Each POST command requires a properly formatted JSON object in the request body.
 
@@ -67,7 +223,82 @@ To request creation of a part, supply a unique name for the part as follows:
 
 ```
 {
-    "name": "<name of the part>"
+    "name": "<name of the part>",
+    "id": "<UUID String>",
+    "external_ids": { "<system>": "<id in system>", ... },
+    "category": "<UUID String>",
+    "attributes": { "<name>": <value>, ... }
+}
+```
+
+The `id` is optional, and is randomly generated when not supplied. A supplied id must not be in use by
+another part. The optional `external_ids` identify the part in other systems, such as an ERP material
+number, and each must not be in use by another part.
+
+### Edit Part Request Body
+To request changes to the attributes of a part, supply only the attributes to change as follows:
+
+```
+{
+    "external_ids": { "<system>": "<id in system>", ... },
+    "on_hand": <quantity in stock>,
+    "location": "<where the part is stored>",
+    "phantom": <bool>,
+    "options": { "<group>": [ "<value>", ... ], ... },
+    "child_options": { "<child UUID String>": { "<group>": [ "<value>", ... ], ... }, ... },
+    "category": "<UUID String or null>",
+    "attributes": { "<name>": <value>, ... },
+    "lead_time_days": <days to procure or build>,
+    "routing": [ { <routing step> }, ... ],
+    "instructions": "<markdown work instructions>",
+    "unit_cost": <cost to buy or build one>,
+    "currency": "<currency code of the unit cost, or null>",
+    "suppliers": [ { "name": "<supplier name>", "unit_cost": <cost>, "price_breaks": [ { "min_quantity": <n>, "unit_cost": <cost> }, ... ], "lead_time_days": <days>, "currency": "<code>" }, ... ]
+}
+```
+
+The part is assigned to an existing `category`, or unassigned when it is `null`. The given `attributes`
+replace all of the part's attributes, and are checked against the schemas of its category as described in
+Attribute Schemas below.
+
+The given `external_ids` replace all of the part's external ids. A `phantom` part is a subassembly which
+only groups parts in the structure of an assembly, and is never built or stocked by itself. The
+`lead_time_days` of a part is the time to procure it, or to build it once its children are available. The
+given `routing` replaces all of the steps of the part's routing, as described in Routings below, and the
+given `instructions` replace the part's work instructions as described in Work Instructions below.
+Giving the part a different `unit_cost` records the new cost in its `cost_history` along with the time it
+changed. Costs must be positive or zero. The given `suppliers` replace all of the part's suppliers and are
+listed in order of preference, the first being preferred. Each supplier must be named once, and its
+`unit_cost` and `lead_time_days` are used by cost and lead time rollups in place of those of the part.
+A supplier may list `price_breaks`, such as `[ { "min_quantity": 100, "unit_cost": 0.02 } ]`, giving lower
+costs when buying at least a quantity of the part. Each break must be for a different `min_quantity` of at
+least `1`. The `currency` of a part or supplier is the three letter ISO 4217 code, such as `EUR`, its `unit_cost` is
+given in. Costs without a currency are in the base currency of the server, and a `null` currency returns
+the part's cost to it.
+
+The `options` of a part are the option groups it can be configured with, such as `"color": ["red", "blue"]`.
+Its `child_options` restrict children to particular options, and a restricted child is only included in a
+configured assembly when the value chosen for each of its groups is one of the listed values. Restricted
+children must be children of the part, and their groups and values must be among its `options`. The given
+`options` and `child_options` replace all of the part's options and restrictions.
+
+### Bulk Delete Request Body
+To delete several parts at once, supply the part identifiers, and whether their subtrees are deleted too, as
+follows:
+
+```
+{
+    "ids": ["<part id1>", "<part id2>", ... ],
+    "recursive": <bool, optional, default false>
+}
+```
+
+### Batch Get Request Body
+To request several parts at once, supply the part identifiers as follows:
+
+```
+{
+    "ids": ["<part id1>", "<part id2>", ... ]
 }
 ```
 
@@ -80,66 +311,652 @@ To request updates to the children of a part, supply the child identifiers for t
 }
 ```
 
+### Edit Relationship Request Body
+To request changes to the metadata of a relationship, supply only the fields to change as follows:
+
+```
+{
+    "quantity": <number of the child used by the parent>,
+    "notes": "<free text>"
+}
+```
+
+### New Category Request Body
+To create a category, supply its name and optionally the category it is a subcategory of as follows:
+
+```
+{
+    "name": "<category name>",
+    "parent": "<UUID String>",
+    "attributes": [
+        {
+            "name": "<attribute name>",
+            "type": "<string|number|integer|boolean>",
+            "required": <bool, optional, default false>,
+            "allowed": [ <value>, ... ]
+        },
+        ...
+    ]
+}
+```
+
+The optional `allowed` values limit the attribute to those values, which must be of its `type`.
+
+### Edit Category Request Body
+To rename or move a category, supply only the fields to change as follows:
+
+```
+{
+    "name": "<category name>",
+    "parent": "<UUID String or null>",
+    "attributes": [ <attribute schema>, ... ]
+}
+```
+
+A `null` parent makes the category a top level category. The given `attributes` replace all of the
+category's attribute schemas.
+
+### New ECO Request Body
+To propose an engineering change order, supply the assembly it changes along with the changes as follows:
+
+```
+{
+    "assembly": "<UUID String>",
+    "description": "<reason for the change>",
+    "changes": [
+        {
+            "part": "<UUID String>",
+            "action": "<add|remove|replace>",
+            "children": ["<child part id1>", "<child part id2>", ... ]
+        },
+        ...
+    ]
+}
+```
+
 "####
 }
 
-#[get("/v1/parts?<filter>")]
-pub fn list_parts(filter: Option<&RawStr>, parts: State<SharedPartsList>) -> Json<Response> {
-    let response = Response::new();
+/// Single page UI for browsing and editing parts through the API
+#[get("/ui")]
+pub fn ui() -> Content<&'static str> {
+    Content(ContentType::HTML, include_str!("../static/ui.html"))
+}
+
+/// Parse the optional `sort` and `order` query parameters of listing routes
+fn parse_sort(
+    sort: Option<&RawStr>,
+    order: Option<&RawStr>,
+) -> Result<Option<PartsListSort>, PartsListError> {
+    match sort {
+        Some(sort) => {
+            let order = order.map_or("asc", |order| order.as_str());
+            Ok(Some(PartsListSort::new(
+                sort.as_str().try_into()?,
+                order.try_into()?,
+            )))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Query parameters of a listing not matched by a named parameter, parsed as
+/// predicates which listed parts must all satisfy
+///
+/// Parse failures are kept rather than rejected so they can be reported in the
+/// response envelope instead of forwarding the request.
+pub struct Predicates(Result<Vec<PartsListPredicate>, PartsListError>);
+
+impl<'q> FromQuery<'q> for Predicates {
+    type Error = Infallible;
+
+    fn from_query(query: Query<'q>) -> Result<Self, Self::Error> {
+        Ok(Predicates(
+            query
+                .map(|item| {
+                    PartsListPredicate::parse(
+                        &item.key.url_decode_lossy(),
+                        &item.value.url_decode_lossy(),
+                    )
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Parse the optional `fields` query parameter selecting a sparse fieldset
+fn parse_fields(fields: Option<&RawStr>) -> Result<Option<Vec<PartField>>, PartsListError> {
+    fields
+        .map(|fields| PartField::parse_list(fields.as_str()))
+        .transpose()
+}
+
+/// Number of parts in a page when a cursor is passed without a limit
+const DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// Cursor and size of the page of a listing to return
+type Page = (Option<PartsListCursor>, usize);
+
+/// Parse the optional `limit` and `cursor` query parameters of listing routes,
+/// which paginate the listing in id order when either is passed
+fn parse_page(
+    limit: Option<usize>,
+    cursor: Option<&RawStr>,
+    sort: Option<PartsListSort>,
+) -> Result<Option<Page>, String> {
+    if limit.is_none() && cursor.is_none() {
+        return Ok(None);
+    }
+    if sort.is_some() {
+        return Err("pages are ordered by id and can't be sorted".into());
+    }
+    let cursor = match cursor {
+        Some(cursor) => Some(cursor.as_str().try_into().map_err(|e| format!("{}", e))?),
+        None => None,
+    };
+    match limit.unwrap_or(DEFAULT_PAGE_LIMIT) {
+        0 => Err("limit must be at least 1".into()),
+        limit => Ok(Some((cursor, limit))),
+    }
+}
+
+/// Raw value of the `If-Match` header, holding the version a client expects a
+/// part to be at
+pub struct IfMatch(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = Infallible;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(IfMatch(
+            request.headers().get_one("If-Match").map(String::from),
+        ))
+    }
+}
+
+/// Parse the version a change is based on, from the `expected_version` query
+/// parameter or else the `If-Match` header, which may be quoted like an etag
+fn parse_expected_version(
+    expected_version: Option<&RawStr>,
+    if_match: &IfMatch,
+) -> Result<Option<u64>, String> {
+    let version = match (expected_version, &if_match.0) {
+        (Some(version), _) => version.as_str(),
+        (None, Some(header)) => header.trim().trim_matches('"'),
+        (None, None) => return Ok(None),
+    };
+    version
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("Invalid expected version {:?}", version))
+}
+
+/// Expect a part to be at the version the client passed, if any, until the
+/// write is done
+fn check_expected_version(
+    parts: &mut dyn PartsStore,
+    id: &Uuid,
+    expected: Option<u64>,
+) -> Result<(), PartsListError> {
+    match expected {
+        Some(expected) => parts.expect_version(id, expected),
+        None => Ok(()),
+    }
+}
+
+/// Reply with 409 Conflict when the response reports a version conflict or a
+/// part still in use
+fn conflict_status(response: Json<Response>) -> status::Custom<Json<Response>> {
+    let conflict = matches!(
+        response.error.as_ref().map(|e| e.code()),
+        Some(PartsErrorCode::VersionConflict) | Some(PartsErrorCode::PartInUse)
+    );
+    if conflict {
+        status::Custom(Status::Conflict, response)
+    } else {
+        busy_status(response)
+    }
+}
+
+/// Reply with 503 Service Unavailable when the write was refused as too many
+/// others were already waiting for the writer thread, or with 429 Too Many
+/// Requests when it would have gone over the quota of the API key it was sent
+/// with
+fn busy_status(response: Json<Response>) -> status::Custom<Json<Response>> {
+    let status = match response.error.as_ref().map(|e| e.code()) {
+        Some(PartsErrorCode::Busy) => Status::ServiceUnavailable,
+        Some(PartsErrorCode::QuotaExceeded) => Status::TooManyRequests,
+        _ => Status::Ok,
+    };
+    status::Custom(status, response)
+}
+
+/// Error response for a write refused before it started, either as too many
+/// writes are waiting or as the parts list can no longer be written, or for a
+/// change which panicked
+fn write_rejected(response: Response, e: WriteRejected) -> Response {
+    match e {
+        WriteRejected::QueueFull => response.error(PartsErrorCode::Busy, &format!("{}", e)),
+        WriteRejected::Stopped => response.error(PartsErrorCode::LockError, &format!("{}", e)),
+        WriteRejected::Panicked => response.error(PartsErrorCode::InternalError, &format!("{}", e)),
+    }
+}
+
+/// Error response for a failed change, distinguishing version conflicts, parts
+/// still in use and exceeded quotas, and carrying the path of any cycle it
+/// would have made
+fn change_error(response: Response, code: PartsErrorCode, e: PartsListError) -> Response {
+    match e {
+        PartsListError::VersionConflict { .. } => {
+            response.error(PartsErrorCode::VersionConflict, &format!("{}", e))
+        }
+        PartsListError::PartInUse { .. } => {
+            response.error(PartsErrorCode::PartInUse, &format!("{}", e))
+        }
+        PartsListError::QuotaExceeded { .. } => {
+            response.error(PartsErrorCode::QuotaExceeded, &format!("{}", e))
+        }
+        PartsListError::AddChildCyclicalRelative { ref path, .. } => {
+            response.cycle_error(code, &format!("{}", e), path.clone())
+        }
+        e => response.error(code, &format!("{}", e)),
+    }
+}
+
+/// Set the listed parts as the data of the response, in the requested order or
+/// page
+fn listing(
+    response: Response,
+    mut parts: Vec<Part>,
+    sort: Option<PartsListSort>,
+    page: Option<Page>,
+) -> Response {
+    match page {
+        Some((cursor, limit)) => {
+            let (parts, next) = PartsListCursor::paginate(cursor, limit, parts);
+            response.data(parts).next_cursor(next)
+        }
+        None => {
+            if let Some(sort) = sort {
+                sort.sort(&mut parts);
+            }
+            response.data(parts)
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/v1/parts?<filter>&<sort>&<order>&<fields>&<limit>&<cursor>&<include_subcategories>&<predicates..>"
+)]
+pub fn list_parts(
+    filter: Option<&RawStr>,
+    sort: Option<&RawStr>,
+    order: Option<&RawStr>,
+    fields: Option<&RawStr>,
+    limit: Option<usize>,
+    cursor: Option<&RawStr>,
+    include_subcategories: Option<bool>,
+    predicates: Predicates,
+    accepted: Accepted,
+    categories: State<CategoryRegistry>,
+    parts: State<SharedPartsList>,
+) -> Negotiated {
+    let format = accepted.0;
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Negotiated(
+                format,
+                Response::new().error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid fields passed: {}", e),
+                ),
+            )
+        }
+    };
+    let sort = match parse_sort(sort, order) {
+        Ok(sort) => sort,
+        Err(e) => {
+            return Negotiated(
+                format,
+                response.error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid sort passed: {}", e),
+                ),
+            )
+        }
+    };
+    let page = match parse_page(limit, cursor, sort) {
+        Ok(page) => page,
+        Err(e) => {
+            return Negotiated(
+                format,
+                response.error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid pagination passed: {}", e),
+                ),
+            )
+        }
+    };
+    let predicates = match predicates.0 {
+        Ok(predicates) => predicates,
+        Err(e) => {
+            return Negotiated(
+                format,
+                response.error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid predicate passed: {}", e),
+                ),
+            )
+        }
+    };
+    let predicates = match categories.lock() {
+        Some(categories) => predicates
+            .into_iter()
+            .map(|predicate| match predicate {
+                PartsListPredicate::InCategory(ids) => categories
+                    .expand(&ids, include_subcategories.unwrap_or(false))
+                    .map(PartsListPredicate::InCategory),
+                predicate => Ok(predicate),
+            })
+            .collect::<Result<Vec<_>, _>>(),
+        None => {
+            return Negotiated(
+                format,
+                response.error(PartsErrorCode::LockError, "Couldn't lock categories!"),
+            )
+        }
+    };
+    let predicates = match predicates {
+        Ok(predicates) => predicates,
+        Err(e) => {
+            return Negotiated(
+                format,
+                response.error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid category passed: {}", e),
+                ),
+            )
+        }
+    };
     match filter
         .unwrap_or_else(|| RawStr::from_str("all"))
         .as_str()
         .try_into()
     {
         Ok(filter) => {
-            if let Ok(parts) = parts.0.try_read() {
-                let parts: &PartsList = &parts;
-                let list = parts.list(filter).into_iter().cloned().collect();
+            let query = PartsListQuery { filter, predicates };
+            if let Some(parts) = parts.try_read() {
+                match parts.query(&query) {
+                    Ok(list) => Negotiated(
+                        format,
+                        listing(
+                            response.result(200, "Fetched all parts successfully"),
+                            list,
+                            sort,
+                            page,
+                        ),
+                    ),
+                    Err(e) => Negotiated(
+                        format,
+                        response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+                    ),
+                }
+            } else {
+                Negotiated(
+                    format,
+                    response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+                )
+            }
+        }
+        Err(e) => Negotiated(
+            format,
+            response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid filter type passed: {}", e),
+            ),
+        ),
+    }
+}
+
+/// Part described by the body of a request to create it
+fn new_part(data: &NewPart) -> Part {
+    let mut part = match data.id {
+        Some(id) => Part::with_id(id, &data.name),
+        None => Part::new(&data.name),
+    };
+    part.external_ids = data.external_ids.clone();
+    part.category = data.category;
+    part.attributes = data.attributes.clone();
+    part
+}
+
+/// Check the attributes of a part against the schemas of its category, giving
+/// the reply listing the mismatches when they don't match
+fn attribute_mismatch(categories: &CategoryRegistry, part: &Part) -> Option<Response> {
+    let categories = match categories.lock() {
+        Some(categories) => categories,
+        None => {
+            return Some(
+                Response::new().error(PartsErrorCode::LockError, "Couldn't lock categories!"),
+            )
+        }
+    };
+    match categories.check_attributes(part.category, &part.attributes) {
+        Ok(errors) if errors.is_empty() => None,
+        Ok(errors) => Some(
+            Response::new()
+                .error(
+                    PartsErrorCode::RequestError,
+                    "Part attributes don't match the schemas of its category",
+                )
+                .attribute_errors(errors),
+        ),
+        Err(e) => Some(Response::new().error(PartsErrorCode::RequestError, &format!("{}", e))),
+    }
+}
+
+#[post("/v1/parts", data = "<data>")]
+pub fn create_part(
+    data: Payload<NewPart>,
+    key: IdempotencyKey,
+    replies: State<IdempotencyCache>,
+    categories: State<CategoryRegistry>,
+    parts: State<SharedPartsList>,
+    quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let body = serde_json::to_string(&*data).unwrap_or_default();
+    busy_status(Json(replies.reply(&key, &body, || {
+        let part = new_part(&data);
+        if let Some(reply) = attribute_mismatch(&categories, &part) {
+            return reply;
+        }
+        if let Err(e) = quota.reserve_parts(1) {
+            return change_error(response, PartsErrorCode::CreatePartError, e);
+        }
+        match parts.change(move |parts| parts.insert(part)) {
+            Ok(Ok(part)) => response
+                .result(201, "New part created successfully")
+                .data(vec![part]),
+            Ok(Err(e)) => {
+                quota.release_parts(1);
+                response.error(PartsErrorCode::CreatePartError, &format!("{}", e))
+            }
+            Err(e) => {
+                quota.release_parts(1);
+                write_rejected(response, e)
+            }
+        }
+    })))
+}
+
+#[put("/v1/parts?<key>", data = "<data>")]
+pub fn upsert_part(
+    data: Payload<NewPart>,
+    key: Option<String>,
+    categories: State<CategoryRegistry>,
+    parts: State<SharedPartsList>,
+    quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let part = new_part(&data);
+    if let Some(reply) = attribute_mismatch(&categories, &part) {
+        return busy_status(Json(reply));
+    }
+    // an upsert only creates a part when none matches, but is refused once no
+    // more parts may be created either way
+    if let Err(e) = quota.reserve_parts(1) {
+        return busy_status(Json(change_error(
+            response,
+            PartsErrorCode::CreatePartError,
+            e,
+        )));
+    }
+    let upserted = parts.change(move |parts| parts.upsert(part, key.as_deref()));
+    if !matches!(upserted, Ok(Ok((_, true)))) {
+        quota.release_parts(1);
+    }
+    busy_status(Json(match upserted {
+        Ok(Ok((part, true))) => response
+            .result(201, "New part created successfully")
+            .data(vec![part]),
+        Ok(Ok((part, false))) => response
+            .result(200, "Existing part updated successfully")
+            .data(vec![part]),
+        Ok(Err(e)) => change_error(response, PartsErrorCode::CreatePartError, e),
+        Err(e) => write_rejected(response, e),
+    }))
+}
+
+/// Number of components ranked by usage when no limit is given
+const DEFAULT_TOP_USED: usize = 10;
+
+#[get("/v1/parts/top_used?<limit>&<fields>")]
+pub fn top_used(
+    limit: Option<usize>,
+    fields: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Json(Response::new().error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid fields passed: {}", e),
+            ))
+        }
+    };
+    if let Some(parts) = parts.try_read() {
+        match parts.top_used(limit.unwrap_or(DEFAULT_TOP_USED)) {
+            Ok(ranked) => {
+                let (ranked, used_by) = ranked.into_iter().unzip();
                 Json(
                     response
-                        .result(200, "Fetched all parts successfully")
-                        .data(list),
+                        .result(200, "Ranked components by usage successfully")
+                        .data(ranked)
+                        .used_by(used_by),
                 )
-            } else {
-                Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
             }
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
         }
-        Err(e) => Json(response.error(
-            PartsErrorCode::RequestError,
-            &format!("Invalid filter type passed: {}", e),
-        )),
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+#[get("/v1/parts/common?<ids>&<fields>")]
+pub fn common_parts(
+    ids: Option<&RawStr>,
+    fields: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Json(Response::new().error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid fields passed: {}", e),
+            ))
+        }
+    };
+    let ids = match ids.map(|ids| {
+        ids.url_decode_lossy()
+            .split(',')
+            .map(|id| id.trim().parse())
+            .collect::<Result<Vec<Uuid>, _>>()
+    }) {
+        Some(Ok(ids)) => ids,
+        Some(Err(e)) => {
+            return Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid ids passed: {}", e),
+            ))
+        }
+        None => return Json(response.error(PartsErrorCode::RequestError, "No ids passed")),
+    };
+    if let Some(parts) = parts.try_read() {
+        match parts.common_components(&ids) {
+            Ok(common) => Json(
+                response
+                    .result(200, "Found components common to all assemblies")
+                    .data(common),
+            ),
+            Err(e @ PartsListError::PartDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
     }
 }
 
-#[post("/v1/parts", format = "json", data = "<data>")]
-pub fn create_part(data: Json<NewPart>, parts: State<SharedPartsList>) -> Json<Response> {
+#[post("/v1/parts/batch_get", data = "<data>")]
+pub fn batch_get(
+    data: Payload<BatchGet>,
+    max_batch: State<MaxBatchSize>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
     let response = Response::new();
-    if let Ok(mut parts) = parts.0.try_write() {
-        let part = Part::new(&data.name);
-        match parts.add(part) {
-            Ok(part) => Json(
+    if let Err(e) = max_batch.check(data.ids.len()) {
+        return Json(response.error(PartsErrorCode::RequestError, &e));
+    }
+    if let Some(parts) = parts.try_read() {
+        match parts.get_many(&data.ids) {
+            Ok((found, missing)) => Json(
                 response
-                    .result(201, "New part created successfully")
-                    .data(vec![part.clone()]),
+                    .result(200, "Fetched requested parts successfully")
+                    .data(found)
+                    .missing(missing),
             ),
-            Err(e) => Json(response.error(PartsErrorCode::CreatePartError, &format!("{}", e))),
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
         }
     } else {
-        Json(response.error(PartsErrorCode::LockError, "Couldn't write lock parts list!"))
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
     }
 }
 
 #[get("/v1/parts/<part_id>")]
-pub fn get_part(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Response> {
+pub fn get_part(
+    part_id: RocketUuid,
+    attachments: State<SharedAttachments>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
     let response = Response::new();
     let part_id = Uuid::from_bytes(*part_id.as_bytes());
-    if let Ok(parts) = parts.0.try_read() {
+    if let Some(parts) = parts.try_read() {
         match parts.get(&part_id) {
-            Ok(part) => Json(
-                response
-                    .result(200, "Found part in parts list")
-                    .data(vec![part.clone()]),
-            ),
+            Ok(part) => match attachments.store.list(&part_id) {
+                Ok(attachments) => Json(
+                    response
+                        .result(200, "Found part in parts list")
+                        .data(vec![part])
+                        .attachments(attachments),
+                ),
+                Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+            },
             Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
         }
     } else {
@@ -147,121 +964,782 @@ pub fn get_part(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Resp
     }
 }
 
-#[delete("/v1/parts/<part_id>")]
-pub fn delete_part(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Response> {
+#[get("/v1/parts/by_name/<name>")]
+pub fn get_part_by_name(name: String, parts: State<SharedPartsList>) -> Json<Response> {
     let response = Response::new();
-    let part_id = Uuid::from_bytes(*part_id.as_bytes());
-    if let Ok(mut parts) = parts.0.try_write() {
-        match parts.delete(&part_id) {
-            Ok(_) => Json(response.result(200, "Deleted part from list")),
-            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+    if let Some(parts) = parts.try_read() {
+        match parts.get_by_name(&name) {
+            Ok(found) if found.is_empty() => Json(response.error(
+                PartsErrorCode::MissingPartError,
+                &format!("{}", PartsListError::PartNameDoesNotExist { name }),
+            )),
+            Ok(found) => Json(response.result(200, "Found part in parts list").data(found)),
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
         }
     } else {
-        Json(response.error(PartsErrorCode::LockError, "Couldn't write lock parts list!"))
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
     }
 }
 
-#[get("/v1/parts/<part_id>/children?<filter>")]
-pub fn get_children(
-    part_id: RocketUuid,
-    filter: Option<&RawStr>,
+#[get("/v1/parts/by_external/<system>/<external_id>", rank = 2)]
+pub fn get_part_by_external(
+    system: String,
+    external_id: String,
     parts: State<SharedPartsList>,
 ) -> Json<Response> {
     let response = Response::new();
-    let part_id = Uuid::from_bytes(*part_id.as_bytes());
-    match filter
-        .unwrap_or_else(|| RawStr::from_str("all"))
-        .as_str()
-        .try_into()
-    {
-        Ok(filter) => match filter {
-            PartsListFilter::All | PartsListFilter::Component | PartsListFilter::TopLevel => {
-                if let Ok(parts) = parts.0.try_read() {
-                    let parts: &PartsList = &parts;
-                    match parts.get_children(&part_id, filter) {
-                        Ok(children) => {
-                            let children = children.into_iter().cloned().collect();
-                            Json(
-                                response
-                                    .result(200, "Fetched all parts successfully")
-                                    .data(children),
-                            )
-                        }
-                        Err(e) => Json(
-                            response.error(PartsErrorCode::MissingPartError, &format!("{}", e)),
-                        ),
-                    }
-                } else {
-                    Json(
-                        response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
-                    )
-                }
+    if let Some(parts) = parts.try_read() {
+        match parts.get_by_external(&system, &external_id) {
+            Ok(part) => Json(
+                response
+                    .result(200, "Found part in parts list")
+                    .data(vec![part]),
+            ),
+            Err(e @ PartsListError::ExternalIdDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
             }
-            _ => Json(response.error(
-                PartsErrorCode::RequestError,
-                "Unsupported filter on children, only all, top_level and component are supported",
-            )),
-        },
-        Err(e) => Json(response.error(
-            PartsErrorCode::RequestError,
-            &format!("Invalid filter type passed: {}", e),
-        )),
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
     }
 }
 
-#[post(
-    "/v1/parts/<part_id>/children?<action>",
-    format = "json",
-    data = "<data>"
-)]
-pub fn update_children(
+#[patch("/v1/parts/<part_id>?<expected_version>", data = "<data>")]
+pub fn edit_part(
     part_id: RocketUuid,
-    action: Option<&RawStr>,
-    data: Json<UpdateChildren>,
+    expected_version: Option<&RawStr>,
+    if_match: IfMatch,
+    data: Payload<PartEdit>,
+    categories: State<CategoryRegistry>,
     parts: State<SharedPartsList>,
-) -> Json<Response> {
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
     let response = Response::new();
     let part_id = Uuid::from_bytes(*part_id.as_bytes());
-    match action
-        .unwrap_or_else(|| RawStr::from_str("add"))
-        .as_str()
-        .try_into()
-    {
-        Ok(action) => {
-            if let Ok(mut parts) = parts.0.try_write() {
-                match parts.update(
-                    &part_id,
-                    &data.children.iter().collect::<Vec<&Uuid>>(),
-                    action,
-                ) {
-                    Ok(_) => Json(response.result(200, "Part children updated successfully")),
-                    Err(e) => {
-                        Json(response.error(PartsErrorCode::CreatePartError, &format!("{}", e)))
-                    }
+    // a new unit cost is recorded at the time the edit arrived, also when
+    // the edit is replayed from a write-ahead log
+    let edit = data.into_inner().stamped();
+    if edit.lifecycle.is_some() {
+        let e = PartsListError::LifecycleNotEditable { id: part_id };
+        return conflict_status(Json(
+            response.error(PartsErrorCode::RequestError, &format!("{}", e)),
+        ));
+    }
+    let expected_version = match parse_expected_version(expected_version, &if_match) {
+        Ok(version) => version,
+        Err(e) => return conflict_status(Json(response.error(PartsErrorCode::RequestError, &e))),
+    };
+    let categories = categories.inner().clone();
+    conflict_status(Json(
+        parts
+            .change(move |parts| {
+                let mut edited =
+                    match check_expected_version(&mut **parts, &part_id, expected_version)
+                        .and_then(|_| parts.get(&part_id))
+                    {
+                        Ok(part) => part,
+                        Err(e) => {
+                            return change_error(response, PartsErrorCode::CreatePartError, e)
+                        }
+                    };
+                edit.apply(&mut edited);
+                if let Some(reply) = attribute_mismatch(&categories, &edited) {
+                    return reply;
+                }
+                match parts.edit(&part_id, &edit) {
+                    Ok(part) => response
+                        .result(200, "Part edited successfully")
+                        .data(vec![part]),
+                    Err(e) => change_error(response, PartsErrorCode::CreatePartError, e),
+                }
+            })
+            .unwrap_or_else(|e| write_rejected(Response::new(), e)),
+    ))
+}
+
+#[delete("/v1/parts?<filter>")]
+pub fn delete_parts(
+    filter: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+    attachments: State<SharedAttachments>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    busy_status(
+        match filter
+            .map(|filter| filter.as_str())
+            .unwrap_or("")
+            .try_into()
+        {
+            Ok(PartsListFilter::Orphan) => match parts.change(|parts| {
+                parts.remove_orphans().map(|removed| {
+                    let purged = purged(&mut **parts, &removed);
+                    (removed, purged)
+                })
+            }) {
+                Ok(Ok((removed, purged))) => {
+                    attachments.remove_parts(&purged);
+                    Json(
+                        response
+                            .result(200, "Deleted all orphan parts from list")
+                            .data(removed),
+                    )
+                }
+                Ok(Err(e)) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+                Err(e) => Json(write_rejected(response, e)),
+            },
+            Ok(_) => Json(response.error(
+                PartsErrorCode::RequestError,
+                "Only orphan parts can be deleted in bulk, use filter=orphan",
+            )),
+            Err(e) => Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid filter type passed: {}", e),
+            )),
+        },
+    )
+}
+
+#[post("/v1/parts/bulk_delete", data = "<data>")]
+pub fn bulk_delete(
+    data: Payload<BulkDelete>,
+    max_batch: State<MaxBatchSize>,
+    parts: State<SharedPartsList>,
+    attachments: State<SharedAttachments>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    if let Err(e) = max_batch.check(data.ids.len()) {
+        return busy_status(Json(response.error(PartsErrorCode::RequestError, &e)));
+    }
+    let data = data.into_inner();
+    busy_status(Json(
+        parts
+            .change(move |parts| {
+                let mut removed = Vec::new();
+                let mut deletions = Vec::new();
+                for (id, result) in data
+                    .ids
+                    .iter()
+                    .zip(parts.delete_many(&data.ids, data.recursive))
+                {
+                    deletions.push(match result {
+                        Ok(parts) => {
+                            let deleted = parts.iter().map(|part| part.id).collect();
+                            removed.extend(parts);
+                            DeleteResult {
+                                id: *id,
+                                deleted,
+                                error: None,
+                            }
+                        }
+                        Err(e) => DeleteResult {
+                            id: *id,
+                            deleted: Vec::new(),
+                            error: change_error(
+                                Response::new(),
+                                PartsErrorCode::MissingPartError,
+                                e,
+                            )
+                            .error,
+                        },
+                    });
+                }
+                let succeeded = deletions
+                    .iter()
+                    .filter(|result| result.error.is_none())
+                    .count();
+                let purged = purged(&mut **parts, &removed);
+                let response = response
+                    .result(
+                        200,
+                        &format!("Deleted {} of {} listed parts", succeeded, deletions.len()),
+                    )
+                    .data(removed)
+                    .deletions(deletions);
+                (response, purged)
+            })
+            .map(|(response, purged)| {
+                attachments.remove_parts(&purged);
+                response
+            })
+            .unwrap_or_else(|e| write_rejected(Response::new(), e)),
+    ))
+}
+
+/// Largest number of parts a single request may list when updating children
+/// or acting on several parts, as chosen by the `max_batch_size` setting, so
+/// no request holds the parts list for long
+pub struct MaxBatchSize(pub usize);
+
+impl MaxBatchSize {
+    /// Check the number of parts listed by a request against the limit
+    fn check(&self, listed: usize) -> Result<(), String> {
+        if listed > self.0 {
+            Err(format!(
+                "Request lists {} parts, more than the limit of {}",
+                listed, self.0
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Whether deleting a part still used by assemblies fails unless forced, as
+/// chosen by the `strict_delete` setting
+pub struct StrictDelete(pub bool);
+
+/// Check that a part isn't used by any assembly when deletes are strict
+fn check_unused(parts: &dyn PartsStore, id: &Uuid, strict: bool) -> Result<(), PartsListError> {
+    if !strict {
+        return Ok(());
+    }
+    let mut parents: Vec<Uuid> = parts.get(id)?.parents.into_iter().collect();
+    if parents.is_empty() {
+        return Ok(());
+    }
+    parents.sort();
+    Err(PartsListError::PartInUse { id: *id, parents })
+}
+
+/// Ids of the `removed` parts, and of any deleted earlier, gone for good, whose
+/// attachments can be removed
+fn purged(parts: &mut dyn PartsStore, removed: &[Part]) -> Vec<Uuid> {
+    let ids: Vec<Uuid> = removed.iter().map(|part| part.id).collect();
+    parts.purged(&ids)
+}
+
+/// Error response for a failed delete, listing the assemblies still using the
+/// part in `data` when it is in use
+fn delete_error(response: Response, parts: &dyn PartsStore, e: PartsListError) -> Response {
+    let parents = match &e {
+        PartsListError::PartInUse { parents, .. } => parts.get_many(parents).ok(),
+        _ => None,
+    };
+    let response = change_error(response, PartsErrorCode::MissingPartError, e);
+    match parents {
+        Some((parents, _)) => response.data(parents),
+        None => response,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[delete("/v1/parts/<part_id>?<recursive>&<dry_run>&<force>&<expected_version>")]
+pub fn delete_part(
+    part_id: RocketUuid,
+    recursive: Option<bool>,
+    dry_run: Option<bool>,
+    force: Option<bool>,
+    expected_version: Option<&RawStr>,
+    if_match: IfMatch,
+    strict: State<StrictDelete>,
+    parts: State<SharedPartsList>,
+    attachments: State<SharedAttachments>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let strict = strict.0 && !force.unwrap_or(false);
+    let expected_version = match parse_expected_version(expected_version, &if_match) {
+        Ok(version) => version,
+        Err(e) => return conflict_status(Json(response.error(PartsErrorCode::RequestError, &e))),
+    };
+    conflict_status(
+        match (recursive.unwrap_or(false), dry_run.unwrap_or(false)) {
+            (recursive, true) => {
+                if let Some(parts) = parts.try_read() {
+                    let removed = check_unused(&*parts, &part_id, strict).and_then(|_| {
+                        if recursive {
+                            parts.delete_tree_candidates(&part_id)
+                        } else {
+                            parts.get(&part_id).map(|part| vec![part])
+                        }
+                    });
+                    match removed {
+                        Ok(removed) => Json(
+                            response
+                                .result(200, "Found parts that would be deleted")
+                                .data(removed),
+                        ),
+                        Err(e) => Json(delete_error(response, &*parts, e)),
+                    }
+                } else {
+                    Json(
+                        response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+                    )
                 }
-            } else {
-                Json(response.error(PartsErrorCode::LockError, "Couldn't write lock parts list!"))
             }
+            (true, false) => Json(
+                parts
+                    .change(move |parts| {
+                        match check_expected_version(&mut **parts, &part_id, expected_version)
+                            .and_then(|_| check_unused(&**parts, &part_id, strict))
+                            .and_then(|_| parts.delete_tree(&part_id))
+                        {
+                            Ok(removed) => {
+                                let purged = purged(&mut **parts, &removed);
+                                let response = response
+                                    .result(200, "Deleted part and its subtree from list")
+                                    .data(removed);
+                                (response, purged)
+                            }
+                            Err(e) => (delete_error(response, &**parts, e), Vec::new()),
+                        }
+                    })
+                    .map(|(response, purged)| {
+                        attachments.remove_parts(&purged);
+                        response
+                    })
+                    .unwrap_or_else(|e| write_rejected(Response::new(), e)),
+            ),
+            (false, false) => Json(
+                parts
+                    .change(move |parts| {
+                        match check_expected_version(&mut **parts, &part_id, expected_version)
+                            .and_then(|_| check_unused(&**parts, &part_id, strict))
+                            .and_then(|_| parts.remove(&part_id))
+                        {
+                            Ok(_) => (
+                                response.result(200, "Deleted part from list"),
+                                parts.purged(&[part_id]),
+                            ),
+                            Err(e) => (delete_error(response, &**parts, e), Vec::new()),
+                        }
+                    })
+                    .map(|(response, purged)| {
+                        attachments.remove_parts(&purged);
+                        response
+                    })
+                    .unwrap_or_else(|e| write_rejected(Response::new(), e)),
+            ),
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/v1/parts/<part_id>/children?<filter>&<sort>&<order>&<fields>&<limit>&<cursor>")]
+pub fn get_children(
+    part_id: RocketUuid,
+    filter: Option<&RawStr>,
+    sort: Option<&RawStr>,
+    order: Option<&RawStr>,
+    fields: Option<&RawStr>,
+    limit: Option<usize>,
+    cursor: Option<&RawStr>,
+    accepted: Accepted,
+    parts: State<SharedPartsList>,
+) -> Negotiated {
+    let format = accepted.0;
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Negotiated(
+                format,
+                Response::new().error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid fields passed: {}", e),
+                ),
+            )
         }
-        Err(e) => Json(response.error(
+    };
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let sort = match parse_sort(sort, order) {
+        Ok(sort) => sort,
+        Err(e) => {
+            return Negotiated(
+                format,
+                response.error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid sort passed: {}", e),
+                ),
+            )
+        }
+    };
+    let page = match parse_page(limit, cursor, sort) {
+        Ok(page) => page,
+        Err(e) => {
+            return Negotiated(
+                format,
+                response.error(
+                    PartsErrorCode::RequestError,
+                    &format!("Invalid pagination passed: {}", e),
+                ),
+            )
+        }
+    };
+    match filter
+        .unwrap_or_else(|| RawStr::from_str("all"))
+        .as_str()
+        .try_into()
+    {
+        Ok(filter) => match filter {
+            PartsListFilter::All
+            | PartsListFilter::Component
+            | PartsListFilter::Subassembly
+            | PartsListFilter::TopLevel => {
+                if let Some(parts) = parts.try_read() {
+                    match parts.get_children(&part_id, filter) {
+                        Ok(children) => Negotiated(format, listing(
+                            response.result(200, "Fetched all parts successfully"),
+                            children,
+                            sort,
+                            page,
+                        )),
+                        Err(e) => Negotiated(format,
+                            response.error(PartsErrorCode::MissingPartError, &format!("{}", e)),
+                        ),
+                    }
+                } else {
+                    Negotiated(format,
+                        response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+                    )
+                }
+            }
+            _ => Negotiated(format, response.error(
+                PartsErrorCode::RequestError,
+                "Unsupported filter on children, only all, top_level, component and subassembly are supported",
+            )),
+        },
+        Err(e) => Negotiated(format, response.error(
             PartsErrorCode::RequestError,
-            &format!("Invalid action type passed: {}", e),
+            &format!("Invalid filter type passed: {}", e),
         )),
     }
 }
 
-#[get("/v1/parts/<part_id>/contained")]
-pub fn get_contained(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Response> {
+#[allow(clippy::too_many_arguments)]
+#[post(
+    "/v1/parts/<part_id>/children?<action>&<expected_version>",
+    data = "<data>"
+)]
+pub fn update_children(
+    part_id: RocketUuid,
+    action: Option<&RawStr>,
+    expected_version: Option<&RawStr>,
+    if_match: IfMatch,
+    key: IdempotencyKey,
+    replies: State<IdempotencyCache>,
+    data: Payload<UpdateChildren>,
+    max_batch: State<MaxBatchSize>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let expected_version = match parse_expected_version(expected_version, &if_match) {
+        Ok(version) => version,
+        Err(e) => return conflict_status(Json(response.error(PartsErrorCode::RequestError, &e))),
+    };
+    if let Err(e) = max_batch.check(data.children.len()) {
+        return conflict_status(Json(response.error(PartsErrorCode::RequestError, &e)));
+    }
+    conflict_status(
+        match action
+            .unwrap_or_else(|| RawStr::from_str("add"))
+            .as_str()
+            .try_into()
+        {
+            Ok(action) => {
+                let body = serde_json::to_string(&*data).unwrap_or_default();
+                Json(replies.reply(&key, &body, || {
+                    let data = data.into_inner();
+                    parts
+                        .change(move |parts| {
+                            let children: Vec<&Uuid> = data.children.iter().collect();
+                            match check_expected_version(&mut **parts, &part_id, expected_version)
+                                .and_then(|_| parts.update(&part_id, &children, action))
+                            {
+                                Ok(_) => response.result(200, "Part children updated successfully"),
+                                Err(e) => {
+                                    change_error(response, PartsErrorCode::CreatePartError, e)
+                                }
+                            }
+                        })
+                        .unwrap_or_else(|e| write_rejected(Response::new(), e))
+                }))
+            }
+            Err(e) => Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid action type passed: {}", e),
+            )),
+        },
+    )
+}
+
+#[post("/v1/parts/<part_id>/restore")]
+pub fn restore_part(
+    part_id: RocketUuid,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    busy_status(Json(
+        match parts.change(move |parts| parts.restore(&part_id)) {
+            Ok(Ok(part)) => response
+                .result(200, "Deleted part restored successfully")
+                .data(vec![part]),
+            Ok(Err(e)) => response.error(PartsErrorCode::MissingPartError, &format!("{}", e)),
+            Err(e) => write_rejected(response, e),
+        },
+    ))
+}
+
+#[post("/v1/parts/<part_id>/move?<from>&<to>")]
+pub fn move_part(
+    part_id: RocketUuid,
+    from: Option<RocketUuid>,
+    to: Option<RocketUuid>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (
+            Uuid::from_bytes(*from.as_bytes()),
+            Uuid::from_bytes(*to.as_bytes()),
+        ),
+        _ => {
+            return busy_status(Json(response.error(
+                PartsErrorCode::RequestError,
+                "The ids of both the from and to parents are required",
+            )))
+        }
+    };
+    let moved = parts.change(move |parts| {
+        parts
+            .move_child(&part_id, &from, &to)
+            .and_then(|_| parts.get_many(&[part_id, from, to]))
+    });
+    busy_status(Json(match moved {
+        Ok(Ok((moved, _))) => response.result(200, "Part moved successfully").data(moved),
+        Ok(Err(e)) => change_error(response, PartsErrorCode::CreatePartError, e),
+        Err(e) => write_rejected(response, e),
+    }))
+}
+
+#[get("/v1/relationships/<relationship_id>")]
+pub fn get_relationship(
+    relationship_id: RocketUuid,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = Response::new();
+    let relationship_id = Uuid::from_bytes(*relationship_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        match parts.get_relationship(&relationship_id) {
+            Ok(relationship) => Json(
+                response
+                    .result(200, "Found relationship in parts list")
+                    .relationships(vec![relationship]),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+#[patch("/v1/relationships/<relationship_id>", data = "<data>")]
+pub fn edit_relationship(
+    relationship_id: RocketUuid,
+    data: Payload<RelationshipEdit>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let relationship_id = Uuid::from_bytes(*relationship_id.as_bytes());
+    let edit = data.into_inner();
+    busy_status(Json(
+        match parts.change(move |parts| parts.edit_relationship(&relationship_id, &edit)) {
+            Ok(Ok(relationship)) => response
+                .result(200, "Relationship edited successfully")
+                .relationships(vec![relationship]),
+            Ok(Err(e @ PartsListError::InvalidQuantity { .. })) => {
+                response.error(PartsErrorCode::RequestError, &format!("{}", e))
+            }
+            Ok(Err(e)) => response.error(PartsErrorCode::MissingPartError, &format!("{}", e)),
+            Err(e) => write_rejected(response, e),
+        },
+    ))
+}
+
+/// Check that a part exists before changing its attachments, without holding
+/// the parts list lock while they are changed
+#[allow(clippy::result_large_err)]
+fn check_part_exists(parts: &SharedPartsList, id: &Uuid) -> Result<(), Response> {
+    match parts.try_read() {
+        Some(parts) => parts.get(id).map(|_| ()).map_err(|e| {
+            Response::new().error(PartsErrorCode::MissingPartError, &format!("{}", e))
+        }),
+        None => {
+            Err(Response::new().error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+        }
+    }
+}
+
+#[post("/v1/parts/<part_id>/attachments", data = "<data>")]
+pub fn upload_attachments(
+    part_id: RocketUuid,
+    content_type: &ContentType,
+    data: Data,
+    attachments: State<SharedAttachments>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Err(response) = check_part_exists(&parts, &part_id) {
+        return Json(response);
+    }
+    let boundary = match content_type
+        .params()
+        .find(|(key, _)| *key == "boundary")
+        .filter(|_| content_type.is_form_data())
+    {
+        Some((_, boundary)) => boundary,
+        None => {
+            return Json(response.error(
+                PartsErrorCode::RequestError,
+                "Attachments must be uploaded as multipart/form-data",
+            ))
+        }
+    };
+    let uploads = match Upload::read_all(data.open(), boundary, attachments.max_size) {
+        Ok(uploads) if uploads.is_empty() => {
+            return Json(response.error(PartsErrorCode::RequestError, "No files uploaded"))
+        }
+        Ok(uploads) => uploads,
+        Err(e) => {
+            return Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid upload: {}", e),
+            ))
+        }
+    };
+    let mut saved = Vec::new();
+    for upload in uploads {
+        let attachment =
+            Attachment::new(part_id, &upload.filename, &upload.content_type, upload.size);
+        let saved_upload = upload
+            .open()
+            .and_then(|mut contents| attachments.store.save(&attachment, &mut contents));
+        if let Err(e) = saved_upload {
+            return Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)));
+        }
+        saved.push(attachment);
+    }
+    Json(
+        response
+            .result(201, "Attachments uploaded successfully")
+            .attachments(saved),
+    )
+}
+
+#[get("/v1/parts/<part_id>/attachments", rank = 2)]
+pub fn list_attachments(
+    part_id: RocketUuid,
+    attachments: State<SharedAttachments>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
     let response = Response::new();
     let part_id = Uuid::from_bytes(*part_id.as_bytes());
-    if let Ok(parts) = parts.0.try_read() {
-        match parts.get_children(&part_id, PartsListFilter::Assembly) {
-            Ok(children) => {
-                let children = children.into_iter().cloned().collect();
+    if let Err(response) = check_part_exists(&parts, &part_id) {
+        return Json(response);
+    }
+    match attachments.store.list(&part_id) {
+        Ok(found) => Json(
+            response
+                .result(200, "Fetched attachments successfully")
+                .attachments(found),
+        ),
+        Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+    }
+}
+
+/// Error response for a failed attachment lookup
+fn attachment_error(e: PartsListError) -> Response {
+    let code = match e {
+        PartsListError::AttachmentDoesNotExist { .. } => PartsErrorCode::MissingPartError,
+        _ => PartsErrorCode::StorageError,
+    };
+    Response::new().error(code, &format!("{}", e))
+}
+
+#[allow(clippy::result_large_err)]
+#[get("/v1/parts/<part_id>/attachments/<attachment_id>")]
+pub fn download_attachment(
+    part_id: RocketUuid,
+    attachment_id: RocketUuid,
+    attachments: State<SharedAttachments>,
+) -> Result<rocket::Response<'static>, Json<Response>> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let attachment_id = Uuid::from_bytes(*attachment_id.as_bytes());
+    match attachments.store.open(&part_id, &attachment_id) {
+        Ok((attachment, contents)) => Ok(rocket::Response::build()
+            .header(
+                ContentType::parse_flexible(&attachment.content_type)
+                    .unwrap_or(ContentType::Binary),
+            )
+            .raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", attachment.filename),
+            )
+            .streamed_body(contents)
+            .finalize()),
+        Err(e) => Err(Json(attachment_error(e))),
+    }
+}
+
+#[delete("/v1/parts/<part_id>/attachments/<attachment_id>")]
+pub fn delete_attachment(
+    part_id: RocketUuid,
+    attachment_id: RocketUuid,
+    attachments: State<SharedAttachments>,
+    _quota: Metered,
+) -> Json<Response> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let attachment_id = Uuid::from_bytes(*attachment_id.as_bytes());
+    match attachments.store.remove(&part_id, &attachment_id) {
+        Ok(attachment) => Json(
+            Response::new()
+                .result(200, "Deleted attachment")
+                .attachments(vec![attachment]),
+        ),
+        Err(e) => Json(attachment_error(e)),
+    }
+}
+
+#[get("/v1/parts/<part_id>/parents?<fields>")]
+pub fn get_parents(
+    part_id: RocketUuid,
+    fields: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Json(Response::new().error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid fields passed: {}", e),
+            ))
+        }
+    };
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        let parents = parts.get(&part_id).and_then(|part| {
+            let ids: Vec<Uuid> = part.parents.into_iter().collect();
+            parts.get_many(&ids)
+        });
+        match parents {
+            Ok((mut parents, _)) => {
+                parents.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
                 Json(
                     response
                         .result(200, "Fetched all parts successfully")
-                        .data(children),
+                        .data(parents),
                 )
             }
             Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
@@ -270,3 +1748,2143 @@ pub fn get_contained(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json
         Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
     }
 }
+
+#[get("/v1/parts/<part_id>/summary", rank = 2)]
+pub fn get_summary(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        match parts.summary(&part_id) {
+            Ok(summary) => Json(
+                response
+                    .result(200, "Summarized subtree of part successfully")
+                    .summary(summary),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+#[get("/v1/parts/<part_id>/contained?<fields>")]
+pub fn get_contained(
+    part_id: RocketUuid,
+    fields: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Json(Response::new().error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid fields passed: {}", e),
+            ))
+        }
+    };
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        match parts.get_ancestors(&part_id, None) {
+            Ok(ancestors) => Json(
+                response
+                    .result(200, "Fetched all parts successfully")
+                    .data(ancestors),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+#[get("/v1/parts/<part_id>/ancestors?<depth>&<fields>")]
+pub fn get_ancestors(
+    part_id: RocketUuid,
+    depth: Option<usize>,
+    fields: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Json(Response::new().error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid fields passed: {}", e),
+            ))
+        }
+    };
+    if depth == Some(0) {
+        return Json(response.error(PartsErrorCode::RequestError, "Depth must be at least 1"));
+    }
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        match parts.get_ancestors(&part_id, depth) {
+            Ok(ancestors) => Json(
+                response
+                    .result(200, "Fetched all parts successfully")
+                    .data(ancestors),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Parse the configuration an assembly is resolved with, if one is given
+fn parse_config(config: Option<&RawStr>) -> Result<Option<Configuration>, PartsListError> {
+    config
+        .map(|config| variants::parse_config(&config.url_decode_lossy()))
+        .transpose()
+}
+
+/// Parse the rule choosing the supplier of each part, preferred suppliers
+/// being used when none is given
+fn parse_policy(policy: Option<&RawStr>) -> Result<SupplierPolicy, PartsListError> {
+    policy.map_or(Ok(SupplierPolicy::default()), |policy| {
+        policy.as_str().try_into()
+    })
+}
+
+/// Error response for an explosion which failed, distinguishing missing parts
+/// and invalid configurations from storage failures
+fn explode_error(response: Response, e: PartsListError) -> Response {
+    let code = match e {
+        PartsListError::PartDoesNotExist { .. } => PartsErrorCode::MissingPartError,
+        PartsListError::InvalidConfigString { .. }
+        | PartsListError::UnknownOptionValue { .. }
+        | PartsListError::OptionNotConfigured { .. }
+        | PartsListError::InvalidSupplierPolicyString { .. }
+        | PartsListError::InvalidCurrency { .. }
+        | PartsListError::UnknownCurrency { .. }
+        | PartsListError::ChangesUnavailable
+        | PartsListError::InvalidCursor { .. }
+        | PartsListError::NotReplicating => PartsErrorCode::RequestError,
+        PartsListError::ChangesExpired { .. } => PartsErrorCode::ChangesExpired,
+        _ => PartsErrorCode::StorageError,
+    };
+    response.error(code, &format!("{}", e))
+}
+
+#[get("/v1/parts/<part_id>/shortage?<build_qty>&<config>")]
+pub fn get_shortage(
+    part_id: RocketUuid,
+    build_qty: Option<u64>,
+    config: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let config = match parse_config(config) {
+        Ok(config) => config,
+        Err(e) => return Json(explode_error(response, e)),
+    };
+    if let Some(parts) = parts.try_read() {
+        match inventory::shortages(&*parts, &part_id, build_qty.unwrap_or(1), config.as_ref()) {
+            Ok(shortages) => Json(
+                response
+                    .result(200, "Checked stock for build successfully")
+                    .shortages(shortages),
+            ),
+            Err(e) => Json(explode_error(response, e)),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+#[get("/v1/parts/<part_id>/lead_time?<config>&<policy>")]
+pub fn get_lead_time(
+    part_id: RocketUuid,
+    config: Option<&RawStr>,
+    policy: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+    rates: State<ExchangeRates>,
+) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let config = match parse_config(config) {
+        Ok(config) => config,
+        Err(e) => return Json(explode_error(response, e)),
+    };
+    let policy = match parse_policy(policy) {
+        Ok(policy) => policy,
+        Err(e) => return Json(explode_error(response, e)),
+    };
+    if let Some(parts) = parts.try_read() {
+        match inventory::lead_time(&*parts, &part_id, config.as_ref(), policy, &rates) {
+            Ok(lead_time) => Json(
+                response
+                    .result(200, "Found critical procurement path successfully")
+                    .lead_time(lead_time),
+            ),
+            Err(e) => Json(explode_error(response, e)),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Reply holding either a JSON response or its data in another format
+#[allow(clippy::large_enum_variant)]
+#[derive(Responder)]
+pub enum Formatted {
+    Json(Json<Response>),
+    Other(Content<String>),
+    Binary(Content<Vec<u8>>),
+}
+
+#[get("/v1/parts/<part_id>/picklist?<qty>&<format>&<config>")]
+pub fn get_picklist(
+    part_id: RocketUuid,
+    qty: Option<u64>,
+    format: Option<&RawStr>,
+    config: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Formatted {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let csv = match format.map(|format| format.as_str()) {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(format) => {
+            return Formatted::Json(Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid format passed: {:?}", format),
+            )))
+        }
+    };
+    let config = match parse_config(config) {
+        Ok(config) => config,
+        Err(e) => return Formatted::Json(Json(explode_error(response, e))),
+    };
+    let items = if let Some(parts) = parts.try_read() {
+        match inventory::picklist(&*parts, &part_id, qty.unwrap_or(1), config.as_ref()) {
+            Ok(items) => items,
+            Err(e) => return Formatted::Json(Json(explode_error(response, e))),
+        }
+    } else {
+        return Formatted::Json(Json(
+            response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+        ));
+    };
+    if !csv {
+        return Formatted::Json(Json(
+            response
+                .result(200, "Built pick list successfully")
+                .picklist(items),
+        ));
+    }
+    match inventory::picklist_csv(&items) {
+        Ok(csv) => Formatted::Other(Content(ContentType::CSV, csv)),
+        Err(e) => Formatted::Json(Json(
+            response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        )),
+    }
+}
+
+/// Cost of building a quantity of an assembly, converted into the requested
+/// currency or the base currency of the server
+#[get("/v1/parts/<part_id>/cost?<policy>&<currency>&<qty>", rank = 2)]
+pub fn get_cost(
+    part_id: RocketUuid,
+    policy: Option<&RawStr>,
+    currency: Option<&RawStr>,
+    qty: Option<u64>,
+    parts: State<SharedPartsList>,
+    rates: State<ExchangeRates>,
+) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let policy = match parse_policy(policy) {
+        Ok(policy) => policy,
+        Err(e) => return Json(explode_error(response, e)),
+    };
+    let currency = currency.map_or(rates.base(), |currency| currency.as_str());
+    if let Err(e) = currency::check_code(currency) {
+        return Json(explode_error(response, e));
+    }
+    if let Some(parts) = parts.try_read() {
+        match cost::rollup(
+            &*parts,
+            &part_id,
+            policy,
+            qty.unwrap_or(1),
+            &rates,
+            currency,
+        ) {
+            Ok(rollup) => Json(
+                response
+                    .result(200, "Rolled up cost successfully")
+                    .cost(rollup),
+            ),
+            Err(e) => Json(explode_error(response, e)),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Request for quote of the components needed to build a quantity of an
+/// assembly, with target prices from its suppliers, as JSON or CSV for
+/// spreadsheets
+#[get("/v1/parts/<part_id>/rfq?<qty>&<policy>&<currency>&<format>", rank = 2)]
+pub fn get_rfq(
+    part_id: RocketUuid,
+    qty: Option<u64>,
+    policy: Option<&RawStr>,
+    currency: Option<&RawStr>,
+    format: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+    rates: State<ExchangeRates>,
+) -> Formatted {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let csv = match format.map(|format| format.as_str()) {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(format) => {
+            return Formatted::Json(Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid format passed: {:?}", format),
+            )))
+        }
+    };
+    let policy = match parse_policy(policy) {
+        Ok(policy) => policy,
+        Err(e) => return Formatted::Json(Json(explode_error(response, e))),
+    };
+    let currency = currency.map_or(rates.base(), |currency| currency.as_str());
+    if let Err(e) = currency::check_code(currency) {
+        return Formatted::Json(Json(explode_error(response, e)));
+    }
+    let lines = if let Some(parts) = parts.try_read() {
+        match cost::rfq(
+            &*parts,
+            &part_id,
+            policy,
+            qty.unwrap_or(1),
+            &rates,
+            currency,
+        ) {
+            Ok(lines) => lines,
+            Err(e) => return Formatted::Json(Json(explode_error(response, e))),
+        }
+    } else {
+        return Formatted::Json(Json(
+            response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+        ));
+    };
+    if !csv {
+        return Formatted::Json(Json(
+            response
+                .result(200, "Built request for quote successfully")
+                .rfq(lines),
+        ));
+    }
+    match cost::rfq_csv(&lines) {
+        Ok(csv) => Formatted::Other(Content(ContentType::CSV, csv)),
+        Err(e) => Formatted::Json(Json(
+            response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        )),
+    }
+}
+
+#[get("/v1/parts/<part_id>/cost_history", rank = 2)]
+pub fn get_cost_history(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        match parts.get(&part_id) {
+            Ok(part) => Json(
+                response
+                    .result(200, "Fetched cost history successfully")
+                    .cost_history(CostHistory::of(&part)),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Cost of the components of an assembly each time one of their costs
+/// changed, as JSON or CSV for spreadsheets
+#[get("/v1/parts/<part_id>/cost_trend?<format>", rank = 2)]
+pub fn get_cost_trend(
+    part_id: RocketUuid,
+    format: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+    rates: State<ExchangeRates>,
+) -> Formatted {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let csv = match format.map(|format| format.as_str()) {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(format) => {
+            return Formatted::Json(Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid format passed: {:?}", format),
+            )))
+        }
+    };
+    let points = if let Some(parts) = parts.try_read() {
+        match cost::cost_trend(&*parts, &part_id, &rates) {
+            Ok(points) => points,
+            Err(e) => return Formatted::Json(Json(explode_error(response, e))),
+        }
+    } else {
+        return Formatted::Json(Json(
+            response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+        ));
+    };
+    if !csv {
+        return Formatted::Json(Json(
+            response
+                .result(200, "Built cost trend successfully")
+                .cost_trend(points),
+        ));
+    }
+    match cost::cost_trend_csv(&points) {
+        Ok(csv) => Formatted::Other(Content(ContentType::CSV, csv)),
+        Err(e) => Formatted::Json(Json(
+            response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        )),
+    }
+}
+
+#[get("/v1/parts/<part_id>/report.html", rank = 2)]
+pub fn get_report(part_id: RocketUuid, parts: State<SharedPartsList>) -> Formatted {
+    report(part_id, false, parts)
+}
+
+#[get("/v1/parts/<part_id>/report?<format>")]
+pub fn get_report_formatted(
+    part_id: RocketUuid,
+    format: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Formatted {
+    match format.map(|format| format.as_str()) {
+        None | Some("html") => report(part_id, false, parts),
+        Some("pdf") => report(part_id, true, parts),
+        Some(format) => Formatted::Json(Json(Response::new().error(
+            PartsErrorCode::RequestError,
+            &format!("Invalid format passed: {:?}", format),
+        ))),
+    }
+}
+
+/// Render the report of an assembly as either an HTML page or a PDF document
+fn report(part_id: RocketUuid, pdf: bool, parts: State<SharedPartsList>) -> Formatted {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let report = if let Some(parts) = parts.try_read() {
+        match BomReport::new(&*parts, &part_id) {
+            Ok(report) => report,
+            Err(e) => return Formatted::Json(Json(explode_error(response, e))),
+        }
+    } else {
+        return Formatted::Json(Json(
+            response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+        ));
+    };
+    if pdf {
+        return match report.to_pdf() {
+            Ok(bytes) => Formatted::Binary(Content(ContentType::PDF, bytes)),
+            Err(e) => Formatted::Json(Json(
+                response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+            )),
+        };
+    }
+    match report.render() {
+        Ok(html) => Formatted::Other(Content(ContentType::HTML, html)),
+        Err(e) => Formatted::Json(Json(
+            response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        )),
+    }
+}
+
+/// Label identifying a part for the stockroom, either a QR code or Code 128
+/// barcode of its id as an SVG or PNG image, or a ZPL program printing its
+/// name and barcode
+#[get("/v1/parts/<part_id>/label?<format>&<image>")]
+pub fn get_label(
+    part_id: RocketUuid,
+    format: Option<&RawStr>,
+    image: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Formatted {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let format = match format.map_or(Ok(LabelFormat::Qr), |format| format.as_str().try_into()) {
+        Ok(format) => format,
+        Err(e) => {
+            return Formatted::Json(Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid format passed: {}", e),
+            )))
+        }
+    };
+    let image = match image.map_or(Ok(ImageFormat::Svg), |image| image.as_str().try_into()) {
+        Ok(image) => image,
+        Err(e) => {
+            return Formatted::Json(Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid image passed: {}", e),
+            )))
+        }
+    };
+    let part = if let Some(parts) = parts.try_read() {
+        match parts.get(&part_id) {
+            Ok(part) => part,
+            Err(e) => {
+                return Formatted::Json(Json(
+                    response.error(PartsErrorCode::MissingPartError, &format!("{}", e)),
+                ))
+            }
+        }
+    } else {
+        return Formatted::Json(Json(
+            response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+        ));
+    };
+    let barcode = match format {
+        LabelFormat::Qr => Barcode::qr(&part.id.to_string()),
+        LabelFormat::Code128 => Barcode::code128(&part.id.to_string()),
+        LabelFormat::Zpl => {
+            return Formatted::Other(Content(ContentType::Plain, label::zpl(&part)))
+        }
+    };
+    let rendered = barcode.and_then(|barcode| match image {
+        ImageFormat::Svg => Ok(Formatted::Other(Content(
+            ContentType::SVG,
+            barcode.to_svg(),
+        ))),
+        ImageFormat::Png => barcode
+            .to_png()
+            .map(|png| Formatted::Binary(Content(ContentType::PNG, png))),
+    });
+    rendered.unwrap_or_else(|e| {
+        Formatted::Json(Json(
+            response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        ))
+    })
+}
+
+#[get("/v1/parts/<part_id>/routing", rank = 2)]
+pub fn get_routing(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        match parts.get(&part_id) {
+            Ok(part) => Json(
+                response
+                    .result(200, "Fetched routing successfully")
+                    .routing(Routing::of(&part)),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Replace the routing of a part with the steps `change` makes of it, replying
+/// with the routing as changed
+fn change_routing<F: FnOnce(&mut Vec<RoutingStep>) + Send + 'static>(
+    part_id: Uuid,
+    parts: &SharedPartsList,
+    message: &'static str,
+    change: F,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    busy_status(Json(
+        parts
+            .change(move |parts| {
+                let mut steps = match parts.get(&part_id) {
+                    Ok(part) => part.routing,
+                    Err(e) => {
+                        return response.error(PartsErrorCode::MissingPartError, &format!("{}", e))
+                    }
+                };
+                change(&mut steps);
+                let edit = PartEdit {
+                    routing: Some(steps),
+                    ..PartEdit::default()
+                };
+                match parts.edit(&part_id, &edit) {
+                    Ok(part) => response.result(200, message).routing(Routing::of(&part)),
+                    Err(e) => change_error(response, PartsErrorCode::CreatePartError, e),
+                }
+            })
+            .unwrap_or_else(|e| write_rejected(Response::new(), e)),
+    ))
+}
+
+/// Replace every step of the routing of an assembly, in the order given
+#[put("/v1/parts/<part_id>/routing", data = "<data>")]
+pub fn set_routing(
+    part_id: RocketUuid,
+    data: Payload<Vec<RoutingStep>>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let steps = data.into_inner();
+    change_routing(
+        part_id,
+        &parts,
+        "Routing replaced successfully",
+        move |routing| *routing = steps,
+    )
+}
+
+/// Add a step to the end of the routing of an assembly
+#[post("/v1/parts/<part_id>/routing", data = "<data>")]
+pub fn add_routing_step(
+    part_id: RocketUuid,
+    data: Payload<RoutingStep>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let step = data.into_inner();
+    change_routing(
+        part_id,
+        &parts,
+        "Routing step added successfully",
+        move |routing| routing.push(step),
+    )
+}
+
+#[delete("/v1/parts/<part_id>/routing")]
+pub fn delete_routing(
+    part_id: RocketUuid,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    change_routing(part_id, &parts, "Routing removed successfully", Vec::clear)
+}
+
+#[get("/v1/parts/<part_id>/instructions", rank = 2)]
+pub fn get_instructions(part_id: RocketUuid, parts: State<SharedPartsList>) -> Json<Response> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    if let Some(parts) = parts.try_read() {
+        match parts.get(&part_id) {
+            Ok(part) => Json(
+                response
+                    .result(200, "Fetched work instructions successfully")
+                    .instructions(Instructions::of(&part)),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Replace the markdown work instructions of an assembly, bumping its version
+/// like any other edit so they can be changed without losing another's edit
+#[put("/v1/parts/<part_id>/instructions?<expected_version>", data = "<data>")]
+pub fn set_instructions(
+    part_id: RocketUuid,
+    expected_version: Option<&RawStr>,
+    if_match: IfMatch,
+    data: Payload<NewInstructions>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let expected_version = match parse_expected_version(expected_version, &if_match) {
+        Ok(version) => version,
+        Err(e) => return conflict_status(Json(response.error(PartsErrorCode::RequestError, &e))),
+    };
+    let edit = PartEdit {
+        instructions: Some(data.into_inner().markdown),
+        ..PartEdit::default()
+    };
+    let edited = parts.change(move |parts| {
+        check_expected_version(&mut **parts, &part_id, expected_version)
+            .and_then(|_| parts.edit(&part_id, &edit))
+    });
+    conflict_status(Json(match edited {
+        Ok(Ok(part)) => response
+            .result(200, "Work instructions replaced successfully")
+            .instructions(Instructions::of(&part)),
+        Ok(Err(e @ PartsListError::PartDoesNotExist { .. })) => {
+            response.error(PartsErrorCode::MissingPartError, &format!("{}", e))
+        }
+        Ok(Err(e)) => change_error(response, PartsErrorCode::CreatePartError, e),
+        Err(e) => write_rejected(response, e),
+    }))
+}
+
+#[get("/v1/search?<q>&<fuzzy>&<fields>")]
+pub fn search(
+    q: Option<String>,
+    fuzzy: Option<bool>,
+    fields: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> Json<Response> {
+    let response = match parse_fields(fields) {
+        Ok(fields) => Response::new().fields(fields),
+        Err(e) => {
+            return Json(Response::new().error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid fields passed: {}", e),
+            ))
+        }
+    };
+    let q = match q {
+        Some(q) if !q.trim().is_empty() => q,
+        _ => return Json(response.error(PartsErrorCode::RequestError, "No search query passed")),
+    };
+    if let Some(parts) = parts.try_read() {
+        match parts.search(&q, fuzzy.unwrap_or(false)) {
+            Ok(hits) => {
+                let (found, scores) = hits.into_iter().unzip();
+                Json(
+                    response
+                        .result(200, "Searched parts successfully")
+                        .data(found)
+                        .scores(scores),
+                )
+            }
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+#[get("/v1/stats")]
+pub fn get_stats(parts: State<SharedPartsList>) -> Json<Response> {
+    let response = Response::new();
+    if let Some(parts) = parts.try_read() {
+        match parts.stats() {
+            Ok(stats) => Json(
+                response
+                    .result(200, "Fetched parts list statistics successfully")
+                    .stats(stats),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Changes made after the change numbered `since`, so mirrors can follow the
+/// parts without downloading them all again
+#[get("/v1/changes?<since>&<limit>")]
+pub fn get_changes(
+    since: Option<u64>,
+    limit: Option<usize>,
+    parts: State<SharedPartsList>,
+    max_batch: State<MaxBatchSize>,
+) -> Json<Response> {
+    let response = Response::new();
+    let limit = limit.unwrap_or(max_batch.0).min(max_batch.0).max(1);
+    match parts
+        .changes()
+        .and_then(|journal| journal.since(since.unwrap_or(0), limit))
+    {
+        Ok(changes) => Json(
+            response
+                .result(200, "Fetched changes successfully")
+                .changes(changes),
+        ),
+        Err(e) => Json(explode_error(response, e)),
+    }
+}
+
+/// Every part along with the cursor of the latest change they include, for
+/// mirrors to start from
+#[get("/v1/changes/snapshot")]
+pub fn get_change_snapshot(parts: State<SharedPartsList>) -> Json<Response> {
+    let response = Response::new();
+    match parts.change_snapshot() {
+        Ok((cursor, parts)) => Json(
+            response
+                .result(200, "Fetched parts with their change cursor successfully")
+                .data(parts)
+                .changes(ChangeFeed {
+                    cursor,
+                    more: false,
+                    changes: Vec::new(),
+                }),
+        ),
+        Err(e) => Json(explode_error(response, e)),
+    }
+}
+
+/// Progress of the follower copying the parts of its leader
+#[get("/v1/replication")]
+pub fn get_replication(replication: State<Replication>) -> Json<Response> {
+    let response = Response::new();
+    match replication.status() {
+        Ok(status) => Json(
+            response
+                .result(200, "Fetched replication status successfully")
+                .replication(status),
+        ),
+        Err(e) => Json(explode_error(response, e)),
+    }
+}
+
+/// Performance counters recorded since the server started, which are
+/// process-wide and need no access to the parts
+#[get("/v1/admin/perf")]
+pub fn get_perf(_admin: Admin) -> Json<Response> {
+    Json(
+        Response::new()
+            .result(200, "Fetched performance counters successfully")
+            .perf(perf::summary()),
+    )
+}
+
+#[get("/v1/admin/validate")]
+pub fn validate(parts: State<SharedPartsList>, _admin: Admin) -> Json<Response> {
+    let response = Response::new();
+    if let Some(parts) = parts.try_read() {
+        match parts.validate() {
+            Ok(report) => {
+                let description = if report.is_valid() {
+                    String::from("Parts list is valid")
+                } else {
+                    format!("Parts list has {} violations", report.violations.len())
+                };
+                Json(response.result(200, &description).validation(report))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+#[get("/v1/admin/duplicates")]
+pub fn duplicates(parts: State<SharedPartsList>, _admin: Admin) -> Json<Response> {
+    let response = Response::new();
+    if let Some(parts) = parts.try_read() {
+        match analysis::duplicates(&*parts) {
+            Ok(groups) => {
+                let description =
+                    format!("Found {} groups of duplicate subassemblies", groups.len());
+                Json(response.result(200, &description).duplicates(groups))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"))
+    }
+}
+
+/// Largest archive accepted by `restore`
+const MAX_BACKUP_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Compressed archive streamed by `backup`
+type BackupArchive = Content<Stream<GzEncoder<Cursor<Vec<u8>>>>>;
+
+#[allow(clippy::result_large_err)]
+#[get("/v1/admin/backup")]
+pub fn backup(
+    parts: State<SharedPartsList>,
+    categories: State<CategoryRegistry>,
+    ecos: State<EcoRegistry>,
+    releases: State<ReleaseRegistry>,
+    _admin: Admin,
+) -> Result<BackupArchive, Json<Response>> {
+    let response = Response::new();
+    let categories = match categories.lock() {
+        Some(categories) => categories.list(),
+        None => {
+            return Err(Json(
+                response.error(PartsErrorCode::LockError, "Couldn't lock categories!"),
+            ))
+        }
+    };
+    let ecos = match ecos.lock() {
+        Some(ecos) => ecos.list(None, None),
+        None => {
+            return Err(Json(
+                response.error(PartsErrorCode::LockError, "Couldn't lock ECOs!"),
+            ))
+        }
+    };
+    let releases = match releases.lock() {
+        Some(releases) => releases.list(None, None),
+        None => {
+            return Err(Json(
+                response.error(PartsErrorCode::LockError, "Couldn't lock releases!"),
+            ))
+        }
+    };
+    let parts = match parts.try_read() {
+        Some(parts) => parts.parts(),
+        None => {
+            return Err(Json(response.error(
+                PartsErrorCode::LockError,
+                "Couldn't read lock parts list!",
+            )))
+        }
+    };
+    // the lock is released before the archive is compressed and sent
+    match parts.and_then(|parts| {
+        Backup::new(parts)
+            .with_categories(categories)
+            .with_ecos(ecos)
+            .with_releases(releases)
+            .compressed()
+    }) {
+        Ok(archive) => Ok(Content(
+            ContentType::new("application", "gzip"),
+            Stream::from(archive),
+        )),
+        Err(e) => Err(Json(
+            response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        )),
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::result_large_err)]
+#[post("/v1/admin/restore?<dry_run>", data = "<data>")]
+pub fn restore(
+    data: Data,
+    dry_run: Option<bool>,
+    parts: State<SharedPartsList>,
+    categories: State<CategoryRegistry>,
+    ecos: State<EcoRegistry>,
+    releases: State<ReleaseRegistry>,
+    _admin: Admin,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let backup = match Backup::decompress(data.open().take(MAX_BACKUP_SIZE)) {
+        Ok(backup) => backup,
+        Err(e) => {
+            return busy_status(Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid backup archive: {}", e),
+            )))
+        }
+    };
+    let report = ValidationReport::check(&backup.parts);
+    if !report.is_valid() {
+        let description = format!("Backup has {} violations", report.violations.len());
+        return busy_status(Json(
+            response
+                .error(PartsErrorCode::RequestError, &description)
+                .validation(report),
+        ));
+    }
+    // the categories are restored after the parts, so checked beforehand
+    if let Err(e) = category::check_tree(&backup.categories) {
+        return busy_status(Json(unrestorable(response, e)));
+    }
+    if dry_run.unwrap_or(false) {
+        // restored into a new list, with the same checks as restoring it
+        let restored = match parts.try_read() {
+            Some(parts) => parts.restored(backup.parts),
+            None => {
+                return busy_status(Json(
+                    response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+                ))
+            }
+        };
+        return busy_status(Json(match restored {
+            Ok(restored) => {
+                let description = format!(
+                    "Backup is valid, {} parts would be restored",
+                    restored.len()
+                );
+                response
+                    .result(200, &description)
+                    .validation(report)
+                    .stats(restored.stats())
+            }
+            Err(e) => unrestorable(response, e).validation(report),
+        }));
+    }
+    let Backup {
+        parts: backup_parts,
+        categories: backup_categories,
+        ecos: backup_ecos,
+        releases: backup_releases,
+        ..
+    } = backup;
+    let restored = parts.change(move |parts| {
+        let restored = parts
+            .restored(backup_parts)
+            .map_err(|e| unrestorable(Response::new(), e))?;
+        let (count, stats) = (restored.len(), restored.stats());
+        match parts.replace_all(restored.into_iter().collect()) {
+            Ok(()) => Ok((count, stats)),
+            Err(e) => Err(Response::new().error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    });
+    // the rest of the dataset is replaced once the parts are
+    busy_status(Json(match restored {
+        Ok(Ok((count, stats))) => match restore_categories(&categories, backup_categories)
+            .and_then(|_| restore_ecos(&ecos, backup_ecos))
+            .and_then(|_| restore_releases(&releases, backup_releases))
+        {
+            Ok(()) => response
+                .result(200, &format!("Restored {} parts from backup", count))
+                .stats(stats),
+            Err(e) => response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        },
+        Ok(Err(reply)) => reply,
+        Err(e) => write_rejected(response, e),
+    }))
+}
+
+/// Replace the categories with those of a backup once its parts are restored
+fn restore_categories(
+    registry: &CategoryRegistry,
+    categories: Vec<Category>,
+) -> Result<(), PartsListError> {
+    match registry.lock() {
+        Some(mut registry) => registry.replace_all(categories),
+        None => Err(PartsListError::Storage {
+            s: String::from("Couldn't lock categories!"),
+        }),
+    }
+}
+
+/// Replace the ECOs with those of a backup once its parts are restored
+fn restore_ecos(registry: &EcoRegistry, ecos: Vec<Eco>) -> Result<(), PartsListError> {
+    match registry.lock() {
+        Some(mut registry) => registry.replace_all(ecos),
+        None => Err(PartsListError::Storage {
+            s: String::from("Couldn't lock ECOs!"),
+        }),
+    }
+}
+
+/// Replace the releases with those of a backup once its parts are restored
+fn restore_releases(
+    registry: &ReleaseRegistry,
+    releases: Vec<Release>,
+) -> Result<(), PartsListError> {
+    match registry.lock() {
+        Some(mut registry) => registry.replace_all(releases),
+        None => Err(PartsListError::Storage {
+            s: String::from("Couldn't lock releases!"),
+        }),
+    }
+}
+
+/// Error response for a backup whose parts can't be restored, leaving the
+/// parts unchanged
+fn unrestorable(response: Response, e: PartsListError) -> Response {
+    response.error(
+        PartsErrorCode::RequestError,
+        &format!("Backup can't be restored: {}", e),
+    )
+}
+
+/// Most parts generated by a single request to `generate`
+const MAX_GENERATED_PARTS: usize = 100_000;
+
+/// Shape of the BOM generated by `generate` when not given
+const DEFAULT_SHAPE: BomShape = BomShape {
+    parts: 100,
+    depth: 3,
+    fanout: 4,
+};
+
+/// Add randomly generated assemblies of the requested shape, reproducibly
+/// when given a `seed`, returning the new top level assemblies
+#[post("/v1/admin/generate?<parts>&<depth>&<fanout>&<seed>")]
+pub fn generate(
+    parts: Option<usize>,
+    depth: Option<usize>,
+    fanout: Option<usize>,
+    seed: Option<u64>,
+    parts_list: State<SharedPartsList>,
+    _admin: Admin,
+    quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let shape = BomShape {
+        parts: parts.unwrap_or(DEFAULT_SHAPE.parts),
+        depth: depth.unwrap_or(DEFAULT_SHAPE.depth),
+        fanout: fanout.unwrap_or(DEFAULT_SHAPE.fanout),
+    };
+    if shape.parts == 0 || shape.parts > MAX_GENERATED_PARTS {
+        let description = format!("parts must be between 1 and {}", MAX_GENERATED_PARTS);
+        return busy_status(Json(
+            response.error(PartsErrorCode::RequestError, &description),
+        ));
+    }
+    if shape.depth == 0 || shape.fanout == 0 {
+        return busy_status(Json(response.error(
+            PartsErrorCode::RequestError,
+            "depth and fanout must be at least 1",
+        )));
+    }
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let generated = generator::generate(&shape, &mut rng);
+    let stats = generated.iter().cloned().collect::<PartsList>().stats();
+    if let Err(e) = quota.reserve_parts(shape.parts as u64) {
+        return busy_status(Json(change_error(
+            response,
+            PartsErrorCode::RequestError,
+            e,
+        )));
+    }
+    let roots = parts_list.change(move |parts| {
+        generator::add_to(&mut **parts, generated)
+            .and_then(|roots| roots.iter().map(|id| parts.get(id)).collect())
+    });
+    if !matches!(roots, Ok(Ok(_))) {
+        quota.release_parts(shape.parts as u64);
+    }
+    busy_status(Json(match roots {
+        Ok(Ok(roots)) => response
+            .result(200, &format!("Generated {} parts", shape.parts))
+            .data(roots)
+            .stats(stats),
+        Ok(Err(e)) => response.error(PartsErrorCode::StorageError, &format!("{}", e)),
+        Err(e) => write_rejected(response, e),
+    }))
+}
+
+#[get("/v1/admin/quotas")]
+pub fn list_quotas(quotas: State<QuotaRegistry>, _admin: Admin) -> Json<Response> {
+    let response = Response::new();
+    if let Some(mut quotas) = quotas.lock() {
+        Json(
+            response
+                .result(200, "Fetched quotas successfully")
+                .quotas(quotas.list()),
+        )
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock quotas!"))
+    }
+}
+
+#[get("/v1/admin/quotas/<key>")]
+pub fn get_quota(key: String, quotas: State<QuotaRegistry>, _admin: Admin) -> Json<Response> {
+    let response = Response::new();
+    if let Some(mut quotas) = quotas.lock() {
+        match quotas.get(&key) {
+            Ok(quota) => Json(
+                response
+                    .result(200, "Fetched quota successfully")
+                    .quotas(vec![quota]),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock quotas!"))
+    }
+}
+
+#[put("/v1/admin/quotas/<key>", data = "<data>")]
+pub fn set_quota(
+    key: String,
+    data: Payload<QuotaLimits>,
+    quotas: State<QuotaRegistry>,
+    _admin: Admin,
+) -> Json<Response> {
+    let response = Response::new();
+    if key.trim().is_empty() {
+        return Json(response.error(PartsErrorCode::RequestError, "API keys can't be blank"));
+    }
+    if let Some(mut quotas) = quotas.lock() {
+        match quotas.set(&key, data.into_inner()) {
+            Ok(quota) => Json(
+                response
+                    .result(200, "Quota set successfully")
+                    .quotas(vec![quota]),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::StorageError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock quotas!"))
+    }
+}
+
+#[delete("/v1/admin/quotas/<key>")]
+pub fn delete_quota(key: String, quotas: State<QuotaRegistry>, _admin: Admin) -> Json<Response> {
+    let response = Response::new();
+    if let Some(mut quotas) = quotas.lock() {
+        match quotas.remove(&key) {
+            Ok(quota) => Json(
+                response
+                    .result(200, "Quota removed successfully")
+                    .quotas(vec![quota]),
+            ),
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock quotas!"))
+    }
+}
+
+#[post("/v1/ecos", data = "<data>")]
+pub fn create_eco(
+    data: Payload<NewEco>,
+    ecos: State<EcoRegistry>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    let parts = match parts.try_read() {
+        Some(parts) => parts,
+        None => {
+            return Json(
+                response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+            )
+        }
+    };
+    if let Some(mut ecos) = ecos.lock() {
+        let data = data.into_inner();
+        match ecos.create(&*parts, data.assembly, &data.description, data.changes) {
+            Ok(eco) => Json(
+                response
+                    .result(201, "New ECO proposed successfully")
+                    .ecos(vec![eco]),
+            ),
+            Err(e @ PartsListError::PartDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::RequestError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock ECOs!"))
+    }
+}
+
+#[get("/v1/ecos?<assembly>&<status>")]
+pub fn list_ecos(
+    assembly: Option<RocketUuid>,
+    status: Option<&RawStr>,
+    ecos: State<EcoRegistry>,
+) -> Json<Response> {
+    let response = Response::new();
+    let assembly = assembly.map(|id| Uuid::from_bytes(*id.as_bytes()));
+    let status = match status.map(|status| status.as_str().try_into()).transpose() {
+        Ok(status) => status,
+        Err(e) => {
+            return Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid status passed: {}", e),
+            ))
+        }
+    };
+    if let Some(ecos) = ecos.lock() {
+        Json(
+            response
+                .result(200, "Fetched ECOs successfully")
+                .ecos(ecos.list(assembly, status)),
+        )
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock ECOs!"))
+    }
+}
+
+#[get("/v1/ecos/<eco_id>")]
+pub fn get_eco(eco_id: RocketUuid, ecos: State<EcoRegistry>) -> Json<Response> {
+    let response = Response::new();
+    let eco_id = Uuid::from_bytes(*eco_id.as_bytes());
+    if let Some(ecos) = ecos.lock() {
+        match ecos.get(&eco_id) {
+            Ok(eco) => Json(response.result(200, "Found ECO").ecos(vec![eco])),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock ECOs!"))
+    }
+}
+
+#[post("/v1/ecos/<eco_id>/approve")]
+pub fn approve_eco(
+    eco_id: RocketUuid,
+    ecos: State<EcoRegistry>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    let eco_id = Uuid::from_bytes(*eco_id.as_bytes());
+    if let Some(mut ecos) = ecos.lock() {
+        match ecos.approve(&eco_id) {
+            Ok(eco) => Json(
+                response
+                    .result(200, "ECO approved successfully")
+                    .ecos(vec![eco]),
+            ),
+            Err(e @ PartsListError::EcoDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::RequestError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock ECOs!"))
+    }
+}
+
+#[post("/v1/ecos/<eco_id>/reject")]
+pub fn reject_eco(eco_id: RocketUuid, ecos: State<EcoRegistry>, _quota: Metered) -> Json<Response> {
+    let response = Response::new();
+    let eco_id = Uuid::from_bytes(*eco_id.as_bytes());
+    if let Some(mut ecos) = ecos.lock() {
+        match ecos.reject(&eco_id) {
+            Ok(eco) => Json(
+                response
+                    .result(200, "ECO rejected successfully")
+                    .ecos(vec![eco]),
+            ),
+            Err(e @ PartsListError::EcoDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::RequestError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock ECOs!"))
+    }
+}
+
+#[post("/v1/ecos/<eco_id>/apply")]
+pub fn apply_eco(
+    eco_id: RocketUuid,
+    ecos: State<EcoRegistry>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let eco_id = Uuid::from_bytes(*eco_id.as_bytes());
+    let ecos = ecos.inner().clone();
+    conflict_status(Json(
+        parts
+            .change(move |parts| {
+                // the parts are locked first, as when proposing an ECO
+                if let Some(mut ecos) = ecos.lock() {
+                    match ecos.apply(&eco_id, &mut **parts) {
+                        Ok((eco, changed)) => response
+                            .result(200, "ECO applied successfully")
+                            .data(changed)
+                            .ecos(vec![eco]),
+                        Err(e @ PartsListError::EcoDoesNotExist { .. }) => {
+                            response.error(PartsErrorCode::MissingPartError, &format!("{}", e))
+                        }
+                        Err(e) => change_error(response, PartsErrorCode::RequestError, e),
+                    }
+                } else {
+                    response.error(PartsErrorCode::LockError, "Couldn't lock ECOs!")
+                }
+            })
+            .unwrap_or_else(|e| write_rejected(Response::new(), e)),
+    ))
+}
+
+#[post("/v1/releases", data = "<data>")]
+pub fn submit_release(
+    data: Payload<NewRelease>,
+    releases: State<ReleaseRegistry>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    let parts = match parts.try_read() {
+        Some(parts) => parts,
+        None => {
+            return Json(
+                response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+            )
+        }
+    };
+    if let Some(mut releases) = releases.lock() {
+        let data = data.into_inner();
+        match releases.submit(&*parts, data.assembly, &data.description) {
+            Ok(release) => Json(
+                response
+                    .result(201, "Assembly submitted for release successfully")
+                    .releases(vec![release]),
+            ),
+            Err(e @ PartsListError::PartDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::RequestError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock releases!"))
+    }
+}
+
+#[get("/v1/releases?<assembly>&<status>")]
+pub fn list_releases(
+    assembly: Option<RocketUuid>,
+    status: Option<&RawStr>,
+    releases: State<ReleaseRegistry>,
+) -> Json<Response> {
+    let response = Response::new();
+    let assembly = assembly.map(|id| Uuid::from_bytes(*id.as_bytes()));
+    let status = match status.map(|status| status.as_str().try_into()).transpose() {
+        Ok(status) => status,
+        Err(e) => {
+            return Json(response.error(
+                PartsErrorCode::RequestError,
+                &format!("Invalid status passed: {}", e),
+            ))
+        }
+    };
+    if let Some(releases) = releases.lock() {
+        Json(
+            response
+                .result(200, "Fetched releases successfully")
+                .releases(releases.list(assembly, status)),
+        )
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock releases!"))
+    }
+}
+
+#[get("/v1/releases/<release_id>")]
+pub fn get_release(release_id: RocketUuid, releases: State<ReleaseRegistry>) -> Json<Response> {
+    let response = Response::new();
+    let release_id = Uuid::from_bytes(*release_id.as_bytes());
+    if let Some(releases) = releases.lock() {
+        match releases.get(&release_id) {
+            Ok(release) => Json(
+                response
+                    .result(200, "Found release")
+                    .releases(vec![release]),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock releases!"))
+    }
+}
+
+#[post("/v1/releases/<release_id>/approve")]
+pub fn approve_release(
+    release_id: RocketUuid,
+    approver: ReleaseApprover,
+    releases: State<ReleaseRegistry>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> status::Custom<Json<Response>> {
+    let response = Response::new();
+    let release_id = Uuid::from_bytes(*release_id.as_bytes());
+    let releases = releases.inner().clone();
+    conflict_status(Json(
+        parts
+            .change(move |parts| {
+                // the parts are locked first, as when submitting a release
+                if let Some(mut releases) = releases.lock() {
+                    match releases.approve(&mut **parts, &release_id, &approver.0) {
+                        Ok(release) => response
+                            .result(200, "Release approved successfully")
+                            .releases(vec![release]),
+                        Err(e @ PartsListError::ReleaseDoesNotExist { .. }) => {
+                            response.error(PartsErrorCode::MissingPartError, &format!("{}", e))
+                        }
+                        Err(e @ PartsListError::Storage { .. }) => {
+                            response.error(PartsErrorCode::StorageError, &format!("{}", e))
+                        }
+                        Err(e) => change_error(response, PartsErrorCode::RequestError, e),
+                    }
+                } else {
+                    response.error(PartsErrorCode::LockError, "Couldn't lock releases!")
+                }
+            })
+            .unwrap_or_else(|e| write_rejected(Response::new(), e)),
+    ))
+}
+
+#[post("/v1/releases/<release_id>/reject")]
+pub fn reject_release(
+    release_id: RocketUuid,
+    approver: ReleaseApprover,
+    releases: State<ReleaseRegistry>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    let release_id = Uuid::from_bytes(*release_id.as_bytes());
+    if let Some(mut releases) = releases.lock() {
+        match releases.reject(&release_id, &approver.0) {
+            Ok(release) => Json(
+                response
+                    .result(200, "Release rejected successfully")
+                    .releases(vec![release]),
+            ),
+            Err(e @ PartsListError::ReleaseDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::RequestError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock releases!"))
+    }
+}
+
+#[post("/v1/categories", data = "<data>")]
+pub fn create_category(
+    data: Payload<NewCategory>,
+    categories: State<CategoryRegistry>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    if let Some(mut categories) = categories.lock() {
+        let data = data.into_inner();
+        match categories.create(&data.name, data.parent, data.attributes) {
+            Ok(category) => Json(
+                response
+                    .result(201, "New category created successfully")
+                    .categories(vec![category]),
+            ),
+            Err(e @ PartsListError::CategoryDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::CreatePartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock categories!"))
+    }
+}
+
+#[get("/v1/categories")]
+pub fn list_categories(categories: State<CategoryRegistry>) -> Json<Response> {
+    let response = Response::new();
+    if let Some(categories) = categories.lock() {
+        Json(
+            response
+                .result(200, "Fetched categories successfully")
+                .categories(categories.list()),
+        )
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock categories!"))
+    }
+}
+
+#[get("/v1/categories/<category_id>")]
+pub fn get_category(
+    category_id: RocketUuid,
+    categories: State<CategoryRegistry>,
+) -> Json<Response> {
+    let response = Response::new();
+    let category_id = Uuid::from_bytes(*category_id.as_bytes());
+    if let Some(categories) = categories.lock() {
+        match categories.get(&category_id) {
+            Ok(category) => Json(
+                response
+                    .result(200, "Found category")
+                    .categories(vec![category]),
+            ),
+            Err(e) => Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock categories!"))
+    }
+}
+
+#[patch("/v1/categories/<category_id>", data = "<data>")]
+pub fn edit_category(
+    category_id: RocketUuid,
+    data: Payload<CategoryEdit>,
+    categories: State<CategoryRegistry>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    let category_id = Uuid::from_bytes(*category_id.as_bytes());
+    if let Some(mut categories) = categories.lock() {
+        match categories.edit(&category_id, &data) {
+            Ok(category) => Json(
+                response
+                    .result(200, "Category edited successfully")
+                    .categories(vec![category]),
+            ),
+            Err(e @ PartsListError::CategoryDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::CreatePartError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock categories!"))
+    }
+}
+
+#[delete("/v1/categories/<category_id>")]
+pub fn delete_category(
+    category_id: RocketUuid,
+    categories: State<CategoryRegistry>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> Json<Response> {
+    let response = Response::new();
+    let category_id = Uuid::from_bytes(*category_id.as_bytes());
+    // the parts are locked first, as when proposing an ECO
+    let parts = match parts.try_read() {
+        Some(parts) => parts,
+        None => {
+            return Json(
+                response.error(PartsErrorCode::LockError, "Couldn't read lock parts list!"),
+            )
+        }
+    };
+    if let Some(mut categories) = categories.lock() {
+        match categories.delete(&category_id, &*parts) {
+            Ok(category) => Json(
+                response
+                    .result(200, "Deleted category")
+                    .categories(vec![category]),
+            ),
+            Err(e @ PartsListError::CategoryDoesNotExist { .. }) => {
+                Json(response.error(PartsErrorCode::MissingPartError, &format!("{}", e)))
+            }
+            Err(e @ PartsListError::Storage { .. }) => {
+                Json(response.error(PartsErrorCode::StorageError, &format!("{}", e)))
+            }
+            Err(e) => Json(response.error(PartsErrorCode::RequestError, &format!("{}", e))),
+        }
+    } else {
+        Json(response.error(PartsErrorCode::LockError, "Couldn't lock categories!"))
+    }
+}
+
+#[catch(400)]
+pub fn bad_request(request: &Request) -> Json<Response> {
+    Json(Response::new().error(
+        PartsErrorCode::RequestError,
+        &format!(
+            "Malformed request to {} {}",
+            request.method(),
+            request.uri()
+        ),
+    ))
+}
+
+#[catch(404)]
+pub fn not_found(request: &Request) -> Json<Response> {
+    Json(Response::new().error(
+        PartsErrorCode::RequestError,
+        &format!("No route for {} {}", request.method(), request.uri()),
+    ))
+}
+
+#[catch(403)]
+pub fn forbidden(request: &Request) -> Json<Response> {
+    match &request.local_cache(ApproverRejection::default).0 {
+        Some(description) => Json(Response::new().error(PartsErrorCode::Forbidden, description)),
+        None => Json(Response::new().error(
+            PartsErrorCode::Forbidden,
+            &format!("{}", PartsListError::NotAdmin),
+        )),
+    }
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request) -> Json<Response> {
+    Json(Response::new().error(
+        PartsErrorCode::RequestError,
+        &format!(
+            "Couldn't parse the body of {} {}, check it against the request format",
+            request.method(),
+            request.uri()
+        ),
+    ))
+}
+
+#[catch(413)]
+pub fn payload_too_large(request: &Request) -> Json<Response> {
+    Json(Response::new().error(
+        PartsErrorCode::RequestError,
+        &format!(
+            "Body of {} {} is larger than the limit of {} bytes",
+            request.method(),
+            request.uri(),
+            payload_limit(request)
+        ),
+    ))
+}
+
+#[catch(429)]
+pub fn too_many_requests(request: &Request) -> Json<Response> {
+    match &request.local_cache(QuotaRejection::default).0 {
+        Some(description) => {
+            Json(Response::new().error(PartsErrorCode::QuotaExceeded, description))
+        }
+        None => Json(Response::new().error(
+            PartsErrorCode::Busy,
+            &format!(
+                "Too many requests to {} {}",
+                request.method(),
+                request.uri()
+            ),
+        )),
+    }
+}
+
+#[catch(500)]
+pub fn internal_error(request: &Request) -> Json<Response> {
+    Json(Response::new().error(
+        PartsErrorCode::InternalError,
+        &format!(
+            "Internal error handling {} {}",
+            request.method(),
+            request.uri()
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use rocket::config::{Config, Environment, Limits, Value};
+    use rocket::http::Header;
+    use rocket::local::{Client, LocalResponse};
+
+    use super::*;
+    use crate::errors::PartsError;
+    use crate::release::ReleaseStatus;
+
+    /// Client of a server with every route mounted, as configured by `config`,
+    /// along with the parts list it serves
+    fn serve(config: Config) -> (Client, SharedPartsList) {
+        let rocket = rocket::custom(config);
+        let parts_list = SharedPartsList::from_config(rocket.config()).unwrap();
+        let quotas = crate::quotas_config(rocket.config()).unwrap();
+        let releases = crate::releases_config(rocket.config()).unwrap();
+        let client = Client::new(crate::mount_routes(
+            rocket,
+            parts_list.clone(),
+            ExchangeRates::default(),
+            quotas,
+            CategoryRegistry::new(),
+            EcoRegistry::new(),
+            releases,
+            Replication::default(),
+        ))
+        .unwrap();
+        (client, parts_list)
+    }
+
+    fn client(config: Config) -> Client {
+        serve(config).0
+    }
+
+    fn create(client: &Client, name: &str) -> Part {
+        let mut reply = client
+            .post("/v1/parts")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"name": "{}"}}"#, name))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Ok);
+        let response: Response = serde_json::from_str(&reply.body_string().unwrap()).unwrap();
+        response.data.unwrap().remove(0)
+    }
+
+    /// Code of the error in the body of a `/v1` or `/v2` reply
+    fn error_code(reply: &mut LocalResponse) -> PartsErrorCode {
+        let mut body: serde_json::Value =
+            serde_json::from_str(&reply.body_string().unwrap()).unwrap();
+        let error: PartsError = serde_json::from_value(body["error"].take()).unwrap();
+        *error.code()
+    }
+
+    fn if_match(version: u64) -> Header<'static> {
+        Header::new("If-Match", format!("\"{}\"", version))
+    }
+
+    #[test]
+    fn stale_versions_conflict() {
+        let client = client(Config::development());
+        let assy = create(&client, "assy");
+        let screw = create(&client, "screw");
+        let edit = |path: String| {
+            client
+                .patch(path)
+                .header(ContentType::JSON)
+                .body(r#"{"location": "A1"}"#)
+        };
+        let reply = edit(format!(
+            "/v1/parts/{}?expected_version={}",
+            assy.id, assy.version
+        ))
+        .dispatch();
+        assert_eq!(reply.status(), Status::Ok);
+
+        // every change based on the version before the edit is refused
+        let mut reply = edit(format!(
+            "/v1/parts/{}?expected_version={}",
+            assy.id, assy.version
+        ))
+        .dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::VersionConflict);
+
+        let mut reply = client
+            .post(format!("/v1/parts/{}/children", assy.id))
+            .header(ContentType::JSON)
+            .header(if_match(assy.version))
+            .body(format!(r#"{{"children": ["{}"]}}"#, screw.id))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::VersionConflict);
+
+        let mut reply = client
+            .delete(format!("/v1/parts/{}", assy.id))
+            .header(if_match(assy.version))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::VersionConflict);
+
+        let mut reply = edit(format!("/v2/parts/{}", assy.id))
+            .header(if_match(assy.version))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::VersionConflict);
+
+        let mut reply = client
+            .post(format!(
+                "/v2/parts/{}/children?expected_version={}",
+                assy.id, assy.version
+            ))
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"children": ["{}"]}}"#, screw.id))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::VersionConflict);
+
+        let mut reply = client
+            .delete(format!(
+                "/v2/parts/{}?expected_version={}",
+                assy.id, assy.version
+            ))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::VersionConflict);
+
+        // while the current version is accepted
+        let reply = client
+            .delete(format!("/v2/parts/{}", assy.id))
+            .header(if_match(assy.version + 1))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Ok);
+    }
+
+    #[test]
+    fn v2_failures_carry_their_status() {
+        let client = client(
+            Config::build(Environment::Development)
+                .extra("strict_delete", true)
+                .extra("max_batch_size", 1)
+                .unwrap(),
+        );
+        let mut reply = client
+            .get(format!("/v2/parts/{}", Uuid::new_v4()))
+            .dispatch();
+        assert_eq!(reply.status(), Status::NotFound);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::MissingPartError);
+
+        let assy = create(&client, "assy");
+        let screw = create(&client, "screw");
+        let nut = create(&client, "nut");
+        let add_children = |parent: &Part, children: &[&Part]| {
+            let children: Vec<String> = children
+                .iter()
+                .map(|child| format!("\"{}\"", child.id))
+                .collect();
+            client
+                .post(format!("/v2/parts/{}/children", parent.id))
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"children": [{}]}}"#, children.join(", ")))
+                .dispatch()
+        };
+        let mut reply = add_children(&assy, &[&screw, &nut]);
+        assert_eq!(reply.status(), Status::PayloadTooLarge);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::RequestError);
+        assert_eq!(add_children(&assy, &[&screw]).status(), Status::Ok);
+
+        let mut reply = add_children(&screw, &[&assy]);
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::CreatePartError);
+
+        // parts in use are only deleted when forced, by either API
+        let mut reply = client.delete(format!("/v2/parts/{}", screw.id)).dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::PartInUse);
+        let mut reply = client.delete(format!("/v1/parts/{}", screw.id)).dispatch();
+        assert_eq!(reply.status(), Status::Conflict);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::PartInUse);
+        let reply = client
+            .delete(format!("/v2/parts/{}?force=true", screw.id))
+            .dispatch();
+        assert_eq!(reply.status(), Status::Ok);
+    }
+
+    #[test]
+    fn refuses_oversized_and_mistyped_bodies() {
+        let client = client(
+            Config::build(Environment::Development)
+                .limits(Limits::default().limit("json", 64))
+                .unwrap(),
+        );
+        for path in &["/v1/parts", "/v2/parts"] {
+            let mut reply = client
+                .post(*path)
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"name": "{}"}}"#, "x".repeat(64)))
+                .dispatch();
+            assert_eq!(reply.status(), Status::PayloadTooLarge);
+            assert_eq!(error_code(&mut reply), PartsErrorCode::RequestError);
+
+            let mut reply = client
+                .post(*path)
+                .header(ContentType::JSON)
+                .body(r#"{"name": 5}"#)
+                .dispatch();
+            assert_eq!(reply.status(), Status::UnprocessableEntity);
+            assert_eq!(error_code(&mut reply), PartsErrorCode::RequestError);
+
+            let reply = client
+                .post(*path)
+                .header(ContentType::JSON)
+                .body(r#"{"name": "#)
+                .dispatch();
+            assert_eq!(reply.status(), Status::BadRequest);
+        }
+        create(&client, &"x".repeat(50));
+    }
+
+    #[test]
+    fn quotas_refuse_requests_past_their_limits() {
+        let anonymous: BTreeMap<String, Value> = vec![
+            ("max_requests".to_string(), Value::Integer(2)),
+            ("max_parts_created".to_string(), Value::Integer(1)),
+        ]
+        .into_iter()
+        .collect();
+        let client = client(
+            Config::build(Environment::Development)
+                .extra("anonymous_quota", anonymous)
+                .extra("admin_token", "secret")
+                .unwrap(),
+        );
+        create(&client, "assy");
+
+        // the request is admitted, but may create no more parts
+        let mut reply = client
+            .post("/v2/parts")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "screw"}"#)
+            .dispatch();
+        assert_eq!(reply.status(), Status::TooManyRequests);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::QuotaExceeded);
+
+        // and no more requests are admitted
+        let mut reply = client
+            .post("/v1/parts")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "nut"}"#)
+            .dispatch();
+        assert_eq!(reply.status(), Status::TooManyRequests);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::QuotaExceeded);
+
+        // as are requests with a key without a quota of its own
+        let mut reply = client
+            .post("/v1/parts")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer made-up"))
+            .body(r#"{"name": "nut"}"#)
+            .dispatch();
+        assert_eq!(reply.status(), Status::TooManyRequests);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::QuotaExceeded);
+
+        // requests with a key count against its own quota
+        let reply = client
+            .put("/v1/admin/quotas/ci")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer secret"))
+            .body(r#"{"max_requests": 1}"#)
+            .dispatch();
+        assert_eq!(reply.status(), Status::Ok);
+        let mut replies = (0..2).map(|_| {
+            client
+                .post("/v1/parts")
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", "Bearer ci"))
+                .body(format!(r#"{{"name": "{}"}}"#, Uuid::new_v4()))
+                .dispatch()
+        });
+        assert_eq!(replies.next().unwrap().status(), Status::Ok);
+        let mut reply = replies.next().unwrap();
+        assert_eq!(reply.status(), Status::TooManyRequests);
+        assert_eq!(error_code(&mut reply), PartsErrorCode::QuotaExceeded);
+    }
+
+    #[test]
+    fn releases_are_decided_by_the_tokens_of_their_roles() {
+        let tokens: BTreeMap<String, Value> = vec![
+            ("engineering".to_string(), Value::from("e-secret")),
+            ("quality".to_string(), Value::from("q-secret")),
+        ]
+        .into_iter()
+        .collect();
+        let client = client(
+            Config::build(Environment::Development)
+                .extra("release_tokens", tokens)
+                .unwrap(),
+        );
+        let assy = create(&client, "assy");
+        let mut reply = client
+            .post("/v1/releases")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"assembly": "{}"}}"#, assy.id))
+            .dispatch();
+        let response: Response = serde_json::from_str(&reply.body_string().unwrap()).unwrap();
+        let release = response.releases.unwrap().remove(0);
+        let decide = |decision: &str, token: Option<&str>| {
+            let request = client.post(format!("/v1/releases/{}/{}", release.id, decision));
+            match token {
+                Some(token) => request
+                    .header(Header::new("Authorization", format!("Bearer {}", token)))
+                    .dispatch(),
+                None => request.dispatch(),
+            }
+        };
+
+        // approvals are only recorded for the role whose token was sent
+        for token in &[None, Some("engineering"), Some("admin")] {
+            for decision in &["approve", "reject"] {
+                let mut reply = decide(decision, *token);
+                assert_eq!(reply.status(), Status::Forbidden);
+                assert_eq!(error_code(&mut reply), PartsErrorCode::Forbidden);
+            }
+        }
+        let mut reply = decide("approve", Some("q-secret"));
+        let response: Response = serde_json::from_str(&reply.body_string().unwrap()).unwrap();
+        let approved = response.releases.unwrap().remove(0);
+        assert_eq!(approved.approvals[0].role, "quality");
+        assert_eq!(approved.status, ReleaseStatus::InReview);
+
+        // and the assembly is released once every role approves it
+        assert_eq!(decide("approve", Some("e-secret")).status(), Status::Ok);
+        let mut reply = client.get(format!("/v2/parts/{}", assy.id)).dispatch();
+        let part: serde_json::Value = serde_json::from_str(&reply.body_string().unwrap()).unwrap();
+        assert_eq!(part["data"]["lifecycle"], "released");
+
+        // which clients can't edit themselves
+        for path in &["/v1/parts", "/v2/parts"] {
+            let mut reply = client
+                .patch(format!("{}/{}", path, assy.id))
+                .header(ContentType::JSON)
+                .body(r#"{"lifecycle": "in_work"}"#)
+                .dispatch();
+            assert_eq!(error_code(&mut reply), PartsErrorCode::RequestError);
+        }
+    }
+
+    #[test]
+    fn refuses_writes_once_the_queue_is_full() {
+        let (client, parts_list) = serve(
+            Config::build(Environment::Development)
+                .extra("write_queue", 1)
+                .unwrap(),
+        );
+        let (started, running) = mpsc::channel();
+        let (finish, finishing) = mpsc::channel::<()>();
+        let holding = parts_list.clone();
+        let holder = thread::spawn(move || {
+            holding.change(move |_| {
+                started.send(()).unwrap();
+                let _ = finishing.recv();
+            })
+        });
+        running.recv().unwrap();
+
+        // of two more changes, one waits for the writer thread and fills the
+        // queue, so the other is refused straight away
+        let (replied, replies) = mpsc::channel();
+        for _ in 0..2 {
+            let (parts_list, replied) = (parts_list.clone(), replied.clone());
+            thread::spawn(move || replied.send(parts_list.change(|_| ())).unwrap());
+        }
+        assert_eq!(replies.recv().unwrap(), Err(WriteRejected::QueueFull));
+
+        for path in &["/v1/parts", "/v2/parts"] {
+            let mut reply = client
+                .post(*path)
+                .header(ContentType::JSON)
+                .body(r#"{"name": "screw"}"#)
+                .dispatch();
+            assert_eq!(reply.status(), Status::ServiceUnavailable);
+            assert_eq!(error_code(&mut reply), PartsErrorCode::Busy);
+        }
+        // while reads carry on
+        assert_eq!(client.get("/v2/parts").dispatch().status(), Status::Ok);
+
+        drop(finish);
+        assert_eq!(holder.join().unwrap(), Ok(()));
+        assert_eq!(replies.recv().unwrap(), Ok(()));
+        create(&client, "screw");
+    }
+
+    #[test]
+    fn deleting_parts_removes_their_attachments() {
+        let dir = std::env::temp_dir().join(format!("bom-attachments-{}", Uuid::new_v4()));
+        let client = client(
+            Config::build(Environment::Development)
+                .extra("attachments_dir", dir.to_str().unwrap())
+                .unwrap(),
+        );
+        let form_data = ContentType::with_params("multipart", "form-data", ("boundary", "XYZ"));
+        let body = "--XYZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"sheet.pdf\"\r\n\
+            Content-Type: application/pdf\r\n\r\n\
+            %PDF-\r\n\
+            --XYZ--\r\n";
+        for path in &["/v1/parts", "/v2/parts"] {
+            let part = create(&client, &format!("bracket {}", path));
+            let reply = client
+                .post(format!("/v1/parts/{}/attachments", part.id))
+                .header(form_data.clone())
+                .body(body)
+                .dispatch();
+            assert_eq!(reply.status(), Status::Ok);
+            assert!(dir.join(part.id.to_string()).exists());
+
+            let reply = client.delete(format!("{}/{}", path, part.id)).dispatch();
+            assert_eq!(reply.status(), Status::Ok);
+            assert!(!dir.join(part.id.to_string()).exists());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
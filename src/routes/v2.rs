@@ -0,0 +1,399 @@
+use std::convert::TryInto;
+
+use rocket::http::{RawStr, Status};
+use rocket::response::status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use rocket_contrib::uuid::Uuid as RocketUuid;
+use uuid::Uuid;
+
+use crate::attachments::SharedAttachments;
+use crate::category::CategoryRegistry;
+use crate::errors::{PartsError, PartsErrorCode};
+use crate::negotiate::Payload;
+use crate::parts_list::{
+    Part, PartEdit, PartSummary, PartsListCursor, PartsListError, PartsListFilter, PartsListStats,
+    PartsListUpdate,
+};
+use crate::query::{NewPart, UpdateChildren};
+use crate::quota::Metered;
+use crate::response::{ErrorReply, Reply};
+use crate::writer::WriteRejected;
+use crate::SharedPartsList;
+
+use super::{
+    attribute_mismatch, check_expected_version, check_unused, new_part, parse_expected_version,
+    parse_page, parse_sort, IfMatch, MaxBatchSize, StrictDelete,
+};
+
+/// Reply of a `/v2` route, holding its data on success or only the error
+/// otherwise, along with the HTTP status telling which
+pub type V2<T> = Result<status::Custom<Json<Reply<T>>>, status::Custom<Json<ErrorReply>>>;
+
+fn success<T>(status: Status, reply: Reply<T>) -> V2<T> {
+    Ok(status::Custom(status, Json(reply)))
+}
+
+fn failure<T>(status: Status, code: PartsErrorCode, description: &str) -> V2<T> {
+    Err(status::Custom(
+        status,
+        Json(ErrorReply::new(PartsError::new(code, description.into()))),
+    ))
+}
+
+/// Failure of the parts list, with the HTTP status and error code telling
+/// what kind of failure it is
+fn parts_failure<T>(e: PartsListError) -> V2<T> {
+    let (status, code) = match e {
+        PartsListError::PartDoesNotExist { .. }
+        | PartsListError::PartNameDoesNotExist { .. }
+        | PartsListError::ExternalIdDoesNotExist { .. }
+        | PartsListError::CategoryDoesNotExist { .. }
+        | PartsListError::RelationshipDoesNotExist { .. }
+        | PartsListError::NotAChild { .. } => (Status::NotFound, PartsErrorCode::MissingPartError),
+        PartsListError::VersionConflict { .. } => {
+            (Status::Conflict, PartsErrorCode::VersionConflict)
+        }
+        PartsListError::PartInUse { .. } => (Status::Conflict, PartsErrorCode::PartInUse),
+        PartsListError::QuotaExceeded { .. } => {
+            (Status::TooManyRequests, PartsErrorCode::QuotaExceeded)
+        }
+        PartsListError::PartExists { .. }
+        | PartsListError::PartNameExists { .. }
+        | PartsListError::ExternalIdInUse { .. }
+        | PartsListError::AddChildCyclicalRelative { .. } => {
+            (Status::Conflict, PartsErrorCode::CreatePartError)
+        }
+        PartsListError::Storage { .. }
+        | PartsListError::Serialization(_)
+        | PartsListError::MismatchedId { .. }
+        | PartsListError::MissingRelative { .. }
+        | PartsListError::Unknown => (Status::InternalServerError, PartsErrorCode::StorageError),
+        _ => (Status::BadRequest, PartsErrorCode::RequestError),
+    };
+    let mut error = PartsError::new(code, format!("{}", e));
+    if let PartsListError::AddChildCyclicalRelative { path, .. } = e {
+        error = error.with_cycle(path);
+    }
+    Err(status::Custom(status, Json(ErrorReply::new(error))))
+}
+
+/// Failure to check the attributes of a part against its category
+fn attribute_failure<T>(reply: ErrorReply) -> V2<T> {
+    let status = match reply.error.code() {
+        PartsErrorCode::LockError => Status::ServiceUnavailable,
+        _ => Status::UnprocessableEntity,
+    };
+    Err(status::Custom(status, Json(reply)))
+}
+
+fn read_lock_failure<T>() -> V2<T> {
+    failure(
+        Status::ServiceUnavailable,
+        PartsErrorCode::LockError,
+        "Couldn't read lock parts list!",
+    )
+}
+
+/// Failure of a write refused before it started, telling clients to back off
+/// when too many writes are already waiting, or of a change which panicked
+fn write_rejected<T>(e: WriteRejected) -> V2<T> {
+    match e {
+        WriteRejected::QueueFull => failure(
+            Status::ServiceUnavailable,
+            PartsErrorCode::Busy,
+            &format!("{}", e),
+        ),
+        WriteRejected::Stopped => failure(
+            Status::ServiceUnavailable,
+            PartsErrorCode::LockError,
+            &format!("{}", e),
+        ),
+        WriteRejected::Panicked => failure(
+            Status::InternalServerError,
+            PartsErrorCode::InternalError,
+            &format!("{}", e),
+        ),
+    }
+}
+
+/// Reply with the listed parts, in the requested order or page
+fn listing(
+    mut parts: Vec<Part>,
+    sort: Option<&RawStr>,
+    order: Option<&RawStr>,
+    limit: Option<usize>,
+    cursor: Option<&RawStr>,
+) -> V2<Vec<Part>> {
+    let sort = match parse_sort(sort, order) {
+        Ok(sort) => sort,
+        Err(e) => return parts_failure(e),
+    };
+    match parse_page(limit, cursor, sort) {
+        Ok(Some((cursor, limit))) => {
+            let (parts, next) = PartsListCursor::paginate(cursor, limit, parts);
+            success(Status::Ok, Reply::new(parts).next_cursor(next))
+        }
+        Ok(None) => {
+            if let Some(sort) = sort {
+                sort.sort(&mut parts);
+            }
+            success(Status::Ok, Reply::new(parts))
+        }
+        Err(e) => failure(
+            Status::BadRequest,
+            PartsErrorCode::RequestError,
+            &format!("Invalid pagination passed: {}", e),
+        ),
+    }
+}
+
+/// Parse the version a change is based on, rejecting malformed versions
+fn expected_version<T>(
+    expected_version: Option<&RawStr>,
+    if_match: &IfMatch,
+) -> Result<Option<u64>, V2<T>> {
+    parse_expected_version(expected_version, if_match)
+        .map_err(|e| failure(Status::BadRequest, PartsErrorCode::RequestError, &e))
+}
+
+#[get("/v2/parts?<filter>&<sort>&<order>&<limit>&<cursor>")]
+pub fn list_parts(
+    filter: Option<&RawStr>,
+    sort: Option<&RawStr>,
+    order: Option<&RawStr>,
+    limit: Option<usize>,
+    cursor: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> V2<Vec<Part>> {
+    let filter: PartsListFilter = match filter.map_or("all", |filter| filter.as_str()).try_into() {
+        Ok(filter) => filter,
+        Err(e) => return parts_failure(e),
+    };
+    let list = match parts.try_read() {
+        Some(parts) => parts.list(filter),
+        None => return read_lock_failure(),
+    };
+    match list {
+        Ok(list) => listing(list, sort, order, limit, cursor),
+        Err(e) => parts_failure(e),
+    }
+}
+
+#[post("/v2/parts", data = "<data>")]
+pub fn create_part(
+    data: Payload<NewPart>,
+    categories: State<CategoryRegistry>,
+    parts: State<SharedPartsList>,
+    quota: Metered,
+) -> V2<Part> {
+    let part = new_part(&data);
+    if let Some(reply) = attribute_mismatch(&categories, &part).and_then(ErrorReply::from_response)
+    {
+        return attribute_failure(reply);
+    }
+    if let Err(e) = quota.reserve_parts(1) {
+        return parts_failure(e);
+    }
+    match parts.change(move |parts| parts.insert(part)) {
+        Ok(Ok(part)) => success(Status::Created, Reply::new(part)),
+        Ok(Err(e)) => {
+            quota.release_parts(1);
+            parts_failure(e)
+        }
+        Err(e) => {
+            quota.release_parts(1);
+            write_rejected(e)
+        }
+    }
+}
+
+#[get("/v2/parts/<part_id>")]
+pub fn get_part(part_id: RocketUuid, parts: State<SharedPartsList>) -> V2<Part> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    match parts.try_read() {
+        Some(parts) => match parts.get(&part_id) {
+            Ok(part) => success(Status::Ok, Reply::new(part)),
+            Err(e) => parts_failure(e),
+        },
+        None => read_lock_failure(),
+    }
+}
+
+#[patch("/v2/parts/<part_id>?<expected_version>", data = "<data>")]
+pub fn edit_part(
+    part_id: RocketUuid,
+    expected_version: Option<&RawStr>,
+    if_match: IfMatch,
+    data: Payload<PartEdit>,
+    categories: State<CategoryRegistry>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> V2<Part> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let edit = data.into_inner().stamped();
+    if edit.lifecycle.is_some() {
+        return parts_failure(PartsListError::LifecycleNotEditable { id: part_id });
+    }
+    let expected_version = match self::expected_version(expected_version, &if_match) {
+        Ok(version) => version,
+        Err(reply) => return reply,
+    };
+    let categories = categories.inner().clone();
+    parts
+        .change(move |parts| {
+            let mut edited = match check_expected_version(&mut **parts, &part_id, expected_version)
+                .and_then(|_| parts.get(&part_id))
+            {
+                Ok(part) => part,
+                Err(e) => return parts_failure(e),
+            };
+            edit.apply(&mut edited);
+            if let Some(reply) =
+                attribute_mismatch(&categories, &edited).and_then(ErrorReply::from_response)
+            {
+                return attribute_failure(reply);
+            }
+            match parts.edit(&part_id, &edit) {
+                Ok(part) => success(Status::Ok, Reply::new(part)),
+                Err(e) => parts_failure(e),
+            }
+        })
+        .unwrap_or_else(write_rejected)
+}
+
+#[delete("/v2/parts/<part_id>?<force>&<expected_version>")]
+pub fn delete_part(
+    part_id: RocketUuid,
+    force: Option<bool>,
+    expected_version: Option<&RawStr>,
+    if_match: IfMatch,
+    strict: State<StrictDelete>,
+    parts: State<SharedPartsList>,
+    attachments: State<SharedAttachments>,
+    _quota: Metered,
+) -> V2<Part> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let strict = strict.0 && !force.unwrap_or(false);
+    let expected_version = match self::expected_version(expected_version, &if_match) {
+        Ok(version) => version,
+        Err(reply) => return reply,
+    };
+    let removed = parts.change(move |parts| {
+        check_expected_version(&mut **parts, &part_id, expected_version)
+            .and_then(|_| check_unused(&**parts, &part_id, strict))
+            .and_then(|_| parts.get(&part_id))
+            .and_then(|part| parts.remove(&part_id).map(|_| part))
+            .map(|part| (part, parts.purged(&[part_id])))
+    });
+    match removed {
+        Ok(Ok((part, purged))) => {
+            attachments.remove_parts(&purged);
+            success(Status::Ok, Reply::new(part))
+        }
+        Ok(Err(e)) => parts_failure(e),
+        Err(e) => write_rejected(e),
+    }
+}
+
+#[get("/v2/parts/<part_id>/children?<filter>&<sort>&<order>&<limit>&<cursor>")]
+pub fn get_children(
+    part_id: RocketUuid,
+    filter: Option<&RawStr>,
+    sort: Option<&RawStr>,
+    order: Option<&RawStr>,
+    limit: Option<usize>,
+    cursor: Option<&RawStr>,
+    parts: State<SharedPartsList>,
+) -> V2<Vec<Part>> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let filter: PartsListFilter = match filter.map_or("all", |filter| filter.as_str()).try_into() {
+        Ok(filter) => filter,
+        Err(e) => return parts_failure(e),
+    };
+    match filter {
+        PartsListFilter::All
+        | PartsListFilter::Component
+        | PartsListFilter::Subassembly
+        | PartsListFilter::TopLevel => {}
+        _ => {
+            return failure(
+                Status::BadRequest,
+                PartsErrorCode::RequestError,
+                "Unsupported filter on children, only all, top_level, component and subassembly are supported",
+            )
+        }
+    }
+    let children = match parts.try_read() {
+        Some(parts) => parts.get_children(&part_id, filter),
+        None => return read_lock_failure(),
+    };
+    match children {
+        Ok(children) => listing(children, sort, order, limit, cursor),
+        Err(e) => parts_failure(e),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post(
+    "/v2/parts/<part_id>/children?<action>&<expected_version>",
+    data = "<data>"
+)]
+pub fn update_children(
+    part_id: RocketUuid,
+    action: Option<&RawStr>,
+    expected_version: Option<&RawStr>,
+    if_match: IfMatch,
+    data: Payload<UpdateChildren>,
+    max_batch: State<MaxBatchSize>,
+    parts: State<SharedPartsList>,
+    _quota: Metered,
+) -> V2<Part> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    let action: PartsListUpdate = match action.map_or("add", |action| action.as_str()).try_into() {
+        Ok(action) => action,
+        Err(e) => return parts_failure(e),
+    };
+    let expected_version = match self::expected_version(expected_version, &if_match) {
+        Ok(version) => version,
+        Err(reply) => return reply,
+    };
+    if let Err(e) = max_batch.check(data.children.len()) {
+        return failure(Status::PayloadTooLarge, PartsErrorCode::RequestError, &e);
+    }
+    let data = data.into_inner();
+    let updated = parts.change(move |parts| {
+        let children: Vec<&Uuid> = data.children.iter().collect();
+        check_expected_version(&mut **parts, &part_id, expected_version)
+            .and_then(|_| parts.update(&part_id, &children, action))
+            .and_then(|_| parts.get(&part_id))
+    });
+    match updated {
+        Ok(Ok(part)) => success(Status::Ok, Reply::new(part)),
+        Ok(Err(e)) => parts_failure(e),
+        Err(e) => write_rejected(e),
+    }
+}
+
+#[get("/v2/parts/<part_id>/summary")]
+pub fn get_summary(part_id: RocketUuid, parts: State<SharedPartsList>) -> V2<PartSummary> {
+    let part_id = Uuid::from_bytes(*part_id.as_bytes());
+    match parts.try_read() {
+        Some(parts) => match parts.summary(&part_id) {
+            Ok(summary) => success(Status::Ok, Reply::new(summary)),
+            Err(e) => parts_failure(e),
+        },
+        None => read_lock_failure(),
+    }
+}
+
+#[get("/v2/stats")]
+pub fn get_stats(parts: State<SharedPartsList>) -> V2<PartsListStats> {
+    match parts.try_read() {
+        Some(parts) => match parts.stats() {
+            Ok(stats) => success(Status::Ok, Reply::new(stats)),
+            Err(e) => parts_failure(e),
+        },
+        None => read_lock_failure(),
+    }
+}
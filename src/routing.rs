@@ -0,0 +1,115 @@
+use uuid::Uuid;
+
+use crate::parts_list::{Part, PartsListError};
+
+/// Operation done to build an assembly, such as placing parts, reflowing or
+/// inspecting boards, with the standard time it takes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoutingStep {
+    /// Name of the operation, such as `SMT` or `Inspect`
+    pub operation: String,
+    /// Line or station the operation is done at, empty when unknown
+    #[serde(default)]
+    pub work_center: String,
+    /// Standard minutes taken to set up the operation for a batch
+    #[serde(default)]
+    pub setup_minutes: f64,
+    /// Standard minutes taken by the operation for each unit built
+    #[serde(default)]
+    pub run_minutes: f64,
+}
+
+/// Routing of a part with the standard times of its steps totalled
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Routing {
+    pub id: Uuid,
+    pub steps: Vec<RoutingStep>,
+    /// Standard minutes taken to set up every step for a batch
+    pub setup_minutes: f64,
+    /// Standard minutes taken by every step for each unit built
+    pub run_minutes: f64,
+}
+
+impl Routing {
+    pub fn of(part: &Part) -> Routing {
+        Routing {
+            id: part.id,
+            steps: part.routing.clone(),
+            setup_minutes: part
+                .routing
+                .iter()
+                .fold(0.0, |total, step| total + step.setup_minutes),
+            run_minutes: part
+                .routing
+                .iter()
+                .fold(0.0, |total, step| total + step.run_minutes),
+        }
+    }
+
+    /// Standard minutes taken to build `quantity` of the part in a single
+    /// batch
+    pub fn minutes(&self, quantity: u64) -> f64 {
+        self.setup_minutes + self.run_minutes * quantity as f64
+    }
+}
+
+/// Check that each step of the routing of a part names its operation and
+/// takes a finite time which isn't negative, counting steps from 1 as reports
+/// do when one is invalid
+pub fn check(part: &Part) -> Result<(), PartsListError> {
+    for (index, step) in part.routing.iter().enumerate() {
+        let invalid = |reason: &str| PartsListError::InvalidRoutingStep {
+            id: part.id,
+            position: index + 1,
+            reason: reason.into(),
+        };
+        if step.operation.trim().is_empty() {
+            return Err(invalid("the operation has no name"));
+        }
+        for minutes in &[step.setup_minutes, step.run_minutes] {
+            if !minutes.is_finite() || *minutes < 0.0 {
+                return Err(invalid("standard times must be positive or zero"));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_times() {
+        let step = |operation: &str, setup_minutes, run_minutes| RoutingStep {
+            operation: operation.into(),
+            work_center: String::new(),
+            setup_minutes,
+            run_minutes,
+        };
+        let mut board = Part::new("board");
+        board.routing = vec![
+            step("SMT", 30.0, 1.5),
+            step("Reflow", 10.0, 0.5),
+            step("Inspect", 0.0, 2.0),
+        ];
+        check(&board).unwrap();
+        assert_eq!(
+            Routing::of(&Part::new("bare")).setup_minutes.to_string(),
+            "0"
+        );
+        let routing = Routing::of(&board);
+        assert_eq!((routing.setup_minutes, routing.run_minutes), (40.0, 4.0));
+        assert_eq!(routing.minutes(10), 80.0);
+
+        board.routing.push(step(" ", 0.0, 1.0));
+        assert_matches!(
+            check(&board),
+            Err(PartsListError::InvalidRoutingStep { position: 4, .. })
+        );
+        board.routing[3] = step("Pack", f64::NAN, 1.0);
+        assert!(check(&board).is_err());
+        board.routing[3].setup_minutes = -1.0;
+        assert!(check(&board).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::cow_map::CowMap;
+use crate::parts_list::{attribute_text, Part};
+
+/// Split text into lowercase alphanumeric terms
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+/// Fewest edits needed to turn one term into the other
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Similarity between 0 and 1 of two terms, from the edits needed to turn one
+/// into the other relative to the length of the longer term
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let length = a.chars().count().max(b.chars().count());
+    if length == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / length as f64
+}
+
+/// Lowest similarity at which a term is considered a fuzzy match
+pub const FUZZY_THRESHOLD: f64 = 0.7;
+
+/// Part matching a search along with its relevance, higher scores ranking first
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub id: Uuid,
+    pub score: f64,
+}
+
+/// Inverted index from the terms of part names and attribute values to the
+/// parts using them
+///
+/// The maps are copied on write like those of the parts list holding the
+/// index, so it is kept up to date as parts change rather than rebuilt for
+/// each search.
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    /// Number of times each term occurs in each part
+    postings: CowMap<String, HashMap<Uuid, usize>>,
+    /// Number of terms in each part
+    lengths: CowMap<Uuid, usize>,
+}
+
+/// Terms of the name and attribute values of a part
+fn terms(part: &Part) -> Vec<String> {
+    let mut terms = tokenize(&part.name);
+    for value in part.attributes.values() {
+        terms.extend(tokenize(&attribute_text(value)));
+    }
+    terms
+}
+
+impl SearchIndex {
+    pub fn new() -> SearchIndex {
+        SearchIndex::default()
+    }
+
+    pub fn insert(&mut self, part: &Part) {
+        let terms = terms(part);
+        self.lengths.insert(part.id, terms.len());
+        for term in terms {
+            *self
+                .postings
+                .get_or_default(term)
+                .entry(part.id)
+                .or_default() += 1;
+        }
+    }
+
+    /// Remove a part, which must be given as it was when inserted
+    pub fn remove(&mut self, part: &Part) {
+        self.lengths.remove(&part.id);
+        for term in terms(part) {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.remove(&part.id);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Postings of the indexed terms matching a query term along with how
+    /// closely they match, either only the term itself or any term similar
+    /// enough when `fuzzy`
+    fn matching_terms(&self, term: &str, fuzzy: bool) -> Vec<(&HashMap<Uuid, usize>, f64)> {
+        if !fuzzy {
+            return self
+                .postings
+                .get(term)
+                .map(|postings| (postings, 1.0))
+                .into_iter()
+                .collect();
+        }
+        self.postings
+            .iter()
+            .map(|(indexed, postings)| (postings, similarity(term, indexed)))
+            .filter(|(_, similarity)| *similarity >= FUZZY_THRESHOLD)
+            .collect()
+    }
+
+    /// Find the parts containing any term of `query`, ranked by the inverse
+    /// document frequency of the terms they match, so parts matching more and
+    /// rarer terms rank first, with parts having fewer terms winning ties
+    ///
+    /// With `fuzzy` set, query terms also match terms within a few edits of
+    /// them, such as misspellings, with weaker matches adding less to the score.
+    pub fn search(&self, query: &str, fuzzy: bool) -> Vec<SearchHit> {
+        let total = self.lengths.len() as f64;
+        let mut scores: HashMap<Uuid, f64> = HashMap::new();
+        for term in tokenize(query) {
+            // credit each part once per query term, for its closest matching term
+            let mut best: HashMap<Uuid, f64> = HashMap::new();
+            for (postings, similarity) in self.matching_terms(&term, fuzzy) {
+                let idf = (1.0 + total / postings.len() as f64).ln();
+                for (id, count) in postings {
+                    let score = idf * *count as f64 * similarity;
+                    let entry = best.entry(*id).or_default();
+                    *entry = entry.max(score);
+                }
+            }
+            for (id, score) in best {
+                *scores.entry(id).or_default() += score;
+            }
+        }
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .map(|(id, score)| {
+                let length = self.lengths.get(&id).copied().unwrap_or(1).max(1) as f64;
+                SearchHit {
+                    id,
+                    score: score / length.sqrt(),
+                }
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        hits
+    }
+}
+
+impl<'a> std::iter::FromIterator<&'a Part> for SearchIndex {
+    fn from_iter<I: IntoIterator<Item = &'a Part>>(iter: I) -> Self {
+        let mut index = SearchIndex::new();
+        for part in iter {
+            index.insert(part);
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranked_search() {
+        let parts = [
+            Part::new("Resistor 10k"),
+            Part::new("Resistor 4.7k"),
+            Part::new("Capacitor 10uF"),
+            Part::new("Resistor network 10k 8-pin"),
+        ];
+        let index: SearchIndex = parts.iter().collect();
+        let ids = |query| -> Vec<Uuid> {
+            index
+                .search(query, false)
+                .iter()
+                .map(|hit| hit.id)
+                .collect()
+        };
+
+        assert_eq!(
+            ids("resistor 10K"),
+            vec![parts[0].id, parts[3].id, parts[1].id]
+        );
+        assert_eq!(ids("capacitor"), vec![parts[2].id]);
+        assert!(ids("inductor").is_empty());
+        assert!(ids("").is_empty());
+    }
+
+    #[test]
+    fn indexes_attribute_values() {
+        let mut resistor = Part::new("Resistor 10k");
+        resistor
+            .attributes
+            .insert("package".into(), serde_json::json!("0603 SMD"));
+        let mut index: SearchIndex = [&resistor].iter().copied().collect();
+        assert_eq!(index.search("smd", false)[0].id, resistor.id);
+
+        index.remove(&resistor);
+        assert!(index.search("smd resistor", false).is_empty());
+        assert!(index.search("resistor", true).is_empty());
+    }
+
+    #[test]
+    fn fuzzy_search() {
+        let parts = [Part::new("Resistor 10k\u{3a9}"), Part::new("Register file")];
+        let index: SearchIndex = parts.iter().collect();
+        assert!(index.search("resister 10k", false).is_empty());
+
+        let hits = index.search("resister 10k", true);
+        assert_eq!(hits[0].id, parts[0].id);
+        assert_eq!(hits.len(), 2);
+        assert!(hits[0].score > hits[1].score);
+
+        assert_eq!(levenshtein("resister", "resistor"), 1);
+        assert_eq!(similarity("10k", "10k"), 1.0);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::backup::Backup;
+use crate::parts_list::{Part, PartsListError, ValidationReport};
+use crate::store::PartsStore;
+
+/// Parts in a JSON seed file, either a backup archive once decompressed, the
+/// parts list written by automatic snapshots or a plain list of parts
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SeedJson {
+    Backup(Backup),
+    Snapshot(HashMap<Uuid, Part>),
+    Parts(Vec<Part>),
+}
+
+/// Row of a CSV seed file linking a child to its parent, both named
+#[derive(Deserialize)]
+struct SeedRow {
+    parent: String,
+    #[serde(default)]
+    child: String,
+}
+
+fn invalid(path: &Path, s: impl ToString) -> PartsListError {
+    PartsListError::InvalidSeed {
+        path: path.display().to_string(),
+        s: s.to_string(),
+    }
+}
+
+/// Read the parts of a seed file, in JSON or in CSV when its name ends with
+/// `.csv`
+///
+/// CSV files use the `parent,child` layout imported by the client, creating a
+/// part for each name and linking the children of each row to their parent.
+/// Rows without a child declare a standalone part. Any other columns, such as
+/// `quantity`, are ignored.
+pub fn read(path: &Path) -> Result<Vec<Part>, PartsListError> {
+    let file = BufReader::new(File::open(path)?);
+    let csv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    let parts = if csv {
+        read_csv(file).map_err(|e| invalid(path, e))?
+    } else {
+        match serde_json::from_reader(file).map_err(|e| invalid(path, e))? {
+            SeedJson::Backup(backup) => backup.parts,
+            SeedJson::Snapshot(parts) => parts.into_values().collect(),
+            SeedJson::Parts(parts) => parts,
+        }
+    };
+    let report = ValidationReport::check(&parts);
+    if !report.is_valid() {
+        let s = format!("{} violations", report.violations.len());
+        return Err(invalid(path, s));
+    }
+    Ok(parts)
+}
+
+fn read_csv<R: Read>(reader: R) -> Result<Vec<Part>, csv::Error> {
+    let mut parts: Vec<Part> = Vec::new();
+    let mut by_name: HashMap<String, usize> = HashMap::new();
+    let mut index = |name: &str, parts: &mut Vec<Part>| -> usize {
+        *by_name.entry(name.into()).or_insert_with(|| {
+            parts.push(Part::new(name));
+            parts.len() - 1
+        })
+    };
+    for row in csv::Reader::from_reader(reader).deserialize() {
+        let row: SeedRow = row?;
+        let (parent, child) = (row.parent.trim(), row.child.trim());
+        if parent.is_empty() {
+            continue;
+        }
+        let parent = index(parent, &mut parts);
+        if child.is_empty() {
+            continue;
+        }
+        let child = index(child, &mut parts);
+        let (parent_id, child_id) = (parts[parent].id, parts[child].id);
+        parts[parent].children.insert(child_id);
+        parts[child].parents.insert(parent_id);
+    }
+    Ok(parts)
+}
+
+/// Load the parts of a seed file into `store` when it holds no parts, returning
+/// the number loaded, or nothing when the store already holds parts so those
+/// kept by persistent backends are never replaced
+pub fn load(store: &mut dyn PartsStore, path: &Path) -> Result<Option<usize>, PartsListError> {
+    let parts = read(path)?;
+    if !store.parts()?.is_empty() {
+        return Ok(None);
+    }
+    let count = parts.len();
+    store.replace_all(parts)?;
+    Ok(Some(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts_list::{PartsList, PartsListFilter};
+    use std::fs;
+
+    #[test]
+    fn seed_files() {
+        let dir = std::env::temp_dir().join(format!("bom-seed-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("seed.csv");
+        fs::write(
+            &csv,
+            "parent,child,quantity\nbike,wheel,2\nwheel,spoke,32\nbike,frame,1\nbell,,\n",
+        )
+        .unwrap();
+
+        let mut store = PartsList::new();
+        assert_eq!(load(&mut store, &csv).unwrap(), Some(5));
+        let bike = store.get_by_name("bike")[0].id;
+        assert_eq!(
+            store
+                .get_children(&bike, PartsListFilter::All)
+                .unwrap()
+                .len(),
+            3
+        );
+        // parts already held are kept
+        assert_eq!(load(&mut store, &csv).unwrap(), None);
+
+        let json = dir.join("seed.json");
+        fs::write(&json, serde_json::to_vec(&store).unwrap()).unwrap();
+        assert_eq!(read(&json).unwrap().len(), 5);
+        let parts = store.parts().unwrap();
+        fs::write(&json, serde_json::to_vec(&Backup::new(parts)).unwrap()).unwrap();
+        assert_eq!(read(&json).unwrap().len(), 5);
+
+        fs::write(&csv, "parent,child\na,b\nb,a\n").unwrap();
+        assert_matches!(read(&csv), Err(PartsListError::InvalidSeed { .. }));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
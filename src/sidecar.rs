@@ -0,0 +1,63 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::parts_list::PartsListError;
+
+/// File next to the log of the `wal` backend holding data kept alongside the
+/// parts, such as `bom.categories.json` for the log `bom.wal`
+pub fn path(wal_path: &Path, name: &str) -> PathBuf {
+    let stem = wal_path
+        .file_stem()
+        .map_or_else(|| "bom".into(), |stem| stem.to_string_lossy());
+    wal_path.with_file_name(format!("{}.{}.json", stem, name))
+}
+
+/// Read the items saved in a sidecar file, none when it doesn't exist yet
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, PartsListError> {
+    match File::open(path) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace the items saved in a sidecar file
+pub fn save<T: Serialize>(path: &Path, items: &[T]) -> Result<(), PartsListError> {
+    // written aside and renamed so the file is never seen half written
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, items)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_next_to_the_log() {
+        assert_eq!(
+            path(Path::new("/var/lib/bom/parts.wal"), "categories"),
+            PathBuf::from("/var/lib/bom/parts.categories.json")
+        );
+
+        let dir = std::env::temp_dir().join(format!("bom-sidecar-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = path(&dir.join("bom.wal"), "numbers");
+        assert!(load::<u32>(&path).unwrap().is_empty());
+        save(&path, &[1, 2, 3]).unwrap();
+        assert_eq!(load::<u32>(&path).unwrap(), vec![1, 2, 3]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
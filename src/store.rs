@@ -0,0 +1,897 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::parts_list::{
+    DuplicateNamePolicy, Part, PartEdit, PartSummary, PartsList, PartsListError, PartsListFilter,
+    PartsListQuery, PartsListStats, PartsListUpdate, Relationship, RelationshipEdit,
+    ValidationReport,
+};
+
+pub mod cache;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod soft_delete;
+pub mod wal;
+
+impl From<std::io::Error> for PartsListError {
+    fn from(e: std::io::Error) -> Self {
+        PartsListError::Storage { s: e.to_string() }
+    }
+}
+
+/// Storage backend for the parts served by `SharedPartsList`
+///
+/// Backends only need to provide lookup, insertion, removal, iteration and
+/// relationship updates. Queries over the graph have default implementations
+/// which load every part into an in-memory `PartsList`, and can be overridden
+/// by backends able to answer them more efficiently.
+pub trait PartsStore: Send + Sync {
+    /// Retrieve a single part
+    fn get(&self, id: &Uuid) -> Result<Part, PartsListError>;
+
+    /// Add a new part, failing if a part with the same id already exists
+    fn insert(&mut self, part: Part) -> Result<Part, PartsListError>;
+
+    /// Remove a part and unlink it from all of its parents and children
+    fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError>;
+
+    /// Retrieve every part in the store
+    fn parts(&self) -> Result<Vec<Part>, PartsListError>;
+
+    /// Add, remove or replace the children of a part
+    fn update(
+        &mut self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<(), PartsListError>;
+
+    /// Change the attributes of a part, returning the edited part
+    fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError>;
+
+    /// Change the metadata of the relationship between a parent and child,
+    /// returning the edited relationship
+    fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<Relationship, PartsListError>;
+
+    /// Retrieve the relationship between a parent and child by its id
+    fn get_relationship(&self, id: &Uuid) -> Result<Relationship, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let relationship = snapshot.get_relationship(id)?;
+        Ok(relationship.clone())
+    }
+
+    /// Fail unless part `id` is at the `expected` version
+    fn check_version(&self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        let part = self.get(id)?;
+        if part.version != expected {
+            return Err(PartsListError::VersionConflict {
+                id: *id,
+                expected,
+                actual: part.version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Fail unless part `id` is at the `expected` version, and keep failing
+    /// changes made before `end_write` if it no longer is, so a change based
+    /// on an earlier read of the part doesn't overwrite edits made since
+    ///
+    /// Stores modified only through one server just check the version, as the
+    /// write lock keeps other changes out until the write ends. Stores shared
+    /// between servers check it again along with each change.
+    fn expect_version(&mut self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        self.check_version(id, expected)
+    }
+
+    /// Forget the versions expected since the write began, once it is done
+    fn end_write(&mut self) {}
+
+    /// Retrieve several parts at once, returning the parts found along with the
+    /// ids of those which don't exist
+    fn get_many(&self, ids: &[Uuid]) -> Result<(Vec<Part>, Vec<Uuid>), PartsListError> {
+        let mut found = Vec::new();
+        let mut missing = Vec::new();
+        for id in ids {
+            match self.get(id) {
+                Ok(part) => found.push(part),
+                Err(PartsListError::PartDoesNotExist { id }) => missing.push(id),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((found, missing))
+    }
+
+    /// Retrieve the parts with a name, of which there is at most one unless
+    /// duplicate names are allowed
+    fn get_by_name(&self, name: &str) -> Result<Vec<Part>, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let parts = snapshot.get_by_name(name);
+        Ok(parts.into_iter().cloned().collect())
+    }
+
+    /// Retrieve the part known by `external_id` in an external `system`
+    fn get_by_external(&self, system: &str, external_id: &str) -> Result<Part, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let part = snapshot.get_by_external(system, external_id)?;
+        Ok(part.clone())
+    }
+
+    /// Find the parts whose names or attribute values match the terms of
+    /// `query` along with their scores, most relevant first, tolerating misspelt
+    /// terms when `fuzzy`
+    fn search(&self, query: &str, fuzzy: bool) -> Result<Vec<(Part, f64)>, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let hits = snapshot.search(query, fuzzy);
+        Ok(hits
+            .into_iter()
+            .map(|(part, score)| (part.clone(), score))
+            .collect())
+    }
+
+    /// Load every part into an in-memory parts list
+    fn snapshot(&self) -> Result<PartsList, PartsListError> {
+        Ok(self.parts()?.into_iter().collect())
+    }
+
+    fn list(&self, filter: PartsListFilter) -> Result<Vec<Part>, PartsListError> {
+        Ok(self.snapshot()?.list(filter).into_iter().cloned().collect())
+    }
+
+    /// List parts matching the filter of `query`, keeping those which also
+    /// satisfy all of its predicates
+    fn query(&self, query: &PartsListQuery) -> Result<Vec<Part>, PartsListError> {
+        let mut list = self.list(query.filter)?;
+        list.retain(|part| query.matches(part));
+        Ok(list)
+    }
+
+    fn get_children(
+        &self,
+        id: &Uuid,
+        filter: PartsListFilter,
+    ) -> Result<Vec<Part>, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let children = snapshot.get_children(id, filter)?;
+        Ok(children.into_iter().cloned().collect())
+    }
+
+    /// Find the assemblies containing a part, up to `depth` levels above it
+    /// or all of them when no depth is given
+    fn get_ancestors(&self, id: &Uuid, depth: Option<usize>) -> Result<Vec<Part>, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let ancestors = snapshot.get_ancestors(id, depth)?;
+        Ok(ancestors.into_iter().cloned().collect())
+    }
+
+    fn stats(&self) -> Result<PartsListStats, PartsListError> {
+        Ok(self.snapshot()?.stats())
+    }
+
+    /// Count the children and descendants of a part along with the depth of
+    /// its subtree, which is built from the subtree query so the rollup cache
+    /// answers it when enabled
+    fn summary(&self, id: &Uuid) -> Result<PartSummary, PartsListError> {
+        let part = self.get(id)?;
+        let descendants = self.get_children(id, PartsListFilter::All)?;
+        let mut by_id: HashMap<Uuid, &Part> =
+            descendants.iter().map(|part| (part.id, part)).collect();
+        by_id.insert(part.id, &part);
+        Ok(PartSummary {
+            id: *id,
+            children: part.children.len(),
+            descendants: descendants.len(),
+            depth: subtree_depth(&by_id, id, &mut HashMap::new()),
+        })
+    }
+
+    /// Rank the components by the number of distinct assemblies containing
+    /// them, most used first, keeping the first `limit`
+    fn top_used(&self, limit: usize) -> Result<Vec<(Part, usize)>, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let ranked = snapshot.top_used(limit);
+        Ok(ranked
+            .into_iter()
+            .map(|(part, used_by)| (part.clone(), used_by))
+            .collect())
+    }
+
+    /// Validate the parts as held by the backend, without the repairs made when
+    /// loading them into a `PartsList`
+    fn validate(&self) -> Result<ValidationReport, PartsListError> {
+        Ok(ValidationReport::check(&self.parts()?))
+    }
+
+    fn delete_tree_candidates(&self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        let snapshot = self.snapshot()?;
+        let candidates = snapshot.delete_tree_candidates(id)?;
+        Ok(candidates.into_iter().cloned().collect())
+    }
+
+    fn delete_tree(&mut self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        let removed = self.delete_tree_candidates(id)?;
+        for part in &removed {
+            self.remove(&part.id)?;
+        }
+        Ok(removed)
+    }
+
+    /// Create `part`, or merge its external ids into the existing part it
+    /// matches, returning the stored part and whether it was created
+    ///
+    /// The part is matched by its id in the external `system` when one is
+    /// given, otherwise by its name. A matched part is only edited when the
+    /// external ids would change it, so repeating an upsert keeps its version.
+    fn upsert(&mut self, part: Part, system: Option<&str>) -> Result<(Part, bool), PartsListError> {
+        let existing = match system {
+            Some(system) => {
+                let external_id = part.external_ids.get(system).ok_or_else(|| {
+                    PartsListError::MissingExternalId {
+                        system: system.into(),
+                    }
+                })?;
+                match self.get_by_external(system, external_id) {
+                    Ok(existing) => Some(existing),
+                    Err(PartsListError::ExternalIdDoesNotExist { .. }) => None,
+                    Err(e) => return Err(e),
+                }
+            }
+            None => {
+                let mut named = self.get_by_name(&part.name)?;
+                if named.len() > 1 {
+                    return Err(PartsListError::AmbiguousName { name: part.name });
+                }
+                named.pop()
+            }
+        };
+        let existing = match existing {
+            Some(existing) => existing,
+            None => return Ok((self.insert(part)?, true)),
+        };
+        if existing.name != part.name {
+            return Err(PartsListError::MatchedPartNameDiffers {
+                id: existing.id,
+                name: existing.name,
+            });
+        }
+        let mut external_ids = existing.external_ids.clone();
+        external_ids.extend(part.external_ids);
+        if external_ids == existing.external_ids {
+            return Ok((existing, false));
+        }
+        let edit = PartEdit {
+            external_ids: Some(external_ids),
+            ..PartEdit::default()
+        };
+        Ok((self.edit(&existing.id, &edit)?, false))
+    }
+
+    /// Delete each of the listed parts, along with their subtrees when
+    /// `recursive`, returning the removed parts or the failure for each id in
+    /// the same order
+    ///
+    /// A failure to delete one part doesn't stop the rest from being deleted.
+    fn delete_many(
+        &mut self,
+        ids: &[Uuid],
+        recursive: bool,
+    ) -> Vec<Result<Vec<Part>, PartsListError>> {
+        ids.iter()
+            .map(|id| {
+                if recursive {
+                    self.delete_tree(id)
+                } else {
+                    let part = self.get(id)?;
+                    self.remove(id).map(|_| vec![part])
+                }
+            })
+            .collect()
+    }
+
+    /// Find the components used by every one of the listed assemblies,
+    /// directly or through their subassemblies, ordered by name
+    fn common_components(&self, ids: &[Uuid]) -> Result<Vec<Part>, PartsListError> {
+        let mut common: Option<Vec<Part>> = None;
+        for id in ids {
+            let components = self.get_children(id, PartsListFilter::Component)?;
+            common = Some(match common {
+                None => components,
+                Some(common) => {
+                    let used: HashSet<Uuid> = components.iter().map(|part| part.id).collect();
+                    common
+                        .into_iter()
+                        .filter(|part| used.contains(&part.id))
+                        .collect()
+                }
+            });
+        }
+        let mut common = common.unwrap_or_default();
+        common.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        Ok(common)
+    }
+
+    /// Remove every part with neither parents nor children, returning the
+    /// removed parts
+    fn remove_orphans(&mut self) -> Result<Vec<Part>, PartsListError> {
+        let removed = self.list(PartsListFilter::Orphan)?;
+        for part in &removed {
+            self.remove(&part.id)?;
+        }
+        Ok(removed)
+    }
+
+    /// Handling of new parts given the name of an existing part
+    fn name_policy(&self) -> DuplicateNamePolicy {
+        DuplicateNamePolicy::Reject
+    }
+
+    /// Parts list the store would hold once `replace_all` restored `parts`,
+    /// built with every check made when restoring them but without changing
+    /// the store
+    fn restored(&self, parts: Vec<Part>) -> Result<PartsList, PartsListError> {
+        let mut restored = PartsList::new();
+        restored.set_name_policy(self.name_policy());
+        add_linked(&mut restored, parts)?;
+        Ok(restored)
+    }
+
+    /// Replace every part in the store with `parts`, as when restoring a backup
+    ///
+    /// The parts are restored into a new parts list before any part is
+    /// removed, so parts which can't be restored leave the store unchanged.
+    /// Backends able to swap in the whole list at once override this.
+    fn replace_all(&mut self, parts: Vec<Part>) -> Result<(), PartsListError> {
+        let restored = self.restored(parts)?;
+        for part in self.parts()? {
+            self.remove(&part.id)?;
+        }
+        add_linked(self, restored.parts()?)
+    }
+
+    /// Move `child` from parent `from` to parent `to`, keeping the quantity
+    /// and notes of its relationship
+    ///
+    /// The move is checked before anything is changed, so the child is never
+    /// left unlinked from `from` without being linked to `to`. Stores modified
+    /// only through one server hold the write lock across the whole move.
+    fn move_child(&mut self, child: &Uuid, from: &Uuid, to: &Uuid) -> Result<(), PartsListError> {
+        let parent = self.get(from)?;
+        self.get(to)?;
+        if !parent.children.contains(child) {
+            return Err(PartsListError::NotAChild {
+                parent: *from,
+                child: *child,
+            });
+        }
+        if from == to {
+            return Ok(());
+        }
+        // can't move a part below itself or one of its descendants
+        let cyclical = to == child
+            || self
+                .get_children(child, PartsListFilter::All)?
+                .iter()
+                .any(|part| part.id == *to);
+        if cyclical {
+            return Err(PartsListError::AddChildCyclicalRelative {
+                parent: *to,
+                child: *child,
+                path: self.snapshot()?.cycle_path(to, child),
+            });
+        }
+        let linked = self.get(to)?.children.contains(child);
+        self.update(from, &[child], PartsListUpdate::Remove)?;
+        if !linked {
+            self.update(to, &[child], PartsListUpdate::Add)?;
+            if let Some(relationship) = parent.relationships.get(child) {
+                let edit = RelationshipEdit::copy_of(relationship);
+                if !edit.is_default() {
+                    self.edit_relationship(&Relationship::id_for(to, child), &edit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Recreate a part which was deleted, when the backend keeps deleted parts
+    fn restore(&mut self, id: &Uuid) -> Result<Part, PartsListError> {
+        Err(PartsListError::NotDeleted { id: *id })
+    }
+
+    /// Ids of the parts gone for good out of those just `deleted` and any
+    /// kept since they were deleted earlier, so data held about them outside
+    /// the store, such as their attachments, can be removed
+    fn purged(&mut self, deleted: &[Uuid]) -> Vec<Uuid> {
+        deleted.to_vec()
+    }
+}
+
+impl PartsStore for PartsList {
+    fn get(&self, id: &Uuid) -> Result<Part, PartsListError> {
+        PartsList::get(self, id).cloned()
+    }
+
+    fn insert(&mut self, part: Part) -> Result<Part, PartsListError> {
+        PartsList::add(self, part).cloned()
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+        PartsList::delete(self, id)
+    }
+
+    fn parts(&self) -> Result<Vec<Part>, PartsListError> {
+        Ok(self.iter().cloned().collect())
+    }
+
+    fn update(
+        &mut self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<(), PartsListError> {
+        PartsList::update(self, id, children, op)
+    }
+
+    fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError> {
+        PartsList::edit(self, id, edit).cloned()
+    }
+
+    fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<Relationship, PartsListError> {
+        PartsList::edit_relationship(self, id, edit).cloned()
+    }
+
+    fn get_relationship(&self, id: &Uuid) -> Result<Relationship, PartsListError> {
+        PartsList::get_relationship(self, id).cloned()
+    }
+
+    fn name_policy(&self) -> DuplicateNamePolicy {
+        PartsList::name_policy(self)
+    }
+
+    fn replace_all(&mut self, parts: Vec<Part>) -> Result<(), PartsListError> {
+        *self = PartsStore::restored(self, parts)?;
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<PartsList, PartsListError> {
+        // shares everything with the list until either is changed
+        Ok(self.clone())
+    }
+
+    fn list(&self, filter: PartsListFilter) -> Result<Vec<Part>, PartsListError> {
+        Ok(PartsList::list(self, filter).into_iter().cloned().collect())
+    }
+
+    fn query(&self, query: &PartsListQuery) -> Result<Vec<Part>, PartsListError> {
+        Ok(PartsList::query(self, query).into_iter().cloned().collect())
+    }
+
+    fn search(&self, query: &str, fuzzy: bool) -> Result<Vec<(Part, f64)>, PartsListError> {
+        Ok(PartsList::search(self, query, fuzzy)
+            .into_iter()
+            .map(|(part, score)| (part.clone(), score))
+            .collect())
+    }
+
+    fn get_by_name(&self, name: &str) -> Result<Vec<Part>, PartsListError> {
+        Ok(PartsList::get_by_name(self, name)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    fn get_by_external(&self, system: &str, external_id: &str) -> Result<Part, PartsListError> {
+        PartsList::get_by_external(self, system, external_id).cloned()
+    }
+
+    fn get_children(
+        &self,
+        id: &Uuid,
+        filter: PartsListFilter,
+    ) -> Result<Vec<Part>, PartsListError> {
+        let children = PartsList::get_children(self, id, filter)?;
+        Ok(children.into_iter().cloned().collect())
+    }
+
+    fn get_ancestors(&self, id: &Uuid, depth: Option<usize>) -> Result<Vec<Part>, PartsListError> {
+        let ancestors = PartsList::get_ancestors(self, id, depth)?;
+        Ok(ancestors.into_iter().cloned().collect())
+    }
+
+    fn stats(&self) -> Result<PartsListStats, PartsListError> {
+        Ok(PartsList::stats(self))
+    }
+
+    fn top_used(&self, limit: usize) -> Result<Vec<(Part, usize)>, PartsListError> {
+        Ok(PartsList::top_used(self, limit)
+            .into_iter()
+            .map(|(part, used_by)| (part.clone(), used_by))
+            .collect())
+    }
+
+    fn validate(&self) -> Result<ValidationReport, PartsListError> {
+        Ok(PartsList::validate(self))
+    }
+
+    fn delete_tree_candidates(&self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        let candidates = PartsList::delete_tree_candidates(self, id)?;
+        Ok(candidates.into_iter().cloned().collect())
+    }
+
+    fn delete_tree(&mut self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        PartsList::delete_tree(self, id)
+    }
+
+    fn remove_orphans(&mut self) -> Result<Vec<Part>, PartsListError> {
+        Ok(PartsList::delete_orphans(self))
+    }
+}
+
+/// Add `parts` to `store` along with the relationships between them
+///
+/// Parts are added without relationships before being linked to their
+/// children, so they can be given in any order, then the metadata of their
+/// relationships is restored.
+fn add_linked<S: PartsStore + ?Sized>(
+    store: &mut S,
+    parts: Vec<Part>,
+) -> Result<(), PartsListError> {
+    let mut links = Vec::new();
+    let mut edits = Vec::new();
+    for mut part in parts {
+        let children: Vec<Uuid> = part.children.drain().collect();
+        part.parents.clear();
+        for relationship in std::mem::take(&mut part.relationships).into_values() {
+            let edit = RelationshipEdit::copy_of(&relationship);
+            if children.contains(&relationship.child) && !edit.is_default() {
+                edits.push((Relationship::id_for(&part.id, &relationship.child), edit));
+            }
+        }
+        if !children.is_empty() {
+            links.push((part.id, children));
+        }
+        store.insert(part)?;
+    }
+    for (id, children) in links {
+        let children: Vec<&Uuid> = children.iter().collect();
+        store.update(&id, &children, PartsListUpdate::Add)?;
+    }
+    for (id, edit) in edits {
+        store.edit_relationship(&id, &edit)?;
+    }
+    Ok(())
+}
+
+/// Number of levels of children below a part within `parts`, memoized in
+/// `depths`
+fn subtree_depth(
+    parts: &HashMap<Uuid, &Part>,
+    id: &Uuid,
+    depths: &mut HashMap<Uuid, usize>,
+) -> usize {
+    if let Some(depth) = depths.get(id) {
+        return *depth;
+    }
+    let depth = match parts.get(id) {
+        Some(part) => part
+            .children
+            .iter()
+            .map(|child| subtree_depth(parts, child, depths) + 1)
+            .max()
+            .unwrap_or(0),
+        None => 0,
+    };
+    depths.insert(*id, depth);
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal store relying on the default graph queries
+    struct VecStore(Vec<Part>);
+
+    impl PartsStore for VecStore {
+        fn get(&self, id: &Uuid) -> Result<Part, PartsListError> {
+            self.0
+                .iter()
+                .find(|part| part.id == *id)
+                .cloned()
+                .ok_or(PartsListError::PartDoesNotExist { id: *id })
+        }
+
+        fn insert(&mut self, part: Part) -> Result<Part, PartsListError> {
+            self.0.push(part.clone());
+            Ok(part)
+        }
+
+        fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+            self.0.retain(|part| part.id != *id);
+            for part in self.0.iter_mut() {
+                part.parents.remove(id);
+                part.children.remove(id);
+            }
+            Ok(())
+        }
+
+        fn parts(&self) -> Result<Vec<Part>, PartsListError> {
+            Ok(self.0.clone())
+        }
+
+        fn update(
+            &mut self,
+            id: &Uuid,
+            children: &[&Uuid],
+            op: PartsListUpdate,
+        ) -> Result<(), PartsListError> {
+            let mut snapshot = self.snapshot()?;
+            snapshot.update(id, children, op)?;
+            self.0 = snapshot.into_iter().collect();
+            Ok(())
+        }
+
+        fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError> {
+            let part = self
+                .0
+                .iter_mut()
+                .find(|part| part.id == *id)
+                .ok_or(PartsListError::PartDoesNotExist { id: *id })?;
+            edit.apply(part);
+            Ok(part.clone())
+        }
+
+        fn edit_relationship(
+            &mut self,
+            id: &Uuid,
+            edit: &RelationshipEdit,
+        ) -> Result<Relationship, PartsListError> {
+            let mut snapshot = self.snapshot()?;
+            let relationship = snapshot.edit_relationship(id, edit)?.clone();
+            self.0 = snapshot.into_iter().collect();
+            Ok(relationship)
+        }
+    }
+
+    #[test]
+    fn default_store_queries() {
+        let mut store: Box<dyn PartsStore> = Box::new(VecStore(Vec::new()));
+        let part1 = store.insert(Part::new("my part")).unwrap();
+        let part2 = store.insert(Part::new("subassy")).unwrap();
+        let part3 = store.insert(Part::new("deep component")).unwrap();
+        store
+            .update(&part1.id, &[&part2.id], PartsListUpdate::Add)
+            .unwrap();
+        store
+            .update(&part2.id, &[&part3.id], PartsListUpdate::Add)
+            .unwrap();
+
+        let mut children = store.get_children(&part1.id, PartsListFilter::All).unwrap();
+        children.sort();
+        let mut expected = vec![part2.clone(), part3.clone()];
+        expected.sort();
+        assert_eq!(children, expected);
+
+        let top_level = store.list(PartsListFilter::TopLevel).unwrap();
+        assert_eq!(top_level, vec![part1.clone()]);
+
+        let missing = Part::new("missing").id;
+        let (found, not_found) = store.get_many(&[part3.id, missing]).unwrap();
+        assert_eq!(found, vec![part3.clone()]);
+        assert_eq!(not_found, vec![missing]);
+
+        let orphan = store.insert(Part::new("orphan")).unwrap();
+        assert_eq!(store.remove_orphans().unwrap(), vec![orphan]);
+
+        let removed = store.delete_tree(&part1.id).unwrap();
+        assert_eq!(removed.len(), 3);
+        assert!(store.parts().unwrap().is_empty());
+    }
+
+    #[test]
+    fn upsert_by_name_or_external_id() {
+        let mut store = PartsList::new();
+        let mut screw = Part::new("screw");
+        screw.external_ids.insert("erp".into(), "100".into());
+        let (created, inserted) = store.upsert(screw.clone(), None).unwrap();
+        assert!(inserted);
+
+        // repeating the upsert leaves the part as it is
+        let (same, inserted) = store.upsert(screw.clone(), Some("erp")).unwrap();
+        assert!(!inserted);
+        assert_eq!(same, created);
+
+        let mut update = Part::new("screw");
+        update.external_ids.insert("plm".into(), "A-1".into());
+        let (updated, inserted) = store.upsert(update, None).unwrap();
+        assert!(!inserted);
+        assert_eq!(updated.id, created.id);
+        assert_eq!(updated.external_ids.len(), 2);
+        assert!(updated.version > created.version);
+
+        let mut renamed = Part::new("bolt");
+        renamed.external_ids.insert("erp".into(), "100".into());
+        assert_matches!(
+            store.upsert(renamed, Some("erp")),
+            Err(PartsListError::MatchedPartNameDiffers { .. })
+        );
+        assert_matches!(
+            store.upsert(Part::new("nut"), Some("erp")),
+            Err(PartsListError::MissingExternalId { .. })
+        );
+    }
+
+    #[test]
+    fn delete_many_reports_each_id() {
+        let mut store = PartsList::new();
+        let assy = store.insert(Part::new("assy")).unwrap().id;
+        let screw = store.insert(Part::new("screw")).unwrap().id;
+        let nut = store.insert(Part::new("nut")).unwrap().id;
+        store
+            .update(&assy, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+
+        let results = PartsStore::delete_many(&mut store, &[assy, Uuid::new_v4(), nut], true);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().len(), 2);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap()[0].id, nut);
+        assert!(store.parts().unwrap().is_empty());
+    }
+
+    #[test]
+    fn common_components_of_assemblies() {
+        let mut store = PartsList::new();
+        let bike = store.insert(Part::new("bike")).unwrap().id;
+        let trike = store.insert(Part::new("trike")).unwrap().id;
+        let wheel = store.insert(Part::new("wheel")).unwrap().id;
+        let spoke = store.insert(Part::new("spoke")).unwrap().id;
+        let bell = store.insert(Part::new("bell")).unwrap().id;
+        let seat = store.insert(Part::new("seat")).unwrap().id;
+        store
+            .update(&wheel, &[&spoke], PartsListUpdate::Add)
+            .unwrap();
+        store
+            .update(&bike, &[&wheel, &bell, &seat], PartsListUpdate::Add)
+            .unwrap();
+        store
+            .update(&trike, &[&spoke, &seat], PartsListUpdate::Add)
+            .unwrap();
+
+        // the bike only uses the spoke through its wheel
+        let common: Vec<Uuid> = store
+            .common_components(&[bike, trike])
+            .unwrap()
+            .into_iter()
+            .map(|part| part.id)
+            .collect();
+        assert_eq!(common, vec![seat, spoke]);
+        assert_eq!(store.common_components(&[bike]).unwrap().len(), 3);
+        assert!(store.common_components(&[bike, Uuid::new_v4()]).is_err());
+    }
+
+    #[test]
+    fn summary_of_subtree() {
+        let mut store = PartsList::new();
+        let bike = store.insert(Part::new("bike")).unwrap().id;
+        let wheel = store.insert(Part::new("wheel")).unwrap().id;
+        let spoke = store.insert(Part::new("spoke")).unwrap().id;
+        let bell = store.insert(Part::new("bell")).unwrap().id;
+        store
+            .update(&wheel, &[&spoke], PartsListUpdate::Add)
+            .unwrap();
+        store
+            .update(&bike, &[&wheel, &spoke, &bell], PartsListUpdate::Add)
+            .unwrap();
+
+        // the spoke is counted once although it is on two levels
+        let summary = store.summary(&bike).unwrap();
+        assert_eq!(
+            (summary.children, summary.descendants, summary.depth),
+            (3, 3, 2)
+        );
+        let summary = store.summary(&spoke).unwrap();
+        assert_eq!(
+            (summary.children, summary.descendants, summary.depth),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn move_child_between_parents() {
+        let mut store = PartsList::new();
+        let from = store.insert(Part::new("old assy")).unwrap().id;
+        let to = store.insert(Part::new("new assy")).unwrap().id;
+        let subassy = store.insert(Part::new("subassy")).unwrap().id;
+        let screw = store.insert(Part::new("screw")).unwrap().id;
+        store
+            .update(&from, &[&subassy], PartsListUpdate::Add)
+            .unwrap();
+        store
+            .update(&subassy, &[&screw], PartsListUpdate::Add)
+            .unwrap();
+        let two = RelationshipEdit {
+            quantity: Some(2),
+            notes: None,
+        };
+        store
+            .edit_relationship(&Relationship::id_for(&from, &subassy), &two)
+            .unwrap();
+
+        // a part can't be moved below its own children
+        assert_matches!(
+            PartsStore::move_child(&mut store, &subassy, &from, &screw),
+            Err(PartsListError::AddChildCyclicalRelative { .. })
+        );
+        assert!(store.get(&from).unwrap().children.contains(&subassy));
+
+        PartsStore::move_child(&mut store, &subassy, &from, &to).unwrap();
+        assert!(store.get(&from).unwrap().children.is_empty());
+        assert_eq!(store.get(&to).unwrap().quantity_of(&subassy), 2);
+        assert_matches!(
+            PartsStore::move_child(&mut store, &subassy, &from, &to),
+            Err(PartsListError::NotAChild { .. })
+        );
+    }
+
+    #[test]
+    fn failed_restore_keeps_parts() {
+        let kept = Part::new("kept");
+        // two parts with the same name, and parts containing each other
+        let duplicates = vec![Part::new("bolt"), Part::new("bolt")];
+        let mut frame = Part::new("frame");
+        let mut fork = Part::new("fork");
+        frame.children.insert(fork.id);
+        fork.children.insert(frame.id);
+        let cyclic = vec![frame, fork];
+
+        let mut stores: Vec<Box<dyn PartsStore>> =
+            vec![Box::new(VecStore(Vec::new())), Box::new(PartsList::new())];
+        for store in stores.iter_mut() {
+            store.insert(kept.clone()).unwrap();
+            assert_matches!(
+                store.restored(duplicates.clone()),
+                Err(PartsListError::PartNameExists { .. })
+            );
+            assert_matches!(
+                store.replace_all(duplicates.clone()),
+                Err(PartsListError::PartNameExists { .. })
+            );
+            // linking the parts fails once they have all been added
+            assert_matches!(
+                store.replace_all(cyclic.clone()),
+                Err(PartsListError::AddChildCyclicalRelative { .. })
+            );
+            let parts = store.parts().unwrap();
+            assert_eq!(parts.len(), 1);
+            assert_eq!(parts[0].id, kept.id);
+        }
+
+        let mut store = PartsList::new();
+        store.set_name_policy(DuplicateNamePolicy::Suffix);
+        PartsStore::replace_all(&mut store, duplicates).unwrap();
+        assert_eq!(store.get_by_name("bolt (2)").len(), 1);
+    }
+
+    #[test]
+    fn snapshot_keeps_name_policy() {
+        let mut store = PartsList::new();
+        store.set_name_policy(DuplicateNamePolicy::Allow);
+        store.insert(Part::new("bolt")).unwrap();
+        store.insert(Part::new("bolt")).unwrap();
+        let snapshot = PartsStore::snapshot(&store).unwrap();
+        assert_eq!(snapshot.name_policy(), DuplicateNamePolicy::Allow);
+        assert!(snapshot.validate().is_valid());
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+use crate::parts_list::{
+    DuplicateNamePolicy, Part, PartEdit, PartsList, PartsListError, PartsListFilter,
+    PartsListStats, PartsListUpdate, Relationship, RelationshipEdit, ValidationReport,
+};
+use crate::perf;
+use crate::store::PartsStore;
+
+/// Cached subtree query result along with the version of the part it was
+/// computed at
+struct CacheEntry {
+    version: u64,
+    parts: Vec<Part>,
+}
+
+/// Parts store wrapper caching the results of subtree queries
+///
+/// Every part has a version number which is bumped, along with the versions
+/// of all of its ancestors, whenever a mutation touches the part. A cached
+/// query is reused for as long as the version of the part it was made for is
+/// unchanged, so repeated explosions of an assembly are lookups until
+/// something below it changes.
+///
+/// Only changes made through the wrapper invalidate the cache, so it must not
+/// be used with backends shared by several server instances.
+pub struct CachedStore {
+    store: Box<dyn PartsStore>,
+    versions: HashMap<Uuid, u64>,
+    next_version: u64,
+    cache: Mutex<HashMap<(Uuid, PartsListFilter), CacheEntry>>,
+}
+
+impl CachedStore {
+    pub fn new(store: Box<dyn PartsStore>) -> CachedStore {
+        CachedStore {
+            store,
+            versions: HashMap::new(),
+            next_version: 1,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn version(&self, id: &Uuid) -> u64 {
+        self.versions.get(id).copied().unwrap_or(0)
+    }
+
+    /// Bump the versions of the given parts and all of their ancestors,
+    /// invalidating every cached subtree containing them
+    fn touch(&mut self, ids: &[Uuid]) {
+        let mut touched: HashSet<Uuid> = HashSet::new();
+        for id in ids {
+            if touched.insert(*id) {
+                if let Ok(ancestors) = self.store.get_ancestors(id, None) {
+                    touched.extend(ancestors.iter().map(|part| part.id));
+                }
+            }
+        }
+        let version = self.next_version;
+        self.next_version += 1;
+        for id in touched {
+            self.versions.insert(id, version);
+        }
+    }
+
+    /// Ids of a part and all of its direct relatives
+    fn neighbourhood(&self, id: &Uuid) -> Vec<Uuid> {
+        match self.store.get(id) {
+            Ok(part) => std::iter::once(part.id)
+                .chain(part.parents)
+                .chain(part.children)
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl PartsStore for CachedStore {
+    fn get(&self, id: &Uuid) -> Result<Part, PartsListError> {
+        self.store.get(id)
+    }
+
+    fn insert(&mut self, part: Part) -> Result<Part, PartsListError> {
+        self.store.insert(part)
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+        let touched = self.neighbourhood(id);
+        self.touch(&touched);
+        self.store.remove(id)
+    }
+
+    fn parts(&self) -> Result<Vec<Part>, PartsListError> {
+        self.store.parts()
+    }
+
+    fn update(
+        &mut self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<(), PartsListError> {
+        // replacing children also touches the children being dropped
+        let mut touched = self.neighbourhood(id);
+        touched.extend(children.iter().map(|child| **child));
+        self.touch(&touched);
+        self.store.update(id, children, op)
+    }
+
+    fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError> {
+        self.touch(&[*id]);
+        self.store.edit(id, edit)
+    }
+
+    fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<Relationship, PartsListError> {
+        let parent = self.store.get_relationship(id)?.parent;
+        self.touch(&[parent]);
+        self.store.edit_relationship(id, edit)
+    }
+
+    fn get_relationship(&self, id: &Uuid) -> Result<Relationship, PartsListError> {
+        self.store.get_relationship(id)
+    }
+
+    fn check_version(&self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        self.store.check_version(id, expected)
+    }
+
+    fn expect_version(&mut self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        self.store.expect_version(id, expected)
+    }
+
+    fn end_write(&mut self) {
+        self.store.end_write()
+    }
+
+    fn get_many(&self, ids: &[Uuid]) -> Result<(Vec<Part>, Vec<Uuid>), PartsListError> {
+        self.store.get_many(ids)
+    }
+
+    fn get_by_name(&self, name: &str) -> Result<Vec<Part>, PartsListError> {
+        self.store.get_by_name(name)
+    }
+
+    fn get_by_external(&self, system: &str, external_id: &str) -> Result<Part, PartsListError> {
+        self.store.get_by_external(system, external_id)
+    }
+
+    fn search(&self, query: &str, fuzzy: bool) -> Result<Vec<(Part, f64)>, PartsListError> {
+        self.store.search(query, fuzzy)
+    }
+
+    fn snapshot(&self) -> Result<PartsList, PartsListError> {
+        self.store.snapshot()
+    }
+
+    fn list(&self, filter: PartsListFilter) -> Result<Vec<Part>, PartsListError> {
+        self.store.list(filter)
+    }
+
+    fn get_children(
+        &self,
+        id: &Uuid,
+        filter: PartsListFilter,
+    ) -> Result<Vec<Part>, PartsListError> {
+        // ancestors found through the filter kept for compatibility depend on
+        // parts above this one, which don't bump its version
+        if let PartsListFilter::Assembly = filter {
+            return self.store.get_children(id, filter);
+        }
+        let version = self.version(id);
+        let key = (*id, filter);
+        if let Ok(cache) = self.cache.lock() {
+            if let Some(entry) = cache.get(&key) {
+                if entry.version == version {
+                    perf::record_cache(true);
+                    return Ok(entry.parts.clone());
+                }
+            }
+        }
+        perf::record_cache(false);
+        let parts = self.store.get_children(id, filter)?;
+        if let Ok(mut cache) = self.cache.lock() {
+            let entry = CacheEntry {
+                version,
+                parts: parts.clone(),
+            };
+            cache.insert(key, entry);
+        }
+        Ok(parts)
+    }
+
+    fn get_ancestors(&self, id: &Uuid, depth: Option<usize>) -> Result<Vec<Part>, PartsListError> {
+        self.store.get_ancestors(id, depth)
+    }
+
+    fn stats(&self) -> Result<PartsListStats, PartsListError> {
+        self.store.stats()
+    }
+
+    fn top_used(&self, limit: usize) -> Result<Vec<(Part, usize)>, PartsListError> {
+        self.store.top_used(limit)
+    }
+
+    fn validate(&self) -> Result<ValidationReport, PartsListError> {
+        self.store.validate()
+    }
+
+    fn delete_tree_candidates(&self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        self.store.delete_tree_candidates(id)
+    }
+
+    fn delete_tree(&mut self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        let mut touched = Vec::new();
+        for part in self.store.delete_tree_candidates(id)? {
+            touched.extend(self.neighbourhood(&part.id));
+        }
+        self.touch(&touched);
+        self.store.delete_tree(id)
+    }
+
+    fn remove_orphans(&mut self) -> Result<Vec<Part>, PartsListError> {
+        // orphans aren't part of any subtree, so no cached query is affected
+        self.store.remove_orphans()
+    }
+
+    fn name_policy(&self) -> DuplicateNamePolicy {
+        self.store.name_policy()
+    }
+
+    fn replace_all(&mut self, parts: Vec<Part>) -> Result<(), PartsListError> {
+        self.store.replace_all(parts)?;
+        // every part may have changed
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    fn purged(&mut self, deleted: &[Uuid]) -> Vec<Uuid> {
+        self.store.purged(deleted)
+    }
+
+    fn restore(&mut self, id: &Uuid) -> Result<Part, PartsListError> {
+        self.store.restore(id)?;
+        let touched = self.neighbourhood(id);
+        self.touch(&touched);
+        self.store.get(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_explosion_invalidated_by_changes() {
+        let mut store = CachedStore::new(Box::new(PartsList::new()));
+        let assy = store.insert(Part::new("assy")).unwrap();
+        let subassy = store.insert(Part::new("subassy")).unwrap();
+        let screw = store.insert(Part::new("screw")).unwrap();
+        let other = store.insert(Part::new("other assy")).unwrap();
+        store
+            .update(&assy.id, &[&subassy.id], PartsListUpdate::Add)
+            .unwrap();
+
+        let children = store.get_children(&assy.id, PartsListFilter::All).unwrap();
+        assert_eq!(children, vec![subassy.clone()]);
+        // repeated query is served from the cache
+        let children = store.get_children(&assy.id, PartsListFilter::All).unwrap();
+        assert_eq!(children, vec![subassy.clone()]);
+
+        // change deeper in the tree invalidates the cached explosion
+        store
+            .update(&subassy.id, &[&screw.id], PartsListUpdate::Add)
+            .unwrap();
+        let mut children = store.get_children(&assy.id, PartsListFilter::All).unwrap();
+        children.sort();
+        let mut expected = vec![subassy.clone(), screw.clone()];
+        expected.sort();
+        assert_eq!(children, expected);
+        let components = store
+            .get_children(&assy.id, PartsListFilter::Component)
+            .unwrap();
+        assert_eq!(components[0].parents.len(), 1);
+
+        // sharing a part with another assembly updates its cached parents
+        store
+            .update(&other.id, &[&screw.id], PartsListUpdate::Add)
+            .unwrap();
+        let components = store
+            .get_children(&assy.id, PartsListFilter::Component)
+            .unwrap();
+        assert_eq!(components[0].parents.len(), 2);
+
+        store.remove(&screw.id).unwrap();
+        let children = store.get_children(&assy.id, PartsListFilter::All).unwrap();
+        assert_eq!(children, vec![subassy.clone()]);
+
+        // restoring a backup replaces every part, even those keeping their ids
+        let mut restored = vec![assy.clone(), subassy.clone()];
+        restored[0].children.clear();
+        restored[1].parents.clear();
+        store.replace_all(restored).unwrap();
+        assert!(store
+            .get_children(&assy.id, PartsListFilter::All)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,1183 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+
+use serde::de::DeserializeOwned;
+use sqlx::postgres::{PgConnection, PgPool, PgPoolOptions};
+use sqlx::{Postgres, Transaction};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+use crate::cost;
+use crate::parts_list::{
+    DuplicateNamePolicy, Part, PartEdit, PartsListError, PartsListFilter, PartsListUpdate,
+    Relationship, RelationshipEdit,
+};
+use crate::release::Lifecycle;
+use crate::routing;
+use crate::store::PartsStore;
+use crate::supplier::{self, Supplier};
+use crate::variants;
+
+impl From<sqlx::Error> for PartsListError {
+    fn from(e: sqlx::Error) -> Self {
+        PartsListError::Storage { s: e.to_string() }
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for PartsListError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        PartsListError::Storage { s: e.to_string() }
+    }
+}
+
+/// Id, name, creation time, version, stock, location, phantom flag, JSON
+/// encoded options and child options, category, JSON encoded attributes, lead
+/// time, JSON encoded routing, work instructions, unit cost and JSON encoded
+/// cost history of a row in the parts table
+type PartRow = (
+    Uuid,
+    String,
+    i64,
+    i64,
+    i64,
+    String,
+    bool,
+    String,
+    String,
+    Option<Uuid>,
+    String,
+    i64,
+    String,
+    String,
+    f64,
+    String,
+);
+
+/// Id, parent and child ids, quantity and notes of a row in the edges table
+type Edge = (Uuid, Uuid, Uuid, i64, String);
+
+/// Part id, system and id within that system of a row in the external_ids
+/// table
+type ExternalIdRow = (Uuid, String, String);
+
+/// Part id, preference, name, unit cost, JSON encoded price breaks, lead time,
+/// currency and manufacturer part number of a row in the part_suppliers table
+type SupplierRow = (
+    Uuid,
+    i32,
+    String,
+    f64,
+    String,
+    i64,
+    Option<String>,
+    Option<String>,
+);
+
+/// Id, currency and lifecycle state of a part costed in a currency other than
+/// the base currency or no longer in work, kept out of `PartRow` which holds as
+/// many columns as sqlx can decode into a tuple
+type ExtraRow = (Uuid, Option<String>, String);
+
+/// Parts with columns decoded from an `ExtraRow` rather than their `PartRow`
+const EXTRA_CONDITION: &str = "(currency IS NOT NULL OR lifecycle <> 'in_work')";
+
+/// Set the currency and lifecycle state of a part from its `ExtraRow`
+fn apply_extra(
+    part: &mut Part,
+    currency: Option<String>,
+    lifecycle: &str,
+) -> Result<(), PartsListError> {
+    part.currency = currency;
+    part.lifecycle = Lifecycle::try_from(lifecycle)?;
+    Ok(())
+}
+
+/// Decode a JSON encoded `column` of part `id`, failing rather than dropping
+/// what the store holds when it can't be read
+fn decode<T: DeserializeOwned>(id: &Uuid, column: &str, value: &str) -> Result<T, PartsListError> {
+    serde_json::from_str(value).map_err(|e| PartsListError::Storage {
+        s: format!("Couldn't decode {} of part {}: {}", column, id, e),
+    })
+}
+
+fn part_from_row(
+    (
+        id,
+        name,
+        created,
+        version,
+        on_hand,
+        location,
+        phantom,
+        options,
+        child_options,
+        category,
+        attributes,
+        lead_time_days,
+        routing,
+        instructions,
+        unit_cost,
+        cost_history,
+    ): PartRow,
+) -> Result<Part, PartsListError> {
+    Ok(Part {
+        id,
+        name,
+        parents: HashSet::new(),
+        children: HashSet::new(),
+        created: created as u64,
+        version: version as u64,
+        external_ids: BTreeMap::new(),
+        on_hand: on_hand as u64,
+        location,
+        phantom,
+        options: decode(&id, "options", &options)?,
+        child_options: decode(&id, "child_options", &child_options)?,
+        relationships: BTreeMap::new(),
+        category,
+        attributes: decode(&id, "attributes", &attributes)?,
+        lead_time_days: lead_time_days as u64,
+        routing: decode(&id, "routing", &routing)?,
+        instructions,
+        unit_cost,
+        cost_history: decode(&id, "cost_history", &cost_history)?,
+        suppliers: Vec::new(),
+        currency: None,
+        lifecycle: Lifecycle::InWork,
+    })
+}
+
+fn relationship_from_edge((id, parent, child, quantity, notes): Edge) -> Relationship {
+    Relationship {
+        id,
+        parent,
+        child,
+        quantity: quantity as u64,
+        notes,
+    }
+}
+
+const DESCENDANTS_QUERY: &str = r#"
+WITH RECURSIVE descendants (id) AS (
+    SELECT child FROM edges WHERE parent = $1
+    UNION
+    SELECT e.child FROM edges e JOIN descendants d ON e.parent = d.id
+)
+SELECT id FROM descendants
+"#;
+
+/// Ancestors of a part up to a number of levels above it
+const ANCESTORS_TO_DEPTH_QUERY: &str = r#"
+WITH RECURSIVE ancestors (id, depth) AS (
+    SELECT parent, 1 FROM edges WHERE child = $1
+    UNION
+    SELECT e.parent, a.depth + 1 FROM edges e JOIN ancestors a ON e.child = a.id
+    WHERE a.depth < $2
+)
+SELECT DISTINCT id FROM ancestors
+"#;
+
+const ANCESTORS_QUERY: &str = r#"
+WITH RECURSIVE ancestors (id) AS (
+    SELECT parent FROM edges WHERE child = $1
+    UNION
+    SELECT e.parent FROM edges e JOIN ancestors a ON e.child = a.id
+)
+SELECT id FROM ancestors
+"#;
+
+/// Shortest path of parts from `$1` down to `$2`, each a parent of the next
+const DESCENT_PATH_QUERY: &str = r#"
+WITH RECURSIVE descent (id, path) AS (
+    SELECT $1::uuid, ARRAY[$1::uuid]
+    UNION ALL
+    SELECT e.child, d.path || e.child FROM edges e JOIN descent d ON e.parent = d.id
+    WHERE NOT e.child = ANY(d.path)
+)
+SELECT path FROM descent WHERE id = $2 ORDER BY array_length(path, 1) LIMIT 1
+"#;
+
+/// Parts store persisted in PostgreSQL, with parts and the parent/child
+/// relationships between them kept in separate `parts` and `edges` tables
+///
+/// Rocket handlers are synchronous, so queries are driven to completion on a
+/// runtime owned by the store.
+///
+/// Part names are unique in the `parts` table, so duplicate names can be
+/// rejected or suffixed but not allowed. External ids and suppliers are kept in
+/// the separate `external_ids` and `part_suppliers` tables.
+///
+/// The versions of parts expected by a write are checked again in the
+/// transaction of each change, with their rows locked until it commits, so they
+/// are compared and changed atomically.
+pub struct PgStore {
+    runtime: Runtime,
+    pool: PgPool,
+    policy: DuplicateNamePolicy,
+    /// Version of each part the changes of the current write expect
+    expected: Vec<(Uuid, u64)>,
+}
+
+impl PgStore {
+    /// Connect to the database at `url`, applying any pending migrations
+    pub fn connect(url: &str, policy: DuplicateNamePolicy) -> Result<PgStore, PartsListError> {
+        if let DuplicateNamePolicy::Allow = policy {
+            return Err(PartsListError::Storage {
+                s: "Duplicate part names can't be allowed with postgres storage".into(),
+            });
+        }
+        let runtime = Runtime::new()?;
+        let pool = runtime.block_on(async {
+            let pool = PgPoolOptions::new().max_connections(8).connect(url).await?;
+            sqlx::migrate!().run(&pool).await?;
+            Ok::<_, PartsListError>(pool)
+        })?;
+        Ok(PgStore {
+            runtime,
+            pool,
+            policy,
+            expected: Vec::new(),
+        })
+    }
+
+    /// Load parts along with all of their relationships, external ids and
+    /// suppliers, or every part when no ids are supplied
+    async fn fetch_parts(&self, ids: Option<&[Uuid]>) -> Result<Vec<Part>, PartsListError> {
+        PgStore::load_parts(&mut self.pool.acquire().await?, ids).await
+    }
+
+    /// Load parts as `fetch_parts` does over a connection, reading them as
+    /// seen by the transaction open on it if any
+    async fn load_parts(
+        con: &mut PgConnection,
+        ids: Option<&[Uuid]>,
+    ) -> Result<Vec<Part>, PartsListError> {
+        #[allow(clippy::type_complexity)]
+        let (rows, edges, external_ids, suppliers, extras): (
+            Vec<PartRow>,
+            Vec<Edge>,
+            Vec<ExternalIdRow>,
+            Vec<SupplierRow>,
+            Vec<ExtraRow>,
+        ) = match ids {
+            Some(ids) => (
+                sqlx::query_as(
+                    "SELECT id, name, created, version, on_hand, location, phantom, options, child_options, category, attributes, lead_time_days, routing, instructions, unit_cost, cost_history FROM parts WHERE id = ANY($1)",
+                )
+                .bind(ids)
+                .fetch_all(&mut *con)
+                .await?,
+                sqlx::query_as(
+                    "SELECT id, parent, child, quantity, notes FROM edges \
+                     WHERE parent = ANY($1) OR child = ANY($1)",
+                )
+                .bind(ids)
+                .fetch_all(&mut *con)
+                .await?,
+                sqlx::query_as(
+                    "SELECT part, system, external_id FROM external_ids WHERE part = ANY($1)",
+                )
+                .bind(ids)
+                .fetch_all(&mut *con)
+                .await?,
+                sqlx::query_as(
+                    "SELECT part, preference, name, unit_cost, price_breaks, lead_time_days, currency, mpn FROM part_suppliers \
+                     WHERE part = ANY($1) ORDER BY part, preference",
+                )
+                .bind(ids)
+                .fetch_all(&mut *con)
+                .await?,
+                sqlx::query_as(&format!(
+                    "SELECT id, currency, lifecycle FROM parts WHERE id = ANY($1) AND {}",
+                    EXTRA_CONDITION
+                ))
+                .bind(ids)
+                .fetch_all(&mut *con)
+                .await?,
+            ),
+            None => (
+                sqlx::query_as("SELECT id, name, created, version, on_hand, location, phantom, options, child_options, category, attributes, lead_time_days, routing, instructions, unit_cost, cost_history FROM parts")
+                    .fetch_all(&mut *con)
+                    .await?,
+                sqlx::query_as("SELECT id, parent, child, quantity, notes FROM edges")
+                    .fetch_all(&mut *con)
+                    .await?,
+                sqlx::query_as("SELECT part, system, external_id FROM external_ids")
+                    .fetch_all(&mut *con)
+                    .await?,
+                sqlx::query_as(
+                    "SELECT part, preference, name, unit_cost, price_breaks, lead_time_days, currency, mpn FROM part_suppliers \
+                     ORDER BY part, preference",
+                )
+                .fetch_all(&mut *con)
+                .await?,
+                sqlx::query_as(&format!(
+                    "SELECT id, currency, lifecycle FROM parts WHERE {}",
+                    EXTRA_CONDITION
+                ))
+                .fetch_all(&mut *con)
+                .await?,
+            ),
+        };
+        let mut parts: HashMap<Uuid, Part> = rows
+            .into_iter()
+            .map(|row| Ok((row.0, part_from_row(row)?)))
+            .collect::<Result<_, PartsListError>>()?;
+        for edge in edges {
+            let (parent, child) = (edge.1, edge.2);
+            if let Some(part) = parts.get_mut(&parent) {
+                part.children.insert(child);
+                part.relationships
+                    .insert(child, relationship_from_edge(edge));
+            }
+            if let Some(part) = parts.get_mut(&child) {
+                part.parents.insert(parent);
+            }
+        }
+        for (id, system, external_id) in external_ids {
+            if let Some(part) = parts.get_mut(&id) {
+                part.external_ids.insert(system, external_id);
+            }
+        }
+        for (id, _, name, unit_cost, price_breaks, lead_time_days, currency, mpn) in suppliers {
+            if let Some(part) = parts.get_mut(&id) {
+                part.suppliers.push(Supplier {
+                    name,
+                    unit_cost,
+                    price_breaks: decode(&id, "supplier price_breaks", &price_breaks)?,
+                    lead_time_days: lead_time_days as u64,
+                    currency,
+                    mpn,
+                });
+            }
+        }
+        for (id, currency, lifecycle) in extras {
+            if let Some(part) = parts.get_mut(&id) {
+                apply_extra(part, currency, &lifecycle)?;
+            }
+        }
+        Ok(parts.into_values().collect())
+    }
+
+    async fn fetch_ids(&self, query: &str, id: Option<&Uuid>) -> Result<Vec<Uuid>, PartsListError> {
+        let query = sqlx::query_as::<_, (Uuid,)>(query);
+        let query = match id {
+            Some(id) => query.bind(id),
+            None => query,
+        };
+        let rows = query.fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    async fn exists(tx: &mut Transaction<'_, Postgres>, id: &Uuid) -> Result<bool, PartsListError> {
+        let row: Option<(Uuid,)> = sqlx::query_as("SELECT id FROM parts WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(tx)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    /// Fail unless every part expected by the current write is still at the
+    /// version it expects, locking them until the transaction ends
+    async fn check_expected(
+        tx: &mut Transaction<'_, Postgres>,
+        expected: &[(Uuid, u64)],
+    ) -> Result<(), PartsListError> {
+        let versions = PgStore::lock_versions(tx, expected).await?;
+        for (id, expected) in expected {
+            match versions.get(id) {
+                None => return Err(PartsListError::PartDoesNotExist { id: *id }),
+                Some(actual) if actual != expected => {
+                    return Err(PartsListError::VersionConflict {
+                        id: *id,
+                        expected: *expected,
+                        actual: *actual,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Versions the parts expected by the current write are left at by the
+    /// transaction, which the rest of the write expects instead
+    async fn made_versions(
+        tx: &mut Transaction<'_, Postgres>,
+        expected: &[(Uuid, u64)],
+    ) -> Result<Vec<(Uuid, u64)>, PartsListError> {
+        let versions = PgStore::lock_versions(tx, expected).await?;
+        Ok(expected
+            .iter()
+            .filter_map(|(id, _)| versions.get(id).map(|version| (*id, *version)))
+            .collect())
+    }
+
+    async fn lock_versions(
+        tx: &mut Transaction<'_, Postgres>,
+        expected: &[(Uuid, u64)],
+    ) -> Result<HashMap<Uuid, u64>, PartsListError> {
+        if expected.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let ids: Vec<Uuid> = expected.iter().map(|(id, _)| *id).collect();
+        let rows: Vec<(Uuid, i64)> =
+            sqlx::query_as("SELECT id, version FROM parts WHERE id = ANY($1) FOR UPDATE")
+                .bind(&ids)
+                .fetch_all(tx)
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(id, version)| (id, version as u64))
+            .collect())
+    }
+
+    /// Choose a new name for part `id` by the duplicate name policy, from the
+    /// names which could clash read in the transaction
+    async fn resolve_name(
+        tx: &mut Transaction<'_, Postgres>,
+        policy: DuplicateNamePolicy,
+        name: &str,
+        id: &Uuid,
+    ) -> Result<String, PartsListError> {
+        // suffixed names start with the name they are made from
+        let rows: Vec<(String, Uuid)> = sqlx::query_as(
+            "SELECT name, id FROM parts WHERE left(name, char_length($1)) = $1 AND id <> $2",
+        )
+        .bind(name)
+        .bind(id)
+        .fetch_all(tx)
+        .await?;
+        let taken: HashMap<String, Uuid> = rows.into_iter().collect();
+        policy.resolve(name, |name| Ok(taken.get(name).copied()))
+    }
+
+    /// Increment the versions of parts which changed
+    async fn bump_versions(
+        tx: &mut Transaction<'_, Postgres>,
+        ids: &[Uuid],
+    ) -> Result<(), PartsListError> {
+        sqlx::query("UPDATE parts SET version = version + 1 WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Replace the suppliers of a part, keeping the order they are preferred in
+    async fn set_suppliers(
+        tx: &mut Transaction<'_, Postgres>,
+        id: &Uuid,
+        suppliers: &[Supplier],
+    ) -> Result<(), PartsListError> {
+        sqlx::query("DELETE FROM part_suppliers WHERE part = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        for (preference, supplier) in suppliers.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO part_suppliers (part, preference, name, unit_cost, price_breaks, lead_time_days, currency, mpn) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            )
+            .bind(id)
+            .bind(preference as i32)
+            .bind(&supplier.name)
+            .bind(supplier.unit_cost)
+            .bind(serde_json::to_string(&supplier.price_breaks)?)
+            .bind(supplier.lead_time_days as i64)
+            .bind(&supplier.currency)
+            .bind(&supplier.mpn)
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the external ids of a part, failing if any of them already
+    /// identifies another part
+    async fn set_external_ids(
+        tx: &mut Transaction<'_, Postgres>,
+        id: &Uuid,
+        external_ids: &BTreeMap<String, String>,
+    ) -> Result<(), PartsListError> {
+        sqlx::query("DELETE FROM external_ids WHERE part = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        for (system, external_id) in external_ids {
+            let result = sqlx::query(
+                "INSERT INTO external_ids (part, system, external_id) VALUES ($1, $2, $3) \
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(id)
+            .bind(system)
+            .bind(external_id)
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() == 0 {
+                let (existing,): (Uuid,) = sqlx::query_as(
+                    "SELECT part FROM external_ids WHERE system = $1 AND external_id = $2",
+                )
+                .bind(system)
+                .bind(external_id)
+                .fetch_one(&mut *tx)
+                .await?;
+                return Err(PartsListError::ExternalIdInUse {
+                    system: system.clone(),
+                    external_id: external_id.clone(),
+                    id: existing,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn add_children(
+        tx: &mut Transaction<'_, Postgres>,
+        parent: &Uuid,
+        children: &[&Uuid],
+    ) -> Result<(), PartsListError> {
+        for child in children {
+            if !PgStore::exists(tx, child).await? {
+                return Err(PartsListError::PartDoesNotExist { id: **child });
+            }
+            // can't add itself or one of its ancestors as a child
+            let (cyclical,): (bool,) =
+                sqlx::query_as(&format!("SELECT $2 = $1 OR $2 IN ({})", ANCESTORS_QUERY))
+                    .bind(parent)
+                    .bind(*child)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if cyclical {
+                let path: Option<(Vec<Uuid>,)> = sqlx::query_as(DESCENT_PATH_QUERY)
+                    .bind(*child)
+                    .bind(parent)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                return Err(PartsListError::AddChildCyclicalRelative {
+                    parent: *parent,
+                    child: **child,
+                    path: path.map(|(path,)| path).unwrap_or_default(),
+                });
+            }
+            let result = sqlx::query(
+                "INSERT INTO edges (id, parent, child) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            )
+            .bind(Relationship::id_for(parent, child))
+            .bind(parent)
+            .bind(*child)
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() > 0 {
+                PgStore::bump_versions(tx, &[*parent, **child]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn update_children(
+        &self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<Vec<(Uuid, u64)>, PartsListError> {
+        let mut tx = self.pool.begin().await?;
+        // Serialize relationship changes so concurrent updates can't form a cycle
+        sqlx::query("LOCK TABLE edges IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut tx)
+            .await?;
+        PgStore::check_expected(&mut tx, &self.expected).await?;
+        if !PgStore::exists(&mut tx, id).await? {
+            return Err(PartsListError::PartDoesNotExist { id: *id });
+        }
+        match op {
+            PartsListUpdate::Add => PgStore::add_children(&mut tx, id, children).await?,
+            PartsListUpdate::Remove => {
+                for child in children {
+                    if !PgStore::exists(&mut tx, child).await? {
+                        return Err(PartsListError::PartDoesNotExist { id: **child });
+                    }
+                    let result = sqlx::query("DELETE FROM edges WHERE parent = $1 AND child = $2")
+                        .bind(id)
+                        .bind(*child)
+                        .execute(&mut tx)
+                        .await?;
+                    if result.rows_affected() > 0 {
+                        PgStore::bump_versions(&mut tx, &[*id, **child]).await?;
+                    }
+                }
+            }
+            PartsListUpdate::Replace => {
+                // the edges of the children kept are left alone, keeping their
+                // quantities and notes
+                let kept: Vec<Uuid> = children.iter().map(|child| **child).collect();
+                let removed: Vec<(Uuid,)> = sqlx::query_as(
+                    "DELETE FROM edges WHERE parent = $1 AND NOT child = ANY($2) RETURNING child",
+                )
+                .bind(id)
+                .bind(&kept)
+                .fetch_all(&mut tx)
+                .await?;
+                let mut unlinked: Vec<Uuid> = removed.into_iter().map(|(child,)| child).collect();
+                if !unlinked.is_empty() {
+                    unlinked.push(*id);
+                    PgStore::bump_versions(&mut tx, &unlinked).await?;
+                }
+                PgStore::add_children(&mut tx, id, children).await?;
+            }
+        }
+        let made = PgStore::made_versions(&mut tx, &self.expected).await?;
+        tx.commit().await?;
+        Ok(made)
+    }
+}
+
+impl PartsStore for PgStore {
+    fn get(&self, id: &Uuid) -> Result<Part, PartsListError> {
+        self.runtime
+            .block_on(self.fetch_parts(Some(&[*id])))?
+            .pop()
+            .ok_or(PartsListError::PartDoesNotExist { id: *id })
+    }
+
+    fn name_policy(&self) -> DuplicateNamePolicy {
+        self.policy
+    }
+
+    fn expect_version(&mut self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        self.check_version(id, expected)?;
+        self.expected.push((*id, expected));
+        Ok(())
+    }
+
+    fn end_write(&mut self) {
+        self.expected.clear();
+    }
+
+    fn get_many(&self, ids: &[Uuid]) -> Result<(Vec<Part>, Vec<Uuid>), PartsListError> {
+        let found = self.runtime.block_on(self.fetch_parts(Some(ids)))?;
+        let found_ids: HashSet<Uuid> = found.iter().map(|part| part.id).collect();
+        let missing = ids
+            .iter()
+            .filter(|id| !found_ids.contains(id))
+            .copied()
+            .collect();
+        Ok((found, missing))
+    }
+
+    fn get_by_name(&self, name: &str) -> Result<Vec<Part>, PartsListError> {
+        self.runtime.block_on(async {
+            let rows: Vec<(Uuid,)> = sqlx::query_as("SELECT id FROM parts WHERE name = $1")
+                .bind(name)
+                .fetch_all(&self.pool)
+                .await?;
+            let ids: Vec<Uuid> = rows.into_iter().map(|(id,)| id).collect();
+            self.fetch_parts(Some(&ids)).await
+        })
+    }
+
+    fn insert(&mut self, mut part: Part) -> Result<Part, PartsListError> {
+        // a name taken between this check and the insert is rejected by the index
+        part.name = self.policy.resolve(&part.name, |name| {
+            let existing: Option<(Uuid,)> = self.runtime.block_on(
+                sqlx::query_as("SELECT id FROM parts WHERE name = $1")
+                    .bind(name)
+                    .fetch_optional(&self.pool),
+            )?;
+            Ok(existing.map(|(id,)| id))
+        })?;
+        self.expected = self.runtime.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            PgStore::check_expected(&mut tx, &self.expected).await?;
+            let result = sqlx::query(
+                "INSERT INTO parts \
+                 (id, name, created, version, on_hand, location, phantom, options, child_options, category, attributes, lead_time_days, routing, instructions, unit_cost, cost_history, currency, lifecycle) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18) ON CONFLICT DO NOTHING",
+            )
+            .bind(part.id)
+            .bind(&part.name)
+            .bind(part.created as i64)
+            .bind(part.version as i64)
+            .bind(part.on_hand as i64)
+            .bind(&part.location)
+            .bind(part.phantom)
+            .bind(serde_json::to_string(&part.options)?)
+            .bind(serde_json::to_string(&part.child_options)?)
+            .bind(part.category)
+            .bind(serde_json::to_string(&part.attributes)?)
+            .bind(part.lead_time_days as i64)
+            .bind(serde_json::to_string(&part.routing)?)
+            .bind(&part.instructions)
+            .bind(part.unit_cost)
+            .bind(serde_json::to_string(&part.cost_history)?)
+            .bind(&part.currency)
+            .bind(Into::<&str>::into(part.lifecycle))
+            .execute(&mut tx)
+            .await?;
+            if result.rows_affected() == 0 {
+                let existing: Option<(Uuid,)> =
+                    sqlx::query_as("SELECT id FROM parts WHERE name = $1 AND id <> $2")
+                        .bind(&part.name)
+                        .bind(part.id)
+                        .fetch_optional(&mut tx)
+                        .await?;
+                return Err(match existing {
+                    Some((id,)) => PartsListError::PartNameExists {
+                        name: part.name.clone(),
+                        id,
+                    },
+                    None => PartsListError::PartExists {
+                        name: part.name.clone(),
+                        id: part.id,
+                    },
+                });
+            }
+            PgStore::set_external_ids(&mut tx, &part.id, &part.external_ids).await?;
+            PgStore::set_suppliers(&mut tx, &part.id, &part.suppliers).await?;
+            let made = PgStore::made_versions(&mut tx, &self.expected).await?;
+            tx.commit().await?;
+            Ok(made)
+        })?;
+        Ok(Part {
+            parents: HashSet::new(),
+            children: HashSet::new(),
+            ..part
+        })
+    }
+
+    fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError> {
+        let (edited, made) = self.runtime.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            PgStore::check_expected(&mut tx, &self.expected).await?;
+            // the part is edited as read with its row locked, so concurrent
+            // edits of other fields aren't undone
+            if !PgStore::exists(&mut tx, id).await? {
+                return Err(PartsListError::PartDoesNotExist { id: *id });
+            }
+            let mut edited = PgStore::load_parts(&mut tx, Some(&[*id]))
+                .await?
+                .pop()
+                .ok_or(PartsListError::PartDoesNotExist { id: *id })?;
+            let old_name = edited.name.clone();
+            edit.apply(&mut edited);
+            if edited.name != old_name {
+                // a name taken after it is read is rejected by the index
+                edited.name = PgStore::resolve_name(&mut tx, self.policy, &edited.name, id).await?;
+            }
+            variants::check(&edited)?;
+            routing::check(&edited)?;
+            cost::check(&edited)?;
+            supplier::check(&edited)?;
+            if let Some(external_ids) = &edit.external_ids {
+                PgStore::set_external_ids(&mut tx, id, external_ids).await?;
+            }
+            if let Some(suppliers) = &edit.suppliers {
+                PgStore::set_suppliers(&mut tx, id, suppliers).await?;
+            }
+            if edited.name != old_name {
+                sqlx::query("UPDATE parts SET name = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(&edited.name)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(on_hand) = edit.on_hand {
+                sqlx::query("UPDATE parts SET on_hand = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(on_hand as i64)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(location) = &edit.location {
+                sqlx::query("UPDATE parts SET location = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(location)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(phantom) = edit.phantom {
+                sqlx::query("UPDATE parts SET phantom = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(phantom)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(options) = &edit.options {
+                sqlx::query("UPDATE parts SET options = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(serde_json::to_string(options)?)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(category) = edit.category {
+                sqlx::query("UPDATE parts SET category = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(category)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(attributes) = &edit.attributes {
+                sqlx::query("UPDATE parts SET attributes = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(serde_json::to_string(attributes)?)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(lead_time_days) = edit.lead_time_days {
+                sqlx::query("UPDATE parts SET lead_time_days = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(lead_time_days as i64)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(routing) = &edit.routing {
+                sqlx::query("UPDATE parts SET routing = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(serde_json::to_string(routing)?)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(instructions) = &edit.instructions {
+                sqlx::query("UPDATE parts SET instructions = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(instructions)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if edit.unit_cost.is_some() {
+                sqlx::query("UPDATE parts SET unit_cost = $2, cost_history = $3 WHERE id = $1")
+                    .bind(id)
+                    .bind(edited.unit_cost)
+                    .bind(serde_json::to_string(&edited.cost_history)?)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(currency) = &edit.currency {
+                sqlx::query("UPDATE parts SET currency = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(currency)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(child_options) = &edit.child_options {
+                sqlx::query("UPDATE parts SET child_options = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(serde_json::to_string(child_options)?)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            if let Some(lifecycle) = edit.lifecycle {
+                sqlx::query("UPDATE parts SET lifecycle = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(Into::<&str>::into(lifecycle))
+                    .execute(&mut tx)
+                    .await?;
+            }
+            PgStore::bump_versions(&mut tx, &[*id]).await?;
+            let made = PgStore::made_versions(&mut tx, &self.expected).await?;
+            let edited = PgStore::load_parts(&mut tx, Some(&[*id])).await?.pop();
+            tx.commit().await?;
+            Ok((edited, made))
+        })?;
+        self.expected = made;
+        edited.ok_or(PartsListError::PartDoesNotExist { id: *id })
+    }
+
+    fn get_relationship(&self, id: &Uuid) -> Result<Relationship, PartsListError> {
+        let edge: Option<Edge> = self.runtime.block_on(
+            sqlx::query_as("SELECT id, parent, child, quantity, notes FROM edges WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&self.pool),
+        )?;
+        edge.map(relationship_from_edge)
+            .ok_or(PartsListError::RelationshipDoesNotExist { id: *id })
+    }
+
+    fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<Relationship, PartsListError> {
+        // only the given fields are set, keeping concurrent edits of the others
+        edit.apply(&mut self.get_relationship(id)?)?;
+        let (edited, made) = self.runtime.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            PgStore::check_expected(&mut tx, &self.expected).await?;
+            let edge: Option<Edge> = sqlx::query_as(
+                "UPDATE edges SET quantity = COALESCE($2, quantity), notes = COALESCE($3, notes) \
+                 WHERE id = $1 RETURNING id, parent, child, quantity, notes",
+            )
+            .bind(id)
+            .bind(edit.quantity.map(|quantity| quantity as i64))
+            .bind(&edit.notes)
+            .fetch_optional(&mut tx)
+            .await?;
+            let edited = match edge {
+                Some(edge) => relationship_from_edge(edge),
+                None => return Err(PartsListError::RelationshipDoesNotExist { id: *id }),
+            };
+            PgStore::bump_versions(&mut tx, &[edited.parent]).await?;
+            let made = PgStore::made_versions(&mut tx, &self.expected).await?;
+            tx.commit().await?;
+            Ok((edited, made))
+        })?;
+        self.expected = made;
+        Ok(edited)
+    }
+
+    fn get_by_external(&self, system: &str, external_id: &str) -> Result<Part, PartsListError> {
+        self.runtime
+            .block_on(async {
+                let rows: Vec<(Uuid,)> = sqlx::query_as(
+                    "SELECT part FROM external_ids WHERE system = $1 AND external_id = $2",
+                )
+                .bind(system)
+                .bind(external_id)
+                .fetch_all(&self.pool)
+                .await?;
+                let ids: Vec<Uuid> = rows.into_iter().map(|(id,)| id).collect();
+                self.fetch_parts(Some(&ids)).await
+            })?
+            .pop()
+            .ok_or_else(|| PartsListError::ExternalIdDoesNotExist {
+                system: system.into(),
+                external_id: external_id.into(),
+            })
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+        // Relationships are removed along with the part by the edges foreign keys
+        self.expected = self.runtime.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            PgStore::check_expected(&mut tx, &self.expected).await?;
+            sqlx::query(
+                "UPDATE parts SET version = version + 1 WHERE id IN \
+                 (SELECT parent FROM edges WHERE child = $1 \
+                 UNION SELECT child FROM edges WHERE parent = $1)",
+            )
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+            let result = sqlx::query("DELETE FROM parts WHERE id = $1")
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+            if result.rows_affected() == 0 {
+                return Err(PartsListError::PartDoesNotExist { id: *id });
+            }
+            let made = PgStore::made_versions(&mut tx, &self.expected).await?;
+            tx.commit().await?;
+            Ok(made)
+        })?;
+        Ok(())
+    }
+
+    fn remove_orphans(&mut self) -> Result<Vec<Part>, PartsListError> {
+        let (rows, extras, made): (Vec<PartRow>, Vec<ExtraRow>, _) = self.runtime.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            PgStore::check_expected(&mut tx, &self.expected).await?;
+            let extras = sqlx::query_as(&format!(
+                "SELECT id, currency, lifecycle FROM parts WHERE id NOT IN (SELECT child FROM edges) \
+                 AND id NOT IN (SELECT parent FROM edges) AND {} FOR UPDATE",
+                EXTRA_CONDITION
+            ))
+            .fetch_all(&mut tx)
+            .await?;
+            let rows = sqlx::query_as(
+                "DELETE FROM parts WHERE id NOT IN (SELECT child FROM edges) \
+                 AND id NOT IN (SELECT parent FROM edges) \
+                 RETURNING id, name, created, version, on_hand, location, phantom, options, child_options, category, attributes, lead_time_days, routing, instructions, unit_cost, cost_history",
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            let made = PgStore::made_versions(&mut tx, &self.expected).await?;
+            tx.commit().await?;
+            Ok::<_, PartsListError>((rows, extras, made))
+        })?;
+        self.expected = made;
+        let extras: HashMap<Uuid, (Option<String>, String)> = extras
+            .into_iter()
+            .map(|(id, currency, lifecycle)| (id, (currency, lifecycle)))
+            .collect();
+        rows.into_iter()
+            .map(|row| {
+                let mut part = part_from_row(row)?;
+                if let Some((currency, lifecycle)) = extras.get(&part.id) {
+                    apply_extra(&mut part, currency.clone(), lifecycle)?;
+                }
+                Ok(part)
+            })
+            .collect()
+    }
+
+    fn parts(&self) -> Result<Vec<Part>, PartsListError> {
+        self.runtime.block_on(self.fetch_parts(None))
+    }
+
+    fn update(
+        &mut self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<(), PartsListError> {
+        self.expected = self
+            .runtime
+            .block_on(self.update_children(id, children, op))?;
+        Ok(())
+    }
+
+    fn list(&self, filter: PartsListFilter) -> Result<Vec<Part>, PartsListError> {
+        let query = match filter {
+            PartsListFilter::All => return self.parts(),
+            PartsListFilter::TopLevel => {
+                "SELECT id FROM parts WHERE id NOT IN (SELECT child FROM edges)"
+            }
+            PartsListFilter::Assembly => "SELECT DISTINCT parent FROM edges",
+            PartsListFilter::Component => {
+                "SELECT DISTINCT child FROM edges WHERE child NOT IN (SELECT parent FROM edges)"
+            }
+            PartsListFilter::Subassembly => {
+                "SELECT DISTINCT child FROM edges WHERE child IN (SELECT parent FROM edges)"
+            }
+            PartsListFilter::Orphan => {
+                "SELECT id FROM parts WHERE id NOT IN (SELECT child FROM edges) \
+                 AND id NOT IN (SELECT parent FROM edges)"
+            }
+        };
+        self.runtime.block_on(async {
+            let ids = self.fetch_ids(query, None).await?;
+            self.fetch_parts(Some(&ids)).await
+        })
+    }
+
+    fn get_ancestors(&self, id: &Uuid, depth: Option<usize>) -> Result<Vec<Part>, PartsListError> {
+        self.runtime.block_on(async {
+            if self.fetch_parts(Some(&[*id])).await?.is_empty() {
+                return Err(PartsListError::PartDoesNotExist { id: *id });
+            }
+            let ids = match depth {
+                None => self.fetch_ids(ANCESTORS_QUERY, Some(id)).await?,
+                Some(0) => Vec::new(),
+                Some(depth) => {
+                    let rows: Vec<(Uuid,)> = sqlx::query_as(ANCESTORS_TO_DEPTH_QUERY)
+                        .bind(id)
+                        .bind(depth as i64)
+                        .fetch_all(&self.pool)
+                        .await?;
+                    rows.into_iter().map(|(id,)| id).collect()
+                }
+            };
+            self.fetch_parts(Some(&ids)).await
+        })
+    }
+
+    fn get_children(
+        &self,
+        id: &Uuid,
+        filter: PartsListFilter,
+    ) -> Result<Vec<Part>, PartsListError> {
+        let query = match filter {
+            PartsListFilter::All => DESCENDANTS_QUERY.to_string(),
+            PartsListFilter::TopLevel => "SELECT child FROM edges WHERE parent = $1".to_string(),
+            PartsListFilter::Component => format!(
+                "SELECT id FROM ({}) d WHERE id NOT IN (SELECT parent FROM edges)",
+                DESCENDANTS_QUERY
+            ),
+            PartsListFilter::Subassembly => format!(
+                "SELECT id FROM ({}) d WHERE id IN (SELECT parent FROM edges)",
+                DESCENDANTS_QUERY
+            ),
+            PartsListFilter::Assembly => ANCESTORS_QUERY.to_string(),
+            _ => {
+                return Err(PartsListError::InvalidFilterChoice {
+                    s: "get_children".into(),
+                    f: String::from(Into::<&str>::into(filter)),
+                })
+            }
+        };
+        self.runtime.block_on(async {
+            if self.fetch_parts(Some(&[*id])).await?.is_empty() {
+                return Err(PartsListError::PartDoesNotExist { id: *id });
+            }
+            let ids = self.fetch_ids(&query, Some(id)).await?;
+            self.fetch_parts(Some(&ids)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Store connected to the database given by `BOM_TEST_POSTGRES_URL`, these
+    /// tests being skipped when it isn't set
+    fn connect() -> Option<PgStore> {
+        let url = std::env::var("BOM_TEST_POSTGRES_URL").ok()?;
+        Some(PgStore::connect(&url, DuplicateNamePolicy::Reject).unwrap())
+    }
+
+    #[test]
+    fn checks_expected_versions_when_changing() {
+        let (mut store, mut other) = match (connect(), connect()) {
+            (Some(store), Some(other)) => (store, other),
+            _ => return,
+        };
+        let part = store
+            .insert(Part::new(&format!("bracket {}", Uuid::new_v4())))
+            .unwrap();
+        let locate = PartEdit {
+            location: Some("shelf 4".into()),
+            ..Default::default()
+        };
+
+        // edits made by this write keep matching the versions it expects
+        store.expect_version(&part.id, part.version).unwrap();
+        let edited = store.edit(&part.id, &locate).unwrap();
+        assert_eq!(edited.version, part.version + 1);
+        store.edit(&part.id, &locate).unwrap();
+        store.end_write();
+
+        // while another server editing the part leaves the expected version stale
+        store.expect_version(&part.id, part.version + 2).unwrap();
+        let restock = PartEdit {
+            on_hand: Some(3),
+            ..Default::default()
+        };
+        other.edit(&part.id, &restock).unwrap();
+        assert_matches!(
+            store.edit(&part.id, &locate),
+            Err(PartsListError::VersionConflict { id, expected, actual })
+                if id == part.id && expected == part.version + 2 && actual == part.version + 3
+        );
+        assert_matches!(
+            store.remove(&part.id),
+            Err(PartsListError::VersionConflict { .. })
+        );
+        store.end_write();
+
+        assert_eq!(store.get(&part.id).unwrap().on_hand, 3);
+        store.remove(&part.id).unwrap();
+    }
+
+    #[test]
+    fn fails_on_columns_it_cant_decode() {
+        let mut store = match connect() {
+            Some(store) => store,
+            None => return,
+        };
+        let part = store
+            .insert(Part::new(&format!("gasket {}", Uuid::new_v4())))
+            .unwrap();
+        store
+            .runtime
+            .block_on(
+                sqlx::query("UPDATE parts SET attributes = 'not json' WHERE id = $1")
+                    .bind(part.id)
+                    .execute(&store.pool),
+            )
+            .unwrap();
+        assert_matches!(store.get(&part.id), Err(PartsListError::Storage { .. }));
+        assert_matches!(store.parts(), Err(PartsListError::Storage { .. }));
+        store.remove(&part.id).unwrap();
+    }
+}
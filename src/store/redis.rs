@@ -0,0 +1,330 @@
+use std::sync::{Mutex, MutexGuard};
+
+use redis::{Commands, Connection, RedisError};
+use uuid::Uuid;
+
+use crate::parts_list::{
+    DuplicateNamePolicy, Part, PartEdit, PartsList, PartsListError, PartsListUpdate, Relationship,
+    RelationshipEdit,
+};
+use crate::store::PartsStore;
+
+impl From<RedisError> for PartsListError {
+    fn from(e: RedisError) -> Self {
+        PartsListError::Storage { s: e.to_string() }
+    }
+}
+
+/// Hash holding every part serialized as JSON, keyed by part id
+const PARTS_KEY: &str = "bom:parts";
+
+/// Number of changes made to the parts hash, bumped along with every change
+const VERSION_KEY: &str = "bom:version";
+
+/// Hash holding the ids of the parts changed or removed by each change as a
+/// JSON list, keyed by the version the change created
+const CHANGES_KEY: &str = "bom:changes";
+
+/// Number of changes kept in the changes hash, so servers further behind read
+/// every part again
+const MAX_CHANGES: u64 = 1000;
+
+/// Number of times a mutation is retried after losing a race with another
+/// server instance before giving up
+const MAX_RETRIES: usize = 16;
+
+/// Parts store kept in Redis, allowing several bom-server instances to share
+/// the same parts list
+///
+/// Each instance keeps a copy of the parts along with the version of the
+/// parts hash it matches. Before reading or changing the parts an instance
+/// catches up with the changes made by others, reading only the parts they
+/// changed, and a change only writes back the parts it changed.
+///
+/// Mutations use optimistic locking: the version is watched while the change
+/// is made to the copy, and the transaction writing it is retried if another
+/// instance changed the parts before it could be applied. The versions of
+/// parts expected by a write are checked again in every attempt, so they are
+/// compared and changed atomically.
+pub struct RedisStore {
+    replica: Mutex<Replica>,
+    policy: DuplicateNamePolicy,
+}
+
+/// Connection to Redis along with the copy of the parts it serves
+struct Replica {
+    con: Connection,
+    parts: PartsList,
+    /// Version of the parts hash the copy matches, none until it is first read
+    version: Option<u64>,
+    /// Version of each part the changes of the current write expect
+    expected: Vec<(Uuid, u64)>,
+}
+
+impl RedisStore {
+    /// Connect to the Redis server at `url`
+    pub fn connect(url: &str, policy: DuplicateNamePolicy) -> Result<RedisStore, PartsListError> {
+        let con = redis::Client::open(url)?.get_connection()?;
+        let mut replica = Replica {
+            con,
+            parts: PartsList::new(),
+            version: None,
+            expected: Vec::new(),
+        };
+        replica.parts.set_name_policy(policy);
+        let store = RedisStore {
+            replica: Mutex::new(replica),
+            policy,
+        };
+        store.current()?;
+        Ok(store)
+    }
+
+    fn replica(&self) -> Result<MutexGuard<'_, Replica>, PartsListError> {
+        self.replica.lock().map_err(|e| PartsListError::Storage {
+            s: format!("Couldn't lock redis connection: {}", e),
+        })
+    }
+
+    /// Copy of the parts caught up with the changes made by other instances
+    fn current(&self) -> Result<PartsList, PartsListError> {
+        let mut replica = self.replica()?;
+        for _ in 0..MAX_RETRIES {
+            redis::cmd("WATCH")
+                .arg(VERSION_KEY)
+                .query::<()>(&mut replica.con)?;
+            let version = replica.stored_version()?;
+            if replica.version == Some(version) {
+                redis::cmd("UNWATCH").query::<()>(&mut replica.con)?;
+                return Ok(replica.parts.clone());
+            }
+            replica.catch_up(version, self.policy)?;
+        }
+        Err(PartsListError::Storage {
+            s: "Too many concurrent modifications, read abandoned".into(),
+        })
+    }
+
+    /// Apply `op` to the caught up copy of the parts and write back every part
+    /// it changed, retrying if the parts were modified concurrently
+    fn mutate<T, F>(&self, mut op: F) -> Result<T, PartsListError>
+    where
+        F: FnMut(&mut PartsList) -> Result<T, PartsListError>,
+    {
+        let mut replica = self.replica()?;
+        for _ in 0..MAX_RETRIES {
+            redis::cmd("WATCH")
+                .arg(VERSION_KEY)
+                .query::<()>(&mut replica.con)?;
+            let version = replica.stored_version()?;
+            if replica.version != Some(version) {
+                replica.catch_up(version, self.policy)?;
+                continue;
+            }
+            let mut parts = replica.parts.clone();
+            let result = match replica.check_expected().and_then(|_| op(&mut parts)) {
+                Ok(result) => result,
+                Err(e) => {
+                    redis::cmd("UNWATCH").query::<()>(&mut replica.con)?;
+                    return Err(e);
+                }
+            };
+
+            let (changed, removed) = parts.changes_since(&replica.parts);
+            if changed.is_empty() && removed.is_empty() {
+                redis::cmd("UNWATCH").query::<()>(&mut replica.con)?;
+                return Ok(result);
+            }
+            let mut ids: Vec<Uuid> = changed.iter().map(|part| part.id).collect();
+            ids.extend(&removed);
+            let next = version + 1;
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for part in &changed {
+                pipe.hset(PARTS_KEY, part.id.to_string(), serde_json::to_string(part)?)
+                    .ignore();
+            }
+            for id in &removed {
+                pipe.hdel(PARTS_KEY, id.to_string()).ignore();
+            }
+            pipe.set(VERSION_KEY, next)
+                .ignore()
+                .hset(CHANGES_KEY, next, serde_json::to_string(&ids)?)
+                .ignore();
+            if next > MAX_CHANGES {
+                pipe.hdel(CHANGES_KEY, next - MAX_CHANGES).ignore();
+            }
+            // EXEC replies with nil when the watched version was changed
+            let applied: Option<()> = pipe.query(&mut replica.con)?;
+            if applied.is_some() {
+                // the parts now expected are those this write made
+                let expected = std::mem::take(&mut replica.expected);
+                replica.expected = expected
+                    .into_iter()
+                    .filter_map(|(id, _)| parts.get(&id).ok().map(|part| (id, part.version)))
+                    .collect();
+                replica.parts = parts;
+                replica.version = Some(next);
+                return Ok(result);
+            }
+        }
+        Err(PartsListError::Storage {
+            s: "Too many concurrent modifications, update abandoned".into(),
+        })
+    }
+}
+
+impl Replica {
+    fn stored_version(&mut self) -> Result<u64, PartsListError> {
+        let version: Option<u64> = self.con.get(VERSION_KEY)?;
+        Ok(version.unwrap_or(0))
+    }
+
+    /// Bring the copy of the parts up to `version`, reading only the parts
+    /// changed since, or every part once those changes are no longer kept
+    ///
+    /// The version must be watched, and nothing is changed unless it still
+    /// is `version` once the parts are read, as they could otherwise mix
+    /// several versions.
+    fn catch_up(
+        &mut self,
+        version: u64,
+        policy: DuplicateNamePolicy,
+    ) -> Result<(), PartsListError> {
+        let changes = match self.version {
+            Some(copied) if copied < version && version - copied <= MAX_CHANGES => {
+                let versions: Vec<u64> = (copied + 1..=version).collect();
+                let changes: Vec<Option<String>> = redis::cmd("HMGET")
+                    .arg(CHANGES_KEY)
+                    .arg(versions)
+                    .query(&mut self.con)?;
+                changes.into_iter().collect::<Option<Vec<String>>>()
+            }
+            _ => None,
+        };
+        let parts = match changes {
+            Some(changes) => {
+                let mut ids: Vec<Uuid> = Vec::new();
+                for change in changes {
+                    ids.extend(serde_json::from_str::<Vec<Uuid>>(&change)?);
+                }
+                ids.sort();
+                ids.dedup();
+                let values: Vec<Option<String>> = redis::cmd("HMGET")
+                    .arg(PARTS_KEY)
+                    .arg(ids.iter().map(Uuid::to_string).collect::<Vec<_>>())
+                    .query(&mut self.con)?;
+                let mut changed = Vec::new();
+                let mut removed = Vec::new();
+                for (id, value) in ids.into_iter().zip(values) {
+                    match value {
+                        Some(value) => changed.push(serde_json::from_str(&value)?),
+                        None => removed.push(id),
+                    }
+                }
+                let mut parts = self.parts.clone();
+                parts.load_changes(changed, &removed);
+                parts
+            }
+            None => {
+                let values: Vec<String> = self.con.hvals(PARTS_KEY)?;
+                let mut parts = values
+                    .iter()
+                    .map(|value| serde_json::from_str(value))
+                    .collect::<Result<PartsList, _>>()?;
+                parts.set_name_policy(policy);
+                parts
+            }
+        };
+        // EXEC replies with nil when the watched version was changed
+        let unchanged: Option<(Option<u64>,)> = redis::pipe()
+            .atomic()
+            .get(VERSION_KEY)
+            .query(&mut self.con)?;
+        if unchanged.is_some() {
+            self.parts = parts;
+            self.version = Some(version);
+        }
+        Ok(())
+    }
+
+    /// Fail unless every part expected by the current write is still at the
+    /// version it expects
+    fn check_expected(&self) -> Result<(), PartsListError> {
+        for (id, expected) in &self.expected {
+            PartsStore::check_version(&self.parts, id, *expected)?;
+        }
+        Ok(())
+    }
+}
+
+impl PartsStore for RedisStore {
+    fn get(&self, id: &Uuid) -> Result<Part, PartsListError> {
+        let value: Option<String> = self.replica()?.con.hget(PARTS_KEY, id.to_string())?;
+        match value {
+            Some(value) => Ok(serde_json::from_str(&value)?),
+            None => Err(PartsListError::PartDoesNotExist { id: *id }),
+        }
+    }
+
+    fn name_policy(&self) -> DuplicateNamePolicy {
+        self.policy
+    }
+
+    fn expect_version(&mut self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        PartsStore::check_version(&self.current()?, id, expected)?;
+        self.replica()?.expected.push((*id, expected));
+        Ok(())
+    }
+
+    fn end_write(&mut self) {
+        if let Ok(mut replica) = self.replica() {
+            replica.expected.clear();
+        }
+    }
+
+    fn insert(&mut self, part: Part) -> Result<Part, PartsListError> {
+        self.mutate(|parts| parts.add(part.clone()).cloned())
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+        self.mutate(|parts| parts.delete(id))
+    }
+
+    fn parts(&self) -> Result<Vec<Part>, PartsListError> {
+        Ok(self.current()?.into_iter().collect())
+    }
+
+    fn snapshot(&self) -> Result<PartsList, PartsListError> {
+        self.current()
+    }
+
+    fn update(
+        &mut self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<(), PartsListError> {
+        self.mutate(|parts| parts.update(id, children, op))
+    }
+
+    fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError> {
+        self.mutate(|parts| parts.edit(id, edit).cloned())
+    }
+
+    fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<Relationship, PartsListError> {
+        self.mutate(|parts| parts.edit_relationship(id, edit).cloned())
+    }
+
+    fn delete_tree(&mut self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        self.mutate(|parts| parts.delete_tree(id))
+    }
+
+    fn remove_orphans(&mut self) -> Result<Vec<Part>, PartsListError> {
+        self.mutate(|parts| Ok(parts.delete_orphans()))
+    }
+}
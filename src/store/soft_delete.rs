@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::parts_list::{
+    DuplicateNamePolicy, Part, PartEdit, PartsList, PartsListError, PartsListFilter,
+    PartsListStats, PartsListUpdate, Relationship, RelationshipEdit, ValidationReport,
+};
+use crate::store::PartsStore;
+
+/// Deleted part along with the relationships it had when it was removed
+struct Tombstone {
+    part: Part,
+    deleted_at: Instant,
+}
+
+/// Parts store wrapper keeping deleted parts as tombstones so they can be
+/// restored within a retention window
+///
+/// Deleted parts are removed from the wrapped store, so they are excluded from
+/// listings and traversals like any other deleted part. Tombstones are held in
+/// memory and are lost when the server restarts.
+pub struct SoftDeleteStore {
+    store: Box<dyn PartsStore>,
+    retention: Duration,
+    tombstones: HashMap<Uuid, Tombstone>,
+    /// Parts whose tombstones expired since they were last purged
+    expired: Vec<Uuid>,
+}
+
+impl SoftDeleteStore {
+    pub fn new(store: Box<dyn PartsStore>, retention: Duration) -> SoftDeleteStore {
+        SoftDeleteStore {
+            store,
+            retention,
+            tombstones: HashMap::new(),
+            expired: Vec::new(),
+        }
+    }
+
+    fn bury<I: IntoIterator<Item = Part>>(&mut self, parts: I) {
+        let retention = self.retention;
+        let expired = &mut self.expired;
+        self.tombstones.retain(|id, tombstone| {
+            let kept = tombstone.deleted_at.elapsed() < retention;
+            if !kept {
+                expired.push(*id);
+            }
+            kept
+        });
+        let deleted_at = Instant::now();
+        for part in parts {
+            self.tombstones
+                .insert(part.id, Tombstone { part, deleted_at });
+        }
+    }
+}
+
+impl PartsStore for SoftDeleteStore {
+    fn get(&self, id: &Uuid) -> Result<Part, PartsListError> {
+        self.store.get(id)
+    }
+
+    fn insert(&mut self, part: Part) -> Result<Part, PartsListError> {
+        self.store.insert(part)
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+        let part = self.store.get(id)?;
+        self.store.remove(id)?;
+        self.bury(vec![part]);
+        Ok(())
+    }
+
+    fn parts(&self) -> Result<Vec<Part>, PartsListError> {
+        self.store.parts()
+    }
+
+    fn update(
+        &mut self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<(), PartsListError> {
+        self.store.update(id, children, op)
+    }
+
+    fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError> {
+        self.store.edit(id, edit)
+    }
+
+    fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<Relationship, PartsListError> {
+        self.store.edit_relationship(id, edit)
+    }
+
+    fn get_relationship(&self, id: &Uuid) -> Result<Relationship, PartsListError> {
+        self.store.get_relationship(id)
+    }
+
+    fn check_version(&self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        self.store.check_version(id, expected)
+    }
+
+    fn expect_version(&mut self, id: &Uuid, expected: u64) -> Result<(), PartsListError> {
+        self.store.expect_version(id, expected)
+    }
+
+    fn end_write(&mut self) {
+        self.store.end_write()
+    }
+
+    fn get_many(&self, ids: &[Uuid]) -> Result<(Vec<Part>, Vec<Uuid>), PartsListError> {
+        self.store.get_many(ids)
+    }
+
+    fn get_by_name(&self, name: &str) -> Result<Vec<Part>, PartsListError> {
+        self.store.get_by_name(name)
+    }
+
+    fn get_by_external(&self, system: &str, external_id: &str) -> Result<Part, PartsListError> {
+        self.store.get_by_external(system, external_id)
+    }
+
+    fn search(&self, query: &str, fuzzy: bool) -> Result<Vec<(Part, f64)>, PartsListError> {
+        self.store.search(query, fuzzy)
+    }
+
+    fn snapshot(&self) -> Result<PartsList, PartsListError> {
+        self.store.snapshot()
+    }
+
+    fn list(&self, filter: PartsListFilter) -> Result<Vec<Part>, PartsListError> {
+        self.store.list(filter)
+    }
+
+    fn get_children(
+        &self,
+        id: &Uuid,
+        filter: PartsListFilter,
+    ) -> Result<Vec<Part>, PartsListError> {
+        self.store.get_children(id, filter)
+    }
+
+    fn get_ancestors(&self, id: &Uuid, depth: Option<usize>) -> Result<Vec<Part>, PartsListError> {
+        self.store.get_ancestors(id, depth)
+    }
+
+    fn stats(&self) -> Result<PartsListStats, PartsListError> {
+        self.store.stats()
+    }
+
+    fn top_used(&self, limit: usize) -> Result<Vec<(Part, usize)>, PartsListError> {
+        self.store.top_used(limit)
+    }
+
+    fn validate(&self) -> Result<ValidationReport, PartsListError> {
+        self.store.validate()
+    }
+
+    fn delete_tree_candidates(&self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        self.store.delete_tree_candidates(id)
+    }
+
+    fn delete_tree(&mut self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        let removed = self.store.delete_tree(id)?;
+        self.bury(removed.clone());
+        Ok(removed)
+    }
+
+    fn remove_orphans(&mut self) -> Result<Vec<Part>, PartsListError> {
+        let removed = self.store.remove_orphans()?;
+        self.bury(removed.clone());
+        Ok(removed)
+    }
+
+    fn name_policy(&self) -> DuplicateNamePolicy {
+        self.store.name_policy()
+    }
+
+    /// Parts just deleted can still be restored, so only those whose
+    /// tombstones expired are gone
+    fn purged(&mut self, _deleted: &[Uuid]) -> Vec<Uuid> {
+        std::mem::take(&mut self.expired)
+    }
+
+    fn replace_all(&mut self, parts: Vec<Part>) -> Result<(), PartsListError> {
+        let replaced = self.store.parts()?;
+        self.store.replace_all(parts)?;
+        self.bury(replaced);
+        Ok(())
+    }
+
+    /// Recreate a deleted part, relinking it to the relatives it had when it
+    /// was deleted which still exist. Links which would now form a cycle are
+    /// skipped. The links to its children keep their metadata, while those
+    /// from its parents are restored with the defaults.
+    fn restore(&mut self, id: &Uuid) -> Result<Part, PartsListError> {
+        match self.tombstones.get(id) {
+            Some(tombstone) if tombstone.deleted_at.elapsed() < self.retention => (),
+            _ => return Err(PartsListError::NotDeleted { id: *id }),
+        }
+        let part = self.tombstones[id].part.clone();
+        self.store.insert(Part {
+            parents: Default::default(),
+            children: Default::default(),
+            ..part.clone()
+        })?;
+        self.tombstones.remove(id);
+        for parent in &part.parents {
+            if self.store.get(parent).is_ok() {
+                let _ = self.store.update(parent, &[id], PartsListUpdate::Add);
+            }
+        }
+        let children: Vec<&Uuid> = part
+            .children
+            .iter()
+            .filter(|child| self.store.get(child).is_ok())
+            .collect();
+        for child in children {
+            if self
+                .store
+                .update(id, &[child], PartsListUpdate::Add)
+                .is_ok()
+            {
+                if let Some(relationship) = part.relationships.get(child) {
+                    let edit = RelationshipEdit::copy_of(relationship);
+                    let _ = self.store.edit_relationship(&relationship.id, &edit);
+                }
+            }
+        }
+        self.store.get(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_deleted_part() {
+        let retention = Duration::from_secs(60);
+        let mut store = SoftDeleteStore::new(Box::new(PartsList::new()), retention);
+        let assy = store.insert(Part::new("assy")).unwrap();
+        let subassy = store.insert(Part::new("subassy")).unwrap();
+        let screw = store.insert(Part::new("screw")).unwrap();
+        store
+            .update(&assy.id, &[&subassy.id], PartsListUpdate::Add)
+            .unwrap();
+        store
+            .update(&subassy.id, &[&screw.id], PartsListUpdate::Add)
+            .unwrap();
+
+        store.remove(&subassy.id).unwrap();
+        assert_matches!(
+            store.get(&subassy.id),
+            Err(PartsListError::PartDoesNotExist { .. })
+        );
+        assert!(store
+            .get_children(&assy.id, PartsListFilter::All)
+            .unwrap()
+            .is_empty());
+
+        let restored = store.restore(&subassy.id).unwrap();
+        assert!(restored.parents.contains(&assy.id));
+        assert!(restored.children.contains(&screw.id));
+        assert_eq!(
+            store
+                .get_children(&assy.id, PartsListFilter::All)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // tombstone is consumed by the restore
+        assert_matches!(
+            store.restore(&subassy.id),
+            Err(PartsListError::NotDeleted { .. })
+        );
+    }
+
+    #[test]
+    fn restore_after_retention() {
+        let mut store = SoftDeleteStore::new(Box::new(PartsList::new()), Duration::from_secs(0));
+        let part = store.insert(Part::new("my part")).unwrap();
+        store.remove(&part.id).unwrap();
+        assert_matches!(
+            store.restore(&part.id),
+            Err(PartsListError::NotDeleted { .. })
+        );
+    }
+
+    #[test]
+    fn purges_expired_tombstones() {
+        let mut store = SoftDeleteStore::new(Box::new(PartsList::new()), Duration::from_secs(0));
+        let first = store.insert(Part::new("first")).unwrap();
+        let second = store.insert(Part::new("second")).unwrap();
+        store.remove(&first.id).unwrap();
+        assert!(store.purged(&[first.id]).is_empty());
+
+        // the tombstone of the first part expires once another part is deleted
+        store.remove(&second.id).unwrap();
+        assert_eq!(store.purged(&[second.id]), vec![first.id]);
+        assert!(store.purged(&[]).is_empty());
+    }
+}
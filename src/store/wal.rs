@@ -0,0 +1,750 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use uuid::Uuid;
+
+use crate::parts_list::{
+    DuplicateNamePolicy, Part, PartEdit, PartsList, PartsListError, PartsListFilter,
+    PartsListQuery, PartsListStats, PartsListUpdate, Relationship, RelationshipEdit,
+    ValidationReport,
+};
+use crate::store::PartsStore;
+
+/// Mutation recorded in the log
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalOp {
+    Insert {
+        part: Part,
+    },
+    Remove {
+        id: Uuid,
+    },
+    Update {
+        id: Uuid,
+        children: Vec<Uuid>,
+        action: String,
+    },
+    Edit {
+        id: Uuid,
+        edit: PartEdit,
+    },
+    EditRelationship {
+        id: Uuid,
+        edit: RelationshipEdit,
+    },
+}
+
+impl WalOp {
+    /// Make the change to `store`, returning the part it inserted or edited
+    pub fn apply(self, store: &mut dyn PartsStore) -> Result<Option<Part>, PartsListError> {
+        match self {
+            WalOp::Insert { part } => store.insert(part).map(Some),
+            WalOp::Remove { id } => store.remove(&id).map(|_| None),
+            WalOp::Update {
+                id,
+                children,
+                action,
+            } => {
+                let action = PartsListUpdate::try_from(action.as_str())?;
+                let children: Vec<&Uuid> = children.iter().collect();
+                store.update(&id, &children, action).map(|_| None)
+            }
+            WalOp::Edit { id, edit } => store.edit(&id, &edit).map(Some),
+            WalOp::EditRelationship { id, edit } => {
+                store.edit_relationship(&id, &edit).map(|_| None)
+            }
+        }
+    }
+}
+
+/// Line of the log, numbered so entries already included in a snapshot are
+/// skipped when replaying
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WalEntry {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub op: WalOp,
+}
+
+/// Changes following a cursor, as returned by the change feed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChangeFeed {
+    /// Number of the last change returned, to pass as the cursor of the next
+    /// request
+    pub cursor: u64,
+    /// Whether later changes were left out to keep to the requested limit
+    pub more: bool,
+    pub changes: Vec<WalEntry>,
+}
+
+/// Latest entries logged by a `WalStore`, kept in memory for the change feed
+///
+/// Entries outlive the compaction of the log, up to the `retain` most recent,
+/// so readers falling behind by less than that can carry on from their cursor.
+/// Older cursors have passed the horizon of the journal, and their readers
+/// must copy the parts afresh.
+struct Journal {
+    entries: VecDeque<WalEntry>,
+    /// Number of the last change before the oldest entry kept
+    horizon: u64,
+    retain: usize,
+}
+
+/// Handle on the journal of a `WalStore`, shared with the routes serving the
+/// change feed
+#[derive(Clone)]
+pub struct ChangeJournal(Arc<RwLock<Journal>>);
+
+impl ChangeJournal {
+    fn new(horizon: u64, retain: usize) -> ChangeJournal {
+        ChangeJournal(Arc::new(RwLock::new(Journal {
+            entries: VecDeque::new(),
+            horizon,
+            retain: retain.max(1),
+        })))
+    }
+
+    fn push(&self, entry: WalEntry) {
+        if let Ok(mut journal) = self.0.write() {
+            journal.entries.push_back(entry);
+            while journal.entries.len() > journal.retain {
+                if let Some(dropped) = journal.entries.pop_front() {
+                    journal.horizon = dropped.seq;
+                }
+            }
+        }
+    }
+
+    /// Forget every entry, as the changes up to `seq` can no longer be
+    /// replayed one at a time
+    fn reset(&self, seq: u64) {
+        if let Ok(mut journal) = self.0.write() {
+            journal.entries.clear();
+            journal.horizon = seq;
+        }
+    }
+
+    /// Number of the latest change
+    pub fn cursor(&self) -> Result<u64, PartsListError> {
+        let journal = self.0.read().map_err(|_| journal_lock_error())?;
+        Ok(journal
+            .entries
+            .back()
+            .map_or(journal.horizon, |entry| entry.seq))
+    }
+
+    /// At most `limit` changes made after the change numbered `since`, oldest
+    /// first
+    pub fn since(&self, since: u64, limit: usize) -> Result<ChangeFeed, PartsListError> {
+        let journal = self.0.read().map_err(|_| journal_lock_error())?;
+        let latest = journal
+            .entries
+            .back()
+            .map_or(journal.horizon, |entry| entry.seq);
+        if since > latest {
+            return Err(PartsListError::InvalidCursor { cursor: since });
+        }
+        if since < journal.horizon {
+            return Err(PartsListError::ChangesExpired {
+                cursor: since,
+                horizon: journal.horizon,
+            });
+        }
+        let changes: Vec<WalEntry> = journal
+            .entries
+            .iter()
+            .filter(|entry| entry.seq > since)
+            .take(limit)
+            .cloned()
+            .collect();
+        let cursor = changes.last().map_or(since, |entry| entry.seq);
+        Ok(ChangeFeed {
+            cursor,
+            more: cursor < latest,
+            changes,
+        })
+    }
+}
+
+fn journal_lock_error() -> PartsListError {
+    PartsListError::Storage {
+        s: String::from("Couldn't lock change journal!"),
+    }
+}
+
+/// Parts list as of the last compaction, along with the number of the last
+/// entry it includes
+#[derive(Deserialize)]
+struct WalSnapshot {
+    seq: u64,
+    parts: PartsList,
+}
+
+/// Snapshot being written from the parts held by the store
+#[derive(Serialize)]
+struct WalSnapshotRef<'a> {
+    seq: u64,
+    parts: &'a PartsList,
+}
+
+/// Parts store held in memory and made durable by an append-only write-ahead
+/// log
+///
+/// Every mutation is first applied to a copy of the parts held in memory, which
+/// shares every part the mutation leaves alone. Once it succeeded it is
+/// written and synced to the log, and only then is the copy swapped in, so
+/// readers and the change feed never see a change which isn't durable, and
+/// every entry of the log can be replayed over the latest snapshot when the
+/// store is opened. Once
+/// the log holds `compact_after` entries the parts are written to a new
+/// snapshot and the log is truncated. The latest `compact_after` entries are
+/// also kept in a `ChangeJournal` for the change feed.
+pub struct WalStore {
+    parts: PartsList,
+    log: File,
+    path: PathBuf,
+    snapshot_path: PathBuf,
+    seq: u64,
+    entries: usize,
+    compact_after: usize,
+    journal: ChangeJournal,
+}
+
+impl WalStore {
+    /// Open the log at `path`, with its snapshot alongside it, creating both
+    /// when they don't exist yet
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        policy: DuplicateNamePolicy,
+        compact_after: usize,
+    ) -> Result<WalStore, PartsListError> {
+        let path = path.as_ref().to_path_buf();
+        let mut snapshot_path = path.clone().into_os_string();
+        snapshot_path.push(".snapshot");
+        let snapshot_path = PathBuf::from(snapshot_path);
+
+        let (mut seq, mut parts) = match File::open(&snapshot_path) {
+            Ok(file) => {
+                let snapshot: WalSnapshot = serde_json::from_reader(BufReader::new(file))?;
+                (snapshot.seq, snapshot.parts)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, PartsList::new()),
+            Err(e) => return Err(e.into()),
+        };
+        parts.set_name_policy(policy);
+        let journal = ChangeJournal::new(seq, compact_after);
+
+        let mut entries = 0;
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                // a line cut short by a crash while appending ends the log
+                let entry: WalEntry = match serde_json::from_str(&line?) {
+                    Ok(entry) => entry,
+                    Err(_) => break,
+                };
+                entries += 1;
+                if entry.seq <= seq {
+                    continue;
+                }
+                seq = entry.seq;
+                // only mutations which succeeded are logged, so one failing
+                // now means the log doesn't match the parts it was written for
+                if let Err(e) = entry.op.clone().apply(&mut parts) {
+                    return Err(PartsListError::WalReplay {
+                        seq,
+                        s: format!("{}", e),
+                    });
+                }
+                journal.push(entry);
+            }
+        }
+
+        let log = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut store = WalStore {
+            parts,
+            log,
+            path,
+            snapshot_path,
+            seq,
+            entries,
+            compact_after,
+            journal,
+        };
+        if store.entries >= store.compact_after {
+            store.compact()?;
+        }
+        Ok(store)
+    }
+
+    /// Apply `op` to a copy of the parts, write and sync it to the log, then
+    /// swap the copy in
+    ///
+    /// Mutations which fail, or which can't be written to the log, leave the
+    /// parts unchanged and aren't logged.
+    fn record(&mut self, op: WalOp) -> Result<Option<Part>, PartsListError> {
+        let entry = WalEntry {
+            seq: self.seq + 1,
+            op,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut parts = self.parts.clone();
+        let result = entry.op.clone().apply(&mut parts)?;
+        self.append(line.as_bytes())?;
+
+        self.parts = parts;
+        self.seq += 1;
+        self.journal.push(entry);
+        self.entries += 1;
+        if self.entries >= self.compact_after {
+            // the change is already durable, so compaction is retried after
+            // the next change rather than failing this one
+            if let Err(e) = self.compact() {
+                tracing::warn!("Failed to compact {}: {}", self.path.display(), e);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Write and sync `line` to the log, cutting the log back to its previous
+    /// length when that fails
+    fn append(&mut self, line: &[u8]) -> Result<(), PartsListError> {
+        let len = self.log.metadata()?.len();
+        if let Err(e) = self.log.write_all(line).and_then(|_| self.log.sync_data()) {
+            // an entry cut short ends the log when it's replayed, which would
+            // hide every entry appended after it
+            let _ = self.log.set_len(len);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Write the parts to a new snapshot and truncate the log
+    ///
+    /// The snapshot is replaced atomically, and a crash before the log is
+    /// truncated only leaves entries which are skipped on replay.
+    pub fn compact(&mut self) -> Result<(), PartsListError> {
+        self.write_snapshot()?;
+        self.truncate_log()
+    }
+
+    fn truncate_log(&mut self) -> Result<(), PartsListError> {
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.entries = 0;
+        Ok(())
+    }
+
+    /// Replace the snapshot with the parts held by the store, which then
+    /// includes every entry of the log
+    fn write_snapshot(&self) -> Result<(), PartsListError> {
+        let mut tmp_path = self.snapshot_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            let snapshot = WalSnapshotRef {
+                seq: self.seq,
+                parts: &self.parts,
+            };
+            serde_json::to_writer(&mut writer, &snapshot)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.snapshot_path)?;
+        Ok(())
+    }
+
+    /// Path of the log
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Journal of the latest changes, for the change feed
+    pub fn journal(&self) -> ChangeJournal {
+        self.journal.clone()
+    }
+}
+
+impl PartsStore for WalStore {
+    fn get(&self, id: &Uuid) -> Result<Part, PartsListError> {
+        self.parts.get(id).cloned()
+    }
+
+    fn insert(&mut self, part: Part) -> Result<Part, PartsListError> {
+        match self.record(WalOp::Insert { part })? {
+            Some(part) => Ok(part),
+            None => Err(PartsListError::Unknown),
+        }
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Result<(), PartsListError> {
+        self.record(WalOp::Remove { id: *id }).map(|_| ())
+    }
+
+    fn parts(&self) -> Result<Vec<Part>, PartsListError> {
+        PartsStore::parts(&self.parts)
+    }
+
+    fn name_policy(&self) -> DuplicateNamePolicy {
+        self.parts.name_policy()
+    }
+
+    /// Swap in the restored parts and make them durable with a new snapshot
+    /// rather than logging every part
+    ///
+    /// The restore counts as a change of its own which can't be replayed, so
+    /// every earlier cursor of the change feed expires.
+    fn replace_all(&mut self, parts: Vec<Part>) -> Result<(), PartsListError> {
+        let restored = PartsStore::restored(self, parts)?;
+        let replaced = std::mem::replace(&mut self.parts, restored);
+        self.seq += 1;
+        if let Err(e) = self.write_snapshot() {
+            self.parts = replaced;
+            self.seq -= 1;
+            return Err(e);
+        }
+        self.journal.reset(self.seq);
+        // entries of the log are included in the snapshot, so are skipped
+        // even if it can't be truncated now
+        self.truncate_log()
+    }
+
+    fn update(
+        &mut self,
+        id: &Uuid,
+        children: &[&Uuid],
+        op: PartsListUpdate,
+    ) -> Result<(), PartsListError> {
+        self.record(WalOp::Update {
+            id: *id,
+            children: children.iter().map(|child| **child).collect(),
+            action: Into::<&str>::into(op).into(),
+        })
+        .map(|_| ())
+    }
+
+    fn edit(&mut self, id: &Uuid, edit: &PartEdit) -> Result<Part, PartsListError> {
+        match self.record(WalOp::Edit {
+            id: *id,
+            edit: edit.clone(),
+        })? {
+            Some(part) => Ok(part),
+            None => Err(PartsListError::Unknown),
+        }
+    }
+
+    fn edit_relationship(
+        &mut self,
+        id: &Uuid,
+        edit: &RelationshipEdit,
+    ) -> Result<Relationship, PartsListError> {
+        self.record(WalOp::EditRelationship {
+            id: *id,
+            edit: edit.clone(),
+        })?;
+        self.parts.get_relationship(id).cloned()
+    }
+
+    fn get_relationship(&self, id: &Uuid) -> Result<Relationship, PartsListError> {
+        PartsStore::get_relationship(&self.parts, id)
+    }
+
+    fn get_by_name(&self, name: &str) -> Result<Vec<Part>, PartsListError> {
+        PartsStore::get_by_name(&self.parts, name)
+    }
+
+    fn get_by_external(&self, system: &str, external_id: &str) -> Result<Part, PartsListError> {
+        PartsStore::get_by_external(&self.parts, system, external_id)
+    }
+
+    fn search(&self, query: &str, fuzzy: bool) -> Result<Vec<(Part, f64)>, PartsListError> {
+        PartsStore::search(&self.parts, query, fuzzy)
+    }
+
+    fn snapshot(&self) -> Result<PartsList, PartsListError> {
+        PartsStore::snapshot(&self.parts)
+    }
+
+    fn list(&self, filter: PartsListFilter) -> Result<Vec<Part>, PartsListError> {
+        PartsStore::list(&self.parts, filter)
+    }
+
+    fn query(&self, query: &PartsListQuery) -> Result<Vec<Part>, PartsListError> {
+        PartsStore::query(&self.parts, query)
+    }
+
+    fn get_children(
+        &self,
+        id: &Uuid,
+        filter: PartsListFilter,
+    ) -> Result<Vec<Part>, PartsListError> {
+        PartsStore::get_children(&self.parts, id, filter)
+    }
+
+    fn get_ancestors(&self, id: &Uuid, depth: Option<usize>) -> Result<Vec<Part>, PartsListError> {
+        PartsStore::get_ancestors(&self.parts, id, depth)
+    }
+
+    fn stats(&self) -> Result<PartsListStats, PartsListError> {
+        PartsStore::stats(&self.parts)
+    }
+
+    fn top_used(&self, limit: usize) -> Result<Vec<(Part, usize)>, PartsListError> {
+        PartsStore::top_used(&self.parts, limit)
+    }
+
+    fn validate(&self) -> Result<ValidationReport, PartsListError> {
+        PartsStore::validate(&self.parts)
+    }
+
+    fn delete_tree_candidates(&self, id: &Uuid) -> Result<Vec<Part>, PartsListError> {
+        PartsStore::delete_tree_candidates(&self.parts, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log() -> PathBuf {
+        std::env::temp_dir().join(format!("bom-wal-{}", Uuid::new_v4()))
+    }
+
+    fn cleanup(path: &Path) {
+        let _ = fs::remove_file(path);
+        let mut snapshot = path.to_path_buf().into_os_string();
+        snapshot.push(".snapshot");
+        let _ = fs::remove_file(snapshot);
+    }
+
+    #[test]
+    fn replay_after_reopen() {
+        let path = temp_log();
+        let (assy, screw) = {
+            let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+            let assy = store.insert(Part::new("assy")).unwrap();
+            let screw = store.insert(Part::new("screw")).unwrap();
+            let nut = store.insert(Part::new("nut")).unwrap();
+            store
+                .update(&assy.id, &[&screw.id, &nut.id], PartsListUpdate::Add)
+                .unwrap();
+            store.remove(&nut.id).unwrap();
+            // rejected mutations aren't logged
+            assert!(store.insert(Part::new("screw")).is_err());
+            assert_matches!(
+                store.update(&assy.id, &[&nut.id], PartsListUpdate::Remove),
+                Err(PartsListError::NotAChild { .. })
+            );
+            (assy, screw)
+        };
+
+        let store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+        assert_eq!(store.parts().unwrap().len(), 2);
+        let children = store.get_children(&assy.id, PartsListFilter::All).unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].id, screw.id);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn rejected_remove() {
+        let path = temp_log();
+        let (assy, screw) = {
+            let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+            let assy = store.insert(Part::new("assy")).unwrap();
+            let screw = store.insert(Part::new("screw")).unwrap();
+            store
+                .update(&assy.id, &[&screw.id], PartsListUpdate::Add)
+                .unwrap();
+            // removing a part which isn't a child is refused without
+            // changing the parts or the log
+            assert_matches!(
+                store.update(&screw.id, &[&assy.id], PartsListUpdate::Remove),
+                Err(PartsListError::NotAChild { .. })
+            );
+            assert_matches!(
+                store.update(&assy.id, &[&screw.id, &assy.id], PartsListUpdate::Remove),
+                Err(PartsListError::NotAChild { .. })
+            );
+            assert_eq!(fs::read_to_string(store.path()).unwrap().lines().count(), 3);
+            (assy, screw)
+        };
+
+        let store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+        let children = store.get_children(&assy.id, PartsListFilter::All).unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].id, screw.id);
+        drop(store);
+
+        // a log holding a mutation which can't be applied isn't replayed past it
+        let bad = WalEntry {
+            seq: 4,
+            op: WalOp::Update {
+                id: screw.id,
+                children: vec![assy.id],
+                action: String::from("remove"),
+            },
+        };
+        let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(log, "{}", serde_json::to_string(&bad).unwrap()).unwrap();
+        assert_matches!(
+            WalStore::open(&path, DuplicateNamePolicy::Reject, 100).map(|_| ()),
+            Err(PartsListError::WalReplay { seq: 4, .. })
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn replay_keeps_cost_times() {
+        let path = temp_log();
+        let (id, history) = {
+            let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+            let screw = store.insert(Part::new("screw")).unwrap();
+            let edit = PartEdit {
+                unit_cost: Some(0.1),
+                ..PartEdit::default()
+            };
+            let screw = store.edit(&screw.id, &edit.stamped()).unwrap();
+            (screw.id, screw.cost_history)
+        };
+
+        // the cost is recorded at the time of the edit, not of the replay
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+        assert_eq!(store.get(&id).unwrap().cost_history, history);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn replace_all_writes_snapshot() {
+        let path = temp_log();
+        {
+            let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+            store.insert(Part::new("a")).unwrap();
+            store
+                .replace_all(vec![Part::new("b"), Part::new("c")])
+                .unwrap();
+            assert_eq!(store.entries, 0);
+            assert!(store
+                .replace_all(vec![Part::new("d"), Part::new("d")])
+                .is_err());
+            store.insert(Part::new("e")).unwrap();
+        }
+
+        let store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+        let mut names: Vec<String> = store.parts().unwrap().into_iter().map(|p| p.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["b", "c", "e"]);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn change_feed_outlives_compaction() {
+        let path = temp_log();
+        let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 3).unwrap();
+        let journal = store.journal();
+        let assy = store.insert(Part::new("assy")).unwrap();
+        let screw = store.insert(Part::new("screw")).unwrap();
+        store
+            .update(&assy.id, &[&screw.id], PartsListUpdate::Add)
+            .unwrap();
+        // the log was compacted, but the changes are still journaled
+        assert_eq!(store.entries, 0);
+        let feed = journal.since(0, 2).unwrap();
+        assert_eq!((feed.cursor, feed.more, feed.changes.len()), (2, true, 2));
+        assert_matches!(feed.changes[0].op, WalOp::Insert { ref part } if part.id == assy.id);
+        let feed = journal.since(feed.cursor, 10).unwrap();
+        assert_eq!((feed.cursor, feed.more), (3, false));
+        assert_matches!(feed.changes[0].op, WalOp::Update { id, .. } if id == assy.id);
+        assert!(journal.since(3, 10).unwrap().changes.is_empty());
+        assert_matches!(
+            journal.since(4, 10),
+            Err(PartsListError::InvalidCursor { cursor: 4 })
+        );
+
+        // replaying the changes on another store copies the parts
+        let mut mirror = PartsList::new();
+        for change in journal.since(0, 10).unwrap().changes {
+            change.op.apply(&mut mirror).unwrap();
+        }
+        assert!(mirror.get(&screw.id).unwrap().parents.contains(&assy.id));
+
+        // only the latest changes are kept
+        store.insert(Part::new("nut")).unwrap();
+        assert_matches!(
+            journal.since(0, 10),
+            Err(PartsListError::ChangesExpired { horizon: 1, .. })
+        );
+        assert_eq!(journal.since(1, 10).unwrap().changes.len(), 3);
+
+        // a restore can't be replayed, so every cursor expires
+        store.replace_all(vec![Part::new("bolt")]).unwrap();
+        assert_eq!(journal.cursor().unwrap(), 5);
+        assert_matches!(
+            journal.since(4, 10),
+            Err(PartsListError::ChangesExpired { horizon: 5, .. })
+        );
+        drop(store);
+
+        // the journal starts again from the snapshot when reopened
+        let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 3).unwrap();
+        store.insert(Part::new("washer")).unwrap();
+        let feed = store.journal().since(5, 10).unwrap();
+        assert_eq!((feed.cursor, feed.changes.len()), (6, 1));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn compaction() {
+        let path = temp_log();
+        {
+            let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 3).unwrap();
+            for name in &["a", "b", "c", "d"] {
+                store.insert(Part::new(name)).unwrap();
+            }
+            // the first three entries were compacted into the snapshot
+            assert_eq!(store.entries, 1);
+            assert_eq!(fs::read_to_string(store.path()).unwrap().lines().count(), 1);
+        }
+
+        let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 3).unwrap();
+        assert_eq!(store.parts().unwrap().len(), 4);
+        store.insert(Part::new("e")).unwrap();
+        drop(store);
+        let store = WalStore::open(&path, DuplicateNamePolicy::Reject, 3).unwrap();
+        assert_eq!(store.parts().unwrap().len(), 5);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn failed_write_changes_nothing() {
+        let path = temp_log();
+        let mut store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+        let assy = store.insert(Part::new("assy")).unwrap();
+        let journal = store.journal();
+
+        // a log opened read only refuses every write
+        let writable = std::mem::replace(&mut store.log, File::open(&path).unwrap());
+        assert!(store.insert(Part::new("screw")).is_err());
+        assert!(store.remove(&assy.id).is_err());
+        assert!(store.get_by_name("screw").unwrap().is_empty());
+        assert!(store.get(&assy.id).is_ok());
+        assert_eq!(journal.cursor().unwrap(), 1);
+
+        // the sequence carries on once the log can be written again
+        store.log = writable;
+        let screw = store.insert(Part::new("screw")).unwrap();
+        assert_eq!(journal.since(1, 10).unwrap().changes[0].seq, 2);
+        drop(store);
+        let store = WalStore::open(&path, DuplicateNamePolicy::Reject, 100).unwrap();
+        assert_eq!(store.parts().unwrap().len(), 2);
+        assert!(store.get(&screw.id).is_ok());
+        cleanup(&path);
+    }
+}
@@ -0,0 +1,366 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use crate::currency::{self, ExchangeRates};
+use crate::parts_list::{Part, PartsListError};
+
+/// Lower price a supplier charges for each of the part when buying at least a
+/// quantity of it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PriceBreak {
+    pub min_quantity: u64,
+    pub unit_cost: f64,
+}
+
+/// Source a part can be bought from, with what it charges and how long it
+/// takes to deliver
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Supplier {
+    pub name: String,
+    /// Price of one of the part from the supplier
+    #[serde(default)]
+    pub unit_cost: f64,
+    /// Prices of the part when buying larger quantities
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub price_breaks: Vec<PriceBreak>,
+    /// Days the supplier takes to deliver the part
+    #[serde(default)]
+    pub lead_time_days: u64,
+    /// Currency the supplier charges in, the base currency of the server when
+    /// not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Manufacturer part number the supplier sells the part under
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mpn: Option<String>,
+}
+
+impl Supplier {
+    /// Price of each of the part when buying `quantity` of it, along with the
+    /// least quantity of the price break giving it, if any
+    ///
+    /// The lowest price of the breaks `quantity` reaches is used, falling back
+    /// to the supplier's unit cost.
+    pub fn price(&self, quantity: u64) -> (f64, Option<u64>) {
+        self.price_breaks
+            .iter()
+            .filter(|price_break| price_break.min_quantity <= quantity)
+            .fold((self.unit_cost, None), |best, price_break| {
+                if price_break.unit_cost < best.0 {
+                    (price_break.unit_cost, Some(price_break.min_quantity))
+                } else {
+                    best
+                }
+            })
+    }
+}
+
+/// Price paid for each of a part bought for a rollup, from the supplier chosen
+/// by its policy when the part has any
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quote<'a> {
+    pub supplier: Option<&'a Supplier>,
+    pub unit_cost: f64,
+    /// Least quantity of the price break of the supplier giving the price
+    pub price_break: Option<u64>,
+}
+
+/// Rule choosing which supplier of a part is used by cost and lead time
+/// rollups
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SupplierPolicy {
+    /// The first supplier listed, suppliers being listed in order of
+    /// preference
+    #[default]
+    Preferred,
+    /// The supplier with the lowest unit cost
+    Cheapest,
+    /// The supplier which delivers soonest
+    ShortestLeadTime,
+}
+
+impl TryFrom<&str> for SupplierPolicy {
+    type Error = PartsListError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "preferred" => Ok(SupplierPolicy::Preferred),
+            "cheapest" => Ok(SupplierPolicy::Cheapest),
+            "shortest_lead_time" => Ok(SupplierPolicy::ShortestLeadTime),
+            _ => Err(PartsListError::InvalidSupplierPolicyString { s: s.into() }),
+        }
+    }
+}
+
+impl From<SupplierPolicy> for &str {
+    fn from(p: SupplierPolicy) -> &'static str {
+        match p {
+            SupplierPolicy::Preferred => "preferred",
+            SupplierPolicy::Cheapest => "cheapest",
+            SupplierPolicy::ShortestLeadTime => "shortest_lead_time",
+        }
+    }
+}
+
+/// Supplier of a part chosen by `policy` to buy `quantity` of it, or none
+/// when the part has no suppliers
+///
+/// Suppliers are compared by the price they charge for the quantity, in the
+/// base currency of `rates`, failing when one of them charges in a currency
+/// without a rate. Suppliers which are as cheap or as quick as each other are
+/// told apart by preference.
+pub fn choose<'a>(
+    part: &'a Part,
+    policy: SupplierPolicy,
+    quantity: u64,
+    rates: &ExchangeRates,
+) -> Result<Option<&'a Supplier>, PartsListError> {
+    let suppliers = part.suppliers.iter().enumerate();
+    let chosen = match policy {
+        SupplierPolicy::Preferred => return Ok(part.suppliers.first()),
+        SupplierPolicy::Cheapest => {
+            let costs = suppliers
+                .map(|(preference, supplier)| {
+                    let cost = rates.convert(
+                        supplier.price(quantity).0,
+                        supplier.currency.as_deref(),
+                        rates.base(),
+                    )?;
+                    Ok((preference, supplier, cost))
+                })
+                .collect::<Result<Vec<_>, PartsListError>>()?;
+            costs
+                .into_iter()
+                .min_by(|(a_preference, _, a), (b_preference, _, b)| {
+                    a.partial_cmp(b)
+                        .unwrap_or(Ordering::Equal)
+                        .then(a_preference.cmp(b_preference))
+                })
+                .map(|(preference, supplier, _)| (preference, supplier))
+        }
+        SupplierPolicy::ShortestLeadTime => {
+            suppliers.min_by_key(|(preference, supplier)| (supplier.lead_time_days, *preference))
+        }
+    };
+    Ok(chosen.map(|(_, supplier)| supplier))
+}
+
+/// Price of each of a part when buying `quantity` of it from the supplier
+/// chosen by `policy`, or the part's own cost when it has no suppliers,
+/// converted into `currency`
+pub fn quote<'a>(
+    part: &'a Part,
+    policy: SupplierPolicy,
+    quantity: u64,
+    rates: &ExchangeRates,
+    currency: &str,
+) -> Result<Quote<'a>, PartsListError> {
+    match choose(part, policy, quantity, rates)? {
+        Some(supplier) => {
+            let (unit_cost, price_break) = supplier.price(quantity);
+            Ok(Quote {
+                supplier: Some(supplier),
+                unit_cost: rates.convert(unit_cost, supplier.currency.as_deref(), currency)?,
+                price_break,
+            })
+        }
+        None => Ok(Quote {
+            supplier: None,
+            unit_cost: rates.convert(part.unit_cost, part.currency.as_deref(), currency)?,
+            price_break: None,
+        }),
+    }
+}
+
+/// Lead time of a part from the supplier chosen by `policy` to buy one of it,
+/// or the part's own lead time when it has no suppliers
+pub fn lead_time_days(
+    part: &Part,
+    policy: SupplierPolicy,
+    rates: &ExchangeRates,
+) -> Result<u64, PartsListError> {
+    Ok(choose(part, policy, 1, rates)?
+        .map_or(part.lead_time_days, |supplier| supplier.lead_time_days))
+}
+
+/// Check that each supplier of a part is named once and charges finite costs
+/// which aren't negative, in a currency given by its code, with at most one
+/// price break for each quantity
+pub fn check(part: &Part) -> Result<(), PartsListError> {
+    let mut names = HashSet::new();
+    for supplier in &part.suppliers {
+        let invalid = |reason: &str| PartsListError::InvalidSupplier {
+            id: part.id,
+            name: supplier.name.clone(),
+            reason: reason.into(),
+        };
+        if supplier.name.trim().is_empty() {
+            return Err(invalid("suppliers must be named"));
+        }
+        if !names.insert(supplier.name.as_str()) {
+            return Err(invalid("the supplier is listed more than once"));
+        }
+        if !supplier.unit_cost.is_finite() || supplier.unit_cost < 0.0 {
+            return Err(invalid("costs must be positive or zero"));
+        }
+        let mut quantities = HashSet::new();
+        for price_break in &supplier.price_breaks {
+            if !price_break.unit_cost.is_finite() || price_break.unit_cost < 0.0 {
+                return Err(invalid("costs must be positive or zero"));
+            }
+            if price_break.min_quantity == 0 || !quantities.insert(price_break.min_quantity) {
+                return Err(invalid(
+                    "price breaks must be for different quantities of at least 1",
+                ));
+            }
+        }
+        if let Some(code) = &supplier.currency {
+            if currency::check_code(code).is_err() {
+                return Err(invalid(
+                    "currencies must be three letter codes like \"USD\"",
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chooses_by_policy() {
+        let supplier = |name: &str, unit_cost, lead_time_days| Supplier {
+            name: name.into(),
+            unit_cost,
+            price_breaks: Vec::new(),
+            lead_time_days,
+            currency: None,
+            mpn: None,
+        };
+        let rates = ExchangeRates::default();
+        let mut screw = Part::new("screw");
+        screw.unit_cost = 0.5;
+        screw.lead_time_days = 3;
+        assert_eq!(
+            choose(&screw, SupplierPolicy::Cheapest, 1, &rates).unwrap(),
+            None
+        );
+        assert_eq!(
+            quote(&screw, SupplierPolicy::Cheapest, 1, &rates, "USD")
+                .unwrap()
+                .unit_cost,
+            0.5
+        );
+
+        screw.suppliers = vec![
+            supplier("Acme", 0.12, 10),
+            supplier("Bolt Co", 0.10, 14),
+            supplier("Fastenal", 0.10, 2),
+            supplier("Local", 0.30, 2),
+        ];
+        check(&screw).unwrap();
+        let chosen = |policy| {
+            choose(&screw, policy, 1, &rates)
+                .unwrap()
+                .map(|s| s.name.as_str())
+        };
+        assert_eq!(chosen(SupplierPolicy::Preferred), Some("Acme"));
+        assert_eq!(chosen(SupplierPolicy::Cheapest), Some("Bolt Co"));
+        assert_eq!(chosen(SupplierPolicy::ShortestLeadTime), Some("Fastenal"));
+        assert_eq!(
+            lead_time_days(&screw, SupplierPolicy::Cheapest, &rates).unwrap(),
+            14
+        );
+        assert_eq!(
+            SupplierPolicy::try_from("shortest_lead_time").unwrap(),
+            SupplierPolicy::ShortestLeadTime
+        );
+        assert!(SupplierPolicy::try_from("fastest").is_err());
+
+        // costs in other currencies are compared once converted
+        screw.suppliers[3].currency = Some(String::from("GBP"));
+        assert_matches!(
+            choose(&screw, SupplierPolicy::Cheapest, 1, &rates),
+            Err(PartsListError::UnknownCurrency { .. })
+        );
+        let mut rates = rates;
+        rates.with_rate("GBP", 0.25).unwrap();
+        let chosen = choose(&screw, SupplierPolicy::Cheapest, 1, &rates).unwrap();
+        assert_eq!(chosen.map(|s| s.name.as_str()), Some("Local"));
+        assert_eq!(
+            format!(
+                "{:.3}",
+                quote(&screw, SupplierPolicy::Cheapest, 1, &rates, "USD")
+                    .unwrap()
+                    .unit_cost
+            ),
+            "0.075"
+        );
+
+        screw.suppliers[3].currency = Some(String::from("pounds"));
+        assert!(check(&screw).is_err());
+        screw.suppliers[3].currency = None;
+        screw.suppliers.push(supplier("Acme", 0.2, 1));
+        assert_matches!(
+            check(&screw),
+            Err(PartsListError::InvalidSupplier { ref name, .. }) if name == "Acme"
+        );
+    }
+
+    #[test]
+    fn price_breaks_for_quantity() {
+        let rates = ExchangeRates::default();
+        let price_break = |min_quantity, unit_cost| PriceBreak {
+            min_quantity,
+            unit_cost,
+        };
+        let mut resistor = Part::new("resistor");
+        resistor.suppliers = vec![
+            Supplier {
+                name: String::from("Acme"),
+                unit_cost: 0.05,
+                price_breaks: vec![price_break(100, 0.02), price_break(1000, 0.01)],
+                lead_time_days: 5,
+                currency: None,
+                mpn: None,
+            },
+            Supplier {
+                name: String::from("Local"),
+                unit_cost: 0.03,
+                price_breaks: Vec::new(),
+                lead_time_days: 1,
+                currency: None,
+                mpn: None,
+            },
+        ];
+        check(&resistor).unwrap();
+        assert_eq!(resistor.suppliers[0].price(99), (0.05, None));
+        assert_eq!(resistor.suppliers[0].price(100), (0.02, Some(100)));
+        assert_eq!(resistor.suppliers[0].price(5000), (0.01, Some(1000)));
+
+        // the cheapest supplier depends on the quantity bought
+        let cheapest = |quantity| {
+            let quote =
+                quote(&resistor, SupplierPolicy::Cheapest, quantity, &rates, "USD").unwrap();
+            (
+                quote.supplier.map(|s| s.name.as_str()),
+                quote.unit_cost,
+                quote.price_break,
+            )
+        };
+        assert_eq!(cheapest(10), (Some("Local"), 0.03, None));
+        assert_eq!(cheapest(250), (Some("Acme"), 0.02, Some(100)));
+        let preferred = quote(&resistor, SupplierPolicy::Preferred, 10, &rates, "USD").unwrap();
+        assert_eq!((preferred.unit_cost, preferred.price_break), (0.05, None));
+
+        resistor.suppliers[0]
+            .price_breaks
+            .push(price_break(100, 0.015));
+        assert!(check(&resistor).is_err());
+        resistor.suppliers[0].price_breaks = vec![price_break(0, 0.01)];
+        assert!(check(&resistor).is_err());
+    }
+}
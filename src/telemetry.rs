@@ -0,0 +1,156 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Config, Data, Request};
+use tracing::{field, info_span, Level, Metadata, Span, Subscriber};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::Registry;
+
+use crate::parts_list::PartsListError;
+use crate::request_id::RequestId;
+
+/// Most verbose spans recorded when not configured, covering the requests,
+/// lock acquisition, traversals and serialization of the server
+const DEFAULT_TRACE_LEVEL: Level = Level::DEBUG;
+
+/// Filter keeping only the spans and events of this crate up to a level, so
+/// those of the libraries it uses, such as the exporter, aren't traced
+struct CrateFilter(Level);
+
+impl<S: Subscriber> Layer<S> for CrateFilter {
+    fn enabled(&self, metadata: &Metadata<'_>, _: Context<'_, S>) -> bool {
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME")) && *metadata.level() <= self.0
+    }
+}
+
+/// Resources kept alive for as long as traces are exported, managed by Rocket
+/// alongside the routes
+pub struct Telemetry {
+    #[cfg(feature = "otlp")]
+    _runtime: tokio::runtime::Runtime,
+}
+
+/// Install the tracing subscriber selected by the Rocket configuration,
+/// returning the resources it needs to stay alive, or nothing when tracing
+/// isn't enabled
+///
+/// Spans are printed along with their timings when they close if the
+/// `trace_spans` setting is enabled, and exported to the OpenTelemetry
+/// collector at the `otlp_endpoint` setting when built with the `otlp`
+/// feature. The `trace_level` setting selects the most verbose spans recorded,
+/// `debug` by default.
+pub fn init(config: &Config) -> Result<Option<Telemetry>, PartsListError> {
+    let print = config.get_bool("trace_spans").unwrap_or(false);
+    let endpoint = config.get_str("otlp_endpoint").ok();
+    if !print && endpoint.is_none() {
+        return Ok(None);
+    }
+    let level = match config.get_str("trace_level") {
+        Ok(level) => level.parse().map_err(|_| PartsListError::Storage {
+            s: format!("Unsupported trace level {:?}", level),
+        })?,
+        Err(_) => DEFAULT_TRACE_LEVEL,
+    };
+    let fmt = if print {
+        Some(tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE))
+    } else {
+        None
+    };
+    let subscriber = Registry::default().with(CrateFilter(level)).with(fmt);
+
+    #[cfg(feature = "otlp")]
+    let (subscriber, telemetry) = {
+        let runtime = tokio::runtime::Runtime::new()
+            .map_err(|e| PartsListError::Storage { s: e.to_string() })?;
+        let otlp = match endpoint {
+            Some(endpoint) => {
+                let _guard = runtime.enter();
+                let tracer = opentelemetry_otlp::new_pipeline()
+                    .with_endpoint(endpoint)
+                    .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+                        opentelemetry::sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                            "service.name",
+                            "bom-server",
+                        )]),
+                    ))
+                    .with_tonic()
+                    .install_batch(opentelemetry::runtime::Tokio)
+                    .map_err(|e| PartsListError::Storage { s: e.to_string() })?;
+                Some(tracing_opentelemetry::layer().with_tracer(tracer))
+            }
+            None => None,
+        };
+        (subscriber.with(otlp), Telemetry { _runtime: runtime })
+    };
+    #[cfg(not(feature = "otlp"))]
+    let telemetry = {
+        if endpoint.is_some() {
+            return Err(PartsListError::Storage {
+                s: "otlp_endpoint requires bom-server to be built with the otlp feature".into(),
+            });
+        }
+        Telemetry {}
+    };
+
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|e| PartsListError::Storage { s: e.to_string() })?;
+    Ok(Some(telemetry))
+}
+
+/// Span covering the handling of a request, which the spans of the route are
+/// recorded within
+struct RequestSpan(Span);
+
+/// Fairing recording a span for each request, named by its method and uri and
+/// ending with its status
+///
+/// Rocket handles each request on a single thread, so the span is entered
+/// when the request arrives and left once its response is ready. Must be
+/// attached after the request ids fairing to record the id of each request.
+pub struct RequestSpans;
+
+impl Fairing for RequestSpans {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request spans",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let id = request.local_cache(|| RequestId(String::new())).0.clone();
+        let span = info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            request_id = %id,
+            status = field::Empty,
+        );
+        span.with_subscriber(|(id, subscriber)| subscriber.enter(id));
+        request.local_cache(|| RequestSpan(span));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut rocket::Response) {
+        let span = &request.local_cache(|| RequestSpan(Span::none())).0;
+        span.record("status", &response.status().code);
+        span.with_subscriber(|(id, subscriber)| subscriber.exit(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::config::Environment;
+
+    #[test]
+    fn tracing_config() {
+        let config = Config::build(Environment::Development).finalize().unwrap();
+        assert!(init(&config).unwrap().is_none());
+
+        let config = Config::build(Environment::Development)
+            .extra("trace_spans", true)
+            .extra("trace_level", "loud")
+            .finalize()
+            .unwrap();
+        assert_matches!(init(&config).err(), Some(PartsListError::Storage { .. }));
+    }
+}
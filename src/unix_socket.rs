@@ -0,0 +1,132 @@
+use std::fs;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use rocket::Config;
+
+use crate::parts_list::PartsListError;
+
+/// Listener on a Unix domain socket relaying each connection to the address
+/// the server listens on, so co-located processes can reach the API through
+/// the file system
+///
+/// Rocket only listens on TCP, so the server should stay bound to a loopback
+/// address when the API mustn't be reachable from other hosts.
+#[derive(Clone, Debug)]
+pub struct UnixSocketRelay {
+    pub path: PathBuf,
+    pub target: SocketAddr,
+}
+
+impl UnixSocketRelay {
+    pub fn new<P: AsRef<Path>>(path: P, target: SocketAddr) -> UnixSocketRelay {
+        UnixSocketRelay {
+            path: path.as_ref().to_path_buf(),
+            target,
+        }
+    }
+
+    /// Relay from the socket given by the `unix_socket` setting of the Rocket
+    /// configuration to its `address` and `port`
+    pub fn from_config(config: &Config) -> Result<Option<UnixSocketRelay>, PartsListError> {
+        let path = match config.get_str("unix_socket") {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        };
+        let target = (config.address.as_str(), config.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| PartsListError::Storage {
+                s: format!("Couldn't resolve address {:?}", config.address),
+            })?;
+        Ok(Some(UnixSocketRelay::new(path, target)))
+    }
+
+    /// Bind the socket, replacing the socket file left by a previous run, and
+    /// relay its connections from a background thread
+    pub fn spawn(self) -> Result<thread::JoinHandle<()>, PartsListError> {
+        match fs::symlink_metadata(&self.path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&self.path)?,
+            Ok(_) => {
+                return Err(PartsListError::Storage {
+                    s: format!("{} exists and isn't a socket", self.path.display()),
+                })
+            }
+            Err(_) => {}
+        }
+        let listener = UnixListener::bind(&self.path)?;
+        Ok(thread::spawn(move || {
+            for client in listener.incoming() {
+                let target = self.target;
+                match client {
+                    Ok(client) => {
+                        thread::spawn(move || {
+                            if let Err(e) = relay(client, target) {
+                                tracing::warn!("Failed to relay connection to {}: {}", target, e);
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!(
+                        "Failed to accept connection on {}: {}",
+                        self.path.display(),
+                        e
+                    ),
+                }
+            }
+        }))
+    }
+}
+
+/// Copy bytes both ways between a client and the server until both are done
+fn relay(client: UnixStream, target: SocketAddr) -> io::Result<()> {
+    let server = TcpStream::connect(target)?;
+    let mut requests = client.try_clone()?;
+    let mut upstream = server.try_clone()?;
+    let forward = thread::spawn(move || {
+        let _ = io::copy(&mut requests, &mut upstream);
+        let _ = upstream.shutdown(Shutdown::Write);
+    });
+    let (mut replies, mut downstream) = (server, client);
+    let copied = io::copy(&mut replies, &mut downstream);
+    let _ = downstream.shutdown(Shutdown::Write);
+    let _ = forward.join();
+    copied.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn relays_connections() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut request = String::new();
+            stream.read_to_string(&mut request).unwrap();
+            stream.write_all(request.to_uppercase().as_bytes()).unwrap();
+        });
+
+        let path = std::env::temp_dir().join(format!("bom-{}.sock", uuid::Uuid::new_v4()));
+        fs::write(&path, "not a socket").unwrap();
+        let relay = UnixSocketRelay::new(&path, target);
+        assert!(relay.clone().spawn().is_err());
+        fs::remove_file(&path).unwrap();
+        relay.spawn().unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"get /v1/parts").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "GET /V1/PARTS");
+        fs::remove_file(&path).unwrap();
+    }
+}
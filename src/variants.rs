@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use uuid::Uuid;
+
+use crate::parts_list::{Part, PartsListError};
+
+/// Option groups offered by an assembly, such as `color`, each with the values
+/// it can be configured to
+pub type OptionGroups = BTreeMap<String, BTreeSet<String>>;
+
+/// Value chosen for each option group when resolving a configured assembly
+pub type Configuration = BTreeMap<String, String>;
+
+/// Parse a comma separated list of `group:value` choices, such as
+/// `color:red,size:large`
+pub fn parse_config(s: &str) -> Result<Configuration, PartsListError> {
+    let mut config = Configuration::new();
+    for choice in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        match choice.split_once(':') {
+            Some((group, value)) if !group.trim().is_empty() && !value.trim().is_empty() => {
+                config.insert(group.trim().into(), value.trim().into());
+            }
+            _ => return Err(PartsListError::InvalidConfigString { s: s.into() }),
+        }
+    }
+    Ok(config)
+}
+
+/// Check that the options restricting the children of a part are among the
+/// options it offers, and only restrict its children
+pub fn check(part: &Part) -> Result<(), PartsListError> {
+    for (child, options) in &part.child_options {
+        if !part.children.contains(child) {
+            return Err(PartsListError::NotAChild {
+                parent: part.id,
+                child: *child,
+            });
+        }
+        for (group, values) in options {
+            let offered =
+                part.options
+                    .get(group)
+                    .ok_or_else(|| PartsListError::UnknownOptionGroup {
+                        id: part.id,
+                        group: group.clone(),
+                    })?;
+            if let Some(value) = values.difference(offered).next() {
+                return Err(PartsListError::UnknownOptionValue {
+                    id: part.id,
+                    group: group.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Children of a part included in `config`, or all of them when no
+/// configuration is given
+///
+/// A child is included when the value chosen for each group restricting it is
+/// one of the values it is restricted to. Every group offered by the part must
+/// be configured to one of its values, while choices for groups the part
+/// doesn't offer are ignored, as they may be offered by other parts of the
+/// assembly.
+pub fn configured_children(
+    part: &Part,
+    config: Option<&Configuration>,
+) -> Result<Vec<Uuid>, PartsListError> {
+    let config = match config {
+        Some(config) => config,
+        None => return Ok(part.children.iter().copied().collect()),
+    };
+    for (group, values) in &part.options {
+        let value = config
+            .get(group)
+            .ok_or_else(|| PartsListError::OptionNotConfigured {
+                id: part.id,
+                group: group.clone(),
+            })?;
+        if !values.contains(value) {
+            return Err(PartsListError::UnknownOptionValue {
+                id: part.id,
+                group: group.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    Ok(part
+        .children
+        .iter()
+        .filter(|child| {
+            part.child_options.get(child).is_none_or(|options| {
+                options.iter().all(|(group, values)| {
+                    config
+                        .get(group)
+                        .is_some_and(|value| values.contains(value))
+                })
+            })
+        })
+        .copied()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[&str]) -> BTreeSet<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn configured_children_by_option() {
+        let mut assy = Part::new("lamp");
+        let red = Uuid::new_v4();
+        let blue = Uuid::new_v4();
+        let bulb = Uuid::new_v4();
+        assy.children = [red, blue, bulb].iter().copied().collect();
+        assy.options
+            .insert("color".into(), values(&["red", "blue"]));
+        assy.child_options.insert(red, {
+            let mut options = OptionGroups::new();
+            options.insert("color".into(), values(&["red"]));
+            options
+        });
+        assy.child_options.insert(blue, {
+            let mut options = OptionGroups::new();
+            options.insert("color".into(), values(&["blue"]));
+            options
+        });
+        check(&assy).unwrap();
+
+        let config = parse_config("color:red, size:large").unwrap();
+        let mut children = configured_children(&assy, Some(&config)).unwrap();
+        children.sort();
+        let mut expected = vec![red, bulb];
+        expected.sort();
+        assert_eq!(children, expected);
+        assert_eq!(configured_children(&assy, None).unwrap().len(), 3);
+
+        assert_matches!(
+            configured_children(&assy, Some(&parse_config("size:large").unwrap())),
+            Err(PartsListError::OptionNotConfigured { .. })
+        );
+        assert_matches!(
+            configured_children(&assy, Some(&parse_config("color:green").unwrap())),
+            Err(PartsListError::UnknownOptionValue { .. })
+        );
+        assert_matches!(
+            parse_config("color"),
+            Err(PartsListError::InvalidConfigString { .. })
+        );
+
+        assy.options.clear();
+        assert_matches!(check(&assy), Err(PartsListError::UnknownOptionGroup { .. }));
+    }
+}
@@ -0,0 +1,197 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
+
+use arc_swap::ArcSwapOption;
+use thiserror::Error;
+
+use crate::parts_list::PartsList;
+use crate::perf::{self, LockMode};
+use crate::store::PartsStore;
+use crate::PartsWriter;
+
+/// Reason a write to the parts store was refused before it was made, or
+/// failed without an outcome
+#[derive(Error, Clone, Copy, Debug, PartialEq)]
+pub enum WriteRejected {
+    #[error("Too many writes are waiting for the parts list, try again later")]
+    QueueFull,
+    #[error("Couldn't write lock parts list!")]
+    Stopped,
+    #[error("The change to the parts list failed unexpectedly")]
+    Panicked,
+}
+
+/// Outcome of a change, of whichever type its sender expects back
+type Outcome = Box<dyn Any + Send>;
+
+/// Command sent to the writer thread
+pub enum WriteCommand {
+    /// Make a change to the store, replying with its outcome once readers
+    /// can see the change
+    Change {
+        change: Box<dyn FnOnce(&mut PartsWriter<'_>) -> Outcome + Send>,
+        reply: SyncSender<Result<Outcome, WriteRejected>>,
+    },
+}
+
+/// Bounded queue of commands to the writer thread, the only thread changing
+/// the parts store
+///
+/// Routes and background tasks send the changes they make as commands and
+/// only wait for the reply, while the writer thread makes them one at a time
+/// in the order they arrived. At most `capacity` commands wait, further
+/// changes being refused straight away so callers can tell clients to back
+/// off rather than park a worker behind a slow write. Readers never queue, as
+/// they are served from snapshots or the lock. Clones share the queue, and
+/// the writer thread stops once every clone is dropped.
+#[derive(Clone)]
+pub struct WriteQueue {
+    commands: SyncSender<WriteCommand>,
+}
+
+impl WriteQueue {
+    /// Start the writer thread, owning the write path of `store` and
+    /// replacing `snapshot`, when kept, after each change
+    ///
+    /// At least one change may wait, so none is refused while the writer is
+    /// merely between changes.
+    pub fn new(
+        capacity: usize,
+        store: Arc<RwLock<Box<dyn PartsStore>>>,
+        snapshot: Option<Arc<ArcSwapOption<PartsList>>>,
+    ) -> WriteQueue {
+        let (commands, queue) = mpsc::sync_channel(capacity.max(1));
+        thread::spawn(move || write(queue, store, snapshot));
+        WriteQueue { commands }
+    }
+
+    /// Have the writer thread make `change` once the changes queued before
+    /// are done, waiting for what it returns, and failing without waiting
+    /// when the queue is full
+    pub fn change<R, F>(&self, change: F) -> Result<R, WriteRejected>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut PartsWriter<'_>) -> R + Send + 'static,
+    {
+        let queued = Instant::now();
+        let (reply, outcome) = mpsc::sync_channel(1);
+        let command = WriteCommand::Change {
+            change: Box::new(move |store| {
+                perf::record_lock(LockMode::Write, queued.elapsed(), true);
+                Box::new(change(store))
+            }),
+            reply,
+        };
+        if let Err(e) = self.commands.try_send(command) {
+            perf::record_lock(LockMode::Write, queued.elapsed(), false);
+            return Err(match e {
+                TrySendError::Full(_) => WriteRejected::QueueFull,
+                TrySendError::Disconnected(_) => WriteRejected::Stopped,
+            });
+        }
+        match outcome.recv() {
+            Ok(Ok(outcome)) => outcome
+                .downcast()
+                .map(|outcome| *outcome)
+                .map_err(|_| WriteRejected::Stopped),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(WriteRejected::Stopped),
+        }
+    }
+}
+
+/// Make the changes sent to the queue one at a time, until the queue itself
+/// is dropped
+///
+/// A change which panics is answered with an error and the next is made as
+/// usual, the writer being dropped only after the panic is caught so the lock
+/// isn't poisoned.
+fn write(
+    queue: Receiver<WriteCommand>,
+    store: Arc<RwLock<Box<dyn PartsStore>>>,
+    snapshot: Option<Arc<ArcSwapOption<PartsList>>>,
+) {
+    for command in queue {
+        match command {
+            WriteCommand::Change { change, reply } => {
+                // the writer is dropped, replacing the snapshot, before replying
+                let outcome = match store.write() {
+                    Ok(store) => {
+                        let mut writer = PartsWriter {
+                            store,
+                            snapshot: snapshot.as_deref(),
+                            changed: false,
+                        };
+                        panic::catch_unwind(AssertUnwindSafe(|| change(&mut writer))).map_err(
+                            |_| {
+                                // the change may have been partly made
+                                writer.changed = true;
+                                WriteRejected::Panicked
+                            },
+                        )
+                    }
+                    Err(_) => Err(WriteRejected::Stopped),
+                };
+                let _ = reply.send(outcome);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::parts_list::Part;
+
+    #[test]
+    fn refuses_changes_once_full() {
+        let store: Box<dyn PartsStore> = Box::new(PartsList::new());
+        let queue = WriteQueue::new(1, Arc::new(RwLock::new(store)), None);
+        let (started, running) = channel();
+        let (finish, finishing) = channel::<()>();
+        let first = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                queue.change(move |_| {
+                    started.send(()).unwrap();
+                    finishing.recv().unwrap();
+                })
+            })
+        };
+        running.recv().unwrap();
+
+        // the writer is busy with the first change, so one more fills the queue
+        let (reply, replied) = mpsc::sync_channel(1);
+        let queued = queue.commands.try_send(WriteCommand::Change {
+            change: Box::new(|store| Box::new(store.insert(Part::new("queued")).is_ok())),
+            reply,
+        });
+        assert!(queued.is_ok());
+        assert_eq!(queue.change(|_| ()), Err(WriteRejected::QueueFull));
+
+        finish.send(()).unwrap();
+        assert_eq!(first.join().unwrap(), Ok(()));
+        let inserted = replied.recv().unwrap().unwrap().downcast::<bool>().unwrap();
+        assert!(*inserted);
+        assert_eq!(queue.change(|store| store.parts().unwrap().len()), Ok(1));
+    }
+
+    #[test]
+    fn keeps_writing_after_a_change_panics() {
+        let store: Box<dyn PartsStore> = Box::new(PartsList::new());
+        let store = Arc::new(RwLock::new(store));
+        let queue = WriteQueue::new(1, store.clone(), None);
+
+        let panicked = queue.change(|_| -> bool { panic!("change failed") });
+        assert_eq!(panicked, Err(WriteRejected::Panicked));
+        assert!(!store.is_poisoned());
+        let inserted = queue.change(|store| store.insert(Part::new("after")).is_ok());
+        assert_eq!(inserted, Ok(true));
+    }
+}